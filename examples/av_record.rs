@@ -3,7 +3,8 @@
 //! This example demonstrates a complete A/V recording pipeline:
 //! 1. AVCaptureSession capturing from both camera and microphone
 //! 2. VTCompressionSession for H.264 video encoding
-//! 3. AVAssetWriter for muxing video + audio into a MOV file
+//! 3. Mp4Writer (AVAssetWriter) for muxing video + audio into a MOV file
+//! 4. EBU R128 loudness and recording metadata (date, encoder) stored in the file
 //!
 //! Run with: cargo run --example av_record --features helpers
 //!
@@ -11,22 +12,20 @@
 //! The output file will be saved as "output_av.mov".
 
 use core_foundation_sys::base::OSStatus;
-use core_media_sys::CMTime;
+use core_media_sys::{CMSampleBufferRef, CMTime};
 use libc::c_void;
 use objc2::rc::Retained;
 use objc2::runtime::{Bool, Sel};
 use objc2::{class, msg_send};
 use objc2_av_foundation::{
-    AVAssetWriter, AVAssetWriterInput, AVCaptureAudioDataOutput, AVCaptureDevice,
-    AVCaptureDeviceInput, AVCaptureSession, AVCaptureVideoDataOutput, AVMediaTypeAudio,
-    AVMediaTypeVideo,
+    AVCaptureAudioDataOutput, AVCaptureDevice, AVCaptureDeviceInput, AVCaptureSession,
+    AVCaptureVideoDataOutput, AVMediaTypeAudio, AVMediaTypeVideo,
 };
-use objc2_core_media::CMSampleBuffer;
-use objc2_foundation::{ns_string, NSDictionary, NSError, NSNumber, NSObject, NSString, NSURL};
+use objc2_foundation::{ns_string, NSNumber, NSObject};
 use std::ptr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use video_toolbox_sys::codecs;
 use video_toolbox_sys::compression::{
@@ -37,7 +36,9 @@ use video_toolbox_sys::compression::{
 use video_toolbox_sys::cv_types::CVPixelBufferRef;
 use video_toolbox_sys::helpers::{
     create_capture_delegate, create_dispatch_queue, run_for_duration, set_sample_buffer_delegate,
-    CompressionSessionBuilder, DelegateCallback,
+    write_loudness_metadata, write_mp4_metadata, AacTrackConfig, AudioFormat, AudioMeter,
+    ChannelLayout, CompressionSessionBuilder, Container, DelegateCallback, Mp4Metadata, Mp4Writer,
+    Mp4WriterError, PipelineEvent, SampleFormat,
 };
 
 // Video parameters
@@ -48,7 +49,9 @@ const VIDEO_BITRATE: i64 = 4_000_000; // 4 Mbps
 
 // Audio parameters
 const SAMPLE_RATE: f64 = 44100.0;
-const NUM_CHANNELS: u32 = 1;
+// Stereo or 5.1 needs a capture device with that many channels
+const CHANNEL_LAYOUT: ChannelLayout = ChannelLayout::Mono;
+const NUM_CHANNELS: u32 = CHANNEL_LAYOUT.channel_count();
 const AUDIO_BITRATE: i32 = 128000; // 128 kbps
 
 // Recording duration
@@ -59,19 +62,13 @@ static VIDEO_FRAME_COUNT: AtomicUsize = AtomicUsize::new(0);
 static ENCODED_VIDEO_FRAMES: AtomicUsize = AtomicUsize::new(0);
 static AUDIO_SAMPLE_COUNT: AtomicUsize = AtomicUsize::new(0);
 static SHOULD_STOP: AtomicBool = AtomicBool::new(false);
+static CLIPPING_WARNED: AtomicBool = AtomicBool::new(false);
 
-// Writer context for both video and audio
-#[allow(dead_code)]
-struct AVWriterContext {
-    asset_writer: Retained<AVAssetWriter>,
-    video_input: Retained<AVAssetWriterInput>,
-    audio_input: Retained<AVAssetWriterInput>,
-}
-
-unsafe impl Send for AVWriterContext {}
-unsafe impl Sync for AVWriterContext {}
+// Level meter for the microphone, created on the first audio buffer
+static AUDIO_METER: Mutex<Option<AudioMeter>> = Mutex::new(None);
 
-static WRITER_CONTEXT: Mutex<Option<AVWriterContext>> = Mutex::new(None);
+// File writer for both video and audio
+static WRITER: Mutex<Option<Mp4Writer>> = Mutex::new(None);
 static mut COMPRESSION_SESSION: VTCompressionSessionRef = ptr::null_mut();
 
 // CoreMedia FFI
@@ -96,24 +93,16 @@ extern "C" fn compression_output_callback(
         return;
     }
 
-    let ctx_guard = WRITER_CONTEXT.lock().unwrap();
-    if let Some(ref ctx) = *ctx_guard {
-        unsafe {
-            let sample_buffer_obj: &CMSampleBuffer = &*(sample_buffer as *const CMSampleBuffer);
-
-            if ctx.video_input.isReadyForMoreMediaData() {
-                let success: Bool =
-                    msg_send![&ctx.video_input, appendSampleBuffer: sample_buffer_obj];
-                if success.as_bool() {
-                    ENCODED_VIDEO_FRAMES.fetch_add(1, Ordering::SeqCst);
-                }
-            }
+    let mut writer_guard = WRITER.lock().unwrap();
+    if let Some(writer) = writer_guard.as_mut() {
+        if let Ok(true) = unsafe { writer.append(sample_buffer as CMSampleBufferRef) } {
+            ENCODED_VIDEO_FRAMES.fetch_add(1, Ordering::SeqCst);
         }
     }
 }
 
 // Video capture delegate callback
-extern "C" fn video_capture_callback(
+extern "C-unwind" fn video_capture_callback(
     _this: *mut c_void,
     _cmd: Sel,
     _output: *mut c_void,
@@ -160,7 +149,7 @@ extern "C" fn video_capture_callback(
 }
 
 // Audio capture delegate callback
-extern "C" fn audio_capture_callback(
+extern "C-unwind" fn audio_capture_callback(
     _this: *mut c_void,
     _cmd: Sel,
     _output: *mut c_void,
@@ -174,14 +163,36 @@ extern "C" fn audio_capture_callback(
 
         AUDIO_SAMPLE_COUNT.fetch_add(1, Ordering::SeqCst);
 
-        let ctx_guard = WRITER_CONTEXT.lock().unwrap();
-        if let Some(ref ctx) = *ctx_guard {
-            let sample_buffer_obj: &CMSampleBuffer = &*(sample_buffer as *const CMSampleBuffer);
-
-            if ctx.audio_input.isReadyForMoreMediaData() {
-                let _: Bool = msg_send![&ctx.audio_input, appendSampleBuffer: sample_buffer_obj];
+        // Warn about a muted or clipping microphone while recording, rather
+        // than after the file is written
+        let mut meter_guard = AUDIO_METER.lock().unwrap();
+        let meter = meter_guard.get_or_insert_with(|| {
+            AudioMeter::new(AudioFormat::new(SAMPLE_RATE, NUM_CHANNELS, SampleFormat::F32))
+                .measure_loudness(true)
+        });
+        if let Ok(events) = meter.process_sample_buffer(sample_buffer as CMSampleBufferRef) {
+            for event in events {
+                match event {
+                    PipelineEvent::SilenceStarted { .. } => {
+                        eprintln!("  Warning: microphone appears muted (no signal for 2 sec)");
+                    }
+                    PipelineEvent::SilenceEnded { .. } => {
+                        eprintln!("  Microphone signal restored");
+                    }
+                    PipelineEvent::ClippingDetected { channel, .. }
+                        if !CLIPPING_WARNED.swap(true, Ordering::SeqCst) =>
+                    {
+                        eprintln!("  Warning: microphone input clipping on channel {}", channel);
+                    }
+                    _ => {}
+                }
             }
         }
+        drop(meter_guard);
+
+        if let Some(writer) = WRITER.lock().unwrap().as_mut() {
+            let _ = writer.append(sample_buffer as CMSampleBufferRef);
+        }
     }
 }
 
@@ -199,93 +210,16 @@ fn create_compression_session() -> Result<VTCompressionSessionRef, OSStatus> {
     }
 }
 
-fn setup_asset_writer(
-    output_path: &str,
-) -> Result<
-    (
-        Retained<AVAssetWriter>,
-        Retained<AVAssetWriterInput>,
-        Retained<AVAssetWriterInput>,
-    ),
-    String,
-> {
-    unsafe {
-        let path_str = NSString::from_str(output_path);
-        let output_url = NSURL::fileURLWithPath(&path_str);
-
-        // Remove existing file
-        let file_manager: Retained<NSObject> = msg_send![class!(NSFileManager), defaultManager];
-        let _: Bool = msg_send![&file_manager, removeItemAtPath: &*path_str, error: ptr::null_mut::<*mut NSError>()];
-
-        let file_type = ns_string!("com.apple.quicktime-movie");
-        let asset_writer = AVAssetWriter::assetWriterWithURL_fileType_error(&output_url, file_type)
-            .map_err(|e| format!("Failed to create asset writer: {:?}", e))?;
-
-        // Video input (passthrough for H.264)
-        let video_media_type = ns_string!("vide");
-        let video_input = AVAssetWriterInput::assetWriterInputWithMediaType_outputSettings(
-            video_media_type,
-            None,
-        );
-        video_input.setExpectsMediaDataInRealTime(true);
-
-        // Audio input with AAC encoding settings
-        let audio_media_type = ns_string!("soun");
-
-        let format_key = NSString::from_str("AVFormatIDKey");
-        let sample_rate_key = NSString::from_str("AVSampleRateKey");
-        let channels_key = NSString::from_str("AVNumberOfChannelsKey");
-        let bitrate_key = NSString::from_str("AVEncoderBitRateKey");
-
-        let format_value: Retained<NSNumber> =
-            msg_send![class!(NSNumber), numberWithUnsignedInt: codecs::audio::AAC];
-        let sample_rate_value: Retained<NSNumber> =
-            msg_send![class!(NSNumber), numberWithDouble: SAMPLE_RATE];
-        let channels_value: Retained<NSNumber> =
-            msg_send![class!(NSNumber), numberWithUnsignedInt: NUM_CHANNELS];
-        let bitrate_value: Retained<NSNumber> =
-            msg_send![class!(NSNumber), numberWithInt: AUDIO_BITRATE];
-
-        let keys: [&NSString; 4] = [&format_key, &sample_rate_key, &channels_key, &bitrate_key];
-        let objects: [&NSNumber; 4] = [
-            &format_value,
-            &sample_rate_value,
-            &channels_value,
-            &bitrate_value,
-        ];
-
-        let audio_settings: Retained<NSDictionary<NSString, NSObject>> = msg_send![
-            class!(NSDictionary),
-            dictionaryWithObjects: objects.as_ptr(),
-            forKeys: keys.as_ptr(),
-            count: 4usize
-        ];
-
-        let audio_input: Retained<AVAssetWriterInput> = msg_send![
-            class!(AVAssetWriterInput),
-            assetWriterInputWithMediaType: audio_media_type,
-            outputSettings: &*audio_settings
-        ];
-        audio_input.setExpectsMediaDataInRealTime(true);
-
-        // Add inputs
-        asset_writer.addInput(&video_input);
-        asset_writer.addInput(&audio_input);
-
-        if !asset_writer.startWriting() {
-            return Err("Failed to start asset writer".to_string());
-        }
-
-        let zero_time = objc2_core_media::CMTime {
-            value: 0,
-            timescale: 600,
-            flags: objc2_core_media::CMTimeFlags(1),
-            epoch: 0,
-        };
-        let _: () = msg_send![&asset_writer, startSessionAtSourceTime: zero_time];
-
-        Ok((asset_writer, video_input, audio_input))
-    }
+fn setup_writer(output_path: &str) -> Result<Mp4Writer, Mp4WriterError> {
+    let mut writer = Mp4Writer::new(output_path, Container::Mov)?;
+    // Video is already H.264, audio is encoded to AAC by the writer
+    writer.add_video_track_passthrough()?;
+    writer.add_audio_track_aac(&AacTrackConfig {
+        sample_rate: SAMPLE_RATE,
+        channel_layout: CHANNEL_LAYOUT,
+        bitrate: AUDIO_BITRATE,
+    })?;
+    Ok(writer)
 }
 
 fn main() {
@@ -317,13 +251,13 @@ fn main() {
     unsafe {
         // Set up asset writer
         println!("Setting up asset writer...");
-        let (asset_writer, video_input, audio_input) = match setup_asset_writer(&output_path) {
-            Ok(x) => x,
+        match setup_writer(&output_path) {
+            Ok(writer) => *WRITER.lock().unwrap() = Some(writer),
             Err(e) => {
-                eprintln!("Failed: {}", e);
+                eprintln!("Failed to set up asset writer: {}", e);
                 return;
             }
-        };
+        }
 
         // Create video compression session using builder
         println!("Creating H.264 encoder...");
@@ -336,16 +270,6 @@ fn main() {
         };
         COMPRESSION_SESSION = compression_session;
 
-        // Store writer context
-        {
-            let mut ctx = WRITER_CONTEXT.lock().unwrap();
-            *ctx = Some(AVWriterContext {
-                asset_writer: asset_writer.clone(),
-                video_input: video_input.clone(),
-                audio_input: audio_input.clone(),
-            });
-        }
-
         // Set up capture session
         println!("Setting up capture session...");
         let capture_session = AVCaptureSession::new();
@@ -455,6 +379,7 @@ fn main() {
         println!("\nStarting recording...");
         println!("Recording for {} seconds...\n", RECORD_DURATION_SECS);
 
+        let started_at = SystemTime::now();
         capture_session.startRunning();
 
         // Keep delegates alive
@@ -492,22 +417,11 @@ fn main() {
         std::thread::sleep(Duration::from_millis(500));
 
         // Finish writing
-        video_input.markAsFinished();
-        audio_input.markAsFinished();
-
         println!("Finalizing file...");
-
-        use block2::StackBlock;
-        let finished = std::sync::Arc::new(AtomicBool::new(false));
-        let finished_clone = finished.clone();
-        let block = StackBlock::new(move || {
-            finished_clone.store(true, Ordering::SeqCst);
-        });
-        let _: () = msg_send![&asset_writer, finishWritingWithCompletionHandler: &block];
-
-        let timeout = std::time::Instant::now();
-        while !finished.load(Ordering::SeqCst) && timeout.elapsed() < Duration::from_secs(10) {
-            std::thread::sleep(Duration::from_millis(100));
+        if let Some(writer) = WRITER.lock().unwrap().take() {
+            if let Err(e) = writer.finish().wait() {
+                eprintln!("Failed to finish writing: {}", e);
+            }
         }
 
         VTCompressionSessionInvalidate(compression_session);
@@ -527,6 +441,31 @@ fn main() {
             "  Audio samples: {}",
             AUDIO_SAMPLE_COUNT.load(Ordering::SeqCst)
         );
+        if let Some(meter) = AUDIO_METER.lock().unwrap().as_ref() {
+            if meter.is_silent() {
+                println!("  Warning: audio track ends in silence - check the microphone");
+            }
+            if meter.clipped_samples() > 0 {
+                println!("  Clipped audio samples: {}", meter.clipped_samples());
+            }
+            if let Some(loudness) = meter.loudness() {
+                let measurement = loudness.measurement();
+                println!(
+                    "  Loudness: {:.1} LUFS integrated, {:.1} dBTP true peak",
+                    measurement.integrated_lufs, measurement.true_peak_dbtp
+                );
+                if let Err(e) = write_loudness_metadata(&output_path, &measurement) {
+                    eprintln!("  Failed to store loudness metadata: {}", e);
+                }
+            }
+        }
+        let metadata = Mp4Metadata::new()
+            .title("av_record")
+            .creation_time(started_at)
+            .encoder(concat!("video-toolbox-sys ", env!("CARGO_PKG_VERSION")));
+        if let Err(e) = write_mp4_metadata(&output_path, &metadata) {
+            eprintln!("  Failed to store metadata: {}", e);
+        }
         println!("  Output: {}", output_path);
 
         if let Ok(metadata) = std::fs::metadata(&output_path) {
@@ -12,14 +12,12 @@ use core_foundation::boolean::CFBoolean;
 use core_foundation::dictionary::CFDictionary;
 use core_foundation::number::CFNumber;
 use core_foundation::string::CFString;
-use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, CFTypeRef, OSStatus};
+use core_foundation_sys::base::{kCFAllocatorDefault, CFTypeRef, OSStatus};
 use core_foundation_sys::dictionary::CFDictionaryRef;
 use core_foundation_sys::string::CFStringRef;
 use video_toolbox_sys::cv_types::{
-    kCVPixelBufferCGBitmapContextCompatibilityKey, kCVPixelBufferCGImageCompatibilityKey,
-    kCVPixelBufferHeightKey, kCVPixelBufferPixelFormatTypeKey, kCVPixelBufferWidthKey,
-    CVPixelBufferCreate, CVPixelBufferGetBaseAddress, CVPixelBufferGetBytesPerRow,
-    CVPixelBufferLockBaseAddress, CVPixelBufferRef, CVPixelBufferUnlockBaseAddress,
+    kCVPixelBufferPixelFormatTypeKey, CVPixelBufferGetBaseAddress, CVPixelBufferGetBytesPerRow,
+    CVPixelBufferLockBaseAddress, CVPixelBufferUnlockBaseAddress,
 };
 use std::io::Write;
 use std::process::Command;
@@ -35,6 +33,7 @@ use video_toolbox_sys::compression::{
     VTCompressionSessionEncodeFrame, VTCompressionSessionInvalidate,
     VTCompressionSessionPrepareToEncodeFrames, VTCompressionSessionRef, VTEncodeInfoFlags,
 };
+use video_toolbox_sys::helpers::{PixelBufferPool, SendablePixelBuffer};
 use video_toolbox_sys::session::VTSessionSetProperty;
 
 const K_CM_VIDEO_CODEC_TYPE_H264: u32 = 0x61766331;
@@ -76,43 +75,12 @@ extern "C" fn compression_callback(
     }
 }
 
-fn create_test_frame(frame_num: usize) -> CVPixelBufferRef {
+fn create_test_frame(pool: &PixelBufferPool, frame_num: usize) -> SendablePixelBuffer {
+    // Pooled buffers are recycled once the encoder releases them, instead of
+    // allocating a new 8 MB buffer per frame
+    let frame = pool.acquire().expect("Failed to acquire pixel buffer");
+    let pixel_buffer = frame.as_raw();
     unsafe {
-        let mut pixel_buffer: CVPixelBufferRef = ptr::null_mut();
-
-        let format_key = CFString::wrap_under_get_rule(kCVPixelBufferPixelFormatTypeKey);
-        let width_key = CFString::wrap_under_get_rule(kCVPixelBufferWidthKey);
-        let height_key = CFString::wrap_under_get_rule(kCVPixelBufferHeightKey);
-        let cg_compat_key = CFString::wrap_under_get_rule(kCVPixelBufferCGImageCompatibilityKey);
-        let cg_bitmap_key =
-            CFString::wrap_under_get_rule(kCVPixelBufferCGBitmapContextCompatibilityKey);
-
-        let attrs = CFDictionary::from_CFType_pairs(&[
-            (
-                format_key.as_CFType(),
-                CFNumber::from(K_CV_PIXEL_FORMAT_TYPE_32BGRA as i32).as_CFType(),
-            ),
-            (width_key.as_CFType(), CFNumber::from(WIDTH).as_CFType()),
-            (height_key.as_CFType(), CFNumber::from(HEIGHT).as_CFType()),
-            (
-                cg_compat_key.as_CFType(),
-                CFBoolean::true_value().as_CFType(),
-            ),
-            (
-                cg_bitmap_key.as_CFType(),
-                CFBoolean::true_value().as_CFType(),
-            ),
-        ]);
-
-        CVPixelBufferCreate(
-            kCFAllocatorDefault,
-            WIDTH as usize,
-            HEIGHT as usize,
-            K_CV_PIXEL_FORMAT_TYPE_32BGRA,
-            attrs.as_concrete_TypeRef() as CFDictionaryRef,
-            &mut pixel_buffer,
-        );
-
         CVPixelBufferLockBaseAddress(pixel_buffer, 0);
         let base = CVPixelBufferGetBaseAddress(pixel_buffer) as *mut u8;
         let stride = CVPixelBufferGetBytesPerRow(pixel_buffer);
@@ -131,8 +99,8 @@ fn create_test_frame(frame_num: usize) -> CVPixelBufferRef {
         }
 
         CVPixelBufferUnlockBaseAddress(pixel_buffer, 0);
-        pixel_buffer
     }
+    frame
 }
 
 fn benchmark_native_videotoolbox() -> (f64, usize) {
@@ -208,12 +176,14 @@ fn benchmark_native_videotoolbox() -> (f64, usize) {
         }
 
         VTCompressionSessionPrepareToEncodeFrames(session);
+        let pool = PixelBufferPool::from_compression_session(session)
+            .expect("Session has no pixel buffer pool");
 
         // Encode
         let start = Instant::now();
 
         for frame_num in 0..NUM_FRAMES {
-            let pixel_buffer = create_test_frame(frame_num);
+            let frame = create_test_frame(&pool, frame_num);
 
             let pts = core_media_sys::CMTime {
                 value: frame_num as i64,
@@ -232,15 +202,13 @@ fn benchmark_native_videotoolbox() -> (f64, usize) {
             let mut info_flags: VTEncodeInfoFlags = 0;
             VTCompressionSessionEncodeFrame(
                 session,
-                pixel_buffer,
+                frame.as_raw(),
                 pts,
                 duration,
                 ptr::null(),
                 ptr::null_mut(),
                 &mut info_flags,
             );
-
-            CFRelease(pixel_buffer as CFTypeRef);
         }
 
         let complete_time = core_media_sys::CMTime {
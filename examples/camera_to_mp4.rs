@@ -3,7 +3,7 @@
 //! This example demonstrates the full pipeline:
 //! 1. AVCaptureSession to capture video from the default camera
 //! 2. VTCompressionSession to encode frames as H.264
-//! 3. AVAssetWriter to write the encoded video to an MP4 file
+//! 3. Mp4Writer (AVAssetWriter) to write the encoded video to a MOV file
 //!
 //! Run with: cargo run --example camera_to_mp4 --features helpers
 //!
@@ -11,32 +11,31 @@
 //! The output file will be saved to the current directory as "output.mov".
 
 use core_foundation_sys::base::OSStatus;
-use core_media_sys::CMTime;
+use core_media_sys::{CMSampleBufferRef, CMTime};
 use libc::c_void;
 use objc2::rc::Retained;
 use objc2::runtime::{Bool, Sel};
 use objc2::{class, msg_send, sel};
 use objc2_av_foundation::{
-    AVAssetWriter, AVAssetWriterInput, AVCaptureDevice, AVCaptureDeviceInput, AVCaptureSession,
-    AVCaptureVideoDataOutput, AVMediaTypeVideo,
+    AVCaptureDevice, AVCaptureDeviceInput, AVCaptureSession, AVCaptureVideoDataOutput,
+    AVMediaTypeVideo,
 };
-use objc2_core_media::CMSampleBuffer;
-use objc2_foundation::{ns_string, NSError, NSNumber, NSObject, NSString, NSURL};
+use objc2_foundation::{ns_string, NSNumber, NSObject};
 use std::ptr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::{Mutex, OnceLock};
 use std::time::Duration;
 
 use video_toolbox_sys::codecs;
 use video_toolbox_sys::compression::{
     kVTEncodeInfo_FrameDropped, kVTProfileLevel_H264_High_AutoLevel,
-    VTCompressionSessionCompleteFrames, VTCompressionSessionEncodeFrame,
-    VTCompressionSessionInvalidate, VTCompressionSessionRef, VTEncodeInfoFlags,
+    VTCompressionSessionCompleteFrames, VTCompressionSessionEncodeFrame, VTCompressionSessionRef,
+    VTEncodeInfoFlags,
 };
 use video_toolbox_sys::cv_types::CVPixelBufferRef;
 use video_toolbox_sys::helpers::{
     create_capture_delegate, create_dispatch_queue, run_for_duration, set_sample_buffer_delegate,
-    CompressionSessionBuilder, DelegateCallback,
+    CompressionSessionBuilder, Container, DelegateCallback, Mp4Writer, SendableSession,
 };
 
 // Recording parameters
@@ -51,20 +50,11 @@ static FRAME_COUNT: AtomicUsize = AtomicUsize::new(0);
 static ENCODED_FRAMES: AtomicUsize = AtomicUsize::new(0);
 static SHOULD_STOP: AtomicBool = AtomicBool::new(false);
 
-// Thread-safe wrapper for the asset writer input
-#[allow(dead_code)]
-struct EncoderContext {
-    asset_writer: Retained<AVAssetWriter>,
-    writer_input: Retained<AVAssetWriterInput>,
-}
-
-unsafe impl Send for EncoderContext {}
-unsafe impl Sync for EncoderContext {}
-
-static ENCODER_CONTEXT: Mutex<Option<EncoderContext>> = Mutex::new(None);
+// File writer for the encoded frames
+static WRITER: Mutex<Option<Mp4Writer>> = Mutex::new(None);
 
 // Global compression session (needed for the delegate callback)
-static mut COMPRESSION_SESSION: VTCompressionSessionRef = ptr::null_mut();
+static COMPRESSION_SESSION: OnceLock<SendableSession> = OnceLock::new();
 
 // CoreMedia FFI
 #[link(name = "CoreMedia", kind = "framework")]
@@ -99,25 +89,18 @@ extern "C" fn compression_output_callback(
     // Get the size of the encoded data (for stats)
     let _data_size = unsafe { CMSampleBufferGetTotalSampleSize(sample_buffer) };
 
-    // Append the encoded sample buffer to the asset writer
-    let ctx_guard = ENCODER_CONTEXT.lock().unwrap();
-    if let Some(ref ctx) = *ctx_guard {
-        unsafe {
-            // Convert raw pointer to objc2 reference
-            let sample_buffer_obj: &CMSampleBuffer = &*(sample_buffer as *const CMSampleBuffer);
-
-            if ctx.writer_input.isReadyForMoreMediaData() {
-                let success: Bool =
-                    msg_send![&ctx.writer_input, appendSampleBuffer: sample_buffer_obj];
-                if success.as_bool() {
-                    let frame_num = ENCODED_FRAMES.fetch_add(1, Ordering::SeqCst) + 1;
-                    if frame_num % 30 == 0 {
-                        println!("  Encoded {} frames...", frame_num);
-                    }
-                } else {
-                    eprintln!("Failed to append sample buffer to asset writer");
+    // Append the encoded sample buffer to the file
+    let mut writer_guard = WRITER.lock().unwrap();
+    if let Some(writer) = writer_guard.as_mut() {
+        match unsafe { writer.append(sample_buffer as CMSampleBufferRef) } {
+            Ok(true) => {
+                let frame_num = ENCODED_FRAMES.fetch_add(1, Ordering::SeqCst) + 1;
+                if frame_num % 30 == 0 {
+                    println!("  Encoded {} frames...", frame_num);
                 }
             }
+            Ok(false) => {}
+            Err(e) => eprintln!("Failed to append sample buffer: {}", e),
         }
     }
 }
@@ -136,63 +119,8 @@ fn create_compression_session() -> Result<VTCompressionSessionRef, OSStatus> {
     }
 }
 
-fn setup_asset_writer(
-    output_path: &str,
-) -> Result<(Retained<AVAssetWriter>, Retained<AVAssetWriterInput>), String> {
-    unsafe {
-        // Create output URL
-        let path_str = NSString::from_str(output_path);
-        let output_url = NSURL::fileURLWithPath(&path_str);
-
-        // Remove existing file if present
-        let file_manager: Retained<NSObject> = msg_send![class!(NSFileManager), defaultManager];
-        let _: Bool = msg_send![&file_manager, removeItemAtPath: &*path_str, error: ptr::null_mut::<*mut NSError>()];
-
-        // Create asset writer for MOV/MP4
-        let file_type = ns_string!("com.apple.quicktime-movie");
-
-        let asset_writer_result =
-            AVAssetWriter::assetWriterWithURL_fileType_error(&output_url, file_type);
-
-        let asset_writer = match asset_writer_result {
-            Ok(w) => w,
-            Err(e) => return Err(format!("Failed to create asset writer: {:?}", e)),
-        };
-
-        // Create asset writer input for video
-        // Use passthrough (nil settings) since we're providing already-encoded H.264 data
-        let media_type = ns_string!("vide");
-        let writer_input = AVAssetWriterInput::assetWriterInputWithMediaType_outputSettings(
-            media_type,
-            None, // passthrough mode - data is already H.264 encoded
-        );
-
-        writer_input.setExpectsMediaDataInRealTime(true);
-
-        // Add input to writer
-        asset_writer.addInput(&writer_input);
-
-        // Start writing
-        let success = asset_writer.startWriting();
-        if !success {
-            return Err("Failed to start asset writer".to_string());
-        }
-
-        // Start session at time zero
-        let zero_time = objc2_core_media::CMTime {
-            value: 0,
-            timescale: 600,
-            flags: objc2_core_media::CMTimeFlags(1),
-            epoch: 0,
-        };
-        let _: () = msg_send![&asset_writer, startSessionAtSourceTime: zero_time];
-
-        Ok((asset_writer, writer_input))
-    }
-}
-
 // Delegate callback for video frame capture
-extern "C" fn capture_output_did_output(
+extern "C-unwind" fn capture_output_did_output(
     _this: *mut c_void,
     _cmd: Sel,
     _output: *mut c_void,
@@ -211,6 +139,10 @@ extern "C" fn capture_output_did_output(
             return;
         }
 
+        let Some(session) = COMPRESSION_SESSION.get() else {
+            return;
+        };
+
         let frame_num = FRAME_COUNT.fetch_add(1, Ordering::SeqCst);
         if frame_num == 0 {
             println!("  First frame received!");
@@ -235,7 +167,7 @@ extern "C" fn capture_output_did_output(
         let mut info_flags: VTEncodeInfoFlags = 0;
 
         let status = VTCompressionSessionEncodeFrame(
-            COMPRESSION_SESSION,
+            session.as_raw(),
             pixel_buffer,
             pts,
             duration,
@@ -268,15 +200,19 @@ fn main() {
     println!("Output file: {}\n", output_path);
 
     unsafe {
-        // 1. Set up AVAssetWriter for MP4 output
+        // 1. Set up the file writer with a passthrough H.264 track
         println!("Setting up asset writer...");
-        let (asset_writer, writer_input) = match setup_asset_writer(&output_path) {
-            Ok((w, i)) => (w, i),
+        let writer = Mp4Writer::new(&output_path, Container::Mov).and_then(|mut writer| {
+            writer.add_video_track_passthrough()?;
+            Ok(writer)
+        });
+        match writer {
+            Ok(writer) => *WRITER.lock().unwrap() = Some(writer),
             Err(e) => {
                 eprintln!("Failed to set up asset writer: {}", e);
                 return;
             }
-        };
+        }
 
         // 2. Create VideoToolbox compression session using builder
         println!("Creating H.264 compression session...");
@@ -290,16 +226,8 @@ fn main() {
         println!("Compression session created successfully!");
 
         // Store compression session globally for delegate access
-        COMPRESSION_SESSION = compression_session;
-
-        // Store encoder context for the callback
-        {
-            let mut ctx = ENCODER_CONTEXT.lock().unwrap();
-            *ctx = Some(EncoderContext {
-                asset_writer: asset_writer.clone(),
-                writer_input: writer_input.clone(),
-            });
-        }
+        let compression_session = COMPRESSION_SESSION
+            .get_or_init(|| SendableSession::from_compression(compression_session));
 
         // 3. Set up AVCaptureSession
         println!("Setting up camera capture...");
@@ -457,41 +385,21 @@ fn main() {
             flags: 1,
             epoch: 0,
         };
-        VTCompressionSessionCompleteFrames(compression_session, complete_time);
+        VTCompressionSessionCompleteFrames(compression_session.as_raw(), complete_time);
 
         // Wait a moment for final frames to be encoded
         std::thread::sleep(Duration::from_millis(500));
 
-        // Mark input as finished and finish writing
-        writer_input.markAsFinished();
-
-        // Finish writing asynchronously
+        // Finish the track and write the file
         println!("Finalizing video file...");
-
-        // Use block2 for completion handler
-        use block2::StackBlock;
-
-        let finished = std::sync::Arc::new(AtomicBool::new(false));
-        let finished_clone = finished.clone();
-
-        let block = StackBlock::new(move || {
-            finished_clone.store(true, Ordering::SeqCst);
-        });
-
-        let _: () = msg_send![&asset_writer, finishWritingWithCompletionHandler: &block];
-
-        // Wait for writing to finish (with timeout)
-        let timeout = std::time::Instant::now();
-        while !finished.load(Ordering::SeqCst) {
-            if timeout.elapsed() > Duration::from_secs(10) {
-                eprintln!("Timeout waiting for asset writer to finish");
-                break;
+        if let Some(writer) = WRITER.lock().unwrap().take() {
+            if let Err(e) = writer.finish().wait() {
+                eprintln!("Failed to finish writing: {}", e);
             }
-            std::thread::sleep(Duration::from_millis(100));
         }
 
         // Clean up compression session
-        VTCompressionSessionInvalidate(compression_session);
+        compression_session.invalidate();
 
         // Print summary
         let total_frames = FRAME_COUNT.load(Ordering::SeqCst);
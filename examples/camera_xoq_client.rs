@@ -26,15 +26,21 @@
 //! ```
 
 use anyhow::Result;
-use moq_native::moq_lite::{Origin, Track};
+use moq_native::moq_lite::{Origin, Track, TrackConsumer};
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
+use video_toolbox_sys::helpers::{ReorderBuffer, ReorderConfig};
 use xoq::{IrohClientBuilder, IrohStream};
 
+// MoQ tracks of camera_xoq_stream
+const KEYFRAME_TRACK: &str = "video/keyframes";
+const DELTA_TRACK: &str = "video/deltas";
+
 // Statistics
 static SEGMENTS_RECEIVED: AtomicUsize = AtomicUsize::new(0);
 static BYTES_RECEIVED: AtomicUsize = AtomicUsize::new(0);
@@ -206,86 +212,117 @@ async fn run_moq_client(
         }
     };
 
-    // Subscribe to the video track
-    let track_info = Track {
-        name: "video".to_string(),
-        priority: 0,
-    };
-    let mut track = broadcast.subscribe_track(&track_info);
-    println!("Subscribed to video track. Receiving segments...\n");
+    // camera_xoq_stream publishes keyframes (with the init segment) and
+    // P-frames on separate tracks; read both and merge them by sequence number
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    for (name, priority, is_keyframe_track) in [(KEYFRAME_TRACK, 1, true), (DELTA_TRACK, 0, false)]
+    {
+        let track_info = Track {
+            name: name.to_string(),
+            priority,
+        };
+        let track = broadcast.subscribe_track(&track_info);
+        tokio::spawn(forward_track(name, track, is_keyframe_track, tx.clone()));
+    }
+    drop(tx);
+    println!("Subscribed to video tracks. Receiving segments...\n");
 
     let mut is_first = true;
-    let mut group_count = 0u64;
+    let mut chunk_count = 0u64;
+    let mut reorder = ReorderBuffer::new(ReorderConfig::default());
 
     println!("Waiting for video segments...");
     println!("(Make sure the server is actively streaming)\n");
 
     while !SHOULD_STOP.load(Ordering::SeqCst) {
-        // Get next group with timeout for better feedback
-        let group_result = tokio::time::timeout(
-            std::time::Duration::from_secs(10),
-            track.next_group(),
-        )
-        .await;
-
-        match group_result {
-            Ok(Ok(Some(mut group))) => {
-                group_count += 1;
-
-                // Read all frames from this group
-                loop {
-                    match group.read_frame().await {
-                        Ok(Some(data)) => {
-                            let mut w = writer.lock().await;
-                            w.write_segment(&data, is_first)?;
-                            is_first = false;
-                        }
-                        Ok(None) => {
-                            // No more frames in this group
-                            break;
-                        }
-                        Err(e) => {
-                            eprintln!("  Error reading frame from group {}: {:?}", group_count, e);
-                            break;
-                        }
-                    }
+        // Get next chunk with timeout for better feedback
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(10), rx.recv()).await;
+
+        match chunk {
+            Ok(Some((is_keyframe, data))) => {
+                chunk_count += 1;
+
+                // P-frames before the first keyframe cannot be decoded
+                if is_first && !is_keyframe {
+                    continue;
+                }
+                for segment in reorder.push(data, Instant::now()) {
+                    let mut w = writer.lock().await;
+                    w.write_segment(&segment, is_first)?;
+                    is_first = false;
                 }
             }
-            Ok(Ok(None)) => {
-                if group_count == 0 {
-                    println!("\nTrack ended without sending any data.");
+            Ok(None) => {
+                if chunk_count == 0 {
+                    println!("\nTracks ended without sending any data.");
                     println!("This usually means:");
                     println!("  - The server finished streaming before you connected");
                     println!("  - The server isn't actively streaming");
                     println!("\nTry: Start the client WHILE the server is streaming.");
                 } else {
-                    println!("\nTrack ended after {} groups.", group_count);
-                }
-                break;
-            }
-            Ok(Err(e)) => {
-                eprintln!("\nError getting next group: {:?}", e);
-                if group_count == 0 {
-                    eprintln!("No data received. Server may have disconnected.");
+                    println!("\nTracks ended after {} chunks.", chunk_count);
                 }
                 break;
             }
             Err(_) => {
                 // Timeout - no data in 10 seconds
-                if group_count == 0 {
+                if chunk_count == 0 {
                     println!("  Still waiting for first segment... (10s timeout)");
                     println!("  Hint: Is the server actively streaming?");
                 } else {
-                    println!("  No new segments in 10s (received {} so far)", group_count);
+                    println!("  No new segments in 10s (received {} so far)", chunk_count);
                 }
                 // Continue waiting
             }
         }
+        for segment in reorder.poll(Instant::now()) {
+            let mut w = writer.lock().await;
+            w.write_segment(&segment, false)?;
+        }
+    }
+
+    let mut w = writer.lock().await;
+    for segment in reorder.flush() {
+        w.write_segment(&segment, false)?;
     }
 
     Ok(())
 }
 
+/// Forward the frames of every group of a track, tagged with whether they
+/// come from the keyframe track, until it ends.
+async fn forward_track(
+    name: &'static str,
+    mut track: TrackConsumer,
+    is_keyframe_track: bool,
+    tx: tokio::sync::mpsc::UnboundedSender<(bool, Vec<u8>)>,
+) {
+    loop {
+        match track.next_group().await {
+            Ok(Some(mut group)) => loop {
+                match group.read_frame().await {
+                    Ok(Some(data)) => {
+                        if tx.send((is_keyframe_track, data.to_vec())).is_err() {
+                            return;
+                        }
+                    }
+                    // No more frames in this group
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("  Error reading frame from {}: {:?}", name, e);
+                        break;
+                    }
+                }
+            },
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("\nError getting next group of {}: {:?}", name, e);
+                return;
+            }
+        }
+    }
+}
+
 fn print_help() {
     println!("Usage: camera_xoq_client [OPTIONS] [PATH_OR_SERVER_ID]");
     println!();
@@ -11,6 +11,9 @@
 //!
 //! # iroh mode (P2P)
 //! cargo run --example camera_xoq_player --features xoq-player -- --iroh <SERVER_ID>
+//!
+//! # Decode right away when joining mid-stream instead of waiting for a keyframe
+//! cargo run --example camera_xoq_player --features xoq-player -- --decode-immediately
 //! ```
 
 use anyhow::{anyhow, Result};
@@ -22,18 +25,27 @@ use core_foundation_sys::base::OSStatus;
 use core_media_sys::CMTime;
 use libc::c_void;
 use minifb::{Key, Window, WindowOptions};
-use moq_native::moq_lite::{Origin, Track};
+use moq_native::moq_lite::{Origin, Track, TrackConsumer};
 use std::ptr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use video_toolbox_sys::cv_types::CVPixelBufferRef;
 use video_toolbox_sys::decompression::{
-    VTDecompressionOutputCallbackRecord, VTDecompressionSessionCreate,
+    DecodeFrameFlags, VTDecompressionOutputCallbackRecord, VTDecompressionSessionCreate,
     VTDecompressionSessionDecodeFrame, VTDecompressionSessionInvalidate,
     VTDecompressionSessionRef,
 };
+use video_toolbox_sys::helpers::{
+    create_encoded_sample_buffer, parse_avcc, AccessUnit, AccessUnitAssembler, ReorderBuffer,
+    ReorderConfig, SampleTiming, StartupGate, StartupPolicy, StartupState,
+};
 use xoq::{IrohClientBuilder, IrohStream};
 
+// MoQ tracks of camera_xoq_stream, merged by fragment sequence number
+const KEYFRAME_TRACK: &str = "video/keyframes";
+const DELTA_TRACK: &str = "video/deltas";
+
 // Window parameters
 const WINDOW_WIDTH: usize = 1280;
 const WINDOW_HEIGHT: usize = 720;
@@ -42,6 +54,7 @@ const WINDOW_HEIGHT: usize = 720;
 static FRAMES_DECODED: AtomicUsize = AtomicUsize::new(0);
 static SEGMENTS_RECEIVED: AtomicUsize = AtomicUsize::new(0);
 static SHOULD_STOP: AtomicBool = AtomicBool::new(false);
+static DECODE_IMMEDIATELY: AtomicBool = AtomicBool::new(false);
 
 // Frame buffer for display
 static FRAME_BUFFER: Mutex<Option<Vec<u32>>> = Mutex::new(None);
@@ -57,33 +70,6 @@ extern "C" {
         nal_unit_header_length: i32,
         format_description_out: *mut *mut c_void,
     ) -> OSStatus;
-
-    fn CMSampleBufferCreate(
-        allocator: *const c_void,
-        data_buffer: *const c_void,
-        data_ready: bool,
-        make_data_ready_callback: *const c_void,
-        make_data_ready_refcon: *const c_void,
-        format_description: *const c_void,
-        num_samples: i64,
-        num_sample_timing_entries: i64,
-        sample_timing_array: *const CMSampleTimingInfo,
-        num_sample_size_entries: i64,
-        sample_size_array: *const usize,
-        sample_buffer_out: *mut *mut c_void,
-    ) -> OSStatus;
-
-    fn CMBlockBufferCreateWithMemoryBlock(
-        allocator: *const c_void,
-        memory_block: *mut c_void,
-        block_length: usize,
-        block_allocator: *const c_void,
-        custom_block_source: *const c_void,
-        offset_to_data: usize,
-        data_length: usize,
-        flags: u32,
-        block_buffer_out: *mut *mut c_void,
-    ) -> OSStatus;
 }
 
 #[link(name = "CoreVideo", kind = "framework")]
@@ -96,14 +82,6 @@ extern "C" {
     fn CVPixelBufferGetBytesPerRow(pixel_buffer: CVPixelBufferRef) -> usize;
 }
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-struct CMSampleTimingInfo {
-    duration: CMTime,
-    presentation_time_stamp: CMTime,
-    decode_time_stamp: CMTime,
-}
-
 /// Parsed CMAF init segment containing codec configuration
 struct InitSegment {
     sps: Vec<u8>,
@@ -230,9 +208,9 @@ fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
     None
 }
 
-/// Parse media segment to extract NAL units from mdat
-fn parse_media_segment(data: &[u8]) -> Result<Vec<Vec<u8>>> {
-    let mut nal_units = Vec::new();
+/// Parse media segment to extract access units (complete frames) from mdat
+fn parse_media_segment(data: &[u8]) -> Result<Vec<AccessUnit>> {
+    let mut access_units = Vec::new();
     let mut pos = 0;
 
     // Find mdat box
@@ -245,33 +223,20 @@ fn parse_media_segment(data: &[u8]) -> Result<Vec<Vec<u8>>> {
         }
 
         if box_type == b"mdat" {
-            // Parse AVCC-formatted NAL units (4-byte length prefix)
+            // Parse AVCC-formatted NAL units (4-byte length prefix) and group
+            // slices and SEI into frames; segments end on a frame boundary
             let mdat_data = &data[pos + 8..pos + box_size];
-            let mut mdat_pos = 0;
-
-            while mdat_pos + 4 <= mdat_data.len() {
-                let nal_len = u32::from_be_bytes([
-                    mdat_data[mdat_pos],
-                    mdat_data[mdat_pos + 1],
-                    mdat_data[mdat_pos + 2],
-                    mdat_data[mdat_pos + 3],
-                ]) as usize;
-                mdat_pos += 4;
-
-                if mdat_pos + nal_len > mdat_data.len() {
-                    break;
-                }
-
-                nal_units.push(mdat_data[mdat_pos..mdat_pos + nal_len].to_vec());
-                mdat_pos += nal_len;
-            }
+            let nal_units = parse_avcc(mdat_data, 4).map_err(|e| anyhow!("{}", e))?;
+            let mut assembler = AccessUnitAssembler::new();
+            access_units = assembler.push_all(nal_units);
+            access_units.extend(assembler.end_access_unit());
             break;
         }
 
         pos += box_size;
     }
 
-    Ok(nal_units)
+    Ok(access_units)
 }
 
 /// Decompression output callback
@@ -339,6 +304,8 @@ extern "C" fn decompression_callback(
 struct VideoDecoder {
     session: VTDecompressionSessionRef,
     format_desc: *mut c_void,
+    /// Discards P-frames until the first IDR when joining mid-stream
+    startup: StartupGate,
 }
 
 unsafe impl Send for VideoDecoder {}
@@ -408,102 +375,50 @@ impl VideoDecoder {
                 return Err(anyhow!("Failed to create decompression session: {}", status));
             }
 
+            let policy = if DECODE_IMMEDIATELY.load(Ordering::SeqCst) {
+                StartupPolicy::DecodeImmediately
+            } else {
+                StartupPolicy::WaitForKeyframe
+            };
+
             Ok(Self {
                 session,
                 format_desc,
+                startup: StartupGate::new(policy),
             })
         }
     }
 
-    fn decode(&mut self, nal_data: &[u8]) -> Result<()> {
-        unsafe {
-            // Create AVCC-formatted data (4-byte length prefix)
-            // Box it to ensure stable memory address
-            let mut avcc_data: Box<Vec<u8>> = Box::new(Vec::with_capacity(4 + nal_data.len()));
-            avcc_data.extend_from_slice(&(nal_data.len() as u32).to_be_bytes());
-            avcc_data.extend_from_slice(nal_data);
-
-            // Create block buffer with copy flag to ensure data is copied
-            let mut block_buffer: *mut c_void = ptr::null_mut();
-            let status = CMBlockBufferCreateWithMemoryBlock(
-                ptr::null(),                           // allocator
-                avcc_data.as_mut_ptr() as *mut c_void, // memory block
-                avcc_data.len(),                       // block length
-                ptr::null(),                           // block allocator (NULL = don't free)
-                ptr::null(),                           // custom block source
-                0,                                     // offset
-                avcc_data.len(),                       // data length
-                0,                                     // flags
-                &mut block_buffer,
-            );
-
-            if status != 0 {
-                eprintln!("CMBlockBufferCreate failed: {}", status);
-                return Err(anyhow!("Failed to create block buffer: {}", status));
-            }
-
-            // Create sample buffer
-            let timing = CMSampleTimingInfo {
-                duration: CMTime {
-                    value: 1,
-                    timescale: 30,
-                    flags: 1,
-                    epoch: 0,
-                },
-                presentation_time_stamp: CMTime {
-                    value: SEGMENTS_RECEIVED.load(Ordering::SeqCst) as i64,
-                    timescale: 30,
-                    flags: 1,
-                    epoch: 0,
-                },
-                decode_time_stamp: CMTime {
-                    value: SEGMENTS_RECEIVED.load(Ordering::SeqCst) as i64,
-                    timescale: 30,
-                    flags: 1,
-                    epoch: 0,
-                },
-            };
-
-            let sample_size = avcc_data.len();
-            let mut sample_buffer: *mut c_void = ptr::null_mut();
+    fn decode(&mut self, access_unit: &AccessUnit) -> Result<()> {
+        if !self.startup.admit(access_unit.is_keyframe()) {
+            return Ok(());
+        }
 
-            let status = CMSampleBufferCreate(
-                ptr::null(),
-                block_buffer,
-                true,
-                ptr::null(),
-                ptr::null(),
-                self.format_desc,
-                1,
-                1,
-                &timing,
-                1,
-                &sample_size,
-                &mut sample_buffer,
-            );
+        // One AVCC sample (4-byte length prefixes) for all slices of the frame
+        let avcc_data = access_unit.to_avcc(4).map_err(|e| anyhow!("{}", e))?;
+        let frame = SEGMENTS_RECEIVED.load(Ordering::SeqCst) as i64;
+        let timing = SampleTiming {
+            pts: frame,
+            dts: frame,
+            duration: 1,
+            timescale: 30,
+        };
 
-            if status != 0 {
-                eprintln!("CMSampleBufferCreate failed: {}", status);
-                CFRelease(block_buffer as CFTypeRef);
-                return Err(anyhow!("Failed to create sample buffer: {}", status));
-            }
+        unsafe {
+            // The sample buffer owns a copy of the data and is released on drop
+            let sample_buffer = create_encoded_sample_buffer(&avcc_data, self.format_desc, timing)
+                .map_err(|status| anyhow!("Failed to create sample buffer: {}", status))?;
 
             // Decode synchronously (don't use async for debugging)
             let mut info_flags: u32 = 0;
             let status = VTDecompressionSessionDecodeFrame(
                 self.session,
-                sample_buffer as *mut _,
-                0, // Synchronous decode for debugging
+                sample_buffer.as_raw(),
+                DecodeFrameFlags::empty().bits(), // Synchronous decode for debugging
                 ptr::null_mut(),
                 &mut info_flags,
             );
 
-            // Clean up - must happen after synchronous decode completes
-            // Note: CMSampleBufferCreate with dataReady=true takes ownership of block_buffer,
-            // so releasing sample_buffer also releases block_buffer. Don't release it separately.
-            CFRelease(sample_buffer as CFTypeRef);
-            // avcc_data (Box) is dropped here, which is safe after sync decode
-
             if status != 0 {
                 eprintln!("VTDecompressionSessionDecodeFrame failed: {}", status);
                 return Err(anyhow!("Failed to decode frame: {}", status));
@@ -512,6 +427,10 @@ impl VideoDecoder {
             Ok(())
         }
     }
+
+    fn startup_state(&self) -> StartupState {
+        self.startup.state()
+    }
 }
 
 impl Drop for VideoDecoder {
@@ -592,13 +511,16 @@ async fn run_iroh_receiver(
                             eprintln!("[iroh] Not an init segment: {}", e);
                         }
                     }
-                } else {
+                }
+                // Keyframe chunks carry the init segment ahead of their
+                // fragment, so the chunk that created the decoder is decoded too
+                if init_received {
                     match parse_media_segment(&data) {
-                        Ok(nal_units) => {
+                        Ok(access_units) => {
                             if let Ok(mut dec_guard) = decoder.lock() {
                                 if let Some(ref mut dec) = *dec_guard {
-                                    for nal in nal_units {
-                                        let _ = dec.decode(&nal);
+                                    for access_unit in &access_units {
+                                        let _ = dec.decode(access_unit);
                                     }
                                 }
                             }
@@ -651,66 +573,55 @@ async fn run_moq_client(relay_url: Option<&str>, path: &str, decoder: Arc<Mutex<
         }
     };
 
-    let track_info = Track { name: "video".to_string(), priority: 0 };
-    let mut track = broadcast.subscribe_track(&track_info);
-    println!("Subscribed to video track.\n");
+    // Keyframes and P-frames are published on separate tracks; read both and
+    // merge them by sequence number below
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    for (name, priority) in [(KEYFRAME_TRACK, 1), (DELTA_TRACK, 0)] {
+        let track_info = Track { name: name.to_string(), priority };
+        let track = broadcast.subscribe_track(&track_info);
+        tokio::spawn(forward_track(name, track, tx.clone()));
+    }
+    drop(tx);
+    println!("Subscribed to video tracks.\n");
 
     let mut init_received = false;
+    // Groups may arrive out of order from the relay; decode in sequence order
+    let reorder_config = ReorderConfig::default();
+    let max_wait = reorder_config.max_wait;
+    let mut reorder = ReorderBuffer::new(reorder_config);
 
     while !SHOULD_STOP.load(Ordering::SeqCst) {
-        match tokio::time::timeout(std::time::Duration::from_secs(5), track.next_group()).await {
-            Ok(Ok(Some(mut group))) => {
-                while let Ok(Some(data)) = group.read_frame().await {
-                    SEGMENTS_RECEIVED.fetch_add(1, Ordering::SeqCst);
-
-                    if !init_received {
-                        match parse_init_segment(&data) {
-                            Ok(init) => {
-                                println!("Init segment: {}x{}, SPS: {} bytes, PPS: {} bytes",
-                                         init.width, init.height, init.sps.len(), init.pps.len());
-                                match VideoDecoder::new(&init) {
-                                    Ok(dec) => {
-                                        println!("Decoder created successfully!");
-                                        *decoder.lock().unwrap() = Some(dec);
-                                        init_received = true;
-                                    }
-                                    Err(e) => eprintln!("Failed to create decoder: {}", e),
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Not an init segment: {}", e);
-                            }
-                        }
-                    } else {
-                        match parse_media_segment(&data) {
-                            Ok(nal_units) => {
-                                if nal_units.is_empty() {
-                                    eprintln!("No NAL units found in segment");
-                                } else {
-                                    if let Ok(mut dec_guard) = decoder.lock() {
-                                        if let Some(ref mut dec) = *dec_guard {
-                                            for (i, nal) in nal_units.iter().enumerate() {
-                                                let nal_type = if !nal.is_empty() { nal[0] & 0x1F } else { 0 };
-                                                if let Err(e) = dec.decode(nal) {
-                                                    eprintln!("Decode NAL {} (type {}, {} bytes) failed: {}",
-                                                             i, nal_type, nal.len(), e);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
+        let timeout = if reorder.pending() > 0 { max_wait } else { Duration::from_secs(5) };
+        match tokio::time::timeout(timeout, rx.recv()).await {
+            Ok(Some(data)) => {
+                SEGMENTS_RECEIVED.fetch_add(1, Ordering::SeqCst);
+
+                // P-frames of a GOP joined midway are skipped until the
+                // next keyframe chunk brings the init segment
+                if !init_received {
+                    if let Ok(init) = parse_init_segment(&data) {
+                        println!("Init segment: {}x{}, SPS: {} bytes, PPS: {} bytes",
+                                 init.width, init.height, init.sps.len(), init.pps.len());
+                        match VideoDecoder::new(&init) {
+                            Ok(dec) => {
+                                println!("Decoder created successfully!");
+                                *decoder.lock().unwrap() = Some(dec);
+                                init_received = true;
                             }
-                            Err(e) => eprintln!("Failed to parse media segment: {}", e),
+                            Err(e) => eprintln!("Failed to create decoder: {}", e),
                         }
                     }
                 }
+                // Keyframe chunks carry the init segment ahead of their
+                // fragment, so the chunk that created the decoder is decoded too
+                if init_received {
+                    for segment in reorder.push(data, Instant::now()) {
+                        decode_media_segment(&segment, &decoder);
+                    }
+                }
             }
-            Ok(Ok(None)) => {
-                println!("Track ended.");
-                break;
-            }
-            Ok(Err(e)) => {
-                eprintln!("Error: {:?}", e);
+            Ok(None) => {
+                println!("Tracks ended.");
                 break;
             }
             Err(_) => {
@@ -719,17 +630,69 @@ async fn run_moq_client(relay_url: Option<&str>, path: &str, decoder: Arc<Mutex<
                 }
             }
         }
+        for segment in reorder.poll(Instant::now()) {
+            decode_media_segment(&segment, &decoder);
+        }
+    }
+
+    let stats = reorder.stats();
+    if stats.reordered > 0 || stats.skipped > 0 {
+        println!("Reordered {} segments, skipped {}, dropped {} late",
+                 stats.reordered, stats.skipped, stats.late);
     }
 
     Ok(())
 }
 
+/// Forward the frames of every group of a track until it ends.
+async fn forward_track(name: &'static str, mut track: TrackConsumer, tx: tokio::sync::mpsc::UnboundedSender<Vec<u8>>) {
+    loop {
+        match track.next_group().await {
+            Ok(Some(mut group)) => {
+                while let Ok(Some(data)) = group.read_frame().await {
+                    if tx.send(data.to_vec()).is_err() {
+                        return;
+                    }
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Error on {}: {:?}", name, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Decode the frames of one media segment
+fn decode_media_segment(data: &[u8], decoder: &Mutex<Option<VideoDecoder>>) {
+    match parse_media_segment(data) {
+        Ok(access_units) => {
+            if access_units.is_empty() {
+                eprintln!("No frames found in segment");
+            } else if let Ok(mut dec_guard) = decoder.lock() {
+                if let Some(ref mut dec) = *dec_guard {
+                    for (i, access_unit) in access_units.iter().enumerate() {
+                        if let Err(e) = dec.decode(access_unit) {
+                            eprintln!("Decode frame {} ({} NAL units) failed: {}",
+                                     i, access_unit.nal_units.len(), e);
+                        }
+                    }
+                }
+            }
+        }
+        Err(e) => eprintln!("Failed to parse media segment: {}", e),
+    }
+}
+
 fn print_help() {
     println!("Usage: camera_xoq_player [OPTIONS] [PATH_OR_SERVER_ID]");
     println!();
     println!("Options:");
     println!("  --relay <URL>   Custom MoQ relay URL");
     println!("  --iroh          Use iroh P2P mode");
+    println!("  --decode-immediately");
+    println!("                  Decode before the first keyframe (may show artifacts)");
     println!("  -h, --help      Show help");
 }
 
@@ -756,6 +719,10 @@ async fn main() -> Result<()> {
                 use_iroh = true;
                 i += 1;
             }
+            "--decode-immediately" => {
+                DECODE_IMMEDIATELY.store(true, Ordering::SeqCst);
+                i += 1;
+            }
             "--help" | "-h" => {
                 print_help();
                 return Ok(());
@@ -832,6 +799,7 @@ async fn main() -> Result<()> {
 
     // Main display loop - keep last frame to avoid black flicker
     let mut display_buffer = vec![0u32; WINDOW_WIDTH * WINDOW_HEIGHT];
+    let mut startup_state = None;
 
     while window.is_open() && !window.is_key_down(Key::Escape) && !SHOULD_STOP.load(Ordering::SeqCst) {
         // Give tokio tasks a chance to run
@@ -845,6 +813,18 @@ async fn main() -> Result<()> {
             }
         }
 
+        // Show the startup state in the title until the picture is clean
+        let state = decoder.lock().unwrap().as_ref().map(VideoDecoder::startup_state);
+        if state != startup_state {
+            startup_state = state;
+            window.set_title(match state {
+                None => "Camera XOQ Player - connecting",
+                Some(StartupState::WaitingForKeyframe) => "Camera XOQ Player - waiting for keyframe",
+                Some(StartupState::Provisional) => "Camera XOQ Player - no keyframe yet",
+                Some(StartupState::Synced) => "Camera XOQ Player",
+            });
+        }
+
         window.update_with_buffer(&display_buffer, WINDOW_WIDTH, WINDOW_HEIGHT)?;
 
         // Print stats periodically
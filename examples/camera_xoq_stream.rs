@@ -8,8 +8,20 @@
 //!
 //! # MoQ Structure (relay mode)
 //!
-//! - **Group 0**: Initialization segment (ftyp + moov)
-//! - **Group 1, 2, ...**: Media segments (moof + mdat)
+//! moq-lite only prioritizes whole tracks, so each GOP is split over two
+//! tracks carrying the priorities of
+//! [`MoqPrioritizer`](video_toolbox_sys::helpers::MoqPrioritizer):
+//!
+//! - **`video/keyframes`** (init priority): one group per keyframe, holding
+//!   the keyframe chunk with the init segment
+//! - **`video/deltas`** (delta priority): one group per keyframe, holding the
+//!   P-frame chunks depending on it
+//!
+//! Under congestion relays send the keyframe tracks' groups first, so a
+//! viewer can always join. moq-lite has no group deadline; relays deliver
+//! the newest group of a track first and drop older ones, which is what the
+//! deadline hint asks for, so the deadline is only logged. Players merge the
+//! two tracks by `mfhd` sequence number.
 //!
 //! # iroh Structure (P2P mode)
 //!
@@ -60,7 +72,8 @@ use video_toolbox_sys::compression::{
 use video_toolbox_sys::cv_types::CVPixelBufferRef;
 use video_toolbox_sys::helpers::{
     create_capture_delegate, create_dispatch_queue, run_for_duration, set_sample_buffer_delegate,
-    CmafConfig, CmafMuxer, CompressionSessionBuilder, DelegateCallback, NalExtractor,
+    CompressionSessionBuilder, CompressionSessionConfig, DelegateCallback, LowLatencyChunk,
+    LowLatencyConfig, LowLatencyMuxer, MoqPrioritizer, MoqPriorityConfig,
 };
 use xoq::IrohStream;
 
@@ -68,9 +81,9 @@ use xoq::IrohStream;
 const WIDTH: i32 = 1280;
 const HEIGHT: i32 = 720;
 const FRAME_RATE: f64 = 30.0;
-const BITRATE: i64 = 8_000_000; // 8 Mbps (higher for all-keyframe encoding)
+const BITRATE: i64 = 4_000_000; // 4 Mbps
 const RECORD_DURATION_SECS: u64 = 30;
-const FRAGMENT_DURATION_MS: u32 = 33; // ~1 frame at 30fps for lowest latency
+const KEYFRAME_INTERVAL: Duration = Duration::from_secs(1); // join delay for new viewers
 
 // Global state
 static FRAME_COUNT: AtomicUsize = AtomicUsize::new(0);
@@ -79,12 +92,22 @@ static GROUP_COUNT: AtomicUsize = AtomicUsize::new(0);
 static SHOULD_STOP: AtomicBool = AtomicBool::new(false);
 static INIT_SENT: AtomicBool = AtomicBool::new(false);
 
+/// Track carrying the init segment and keyframe of each GOP
+const KEYFRAME_TRACK: &str = "video/keyframes";
+/// Track carrying the P-frames of each GOP
+const DELTA_TRACK: &str = "video/deltas";
+
 /// Transport mode for streaming
 enum TransportWriter {
     /// MoQ with proper group semantics
-    /// We need to keep the BroadcastProducer alive or the track gets reset
+    /// We need to keep the BroadcastProducer alive or the tracks get reset
     Moq {
-        track: moq_lite::TrackProducer,
+        keyframes: moq_lite::TrackProducer,
+        deltas: moq_lite::TrackProducer,
+        /// Delta group of the latest keyframe, receiving the frames that
+        /// follow it
+        group: Option<moq_lite::GroupProducer>,
+        prioritizer: MoqPrioritizer,
         _broadcast: moq_lite::BroadcastProducer,
     },
     /// iroh P2P with length-prefixed framing
@@ -93,12 +116,8 @@ enum TransportWriter {
 
 // Thread-safe wrapper for streaming context
 struct StreamingContext {
-    muxer: CmafMuxer,
-    extractor: NalExtractor,
+    muxer: LowLatencyMuxer,
     transport: TransportWriter,
-    initialized: bool,
-    /// Stored init segment for late joiners (prepended to keyframe segments)
-    init_segment: Option<Vec<u8>>,
 }
 
 unsafe impl Send for StreamingContext {}
@@ -118,9 +137,39 @@ extern "C" {
     fn CMSampleBufferGetImageBuffer(sbuf: *const c_void) -> CVPixelBufferRef;
 }
 
-/// Write data as a MoQ frame (each frame becomes its own group).
-fn write_moq_group(track: &mut moq_lite::TrackProducer, data: &[u8]) {
-    track.write_frame(Bytes::copy_from_slice(data));
+/// Write a chunk to the track matching its priority: keyframe chunks as a
+/// group of their own on the keyframe track, P-frames into the delta group
+/// of their keyframe.
+///
+/// moq-lite only takes a priority per track, so the hint's priority picks
+/// the track. Its deadline cannot be put on the wire; starting new groups
+/// at each keyframe is what lets relays drop the stale ones.
+fn write_moq_chunk(
+    keyframes: &mut moq_lite::TrackProducer,
+    deltas: &mut moq_lite::TrackProducer,
+    group: &mut Option<moq_lite::GroupProducer>,
+    prioritizer: &mut MoqPrioritizer,
+    chunk: &LowLatencyChunk,
+) {
+    let hint = prioritizer.chunk(chunk);
+    if hint.starts_group || group.is_none() {
+        // Replacing the producer closes the previous delta group
+        *group = Some(deltas.append_group());
+        println!(
+            "  Group {}: priority {}, deadline hint {:?} (not sent)",
+            hint.group,
+            hint.priority,
+            hint.deadline.unwrap_or_default()
+        );
+    }
+    if chunk.is_keyframe {
+        // Dropping the producer closes the single-chunk group
+        keyframes
+            .append_group()
+            .write_frame(Bytes::from(chunk.to_bytes()));
+    } else if let Some(group) = group.as_mut() {
+        group.write_frame(Bytes::from(chunk.to_bytes()));
+    }
 }
 
 /// Write data to iroh stream with length prefix.
@@ -143,11 +192,17 @@ fn write_iroh_frame(stream: &std::sync::Arc<tokio::sync::Mutex<Option<IrohStream
     }
 }
 
-/// Write segment data using the appropriate transport.
-fn write_segment(transport: &mut TransportWriter, data: &[u8]) {
+/// Write a muxed chunk using the appropriate transport.
+fn write_chunk(transport: &mut TransportWriter, chunk: &LowLatencyChunk) {
     match transport {
-        TransportWriter::Moq { track, .. } => write_moq_group(track, data),
-        TransportWriter::Iroh(stream) => write_iroh_frame(stream, data),
+        TransportWriter::Moq {
+            keyframes,
+            deltas,
+            group,
+            prioritizer,
+            ..
+        } => write_moq_chunk(keyframes, deltas, group, prioritizer, chunk),
+        TransportWriter::Iroh(stream) => write_iroh_frame(stream, &chunk.to_bytes()),
     }
 }
 
@@ -180,123 +235,51 @@ extern "C" fn compression_output_callback(
         None => return,
     };
 
-    unsafe {
-        // Get format description and initialize muxer if needed
-        if !ctx.initialized {
-            if let Some(format_desc) = ctx.extractor.get_format_description(sample_buffer) {
-                match ctx.extractor.extract_parameter_sets(format_desc) {
-                    Ok(params) => match ctx.extractor.get_dimensions(format_desc) {
-                        Ok(dims) => {
-                            // Create initialization segment
-                            let init_segment = ctx.muxer.create_init_segment(
-                                &params.sps,
-                                &params.pps,
-                                dims.width,
-                                dims.height,
-                            );
-
-                            // Store init segment for late joiners
-                            ctx.init_segment = Some(init_segment.clone());
-
-                            // Send init segment
-                            write_segment(&mut ctx.transport, &init_segment);
-                            let group_num = GROUP_COUNT.fetch_add(1, Ordering::SeqCst);
-                            INIT_SENT.store(true, Ordering::SeqCst);
-                            println!(
-                                "  Sent init segment as frame {} ({} bytes)",
-                                group_num,
-                                init_segment.len()
-                            );
-                            ctx.initialized = true;
-                        }
-                        Err(e) => eprintln!("Failed to get dimensions: {}", e),
-                    },
-                    Err(e) => eprintln!("Failed to extract parameter sets: {}", e),
-                }
-            }
-        }
-
-        if !ctx.initialized {
+    // One chunk per frame; keyframe chunks carry the init segment for late joiners
+    let chunk = match unsafe { ctx.muxer.push_sample_buffer(sample_buffer) } {
+        Ok(Some(chunk)) => chunk,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Failed to mux frame: {}", e);
             return;
         }
+    };
 
-        // Extract NAL units from the encoded frame
-        let nal_units = match ctx.extractor.extract_nal_units(sample_buffer) {
-            Ok(nals) => nals,
-            Err(e) => {
-                eprintln!("Failed to extract NAL units: {}", e);
-                return;
-            }
-        };
-
-        // Get timing information
-        let timing = ctx.extractor.get_timing(sample_buffer);
-        let is_keyframe = ctx.extractor.is_keyframe(sample_buffer);
-
-        // Convert timing to muxer's timescale (90000)
-        let target_timescale = 90000i32;
-        let pts = (timing.pts as f64 * target_timescale as f64 / timing.timescale as f64) as i64;
-        let dts = (timing.dts as f64 * target_timescale as f64 / timing.timescale as f64) as i64;
-        let duration =
-            (timing.duration as f64 * target_timescale as f64 / timing.timescale as f64) as u32;
-
-        // Add frame to muxer - when a segment is complete, send it
-        if let Some(segment) = ctx.muxer.add_frame(&nal_units, pts, dts, duration, is_keyframe) {
-            // For keyframe segments, prepend init segment for late joiners
-            // (they need both init + keyframe to start decoding)
-            // Non-keyframe segments are sent as-is since they're smaller
-            let data_to_send = if is_keyframe {
-                if let Some(ref init) = ctx.init_segment {
-                    let mut combined = init.clone();
-                    combined.extend_from_slice(&segment);
-                    combined
-                } else {
-                    segment.clone()
-                }
-            } else {
-                segment.clone()
-            };
-
-            write_segment(&mut ctx.transport, &data_to_send);
-            let group_num = GROUP_COUNT.fetch_add(1, Ordering::SeqCst);
-            if is_keyframe {
-                println!(
-                    "  Sent keyframe segment as frame {} ({} bytes + init)",
-                    group_num,
-                    segment.len()
-                );
-            } else {
-                println!(
-                    "  Sent media segment as frame {} ({} bytes)",
-                    group_num,
-                    segment.len()
-                );
-            }
-        }
+    write_chunk(&mut ctx.transport, &chunk);
+    INIT_SENT.store(true, Ordering::SeqCst);
+    let group_num = GROUP_COUNT.fetch_add(1, Ordering::SeqCst);
+    if chunk.is_keyframe {
+        println!(
+            "  Sent keyframe chunk as frame {} ({} bytes + init)",
+            group_num,
+            chunk.fragment.len()
+        );
+    }
 
-        let frame_num = ENCODED_FRAMES.fetch_add(1, Ordering::SeqCst) + 1;
-        if frame_num % 30 == 0 {
-            println!("  Encoded {} frames...", frame_num);
-        }
+    let frame_num = ENCODED_FRAMES.fetch_add(1, Ordering::SeqCst) + 1;
+    if frame_num % 30 == 0 {
+        println!("  Encoded {} frames...", frame_num);
     }
 }
 
-fn create_compression_session() -> Result<VTCompressionSessionRef, OSStatus> {
+fn create_compression_session(
+    muxer: &LowLatencyMuxer,
+) -> Result<VTCompressionSessionRef, OSStatus> {
+    let mut config = CompressionSessionConfig::new(WIDTH, HEIGHT, codecs::video::H264);
+    config.frame_rate = Some(FRAME_RATE);
+    muxer.configure(&mut config);
     unsafe {
-        CompressionSessionBuilder::new(WIDTH, HEIGHT, codecs::video::H264)
+        CompressionSessionBuilder::from_config(config)
             .pixel_format(codecs::pixel::BGRA32)
             .hardware_accelerated(true)
             .bitrate(BITRATE)
-            .frame_rate(FRAME_RATE)
-            .keyframe_interval(1) // Every frame is a keyframe for lowest latency
-            .real_time(true)
             .profile_level(kVTProfileLevel_H264_High_AutoLevel)
             .build_with_context(Some(compression_output_callback), ptr::null_mut())
     }
 }
 
 // Delegate callback for video frame capture
-extern "C" fn capture_output_did_output(
+extern "C-unwind" fn capture_output_did_output(
     _this: *mut c_void,
     _cmd: Sel,
     _output: *mut c_void,
@@ -429,7 +412,10 @@ async fn main() -> anyhow::Result<()> {
     println!("Resolution: {}x{}", WIDTH, HEIGHT);
     println!("Frame rate: {} fps", FRAME_RATE);
     println!("Bitrate: {} Mbps", BITRATE / 1_000_000);
-    println!("Fragment duration: {} ms", FRAGMENT_DURATION_MS);
+    println!(
+        "Keyframe interval: {:?} (one chunk per frame)",
+        KEYFRAME_INTERVAL
+    );
     println!("Duration: {} seconds", RECORD_DURATION_SECS);
     println!(
         "Transport: {}",
@@ -472,8 +458,9 @@ async fn main() -> anyhow::Result<()> {
         );
         println!();
         println!("MoQ Structure:");
-        println!("  - Group 0: Init segment");
-        println!("  - Group 1+: Media segments");
+        println!("  - {}: init + keyframe, one group per GOP", KEYFRAME_TRACK);
+        println!("  - {}: P-frames, one group per GOP", DELTA_TRACK);
+        println!("  - Keyframe track above the delta track; stale groups are dropped");
         println!();
         println!("Connecting to MoQ relay...");
 
@@ -495,43 +482,44 @@ async fn main() -> anyhow::Result<()> {
             }
         };
 
-        println!("Connected! Creating video track...");
+        println!("Connected! Creating video tracks...");
 
+        let prioritizer = MoqPrioritizer::new(MoqPriorityConfig::default(), KEYFRAME_INTERVAL);
+        let priorities = prioritizer.config();
         let mut broadcast = Broadcast::produce();
-        let track = broadcast.producer.create_track(Track {
-            name: "video".to_string(),
-            priority: 0,
+        // Keyframe chunks carry the init segment, so they take its priority
+        let keyframes = broadcast.producer.create_track(Track {
+            name: KEYFRAME_TRACK.to_string(),
+            priority: priorities.init.max(priorities.keyframe),
+        });
+        let deltas = broadcast.producer.create_track(Track {
+            name: DELTA_TRACK.to_string(),
+            priority: priorities.delta,
         });
         origin.producer.publish_broadcast("", broadcast.consumer);
 
-        println!("Video track created.\n");
+        println!("Video tracks created.\n");
         TransportWriter::Moq {
-            track,
+            keyframes,
+            deltas,
+            group: None,
+            prioritizer,
             _broadcast: broadcast.producer,
         }
     };
 
     unsafe {
         // Initialize streaming context
-        {
-            let muxer = CmafMuxer::new(CmafConfig {
-                fragment_duration_ms: FRAGMENT_DURATION_MS,
-                timescale: 90000,
-            });
-
-            let mut ctx = STREAMING_CONTEXT.lock().unwrap();
-            *ctx = Some(StreamingContext {
-                muxer,
-                extractor: NalExtractor::new(),
-                transport,
-                initialized: false,
-                init_segment: None,
-            });
-        }
+        let muxer = LowLatencyMuxer::new(LowLatencyConfig {
+            keyframe_interval: KEYFRAME_INTERVAL,
+            ..Default::default()
+        });
 
         // Create VideoToolbox compression session
         println!("Creating H.264 compression session...");
-        let compression_session = match create_compression_session() {
+        let session_result = create_compression_session(&muxer);
+        *STREAMING_CONTEXT.lock().unwrap() = Some(StreamingContext { muxer, transport });
+        let compression_session = match session_result {
             Ok(s) => s,
             Err(e) => {
                 eprintln!("Failed to create compression session: OSStatus {}", e);
@@ -657,19 +645,16 @@ async fn main() -> anyhow::Result<()> {
 
         std::thread::sleep(Duration::from_millis(500));
 
-        // Flush remaining frames
+        // Completing the frames sent the last chunks through write_chunk, as
+        // every frame is its own chunk; close the last MoQ delta group
         {
             let mut ctx_guard = STREAMING_CONTEXT.lock().unwrap();
-            if let Some(ctx) = ctx_guard.as_mut() {
-                if let Some(segment) = ctx.muxer.flush() {
-                    write_segment(&mut ctx.transport, &segment);
-                    let group_num = GROUP_COUNT.fetch_add(1, Ordering::SeqCst);
-                    println!(
-                        "  Sent final segment as frame {} ({} bytes)",
-                        group_num,
-                        segment.len()
-                    );
-                }
+            if let Some(StreamingContext {
+                transport: TransportWriter::Moq { group, .. },
+                ..
+            }) = ctx_guard.as_mut()
+            {
+                group.take();
             }
         }
 
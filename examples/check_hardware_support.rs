@@ -4,14 +4,8 @@
 
 extern crate video_toolbox_sys;
 
-use video_toolbox_sys::utilities::VTIsHardwareDecodeSupported;
-
-// Common codec FourCC codes
-const K_CM_VIDEO_CODEC_TYPE_H264: u32 = 0x61766331; // 'avc1'
-const K_CM_VIDEO_CODEC_TYPE_HEVC: u32 = 0x68766331; // 'hvc1'
-const K_CM_VIDEO_CODEC_TYPE_MPEG4: u32 = 0x6d703476; // 'mp4v'
-const K_CM_VIDEO_CODEC_TYPE_VP9: u32 = 0x76703039; // 'vp09'
-const K_CM_VIDEO_CODEC_TYPE_AV1: u32 = 0x61763031; // 'av01'
+use video_toolbox_sys::codecs;
+use video_toolbox_sys::utilities::probe_decode_support;
 
 fn fourcc_to_string(code: u32) -> String {
     let bytes = code.to_be_bytes();
@@ -21,17 +15,18 @@ fn fourcc_to_string(code: u32) -> String {
 fn main() {
     println!("Checking hardware decode support on this system:\n");
 
-    let codecs = [
-        (K_CM_VIDEO_CODEC_TYPE_H264, "H.264/AVC"),
-        (K_CM_VIDEO_CODEC_TYPE_HEVC, "H.265/HEVC"),
-        (K_CM_VIDEO_CODEC_TYPE_MPEG4, "MPEG-4"),
-        (K_CM_VIDEO_CODEC_TYPE_VP9, "VP9"),
-        (K_CM_VIDEO_CODEC_TYPE_AV1, "AV1"),
+    let candidates = [
+        (codecs::video::H264, "H.264/AVC"),
+        (codecs::video::HEVC, "H.265/HEVC"),
+        (codecs::video::MPEG4, "MPEG-4"),
+        (codecs::video::VP9, "VP9"),
+        (codecs::video::AV1, "AV1"),
     ];
+    let codec_types: Vec<u32> = candidates.iter().map(|(codec_type, _)| *codec_type).collect();
+    let support = probe_decode_support(&codec_types);
 
-    for (codec_type, name) in codecs.iter() {
-        let supported = unsafe { VTIsHardwareDecodeSupported(*codec_type) };
-        let status = if supported != 0 { "✓ Supported" } else { "✗ Not supported" };
+    for (codec_type, name) in candidates.iter() {
+        let status = if support[codec_type] { "✓ Supported" } else { "✗ Not supported" };
         println!(
             "  {:12} ('{}'): {}",
             name,
@@ -21,6 +21,7 @@ use video_toolbox_sys::compression::{
     kVTVideoEncoderSpecification_EnableHardwareAcceleratedVideoEncoder, VTCompressionOutputCallback,
     VTCompressionSessionCreate, VTCompressionSessionInvalidate,
     VTCompressionSessionPrepareToEncodeFrames, VTCompressionSessionRef, VTEncodeInfoFlags,
+    EncodeInfoFlags,
 };
 use video_toolbox_sys::session::VTSessionSetProperty;
 
@@ -45,7 +46,8 @@ extern "C" fn compression_output_callback(
         return;
     }
 
-    let dropped = (info_flags & 0x2) != 0; // kVTEncodeInfo_FrameDropped
+    let dropped =
+        EncodeInfoFlags::from_bits_retain(info_flags).contains(EncodeInfoFlags::FRAME_DROPPED);
     if dropped {
         println!("Frame was dropped");
     } else {
@@ -19,9 +19,8 @@ use core_media_sys::CMTime;
 use video_toolbox_sys::cv_types::CVImageBufferRef;
 use libc::c_void;
 use video_toolbox_sys::decompression::{
-    kVTDecodeInfo_Asynchronous, kVTDecodeInfo_FrameDropped,
-    kVTVideoDecoderSpecification_EnableHardwareAcceleratedVideoDecoder, VTDecodeInfoFlags,
-    VTDecompressionOutputCallbackRecord,
+    kVTVideoDecoderSpecification_EnableHardwareAcceleratedVideoDecoder, DecodeInfoFlags,
+    VTDecodeInfoFlags, VTDecompressionOutputCallbackRecord,
 };
 
 // Callback invoked when a decoded frame is ready
@@ -39,8 +38,9 @@ extern "C" fn decompression_output_callback(
         return;
     }
 
-    let async_decode = (info_flags & kVTDecodeInfo_Asynchronous) != 0;
-    let dropped = (info_flags & kVTDecodeInfo_FrameDropped) != 0;
+    let info_flags = DecodeInfoFlags::from_bits_retain(info_flags);
+    let async_decode = info_flags.contains(DecodeInfoFlags::ASYNCHRONOUS);
+    let dropped = info_flags.contains(DecodeInfoFlags::FRAME_DROPPED);
 
     if dropped {
         println!("Frame was dropped");
@@ -5,12 +5,14 @@
 //!
 //! Run with: cargo run --example encode_dummy_image --features helpers
 //!
+//! Add `--features leak-tracking` to report pixel buffers that were never released.
+//!
 //! H.264 is used because it has simpler licensing terms compared to HEVC.
 
 extern crate core_foundation;
 extern crate video_toolbox_sys;
 
-use core_foundation_sys::base::{CFTypeRef, OSStatus};
+use core_foundation_sys::base::OSStatus;
 use core_media_sys::CMSampleBufferRef;
 use libc::c_void;
 use std::ptr;
@@ -23,7 +25,8 @@ use video_toolbox_sys::compression::{
     VTCompressionSessionInvalidate, VTCompressionSessionRef, VTEncodeInfoFlags,
 };
 use video_toolbox_sys::helpers::{
-    create_pixel_buffer, run_while, CompressionSessionBuilder, PixelBufferConfig, PixelBufferGuard,
+    create_pixel_buffer, release_pixel_buffer, run_while, CompressionSessionBuilder, LeakCheck,
+    PixelBufferConfig, PixelBufferGuard,
 };
 
 // Declare missing CoreMedia function
@@ -119,6 +122,8 @@ fn main() {
     println!("Resolution: {}x{}", WIDTH, HEIGHT);
     println!("Frames to encode: {}\n", NUM_FRAMES);
 
+    let leak_check = LeakCheck::new();
+
     println!("Creating H.264 compression session...");
 
     // Create compression session using builder
@@ -186,7 +191,7 @@ fn main() {
 
         // Release the pixel buffer
         unsafe {
-            core_foundation_sys::base::CFRelease(pixel_buffer as CFTypeRef);
+            release_pixel_buffer(pixel_buffer);
         }
     }
 
@@ -237,4 +242,5 @@ fn main() {
         VTCompressionSessionInvalidate(session);
     }
     println!("\nSession invalidated.");
+    leak_check.assert_no_leaks();
 }
@@ -2,7 +2,7 @@
 //!
 //! This example demonstrates audio capture pipeline:
 //! 1. AVCaptureSession to capture audio from the default microphone
-//! 2. AVAssetWriter to encode and write AAC audio to an M4A file
+//! 2. Mp4Writer (AVAssetWriter) to encode and write AAC audio to an M4A file
 //!
 //! Run with: cargo run --example mic_to_m4a
 //!
@@ -12,48 +12,32 @@
 use libc::c_void;
 use objc2::rc::Retained;
 use objc2::runtime::{AnyProtocol, Bool, Sel};
-use objc2::{class, msg_send, sel, ClassType};
+use objc2::{msg_send, sel, ClassType};
 use objc2_av_foundation::{
-    AVAssetWriter, AVAssetWriterInput, AVCaptureDevice, AVCaptureDeviceInput, AVCaptureSession,
-    AVCaptureAudioDataOutput, AVMediaTypeAudio,
+    AVCaptureDevice, AVCaptureDeviceInput, AVCaptureSession, AVCaptureAudioDataOutput,
+    AVMediaTypeAudio,
 };
-use objc2_core_media::CMSampleBuffer;
-use objc2_foundation::{ns_string, NSError, NSNumber, NSObject, NSString, NSURL, NSDictionary};
+use objc2_foundation::NSObject;
 use std::ffi::CStr;
 use std::ptr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::time::Duration;
 
+use video_toolbox_sys::helpers::{AacTrackConfig, ChannelLayout, Container, Mp4Writer, Mp4WriterError};
+
 // Recording parameters
 const SAMPLE_RATE: f64 = 44100.0;
-const NUM_CHANNELS: u32 = 1; // Mono
+const CHANNEL_LAYOUT: ChannelLayout = ChannelLayout::Mono;
+const NUM_CHANNELS: u32 = CHANNEL_LAYOUT.channel_count();
 const RECORD_DURATION_SECS: u64 = 5;
 
-// Audio format constants
-const K_AUDIO_FORMAT_MPEG4_AAC: u32 = 0x61616320; // 'aac '
-
-// AVFoundation audio settings keys (as string constants)
-const AV_FORMAT_ID_KEY: &str = "AVFormatIDKey";
-const AV_SAMPLE_RATE_KEY: &str = "AVSampleRateKey";
-const AV_NUMBER_OF_CHANNELS_KEY: &str = "AVNumberOfChannelsKey";
-const AV_ENCODER_BIT_RATE_KEY: &str = "AVEncoderBitRateKey";
-
 // Global state for the recording pipeline
 static SAMPLE_COUNT: AtomicUsize = AtomicUsize::new(0);
 static SHOULD_STOP: AtomicBool = AtomicBool::new(false);
 
-// Thread-safe wrapper for the asset writer input
-#[allow(dead_code)]
-struct AudioWriterContext {
-    asset_writer: Retained<AVAssetWriter>,
-    writer_input: Retained<AVAssetWriterInput>,
-}
-
-unsafe impl Send for AudioWriterContext {}
-unsafe impl Sync for AudioWriterContext {}
-
-static WRITER_CONTEXT: Mutex<Option<AudioWriterContext>> = Mutex::new(None);
+// File writer encoding the captured audio
+static WRITER: Mutex<Option<Mp4Writer>> = Mutex::new(None);
 
 // Dispatch FFI
 #[link(name = "System")]
@@ -69,7 +53,7 @@ extern "C" {
 }
 
 // Delegate method for audio sample capture
-extern "C" fn capture_output_did_output(
+extern "C-unwind" fn capture_output_did_output(
     _this: *mut c_void,
     _cmd: Sel,
     _output: *mut c_void,
@@ -91,97 +75,22 @@ extern "C" fn capture_output_did_output(
         }
 
         // Append the audio sample buffer to the asset writer
-        let ctx_guard = WRITER_CONTEXT.lock().unwrap();
-        if let Some(ref ctx) = *ctx_guard {
-            // Convert raw pointer to objc2 reference
-            let sample_buffer_obj: &CMSampleBuffer = &*(sample_buffer as *const CMSampleBuffer);
-
-            if ctx.writer_input.isReadyForMoreMediaData() {
-                let success: Bool =
-                    msg_send![&ctx.writer_input, appendSampleBuffer: sample_buffer_obj];
-                if !success.as_bool() {
-                    eprintln!("Failed to append audio sample buffer");
-                }
+        if let Some(writer) = WRITER.lock().unwrap().as_mut() {
+            if let Err(e) = writer.append(sample_buffer as core_media_sys::CMSampleBufferRef) {
+                eprintln!("Failed to append audio sample buffer: {}", e);
             }
         }
     }
 }
 
-fn setup_asset_writer(
-    output_path: &str,
-) -> Result<(Retained<AVAssetWriter>, Retained<AVAssetWriterInput>), String> {
-    unsafe {
-        // Create output URL
-        let path_str = NSString::from_str(output_path);
-        let output_url = NSURL::fileURLWithPath(&path_str);
-
-        // Remove existing file if present
-        let file_manager: Retained<NSObject> = msg_send![class!(NSFileManager), defaultManager];
-        let _: Bool = msg_send![&file_manager, removeItemAtPath: &*path_str, error: ptr::null_mut::<*mut NSError>()];
-
-        // Create asset writer for M4A (MPEG-4 audio)
-        let file_type = ns_string!("com.apple.m4a-audio");
-
-        let asset_writer_result =
-            AVAssetWriter::assetWriterWithURL_fileType_error(&output_url, file_type);
-
-        let asset_writer = match asset_writer_result {
-            Ok(w) => w,
-            Err(e) => return Err(format!("Failed to create asset writer: {:?}", e)),
-        };
-
-        // Create audio output settings for AAC encoding
-        let format_key = NSString::from_str(AV_FORMAT_ID_KEY);
-        let sample_rate_key = NSString::from_str(AV_SAMPLE_RATE_KEY);
-        let channels_key = NSString::from_str(AV_NUMBER_OF_CHANNELS_KEY);
-        let bitrate_key = NSString::from_str(AV_ENCODER_BIT_RATE_KEY);
-
-        let format_value: Retained<NSNumber> = msg_send![class!(NSNumber), numberWithUnsignedInt: K_AUDIO_FORMAT_MPEG4_AAC];
-        let sample_rate_value: Retained<NSNumber> = msg_send![class!(NSNumber), numberWithDouble: SAMPLE_RATE];
-        let channels_value: Retained<NSNumber> = msg_send![class!(NSNumber), numberWithUnsignedInt: NUM_CHANNELS];
-        let bitrate_value: Retained<NSNumber> = msg_send![class!(NSNumber), numberWithInt: 128000i32]; // 128 kbps
-
-        // Build the settings dictionary
-        let keys: [&NSString; 4] = [&format_key, &sample_rate_key, &channels_key, &bitrate_key];
-        let objects: [&NSNumber; 4] = [&format_value, &sample_rate_value, &channels_value, &bitrate_value];
-
-        let audio_settings: Retained<NSDictionary<NSString, NSObject>> = msg_send![
-            class!(NSDictionary),
-            dictionaryWithObjects: objects.as_ptr(),
-            forKeys: keys.as_ptr(),
-            count: 4usize
-        ];
-
-        // Create asset writer input for audio
-        let media_type = ns_string!("soun");
-        let writer_input: Retained<AVAssetWriterInput> = msg_send![
-            class!(AVAssetWriterInput),
-            assetWriterInputWithMediaType: media_type,
-            outputSettings: &*audio_settings
-        ];
-
-        writer_input.setExpectsMediaDataInRealTime(true);
-
-        // Add input to writer
-        asset_writer.addInput(&writer_input);
-
-        // Start writing
-        let success = asset_writer.startWriting();
-        if !success {
-            return Err("Failed to start asset writer".to_string());
-        }
-
-        // Start session at time zero
-        let zero_time = objc2_core_media::CMTime {
-            value: 0,
-            timescale: SAMPLE_RATE as i32,
-            flags: objc2_core_media::CMTimeFlags(1),
-            epoch: 0,
-        };
-        let _: () = msg_send![&asset_writer, startSessionAtSourceTime: zero_time];
-
-        Ok((asset_writer, writer_input))
-    }
+fn setup_writer(output_path: &str) -> Result<Mp4Writer, Mp4WriterError> {
+    let mut writer = Mp4Writer::new(output_path, Container::M4a)?;
+    writer.add_audio_track_aac(&AacTrackConfig {
+        sample_rate: SAMPLE_RATE,
+        channel_layout: CHANNEL_LAYOUT,
+        bitrate: 128_000,
+    })?;
+    Ok(writer)
 }
 
 fn main() {
@@ -202,23 +111,14 @@ fn main() {
     println!("Output file: {}\n", output_path);
 
     unsafe {
-        // 1. Set up AVAssetWriter for M4A output
+        // 1. Set up the writer for M4A output
         println!("Setting up asset writer...");
-        let (asset_writer, writer_input) = match setup_asset_writer(&output_path) {
-            Ok((w, i)) => (w, i),
+        match setup_writer(&output_path) {
+            Ok(writer) => *WRITER.lock().unwrap() = Some(writer),
             Err(e) => {
                 eprintln!("Failed to set up asset writer: {}", e);
                 return;
             }
-        };
-
-        // Store writer context for the callback
-        {
-            let mut ctx = WRITER_CONTEXT.lock().unwrap();
-            *ctx = Some(AudioWriterContext {
-                asset_writer: asset_writer.clone(),
-                writer_input: writer_input.clone(),
-            });
         }
 
         // 2. Set up AVCaptureSession for audio
@@ -386,30 +286,12 @@ fn main() {
         // Wait a moment for final samples
         std::thread::sleep(Duration::from_millis(200));
 
-        // Mark input as finished and finish writing
-        writer_input.markAsFinished();
-
+        // Finish the track and write the file
         println!("Finalizing audio file...");
-
-        use block2::StackBlock;
-
-        let finished = std::sync::Arc::new(AtomicBool::new(false));
-        let finished_clone = finished.clone();
-
-        let block = StackBlock::new(move || {
-            finished_clone.store(true, Ordering::SeqCst);
-        });
-
-        let _: () = msg_send![&asset_writer, finishWritingWithCompletionHandler: &block];
-
-        // Wait for writing to finish
-        let timeout = std::time::Instant::now();
-        while !finished.load(Ordering::SeqCst) {
-            if timeout.elapsed() > Duration::from_secs(10) {
-                eprintln!("Timeout waiting for asset writer to finish");
-                break;
+        if let Some(writer) = WRITER.lock().unwrap().take() {
+            if let Err(e) = writer.finish().wait() {
+                eprintln!("Failed to finish writing: {}", e);
             }
-            std::thread::sleep(Duration::from_millis(100));
         }
 
         // Print summary
@@ -11,16 +11,26 @@
 //!
 //! - `init.mp4` - Initialization segment (ftyp + moov with SPS/PPS)
 //! - `segment_001.m4s`, `segment_002.m4s`, ... - Media segments
+//! - `playlist.m3u8` - Live HLS playlist of the most recent segments
+//! - `manifest.mpd` - Live DASH manifest of the same segments
+//! - `player.html` - Media Source Extensions test page for the segments
+//!
+//! Segments that fall out of the playlist window are deleted, so the output
+//! directory stays bounded however long the stream runs.
 //!
 //! The segments can be:
-//! - Played sequentially in VLC: `cat init.mp4 segment_*.m4s > full.mp4`
-//! - Used with DASH/HLS manifests
-//! - Fed to Media Source Extensions in browsers
+//! - Played live with `ffplay cmaf_output/playlist.m3u8` or `ffplay cmaf_output/manifest.mpd`
+//! - Concatenated while still in the window: `cat init.mp4 segment_*.m4s > full.mp4`
+//! - Fed to Media Source Extensions in browsers: serve the directory with
+//!   `python3 -m http.server -d cmaf_output` and open `http://localhost:8000/player.html`
+//! - Watched live in a browser with the `mse-server` feature: segments are
+//!   also pushed over WebSocket, and `http://localhost:8080/` plays them
 //!
 //! # Usage
 //!
 //! ```bash
 //! cargo run --example webcam_cmaf_stream
+//! cargo run --example webcam_cmaf_stream --features mse-server
 //! ```
 //!
 //! # Note
@@ -30,17 +40,8 @@
 use core_foundation_sys::base::OSStatus;
 use core_media_sys::{CMSampleBufferRef, CMTime};
 use libc::c_void;
-use objc2::rc::Retained;
-use objc2::runtime::{Bool, Sel};
-use objc2::{class, msg_send};
-use objc2_av_foundation::{
-    AVCaptureDevice, AVCaptureDeviceInput, AVCaptureSession, AVCaptureVideoDataOutput,
-    AVMediaTypeVideo,
-};
-use objc2_foundation::{ns_string, NSNumber, NSObject};
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::PathBuf;
+use objc2::runtime::Sel;
+use std::fs;
 use std::ptr;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Mutex;
@@ -53,9 +54,11 @@ use video_toolbox_sys::compression::{
 };
 use video_toolbox_sys::cv_types::CVPixelBufferRef;
 use video_toolbox_sys::helpers::{
-    create_capture_delegate, create_dispatch_queue, run_for_duration, set_sample_buffer_delegate,
-    CompressionSessionBuilder, DelegateCallback, CmafConfig, CmafMuxer, NalExtractor,
+    run_for_duration, CaptureSessionBuilder, CompressionSessionBuilder, DelegateCallback,
+    CmafConfig, CmafMuxer, NalExtractor, mp4_mime_type, DashConfig, DashSink, HlsConfig, HlsSink, MsePage, Segment, SegmentSink,
 };
+#[cfg(feature = "mse-server")]
+use video_toolbox_sys::helpers::MseServer;
 
 // Recording parameters
 const WIDTH: i32 = 1280;
@@ -64,6 +67,8 @@ const FRAME_RATE: f64 = 30.0;
 const BITRATE: i64 = 4_000_000; // 4 Mbps
 const RECORD_DURATION_SECS: u64 = 10;
 const FRAGMENT_DURATION_MS: u32 = 2000; // 2-second fragments
+#[cfg(feature = "mse-server")]
+const SERVER_ADDR: &str = "0.0.0.0:8080";
 
 // Global state
 static FRAME_COUNT: AtomicUsize = AtomicUsize::new(0);
@@ -75,7 +80,14 @@ static SHOULD_STOP: AtomicBool = AtomicBool::new(false);
 struct MuxerContext {
     muxer: CmafMuxer,
     extractor: NalExtractor,
-    output_dir: PathBuf,
+    hls: HlsSink,
+    /// DASH manifest over the segment files written by `hls`
+    dash: DashSink,
+    /// Browser test page, written with the init segment
+    page: MsePage,
+    /// Live WebSocket stream of the same segments
+    #[cfg(feature = "mse-server")]
+    server: MseServer,
     initialized: bool,
 }
 
@@ -126,8 +138,9 @@ extern "C" fn compression_output_callback(
         // Get format description and initialize muxer if needed
         if !ctx.initialized {
             if let Some(format_desc) = ctx.extractor.get_format_description(sample_buffer) {
-                match ctx.extractor.extract_parameter_sets(format_desc) {
-                    Ok(params) => {
+                match ctx.extractor.resolve_parameter_sets(sample_buffer) {
+                    Ok((params, source)) => {
+                        println!("  Parameter sets from {:?}", source);
                         match ctx.extractor.get_dimensions(format_desc) {
                             Ok(dims) => {
                                 // Create initialization segment
@@ -138,17 +151,24 @@ extern "C" fn compression_output_callback(
                                     dims.height,
                                 );
 
-                                // Write init segment to file
-                                let init_path = ctx.output_dir.join("init.mp4");
-                                if let Ok(mut file) = File::create(&init_path) {
-                                    if file.write_all(&init_segment).is_ok() {
-                                        println!(
-                                            "  Created initialization segment: {} ({} bytes)",
-                                            init_path.display(),
-                                            init_segment.len()
-                                        );
-                                        ctx.initialized = true;
+                                // Write init segment to the output directory
+                                let init_len = init_segment.len();
+                                let init = Segment::init(init_segment);
+                                if ctx.hls.write_segment(&init).is_ok()
+                                    && ctx.dash.write_segment(&init).is_ok()
+                                    && ctx.page.write_segment(&init).is_ok()
+                                {
+                                    #[cfg(feature = "mse-server")]
+                                    ctx.server.set_init_segment(init.data.clone());
+                                    println!(
+                                        "  Created initialization segment: {}/init.mp4 ({} bytes)",
+                                        ctx.hls.dir().display(),
+                                        init_len
+                                    );
+                                    if let Some(codec) = ctx.muxer.codec_string() {
+                                        println!("  MIME type: {}", mp4_mime_type(&[&codec]));
                                     }
+                                    ctx.initialized = true;
                                 }
                             }
                             Err(e) => eprintln!("Failed to get dimensions: {}", e),
@@ -184,21 +204,8 @@ extern "C" fn compression_output_callback(
             (timing.duration as f64 * target_timescale as f64 / timing.timescale as f64) as u32;
 
         // Add frame to muxer
-        if let Some(segment) = ctx.muxer.add_frame(&nal_units, pts, dts, duration, is_keyframe) {
-            // Write segment to file
-            let segment_num = SEGMENT_COUNT.fetch_add(1, Ordering::SeqCst) + 1;
-            let segment_path = ctx.output_dir.join(format!("segment_{:03}.m4s", segment_num));
-
-            if let Ok(mut file) = File::create(&segment_path) {
-                if file.write_all(&segment).is_ok() {
-                    println!(
-                        "  Created segment {}: {} ({} bytes)",
-                        segment_num,
-                        segment_path.display(),
-                        segment.len()
-                    );
-                }
-            }
+        if let Some(data) = ctx.muxer.add_frame(&nal_units, pts, dts, duration, is_keyframe) {
+            write_media_segment(ctx, data, "segment");
         }
 
         let frame_num = ENCODED_FRAMES.fetch_add(1, Ordering::SeqCst) + 1;
@@ -208,6 +215,32 @@ extern "C" fn compression_output_callback(
     }
 }
 
+/// Write a fragment just emitted by the muxer and update the playlist.
+fn write_media_segment(ctx: &mut MuxerContext, data: Vec<u8>, label: &str) {
+    let len = data.len();
+    let segment = Segment::media(ctx.muxer.sequence_number() - 1, data)
+        .with_duration(ctx.muxer.last_fragment_duration());
+    #[cfg(feature = "mse-server")]
+    ctx.server.broadcast(segment.data.clone());
+    match ctx
+        .hls
+        .write_segment(&segment)
+        .and_then(|()| ctx.dash.write_segment(&segment))
+    {
+        Ok(()) => {
+            let segment_num = SEGMENT_COUNT.fetch_add(1, Ordering::SeqCst) + 1;
+            println!(
+                "  Created {} {}: {:.2}s ({} bytes)",
+                label,
+                segment_num,
+                segment.duration.unwrap_or_default().as_secs_f64(),
+                len
+            );
+        }
+        Err(e) => eprintln!("Failed to write {}: {}", label, e),
+    }
+}
+
 fn create_compression_session() -> Result<VTCompressionSessionRef, OSStatus> {
     unsafe {
         CompressionSessionBuilder::new(WIDTH, HEIGHT, codecs::video::H264)
@@ -223,7 +256,7 @@ fn create_compression_session() -> Result<VTCompressionSessionRef, OSStatus> {
 }
 
 // Delegate callback for video frame capture
-extern "C" fn capture_output_did_output(
+extern "C-unwind" fn capture_output_did_output(
     _this: *mut c_void,
     _cmd: Sel,
     _output: *mut c_void,
@@ -315,13 +348,48 @@ fn main() {
             let muxer = CmafMuxer::new(CmafConfig {
                 fragment_duration_ms: FRAGMENT_DURATION_MS,
                 timescale: 90000,
+                // Webcam frame intervals vary with exposure
+                variable_frame_rate: true,
+                ..Default::default()
             });
 
+            let hls = match HlsSink::new(&output_dir, HlsConfig::default()) {
+                Ok(hls) => hls,
+                Err(e) => {
+                    eprintln!("Failed to create HLS output: {}", e);
+                    return;
+                }
+            };
+
+            let dash = match DashSink::manifest_only(&output_dir, DashConfig::default()) {
+                Ok(dash) => dash,
+                Err(e) => {
+                    eprintln!("Failed to create DASH output: {}", e);
+                    return;
+                }
+            };
+
+            #[cfg(feature = "mse-server")]
+            let server = match MseServer::bind(SERVER_ADDR) {
+                Ok(server) => {
+                    println!("Live stream: http://localhost:{}/\n", server.local_addr().port());
+                    server
+                }
+                Err(e) => {
+                    eprintln!("Failed to start MSE server on {}: {}", SERVER_ADDR, e);
+                    return;
+                }
+            };
+
             let mut ctx = MUXER_CONTEXT.lock().unwrap();
             *ctx = Some(MuxerContext {
                 muxer,
                 extractor: NalExtractor::new(),
-                output_dir: output_dir.clone(),
+                hls,
+                dash,
+                page: MsePage::new(&output_dir),
+                #[cfg(feature = "mse-server")]
+                server,
                 initialized: false,
             });
         }
@@ -340,105 +408,27 @@ fn main() {
         // Store compression session globally for delegate access
         COMPRESSION_SESSION = compression_session;
 
-        // Set up AVCaptureSession
+        // Open the camera in 720p at the encoding frame rate
         println!("Setting up camera capture...");
 
-        let capture_session = AVCaptureSession::new();
-        capture_session.beginConfiguration();
-
-        // Set session preset for 720p
-        let preset = ns_string!("AVCaptureSessionPreset1280x720");
-        let can_set: Bool = msg_send![&capture_session, canSetSessionPreset: preset];
-        if can_set.as_bool() {
-            let _: () = msg_send![&capture_session, setSessionPreset: preset];
-        }
-
-        // Get default video device
-        let media_type = AVMediaTypeVideo.expect("AVMediaTypeVideo not available");
-        let video_device = AVCaptureDevice::defaultDeviceWithMediaType(media_type);
-        let video_device = match video_device {
-            Some(d) => d,
-            None => {
-                eprintln!("No camera device found!");
-                return;
-            }
-        };
-
-        println!("Using camera: {:?}", video_device.localizedName());
-
-        // Create device input
-        let device_input_result = AVCaptureDeviceInput::deviceInputWithDevice_error(&video_device);
-
-        let device_input = match device_input_result {
-            Ok(i) => i,
+        let mut capture_session = match CaptureSessionBuilder::new()
+            .resolution(WIDTH as u32, HEIGHT as u32)
+            .frame_rate(FRAME_RATE)
+            .pixel_format(codecs::pixel::BGRA32)
+            .build(capture_output_did_output as DelegateCallback)
+        {
+            Ok(session) => session,
             Err(e) => {
-                eprintln!("Failed to create device input: {:?}", e);
+                eprintln!("Failed to set up camera: {}", e);
                 return;
             }
         };
 
-        // Add input to session
-        if capture_session.canAddInput(&device_input) {
-            capture_session.addInput(&device_input);
-        } else {
-            eprintln!("Cannot add camera input to session");
-            return;
-        }
-
-        // Create video data output
-        let video_output = AVCaptureVideoDataOutput::new();
-
-        // Set pixel format to BGRA
-        let format_key = ns_string!("PixelFormatType");
-        let format_value: Retained<NSNumber> =
-            msg_send![class!(NSNumber), numberWithUnsignedInt: codecs::pixel::BGRA32];
-
-        let video_settings: Retained<NSObject> = msg_send![
-            class!(NSDictionary),
-            dictionaryWithObject: &*format_value,
-            forKey: format_key
-        ];
-
-        let _: () = msg_send![&video_output, setVideoSettings: &*video_settings];
-        video_output.setAlwaysDiscardsLateVideoFrames(true);
-
-        // Create delegate
-        let delegate = create_capture_delegate(
-            "FMP4CameraDelegate",
-            "AVCaptureVideoDataOutputSampleBufferDelegate",
-            capture_output_did_output as DelegateCallback,
-        )
-        .expect("Failed to create delegate");
-
-        // Create dispatch queue
-        let callback_queue = create_dispatch_queue("com.videotoolbox.fmp4.queue");
-
-        // Set delegate
-        set_sample_buffer_delegate(
-            &*video_output as *const _ as *const c_void,
-            &*delegate as *const _ as *const c_void,
-            callback_queue,
-        );
-
-        // Add output to session
-        if capture_session.canAddOutput(&video_output) {
-            capture_session.addOutput(&video_output);
-        } else {
-            eprintln!("Cannot add video output to session");
-            return;
-        }
-
-        // Commit configuration
-        capture_session.commitConfiguration();
-
         // Start recording
         println!("\nStarting camera capture...");
         println!("Recording for {} seconds...\n", RECORD_DURATION_SECS);
 
-        capture_session.startRunning();
-
-        // Keep delegate alive
-        let _delegate_ref = delegate.clone();
+        capture_session.start();
 
         // Run the run loop
         let mut last_printed: u64 = 0;
@@ -459,7 +449,7 @@ fn main() {
         println!("\nStopping capture...");
         SHOULD_STOP.store(true, Ordering::SeqCst);
 
-        capture_session.stopRunning();
+        capture_session.stop();
 
         // Complete encoding
         let complete_time = CMTime {
@@ -477,20 +467,14 @@ fn main() {
         {
             let mut ctx_guard = MUXER_CONTEXT.lock().unwrap();
             if let Some(ctx) = ctx_guard.as_mut() {
-                if let Some(segment) = ctx.muxer.flush() {
-                    let segment_num = SEGMENT_COUNT.fetch_add(1, Ordering::SeqCst) + 1;
-                    let segment_path = ctx.output_dir.join(format!("segment_{:03}.m4s", segment_num));
-
-                    if let Ok(mut file) = File::create(&segment_path) {
-                        if file.write_all(&segment).is_ok() {
-                            println!(
-                                "  Created final segment {}: {} ({} bytes)",
-                                segment_num,
-                                segment_path.display(),
-                                segment.len()
-                            );
-                        }
-                    }
+                if let Some(data) = ctx.muxer.flush() {
+                    write_media_segment(ctx, data, "final segment");
+                }
+                if let Err(e) = ctx.hls.finish() {
+                    eprintln!("Failed to finish playlist: {}", e);
+                }
+                if let Err(e) = ctx.dash.finish() {
+                    eprintln!("Failed to finish DASH manifest: {}", e);
                 }
             }
         }
@@ -509,6 +493,16 @@ fn main() {
         println!("  Encoded frames: {}", encoded_frames);
         println!("  Segments created: {}", total_segments);
         println!("  Output directory: {}", output_dir.display());
+        #[cfg(feature = "mse-server")]
+        {
+            if let Some(ctx) = MUXER_CONTEXT.lock().unwrap().as_ref() {
+                let stats = ctx.server.stats();
+                println!(
+                    "  Live clients: {} connected ({} in total), {} disconnected as too slow",
+                    stats.clients, stats.connections, stats.slow_disconnects
+                );
+            }
+        }
 
         // Calculate total size
         let mut total_size = 0u64;
@@ -522,8 +516,7 @@ fn main() {
         println!("  Total size: {:.2} MB", total_size as f64 / (1024.0 * 1024.0));
 
         println!("\nTo play the output:");
-        println!("  cat {}/init.mp4 {}/segment_*.m4s > combined.mp4", output_dir.display(), output_dir.display());
-        println!("  ffplay combined.mp4");
+        println!("  ffplay {}/playlist.m3u8", output_dir.display());
 
         println!("\nDone!");
     }
@@ -0,0 +1,183 @@
+//! AudioToolbox AudioConverter FFI bindings.
+//!
+//! AudioConverter performs sample rate, channel count and sample format conversion
+//! of linear PCM, as well as encoding/decoding of compressed formats (AAC, ALAC).
+//! Capture devices and encoders frequently disagree on these parameters, e.g. a
+//! microphone delivering 48 kHz stereo float into a 44.1 kHz mono 16-bit encoder.
+
+use core_foundation_sys::base::OSStatus;
+use libc::c_void;
+
+/// Describes a linear PCM or compressed audio stream.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AudioStreamBasicDescription {
+    pub mSampleRate: f64,
+    pub mFormatID: u32,
+    pub mFormatFlags: u32,
+    pub mBytesPerPacket: u32,
+    pub mFramesPerPacket: u32,
+    pub mBytesPerFrame: u32,
+    pub mChannelsPerFrame: u32,
+    pub mBitsPerChannel: u32,
+    pub mReserved: u32,
+}
+
+/// A single buffer of audio data.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct AudioBuffer {
+    pub mNumberChannels: u32,
+    pub mDataByteSize: u32,
+    pub mData: *mut c_void,
+}
+
+/// A list of audio buffers (one for interleaved data).
+///
+/// The C struct is variable length; this definition covers the interleaved case.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct AudioBufferList {
+    pub mNumberBuffers: u32,
+    pub mBuffers: [AudioBuffer; 1],
+}
+
+/// Describes one packet in a buffer of compressed audio.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AudioStreamPacketDescription {
+    pub mStartOffset: i64,
+    pub mVariableFramesInPacket: u32,
+    pub mDataByteSize: u32,
+}
+
+/// Leading/trailing frames a converter needs for priming (in input frames).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AudioConverterPrimeInfo {
+    pub leadingFrames: u32,
+    pub trailingFrames: u32,
+}
+
+/// Opaque type for AudioConverter.
+#[repr(C)]
+pub struct OpaqueAudioConverter {
+    _private: c_void,
+}
+
+/// Reference to an audio converter.
+pub type AudioConverterRef = *mut OpaqueAudioConverter;
+
+pub type AudioConverterPropertyID = u32;
+
+/// Callback supplying input data to `AudioConverterFillComplexBuffer`.
+pub type AudioConverterComplexInputDataProc = extern "C" fn(
+    inAudioConverter: AudioConverterRef,
+    ioNumberDataPackets: *mut u32,
+    ioData: *mut AudioBufferList,
+    outDataPacketDescription: *mut *mut AudioStreamPacketDescription,
+    inUserData: *mut c_void,
+) -> OSStatus;
+
+// AudioFormatFlags for linear PCM
+pub const kAudioFormatFlagIsFloat: u32 = 1 << 0;
+pub const kAudioFormatFlagIsBigEndian: u32 = 1 << 1;
+pub const kAudioFormatFlagIsSignedInteger: u32 = 1 << 2;
+pub const kAudioFormatFlagIsPacked: u32 = 1 << 3;
+pub const kAudioFormatFlagIsAlignedHigh: u32 = 1 << 4;
+pub const kAudioFormatFlagIsNonInterleaved: u32 = 1 << 5;
+pub const kAudioFormatFlagIsNonMixable: u32 = 1 << 6;
+
+// AudioConverter property IDs
+pub const kAudioConverterPropertyMinimumInputBufferSize: AudioConverterPropertyID = 0x6d696273; // 'mibs'
+pub const kAudioConverterPropertyMinimumOutputBufferSize: AudioConverterPropertyID = 0x6d6f6273; // 'mobs'
+pub const kAudioConverterPropertyMaximumInputPacketSize: AudioConverterPropertyID = 0x78697073; // 'xips'
+pub const kAudioConverterPropertyMaximumOutputPacketSize: AudioConverterPropertyID = 0x786f7073; // 'xops'
+pub const kAudioConverterPropertyCalculateInputBufferSize: AudioConverterPropertyID = 0x63696273; // 'cibs'
+pub const kAudioConverterPropertyCalculateOutputBufferSize: AudioConverterPropertyID = 0x636f6273; // 'cobs'
+pub const kAudioConverterSampleRateConverterComplexity: AudioConverterPropertyID = 0x73726361; // 'srca'
+pub const kAudioConverterSampleRateConverterQuality: AudioConverterPropertyID = 0x73726371; // 'srcq'
+pub const kAudioConverterPrimeMethod: AudioConverterPropertyID = 0x70726d6d; // 'prmm'
+pub const kAudioConverterPrimeInfo: AudioConverterPropertyID = 0x7072696d; // 'prim'
+pub const kAudioConverterChannelMap: AudioConverterPropertyID = 0x63686d70; // 'chmp'
+pub const kAudioConverterCurrentOutputStreamDescription: AudioConverterPropertyID = 0x61636f64; // 'acod'
+pub const kAudioConverterCurrentInputStreamDescription: AudioConverterPropertyID = 0x61636964; // 'acid'
+pub const kAudioConverterEncodeBitRate: AudioConverterPropertyID = 0x62726174; // 'brat'
+
+// kAudioConverterPrimeMethod values
+pub const kConverterPrimeMethod_Pre: u32 = 0;
+pub const kConverterPrimeMethod_Normal: u32 = 1;
+pub const kConverterPrimeMethod_None: u32 = 2;
+
+// kAudioConverterSampleRateConverterQuality values
+pub const kAudioConverterQuality_Max: u32 = 0x7F;
+pub const kAudioConverterQuality_High: u32 = 0x60;
+pub const kAudioConverterQuality_Medium: u32 = 0x40;
+pub const kAudioConverterQuality_Low: u32 = 0x20;
+pub const kAudioConverterQuality_Min: u32 = 0;
+
+// AudioConverter error codes
+pub const kAudioConverterErr_FormatNotSupported: OSStatus = 0x666d743f; // 'fmt?'
+pub const kAudioConverterErr_OperationNotSupported: OSStatus = 0x6f703f3f; // 'op??'
+pub const kAudioConverterErr_PropertyNotSupported: OSStatus = 0x70726f70; // 'prop'
+pub const kAudioConverterErr_InvalidInputSize: OSStatus = 0x696e737a; // 'insz'
+pub const kAudioConverterErr_InvalidOutputSize: OSStatus = 0x6f74737a; // 'otsz'
+pub const kAudioConverterErr_BadPropertySizeError: OSStatus = 0x2173697a; // '!siz'
+pub const kAudioConverterErr_RequiresPacketDescriptionsError: OSStatus = 0x21706b64; // '!pkd'
+pub const kAudioConverterErr_InputSampleRateOutOfRange: OSStatus = 0x21697372; // '!isr'
+pub const kAudioConverterErr_OutputSampleRateOutOfRange: OSStatus = 0x216f7372; // '!osr'
+
+#[link(name = "AudioToolbox", kind = "framework")]
+extern "C" {
+    pub fn AudioConverterNew(
+        inSourceFormat: *const AudioStreamBasicDescription,
+        inDestinationFormat: *const AudioStreamBasicDescription,
+        outAudioConverter: *mut AudioConverterRef,
+    ) -> OSStatus;
+
+    pub fn AudioConverterDispose(inAudioConverter: AudioConverterRef) -> OSStatus;
+
+    /// Flushes internal buffers, e.g. after a discontinuity or end of stream.
+    pub fn AudioConverterReset(inAudioConverter: AudioConverterRef) -> OSStatus;
+
+    pub fn AudioConverterGetPropertyInfo(
+        inAudioConverter: AudioConverterRef,
+        inPropertyID: AudioConverterPropertyID,
+        outSize: *mut u32,
+        outWritable: *mut u8,
+    ) -> OSStatus;
+
+    pub fn AudioConverterGetProperty(
+        inAudioConverter: AudioConverterRef,
+        inPropertyID: AudioConverterPropertyID,
+        ioPropertyDataSize: *mut u32,
+        outPropertyData: *mut c_void,
+    ) -> OSStatus;
+
+    pub fn AudioConverterSetProperty(
+        inAudioConverter: AudioConverterRef,
+        inPropertyID: AudioConverterPropertyID,
+        inPropertyDataSize: u32,
+        inPropertyData: *const c_void,
+    ) -> OSStatus;
+
+    /// Converts data supplied by an input callback, handling rate conversion and
+    /// differing packet sizes.
+    pub fn AudioConverterFillComplexBuffer(
+        inAudioConverter: AudioConverterRef,
+        inInputDataProc: AudioConverterComplexInputDataProc,
+        inInputDataProcUserData: *mut c_void,
+        ioOutputDataPacketSize: *mut u32,
+        outOutputData: *mut AudioBufferList,
+        outPacketDescription: *mut AudioStreamPacketDescription,
+    ) -> OSStatus;
+
+    /// Converts linear PCM without rate conversion in a single call.
+    pub fn AudioConverterConvertBuffer(
+        inAudioConverter: AudioConverterRef,
+        inInputDataSize: u32,
+        inInputData: *const c_void,
+        ioOutputDataSize: *mut u32,
+        outOutputData: *mut c_void,
+    ) -> OSStatus;
+}
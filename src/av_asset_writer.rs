@@ -0,0 +1,249 @@
+//! AVFoundation asset writer bindings for writing MP4 and MOV files.
+//!
+//! As in [`av_capture`](crate::av_capture), the Objective-C API is reached
+//! through thin `unsafe` message wrappers named after their selectors.
+//! Objects are returned as `Retained<AnyObject>`; errors carry the NSError
+//! code (see `AVError`, e.g. `-11823` when the output file already exists).
+
+use block2::Block;
+use core_foundation_sys::base::OSStatus;
+use core_media_sys::{CMFormatDescriptionRef, CMSampleBufferRef};
+use objc2::encode::{Encoding, RefEncode};
+use objc2::rc::Retained;
+use objc2::runtime::{AnyObject, Bool};
+use objc2::{class, msg_send};
+use objc2_foundation::{NSError, NSInteger, NSString, NSURL};
+
+use crate::base::EncodedCMTime;
+
+#[link(name = "AVFoundation", kind = "framework")]
+extern "C" {
+    pub static AVFileTypeQuickTimeMovie: &'static NSString;
+    pub static AVFileTypeMPEG4: &'static NSString;
+    pub static AVFileTypeAppleM4A: &'static NSString;
+}
+
+/// `AVErrorUnknown`
+pub const AVErrorUnknown: OSStatus = -11800;
+
+pub type AVAssetWriterStatus = NSInteger;
+pub const AVAssetWriterStatusUnknown: AVAssetWriterStatus = 0;
+pub const AVAssetWriterStatusWriting: AVAssetWriterStatus = 1;
+pub const AVAssetWriterStatusCompleted: AVAssetWriterStatus = 2;
+pub const AVAssetWriterStatusFailed: AVAssetWriterStatus = 3;
+pub const AVAssetWriterStatusCancelled: AVAssetWriterStatus = 4;
+
+/// Opaque CMSampleBuffer, as passed to Objective-C methods.
+#[repr(C)]
+pub struct opaqueCMSampleBuffer {
+    _private: [u8; 0],
+}
+
+// SAFETY: CMSampleBufferRef is `struct opaqueCMSampleBuffer *` in
+// Objective-C method signatures.
+unsafe impl RefEncode for opaqueCMSampleBuffer {
+    const ENCODING_REF: Encoding =
+        Encoding::Pointer(&Encoding::Struct("opaqueCMSampleBuffer", &[]));
+}
+
+/// Opaque CMFormatDescription, as passed to Objective-C methods.
+#[repr(C)]
+pub struct opaqueCMFormatDescription {
+    _private: [u8; 0],
+}
+
+// SAFETY: CMFormatDescriptionRef is `struct opaqueCMFormatDescription *` in
+// Objective-C method signatures.
+unsafe impl RefEncode for opaqueCMFormatDescription {
+    const ENCODING_REF: Encoding =
+        Encoding::Pointer(&Encoding::Struct("opaqueCMFormatDescription", &[]));
+}
+
+fn error_code(error: Retained<NSError>) -> OSStatus {
+    error.code() as OSStatus
+}
+
+/// `+[AVAssetWriter assetWriterWithURL:fileType:error:]`
+///
+/// # Safety
+///
+/// The `AVAssetWriter` class registered at runtime must declare this selector
+/// with the signature bound here.
+pub unsafe fn AVAssetWriter_assetWriterWithURL(
+    outputURL: &NSURL,
+    outputFileType: &NSString,
+) -> Result<Retained<AnyObject>, OSStatus> {
+    let result: Result<Retained<AnyObject>, Retained<NSError>> = msg_send![
+        class!(AVAssetWriter),
+        assetWriterWithURL: outputURL,
+        fileType: outputFileType,
+        error: _
+    ];
+    result.map_err(error_code)
+}
+
+/// `-[AVAssetWriter addInput:]` if `-canAddInput:` allows it
+///
+/// # Safety
+///
+/// `writer` must be an `AVAssetWriter`; `input` must be an
+/// `AVAssetWriterInput`.
+pub unsafe fn AVAssetWriter_addInput(writer: &AnyObject, input: &AnyObject) -> bool {
+    let can_add: Bool = msg_send![writer, canAddInput: input];
+    if can_add.as_bool() {
+        let _: () = msg_send![writer, addInput: input];
+    }
+    can_add.as_bool()
+}
+
+/// `-[AVAssetWriter startWriting]`; on failure see [`AVAssetWriter_error`].
+///
+/// # Safety
+///
+/// `writer` must be an `AVAssetWriter`.
+pub unsafe fn AVAssetWriter_startWriting(writer: &AnyObject) -> bool {
+    let started: Bool = msg_send![writer, startWriting];
+    started.as_bool()
+}
+
+/// `-[AVAssetWriter startSessionAtSourceTime:]`; samples before
+/// `startTime` are trimmed.
+///
+/// # Safety
+///
+/// `writer` must be an `AVAssetWriter`.
+pub unsafe fn AVAssetWriter_startSessionAtSourceTime(writer: &AnyObject, startTime: EncodedCMTime) {
+    let _: () = msg_send![writer, startSessionAtSourceTime: startTime];
+}
+
+/// `-[AVAssetWriter finishWritingWithCompletionHandler:]`
+///
+/// The handler is called once on an arbitrary queue; check
+/// [`AVAssetWriter_status`] from it.
+///
+/// # Safety
+///
+/// `writer` must be an `AVAssetWriter`.
+pub unsafe fn AVAssetWriter_finishWriting(writer: &AnyObject, handler: &Block<dyn Fn()>) {
+    let _: () = msg_send![writer, finishWritingWithCompletionHandler: handler];
+}
+
+/// `-[AVAssetWriter cancelWriting]`, deleting the partial file
+///
+/// # Safety
+///
+/// `writer` must be an `AVAssetWriter`.
+pub unsafe fn AVAssetWriter_cancelWriting(writer: &AnyObject) {
+    let _: () = msg_send![writer, cancelWriting];
+}
+
+/// `-[AVAssetWriter status]`
+///
+/// # Safety
+///
+/// `writer` must be an `AVAssetWriter`.
+pub unsafe fn AVAssetWriter_status(writer: &AnyObject) -> AVAssetWriterStatus {
+    msg_send![writer, status]
+}
+
+/// The code of `-[AVAssetWriter error]`, set once the status is
+/// [`AVAssetWriterStatusFailed`].
+///
+/// # Safety
+///
+/// `writer` must be an `AVAssetWriter`.
+pub unsafe fn AVAssetWriter_error(writer: &AnyObject) -> Option<OSStatus> {
+    let error: Option<Retained<NSError>> = msg_send![writer, error];
+    error.map(error_code)
+}
+
+/// `+[AVAssetWriterInput assetWriterInputWithMediaType:outputSettings:]`;
+/// `None` settings append samples as they are (passthrough).
+///
+/// # Safety
+///
+/// `outputSettings`, if given, must be an `NSDictionary` of output settings.
+pub unsafe fn AVAssetWriterInput_assetWriterInputWithMediaType(
+    mediaType: &NSString,
+    outputSettings: Option<&AnyObject>,
+) -> Retained<AnyObject> {
+    msg_send![
+        class!(AVAssetWriterInput),
+        assetWriterInputWithMediaType: mediaType,
+        outputSettings: outputSettings
+    ]
+}
+
+/// `+[AVAssetWriterInput assetWriterInputWithMediaType:outputSettings:sourceFormatHint:]`
+///
+/// Passthrough inputs need the hint for containers other than QuickTime
+/// movies, e.g. MPEG-4.
+///
+/// # Safety
+///
+/// `outputSettings`, if given, must be an `NSDictionary` of output settings;
+/// `sourceFormatHint` must be a valid `CMFormatDescriptionRef` of
+/// `mediaType`.
+pub unsafe fn AVAssetWriterInput_assetWriterInputWithMediaType_sourceFormatHint(
+    mediaType: &NSString,
+    outputSettings: Option<&AnyObject>,
+    sourceFormatHint: CMFormatDescriptionRef,
+) -> Retained<AnyObject> {
+    msg_send![
+        class!(AVAssetWriterInput),
+        assetWriterInputWithMediaType: mediaType,
+        outputSettings: outputSettings,
+        sourceFormatHint: sourceFormatHint as *mut opaqueCMFormatDescription
+    ]
+}
+
+/// `-[AVAssetWriterInput setExpectsMediaDataInRealTime:]`
+///
+/// # Safety
+///
+/// `input` must be an `AVAssetWriterInput`.
+pub unsafe fn AVAssetWriterInput_setExpectsMediaDataInRealTime(
+    input: &AnyObject,
+    expectsMediaDataInRealTime: bool,
+) {
+    let _: () = msg_send![
+        input,
+        setExpectsMediaDataInRealTime: Bool::new(expectsMediaDataInRealTime)
+    ];
+}
+
+/// `-[AVAssetWriterInput isReadyForMoreMediaData]`
+///
+/// # Safety
+///
+/// `input` must be an `AVAssetWriterInput`.
+pub unsafe fn AVAssetWriterInput_isReadyForMoreMediaData(input: &AnyObject) -> bool {
+    let ready: Bool = msg_send![input, isReadyForMoreMediaData];
+    ready.as_bool()
+}
+
+/// `-[AVAssetWriterInput appendSampleBuffer:]`
+///
+/// # Safety
+///
+/// `input` must be an `AVAssetWriterInput`; `sampleBuffer` must be a valid
+/// `CMSampleBufferRef`.
+pub unsafe fn AVAssetWriterInput_appendSampleBuffer(
+    input: &AnyObject,
+    sampleBuffer: CMSampleBufferRef,
+) -> bool {
+    let appended: Bool = msg_send![
+        input,
+        appendSampleBuffer: sampleBuffer as *mut opaqueCMSampleBuffer
+    ];
+    appended.as_bool()
+}
+
+/// `-[AVAssetWriterInput markAsFinished]`
+///
+/// # Safety
+///
+/// `input` must be an `AVAssetWriterInput`.
+pub unsafe fn AVAssetWriterInput_markAsFinished(input: &AnyObject) {
+    let _: () = msg_send![input, markAsFinished];
+}
@@ -0,0 +1,378 @@
+//! AVFoundation capture bindings: camera devices, their formats and the
+//! capture session feeding them to a sample buffer delegate.
+//!
+//! As in [`frame_processor`](crate::frame_processor), the Objective-C API is
+//! reached through thin `unsafe` message wrappers named after their
+//! selectors. Objects are returned as `Retained<AnyObject>`; errors carry
+//! the NSError code (see `AVError`, e.g. `-11814` when the device is in use).
+
+use core_foundation_sys::base::OSStatus;
+use core_media_sys::CMFormatDescriptionRef;
+use objc2::encode::{Encoding, RefEncode};
+use objc2::rc::Retained;
+use objc2::runtime::{AnyObject, Bool};
+use objc2::{class, msg_send};
+use objc2_foundation::{NSArray, NSError, NSNumber, NSString};
+
+use crate::base::EncodedCMTime;
+
+#[link(name = "AVFoundation", kind = "framework")]
+extern "C" {
+    pub static AVMediaTypeVideo: &'static NSString;
+    pub static AVMediaTypeAudio: &'static NSString;
+}
+
+/// Opaque CMFormatDescription, as returned by Objective-C methods.
+#[repr(C)]
+pub struct opaqueCMFormatDescription {
+    _private: [u8; 0],
+}
+
+// SAFETY: CMFormatDescriptionRef is `struct opaqueCMFormatDescription *` in
+// Objective-C method signatures.
+unsafe impl RefEncode for opaqueCMFormatDescription {
+    const ENCODING_REF: Encoding =
+        Encoding::Pointer(&Encoding::Struct("opaqueCMFormatDescription", &[]));
+}
+
+fn error_code(error: Retained<NSError>) -> OSStatus {
+    error.code() as OSStatus
+}
+
+/// `+[AVCaptureDevice devicesWithMediaType:]`: every connected device,
+/// including external and Continuity cameras.
+///
+/// # Safety
+///
+/// The `AVCaptureDevice` class registered at runtime must declare this selector
+/// with the signature bound here.
+pub unsafe fn AVCaptureDevice_devicesWithMediaType(
+    mediaType: &NSString,
+) -> Retained<NSArray<AnyObject>> {
+    msg_send![class!(AVCaptureDevice), devicesWithMediaType: mediaType]
+}
+
+/// `+[AVCaptureDevice defaultDeviceWithMediaType:]`
+///
+/// # Safety
+///
+/// The `AVCaptureDevice` class registered at runtime must declare this selector
+/// with the signature bound here.
+pub unsafe fn AVCaptureDevice_defaultDeviceWithMediaType(
+    mediaType: &NSString,
+) -> Option<Retained<AnyObject>> {
+    msg_send![class!(AVCaptureDevice), defaultDeviceWithMediaType: mediaType]
+}
+
+/// `+[AVCaptureDevice deviceWithUniqueID:]`
+///
+/// # Safety
+///
+/// The `AVCaptureDevice` class registered at runtime must declare this selector
+/// with the signature bound here.
+pub unsafe fn AVCaptureDevice_deviceWithUniqueID(
+    deviceUniqueID: &NSString,
+) -> Option<Retained<AnyObject>> {
+    msg_send![class!(AVCaptureDevice), deviceWithUniqueID: deviceUniqueID]
+}
+
+/// `-[AVCaptureDevice uniqueID]`, stable across reconnects and reboots
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`.
+pub unsafe fn AVCaptureDevice_uniqueID(device: &AnyObject) -> Retained<NSString> {
+    msg_send![device, uniqueID]
+}
+
+/// `-[AVCaptureDevice localizedName]`
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`.
+pub unsafe fn AVCaptureDevice_localizedName(device: &AnyObject) -> Retained<NSString> {
+    msg_send![device, localizedName]
+}
+
+/// `-[AVCaptureDevice formats]`, an array of AVCaptureDeviceFormat
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`.
+pub unsafe fn AVCaptureDevice_formats(device: &AnyObject) -> Retained<NSArray<AnyObject>> {
+    msg_send![device, formats]
+}
+
+/// `-[AVCaptureDevice activeFormat]`
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`.
+pub unsafe fn AVCaptureDevice_activeFormat(device: &AnyObject) -> Retained<AnyObject> {
+    msg_send![device, activeFormat]
+}
+
+/// `-[AVCaptureDevice setActiveFormat:]`; requires
+/// [`AVCaptureDevice_lockForConfiguration`].
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`; `activeFormat` must be one of
+/// `device`'s `AVCaptureDeviceFormat`s; `device` must be locked with
+/// `AVCaptureDevice_lockForConfiguration`.
+pub unsafe fn AVCaptureDevice_setActiveFormat(device: &AnyObject, activeFormat: &AnyObject) {
+    let _: () = msg_send![device, setActiveFormat: activeFormat];
+}
+
+/// `-[AVCaptureDevice setActiveVideoMinFrameDuration:]`, i.e. the maximum
+/// frame rate
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`; `device` must be locked with
+/// `AVCaptureDevice_lockForConfiguration`.
+pub unsafe fn AVCaptureDevice_setActiveVideoMinFrameDuration(
+    device: &AnyObject,
+    activeVideoMinFrameDuration: EncodedCMTime,
+) {
+    let _: () = msg_send![
+        device,
+        setActiveVideoMinFrameDuration: activeVideoMinFrameDuration
+    ];
+}
+
+/// `-[AVCaptureDevice setActiveVideoMaxFrameDuration:]`, i.e. the minimum
+/// frame rate
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`; `device` must be locked with
+/// `AVCaptureDevice_lockForConfiguration`.
+pub unsafe fn AVCaptureDevice_setActiveVideoMaxFrameDuration(
+    device: &AnyObject,
+    activeVideoMaxFrameDuration: EncodedCMTime,
+) {
+    let _: () = msg_send![
+        device,
+        setActiveVideoMaxFrameDuration: activeVideoMaxFrameDuration
+    ];
+}
+
+/// `-[AVCaptureDevice lockForConfiguration:]`
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`.
+pub unsafe fn AVCaptureDevice_lockForConfiguration(device: &AnyObject) -> Result<(), OSStatus> {
+    let result: Result<(), Retained<NSError>> = msg_send![device, lockForConfiguration: _];
+    result.map_err(error_code)
+}
+
+/// `-[AVCaptureDevice unlockForConfiguration]`
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`.
+pub unsafe fn AVCaptureDevice_unlockForConfiguration(device: &AnyObject) {
+    let _: () = msg_send![device, unlockForConfiguration];
+}
+
+/// `-[AVCaptureDeviceFormat formatDescription]`, owned by the format
+///
+/// # Safety
+///
+/// `format` must be an `AVCaptureDeviceFormat`.
+pub unsafe fn AVCaptureDeviceFormat_formatDescription(
+    format: &AnyObject,
+) -> CMFormatDescriptionRef {
+    let description: *mut opaqueCMFormatDescription = msg_send![format, formatDescription];
+    description as CMFormatDescriptionRef
+}
+
+/// `-[AVCaptureDeviceFormat videoSupportedFrameRateRanges]`, an array of
+/// AVFrameRateRange
+///
+/// # Safety
+///
+/// `format` must be an `AVCaptureDeviceFormat`.
+pub unsafe fn AVCaptureDeviceFormat_videoSupportedFrameRateRanges(
+    format: &AnyObject,
+) -> Retained<NSArray<AnyObject>> {
+    msg_send![format, videoSupportedFrameRateRanges]
+}
+
+/// `-[AVFrameRateRange minFrameRate]`
+///
+/// # Safety
+///
+/// `range` must be an `AVFrameRateRange`.
+pub unsafe fn AVFrameRateRange_minFrameRate(range: &AnyObject) -> f64 {
+    msg_send![range, minFrameRate]
+}
+
+/// `-[AVFrameRateRange maxFrameRate]`
+///
+/// # Safety
+///
+/// `range` must be an `AVFrameRateRange`.
+pub unsafe fn AVFrameRateRange_maxFrameRate(range: &AnyObject) -> f64 {
+    msg_send![range, maxFrameRate]
+}
+
+/// `-[AVFrameRateRange minFrameDuration]`, the exact duration of the
+/// maximum frame rate (e.g. 1001/30000 for 29.97 fps)
+///
+/// # Safety
+///
+/// `range` must be an `AVFrameRateRange`.
+pub unsafe fn AVFrameRateRange_minFrameDuration(range: &AnyObject) -> EncodedCMTime {
+    msg_send![range, minFrameDuration]
+}
+
+/// `+[AVCaptureDeviceInput deviceInputWithDevice:error:]`
+///
+/// # Safety
+///
+/// `device` must be an `AVCaptureDevice`.
+pub unsafe fn AVCaptureDeviceInput_deviceInputWithDevice(
+    device: &AnyObject,
+) -> Result<Retained<AnyObject>, OSStatus> {
+    let result: Result<Retained<AnyObject>, Retained<NSError>> =
+        msg_send![class!(AVCaptureDeviceInput), deviceInputWithDevice: device, error: _];
+    result.map_err(error_code)
+}
+
+/// `+[AVCaptureSession new]`
+///
+/// # Safety
+///
+/// The `AVCaptureSession` class registered at runtime must declare this
+/// selector with the signature bound here.
+pub unsafe fn AVCaptureSession_new() -> Retained<AnyObject> {
+    msg_send![class!(AVCaptureSession), new]
+}
+
+/// `-[AVCaptureSession beginConfiguration]`
+///
+/// # Safety
+///
+/// `session` must be an `AVCaptureSession`.
+pub unsafe fn AVCaptureSession_beginConfiguration(session: &AnyObject) {
+    let _: () = msg_send![session, beginConfiguration];
+}
+
+/// `-[AVCaptureSession commitConfiguration]`
+///
+/// # Safety
+///
+/// `session` must be an `AVCaptureSession`.
+pub unsafe fn AVCaptureSession_commitConfiguration(session: &AnyObject) {
+    let _: () = msg_send![session, commitConfiguration];
+}
+
+/// `-[AVCaptureSession addInput:]` if `-canAddInput:` allows it
+///
+/// # Safety
+///
+/// `session` must be an `AVCaptureSession`; `input` must be an
+/// `AVCaptureInput`.
+pub unsafe fn AVCaptureSession_addInput(session: &AnyObject, input: &AnyObject) -> bool {
+    let can_add: Bool = msg_send![session, canAddInput: input];
+    if can_add.as_bool() {
+        let _: () = msg_send![session, addInput: input];
+    }
+    can_add.as_bool()
+}
+
+/// `-[AVCaptureSession addOutput:]` if `-canAddOutput:` allows it
+///
+/// # Safety
+///
+/// `session` must be an `AVCaptureSession`; `output` must be an
+/// `AVCaptureOutput`.
+pub unsafe fn AVCaptureSession_addOutput(session: &AnyObject, output: &AnyObject) -> bool {
+    let can_add: Bool = msg_send![session, canAddOutput: output];
+    if can_add.as_bool() {
+        let _: () = msg_send![session, addOutput: output];
+    }
+    can_add.as_bool()
+}
+
+/// `-[AVCaptureSession startRunning]`, blocking until capture has started
+///
+/// # Safety
+///
+/// `session` must be an `AVCaptureSession`.
+pub unsafe fn AVCaptureSession_startRunning(session: &AnyObject) {
+    let _: () = msg_send![session, startRunning];
+}
+
+/// `-[AVCaptureSession stopRunning]`
+///
+/// # Safety
+///
+/// `session` must be an `AVCaptureSession`.
+pub unsafe fn AVCaptureSession_stopRunning(session: &AnyObject) {
+    let _: () = msg_send![session, stopRunning];
+}
+
+/// `-[AVCaptureSession isRunning]`
+///
+/// # Safety
+///
+/// `session` must be an `AVCaptureSession`.
+pub unsafe fn AVCaptureSession_isRunning(session: &AnyObject) -> bool {
+    let running: Bool = msg_send![session, isRunning];
+    running.as_bool()
+}
+
+/// `+[AVCaptureVideoDataOutput new]`
+///
+/// # Safety
+///
+/// The `AVCaptureVideoDataOutput` class registered at runtime must declare this
+/// selector with the signature bound here.
+pub unsafe fn AVCaptureVideoDataOutput_new() -> Retained<AnyObject> {
+    msg_send![class!(AVCaptureVideoDataOutput), new]
+}
+
+/// `-[AVCaptureVideoDataOutput availableVideoCVPixelFormatTypes]`: the pixel
+/// formats the output can deliver, most efficient first
+///
+/// # Safety
+///
+/// `output` must be an `AVCaptureVideoDataOutput`.
+pub unsafe fn AVCaptureVideoDataOutput_availableVideoCVPixelFormatTypes(
+    output: &AnyObject,
+) -> Retained<NSArray<NSNumber>> {
+    msg_send![output, availableVideoCVPixelFormatTypes]
+}
+
+/// `-[AVCaptureVideoDataOutput setVideoSettings:]`, an NSDictionary (or
+/// toll-free bridged CFDictionary) of pixel buffer attributes
+///
+/// # Safety
+///
+/// `output` must be an `AVCaptureVideoDataOutput`; `videoSettings` must be an
+/// `NSDictionary` of video settings.
+pub unsafe fn AVCaptureVideoDataOutput_setVideoSettings(
+    output: &AnyObject,
+    videoSettings: &AnyObject,
+) {
+    let _: () = msg_send![output, setVideoSettings: videoSettings];
+}
+
+/// `-[AVCaptureVideoDataOutput setAlwaysDiscardsLateVideoFrames:]`
+///
+/// # Safety
+///
+/// `output` must be an `AVCaptureVideoDataOutput`.
+pub unsafe fn AVCaptureVideoDataOutput_setAlwaysDiscardsLateVideoFrames(
+    output: &AnyObject,
+    alwaysDiscardsLateVideoFrames: bool,
+) {
+    let _: () = msg_send![
+        output,
+        setAlwaysDiscardsLateVideoFrames: Bool::new(alwaysDiscardsLateVideoFrames)
+    ];
+}
@@ -1,3 +1,6 @@
+use core_media_sys::CMTime;
+use objc2::encode::{Encode, Encoding};
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct VTInt32Point {
@@ -11,3 +14,46 @@ pub struct VTInt32Size {
     pub width: i32,
     pub height: i32,
 }
+
+/// CMTime as passed by value to Objective-C methods and blocks.
+///
+/// Same layout as [`CMTime`], with the `{?=qiIq}` type encoding objc2 and
+/// block2 require of arguments.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct EncodedCMTime {
+    pub value: i64,
+    pub timescale: i32,
+    pub flags: u32,
+    pub epoch: i64,
+}
+
+// SAFETY: same layout as CMTime.
+unsafe impl Encode for EncodedCMTime {
+    const ENCODING: Encoding = Encoding::Struct(
+        "?",
+        &[i64::ENCODING, i32::ENCODING, u32::ENCODING, i64::ENCODING],
+    );
+}
+
+impl From<CMTime> for EncodedCMTime {
+    fn from(time: CMTime) -> Self {
+        Self {
+            value: time.value,
+            timescale: time.timescale,
+            flags: time.flags,
+            epoch: time.epoch,
+        }
+    }
+}
+
+impl From<EncodedCMTime> for CMTime {
+    fn from(time: EncodedCMTime) -> Self {
+        CMTime {
+            value: time.value,
+            timescale: time.timescale,
+            flags: time.flags,
+            epoch: time.epoch,
+        }
+    }
+}
@@ -10,10 +10,15 @@
 //! - Get timing information (PTS, DTS, duration)
 //! - Check sample attachment properties (sync samples/keyframes)
 
-use core_foundation_sys::base::OSStatus;
-use core_media_sys::{CMFormatDescriptionRef, CMSampleBufferRef, CMTime};
+use core_foundation_sys::base::{CFAllocatorRef, OSStatus};
+use core_media_sys::{
+    CMBlockBufferFlags, CMFormatDescriptionRef, CMSampleBufferRef, CMSampleTimingInfo, CMTime,
+};
 use libc::c_void;
 
+use crate::audio_converter::AudioStreamBasicDescription;
+use crate::cv_types::CVImageBufferRef;
+
 /// Opaque type for CMBlockBuffer.
 #[repr(C)]
 pub struct __CMBlockBuffer {
@@ -46,6 +51,12 @@ extern "C" {
     /// Returns NULL if the sample buffer has no data buffer (e.g., for gap samples).
     pub fn CMSampleBufferGetDataBuffer(sbuf: CMSampleBufferRef) -> CMBlockBufferRef;
 
+    /// Returns the image buffer of an uncompressed video sample buffer, e.g.
+    /// a captured frame.
+    ///
+    /// Returns NULL for encoded samples and for frames without new content.
+    pub fn CMSampleBufferGetImageBuffer(sbuf: CMSampleBufferRef) -> CVImageBufferRef;
+
     /// Returns the format description of the samples in the buffer.
     ///
     /// For video, this contains codec information including H.264 parameter sets.
@@ -164,6 +175,67 @@ extern "C" {
         NALUnitHeaderLengthOut: *mut i32,
     ) -> OSStatus;
 
+    // ============================================
+    // Sample buffer creation
+    // ============================================
+
+    /// Creates an H.264 format description from its parameter sets.
+    ///
+    /// # Arguments
+    /// * `parameterSetCount` - Number of parameter sets (at least one SPS and one PPS)
+    /// * `parameterSetPointers` - Parameter sets without start codes or length prefixes
+    /// * `parameterSetSizes` - Size of each parameter set
+    /// * `NALUnitHeaderLength` - Length prefix size of the samples (1, 2 or 4)
+    /// * `formatDescriptionOut` - Returns the format description (+1 retained)
+    pub fn CMVideoFormatDescriptionCreateFromH264ParameterSets(
+        allocator: CFAllocatorRef,
+        parameterSetCount: usize,
+        parameterSetPointers: *const *const u8,
+        parameterSetSizes: *const usize,
+        NALUnitHeaderLength: i32,
+        formatDescriptionOut: *mut CMFormatDescriptionRef,
+    ) -> OSStatus;
+
+    /// Creates a block buffer over a memory block.
+    ///
+    /// With a NULL `memoryBlock`, the block is allocated with `blockAllocator`
+    /// (NULL for the default allocator); pass `kCMBlockBufferAssureMemoryNowFlag`
+    /// to allocate it immediately.
+    pub fn CMBlockBufferCreateWithMemoryBlock(
+        structureAllocator: CFAllocatorRef,
+        memoryBlock: *mut c_void,
+        blockLength: usize,
+        blockAllocator: CFAllocatorRef,
+        customBlockSource: *const c_void,
+        offsetToData: usize,
+        dataLength: usize,
+        flags: CMBlockBufferFlags,
+        blockBufferOut: *mut CMBlockBufferRef,
+    ) -> OSStatus;
+
+    /// Copies bytes into a block buffer, replacing the data at the given offset.
+    pub fn CMBlockBufferReplaceDataBytes(
+        sourceBytes: *const c_void,
+        destinationBuffer: CMBlockBufferRef,
+        offsetIntoDestination: usize,
+        dataLength: usize,
+    ) -> OSStatus;
+
+    /// Creates a sample buffer whose data is ready.
+    ///
+    /// The sample buffer retains `dataBuffer` and `formatDescription`.
+    pub fn CMSampleBufferCreateReady(
+        allocator: CFAllocatorRef,
+        dataBuffer: CMBlockBufferRef,
+        formatDescription: CMFormatDescriptionRef,
+        numSamples: isize,
+        numSampleTimingEntries: isize,
+        sampleTimingArray: *const CMSampleTimingInfo,
+        numSampleSizeEntries: isize,
+        sampleSizeArray: *const usize,
+        sampleBufferOut: *mut CMSampleBufferRef,
+    ) -> OSStatus;
+
     // ============================================
     // Video format description utilities
     // ============================================
@@ -173,8 +245,24 @@ extern "C" {
         videoDesc: CMFormatDescriptionRef,
     ) -> CMVideoDimensions;
 
+    /// Returns the media type (FourCC) of the format description, e.g.
+    /// `'vide'` or `'soun'`.
+    pub fn CMFormatDescriptionGetMediaType(desc: CMFormatDescriptionRef) -> u32;
+
     /// Returns the codec type (FourCC) of the format description.
     pub fn CMFormatDescriptionGetMediaSubType(desc: CMFormatDescriptionRef) -> u32;
+
+    // ============================================
+    // Audio format description utilities
+    // ============================================
+
+    /// Returns the stream description of an audio format description.
+    ///
+    /// Returns NULL if `desc` is not an audio format description. The pointer
+    /// is valid for the lifetime of `desc`.
+    pub fn CMAudioFormatDescriptionGetStreamBasicDescription(
+        desc: CMFormatDescriptionRef,
+    ) -> *const AudioStreamBasicDescription;
 }
 
 /// Video dimensions structure.
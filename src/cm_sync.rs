@@ -0,0 +1,171 @@
+//! CoreMedia CMClock and CMTimebase FFI bindings.
+//!
+//! Clocks and timebases provide a shared notion of time for real-time pipelines:
+//! - CMClock: a source of monotonically increasing time (e.g., the host time clock)
+//! - CMTimebase: a timeline driven by a clock at an adjustable rate (playback time)
+//!
+//! Use [`CMSyncConvertTime`] to map timestamps between clocks and timebases, for
+//! example from a capture device's clock to the host time clock.
+
+use core_foundation_sys::base::{CFAllocatorRef, CFTypeID, CFTypeRef, OSStatus};
+use core_media_sys::{CMClockRef, CMTime};
+use libc::c_void;
+
+/// Opaque type for CMTimebase.
+#[repr(C)]
+pub struct OpaqueCMTimebase {
+    _private: c_void,
+}
+
+/// Reference to a CoreMedia timebase.
+pub type CMTimebaseRef = *mut OpaqueCMTimebase;
+
+/// Either a CMClockRef or a CMTimebaseRef.
+pub type CMClockOrTimebaseRef = CFTypeRef;
+
+/// Rounding method passed to [`CMTimebaseGetTimeWithTimeScale`].
+pub type CMTimeRoundingMethod = u32;
+
+pub const kCMTimeRoundingMethod_RoundHalfAwayFromZero: CMTimeRoundingMethod = 1;
+pub const kCMTimeRoundingMethod_RoundTowardZero: CMTimeRoundingMethod = 2;
+pub const kCMTimeRoundingMethod_RoundAwayFromZero: CMTimeRoundingMethod = 3;
+pub const kCMTimeRoundingMethod_QuickTime: CMTimeRoundingMethod = 4;
+pub const kCMTimeRoundingMethod_RoundTowardPositiveInfinity: CMTimeRoundingMethod = 5;
+pub const kCMTimeRoundingMethod_RoundTowardNegativeInfinity: CMTimeRoundingMethod = 6;
+pub const kCMTimeRoundingMethod_Default: CMTimeRoundingMethod =
+    kCMTimeRoundingMethod_RoundHalfAwayFromZero;
+
+// CMClock / CMTimebase error codes
+pub const kCMClockError_MissingRequiredParameter: OSStatus = -12745;
+pub const kCMClockError_InvalidParameter: OSStatus = -12746;
+pub const kCMClockError_AllocationFailed: OSStatus = -12747;
+pub const kCMClockError_UnsupportedOperation: OSStatus = -12756;
+pub const kCMTimebaseError_MissingRequiredParameter: OSStatus = -12748;
+pub const kCMTimebaseError_InvalidParameter: OSStatus = -12749;
+pub const kCMTimebaseError_AllocationFailed: OSStatus = -12750;
+pub const kCMTimebaseError_TimerIntervalTooShort: OSStatus = -12751;
+pub const kCMTimebaseError_ReadOnly: OSStatus = -12757;
+
+#[link(name = "CoreMedia", kind = "framework")]
+extern "C" {
+    // ============================================
+    // CMClock
+    // ============================================
+
+    /// Returns the clock driven by the host's `mach_absolute_time`.
+    ///
+    /// The returned clock is a shared singleton and must not be released.
+    pub fn CMClockGetHostTimeClock() -> CMClockRef;
+
+    /// Returns the current time of a clock.
+    pub fn CMClockGetTime(clock: CMClockRef) -> CMTime;
+
+    /// Converts a host time CMTime to `mach_absolute_time` units.
+    pub fn CMClockConvertHostTimeToSystemUnits(hostTime: CMTime) -> u64;
+
+    /// Converts `mach_absolute_time` units to a host time CMTime.
+    pub fn CMClockMakeHostTimeFromSystemUnits(hostTime: u64) -> CMTime;
+
+    /// Retrieves the clock's time and the corresponding reference (host) time.
+    pub fn CMClockGetAnchorTime(
+        clock: CMClockRef,
+        clockTimeOut: *mut CMTime,
+        referenceClockTimeOut: *mut CMTime,
+    ) -> OSStatus;
+
+    /// Reports whether two clocks may drift relative to each other.
+    pub fn CMClockMightDrift(clock: CMClockRef, otherClock: CMClockRef) -> u8;
+
+    /// Makes the clock stop functioning (e.g., when its source device goes away).
+    pub fn CMClockInvalidate(clock: CMClockRef);
+
+    pub fn CMClockGetTypeID() -> CFTypeID;
+
+    // ============================================
+    // CMTimebase
+    // ============================================
+
+    /// Creates a timebase driven by the given source clock.
+    ///
+    /// The new timebase starts with rate 0 and time 0.
+    pub fn CMTimebaseCreateWithSourceClock(
+        allocator: CFAllocatorRef,
+        sourceClock: CMClockRef,
+        timebaseOut: *mut CMTimebaseRef,
+    ) -> OSStatus;
+
+    /// Creates a timebase driven by another timebase.
+    pub fn CMTimebaseCreateWithSourceTimebase(
+        allocator: CFAllocatorRef,
+        sourceTimebase: CMTimebaseRef,
+        timebaseOut: *mut CMTimebaseRef,
+    ) -> OSStatus;
+
+    /// Returns the current time of a timebase.
+    pub fn CMTimebaseGetTime(timebase: CMTimebaseRef) -> CMTime;
+
+    /// Returns the current time of a timebase in the given timescale.
+    pub fn CMTimebaseGetTimeWithTimeScale(
+        timebase: CMTimebaseRef,
+        timescale: i32,
+        method: CMTimeRoundingMethod,
+    ) -> CMTime;
+
+    /// Sets the current time of a timebase.
+    pub fn CMTimebaseSetTime(timebase: CMTimebaseRef, time: CMTime) -> OSStatus;
+
+    /// Sets the time of a timebase at a particular source time.
+    pub fn CMTimebaseSetAnchorTime(
+        timebase: CMTimebaseRef,
+        timebaseTime: CMTime,
+        immediateSourceTime: CMTime,
+    ) -> OSStatus;
+
+    /// Returns the rate of a timebase relative to its source.
+    pub fn CMTimebaseGetRate(timebase: CMTimebaseRef) -> f64;
+
+    /// Returns the rate of a timebase relative to the root clock.
+    pub fn CMTimebaseGetEffectiveRate(timebase: CMTimebaseRef) -> f64;
+
+    /// Sets the rate of a timebase (0.0 pauses, 1.0 plays in real time).
+    pub fn CMTimebaseSetRate(timebase: CMTimebaseRef, rate: f64) -> OSStatus;
+
+    /// Sets time and rate atomically, anchored at a source time.
+    pub fn CMTimebaseSetRateAndAnchorTime(
+        timebase: CMTimebaseRef,
+        rate: f64,
+        timebaseTime: CMTime,
+        immediateSourceTime: CMTime,
+    ) -> OSStatus;
+
+    /// Returns the source clock of a timebase, or NULL if it is driven by a timebase.
+    pub fn CMTimebaseCopySourceClock(timebase: CMTimebaseRef) -> CMClockRef;
+
+    pub fn CMTimebaseGetTypeID() -> CFTypeID;
+
+    // ============================================
+    // Conversion
+    // ============================================
+
+    /// Converts a time from one clock or timebase to another.
+    pub fn CMSyncConvertTime(
+        time: CMTime,
+        fromClockOrTimebase: CMClockOrTimebaseRef,
+        toClockOrTimebase: CMClockOrTimebaseRef,
+    ) -> CMTime;
+
+    /// Returns the relative rate of one clock or timebase to another.
+    pub fn CMSyncGetRelativeRate(
+        ofClockOrTimebase: CMClockOrTimebaseRef,
+        relativeToClockOrTimebase: CMClockOrTimebaseRef,
+    ) -> f64;
+
+    /// Returns the current time of a clock or timebase.
+    pub fn CMSyncGetTime(clockOrTimebase: CMClockOrTimebaseRef) -> CMTime;
+
+    /// Reports whether two clocks or timebases may drift relative to each other.
+    pub fn CMSyncMightDrift(
+        clockOrTimebase1: CMClockOrTimebaseRef,
+        clockOrTimebase2: CMClockOrTimebaseRef,
+    ) -> u8;
+}
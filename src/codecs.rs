@@ -22,6 +22,12 @@ pub mod video {
 
     /// JPEG ('jpeg')
     pub const JPEG: u32 = 0x6a706567;
+
+    /// VP9 ('vp09'), decode only
+    pub const VP9: u32 = 0x76703039;
+
+    /// AV1 ('av01'), decode only
+    pub const AV1: u32 = 0x61763031;
 }
 
 /// Pixel format FourCC constants (CVPixelFormatType)
@@ -41,6 +47,12 @@ pub mod pixel {
     /// Bi-Planar Y'CbCr 4:2:0 full range ('420f')
     pub const YUV420_BIPLANAR_FULL_RANGE: u32 = 0x34323066;
 
+    /// Planar Y'CbCr 4:2:0, i.e. I420 ('y420')
+    pub const YUV420_PLANAR: u32 = 0x79343230;
+
+    /// Planar Y'CbCr 4:2:0 full range ('f420')
+    pub const YUV420_PLANAR_FULL_RANGE: u32 = 0x66343230;
+
     /// Planar Y'CbCr 4:2:2 ('y422')
     pub const YUV422: u32 = 0x79343232;
 
@@ -4,6 +4,8 @@ use core_foundation_sys::string::CFStringRef;
 use core_media_sys::{CMItemCount, CMSampleBufferRef, CMTime, CMTimeRange, CMVideoCodecType};
 
 use crate::cv_types::{CVImageBufferRef, CVPixelBufferPoolRef};
+use bitflags::bitflags;
+use block2::Block;
 use libc::{c_int, c_void};
 
 pub const kVTUnlimitedFrameDelayCount: c_int = -1;
@@ -19,8 +21,13 @@ pub type VTCompressionOutputCallback = extern "C" fn(
     infoFlags: VTEncodeInfoFlags,
     sampleBuffer: CMSampleBufferRef,
 );
+/// `void (^)(OSStatus status, VTEncodeInfoFlags infoFlags, CMSampleBufferRef sampleBuffer)`
+///
+/// The sample buffer is untyped because block arguments need an Objective-C
+/// type encoding; cast it to `CMSampleBufferRef`. VideoToolbox copies the
+/// block, so it may be released once the encode call returns.
 pub type VTCompressionOutputHandler =
-    extern "C" fn(status: OSStatus, infoFlags: VTEncodeInfoFlags, sampleBuffer: CMSampleBufferRef);
+    *const Block<dyn Fn(OSStatus, VTEncodeInfoFlags, *mut c_void)>;
 
 // VTEncodeInfoFlags
 //
@@ -28,6 +35,20 @@ pub type VTCompressionOutputHandler =
 pub const kVTEncodeInfo_Asynchronous: VTEncodeInfoFlags = 1 << 0;
 pub const kVTEncodeInfo_FrameDropped: VTEncodeInfoFlags = 1 << 1;
 
+bitflags! {
+    /// Typed form of [`VTEncodeInfoFlags`].
+    ///
+    /// Converts losslessly to the raw value with [`EncodeInfoFlags::bits`].
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct EncodeInfoFlags: VTEncodeInfoFlags {
+        /// The frame is being encoded asynchronously.
+        const ASYNCHRONOUS = kVTEncodeInfo_Asynchronous;
+        /// The frame was dropped by the encoder.
+        const FRAME_DROPPED = kVTEncodeInfo_FrameDropped;
+    }
+}
+
 // VTCompressionSessionOptionFlags
 //
 pub const kVTCompressionSessionBeginFinalPass: VTCompressionSessionOptionFlags = 1 << 0;
@@ -190,7 +211,7 @@ extern "C" {
     pub fn VTCompressionSessionGetTimeRangesForNextPass(
         session: VTCompressionSessionRef,
         timeRangeCountOut: *mut CMItemCount,
-        timeRangeArrayOut: *const CMTimeRange,
+        timeRangeArrayOut: *mut *const CMTimeRange,
     ) -> OSStatus;
 
     // Ending Sessions
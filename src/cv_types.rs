@@ -8,6 +8,9 @@ use core_foundation_sys::dictionary::CFDictionaryRef;
 use core_foundation_sys::string::CFStringRef;
 use core_foundation_sys::base::CFTypeRef;
 use libc::c_void;
+use objc2::encode::{Encoding, RefEncode};
+
+use crate::io_surface::IOSurfaceRef;
 
 /// Opaque type for CVBuffer
 #[repr(C)]
@@ -15,6 +18,12 @@ pub struct __CVBuffer {
     _private: c_void,
 }
 
+// SAFETY: CVBufferRef is `struct __CVBuffer *` in Objective-C method and
+// block signatures.
+unsafe impl RefEncode for __CVBuffer {
+    const ENCODING_REF: Encoding = Encoding::Pointer(&Encoding::Struct("__CVBuffer", &[]));
+}
+
 /// Reference to a CoreVideo buffer.
 pub type CVBufferRef = *mut __CVBuffer;
 
@@ -30,6 +39,30 @@ pub type CVPixelBufferPoolRef = CFTypeRef;
 /// CVReturn success code
 pub const kCVReturnSuccess: i32 = 0;
 
+/// CVReturn for an invalid argument, e.g. a null pool
+pub const kCVReturnInvalidArgument: i32 = -6661;
+
+/// CVReturn for an unsupported pixel format
+pub const kCVReturnInvalidPixelFormat: i32 = -6680;
+
+/// CVReturn when a pool has reached its allocation threshold
+pub const kCVReturnWouldExceedAllocationThreshold: i32 = -6689;
+
+/// Pool flush option that frees every buffer not currently in use
+pub const kCVPixelBufferPoolFlushExcessBuffers: u64 = 1;
+
+/// Lock flag for read-only CPU access to a pixel buffer
+pub const kCVPixelBufferLock_ReadOnly: u64 = 0x00000001;
+
+/// Attachment propagation mode
+pub type CVAttachmentMode = u32;
+
+/// Attachment that is not copied to buffers derived from this one
+pub const kCVAttachmentMode_ShouldNotPropagate: CVAttachmentMode = 0;
+
+/// Attachment that is copied to buffers derived from this one
+pub const kCVAttachmentMode_ShouldPropagate: CVAttachmentMode = 1;
+
 #[link(name = "CoreVideo", kind = "framework")]
 extern "C" {
     // Property keys
@@ -40,6 +73,34 @@ extern "C" {
     pub static kCVPixelBufferCGBitmapContextCompatibilityKey: CFStringRef;
     pub static kCVPixelBufferIOSurfacePropertiesKey: CFStringRef;
 
+    // Pixel buffer pool keys
+    pub static kCVPixelBufferPoolMinimumBufferCountKey: CFStringRef;
+    pub static kCVPixelBufferPoolMaximumBufferAgeKey: CFStringRef;
+    pub static kCVPixelBufferPoolAllocationThresholdKey: CFStringRef;
+
+    // Image buffer attachment keys
+    pub static kCVImageBufferCleanApertureKey: CFStringRef;
+    pub static kCVImageBufferCleanApertureWidthKey: CFStringRef;
+    pub static kCVImageBufferCleanApertureHeightKey: CFStringRef;
+    pub static kCVImageBufferCleanApertureHorizontalOffsetKey: CFStringRef;
+    pub static kCVImageBufferCleanApertureVerticalOffsetKey: CFStringRef;
+
+    // CVBuffer attachments
+    pub fn CVBufferSetAttachment(
+        buffer: CVBufferRef,
+        key: CFStringRef,
+        value: CFTypeRef,
+        attachmentMode: CVAttachmentMode,
+    );
+
+    pub fn CVBufferGetAttachment(
+        buffer: CVBufferRef,
+        key: CFStringRef,
+        attachmentMode: *mut CVAttachmentMode,
+    ) -> CFTypeRef;
+
+    pub fn CVBufferRemoveAttachment(buffer: CVBufferRef, key: CFStringRef);
+
     // CVPixelBuffer functions
     pub fn CVPixelBufferCreate(
         allocator: CFAllocatorRef,
@@ -61,4 +122,67 @@ extern "C" {
     pub fn CVPixelBufferGetWidth(pixelBuffer: CVPixelBufferRef) -> usize;
 
     pub fn CVPixelBufferGetHeight(pixelBuffer: CVPixelBufferRef) -> usize;
+
+    pub fn CVPixelBufferGetPixelFormatType(pixelBuffer: CVPixelBufferRef) -> u32;
+
+    // IOSurface-backed pixel buffers
+    pub fn CVPixelBufferGetIOSurface(pixelBuffer: CVPixelBufferRef) -> IOSurfaceRef;
+
+    pub fn CVPixelBufferCreateWithIOSurface(
+        allocator: CFAllocatorRef,
+        surface: IOSurfaceRef,
+        pixelBufferAttributes: CFDictionaryRef,
+        pixelBufferOut: *mut CVPixelBufferRef,
+    ) -> i32;
+
+    // Planar pixel buffer access
+    pub fn CVPixelBufferIsPlanar(pixelBuffer: CVPixelBufferRef) -> u8;
+
+    pub fn CVPixelBufferGetPlaneCount(pixelBuffer: CVPixelBufferRef) -> usize;
+
+    pub fn CVPixelBufferGetBaseAddressOfPlane(
+        pixelBuffer: CVPixelBufferRef,
+        planeIndex: usize,
+    ) -> *mut c_void;
+
+    pub fn CVPixelBufferGetBytesPerRowOfPlane(
+        pixelBuffer: CVPixelBufferRef,
+        planeIndex: usize,
+    ) -> usize;
+
+    pub fn CVPixelBufferGetWidthOfPlane(
+        pixelBuffer: CVPixelBufferRef,
+        planeIndex: usize,
+    ) -> usize;
+
+    pub fn CVPixelBufferGetHeightOfPlane(
+        pixelBuffer: CVPixelBufferRef,
+        planeIndex: usize,
+    ) -> usize;
+
+    // CVPixelBufferPool functions
+    pub fn CVPixelBufferPoolCreate(
+        allocator: CFAllocatorRef,
+        poolAttributes: CFDictionaryRef,
+        pixelBufferAttributes: CFDictionaryRef,
+        poolOut: *mut CVPixelBufferPoolRef,
+    ) -> i32;
+
+    pub fn CVPixelBufferPoolCreatePixelBuffer(
+        allocator: CFAllocatorRef,
+        pixelBufferPool: CVPixelBufferPoolRef,
+        pixelBufferOut: *mut CVPixelBufferRef,
+    ) -> i32;
+
+    pub fn CVPixelBufferPoolCreatePixelBufferWithAuxAttributes(
+        allocator: CFAllocatorRef,
+        pixelBufferPool: CVPixelBufferPoolRef,
+        auxAttributes: CFDictionaryRef,
+        pixelBufferOut: *mut CVPixelBufferRef,
+    ) -> i32;
+
+    pub fn CVPixelBufferPoolGetPixelBufferAttributes(pool: CVPixelBufferPoolRef)
+        -> CFDictionaryRef;
+
+    pub fn CVPixelBufferPoolFlush(pool: CVPixelBufferPoolRef, options: u64);
 }
@@ -5,7 +5,10 @@ use core_media_sys::{
     CMFormatDescriptionRef, CMSampleBufferRef, CMTime, CMVideoFormatDescriptionRef,
 };
 
+use crate::base::EncodedCMTime;
 use crate::cv_types::{CVImageBufferRef, CVPixelBufferRef};
+use bitflags::bitflags;
+use block2::Block;
 use libc::c_void;
 
 pub type VTDecodeInfoFlags = u32;
@@ -35,13 +38,14 @@ pub type VTDecompressionOutputCallback = extern "C" fn(
     presentationTimeStamp: CMTime,
     presentationDuration: CMTime,
 );
-pub type VTDecompressionOutputHandler = extern "C" fn(
-    status: OSStatus,
-    infoFlags: VTDecodeInfoFlags,
-    imageBuffer: CVImageBufferRef,
-    presentationTimeStamp: CMTime,
-    presentationDuration: CMTime,
-);
+/// `void (^)(OSStatus status, VTDecodeInfoFlags infoFlags, CVImageBufferRef imageBuffer,
+/// CMTime presentationTimeStamp, CMTime presentationDuration)`
+///
+/// VideoToolbox copies the block, so it may be released once the decode call
+/// returns.
+pub type VTDecompressionOutputHandler = *const Block<
+    dyn Fn(OSStatus, VTDecodeInfoFlags, CVImageBufferRef, EncodedCMTime, EncodedCMTime),
+>;
 
 // VTDecodeFrameFlags
 //
@@ -71,6 +75,38 @@ pub const kVTDecodeInfo_Asynchronous: VTDecodeInfoFlags = 1 << 0;
 pub const kVTDecodeInfo_FrameDropped: VTDecodeInfoFlags = 1 << 1;
 pub const kVTDecodeInfo_ImageBufferModifiable: VTDecodeInfoFlags = 1 << 2;
 
+bitflags! {
+    /// Typed form of [`VTDecodeFrameFlags`].
+    ///
+    /// Converts losslessly to the raw value with [`DecodeFrameFlags::bits`].
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct DecodeFrameFlags: VTDecodeFrameFlags {
+        /// See [`kVTDecodeFrame_EnableAsynchronousDecompression`].
+        const ENABLE_ASYNCHRONOUS_DECOMPRESSION = kVTDecodeFrame_EnableAsynchronousDecompression;
+        /// See [`kVTDecodeFrame_DoNotOutputFrame`].
+        const DO_NOT_OUTPUT_FRAME = kVTDecodeFrame_DoNotOutputFrame;
+        /// See [`kVTDecodeFrame_1xRealTimePlayback`].
+        const REAL_TIME_PLAYBACK_1X = kVTDecodeFrame_1xRealTimePlayback;
+        /// See [`kVTDecodeFrame_EnableTemporalProcessing`].
+        const ENABLE_TEMPORAL_PROCESSING = kVTDecodeFrame_EnableTemporalProcessing;
+    }
+}
+
+bitflags! {
+    /// Typed form of [`VTDecodeInfoFlags`].
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct DecodeInfoFlags: VTDecodeInfoFlags {
+        /// The frame was decoded asynchronously.
+        const ASYNCHRONOUS = kVTDecodeInfo_Asynchronous;
+        /// The frame was dropped by the decoder.
+        const FRAME_DROPPED = kVTDecodeInfo_FrameDropped;
+        /// The emitted image buffer may be modified by the client.
+        const IMAGE_BUFFER_MODIFIABLE = kVTDecodeInfo_ImageBufferModifiable;
+    }
+}
+
 #[link(name = "VideoToolBox", kind = "framework")]
 extern "C" {
     pub static kVTDecompressionPropertyKey_PixelBufferPool: CFStringRef;
@@ -82,6 +118,7 @@ extern "C" {
     pub static kVTDecompressionPropertyKey_MaxOutputPresentationTimeStampOfFramesBeingDecoded:
         CFStringRef;
     pub static kVTDecompressionPropertyKey_ContentHasInterframeDependencies: CFStringRef;
+    pub static kVTDecompressionPropertyKey_PropagatePerFrameHDRDisplayMetadata: CFStringRef;
     pub static kVTVideoDecoderSpecification_EnableHardwareAcceleratedVideoDecoder: CFStringRef;
     pub static kVTVideoDecoderSpecification_RequireHardwareAcceleratedVideoDecoder: CFStringRef;
     pub static kVTDecompressionPropertyKey_UsingHardwareAcceleratedVideoDecoder: CFStringRef;
@@ -161,3 +198,26 @@ extern "C" {
         newFormatDesc: CMFormatDescriptionRef,
     ) -> Boolean;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_flags_match_raw_values() {
+        let flags = DecodeFrameFlags::ENABLE_ASYNCHRONOUS_DECOMPRESSION
+            | DecodeFrameFlags::REAL_TIME_PLAYBACK_1X;
+        assert_eq!(
+            flags.bits(),
+            kVTDecodeFrame_EnableAsynchronousDecompression | kVTDecodeFrame_1xRealTimePlayback
+        );
+        assert_eq!(DecodeFrameFlags::default().bits(), 0);
+
+        let info = DecodeInfoFlags::from_bits_retain(
+            kVTDecodeInfo_FrameDropped | kVTDecodeInfo_Asynchronous,
+        );
+        assert!(info.contains(DecodeInfoFlags::FRAME_DROPPED));
+        assert!(info.contains(DecodeInfoFlags::ASYNCHRONOUS));
+        assert!(!info.contains(DecodeInfoFlags::IMAGE_BUFFER_MODIFIABLE));
+    }
+}
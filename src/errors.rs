@@ -36,6 +36,22 @@ pub const kVTPixelTransferNotPermittedErr: OSStatus = -12218;
 pub const kVTColorCorrectionImageRotationFailedErr: OSStatus = -12219;
 pub const kVTVideoDecoderRemovedErr: OSStatus = -17690;
 
+// VTFrameProcessor errors (NSError codes in VTFrameProcessorErrorDomain)
+pub const VTFrameProcessorUnknownError: OSStatus = -19730;
+pub const VTFrameProcessorUnsupportedResolution: OSStatus = -19731;
+pub const VTFrameProcessorSessionNotStarted: OSStatus = -19732;
+pub const VTFrameProcessorSessionAlreadyActive: OSStatus = -19733;
+pub const VTFrameProcessorFatalError: OSStatus = -19734;
+pub const VTFrameProcessorSessionLevelError: OSStatus = -19735;
+pub const VTFrameProcessorInitializationFailed: OSStatus = -19736;
+pub const VTFrameProcessorUnsupportedInput: OSStatus = -19737;
+pub const VTFrameProcessorMemoryAllocationFailure: OSStatus = -19738;
+pub const VTFrameProcessorRevisionNotSupported: OSStatus = -19739;
+pub const VTFrameProcessorProcessingError: OSStatus = -19740;
+pub const VTFrameProcessorInvalidParameterError: OSStatus = -19741;
+pub const VTFrameProcessorInvalidFrameTiming: OSStatus = -19742;
+pub const VTFrameProcessorAssetDownloadFailed: OSStatus = -19743;
+
 /// Convert a VideoToolbox error status to a human-readable message.
 ///
 /// # Example
@@ -80,6 +96,20 @@ pub fn vt_error_to_string(status: OSStatus) -> &'static str {
         kVTPixelTransferNotPermittedErr => "Pixel transfer not permitted",
         kVTColorCorrectionImageRotationFailedErr => "Color correction image rotation failed",
         kVTVideoDecoderRemovedErr => "Video decoder was removed",
+        VTFrameProcessorUnknownError => "Frame processor unknown error",
+        VTFrameProcessorUnsupportedResolution => "Frame processor unsupported resolution",
+        VTFrameProcessorSessionNotStarted => "Frame processor session not started",
+        VTFrameProcessorSessionAlreadyActive => "Frame processor session already active",
+        VTFrameProcessorFatalError => "Frame processor fatal error",
+        VTFrameProcessorSessionLevelError => "Frame processor session-level error",
+        VTFrameProcessorInitializationFailed => "Frame processor initialization failed",
+        VTFrameProcessorUnsupportedInput => "Frame processor unsupported input",
+        VTFrameProcessorMemoryAllocationFailure => "Frame processor memory allocation failed",
+        VTFrameProcessorRevisionNotSupported => "Frame processor revision not supported",
+        VTFrameProcessorProcessingError => "Frame processor processing error",
+        VTFrameProcessorInvalidParameterError => "Frame processor invalid parameter",
+        VTFrameProcessorInvalidFrameTiming => "Frame processor invalid frame timing",
+        VTFrameProcessorAssetDownloadFailed => "Frame processor model download failed",
         _ => "Unknown error",
     }
 }
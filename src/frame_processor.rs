@@ -0,0 +1,278 @@
+//! VTFrameProcessor bindings: frame rate conversion and optical flow
+//! (macOS 15.4+, iOS 26+).
+//!
+//! VTFrameProcessor is an Objective-C API, so the classes are looked up at
+//! runtime (they are missing on older systems) and reached through thin
+//! `unsafe` message wrappers named after their selectors. Objects are
+//! returned as `Retained<AnyObject>`; errors carry the NSError code (see
+//! [`errors`](crate::errors), e.g. `VTFrameProcessorUnsupportedResolution`).
+
+use core_foundation_sys::base::OSStatus;
+use core_foundation_sys::string::CFStringRef;
+use objc2::msg_send;
+use objc2::rc::{Allocated, Retained};
+use objc2::runtime::{AnyClass, AnyObject, Bool};
+use objc2_foundation::{NSArray, NSError, NSInteger, NSNumber};
+use std::ffi::CStr;
+
+use crate::base::EncodedCMTime;
+use crate::cv_types::CVPixelBufferRef;
+
+#[link(name = "VideoToolBox", kind = "framework")]
+extern "C" {
+    pub static VTFrameProcessorErrorDomain: CFStringRef;
+}
+
+pub type VTFrameRateConversionConfigurationQualityPrioritization = NSInteger;
+pub const VTFrameRateConversionConfigurationQualityPrioritizationNormal: NSInteger = 1;
+pub const VTFrameRateConversionConfigurationQualityPrioritizationQuality: NSInteger = 2;
+
+pub type VTFrameRateConversionConfigurationRevision = NSInteger;
+pub const VTFrameRateConversionConfigurationRevision1: NSInteger = 1;
+
+pub type VTFrameRateConversionParametersSubmissionMode = NSInteger;
+pub const VTFrameRateConversionParametersSubmissionModeRandom: NSInteger = 1;
+pub const VTFrameRateConversionParametersSubmissionModeSequential: NSInteger = 2;
+
+pub type VTOpticalFlowConfigurationQualityPrioritization = NSInteger;
+pub const VTOpticalFlowConfigurationQualityPrioritizationNormal: NSInteger = 1;
+pub const VTOpticalFlowConfigurationQualityPrioritizationQuality: NSInteger = 2;
+
+pub type VTOpticalFlowConfigurationRevision = NSInteger;
+pub const VTOpticalFlowConfigurationRevision1: NSInteger = 1;
+
+pub type VTOpticalFlowParametersSubmissionMode = NSInteger;
+pub const VTOpticalFlowParametersSubmissionModeRandom: NSInteger = 1;
+pub const VTOpticalFlowParametersSubmissionModeSequential: NSInteger = 2;
+
+pub const VTFrameProcessorClass: &CStr = c"VTFrameProcessor";
+pub const VTFrameProcessorFrameClass: &CStr = c"VTFrameProcessorFrame";
+pub const VTFrameProcessorOpticalFlowClass: &CStr = c"VTFrameProcessorOpticalFlow";
+pub const VTFrameRateConversionConfigurationClass: &CStr = c"VTFrameRateConversionConfiguration";
+pub const VTFrameRateConversionParametersClass: &CStr = c"VTFrameRateConversionParameters";
+pub const VTOpticalFlowConfigurationClass: &CStr = c"VTOpticalFlowConfiguration";
+pub const VTOpticalFlowParametersClass: &CStr = c"VTOpticalFlowParameters";
+
+/// Look up a VTFrameProcessor class, or `None` on systems without it.
+pub fn VTFrameProcessorGetClass(name: &CStr) -> Option<&'static AnyClass> {
+    AnyClass::get(name)
+}
+
+fn error_code(error: Retained<NSError>) -> OSStatus {
+    error.code() as OSStatus
+}
+
+unsafe fn alloc(name: &CStr) -> Option<Allocated<AnyObject>> {
+    let class = VTFrameProcessorGetClass(name)?;
+    Some(msg_send![class, alloc])
+}
+
+/// `+[VTFrameRateConversionConfiguration isSupported]`
+///
+/// # Safety
+///
+/// The `VTFrameRateConversionConfiguration` class registered at runtime must
+/// declare this selector with the signature bound here, as on the systems named
+/// in the module docs.
+pub unsafe fn VTFrameRateConversionConfiguration_isSupported() -> bool {
+    match VTFrameProcessorGetClass(VTFrameRateConversionConfigurationClass) {
+        Some(class) => {
+            let supported: Bool = msg_send![class, isSupported];
+            supported.as_bool()
+        }
+        None => false,
+    }
+}
+
+/// `-[VTFrameRateConversionConfiguration initWithFrameWidth:frameHeight:usePrecomputedFlow:qualityPrioritization:revision:]`
+///
+/// # Safety
+///
+/// The `VTFrameRateConversionConfiguration` class registered at runtime must
+/// declare this selector with the signature bound here, as on the systems named
+/// in the module docs.
+pub unsafe fn VTFrameRateConversionConfiguration_init(
+    frameWidth: NSInteger,
+    frameHeight: NSInteger,
+    usePrecomputedFlow: bool,
+    qualityPrioritization: VTFrameRateConversionConfigurationQualityPrioritization,
+    revision: VTFrameRateConversionConfigurationRevision,
+) -> Option<Retained<AnyObject>> {
+    let object = alloc(VTFrameRateConversionConfigurationClass)?;
+    msg_send![
+        object,
+        initWithFrameWidth: frameWidth,
+        frameHeight: frameHeight,
+        usePrecomputedFlow: Bool::new(usePrecomputedFlow),
+        qualityPrioritization: qualityPrioritization,
+        revision: revision
+    ]
+}
+
+/// `-[VTFrameRateConversionConfiguration sourcePixelBufferAttributes]`, an
+/// NSDictionary toll-free bridged to CFDictionary.
+///
+/// # Safety
+///
+/// `configuration` must be a `VTFrameRateConversionConfiguration`.
+pub unsafe fn VTFrameRateConversionConfiguration_sourcePixelBufferAttributes(
+    configuration: &AnyObject,
+) -> Option<Retained<AnyObject>> {
+    msg_send![configuration, sourcePixelBufferAttributes]
+}
+
+/// `-[VTFrameRateConversionConfiguration destinationPixelBufferAttributes]`
+///
+/// # Safety
+///
+/// `configuration` must be a `VTFrameRateConversionConfiguration`.
+pub unsafe fn VTFrameRateConversionConfiguration_destinationPixelBufferAttributes(
+    configuration: &AnyObject,
+) -> Option<Retained<AnyObject>> {
+    msg_send![configuration, destinationPixelBufferAttributes]
+}
+
+/// `-[VTOpticalFlowConfiguration initWithFrameWidth:frameHeight:qualityPrioritization:revision:]`
+///
+/// # Safety
+///
+/// The `VTOpticalFlowConfiguration` class registered at runtime must declare
+/// this selector with the signature bound here, as on the systems named in the
+/// module docs.
+pub unsafe fn VTOpticalFlowConfiguration_init(
+    frameWidth: NSInteger,
+    frameHeight: NSInteger,
+    qualityPrioritization: VTOpticalFlowConfigurationQualityPrioritization,
+    revision: VTOpticalFlowConfigurationRevision,
+) -> Option<Retained<AnyObject>> {
+    let object = alloc(VTOpticalFlowConfigurationClass)?;
+    msg_send![
+        object,
+        initWithFrameWidth: frameWidth,
+        frameHeight: frameHeight,
+        qualityPrioritization: qualityPrioritization,
+        revision: revision
+    ]
+}
+
+/// `-[VTFrameProcessorFrame initWithBuffer:presentationTimeStamp:]`
+///
+/// # Safety
+///
+/// `buffer` must be a valid `CVPixelBufferRef`.
+pub unsafe fn VTFrameProcessorFrame_init(
+    buffer: CVPixelBufferRef,
+    presentationTimeStamp: EncodedCMTime,
+) -> Option<Retained<AnyObject>> {
+    let object = alloc(VTFrameProcessorFrameClass)?;
+    msg_send![
+        object,
+        initWithBuffer: buffer,
+        presentationTimeStamp: presentationTimeStamp
+    ]
+}
+
+/// `-[VTFrameRateConversionParameters initWithSourceFrame:nextFrame:opticalFlow:interpolationPhase:submissionMode:destinationFrames:]`
+///
+/// `interpolationPhase` holds one phase in (0, 1) per destination frame.
+///
+/// # Safety
+///
+/// `sourceFrame` must be a `VTFrameProcessorFrame`; `nextFrame` must be a
+/// `VTFrameProcessorFrame`; `opticalFlow`, if given, must be a
+/// `VTFrameProcessorOpticalFlow`; `destinationFrames` must be an array of
+/// `VTFrameProcessorFrame`s.
+pub unsafe fn VTFrameRateConversionParameters_init(
+    sourceFrame: &AnyObject,
+    nextFrame: &AnyObject,
+    opticalFlow: Option<&AnyObject>,
+    interpolationPhase: &NSArray<NSNumber>,
+    submissionMode: VTFrameRateConversionParametersSubmissionMode,
+    destinationFrames: &NSArray<AnyObject>,
+) -> Option<Retained<AnyObject>> {
+    let object = alloc(VTFrameRateConversionParametersClass)?;
+    msg_send![
+        object,
+        initWithSourceFrame: sourceFrame,
+        nextFrame: nextFrame,
+        opticalFlow: opticalFlow,
+        interpolationPhase: interpolationPhase,
+        submissionMode: submissionMode,
+        destinationFrames: destinationFrames
+    ]
+}
+
+/// `-[VTOpticalFlowParameters initWithSourceFrame:nextFrame:submissionMode:destinationOpticalFlow:]`
+///
+/// # Safety
+///
+/// `sourceFrame` must be a `VTFrameProcessorFrame`; `nextFrame` must be a
+/// `VTFrameProcessorFrame`; `destinationOpticalFlow` must be a
+/// `VTFrameProcessorOpticalFlow`.
+pub unsafe fn VTOpticalFlowParameters_init(
+    sourceFrame: &AnyObject,
+    nextFrame: &AnyObject,
+    submissionMode: VTOpticalFlowParametersSubmissionMode,
+    destinationOpticalFlow: &AnyObject,
+) -> Option<Retained<AnyObject>> {
+    let object = alloc(VTOpticalFlowParametersClass)?;
+    msg_send![
+        object,
+        initWithSourceFrame: sourceFrame,
+        nextFrame: nextFrame,
+        submissionMode: submissionMode,
+        destinationOpticalFlow: destinationOpticalFlow
+    ]
+}
+
+/// `+[VTFrameProcessor new]`
+///
+/// # Safety
+///
+/// The `VTFrameProcessor` class registered at runtime must declare this
+/// selector with the signature bound here, as on the systems named in the
+/// module docs.
+pub unsafe fn VTFrameProcessor_new() -> Option<Retained<AnyObject>> {
+    let class = VTFrameProcessorGetClass(VTFrameProcessorClass)?;
+    msg_send![class, new]
+}
+
+/// `-[VTFrameProcessor startSessionWithConfiguration:error:]`
+///
+/// # Safety
+///
+/// `processor` must be a `VTFrameProcessor`; `configuration` must be a
+/// `VTFrameRateConversionConfiguration` or `VTOpticalFlowConfiguration`.
+pub unsafe fn VTFrameProcessor_startSession(
+    processor: &AnyObject,
+    configuration: &AnyObject,
+) -> Result<(), OSStatus> {
+    let result: Result<(), Retained<NSError>> =
+        msg_send![processor, startSessionWithConfiguration: configuration, error: _];
+    result.map_err(error_code)
+}
+
+/// `-[VTFrameProcessor processWithParameters:error:]`, synchronous.
+///
+/// # Safety
+///
+/// `processor` must be a `VTFrameProcessor`; `parameters` must be a
+/// `VTFrameRateConversionParameters` or `VTOpticalFlowParameters` matching the
+/// session's configuration.
+pub unsafe fn VTFrameProcessor_process(
+    processor: &AnyObject,
+    parameters: &AnyObject,
+) -> Result<(), OSStatus> {
+    let result: Result<(), Retained<NSError>> =
+        msg_send![processor, processWithParameters: parameters, error: _];
+    result.map_err(error_code)
+}
+
+/// `-[VTFrameProcessor endSession]`
+///
+/// # Safety
+///
+/// `processor` must be a `VTFrameProcessor`.
+pub unsafe fn VTFrameProcessor_endSession(processor: &AnyObject) {
+    let _: () = msg_send![processor, endSession];
+}
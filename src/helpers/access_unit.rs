@@ -0,0 +1,244 @@
+//! Grouping of received H.264 NAL units into access units (complete frames).
+
+use crate::cm_sample_buffer::nal_unit_type;
+
+use super::nal_extractor::{write_length_prefixed, NalError, NalUnit, SampleTiming};
+use super::source::MediaFrame;
+
+/// All NAL units of one coded picture, in decode order.
+#[derive(Debug, Clone, Default)]
+pub struct AccessUnit {
+    /// Every NAL unit of the access unit, including AUD, SEI and parameter sets
+    pub nal_units: Vec<NalUnit>,
+}
+
+impl AccessUnit {
+    /// Returns true if the access unit contains an IDR slice.
+    pub fn is_keyframe(&self) -> bool {
+        self.nal_units.iter().any(NalUnit::is_idr)
+    }
+
+    /// The video slices, one per slice for multi-slice frames.
+    pub fn slices(&self) -> impl Iterator<Item = &NalUnit> {
+        self.nal_units.iter().filter(|nal| nal.is_slice())
+    }
+
+    /// Serialize as one length-prefixed (AVCC) sample, ready to be wrapped in
+    /// a CMSampleBuffer and decoded as a single frame.
+    ///
+    /// Parameter sets and AUDs are left out: the decoder takes them from the
+    /// format description.
+    pub fn to_avcc(&self, nal_length_size: usize) -> Result<Vec<u8>, NalError> {
+        sample_to_avcc(&self.nal_units, nal_length_size)
+    }
+
+    /// Convert to a [`MediaFrame`] for muxing, keeping only the video slices.
+    pub fn into_media_frame(self, timing: SampleTiming) -> MediaFrame {
+        let is_keyframe = self.is_keyframe();
+        MediaFrame {
+            nal_units: self
+                .nal_units
+                .into_iter()
+                .filter(NalUnit::is_slice)
+                .collect(),
+            timing,
+            is_keyframe,
+            motion_score: None,
+        }
+    }
+}
+
+/// Groups a stream of NAL units into [`AccessUnit`]s before decoding.
+///
+/// Decoding NAL units one at a time breaks on frames split into several
+/// slices and on SEI NAL units, which VideoToolbox only accepts as part of a
+/// complete frame. The assembler finds access unit boundaries as described
+/// in H.264 section 7.4.1.2.3: an access unit delimiter, SEI or parameter
+/// set after the slices of a picture starts a new access unit, as does a
+/// slice whose `first_mb_in_slice` is 0. When the transport knows where
+/// frames end (an RTP marker bit, an MP4 sample boundary), call
+/// [`end_access_unit`](Self::end_access_unit) to emit the frame without
+/// waiting for the first NAL unit of the next one.
+///
+/// Streams using arbitrary slice order, where a picture may not begin with
+/// macroblock 0, need the transport's frame boundaries.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{parse_annex_b, AccessUnitAssembler};
+///
+/// # let stream: Vec<u8> = Vec::new();
+/// let mut assembler = AccessUnitAssembler::new();
+/// for nal in parse_annex_b(&stream) {
+///     if let Some(access_unit) = assembler.push(nal) {
+///         let sample = access_unit.to_avcc(4).unwrap();
+///         // wrap `sample` in a CMSampleBuffer and decode it
+///     }
+/// }
+/// let last = assembler.end_access_unit();
+/// ```
+#[derive(Debug, Default)]
+pub struct AccessUnitAssembler {
+    current: Vec<NalUnit>,
+    /// The current access unit already has a slice
+    has_slice: bool,
+}
+
+impl AccessUnitAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next NAL unit. Returns the previous access unit if this NAL
+    /// unit starts a new one.
+    pub fn push(&mut self, nal: NalUnit) -> Option<AccessUnit> {
+        let completed = if self.has_slice && starts_access_unit(&nal) {
+            self.end_access_unit()
+        } else {
+            None
+        };
+        self.has_slice |= nal.is_slice();
+        let ends_stream = matches!(
+            nal.nal_type,
+            nal_unit_type::END_OF_SEQ | nal_unit_type::END_OF_STREAM
+        );
+        self.current.push(nal);
+        if ends_stream {
+            // End of sequence/stream is the last NAL unit of its access unit
+            return self.end_access_unit();
+        }
+        completed
+    }
+
+    /// Add several NAL units, returning every access unit they complete.
+    pub fn push_all<I>(&mut self, nal_units: I) -> Vec<AccessUnit>
+    where
+        I: IntoIterator<Item = NalUnit>,
+    {
+        nal_units
+            .into_iter()
+            .filter_map(|nal| self.push(nal))
+            .collect()
+    }
+
+    /// Emit the access unit collected so far, e.g. at a transport frame
+    /// boundary or the end of the stream. Returns `None` if it contains no
+    /// slices; anything collected is then kept for the next access unit.
+    pub fn end_access_unit(&mut self) -> Option<AccessUnit> {
+        if !self.has_slice {
+            return None;
+        }
+        self.has_slice = false;
+        Some(AccessUnit {
+            nal_units: std::mem::take(&mut self.current),
+        })
+    }
+
+    /// Discard the partial access unit, e.g. after packet loss.
+    pub fn reset(&mut self) {
+        self.current.clear();
+        self.has_slice = false;
+    }
+}
+
+/// Serialize the NAL units of one frame as an AVCC sample, leaving out
+/// parameter sets and AUDs.
+pub(crate) fn sample_to_avcc(
+    nal_units: &[NalUnit],
+    nal_length_size: usize,
+) -> Result<Vec<u8>, NalError> {
+    let mut sample = Vec::new();
+    for nal in nal_units {
+        if !matches!(
+            nal.nal_type,
+            nal_unit_type::SPS | nal_unit_type::PPS | nal_unit_type::AUD
+        ) {
+            write_length_prefixed(&mut sample, &nal.data, nal_length_size)?;
+        }
+    }
+    Ok(sample)
+}
+
+/// Whether `nal` begins a new access unit once the current one has a slice.
+fn starts_access_unit(nal: &NalUnit) -> bool {
+    match nal.nal_type {
+        nal_unit_type::SEI | nal_unit_type::SPS | nal_unit_type::PPS | nal_unit_type::AUD => true,
+        // Prefix NAL, subset SPS and reserved types
+        14..=18 => true,
+        nal_unit_type::NON_IDR_SLICE | nal_unit_type::IDR_SLICE => first_mb_in_slice_is_zero(nal),
+        _ => false,
+    }
+}
+
+/// `first_mb_in_slice` is the first ue(v) field of the slice header, and a
+/// value of 0 is coded as a single 1 bit.
+fn first_mb_in_slice_is_zero(nal: &NalUnit) -> bool {
+    nal.data.get(1).is_some_and(|b| b & 0x80 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nal(data: &[u8]) -> NalUnit {
+        NalUnit {
+            data: data.to_vec(),
+            nal_type: data[0] & 0x1F,
+        }
+    }
+
+    fn types(access_unit: &AccessUnit) -> Vec<u8> {
+        access_unit.nal_units.iter().map(|n| n.nal_type).collect()
+    }
+
+    #[test]
+    fn test_groups_slices_sei_and_parameter_sets() {
+        let mut assembler = AccessUnitAssembler::new();
+        let stream = [
+            nal(&[0x67, 0x42]),       // SPS
+            nal(&[0x68, 0xCE]),       // PPS
+            nal(&[0x06, 0x05]),       // SEI
+            nal(&[0x65, 0x88, 0x01]), // IDR, first_mb_in_slice = 0
+            nal(&[0x65, 0x40, 0x02]), // IDR, first_mb_in_slice = 1
+            nal(&[0x41, 0x9A, 0x03]), // next frame
+            nal(&[0x41, 0x20, 0x04]),
+            nal(&[0x09, 0xF0]), // AUD
+            nal(&[0x41, 0x9A, 0x05]),
+        ];
+        let access_units = assembler.push_all(stream);
+        assert_eq!(access_units.len(), 2);
+        assert_eq!(types(&access_units[0]), [7, 8, 6, 5, 5]);
+        assert!(access_units[0].is_keyframe());
+        assert_eq!(access_units[0].slices().count(), 2);
+        assert_eq!(types(&access_units[1]), [1, 1]);
+
+        let last = assembler.end_access_unit().unwrap();
+        assert_eq!(types(&last), [9, 1]);
+        assert!(assembler.end_access_unit().is_none());
+
+        // Only the SEI and slices go into the sample
+        assert_eq!(
+            access_units[0].to_avcc(4).unwrap(),
+            [0, 0, 0, 2, 0x06, 0x05, 0, 0, 0, 3, 0x65, 0x88, 0x01, 0, 0, 0, 3, 0x65, 0x40, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_transport_boundaries_and_end_of_stream() {
+        let mut assembler = AccessUnitAssembler::new();
+        assert!(assembler.push(nal(&[0x41, 0x9A, 0x01])).is_none());
+        let frame = assembler.end_access_unit().unwrap();
+        assert_eq!(types(&frame), [1]);
+
+        // End of stream closes the access unit it belongs to
+        assert!(assembler.push(nal(&[0x41, 0x9A, 0x02])).is_none());
+        let frame = assembler.push(nal(&[0x0B])).unwrap();
+        assert_eq!(types(&frame), [1, 11]);
+
+        // A partial access unit can be thrown away
+        assembler.push(nal(&[0x41, 0x20, 0x03]));
+        assembler.reset();
+        assert!(assembler.end_access_unit().is_none());
+    }
+}
@@ -0,0 +1,266 @@
+//! Conversion between Annex B byte streams and length-prefixed (AVCC) samples.
+
+use super::nal_extractor::{
+    parse_annex_b, parse_avcc, push_annex_b_nal, validate_nal_length_size, write_length_prefixed,
+    NalError, NalUnit,
+};
+
+/// Splits an Annex B byte stream, e.g. a raw `.h264` file or RTSP
+/// interleaved data, into NAL units as it arrives.
+///
+/// Unlike [`parse_annex_b`], the stream may be fed in arbitrary chunks; a
+/// start code split across two chunks is still found. Both 3- and 4-byte
+/// start codes are accepted, and zero bytes before a start code
+/// (`trailing_zero_8bits`) are dropped. Bytes before the first start code
+/// are discarded.
+///
+/// Emulation prevention bytes are left in place. They guarantee that a
+/// start code never occurs inside a NAL unit, and VideoToolbox expects them
+/// in AVCC samples as well.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::io::Read;
+/// use video_toolbox_sys::helpers::{AnnexBReader, AvccWriter};
+///
+/// let mut file = std::fs::File::open("input.h264").unwrap();
+/// let mut reader = AnnexBReader::new();
+/// let mut writer = AvccWriter::new(4).unwrap();
+/// let mut chunk = [0u8; 4096];
+/// loop {
+///     let n = file.read(&mut chunk).unwrap();
+///     if n == 0 {
+///         break;
+///     }
+///     for nal in reader.push(&chunk[..n]) {
+///         writer.push(&nal.data).unwrap();
+///     }
+/// }
+/// if let Some(nal) = reader.finish() {
+///     writer.push(&nal.data).unwrap();
+/// }
+/// let avcc = writer.finish();
+/// ```
+#[derive(Debug, Default)]
+pub struct AnnexBReader {
+    /// Data after the last start code, or unscanned data before the first
+    buffer: Vec<u8>,
+    /// Offset in `buffer` where the next start code search resumes
+    scan: usize,
+    /// A start code has been seen
+    started: bool,
+}
+
+impl AnnexBReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next chunk of the stream. Returns the NAL units completed by
+    /// it, i.e. those now followed by a start code.
+    pub fn push(&mut self, data: &[u8]) -> Vec<NalUnit> {
+        self.buffer.extend_from_slice(data);
+
+        let mut nal_units = Vec::new();
+        let mut start = 0;
+        let mut i = self.scan;
+        while i + 3 <= self.buffer.len() {
+            if self.buffer[i..i + 3] == [0, 0, 1] {
+                if self.started {
+                    push_annex_b_nal(&mut nal_units, &self.buffer[start..i]);
+                }
+                self.started = true;
+                i += 3;
+                start = i;
+            } else {
+                i += 1;
+            }
+        }
+
+        // The last two bytes may begin a start code completed by the next chunk
+        let keep_from = if self.started { start } else { i };
+        self.buffer.drain(..keep_from);
+        self.scan = i - keep_from;
+        nal_units
+    }
+
+    /// End the stream, returning the last NAL unit, which has no start code
+    /// after it.
+    pub fn finish(&mut self) -> Option<NalUnit> {
+        let mut nal_units = Vec::new();
+        if self.started {
+            push_annex_b_nal(&mut nal_units, &self.buffer);
+        }
+        self.reset();
+        nal_units.pop()
+    }
+
+    /// Bytes held back waiting for the next start code.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Discard buffered data, e.g. when seeking in a file.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.scan = 0;
+        self.started = false;
+    }
+}
+
+/// Writes NAL units as one length-prefixed (AVCC) sample or stream.
+///
+/// NAL units are checked for emulation prevention: one containing
+/// `00 00 00`, `00 00 01` or `00 00 02` was not escaped, so it would be
+/// misparsed by decoders reading Annex B and is rejected with
+/// [`NalError::MissingEmulationPrevention`].
+#[derive(Debug)]
+pub struct AvccWriter {
+    nal_length_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl AvccWriter {
+    /// Create a writer using `nal_length_size`-byte (1, 2 or 4) length prefixes.
+    pub fn new(nal_length_size: usize) -> Result<Self, NalError> {
+        Ok(Self {
+            nal_length_size: validate_nal_length_size(nal_length_size)?,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Append a NAL unit (without start code or length prefix).
+    pub fn push(&mut self, nal: &[u8]) -> Result<(), NalError> {
+        if nal.is_empty() {
+            return Err(NalError::InvalidNalLength);
+        }
+        check_emulation_prevention(nal)?;
+        write_length_prefixed(&mut self.buffer, nal, self.nal_length_size)
+    }
+
+    /// Append several NAL units.
+    pub fn push_all<'a, I>(&mut self, nal_units: I) -> Result<(), NalError>
+    where
+        I: IntoIterator<Item = &'a NalUnit>,
+    {
+        nal_units
+            .into_iter()
+            .try_for_each(|nal| self.push(&nal.data))
+    }
+
+    pub fn nal_length_size(&self) -> usize {
+        self.nal_length_size
+    }
+
+    /// Bytes written so far.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Take the data written so far, leaving the writer empty.
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+/// Convert a complete Annex B byte stream to length-prefixed (AVCC) data.
+pub fn annex_b_to_avcc(data: &[u8], nal_length_size: usize) -> Result<Vec<u8>, NalError> {
+    let mut writer = AvccWriter::new(nal_length_size)?;
+    writer.push_all(&parse_annex_b(data))?;
+    Ok(writer.finish())
+}
+
+/// Convert length-prefixed (AVCC) data to an Annex B byte stream with
+/// 4-byte start codes.
+pub fn avcc_to_annex_b(data: &[u8], nal_length_size: usize) -> Result<Vec<u8>, NalError> {
+    let mut stream = Vec::with_capacity(data.len());
+    for nal in parse_avcc(data, nal_length_size)? {
+        check_emulation_prevention(&nal.data)?;
+        stream.extend_from_slice(&nal.to_annex_b());
+    }
+    Ok(stream)
+}
+
+/// Reject NAL units containing a three-byte sequence that H.264 section
+/// 7.4.1 forbids inside a NAL unit.
+fn check_emulation_prevention(nal: &[u8]) -> Result<(), NalError> {
+    match nal
+        .windows(3)
+        .position(|w| w[0] == 0 && w[1] == 0 && w[2] <= 2)
+    {
+        Some(offset) => Err(NalError::MissingEmulationPrevention { offset }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(nal_units: &[NalUnit]) -> Vec<Vec<u8>> {
+        nal_units.iter().map(|nal| nal.data.clone()).collect()
+    }
+
+    #[test]
+    fn test_reader_splits_chunked_stream() {
+        let stream = [
+            0xFF, 0xFF, // garbage before the first start code
+            0, 0, 0, 1, 0x67, 0x42, // SPS, 4-byte start code
+            0, 0, 1, 0x68, 0xCE, 0x00, // PPS with trailing zero, 3-byte start code
+            0, 0, 0, 1, 0x65, 0x88, 0x00, 0x00, 0x03, 0x01, // IDR with escaped 00 00 01
+            0, 0, 1, 0x41, 0x9A,
+        ];
+
+        // Whole stream at once and one byte at a time give the same NAL units
+        let mut reader = AnnexBReader::new();
+        let mut whole = reader.push(&stream);
+        whole.extend(reader.finish());
+
+        let mut reader = AnnexBReader::new();
+        let mut bytewise = Vec::new();
+        for byte in stream {
+            bytewise.extend(reader.push(&[byte]));
+        }
+        assert_eq!(reader.buffered_len(), 2);
+        bytewise.extend(reader.finish());
+
+        let expected = vec![
+            vec![0x67, 0x42],
+            vec![0x68, 0xCE],
+            vec![0x65, 0x88, 0x00, 0x00, 0x03, 0x01],
+            vec![0x41, 0x9A],
+        ];
+        assert_eq!(data(&whole), expected);
+        assert_eq!(data(&bytewise), expected);
+        assert_eq!(data(&parse_annex_b(&stream)), expected);
+        assert!(reader.finish().is_none());
+    }
+
+    #[test]
+    fn test_avcc_round_trip_and_emulation_check() {
+        let annex_b = [
+            0, 0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x65, 0x00, 0x00, 0x03, 0x00, 0x88,
+        ];
+        let avcc = annex_b_to_avcc(&annex_b, 2).unwrap();
+        assert_eq!(
+            avcc,
+            [0, 2, 0x67, 0x42, 0, 6, 0x65, 0x00, 0x00, 0x03, 0x00, 0x88]
+        );
+
+        let back = avcc_to_annex_b(&avcc, 2).unwrap();
+        assert_eq!(data(&parse_annex_b(&back)), data(&parse_annex_b(&annex_b)));
+
+        let mut writer = AvccWriter::new(4).unwrap();
+        assert_eq!(
+            writer.push(&[0x65, 0x88, 0x00, 0x00, 0x01]),
+            Err(NalError::MissingEmulationPrevention { offset: 2 })
+        );
+        assert!(writer.is_empty());
+        assert!(AvccWriter::new(3).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Futures-based encoding and decoding for async code such as tokio tasks
+//! (feature `async`).
+//!
+//! The VideoToolbox callbacks are bridged into channels: each encoded frame
+//! resolves the future returned when it was submitted, and decoded frames
+//! arrive on a [`Stream`]. Neither type depends on a particular runtime.
+
+use core_foundation_sys::base::{CFRelease, OSStatus};
+use core_media_sys::CMTime;
+use futures_channel::{mpsc, oneshot};
+use futures_core::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr;
+use std::task::{Context, Poll};
+
+use super::compression_builder::CompressionSessionBuilder;
+use super::compression_session::EncodeOutput;
+use super::decompression_session::{
+    DecodedFrame, DecompressionSession, DecompressionSessionConfig,
+};
+use super::encoded_frame::EncodedFrame;
+use super::nal_extractor::H264ParameterSets;
+use super::output_handler::encode_frame_with_handler;
+use crate::compression::{
+    VTCompressionSessionCompleteFrames, VTCompressionSessionInvalidate, VTCompressionSessionRef,
+};
+use crate::cv_types::CVPixelBufferRef;
+use crate::errors::{kVTInvalidSessionErr, status_to_result};
+
+/// Result of [`AsyncEncoder::encode`]: the encoded frame, `None` if the
+/// encoder dropped it, or the encode error.
+pub type EncodeResult = Result<Option<EncodedFrame>, OSStatus>;
+
+/// A compression session whose frames are awaited individually.
+///
+/// Encoders hold frames back for B-frame reordering and lookahead, so a
+/// frame's future may only resolve after later frames are submitted.
+/// Submit frames as they come and await the futures elsewhere (e.g. in
+/// order from a queue), or call [`flush`](Self::flush) at the end of the
+/// stream; awaiting each frame before submitting the next only works with
+/// frame reordering disabled and a frame delay of 0.
+///
+/// # Example
+///
+/// ```ignore
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{make_time, AsyncEncoder, CompressionSessionBuilder};
+///
+/// let builder = CompressionSessionBuilder::new(1280, 720, codecs::video::H264)
+///     .real_time(true)
+///     .allow_frame_reordering(false);
+/// let encoder = AsyncEncoder::new(builder)?;
+/// let pending = unsafe { encoder.encode(pixel_buffer, make_time(0, 90000), make_time(3000, 90000)) };
+/// if let Some(frame) = pending.await? {
+///     println!("keyframe: {}, pts {}", frame.is_keyframe(), frame.timing().pts);
+/// }
+/// ```
+pub struct AsyncEncoder {
+    session: VTCompressionSessionRef,
+}
+
+// SAFETY: VTCompressionSession may be used from any thread; frames are
+// delivered to per-frame channels rather than to state owned by the encoder.
+unsafe impl Send for AsyncEncoder {}
+unsafe impl Sync for AsyncEncoder {}
+
+impl AsyncEncoder {
+    /// Create the session. The builder's callback target is not used:
+    /// outputs go straight to each frame's future.
+    pub fn new(builder: CompressionSessionBuilder) -> Result<Self, OSStatus> {
+        let session = unsafe { builder.build_with_context(None, ptr::null_mut())? };
+        Ok(Self { session })
+    }
+
+    /// Submit a frame, returning a future for its encoded output.
+    ///
+    /// # Safety
+    ///
+    /// `frame` must be a valid pixel buffer matching the session's dimensions.
+    pub unsafe fn encode(
+        &self,
+        frame: CVPixelBufferRef,
+        pts: CMTime,
+        duration: CMTime,
+    ) -> EncodeFuture {
+        let (sender, receiver) = oneshot::channel();
+        let submitted = encode_frame_with_handler(
+            self.session,
+            frame,
+            pts,
+            duration,
+            ptr::null(),
+            move |output| {
+                let result = match output {
+                    EncodeOutput::Frame { sample_buffer, .. } => {
+                        Ok(Some(EncodedFrame::retain(sample_buffer)))
+                    }
+                    EncodeOutput::Dropped { .. } => Ok(None),
+                    EncodeOutput::Error(status) => Err(status),
+                };
+                // The future may have been dropped
+                let _ = sender.send(result);
+            },
+        );
+        EncodeFuture {
+            state: match submitted {
+                Ok(_) => Ok(receiver),
+                Err(status) => Err(Some(status)),
+            },
+        }
+    }
+
+    /// Emit every pending frame, resolving their futures.
+    pub fn flush(&self) -> Result<(), OSStatus> {
+        let status = unsafe { VTCompressionSessionCompleteFrames(self.session, invalid_time()) };
+        status_to_result(status)
+    }
+
+    /// Get the underlying session reference.
+    pub fn as_raw(&self) -> VTCompressionSessionRef {
+        self.session
+    }
+}
+
+impl Drop for AsyncEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            VTCompressionSessionCompleteFrames(self.session, invalid_time());
+            VTCompressionSessionInvalidate(self.session);
+            CFRelease(self.session);
+        }
+    }
+}
+
+/// Future returned by [`AsyncEncoder::encode`].
+///
+/// Resolves to `Err(kVTInvalidSessionErr)` if the encoder is dropped before
+/// the frame is emitted.
+pub struct EncodeFuture {
+    /// The frame's channel, or the submission error not yet returned
+    state: Result<oneshot::Receiver<EncodeResult>, Option<OSStatus>>,
+}
+
+impl Future for EncodeFuture {
+    type Output = EncodeResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<EncodeResult> {
+        match &mut self.state {
+            Ok(receiver) => Pin::new(receiver)
+                .poll(cx)
+                .map(|result| result.unwrap_or(Err(kVTInvalidSessionErr))),
+            Err(status) => Poll::Ready(Err(status.take().unwrap_or(kVTInvalidSessionErr))),
+        }
+    }
+}
+
+/// A decompression session whose decoded frames are a [`Stream`].
+///
+/// Decode through [`session`](Self::session) (e.g.
+/// [`decode_annex_b`](DecompressionSession::decode_annex_b)) and poll the
+/// stream for frames in decode order. Failed decodes are yielded as errors;
+/// dropped and suppressed frames are skipped.
+///
+/// # Example
+///
+/// ```ignore
+/// use video_toolbox_sys::helpers::{AsyncDecoder, DecodeOptions, DecompressionSessionConfig};
+///
+/// let mut decoder = AsyncDecoder::h264(&parameter_sets, &DecompressionSessionConfig::default())?;
+/// decoder.session().decode_annex_b(&access_unit, timing, DecodeOptions::new())?;
+/// while let Some(frame) = decoder.next().await {
+///     let frame = frame?;
+///     println!("decoded {:?}", frame.pts);
+/// }
+/// ```
+pub struct AsyncDecoder {
+    session: DecompressionSession,
+    frames: mpsc::UnboundedReceiver<Result<DecodedFrame, OSStatus>>,
+}
+
+impl AsyncDecoder {
+    /// Create an H.264 decoder from the stream's SPS and PPS.
+    pub fn h264(
+        parameter_sets: &H264ParameterSets,
+        config: &DecompressionSessionConfig,
+    ) -> Result<Self, OSStatus> {
+        let (sender, frames) = mpsc::unbounded();
+        let session = DecompressionSession::h264(parameter_sets, config, move |frame| {
+            // The stream may have been dropped; the decoder carries on
+            let _ = sender.unbounded_send(frame);
+        })?;
+        Ok(Self { session, frames })
+    }
+
+    /// The session to submit compressed frames to.
+    pub fn session(&self) -> &DecompressionSession {
+        &self.session
+    }
+}
+
+impl Stream for AsyncDecoder {
+    type Item = Result<DecodedFrame, OSStatus>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.frames).poll_next(cx)
+    }
+}
+
+/// `kCMTimeInvalid`, which completes all pending frames.
+fn invalid_time() -> CMTime {
+    CMTime {
+        value: 0,
+        timescale: 0,
+        flags: 0,
+        epoch: 0,
+    }
+}
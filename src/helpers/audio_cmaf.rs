@@ -0,0 +1,624 @@
+//! Audio-only CMAF muxer (AAC or Opus, no video track).
+//!
+//! Produces the same segment layout as [`CmafMuxer`](super::CmafMuxer)
+//! (init segment, then `styp` + `moof` + `mdat` media segments), so the
+//! output works with every [`SegmentSink`](super::SegmentSink), including
+//! [`HlsSink`](super::HlsSink) for audio-only HLS.
+
+use std::time::Duration;
+
+use super::channel_layout::ChannelLayout;
+use super::metadata::Mp4Metadata;
+
+/// Audio codec configuration for the sample description.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioCodec {
+    /// MPEG-4 AAC with its AudioSpecificConfig (e.g. the magic cookie from
+    /// an AudioConverter, or [`aac_audio_specific_config`])
+    Aac { audio_specific_config: Vec<u8> },
+    /// Opus; `pre_skip` is the encoder delay in 48 kHz samples
+    Opus { pre_skip: u16 },
+}
+
+/// The audio track to mux.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioTrackConfig {
+    pub codec: AudioCodec,
+    /// Sample rate in Hz; also the track timescale (Opus always uses 48000)
+    pub sample_rate: u32,
+    /// Channel count; 1, 2 and 6 are signaled as mono, stereo and 5.1
+    /// ([`ChannelLayout`]) in the sample entry
+    pub channels: u16,
+}
+
+impl AudioTrackConfig {
+    /// A track with the channel count of `layout`.
+    pub fn with_layout(codec: AudioCodec, sample_rate: u32, layout: ChannelLayout) -> Self {
+        Self {
+            codec,
+            sample_rate,
+            channels: layout.channel_count() as u16,
+        }
+    }
+
+    /// The speaker layout of the track, if the channel count is a known one.
+    pub fn layout(&self) -> Option<ChannelLayout> {
+        ChannelLayout::from_channel_count(self.channels as u32)
+    }
+
+    /// Track timescale: the sample rate, or 48 kHz for Opus.
+    pub fn timescale(&self) -> u32 {
+        match self.codec {
+            AudioCodec::Opus { .. } => 48_000,
+            AudioCodec::Aac { .. } => self.sample_rate.max(1),
+        }
+    }
+
+    /// RFC 6381 codec string, e.g. `mp4a.40.2` or `opus`.
+    pub fn codec_string(&self) -> String {
+        match &self.codec {
+            AudioCodec::Aac {
+                audio_specific_config,
+            } => {
+                let object_type = audio_specific_config.first().map_or(2, |b| b >> 3);
+                format!("mp4a.40.{}", object_type)
+            }
+            AudioCodec::Opus { .. } => "opus".to_string(),
+        }
+    }
+}
+
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Build a 2-byte AudioSpecificConfig (ISO 14496-3) for `object_type`
+/// (2 = AAC-LC) at a standard sample rate.
+///
+/// Returns `None` for sample rates without a frequency index.
+pub fn aac_audio_specific_config(
+    object_type: u8,
+    sample_rate: u32,
+    channels: u16,
+) -> Option<Vec<u8>> {
+    let index = AAC_SAMPLE_RATES
+        .iter()
+        .position(|&rate| rate == sample_rate)? as u16;
+    let bits = ((object_type as u16 & 0x1F) << 11) | (index << 7) | ((channels & 0x0F) << 3);
+    Some(bits.to_be_bytes().to_vec())
+}
+
+/// A pending audio frame.
+#[derive(Debug, Clone)]
+struct PendingFrame {
+    data: Vec<u8>,
+    duration: u32,
+}
+
+pub(super) fn write_box(buf: &mut Vec<u8>, kind: &[u8; 4], content: &[u8]) {
+    buf.extend_from_slice(&((8 + content.len()) as u32).to_be_bytes());
+    buf.extend_from_slice(kind);
+    buf.extend_from_slice(content);
+}
+
+/// Write an MPEG-4 descriptor with a 4-byte length field.
+fn write_descriptor(buf: &mut Vec<u8>, tag: u8, payload: &[u8]) {
+    let len = payload.len() as u32;
+    buf.push(tag);
+    buf.extend_from_slice(&[
+        0x80 | ((len >> 21) & 0x7F) as u8,
+        0x80 | ((len >> 14) & 0x7F) as u8,
+        0x80 | ((len >> 7) & 0x7F) as u8,
+        (len & 0x7F) as u8,
+    ]);
+    buf.extend_from_slice(payload);
+}
+
+/// Fragmented MP4 muxer for a single audio track.
+///
+/// Every audio frame is a sync sample, so fragments are cut purely on
+/// duration: a new fragment starts with the first frame that would exceed
+/// the target fragment duration. The `ftyp` and `styp` carry audio brands
+/// (`caac` for AAC, `Opus` for Opus) instead of the video ones.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{
+///     aac_audio_specific_config, AudioCmafMuxer, AudioCodec, AudioTrackConfig, HlsConfig,
+///     HlsSink, Segment, SegmentSink,
+/// };
+///
+/// let track = AudioTrackConfig {
+///     codec: AudioCodec::Aac {
+///         audio_specific_config: aac_audio_specific_config(2, 48000, 2).unwrap(),
+///     },
+///     sample_rate: 48000,
+///     channels: 2,
+/// };
+/// let mut muxer = AudioCmafMuxer::new(track, 2000);
+/// let mut hls = HlsSink::new("podcast", HlsConfig::default())?;
+/// hls.write_segment(&Segment::init(muxer.init_segment()))?;
+/// # let (packet, pts) = (Vec::new(), 0);
+/// if let Some(data) = muxer.add_frame(&packet, pts, 1024) {
+///     let segment = Segment::media(muxer.sequence_number() - 1, data)
+///         .with_duration(muxer.last_fragment_duration());
+///     hls.write_segment(&segment)?;
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct AudioCmafMuxer {
+    track: AudioTrackConfig,
+    fragment_duration_ms: u32,
+    track_id: u32,
+    pending_frames: Vec<PendingFrame>,
+    sequence_number: u32,
+    fragment_base_dts: i64,
+    last_fragment_duration: i64,
+    metadata: Option<Mp4Metadata>,
+}
+
+impl AudioCmafMuxer {
+    /// Mux `track` into fragments of about `fragment_duration_ms`.
+    pub fn new(track: AudioTrackConfig, fragment_duration_ms: u32) -> Self {
+        Self {
+            track,
+            fragment_duration_ms,
+            track_id: 1,
+            pending_frames: Vec::new(),
+            sequence_number: 1,
+            fragment_base_dts: 0,
+            last_fragment_duration: 0,
+            metadata: None,
+        }
+    }
+
+    pub fn track(&self) -> &AudioTrackConfig {
+        &self.track
+    }
+
+    /// RFC 6381 codec string for manifests.
+    pub fn codec_string(&self) -> String {
+        self.track.codec_string()
+    }
+
+    /// Metadata for the init segment (see [`Mp4Metadata`]).
+    pub fn set_metadata(&mut self, metadata: Mp4Metadata) {
+        self.metadata = (!metadata.is_empty()).then_some(metadata);
+    }
+
+    /// Create the initialization segment (ftyp + moov).
+    pub fn init_segment(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.write_ftyp(&mut buf);
+        self.write_moov(&mut buf);
+        buf
+    }
+
+    /// Add an encoded audio frame (e.g. one AAC access unit).
+    ///
+    /// * `pts` - Presentation timestamp in track timescale units (samples)
+    /// * `duration` - Frame duration in samples (1024 for AAC-LC)
+    ///
+    /// Returns a media segment when a fragment is complete.
+    pub fn add_frame(&mut self, data: &[u8], pts: i64, duration: u32) -> Option<Vec<u8>> {
+        let timescale = self.track.timescale() as i64;
+        let pending: i64 = self.pending_frames.iter().map(|f| f.duration as i64).sum();
+        let segment = if !self.pending_frames.is_empty()
+            && (pending + duration as i64) * 1000 > self.fragment_duration_ms as i64 * timescale
+        {
+            Some(self.flush_fragment())
+        } else {
+            None
+        };
+
+        if self.pending_frames.is_empty() {
+            self.fragment_base_dts = pts;
+        }
+        self.pending_frames.push(PendingFrame {
+            data: data.to_vec(),
+            duration,
+        });
+        segment
+    }
+
+    /// Flush any remaining frames as a final segment.
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        if self.pending_frames.is_empty() {
+            return None;
+        }
+        Some(self.flush_fragment())
+    }
+
+    /// Get the current sequence number.
+    pub fn sequence_number(&self) -> u32 {
+        self.sequence_number
+    }
+
+    /// Duration of the most recently emitted fragment.
+    pub fn last_fragment_duration(&self) -> Duration {
+        let timescale = self.track.timescale() as f64;
+        Duration::from_secs_f64(self.last_fragment_duration.max(0) as f64 / timescale)
+    }
+
+    /// Get the number of pending frames.
+    pub fn pending_frame_count(&self) -> usize {
+        self.pending_frames.len()
+    }
+
+    fn flush_fragment(&mut self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.write_styp(&mut buf);
+        self.write_moof(&mut buf);
+        self.write_mdat(&mut buf);
+
+        self.last_fragment_duration = self.pending_frames.iter().map(|f| f.duration as i64).sum();
+        self.sequence_number += 1;
+        self.pending_frames.clear();
+        buf
+    }
+
+    fn codec_brand(&self) -> &'static [u8; 4] {
+        match self.track.codec {
+            AudioCodec::Aac { .. } => b"caac", // CMAF AAC media profile
+            AudioCodec::Opus { .. } => b"Opus",
+        }
+    }
+
+    // ========================================
+    // Box writing helpers
+    // ========================================
+
+    fn write_ftyp(&self, buf: &mut Vec<u8>) {
+        let mut content = Vec::new();
+        content.extend_from_slice(b"isom"); // major brand
+        content.extend_from_slice(&0u32.to_be_bytes()); // minor version
+        for brand in [b"isom", b"iso6", b"cmfc", self.codec_brand(), b"mp41"] {
+            content.extend_from_slice(brand);
+        }
+        write_box(buf, b"ftyp", &content);
+    }
+
+    fn write_styp(&self, buf: &mut Vec<u8>) {
+        let mut content = Vec::new();
+        content.extend_from_slice(b"msdh"); // major brand
+        content.extend_from_slice(&0u32.to_be_bytes()); // minor version
+        for brand in [b"msdh", b"msix", b"cmfc", self.codec_brand()] {
+            content.extend_from_slice(brand);
+        }
+        write_box(buf, b"styp", &content);
+    }
+
+    fn write_moov(&self, buf: &mut Vec<u8>) {
+        let mut content = Vec::new();
+        self.write_mvhd(&mut content);
+        self.write_trak(&mut content);
+
+        let mut trex = Vec::new();
+        trex.extend_from_slice(&[0, 0, 0, 0]); // version + flags
+        trex.extend_from_slice(&self.track_id.to_be_bytes());
+        trex.extend_from_slice(&1u32.to_be_bytes()); // default_sample_description_index
+        trex.extend_from_slice(&[0; 12]); // default duration, size, flags
+        let mut mvex = Vec::new();
+        write_box(&mut mvex, b"trex", &trex);
+        write_box(&mut content, b"mvex", &mvex);
+        if let Some(metadata) = &self.metadata {
+            content.extend_from_slice(&metadata.udta_box());
+        }
+
+        write_box(buf, b"moov", &content);
+    }
+
+    fn write_mvhd(&self, buf: &mut Vec<u8>) {
+        let mut content = Vec::new();
+        content.extend_from_slice(&[0, 0, 0, 0]); // version + flags
+        let created = self.metadata.as_ref().and_then(|m| m.mp4_creation_time());
+        content.extend_from_slice(&created.unwrap_or(0).to_be_bytes()); // creation time
+        content.extend_from_slice(&created.unwrap_or(0).to_be_bytes()); // modification time
+        content.extend_from_slice(&self.track.timescale().to_be_bytes());
+        content.extend_from_slice(&0u32.to_be_bytes()); // duration (unknown for live)
+        content.extend_from_slice(&0x00010000u32.to_be_bytes()); // rate (1.0)
+        content.extend_from_slice(&0x0100u16.to_be_bytes()); // volume (1.0)
+        content.extend_from_slice(&[0; 10]); // reserved
+        write_matrix(&mut content);
+        content.extend_from_slice(&[0; 24]); // pre_defined
+        content.extend_from_slice(&(self.track_id + 1).to_be_bytes()); // next_track_id
+        write_box(buf, b"mvhd", &content);
+    }
+
+    fn write_trak(&self, buf: &mut Vec<u8>) {
+        let mut content = Vec::new();
+
+        let mut tkhd = Vec::new();
+        tkhd.extend_from_slice(&[0, 0, 0, 3]); // version + flags (enabled, in movie)
+        tkhd.extend_from_slice(&0u32.to_be_bytes()); // creation time
+        tkhd.extend_from_slice(&0u32.to_be_bytes()); // modification time
+        tkhd.extend_from_slice(&self.track_id.to_be_bytes());
+        tkhd.extend_from_slice(&0u32.to_be_bytes()); // reserved
+        tkhd.extend_from_slice(&0u32.to_be_bytes()); // duration (unknown)
+        tkhd.extend_from_slice(&[0; 8]); // reserved
+        tkhd.extend_from_slice(&0i16.to_be_bytes()); // layer
+        tkhd.extend_from_slice(&1i16.to_be_bytes()); // alternate_group (audio)
+        tkhd.extend_from_slice(&0x0100i16.to_be_bytes()); // volume (1.0)
+        tkhd.extend_from_slice(&0u16.to_be_bytes()); // reserved
+        write_matrix(&mut tkhd);
+        tkhd.extend_from_slice(&[0; 8]); // width and height (none for audio)
+        write_box(&mut content, b"tkhd", &tkhd);
+
+        let mut mdia = Vec::new();
+        let mut mdhd = Vec::new();
+        mdhd.extend_from_slice(&[0, 0, 0, 0]); // version + flags
+        mdhd.extend_from_slice(&0u32.to_be_bytes()); // creation time
+        mdhd.extend_from_slice(&0u32.to_be_bytes()); // modification time
+        mdhd.extend_from_slice(&self.track.timescale().to_be_bytes());
+        mdhd.extend_from_slice(&0u32.to_be_bytes()); // duration
+        mdhd.extend_from_slice(&0x55c4u16.to_be_bytes()); // language (und)
+        mdhd.extend_from_slice(&0u16.to_be_bytes()); // pre_defined
+        write_box(&mut mdia, b"mdhd", &mdhd);
+
+        let mut hdlr = Vec::new();
+        hdlr.extend_from_slice(&[0, 0, 0, 0]); // version + flags
+        hdlr.extend_from_slice(&0u32.to_be_bytes()); // pre_defined
+        hdlr.extend_from_slice(b"soun"); // handler_type
+        hdlr.extend_from_slice(&[0; 12]); // reserved
+        hdlr.extend_from_slice(b"SoundHandler\0"); // name
+        write_box(&mut mdia, b"hdlr", &hdlr);
+
+        self.write_minf(&mut mdia);
+        write_box(&mut content, b"mdia", &mdia);
+        write_box(buf, b"trak", &content);
+    }
+
+    fn write_minf(&self, buf: &mut Vec<u8>) {
+        let mut content = Vec::new();
+        // smhd: version + flags, balance, reserved
+        write_box(&mut content, b"smhd", &[0; 8]);
+
+        let mut dref = Vec::new();
+        dref.extend_from_slice(&[0, 0, 0, 0]); // version + flags
+        dref.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        write_box(&mut dref, b"url ", &[0, 0, 0, 1]); // self-contained
+        let mut dinf = Vec::new();
+        write_box(&mut dinf, b"dref", &dref);
+        write_box(&mut content, b"dinf", &dinf);
+
+        let mut stbl = Vec::new();
+        let mut stsd = Vec::new();
+        stsd.extend_from_slice(&[0, 0, 0, 0]); // version + flags
+        stsd.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        self.write_sample_entry(&mut stsd);
+        write_box(&mut stbl, b"stsd", &stsd);
+        // Empty sample tables: all samples are in fragments
+        write_box(&mut stbl, b"stts", &[0; 8]);
+        write_box(&mut stbl, b"stsc", &[0; 8]);
+        write_box(&mut stbl, b"stsz", &[0; 12]);
+        write_box(&mut stbl, b"stco", &[0; 8]);
+        write_box(&mut content, b"stbl", &stbl);
+
+        write_box(buf, b"minf", &content);
+    }
+
+    fn write_sample_entry(&self, buf: &mut Vec<u8>) {
+        let mut content = Vec::new();
+        content.extend_from_slice(&[0; 6]); // reserved
+        content.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+        content.extend_from_slice(&[0; 8]); // reserved
+        content.extend_from_slice(&self.track.channels.to_be_bytes());
+        content.extend_from_slice(&16u16.to_be_bytes()); // sample size
+        content.extend_from_slice(&[0; 4]); // pre_defined + reserved
+
+        // 16.16 fixed point; rates above 65535 Hz do not fit and are written as 0
+        let rate = if self.track.timescale() <= 0xFFFF {
+            self.track.timescale() << 16
+        } else {
+            0
+        };
+        content.extend_from_slice(&rate.to_be_bytes());
+
+        match &self.track.codec {
+            AudioCodec::Aac {
+                audio_specific_config,
+            } => {
+                let mut decoder_config = vec![
+                    0x40, // objectTypeIndication: MPEG-4 Audio
+                    0x15, // streamType: audio, upstream 0, reserved 1
+                    0, 0, 0, // bufferSizeDB
+                ];
+                decoder_config.extend_from_slice(&0u32.to_be_bytes()); // maxBitrate
+                decoder_config.extend_from_slice(&0u32.to_be_bytes()); // avgBitrate
+                write_descriptor(&mut decoder_config, 0x05, audio_specific_config);
+
+                let mut es = Vec::new();
+                es.extend_from_slice(&(self.track_id as u16).to_be_bytes()); // ES_ID
+                es.push(0); // flags
+                write_descriptor(&mut es, 0x04, &decoder_config);
+                write_descriptor(&mut es, 0x06, &[0x02]); // SLConfig: MP4
+
+                let mut esds = vec![0, 0, 0, 0]; // version + flags
+                write_descriptor(&mut esds, 0x03, &es);
+                write_box(&mut content, b"esds", &esds);
+                self.write_channel_layout(&mut content);
+                write_box(buf, b"mp4a", &content);
+            }
+            AudioCodec::Opus { pre_skip } => {
+                let mut dops = vec![0]; // version
+                dops.push(self.track.channels as u8); // OutputChannelCount
+                dops.extend_from_slice(&pre_skip.to_be_bytes());
+                dops.extend_from_slice(&self.track.sample_rate.to_be_bytes()); // InputSampleRate
+                dops.extend_from_slice(&0i16.to_be_bytes()); // OutputGain
+                match self.track.layout().map(|layout| layout.opus_mapping()) {
+                    Some(mapping) if mapping.family != 0 => {
+                        dops.push(mapping.family); // ChannelMappingFamily
+                        dops.push(mapping.stream_count);
+                        dops.push(mapping.coupled_count);
+                        dops.extend_from_slice(mapping.mapping);
+                    }
+                    _ => dops.push(0), // ChannelMappingFamily (mono/stereo)
+                }
+                write_box(&mut content, b"dOps", &dops);
+                self.write_channel_layout(&mut content);
+                write_box(buf, b"Opus", &content);
+            }
+        }
+    }
+
+    /// `chnl` box, so players do not have to guess the speaker layout.
+    fn write_channel_layout(&self, buf: &mut Vec<u8>) {
+        if let Some(layout) = self.track.layout() {
+            write_box(buf, b"chnl", &layout.chnl_payload());
+        }
+    }
+
+    fn write_moof(&self, buf: &mut Vec<u8>) {
+        let sample_count = self.pending_frames.len();
+        let mut content = Vec::new();
+
+        let mut mfhd = vec![0, 0, 0, 0]; // version + flags
+        mfhd.extend_from_slice(&self.sequence_number.to_be_bytes());
+        write_box(&mut content, b"mfhd", &mfhd);
+
+        let mut traf = Vec::new();
+        let mut tfhd = vec![0, 0x02, 0, 0]; // default-base-is-moof
+        tfhd.extend_from_slice(&self.track_id.to_be_bytes());
+        write_box(&mut traf, b"tfhd", &tfhd);
+        let mut tfdt = vec![1, 0, 0, 0]; // version 1 (64-bit time)
+        tfdt.extend_from_slice(&(self.fragment_base_dts as u64).to_be_bytes());
+        write_box(&mut traf, b"tfdt", &tfdt);
+
+        // moof header + mfhd + traf header + tfhd + tfdt + trun, then the mdat header
+        let trun_size = 8 + 12 + sample_count * 12;
+        let moof_size = 8 + 16 + 8 + 16 + 20 + trun_size;
+        let mut trun = Vec::new();
+        // data-offset, sample-duration, sample-size and sample-flags present
+        trun.extend_from_slice(&[0, 0x00, 0x07, 0x01]);
+        trun.extend_from_slice(&(sample_count as u32).to_be_bytes());
+        trun.extend_from_slice(&((moof_size + 8) as u32).to_be_bytes());
+        for frame in &self.pending_frames {
+            trun.extend_from_slice(&frame.duration.to_be_bytes());
+            trun.extend_from_slice(&(frame.data.len() as u32).to_be_bytes());
+            trun.extend_from_slice(&0x02000000u32.to_be_bytes()); // sync, depends on none
+        }
+        write_box(&mut traf, b"trun", &trun);
+        write_box(&mut content, b"traf", &traf);
+
+        write_box(buf, b"moof", &content);
+    }
+
+    fn write_mdat(&self, buf: &mut Vec<u8>) {
+        let total: usize = self.pending_frames.iter().map(|f| f.data.len()).sum();
+        buf.extend_from_slice(&((8 + total) as u32).to_be_bytes());
+        buf.extend_from_slice(b"mdat");
+        for frame in &self.pending_frames {
+            buf.extend_from_slice(&frame.data);
+        }
+    }
+}
+
+fn write_matrix(buf: &mut Vec<u8>) {
+    let matrix: [u32; 9] = [0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000];
+    for m in &matrix {
+        buf.extend_from_slice(&m.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::mfra::find_box;
+    use crate::helpers::RandomAccessIndex;
+
+    fn aac_track() -> AudioTrackConfig {
+        AudioTrackConfig {
+            codec: AudioCodec::Aac {
+                audio_specific_config: aac_audio_specific_config(2, 48000, 2).unwrap(),
+            },
+            sample_rate: 48000,
+            channels: 2,
+        }
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_aac_init_and_duration_fragmentation() {
+        assert_eq!(aac_track().codec_string(), "mp4a.40.2");
+        let AudioCodec::Aac {
+            audio_specific_config,
+        } = &aac_track().codec
+        else {
+            unreachable!()
+        };
+        assert_eq!(audio_specific_config, &[0x11, 0x90]);
+
+        let mut muxer = AudioCmafMuxer::new(aac_track(), 1000);
+        let init = muxer.init_segment();
+        assert_eq!(&init[4..8], b"ftyp");
+        assert!(contains(&init[..32], b"caac"));
+        assert!(contains(&init, b"soun") && contains(&init, b"mp4a") && contains(&init, b"esds"));
+        assert!(!contains(&init, b"vide"));
+
+        // 1024-sample frames at 48 kHz: 46 frames fit in one second
+        let mut segments = Vec::new();
+        for i in 0..100 {
+            segments.extend(muxer.add_frame(&[i as u8; 8], i * 1024, 1024));
+        }
+        assert_eq!(segments.len(), 2);
+        assert_eq!(muxer.last_fragment_duration().as_micros(), 981_333);
+        assert_eq!(muxer.pending_frame_count(), 8);
+
+        // Fragments start with a sync sample and are indexed
+        let mut index = RandomAccessIndex::new(1);
+        let point = index.add_fragment(0, &segments[1]).unwrap();
+        assert_eq!(point.time, 46 * 1024);
+        let moof = find_box(&segments[1], 0, segments[1].len(), b"moof").unwrap();
+        let mdat = find_box(&segments[1], moof.end(), segments[1].len(), b"mdat").unwrap();
+        assert_eq!(mdat.size, 8 + 46 * 8);
+    }
+
+    #[test]
+    fn test_opus_sample_entry() {
+        let track = AudioTrackConfig {
+            codec: AudioCodec::Opus { pre_skip: 312 },
+            sample_rate: 44100,
+            channels: 1,
+        };
+        assert_eq!(track.timescale(), 48000);
+        assert_eq!(track.codec_string(), "opus");
+        let init = AudioCmafMuxer::new(track, 2000).init_segment();
+        assert!(contains(&init[..32], b"Opus"));
+        // dOps: version, 1 channel, pre-skip 312, input rate 44100
+        assert!(contains(
+            &init,
+            &[b'd', b'O', b'p', b's', 0, 1, 0x01, 0x38, 0, 0, 0xAC, 0x44]
+        ));
+    }
+
+    #[test]
+    fn test_surround_sample_entries() {
+        let track = AudioTrackConfig::with_layout(
+            AudioCodec::Opus { pre_skip: 312 },
+            48000,
+            ChannelLayout::Surround51,
+        );
+        assert_eq!(track.channels, 6);
+        let init = AudioCmafMuxer::new(track, 2000).init_segment();
+        // Mapping family 1 with 4 streams, 2 coupled, and the 5.1 mapping table
+        assert!(contains(&init, &[0, 0, 1, 4, 2, 0, 4, 1, 2, 3, 5]));
+        // chnl: channel structured, ChannelConfiguration 6
+        assert!(contains(&init, &[b'c', b'h', b'n', b'l', 0, 0, 0, 0, 1, 6]));
+
+        let config = aac_audio_specific_config(2, 48000, 6).unwrap();
+        let track = AudioTrackConfig::with_layout(
+            AudioCodec::Aac {
+                audio_specific_config: config,
+            },
+            48000,
+            ChannelLayout::Surround51,
+        );
+        let init = AudioCmafMuxer::new(track, 2000).init_segment();
+        assert!(contains(&init, &[0x11, 0xB0]));
+        assert!(contains(&init, &[b'c', b'h', b'n', b'l', 0, 0, 0, 0, 1, 6]));
+    }
+}
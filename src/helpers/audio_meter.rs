@@ -0,0 +1,397 @@
+//! Audio level metering with silence and clipping detection.
+
+use std::time::Duration;
+
+use core_foundation_sys::base::OSStatus;
+use core_media_sys::CMSampleBufferRef;
+use libc::c_void;
+
+use super::audio_resampler::{decode_samples, AudioFormat, SampleFormat};
+use super::events::{emit, PipelineEvent};
+use super::loudness::LoudnessMeter;
+use crate::audio_converter::{
+    kAudioConverterErr_FormatNotSupported, kAudioFormatFlagIsBigEndian, kAudioFormatFlagIsFloat,
+    kAudioFormatFlagIsNonInterleaved, kAudioFormatFlagIsSignedInteger,
+};
+use crate::cm_sample_buffer::{
+    CMAudioFormatDescriptionGetStreamBasicDescription, CMBlockBufferCopyDataBytes,
+    CMBlockBufferGetDataLength, CMSampleBufferGetDataBuffer, CMSampleBufferGetFormatDescription,
+};
+use crate::codecs;
+
+/// Convert a linear level (1.0 = full scale) to dBFS.
+pub fn to_dbfs(level: f32) -> f32 {
+    if level <= 0.0 {
+        f32::NEG_INFINITY
+    } else {
+        20.0 * level.log10()
+    }
+}
+
+/// Per-channel levels of one buffer, linear with 1.0 as full scale.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioLevels {
+    pub rms: Vec<f32>,
+    pub peak: Vec<f32>,
+}
+
+impl AudioLevels {
+    /// Loudest channel RMS, in dBFS.
+    pub fn max_rms_dbfs(&self) -> f32 {
+        to_dbfs(self.rms.iter().copied().fold(0.0, f32::max))
+    }
+
+    /// Loudest channel peak, in dBFS.
+    pub fn max_peak_dbfs(&self) -> f32 {
+        to_dbfs(self.peak.iter().copied().fold(0.0, f32::max))
+    }
+}
+
+/// Measures RMS and peak levels of capture PCM per channel.
+///
+/// A buffer is silent when every channel's RMS is at or below
+/// [`silence_threshold`](Self::silence_threshold). Once silence has lasted
+/// [`silence_duration`](Self::silence_duration), the meter reports
+/// [`PipelineEvent::SilenceStarted`]; samples at or above
+/// [`clip_threshold`](Self::clip_threshold) are reported as
+/// [`PipelineEvent::ClippingDetected`]. Events go both to
+/// [`set_event_handler`](super::set_event_handler) and to the return value of
+/// [`process`](Self::process).
+///
+/// With [`measure_loudness`](Self::measure_loudness), the same audio also
+/// feeds a [`LoudnessMeter`] for the recording's integrated loudness and
+/// true peak.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use video_toolbox_sys::helpers::{AudioFormat, AudioMeter, PipelineEvent, SampleFormat};
+///
+/// let mut meter = AudioMeter::new(AudioFormat::new(48_000.0, 2, SampleFormat::F32))
+///     .silence_duration(Duration::from_secs(3));
+/// # let pcm = [0u8; 0];
+/// for event in meter.process(&pcm) {
+///     if let PipelineEvent::SilenceStarted { .. } = event {
+///         eprintln!("microphone appears muted");
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AudioMeter {
+    format: AudioFormat,
+    silence_threshold: f32,
+    silence_duration: Duration,
+    clip_threshold: f32,
+    frame_index: u64,
+    silent_run: u64,
+    silent: bool,
+    clipped_samples: u64,
+    levels: AudioLevels,
+    loudness: Option<LoudnessMeter>,
+}
+
+impl AudioMeter {
+    /// Create a meter for interleaved PCM in `format`.
+    ///
+    /// Defaults: silence below -60 dBFS for 2 seconds, clipping at -0.1 dBFS.
+    pub fn new(format: AudioFormat) -> Self {
+        Self {
+            format,
+            silence_threshold: -60.0,
+            silence_duration: Duration::from_secs(2),
+            clip_threshold: -0.1,
+            frame_index: 0,
+            silent_run: 0,
+            silent: false,
+            clipped_samples: 0,
+            levels: AudioLevels::default(),
+            loudness: None,
+        }
+    }
+
+    /// RMS level in dBFS at or below which a buffer counts as silent.
+    pub fn silence_threshold(mut self, dbfs: f32) -> Self {
+        self.silence_threshold = dbfs;
+        self
+    }
+
+    /// How long audio must stay silent before [`PipelineEvent::SilenceStarted`].
+    pub fn silence_duration(mut self, duration: Duration) -> Self {
+        self.silence_duration = duration;
+        self
+    }
+
+    /// Sample level in dBFS at or above which a sample counts as clipped.
+    pub fn clip_threshold(mut self, dbfs: f32) -> Self {
+        self.clip_threshold = dbfs.min(0.0);
+        self
+    }
+
+    /// Also measure EBU R128 loudness (off by default).
+    pub fn measure_loudness(mut self, enabled: bool) -> Self {
+        self.loudness = enabled.then(|| LoudnessMeter::new(self.format));
+        self
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Loudness of everything measured, if enabled.
+    pub fn loudness(&self) -> Option<&LoudnessMeter> {
+        self.loudness.as_ref()
+    }
+
+    /// Levels of the most recent buffer.
+    pub fn levels(&self) -> &AudioLevels {
+        &self.levels
+    }
+
+    /// Whether silence is currently reported.
+    pub fn is_silent(&self) -> bool {
+        self.silent
+    }
+
+    /// Total clipped samples across all channels.
+    pub fn clipped_samples(&self) -> u64 {
+        self.clipped_samples
+    }
+
+    /// Number of audio frames measured.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_index
+    }
+
+    /// Measure a buffer of interleaved PCM in the meter's format.
+    ///
+    /// Returns the events emitted for this buffer (usually none).
+    pub fn process(&mut self, pcm: &[u8]) -> Vec<PipelineEvent> {
+        let samples = decode_samples(pcm, self.format.sample_format);
+        self.process_samples(&samples)
+    }
+
+    /// Measure interleaved `f32` samples.
+    pub fn process_samples(&mut self, samples: &[f32]) -> Vec<PipelineEvent> {
+        let channels = self.format.channels.max(1) as usize;
+        let frames = samples.len() / channels;
+        let clip_level = 10f32.powf(self.clip_threshold / 20.0);
+        if let Some(loudness) = &mut self.loudness {
+            loudness.process_samples(samples);
+        }
+
+        let mut sum_squares = vec![0.0f64; channels];
+        let mut peak = vec![0.0f32; channels];
+        let mut clipped = vec![0u64; channels];
+        for frame in samples.chunks_exact(channels) {
+            for (ch, &sample) in frame.iter().enumerate() {
+                let level = sample.abs();
+                sum_squares[ch] += (sample as f64) * (sample as f64);
+                peak[ch] = peak[ch].max(level);
+                if level >= clip_level {
+                    clipped[ch] += 1;
+                }
+            }
+        }
+        let rms = sum_squares
+            .iter()
+            .map(|&sum| (sum / frames.max(1) as f64).sqrt() as f32)
+            .collect();
+        self.levels = AudioLevels { rms, peak };
+
+        let index = self.frame_index;
+        self.frame_index += frames as u64;
+        let mut events = Vec::new();
+        if frames == 0 {
+            return events;
+        }
+
+        if self.levels.max_rms_dbfs() <= self.silence_threshold {
+            self.silent_run += frames as u64;
+            let min_frames = (self.silence_duration.as_secs_f64() * self.format.sample_rate) as u64;
+            if !self.silent && self.silent_run >= min_frames.max(1) {
+                self.silent = true;
+                events.push(PipelineEvent::SilenceStarted {
+                    frame_index: self.frame_index - self.silent_run,
+                });
+            }
+        } else {
+            if self.silent {
+                events.push(PipelineEvent::SilenceEnded {
+                    frame_index: index,
+                    frames: self.silent_run,
+                });
+            }
+            self.silent = false;
+            self.silent_run = 0;
+        }
+
+        for (channel, &samples) in clipped.iter().enumerate() {
+            if samples > 0 {
+                self.clipped_samples += samples;
+                events.push(PipelineEvent::ClippingDetected {
+                    frame_index: index,
+                    channel: channel as u32,
+                    samples,
+                });
+            }
+        }
+
+        for event in &events {
+            emit(event.clone());
+        }
+        events
+    }
+
+    /// Measure a capture sample buffer.
+    ///
+    /// The buffer's own stream description replaces the configured format, so
+    /// this works with whatever the capture device delivers, as long as it is
+    /// native-endian 32-bit float or 16-bit integer linear PCM (interleaved or
+    /// not). Other formats return `kAudioConverterErr_FormatNotSupported`.
+    ///
+    /// # Safety
+    ///
+    /// `sample_buffer` must be a valid CMSampleBuffer.
+    pub unsafe fn process_sample_buffer(
+        &mut self,
+        sample_buffer: CMSampleBufferRef,
+    ) -> Result<Vec<PipelineEvent>, OSStatus> {
+        let desc = CMSampleBufferGetFormatDescription(sample_buffer);
+        if desc.is_null() {
+            return Err(kAudioConverterErr_FormatNotSupported);
+        }
+        let asbd = CMAudioFormatDescriptionGetStreamBasicDescription(desc);
+        if asbd.is_null() {
+            return Err(kAudioConverterErr_FormatNotSupported);
+        }
+        let asbd = *asbd;
+        let flags = asbd.mFormatFlags;
+        let sample_format = match (asbd.mBitsPerChannel, flags) {
+            (32, f) if f & kAudioFormatFlagIsFloat != 0 => SampleFormat::F32,
+            (16, f) if f & kAudioFormatFlagIsSignedInteger != 0 => SampleFormat::I16,
+            _ => return Err(kAudioConverterErr_FormatNotSupported),
+        };
+        if asbd.mFormatID != codecs::audio::LPCM
+            || flags & kAudioFormatFlagIsBigEndian != 0
+            || asbd.mChannelsPerFrame == 0
+        {
+            return Err(kAudioConverterErr_FormatNotSupported);
+        }
+        self.format = AudioFormat::new(asbd.mSampleRate, asbd.mChannelsPerFrame, sample_format);
+        if let Some(loudness) = &mut self.loudness {
+            let measured = loudness.format();
+            if (measured.sample_rate, measured.channels)
+                != (asbd.mSampleRate, asbd.mChannelsPerFrame)
+            {
+                // Filters and weights depend on both
+                *loudness = LoudnessMeter::new(self.format);
+            }
+        }
+
+        let block = CMSampleBufferGetDataBuffer(sample_buffer);
+        if block.is_null() {
+            return Ok(Vec::new());
+        }
+        let len = CMBlockBufferGetDataLength(block);
+        let mut bytes = vec![0u8; len];
+        let status = CMBlockBufferCopyDataBytes(block, 0, len, bytes.as_mut_ptr() as *mut c_void);
+        if status != 0 {
+            return Err(status);
+        }
+
+        let samples = decode_samples(&bytes, sample_format);
+        if flags & kAudioFormatFlagIsNonInterleaved != 0 {
+            Ok(self.process_samples(&interleave(&samples, asbd.mChannelsPerFrame as usize)))
+        } else {
+            Ok(self.process_samples(&samples))
+        }
+    }
+}
+
+/// Interleave consecutive per-channel planes.
+fn interleave(planar: &[f32], channels: usize) -> Vec<f32> {
+    let frames = planar.len() / channels;
+    (0..frames * channels)
+        .map(|i| planar[(i % channels) * frames + i / channels])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo_meter() -> AudioMeter {
+        AudioMeter::new(AudioFormat::new(1000.0, 2, SampleFormat::F32))
+            .silence_duration(Duration::from_millis(200))
+    }
+
+    #[test]
+    fn test_levels_per_channel() {
+        let mut meter = stereo_meter();
+        // Left: full-scale square wave, right: half-scale constant
+        let samples: Vec<f32> = (0..100)
+            .flat_map(|i| [if i % 2 == 0 { 1.0 } else { -1.0 }, 0.5])
+            .collect();
+        let events = meter.process_samples(&samples);
+
+        let levels = meter.levels();
+        assert!((levels.rms[0] - 1.0).abs() < 1e-6);
+        assert!((levels.rms[1] - 0.5).abs() < 1e-6);
+        assert_eq!(levels.peak, vec![1.0, 0.5]);
+        assert!(levels.max_peak_dbfs().abs() < 1e-6);
+        assert_eq!(
+            events,
+            vec![PipelineEvent::ClippingDetected {
+                frame_index: 0,
+                channel: 0,
+                samples: 100
+            }]
+        );
+        assert_eq!(meter.clipped_samples(), 100);
+    }
+
+    #[test]
+    fn test_silence_events() {
+        let mut meter = stereo_meter();
+        let quiet = vec![0.0001f32; 200];
+        let loud = vec![0.1f32; 200];
+        assert!(meter.process_samples(&loud).is_empty());
+        assert!(meter.process_samples(&quiet).is_empty());
+        assert_eq!(
+            meter.process_samples(&quiet),
+            vec![PipelineEvent::SilenceStarted { frame_index: 100 }]
+        );
+        assert!(meter.is_silent());
+        assert!(meter.process_samples(&quiet).is_empty());
+        assert_eq!(
+            meter.process_samples(&loud),
+            vec![PipelineEvent::SilenceEnded {
+                frame_index: 400,
+                frames: 300
+            }]
+        );
+        assert!(!meter.is_silent());
+        assert_eq!(meter.frame_count(), 500);
+    }
+
+    #[test]
+    fn test_process_i16_bytes() {
+        let mut meter = AudioMeter::new(AudioFormat::new(48_000.0, 1, SampleFormat::I16));
+        let bytes: Vec<u8> = [16384i16, -16384]
+            .iter()
+            .flat_map(|s| s.to_ne_bytes())
+            .collect();
+        assert!(meter.process(&bytes).is_empty());
+        assert!((meter.levels().max_rms_dbfs() - to_dbfs(0.5)).abs() < 1e-4);
+        assert_eq!(to_dbfs(0.0), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_interleave_planes() {
+        assert_eq!(
+            interleave(&[1.0, 2.0, 3.0, 10.0, 20.0, 30.0], 2),
+            vec![1.0, 10.0, 2.0, 20.0, 3.0, 30.0]
+        );
+    }
+}
@@ -0,0 +1,604 @@
+//! Audio sample rate, channel layout and sample format conversion.
+//!
+//! Sits between audio capture and an encoder when their formats disagree, e.g. a
+//! microphone delivering 48 kHz stereo `f32` into a 44.1 kHz mono `i16` encoder.
+//! Channel mixing is done in Rust (so down/upmix is deterministic), then an
+//! AudioConverter performs rate and sample format conversion.
+
+use core_foundation_sys::base::OSStatus;
+use libc::c_void;
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+
+use crate::audio_converter::{
+    kAudioConverterErr_FormatNotSupported, kAudioConverterPrimeInfo,
+    kAudioConverterSampleRateConverterQuality, kAudioFormatFlagIsFloat, kAudioFormatFlagIsPacked,
+    kAudioFormatFlagIsSignedInteger, AudioBuffer, AudioBufferList, AudioConverterDispose,
+    AudioConverterFillComplexBuffer, AudioConverterGetProperty, AudioConverterNew,
+    AudioConverterPrimeInfo, AudioConverterRef, AudioConverterReset, AudioConverterSetProperty,
+    AudioStreamBasicDescription, AudioStreamPacketDescription,
+};
+use crate::codecs;
+
+use super::channel_layout::ChannelLayout;
+use super::events::catch_callback_panic;
+
+/// Status returned from the input callback when all queued input has been consumed.
+///
+/// Not an AudioToolbox error: it pauses the conversion until more input arrives.
+const NO_MORE_INPUT: OSStatus = 0x6e6f6d6f; // 'nomo'
+
+/// Status returned from the input callback if it panicked, failing the
+/// conversion.
+const INPUT_PANICKED: OSStatus = 0x21706e63; // '!pnc'
+
+/// Output frames requested from the converter per `AudioConverterFillComplexBuffer` call.
+const OUTPUT_CHUNK_FRAMES: u32 = 4096;
+
+/// Sample representation of interleaved linear PCM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// 32-bit float in [-1.0, 1.0]
+    F32,
+    /// 16-bit signed integer
+    I16,
+}
+
+impl SampleFormat {
+    /// Size of a single sample in bytes.
+    pub fn bytes_per_sample(&self) -> u32 {
+        match self {
+            SampleFormat::F32 => 4,
+            SampleFormat::I16 => 2,
+        }
+    }
+}
+
+/// Interleaved linear PCM format description.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioFormat {
+    /// Sample rate in Hz
+    pub sample_rate: f64,
+    /// Number of interleaved channels
+    pub channels: u32,
+    /// Sample representation
+    pub sample_format: SampleFormat,
+}
+
+impl AudioFormat {
+    pub fn new(sample_rate: f64, channels: u32, sample_format: SampleFormat) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            sample_format,
+        }
+    }
+
+    /// The speaker layout implied by the channel count, if it is a known one.
+    pub fn layout(&self) -> Option<ChannelLayout> {
+        ChannelLayout::from_channel_count(self.channels)
+    }
+
+    /// Size of one frame (one sample for every channel) in bytes.
+    pub fn bytes_per_frame(&self) -> u32 {
+        self.channels * self.sample_format.bytes_per_sample()
+    }
+
+    /// Build the equivalent `AudioStreamBasicDescription`.
+    pub fn stream_description(&self) -> AudioStreamBasicDescription {
+        let flags = match self.sample_format {
+            SampleFormat::F32 => kAudioFormatFlagIsFloat | kAudioFormatFlagIsPacked,
+            SampleFormat::I16 => kAudioFormatFlagIsSignedInteger | kAudioFormatFlagIsPacked,
+        };
+        AudioStreamBasicDescription {
+            mSampleRate: self.sample_rate,
+            mFormatID: codecs::audio::LPCM,
+            mFormatFlags: flags,
+            mBytesPerPacket: self.bytes_per_frame(),
+            mFramesPerPacket: 1,
+            mBytesPerFrame: self.bytes_per_frame(),
+            mChannelsPerFrame: self.channels,
+            mBitsPerChannel: self.sample_format.bytes_per_sample() * 8,
+            mReserved: 0,
+        }
+    }
+}
+
+/// Mixes interleaved `f32` audio from one channel count to another.
+///
+/// The mix is a row-major `output_channels x input_channels` gain matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelMapper {
+    input_channels: usize,
+    output_channels: usize,
+    matrix: Vec<f32>,
+}
+
+impl ChannelMapper {
+    /// Create a mapper with the default mix for the given channel counts.
+    ///
+    /// - equal counts: passthrough
+    /// - 5.1 to or from mono or stereo: [`ChannelLayout::mix_matrix`], so the
+    ///   LFE is not mixed into the right channel
+    /// - mono input: duplicated to every output channel
+    /// - mono output: average of all input channels
+    /// - otherwise: input channel `i` feeds output `i % output_channels`, averaged
+    ///
+    /// Returns `None` if either channel count is zero.
+    pub fn new(input_channels: u32, output_channels: u32) -> Option<Self> {
+        if input_channels == 0 || output_channels == 0 {
+            return None;
+        }
+        let layouts = (
+            ChannelLayout::from_channel_count(input_channels),
+            ChannelLayout::from_channel_count(output_channels),
+        );
+        if let (Some(input), Some(output)) = layouts {
+            if input == ChannelLayout::Surround51 || output == ChannelLayout::Surround51 {
+                return Some(Self::for_layouts(input, output));
+            }
+        }
+
+        let (ic, oc) = (input_channels as usize, output_channels as usize);
+        let mut matrix = vec![0.0; ic * oc];
+
+        if ic == 1 {
+            matrix.iter_mut().for_each(|g| *g = 1.0);
+        } else {
+            for input in 0..ic {
+                matrix[(input % oc) * ic + input] = 1.0;
+            }
+            for row in matrix.chunks_mut(ic) {
+                let sources = row.iter().filter(|&&g| g != 0.0).count();
+                if sources > 1 {
+                    row.iter_mut().for_each(|g| *g /= sources as f32);
+                }
+            }
+        }
+
+        Some(Self {
+            input_channels: ic,
+            output_channels: oc,
+            matrix,
+        })
+    }
+
+    /// Create a mapper between two speaker layouts (see
+    /// [`ChannelLayout::mix_matrix`]).
+    pub fn for_layouts(input: ChannelLayout, output: ChannelLayout) -> Self {
+        Self {
+            input_channels: input.channel_count() as usize,
+            output_channels: output.channel_count() as usize,
+            matrix: input.mix_matrix(output),
+        }
+    }
+
+    /// Create a mapper from an explicit row-major gain matrix.
+    ///
+    /// Returns `None` if either channel count is zero or the matrix is not
+    /// `output_channels * input_channels` long.
+    pub fn with_matrix(
+        input_channels: u32,
+        output_channels: u32,
+        matrix: Vec<f32>,
+    ) -> Option<Self> {
+        if input_channels == 0
+            || output_channels == 0
+            || matrix.len() != input_channels as usize * output_channels as usize
+        {
+            return None;
+        }
+        Some(Self {
+            input_channels: input_channels as usize,
+            output_channels: output_channels as usize,
+            matrix,
+        })
+    }
+
+    /// Returns true if the mapper leaves samples unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.input_channels == self.output_channels
+            && self.matrix.iter().enumerate().all(|(i, &g)| {
+                let expected = if i / self.input_channels == i % self.input_channels {
+                    1.0
+                } else {
+                    0.0
+                };
+                g == expected
+            })
+    }
+
+    /// Mix interleaved input frames, appending the result to `output`.
+    ///
+    /// Trailing samples that don't form a whole frame are ignored.
+    pub fn map(&self, input: &[f32], output: &mut Vec<f32>) {
+        if self.is_identity() {
+            output.extend_from_slice(input);
+            return;
+        }
+        for frame in input.chunks_exact(self.input_channels) {
+            for row in self.matrix.chunks(self.input_channels) {
+                output.push(row.iter().zip(frame).map(|(g, s)| g * s).sum());
+            }
+        }
+    }
+}
+
+/// Input queue handed to the converter's input callback.
+struct InputState {
+    samples: *const f32,
+    frames: u32,
+    consumed: u32,
+    channels: u32,
+    end_of_stream: bool,
+}
+
+/// Converts interleaved PCM between sample rates, channel layouts and sample formats.
+///
+/// Feed capture buffers with [`AudioResampler::process`]; each call returns as much
+/// converted output as is available. Call [`AudioResampler::flush`] at end of
+/// stream to drain the frames still held by the sample rate converter.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{AudioFormat, AudioResampler, SampleFormat};
+///
+/// let mic = AudioFormat::new(48_000.0, 2, SampleFormat::F32);
+/// let encoder = AudioFormat::new(44_100.0, 1, SampleFormat::I16);
+/// let mut resampler = AudioResampler::new(mic, encoder).unwrap();
+///
+/// let captured = vec![0u8; 1024 * mic.bytes_per_frame() as usize];
+/// let pcm = resampler.process(&captured).unwrap();
+/// // Hand `pcm` to the encoder; skip `resampler.priming_frames()` leading frames
+/// // if the encoder's timeline must align with the capture timeline.
+/// ```
+pub struct AudioResampler {
+    converter: AudioConverterRef,
+    input: AudioFormat,
+    output: AudioFormat,
+    mapper: ChannelMapper,
+    pending: Vec<f32>,
+    prime_info: AudioConverterPrimeInfo,
+    frames_in: u64,
+    frames_out: u64,
+}
+
+unsafe impl Send for AudioResampler {}
+
+impl AudioResampler {
+    /// Create a resampler using the default [`ChannelMapper`] mix.
+    ///
+    /// Returns `kAudioConverterErr_FormatNotSupported` if either format has
+    /// zero channels.
+    pub fn new(input: AudioFormat, output: AudioFormat) -> Result<Self, OSStatus> {
+        let mapper = ChannelMapper::new(input.channels, output.channels)
+            .ok_or(kAudioConverterErr_FormatNotSupported)?;
+        Self::with_channel_mapper(input, output, mapper)
+    }
+
+    /// Create a resampler with a custom channel mix.
+    pub fn with_channel_mapper(
+        input: AudioFormat,
+        output: AudioFormat,
+        mapper: ChannelMapper,
+    ) -> Result<Self, OSStatus> {
+        // Mixing happens before conversion, so the converter sees the output layout
+        // as float at the input rate.
+        let mixed = AudioFormat::new(input.sample_rate, output.channels, SampleFormat::F32);
+        let source = mixed.stream_description();
+        let destination = output.stream_description();
+
+        let mut converter: AudioConverterRef = ptr::null_mut();
+        let status = unsafe { AudioConverterNew(&source, &destination, &mut converter) };
+        if status != 0 {
+            return Err(status);
+        }
+
+        let mut prime_info = AudioConverterPrimeInfo::default();
+        let mut size = mem::size_of::<AudioConverterPrimeInfo>() as u32;
+        let status = unsafe {
+            AudioConverterGetProperty(
+                converter,
+                kAudioConverterPrimeInfo,
+                &mut size,
+                &mut prime_info as *mut _ as *mut c_void,
+            )
+        };
+        if status != 0 {
+            // Converters without rate conversion have no priming.
+            prime_info = AudioConverterPrimeInfo::default();
+        }
+
+        Ok(Self {
+            converter,
+            input,
+            output,
+            mapper,
+            pending: Vec::new(),
+            prime_info,
+            frames_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    /// Set the sample rate converter quality (`kAudioConverterQuality_*`).
+    pub fn set_quality(&mut self, quality: u32) -> Result<(), OSStatus> {
+        let status = unsafe {
+            AudioConverterSetProperty(
+                self.converter,
+                kAudioConverterSampleRateConverterQuality,
+                mem::size_of::<u32>() as u32,
+                &quality as *const u32 as *const c_void,
+            )
+        };
+        crate::errors::status_to_result(status)
+    }
+
+    /// Convert interleaved input bytes, returning the output bytes produced so far.
+    ///
+    /// Output may lag input by the converter's latency; it is returned by later
+    /// calls or by [`AudioResampler::flush`].
+    pub fn process(&mut self, input: &[u8]) -> Result<Vec<u8>, OSStatus> {
+        let samples = decode_samples(input, self.input.sample_format);
+        self.frames_in += (samples.len() / self.input.channels as usize) as u64;
+        self.mapper.map(&samples, &mut self.pending);
+        self.drain(false)
+    }
+
+    /// Drain all buffered audio at end of stream and reset the converter.
+    pub fn flush(&mut self) -> Result<Vec<u8>, OSStatus> {
+        let output = self.drain(true);
+        let status = unsafe { AudioConverterReset(self.converter) };
+        self.pending.clear();
+        output.and_then(|out| crate::errors::status_to_result(status).map(|_| out))
+    }
+
+    /// Discard buffered audio, e.g. after a capture discontinuity.
+    pub fn reset(&mut self) -> Result<(), OSStatus> {
+        self.pending.clear();
+        crate::errors::status_to_result(unsafe { AudioConverterReset(self.converter) })
+    }
+
+    /// Priming frames reported by the converter (in input frames).
+    pub fn prime_info(&self) -> AudioConverterPrimeInfo {
+        self.prime_info
+    }
+
+    /// Leading frames of output that precede the first input sample.
+    pub fn priming_frames(&self) -> u32 {
+        let ratio = self.output.sample_rate / self.input.sample_rate;
+        (self.prime_info.leadingFrames as f64 * ratio).round() as u32
+    }
+
+    /// Delay introduced by the converter.
+    pub fn latency(&self) -> Duration {
+        Duration::from_secs_f64(self.prime_info.leadingFrames as f64 / self.input.sample_rate)
+    }
+
+    /// Total input frames consumed.
+    pub fn frames_in(&self) -> u64 {
+        self.frames_in
+    }
+
+    /// Total output frames produced; use to derive encoder timestamps.
+    pub fn frames_out(&self) -> u64 {
+        self.frames_out
+    }
+
+    pub fn input_format(&self) -> AudioFormat {
+        self.input
+    }
+
+    pub fn output_format(&self) -> AudioFormat {
+        self.output
+    }
+
+    fn drain(&mut self, end_of_stream: bool) -> Result<Vec<u8>, OSStatus> {
+        let channels = self.output.channels;
+        let bytes_per_frame = self.output.bytes_per_frame() as usize;
+        let mut state = InputState {
+            samples: self.pending.as_ptr(),
+            frames: (self.pending.len() / channels as usize) as u32,
+            consumed: 0,
+            channels,
+            end_of_stream,
+        };
+
+        let mut output = Vec::new();
+        let mut produced = 0u64;
+        let result = loop {
+            let start = output.len();
+            output.resize(start + OUTPUT_CHUNK_FRAMES as usize * bytes_per_frame, 0);
+
+            let mut buffers = AudioBufferList {
+                mNumberBuffers: 1,
+                mBuffers: [AudioBuffer {
+                    mNumberChannels: channels,
+                    mDataByteSize: OUTPUT_CHUNK_FRAMES * bytes_per_frame as u32,
+                    mData: output[start..].as_mut_ptr() as *mut c_void,
+                }],
+            };
+            let mut frames = OUTPUT_CHUNK_FRAMES;
+            let status = unsafe {
+                AudioConverterFillComplexBuffer(
+                    self.converter,
+                    input_proc,
+                    &mut state as *mut InputState as *mut c_void,
+                    &mut frames,
+                    &mut buffers,
+                    ptr::null_mut(),
+                )
+            };
+
+            output.truncate(start + frames as usize * bytes_per_frame);
+            produced += frames as u64;
+
+            if status == NO_MORE_INPUT || (status == 0 && frames == 0) {
+                break Ok(());
+            }
+            if status != 0 {
+                break Err(status);
+            }
+        };
+
+        // The converter has taken these frames whether or not it failed, so
+        // they must not be fed again.
+        let consumed = state.consumed as usize * channels as usize;
+        self.pending.drain(..consumed);
+        // Output is discarded on error, so it is not counted either.
+        result?;
+        self.frames_out += produced;
+        Ok(output)
+    }
+}
+
+impl Drop for AudioResampler {
+    fn drop(&mut self) {
+        unsafe {
+            AudioConverterDispose(self.converter);
+        }
+    }
+}
+
+/// Supplies queued frames to the converter.
+extern "C" fn input_proc(
+    _converter: AudioConverterRef,
+    io_packets: *mut u32,
+    io_data: *mut AudioBufferList,
+    _packet_descriptions: *mut *mut AudioStreamPacketDescription,
+    user_data: *mut c_void,
+) -> OSStatus {
+    catch_callback_panic("audio converter input", || {
+        supply_input(io_packets, io_data, user_data)
+    })
+    .unwrap_or(INPUT_PANICKED)
+}
+
+fn supply_input(
+    io_packets: *mut u32,
+    io_data: *mut AudioBufferList,
+    user_data: *mut c_void,
+) -> OSStatus {
+    let state = unsafe { &mut *(user_data as *mut InputState) };
+    let io_data = unsafe { &mut *io_data };
+    let remaining = state.frames - state.consumed;
+
+    if remaining == 0 {
+        unsafe { *io_packets = 0 };
+        io_data.mBuffers[0].mDataByteSize = 0;
+        io_data.mBuffers[0].mData = ptr::null_mut();
+        // Returning noErr with no packets signals end of stream to the converter.
+        return if state.end_of_stream {
+            0
+        } else {
+            NO_MORE_INPUT
+        };
+    }
+
+    let frames = unsafe { (*io_packets).min(remaining) };
+    let offset = (state.consumed * state.channels) as usize;
+    io_data.mBuffers[0].mNumberChannels = state.channels;
+    io_data.mBuffers[0].mDataByteSize = frames * state.channels * 4;
+    io_data.mBuffers[0].mData = unsafe { state.samples.add(offset) } as *mut c_void;
+    unsafe { *io_packets = frames };
+    state.consumed += frames;
+    0
+}
+
+/// Decode interleaved PCM bytes in native byte order to `f32` samples.
+pub(crate) fn decode_samples(bytes: &[u8], format: SampleFormat) -> Vec<f32> {
+    match format {
+        SampleFormat::F32 => bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        SampleFormat::I16 => bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_ne_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_description() {
+        let asbd = AudioFormat::new(44_100.0, 1, SampleFormat::I16).stream_description();
+        assert_eq!(asbd.mFormatID, codecs::audio::LPCM);
+        assert_eq!(asbd.mBytesPerFrame, 2);
+        assert_eq!(asbd.mBitsPerChannel, 16);
+        assert_eq!(
+            asbd.mFormatFlags,
+            kAudioFormatFlagIsSignedInteger | kAudioFormatFlagIsPacked
+        );
+
+        let asbd = AudioFormat::new(48_000.0, 2, SampleFormat::F32).stream_description();
+        assert_eq!(asbd.mBytesPerPacket, 8);
+        assert_eq!(asbd.mChannelsPerFrame, 2);
+    }
+
+    #[test]
+    fn test_channel_mapper_default_mixes() {
+        let mut out = Vec::new();
+        ChannelMapper::new(2, 1)
+            .unwrap()
+            .map(&[1.0, 0.0, 0.5, 0.5], &mut out);
+        assert_eq!(out, vec![0.5, 0.5]);
+
+        out.clear();
+        ChannelMapper::new(1, 2)
+            .unwrap()
+            .map(&[0.25, -0.5], &mut out);
+        assert_eq!(out, vec![0.25, 0.25, -0.5, -0.5]);
+
+        out.clear();
+        ChannelMapper::new(4, 2)
+            .unwrap()
+            .map(&[1.0, 0.0, 0.0, 1.0], &mut out);
+        assert_eq!(out, vec![0.5, 0.5]);
+
+        out.clear();
+        ChannelMapper::new(2, 4).unwrap().map(&[0.1, 0.2], &mut out);
+        assert_eq!(out, vec![0.1, 0.2, 0.0, 0.0]);
+
+        assert!(ChannelMapper::new(2, 2).unwrap().is_identity());
+        assert!(!ChannelMapper::new(2, 1).unwrap().is_identity());
+        assert!(ChannelMapper::new(0, 2).is_none());
+        assert!(ChannelMapper::new(2, 0).is_none());
+    }
+
+    #[test]
+    fn test_channel_mapper_custom_matrix() {
+        assert!(ChannelMapper::with_matrix(2, 1, vec![1.0]).is_none());
+        assert!(ChannelMapper::with_matrix(0, 2, Vec::new()).is_none());
+
+        // Swap left and right
+        let mapper = ChannelMapper::with_matrix(2, 2, vec![0.0, 1.0, 1.0, 0.0]).unwrap();
+        assert!(!mapper.is_identity());
+        let mut out = Vec::new();
+        mapper.map(&[0.1, 0.9], &mut out);
+        assert_eq!(out, vec![0.9, 0.1]);
+    }
+
+    #[test]
+    fn test_decode_samples() {
+        let bytes: Vec<u8> = [i16::MIN, 0, 16384]
+            .iter()
+            .flat_map(|s| s.to_ne_bytes())
+            .collect();
+        assert_eq!(
+            decode_samples(&bytes, SampleFormat::I16),
+            vec![-1.0, 0.0, 0.5]
+        );
+
+        let bytes: Vec<u8> = [0.25f32, -1.0]
+            .iter()
+            .flat_map(|s| s.to_ne_bytes())
+            .collect();
+        assert_eq!(decode_samples(&bytes, SampleFormat::F32), vec![0.25, -1.0]);
+    }
+}
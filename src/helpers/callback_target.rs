@@ -0,0 +1,360 @@
+//! Moving session output callbacks off VideoToolbox's threads.
+
+use core_foundation_sys::base::{CFRelease, CFRetain, CFTypeRef};
+use libc::c_void;
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::events::catch_callback_panic;
+
+/// How long an idle worker sleeps before checking whether it was stopped.
+const PARK_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[link(name = "System")]
+extern "C" {
+    fn dispatch_async_f(queue: *mut c_void, context: *mut c_void, work: extern "C" fn(*mut c_void));
+}
+
+/// A serial dispatch queue to run callbacks on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallbackQueue(*mut c_void);
+
+// SAFETY: dispatch queues may be targeted from any thread.
+unsafe impl Send for CallbackQueue {}
+unsafe impl Sync for CallbackQueue {}
+
+impl CallbackQueue {
+    /// Wrap a dispatch queue, e.g. from
+    /// [`create_dispatch_queue`](super::create_dispatch_queue).
+    ///
+    /// # Safety
+    ///
+    /// `queue` must be a valid dispatch queue that outlives every session
+    /// using it.
+    pub unsafe fn from_raw(queue: *mut c_void) -> Self {
+        Self(queue)
+    }
+
+    pub fn as_raw(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Where a session's output callback runs.
+///
+/// By default callbacks run on the VideoToolbox thread that produced the
+/// output, so a slow callback, or one contending on a lock, delays the
+/// session. The other targets retain the output (sample or image buffer)
+/// and run the callback elsewhere; the buffer is valid for the duration of
+/// the callback as usual. Outputs are delivered in the order the session
+/// produced them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CallbackTarget {
+    /// On the VideoToolbox callback thread
+    #[default]
+    Inline,
+    /// On a dedicated worker thread, handed over through a lock-free
+    /// single-producer queue of up to `capacity` outputs. When the queue is
+    /// full the session's callback thread waits for the worker.
+    Worker { capacity: usize },
+    /// On a serial dispatch queue
+    DispatchQueue(CallbackQueue),
+}
+
+/// Bounded lock-free single-producer, single-consumer ring buffer.
+struct SpscQueue<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Next slot to read, written by the consumer only
+    head: AtomicUsize,
+    /// Next slot to write, written by the producer only
+    tail: AtomicUsize,
+}
+
+// SAFETY: each slot is accessed by one side at a time, as ordered by
+// `head` and `tail`.
+unsafe impl<T: Send> Send for SpscQueue<T> {}
+unsafe impl<T: Send> Sync for SpscQueue<T> {}
+
+impl<T> SpscQueue<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            slots: (0..capacity.max(1))
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Producer side. Returns the item if the queue is full.
+    ///
+    /// # Safety
+    ///
+    /// Must not be called concurrently with itself.
+    unsafe fn push(&self, item: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == self.slots.len() {
+            return Err(item);
+        }
+        (*self.slots[tail % self.slots.len()].get()).write(item);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Consumer side.
+    ///
+    /// # Safety
+    ///
+    /// Must not be called concurrently with itself.
+    unsafe fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let item = (*self.slots[head % self.slots.len()].get()).assume_init_read();
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+}
+
+impl<T> Drop for SpscQueue<T> {
+    fn drop(&mut self) {
+        while unsafe { self.pop() }.is_some() {}
+    }
+}
+
+struct WorkerShared<T> {
+    queue: SpscQueue<T>,
+    /// Held by the producer while pushing
+    producing: AtomicBool,
+    stop: AtomicBool,
+    handled: AtomicU64,
+}
+
+/// Runs a handler on a dedicated thread for items sent from callbacks.
+///
+/// Items are handed over through a lock-free bounded queue, so sending
+/// never takes a lock. [`send`](Self::send) waits while the queue is full.
+/// Sends are meant to come from one thread at a time, as session callbacks
+/// do; concurrent senders are serialized with a spin flag. Handler panics
+/// are caught and reported as
+/// [`PipelineEvent::CallbackPanicked`](super::PipelineEvent::CallbackPanicked).
+///
+/// Dropping the worker delivers the items still queued, then joins the
+/// thread.
+pub struct CallbackWorker<T: Send + 'static> {
+    shared: Arc<WorkerShared<T>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> CallbackWorker<T> {
+    /// Start a worker thread named `name` with room for `capacity` queued items.
+    pub fn spawn<F>(name: &'static str, capacity: usize, mut handler: F) -> Self
+    where
+        F: FnMut(T) + Send + 'static,
+    {
+        let shared = Arc::new(WorkerShared {
+            queue: SpscQueue::new(capacity),
+            producing: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+            handled: AtomicU64::new(0),
+        });
+
+        let worker_shared = shared.clone();
+        let worker = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let shared = worker_shared;
+                loop {
+                    // SAFETY: this thread is the only consumer
+                    match unsafe { shared.queue.pop() } {
+                        Some(item) => {
+                            catch_callback_panic(name, || handler(item));
+                            shared.handled.fetch_add(1, Ordering::Relaxed);
+                        }
+                        None if shared.stop.load(Ordering::Acquire) => {
+                            // Items sent before the stop flag are visible now
+                            if unsafe { shared.queue.pop() }.is_none() {
+                                break;
+                            }
+                        }
+                        None => thread::park_timeout(PARK_TIMEOUT),
+                    }
+                }
+            })
+            .expect("failed to spawn callback worker");
+
+        Self {
+            shared,
+            worker: Some(worker),
+        }
+    }
+
+    /// Queue `item` for the handler, waiting while the queue is full.
+    pub fn send(&self, mut item: T) {
+        while self
+            .shared
+            .producing
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            std::hint::spin_loop();
+        }
+        // SAFETY: `producing` makes this the only producer
+        while let Err(rejected) = unsafe { self.shared.queue.push(item) } {
+            item = rejected;
+            self.wake();
+            thread::yield_now();
+        }
+        self.shared.producing.store(false, Ordering::Release);
+        self.wake();
+    }
+
+    /// Items handled so far.
+    pub fn handled(&self) -> u64 {
+        self.shared.handled.load(Ordering::Relaxed)
+    }
+
+    fn wake(&self) {
+        if let Some(worker) = &self.worker {
+            worker.thread().unpark();
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for CallbackWorker<T> {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            // A handler dropping its own session must not join itself
+            if worker.thread().id() != thread::current().id() {
+                worker.thread().unpark();
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+/// A callback output with its CoreFoundation object retained while it waits
+/// to be delivered.
+struct Retained<T> {
+    output: T,
+    object: CFTypeRef,
+}
+
+// SAFETY: the retained object keeps the output's buffer alive, and sample
+// and image buffers may be used from any thread.
+unsafe impl<T> Send for Retained<T> {}
+
+impl<T> Retained<T> {
+    fn new(output: T, object: CFTypeRef) -> Self {
+        if !object.is_null() {
+            unsafe { CFRetain(object) };
+        }
+        Self { output, object }
+    }
+}
+
+impl<T> Drop for Retained<T> {
+    fn drop(&mut self) {
+        if !self.object.is_null() {
+            unsafe { CFRelease(self.object) };
+        }
+    }
+}
+
+/// Wrap `callback` to run on `target`. `object` returns the buffer an
+/// output refers to (or null), which is retained until it is delivered.
+pub(crate) fn offload<T, F>(
+    target: CallbackTarget,
+    name: &'static str,
+    object: fn(&T) -> CFTypeRef,
+    callback: F,
+) -> Box<dyn Fn(T) + Send + Sync>
+where
+    T: Copy + 'static,
+    F: Fn(T) + Send + Sync + 'static,
+{
+    match target {
+        CallbackTarget::Inline => Box::new(callback),
+        CallbackTarget::Worker { capacity } => {
+            let worker = CallbackWorker::spawn(name, capacity, move |retained: Retained<T>| {
+                callback(retained.output)
+            });
+            Box::new(move |output| worker.send(Retained::new(output, object(&output))))
+        }
+        CallbackTarget::DispatchQueue(queue) => {
+            let callback: Arc<dyn Fn(T) + Send + Sync> = Arc::new(callback);
+            Box::new(move |output| {
+                let work = Box::new(Dispatched {
+                    output: Retained::new(output, object(&output)),
+                    callback: callback.clone(),
+                    name,
+                });
+                unsafe {
+                    dispatch_async_f(
+                        queue.as_raw(),
+                        Box::into_raw(work) as *mut c_void,
+                        run_dispatched::<T>,
+                    )
+                };
+            })
+        }
+    }
+}
+
+struct Dispatched<T> {
+    output: Retained<T>,
+    callback: Arc<dyn Fn(T) + Send + Sync>,
+    name: &'static str,
+}
+
+extern "C" fn run_dispatched<T: Copy>(context: *mut c_void) {
+    let work = unsafe { Box::from_raw(context as *mut Dispatched<T>) };
+    catch_callback_panic(work.name, || (work.callback)(work.output.output));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_spsc_queue_wraps_around() {
+        let queue = SpscQueue::new(2);
+        unsafe {
+            for round in 0..3 {
+                assert!(queue.push(round * 2).is_ok());
+                assert!(queue.push(round * 2 + 1).is_ok());
+                assert_eq!(queue.push(99), Err(99));
+                assert_eq!(queue.pop(), Some(round * 2));
+                assert_eq!(queue.pop(), Some(round * 2 + 1));
+                assert_eq!(queue.pop(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_worker_delivers_in_order_and_drains_on_drop() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let worker = CallbackWorker::spawn("test-worker", 4, move |n: u32| {
+            if n == 5 {
+                panic!("handler failed");
+            }
+            sink.lock().unwrap().push(n);
+        });
+        // More items than the queue holds: senders wait for the worker
+        for n in 0..100 {
+            worker.send(n);
+        }
+        drop(worker);
+
+        let expected: Vec<u32> = (0..100).filter(|&n| n != 5).collect();
+        assert_eq!(*received.lock().unwrap(), expected);
+    }
+}
@@ -0,0 +1,556 @@
+//! Camera enumeration and capture session setup.
+//!
+//! [`list_video_devices`] reports every camera with the resolutions, frame
+//! rates and native pixel formats it supports, and a
+//! [`CaptureSessionBuilder`] opens one of them in a chosen format instead of
+//! the default camera at a fixed session preset.
+
+use core_foundation::base::TCFType;
+use core_foundation_sys::base::OSStatus;
+use objc2::rc::Retained;
+use objc2::runtime::AnyObject;
+use objc2_foundation::NSString;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::cf_dict::CFDictBuilder;
+use super::clock::make_time;
+use super::delegate::{CaptureDelegate, DelegateCallback};
+use super::events::{emit, PipelineEvent};
+use super::input_format::{negotiate_input_format, InputPath};
+use crate::av_capture::{
+    AVCaptureDeviceFormat_formatDescription, AVCaptureDeviceFormat_videoSupportedFrameRateRanges,
+    AVCaptureDeviceInput_deviceInputWithDevice, AVCaptureDevice_defaultDeviceWithMediaType,
+    AVCaptureDevice_deviceWithUniqueID, AVCaptureDevice_devicesWithMediaType,
+    AVCaptureDevice_formats, AVCaptureDevice_localizedName, AVCaptureDevice_lockForConfiguration,
+    AVCaptureDevice_setActiveFormat, AVCaptureDevice_setActiveVideoMaxFrameDuration,
+    AVCaptureDevice_setActiveVideoMinFrameDuration, AVCaptureDevice_uniqueID,
+    AVCaptureDevice_unlockForConfiguration, AVCaptureSession_addInput, AVCaptureSession_addOutput,
+    AVCaptureSession_beginConfiguration, AVCaptureSession_commitConfiguration,
+    AVCaptureSession_isRunning, AVCaptureSession_new, AVCaptureSession_startRunning,
+    AVCaptureSession_stopRunning, AVCaptureVideoDataOutput_availableVideoCVPixelFormatTypes,
+    AVCaptureVideoDataOutput_new, AVCaptureVideoDataOutput_setAlwaysDiscardsLateVideoFrames,
+    AVCaptureVideoDataOutput_setVideoSettings, AVFrameRateRange_maxFrameRate,
+    AVFrameRateRange_minFrameDuration, AVFrameRateRange_minFrameRate, AVMediaTypeVideo,
+};
+use crate::base::EncodedCMTime;
+use crate::cm_sample_buffer::{
+    CMFormatDescriptionGetMediaSubType, CMVideoFormatDescriptionGetDimensions,
+};
+use crate::codecs;
+use crate::cv_types::kCVPixelBufferPixelFormatTypeKey;
+
+/// Frame rates within this distance of a range's bounds count as inside it,
+/// so 29.97 matches a 30000/1001 range
+const FRAME_RATE_TOLERANCE: f64 = 0.01;
+
+/// Distinguishes the delegate classes registered by each [`CaptureSession`]
+static DELEGATE_CLASS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A range of frame rates a camera format supports.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameRateRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl FrameRateRange {
+    pub fn contains(&self, frame_rate: f64) -> bool {
+        frame_rate >= self.min - FRAME_RATE_TOLERANCE
+            && frame_rate <= self.max + FRAME_RATE_TOLERANCE
+    }
+}
+
+/// One capture format of a camera.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraFormat {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate_ranges: Vec<FrameRateRange>,
+    /// Native pixel format (FourCC), e.g. `'420v'`; the session converts to
+    /// other output formats at some cost
+    pub pixel_format: u32,
+}
+
+impl CameraFormat {
+    pub fn supports_frame_rate(&self, frame_rate: f64) -> bool {
+        self.frame_rate_ranges
+            .iter()
+            .any(|range| range.contains(frame_rate))
+    }
+
+    pub fn max_frame_rate(&self) -> f64 {
+        self.frame_rate_ranges
+            .iter()
+            .map(|range| range.max)
+            .fold(0.0, f64::max)
+    }
+}
+
+/// A camera and the formats it can capture in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraInfo {
+    /// Stable identifier for [`CaptureSessionBuilder::device_id`]
+    pub unique_id: String,
+    pub name: String,
+    pub formats: Vec<CameraFormat>,
+}
+
+/// All connected cameras, including external and Continuity cameras.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::list_video_devices;
+///
+/// for camera in list_video_devices() {
+///     println!("{} ({})", camera.name, camera.unique_id);
+///     for format in &camera.formats {
+///         println!("  {}x{} up to {} fps", format.width, format.height, format.max_frame_rate());
+///     }
+/// }
+/// ```
+pub fn list_video_devices() -> Vec<CameraInfo> {
+    let devices = unsafe { AVCaptureDevice_devicesWithMediaType(AVMediaTypeVideo) };
+    devices.iter().map(|device| camera_info(&device)).collect()
+}
+
+fn camera_info(device: &AnyObject) -> CameraInfo {
+    unsafe {
+        CameraInfo {
+            unique_id: AVCaptureDevice_uniqueID(device).to_string(),
+            name: AVCaptureDevice_localizedName(device).to_string(),
+            formats: device_formats(device)
+                .into_iter()
+                .map(|(_, format)| format)
+                .collect(),
+        }
+    }
+}
+
+/// The device's AVCaptureDeviceFormats with their descriptions.
+unsafe fn device_formats(device: &AnyObject) -> Vec<(Retained<AnyObject>, CameraFormat)> {
+    AVCaptureDevice_formats(device)
+        .iter()
+        .map(|format| {
+            let description = AVCaptureDeviceFormat_formatDescription(&format);
+            let dimensions = CMVideoFormatDescriptionGetDimensions(description);
+            let frame_rate_ranges = AVCaptureDeviceFormat_videoSupportedFrameRateRanges(&format)
+                .iter()
+                .map(|range| FrameRateRange {
+                    min: AVFrameRateRange_minFrameRate(&range),
+                    max: AVFrameRateRange_maxFrameRate(&range),
+                })
+                .collect();
+            let info = CameraFormat {
+                width: dimensions.width as u32,
+                height: dimensions.height as u32,
+                frame_rate_ranges,
+                pixel_format: CMFormatDescriptionGetMediaSubType(description),
+            };
+            (format, info)
+        })
+        .collect()
+}
+
+/// Errors from [`CaptureSessionBuilder::build`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureError {
+    /// No camera with the requested ID, or no camera at all
+    NoDevice,
+    /// The camera has no format with the requested resolution and frame rate
+    UnsupportedFormat,
+    /// The camera could not be opened or configured (NSError code), e.g.
+    /// because access was denied
+    Device(OSStatus),
+    /// The session rejected the camera input or the video output
+    SessionRejected,
+    /// The sample buffer delegate could not be created
+    Delegate(&'static str),
+}
+
+impl std::fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureError::NoDevice => write!(f, "Camera not found"),
+            CaptureError::UnsupportedFormat => {
+                write!(f, "Camera does not support the requested format")
+            }
+            CaptureError::Device(code) => write!(f, "Failed to configure camera: error {}", code),
+            CaptureError::SessionRejected => write!(f, "Capture session rejected input or output"),
+            CaptureError::Delegate(message) => write!(f, "Failed to create delegate: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// Pick the format best matching the request: the requested resolution and
+/// frame rate if given, preferring native pixel formats early in `preferred`,
+/// then the largest resolution and the highest frame rate.
+fn select_format(
+    formats: &[CameraFormat],
+    resolution: Option<(u32, u32)>,
+    frame_rate: Option<f64>,
+    preferred: &[u32],
+) -> Option<usize> {
+    formats
+        .iter()
+        .enumerate()
+        .filter(|(_, format)| {
+            resolution.is_none_or(|size| (format.width, format.height) == size)
+                && frame_rate.is_none_or(|rate| format.supports_frame_rate(rate))
+        })
+        .max_by_key(|(_, format)| {
+            (
+                preferred
+                    .iter()
+                    .position(|&pixel_format| pixel_format == format.pixel_format)
+                    .map(|position| preferred.len() - position),
+                format.width as u64 * format.height as u64,
+                (format.max_frame_rate() * 1000.0) as u64,
+            )
+        })
+        .map(|(index, _)| index)
+}
+
+/// Frame duration for `frame_rate`, exact when it is a range's maximum
+/// (e.g. 1001/30000 for 29.97 fps).
+unsafe fn frame_duration(format: &AnyObject, frame_rate: f64) -> EncodedCMTime {
+    for range in AVCaptureDeviceFormat_videoSupportedFrameRateRanges(format).iter() {
+        if (AVFrameRateRange_maxFrameRate(&range) - frame_rate).abs() < FRAME_RATE_TOLERANCE {
+            return AVFrameRateRange_minFrameDuration(&range);
+        }
+    }
+    make_time(1000, (frame_rate * 1000.0).round() as i32).into()
+}
+
+/// Opens a camera in a specific format and delivers its frames to a
+/// [`DelegateCallback`].
+///
+/// Without a [`device_id`](Self::device_id) the default camera is used;
+/// without a [`resolution`](Self::resolution) or
+/// [`frame_rate`](Self::frame_rate), the largest and fastest format is
+/// chosen. Formats whose native pixel format matches the requested output
+/// [`pixel_format`](Self::pixel_format) are preferred, or with
+/// [`negotiate_input`](Self::negotiate_input), ones the encoder takes as is.
+///
+/// # Example
+///
+/// ```no_run
+/// use libc::c_void;
+/// use objc2::runtime::Sel;
+/// use video_toolbox_sys::helpers::{list_video_devices, CaptureSessionBuilder};
+///
+/// extern "C-unwind" fn on_frame(
+///     _this: *mut c_void,
+///     _cmd: Sel,
+///     _output: *mut c_void,
+///     sample_buffer: *mut c_void,
+///     _connection: *mut c_void,
+/// ) {
+///     // encode the sample buffer's pixel buffer
+/// }
+///
+/// let camera = list_video_devices().into_iter().next().expect("no camera");
+/// let mut session = CaptureSessionBuilder::new()
+///     .device_id(&camera.unique_id)
+///     .resolution(1920, 1080)
+///     .frame_rate(60.0)
+///     .build(on_frame)?;
+/// session.start();
+/// # Ok::<(), video_toolbox_sys::helpers::CaptureError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct CaptureSessionBuilder {
+    device_id: Option<String>,
+    resolution: Option<(u32, u32)>,
+    frame_rate: Option<f64>,
+    pixel_format: u32,
+    /// Source formats of the encoder to negotiate the output format with
+    encoder_formats: Option<Vec<u32>>,
+    discard_late_frames: bool,
+}
+
+impl Default for CaptureSessionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CaptureSessionBuilder {
+    /// Capture from the default camera in NV12 (the encoder's native input),
+    /// discarding frames that arrive while the callback is still busy.
+    pub fn new() -> Self {
+        Self {
+            device_id: None,
+            resolution: None,
+            frame_rate: None,
+            pixel_format: codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE,
+            encoder_formats: None,
+            discard_late_frames: true,
+        }
+    }
+
+    /// Capture from the camera with this [`CameraInfo::unique_id`].
+    pub fn device_id(mut self, unique_id: &str) -> Self {
+        self.device_id = Some(unique_id.to_string());
+        self
+    }
+
+    pub fn resolution(mut self, width: u32, height: u32) -> Self {
+        self.resolution = Some((width, height));
+        self
+    }
+
+    pub fn frame_rate(mut self, frame_rate: f64) -> Self {
+        self.frame_rate = Some(frame_rate);
+        self
+    }
+
+    /// Pixel format (FourCC) of the delivered buffers.
+    pub fn pixel_format(mut self, pixel_format: u32) -> Self {
+        self.pixel_format = pixel_format;
+        self
+    }
+
+    /// Choose the delivered pixel format instead of
+    /// [`pixel_format`](Self::pixel_format): the camera's native format if
+    /// the encoder takes it, else one of `encoder_formats` (best first, e.g.
+    /// [`ENCODER_INPUT_FORMATS`](super::ENCODER_INPUT_FORMATS)) the capture
+    /// output converts to. The outcome is [`CaptureSession::input_path`],
+    /// also reported as [`PipelineEvent::InputFormatNegotiated`].
+    pub fn negotiate_input(mut self, encoder_formats: &[u32]) -> Self {
+        self.encoder_formats = Some(encoder_formats.to_vec());
+        self
+    }
+
+    pub fn discard_late_frames(mut self, discard: bool) -> Self {
+        self.discard_late_frames = discard;
+        self
+    }
+
+    /// Open the camera and configure the session. Frames are delivered to
+    /// `callback` on a dedicated queue once the session is
+    /// [started](CaptureSession::start).
+    pub fn build(&self, callback: DelegateCallback) -> Result<CaptureSession, CaptureError> {
+        unsafe {
+            let device = match &self.device_id {
+                Some(id) => AVCaptureDevice_deviceWithUniqueID(&NSString::from_str(id)),
+                None => AVCaptureDevice_defaultDeviceWithMediaType(AVMediaTypeVideo),
+            }
+            .ok_or(CaptureError::NoDevice)?;
+            let mut formats = device_formats(&device);
+            let infos: Vec<CameraFormat> =
+                formats.iter().map(|(_, format)| format.clone()).collect();
+            let preferred = match &self.encoder_formats {
+                Some(encoder_formats) => encoder_formats.as_slice(),
+                None => std::slice::from_ref(&self.pixel_format),
+            };
+            let index = select_format(&infos, self.resolution, self.frame_rate, preferred)
+                .ok_or(CaptureError::UnsupportedFormat)?;
+            let (format, info) = formats.swap_remove(index);
+
+            let session = AVCaptureSession_new();
+            AVCaptureSession_beginConfiguration(&session);
+            let result = self.configure(&session, &device, &format, info.pixel_format, callback);
+            AVCaptureSession_commitConfiguration(&session);
+            let configured = result?;
+            Ok(CaptureSession {
+                session,
+                _input: configured.input,
+                _output: configured.output,
+                _delegate: configured.delegate,
+                format: info,
+                input_path: configured.input_path,
+            })
+        }
+    }
+
+    unsafe fn configure(
+        &self,
+        session: &AnyObject,
+        device: &AnyObject,
+        format: &AnyObject,
+        native_format: u32,
+        callback: DelegateCallback,
+    ) -> Result<Configured, CaptureError> {
+        let input =
+            AVCaptureDeviceInput_deviceInputWithDevice(device).map_err(CaptureError::Device)?;
+        if !AVCaptureSession_addInput(session, &input) {
+            return Err(CaptureError::SessionRejected);
+        }
+
+        // Setting the active format after adding the input switches the
+        // session to the input priority preset, so it is not overridden
+        AVCaptureDevice_lockForConfiguration(device).map_err(CaptureError::Device)?;
+        AVCaptureDevice_setActiveFormat(device, format);
+        if let Some(frame_rate) = self.frame_rate {
+            let duration = frame_duration(format, frame_rate);
+            AVCaptureDevice_setActiveVideoMinFrameDuration(device, duration);
+            AVCaptureDevice_setActiveVideoMaxFrameDuration(device, duration);
+        }
+        AVCaptureDevice_unlockForConfiguration(device);
+
+        let output = AVCaptureVideoDataOutput_new();
+        let input_path = match &self.encoder_formats {
+            Some(encoder_formats) => {
+                let capture_formats: Vec<u32> =
+                    AVCaptureVideoDataOutput_availableVideoCVPixelFormatTypes(&output)
+                        .iter()
+                        .map(|format| format.as_u32())
+                        .collect();
+                let path = negotiate_input_format(native_format, &capture_formats, encoder_formats);
+                emit(PipelineEvent::InputFormatNegotiated { path });
+                path
+            }
+            None if native_format == self.pixel_format => InputPath::Native {
+                format: native_format,
+            },
+            None => InputPath::CaptureConversion {
+                native: native_format,
+                format: self.pixel_format,
+            },
+        };
+        let settings = CFDictBuilder::new()
+            .value(
+                kCVPixelBufferPixelFormatTypeKey,
+                input_path.capture_format(),
+            )
+            .build();
+        // CFDictionary is toll-free bridged to NSDictionary
+        let settings_object = &*(settings.as_concrete_TypeRef() as *const AnyObject);
+        AVCaptureVideoDataOutput_setVideoSettings(&output, settings_object);
+        AVCaptureVideoDataOutput_setAlwaysDiscardsLateVideoFrames(
+            &output,
+            self.discard_late_frames,
+        );
+
+        let index = DELEGATE_CLASS_COUNT.fetch_add(1, Ordering::Relaxed);
+        let delegate = CaptureDelegate::new_video(&format!("VTCaptureSession{}", index), callback)
+            .map_err(CaptureError::Delegate)?;
+        delegate.attach_to(&*output as *const AnyObject as *const _);
+        if !AVCaptureSession_addOutput(session, &output) {
+            return Err(CaptureError::SessionRejected);
+        }
+        Ok(Configured {
+            input,
+            output,
+            delegate,
+            input_path,
+        })
+    }
+}
+
+/// The objects a configured session refers to.
+struct Configured {
+    input: Retained<AnyObject>,
+    output: Retained<AnyObject>,
+    delegate: CaptureDelegate,
+    input_path: InputPath,
+}
+
+/// A configured camera capture session from a [`CaptureSessionBuilder`].
+///
+/// The session stops when dropped.
+pub struct CaptureSession {
+    session: Retained<AnyObject>,
+    // Kept alive while the session refers to them
+    _input: Retained<AnyObject>,
+    _output: Retained<AnyObject>,
+    _delegate: CaptureDelegate,
+    format: CameraFormat,
+    input_path: InputPath,
+}
+
+// AVCaptureSession may be started and stopped from any thread.
+unsafe impl Send for CaptureSession {}
+
+impl CaptureSession {
+    /// Start capturing; blocks until frames are flowing.
+    pub fn start(&mut self) {
+        unsafe { AVCaptureSession_startRunning(&self.session) };
+    }
+
+    pub fn stop(&mut self) {
+        unsafe { AVCaptureSession_stopRunning(&self.session) };
+    }
+
+    pub fn is_running(&self) -> bool {
+        unsafe { AVCaptureSession_isRunning(&self.session) }
+    }
+
+    /// The camera format selected for the session.
+    pub fn format(&self) -> &CameraFormat {
+        &self.format
+    }
+
+    /// How frames get from the camera's native format to the delivered one;
+    /// its [`encoder_format`](InputPath::encoder_format) is the source format
+    /// to build the compression session with.
+    pub fn input_path(&self) -> InputPath {
+        self.input_path
+    }
+
+    /// The underlying AVCaptureSession, e.g. to add an audio input.
+    pub fn as_raw(&self) -> &AnyObject {
+        &self.session
+    }
+}
+
+impl Drop for CaptureSession {
+    fn drop(&mut self) {
+        if self.is_running() {
+            self.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(width: u32, height: u32, max_fps: f64, pixel_format: u32) -> CameraFormat {
+        CameraFormat {
+            width,
+            height,
+            frame_rate_ranges: vec![FrameRateRange {
+                min: 1.0,
+                max: max_fps,
+            }],
+            pixel_format,
+        }
+    }
+
+    #[test]
+    fn test_select_format() {
+        let nv12 = codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE;
+        let yuvs = 0x79757673;
+        let formats = [
+            format(1280, 720, 30.0, yuvs),
+            format(1280, 720, 60.0, nv12),
+            format(1920, 1080, 29.97, nv12),
+            format(1920, 1080, 30.0, yuvs),
+        ];
+        assert_eq!(select_format(&formats, None, None, &[nv12]), Some(2));
+        assert_eq!(select_format(&formats, None, None, &[yuvs]), Some(3));
+        // Earlier preferred formats win over larger ones
+        let bgra = codecs::pixel::BGRA32;
+        assert_eq!(
+            select_format(&formats, None, None, &[bgra, yuvs, nv12]),
+            Some(3)
+        );
+        assert_eq!(
+            select_format(&formats, Some((1280, 720)), None, &[yuvs]),
+            Some(0)
+        );
+        assert_eq!(select_format(&formats, None, Some(60.0), &[yuvs]), Some(1));
+        assert_eq!(
+            select_format(&formats, Some((1920, 1080)), Some(29.97), &[nv12]),
+            Some(2)
+        );
+        assert_eq!(
+            select_format(&formats, Some((3840, 2160)), None, &[nv12]),
+            None
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Typed CFDictionary construction for encoder specifications, pixel buffer
+//! attributes and other option dictionaries.
+
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::data::CFData;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_foundation_sys::dictionary::CFDictionaryRef;
+use core_foundation_sys::string::CFStringRef;
+
+/// A dictionary key: a framework key constant, or a string.
+pub trait DictKey {
+    fn to_cf_string(&self) -> CFString;
+}
+
+/// Framework constants such as `kCVPixelBufferWidthKey`. The reference must
+/// be a valid CFString (reading the extern static already requires `unsafe`).
+impl DictKey for CFStringRef {
+    fn to_cf_string(&self) -> CFString {
+        assert!(!self.is_null(), "null CFStringRef dictionary key");
+        unsafe { CFString::wrap_under_get_rule(*self) }
+    }
+}
+
+impl DictKey for CFString {
+    fn to_cf_string(&self) -> CFString {
+        self.clone()
+    }
+}
+
+impl DictKey for str {
+    fn to_cf_string(&self) -> CFString {
+        CFString::new(self)
+    }
+}
+
+impl DictKey for String {
+    fn to_cf_string(&self) -> CFString {
+        CFString::new(self)
+    }
+}
+
+impl<K: DictKey + ?Sized> DictKey for &K {
+    fn to_cf_string(&self) -> CFString {
+        (**self).to_cf_string()
+    }
+}
+
+/// A value that can be stored in a dictionary built by [`CFDictBuilder`].
+pub trait DictValue {
+    fn to_cf_type(&self) -> CFType;
+}
+
+impl DictValue for bool {
+    fn to_cf_type(&self) -> CFType {
+        CFBoolean::from(*self).as_CFType()
+    }
+}
+
+impl DictValue for i32 {
+    fn to_cf_type(&self) -> CFType {
+        CFNumber::from(*self).as_CFType()
+    }
+}
+
+impl DictValue for u32 {
+    /// Stored as a 64-bit number, so values above `i32::MAX` stay positive.
+    fn to_cf_type(&self) -> CFType {
+        CFNumber::from(*self as i64).as_CFType()
+    }
+}
+
+impl DictValue for i64 {
+    fn to_cf_type(&self) -> CFType {
+        CFNumber::from(*self).as_CFType()
+    }
+}
+
+impl DictValue for f64 {
+    fn to_cf_type(&self) -> CFType {
+        CFNumber::from(*self).as_CFType()
+    }
+}
+
+impl DictValue for str {
+    fn to_cf_type(&self) -> CFType {
+        CFString::new(self).as_CFType()
+    }
+}
+
+impl DictValue for String {
+    fn to_cf_type(&self) -> CFType {
+        CFString::new(self).as_CFType()
+    }
+}
+
+/// Stored as CFData.
+impl DictValue for [u8] {
+    fn to_cf_type(&self) -> CFType {
+        CFData::from_buffer(self).as_CFType()
+    }
+}
+
+impl DictValue for Vec<u8> {
+    fn to_cf_type(&self) -> CFType {
+        CFData::from_buffer(self).as_CFType()
+    }
+}
+
+impl<K, V> DictValue for CFDictionary<K, V> {
+    fn to_cf_type(&self) -> CFType {
+        self.as_CFType()
+    }
+}
+
+impl DictValue for CFType {
+    fn to_cf_type(&self) -> CFType {
+        self.clone()
+    }
+}
+
+impl DictValue for CFString {
+    fn to_cf_type(&self) -> CFType {
+        self.as_CFType()
+    }
+}
+
+impl DictValue for CFNumber {
+    fn to_cf_type(&self) -> CFType {
+        self.as_CFType()
+    }
+}
+
+impl<V: DictValue + ?Sized> DictValue for &V {
+    fn to_cf_type(&self) -> CFType {
+        (**self).to_cf_type()
+    }
+}
+
+/// Builds a `CFDictionary` of string keys to CF values.
+///
+/// Later insertions of the same key replace earlier ones. The [`cfdict!`](crate::cfdict)
+/// macro is shorthand for the common case of a fixed set of entries.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::compression::kVTVideoEncoderSpecification_EncoderID;
+/// use video_toolbox_sys::helpers::CFDictBuilder;
+/// # let encoder_id: Option<String> = None;
+///
+/// let mut builder = CFDictBuilder::new().bool("EnableHardwareAcceleratedVideoEncoder", true);
+/// if let Some(id) = &encoder_id {
+///     builder.insert(unsafe { kVTVideoEncoderSpecification_EncoderID }, id.as_str());
+/// }
+/// let specification = builder.build();
+/// ```
+#[derive(Clone, Default)]
+pub struct CFDictBuilder {
+    pairs: Vec<(CFString, CFType)>,
+}
+
+impl CFDictBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert any [`DictValue`].
+    pub fn value(mut self, key: impl DictKey, value: impl DictValue) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    pub fn bool(self, key: impl DictKey, value: bool) -> Self {
+        self.value(key, value)
+    }
+
+    pub fn i32(self, key: impl DictKey, value: i32) -> Self {
+        self.value(key, value)
+    }
+
+    pub fn i64(self, key: impl DictKey, value: i64) -> Self {
+        self.value(key, value)
+    }
+
+    pub fn f64(self, key: impl DictKey, value: f64) -> Self {
+        self.value(key, value)
+    }
+
+    pub fn string(self, key: impl DictKey, value: &str) -> Self {
+        self.value(key, value)
+    }
+
+    /// Insert a nested dictionary.
+    pub fn dict(self, key: impl DictKey, value: CFDictBuilder) -> Self {
+        self.value(key, value.build())
+    }
+
+    /// Insert bytes as CFData.
+    pub fn data(self, key: impl DictKey, value: &[u8]) -> Self {
+        self.value(key, value)
+    }
+
+    /// Insert a value in place, for conditional entries.
+    pub fn insert(&mut self, key: impl DictKey, value: impl DictValue) {
+        let key = key.to_cf_string();
+        let value = value.to_cf_type();
+        match self.pairs.iter_mut().find(|(existing, _)| *existing == key) {
+            Some(pair) => pair.1 = value,
+            None => self.pairs.push((key, value)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn build(&self) -> CFDictionary<CFString, CFType> {
+        CFDictionary::from_CFType_pairs(&self.pairs)
+    }
+}
+
+/// The raw reference of a built dictionary, for passing to the sys API. The
+/// dictionary must outlive the call it is passed to.
+pub fn as_dictionary_ref<K, V>(dictionary: &CFDictionary<K, V>) -> CFDictionaryRef {
+    dictionary.as_concrete_TypeRef()
+}
+
+/// Build a `CFDictionary<CFString, CFType>` from `key => value` entries.
+///
+/// Keys are framework key constants (`CFStringRef`) or strings; values are
+/// anything implementing [`DictValue`]: `bool`, integers, `f64`, strings,
+/// byte slices (as CFData), CF objects, or nested `cfdict!`s. Reading
+/// framework constants requires the invocation to be inside `unsafe`.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::cfdict;
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::compression::kVTVideoEncoderSpecification_EnableHardwareAcceleratedVideoEncoder;
+///
+/// let specification = unsafe {
+///     cfdict! {
+///         kVTVideoEncoderSpecification_EnableHardwareAcceleratedVideoEncoder => true,
+///     }
+/// };
+/// let attributes = cfdict! {
+///     "PixelFormatType" => codecs::pixel::NV12,
+///     "Width" => 1920,
+///     "Height" => 1080,
+///     "IOSurfaceProperties" => cfdict! {},
+/// };
+/// ```
+#[macro_export]
+macro_rules! cfdict {
+    () => {
+        $crate::helpers::CFDictBuilder::new().build()
+    };
+    ($($key:expr => $value:expr),+ $(,)?) => {
+        $crate::helpers::CFDictBuilder::new()
+            $(.value($key, $value))+
+            .build()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_types_and_replacement() {
+        let inner = cfdict! { "Depth" => 2 };
+        let mut builder = CFDictBuilder::new()
+            .bool("Enabled", true)
+            .i32("Width", 1920)
+            .i64("Bitrate", 8_000_000_000)
+            .f64("Rate", 29.97)
+            .string("Name", "camera")
+            .data("Blob", &[1, 2, 3])
+            .value("Inner", inner);
+        builder.insert("Width", 1280);
+        assert_eq!(builder.len(), 7);
+
+        let dict = builder.build();
+        let number = |key: &str| {
+            let value = dict.find(CFString::new(key)).unwrap();
+            value.downcast::<CFNumber>().unwrap()
+        };
+        assert_eq!(number("Width").to_i32(), Some(1280));
+        assert_eq!(number("Bitrate").to_i64(), Some(8_000_000_000));
+        assert_eq!(number("Rate").to_f64(), Some(29.97));
+        let name = dict.find(CFString::new("Name")).unwrap();
+        assert_eq!(name.downcast::<CFString>().unwrap().to_string(), "camera");
+        let blob = dict.find(CFString::new("Blob")).unwrap();
+        assert_eq!(blob.downcast::<CFData>().unwrap().bytes(), [1, 2, 3]);
+        assert!(dict.contains_key(&CFString::new("Inner")));
+        assert!(!as_dictionary_ref(&dict).is_null());
+    }
+}
@@ -0,0 +1,201 @@
+//! Audio channel layouts shared by capture settings, conversion and muxing.
+
+/// `kAudioChannelLayoutTag_Mono`
+const LAYOUT_TAG_MONO: u32 = (100 << 16) | 1;
+/// `kAudioChannelLayoutTag_Stereo`
+const LAYOUT_TAG_STEREO: u32 = (101 << 16) | 2;
+/// `kAudioChannelLayoutTag_MPEG_5_1_A`: L R C LFE Ls Rs
+const LAYOUT_TAG_MPEG_5_1_A: u32 = (121 << 16) | 6;
+
+/// -3 dB, the ITU-R BS.775 gain for center and surround channels in a downmix
+const MINUS_3_DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Speaker layout of interleaved audio.
+///
+/// Channels are interleaved in Core Audio/MPEG order: `L R` for stereo and
+/// `L R C LFE Ls Rs` for 5.1 (`kAudioChannelLayoutTag_MPEG_5_1_A`), the order
+/// AVFoundation capture and the AAC encoder use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ChannelLayout {
+    #[default]
+    Mono,
+    Stereo,
+    /// 5.1 surround
+    Surround51,
+}
+
+/// Opus channel mapping (RFC 7845 section 5.1.1) for a layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusChannelMapping {
+    /// 0 for mono and stereo, 1 for surround
+    pub family: u8,
+    pub stream_count: u8,
+    pub coupled_count: u8,
+    /// Decoded stream channel for each output channel, in Vorbis order
+    pub mapping: &'static [u8],
+}
+
+impl ChannelLayout {
+    /// The layout for a channel count: 1, 2 or 6.
+    pub fn from_channel_count(channels: u32) -> Option<Self> {
+        match channels {
+            1 => Some(Self::Mono),
+            2 => Some(Self::Stereo),
+            6 => Some(Self::Surround51),
+            _ => None,
+        }
+    }
+
+    pub const fn channel_count(&self) -> u32 {
+        match self {
+            Self::Mono => 1,
+            Self::Stereo => 2,
+            Self::Surround51 => 6,
+        }
+    }
+
+    /// `AudioChannelLayoutTag` for Core Audio and AVFoundation.
+    pub fn audio_channel_layout_tag(&self) -> u32 {
+        match self {
+            Self::Mono => LAYOUT_TAG_MONO,
+            Self::Stereo => LAYOUT_TAG_STEREO,
+            Self::Surround51 => LAYOUT_TAG_MPEG_5_1_A,
+        }
+    }
+
+    /// An `AudioChannelLayout` struct with no channel descriptions, in
+    /// native byte order: the value of `AVChannelLayoutKey` (as `NSData`)
+    /// in AVAssetWriter and AVAudioRecorder settings, which is required for
+    /// more than two channels.
+    pub fn audio_channel_layout(&self) -> Vec<u8> {
+        let mut layout = Vec::with_capacity(12);
+        layout.extend_from_slice(&self.audio_channel_layout_tag().to_ne_bytes());
+        layout.extend_from_slice(&0u32.to_ne_bytes()); // mChannelBitmap
+        layout.extend_from_slice(&0u32.to_ne_bytes()); // mNumberChannelDescriptions
+        layout
+    }
+
+    /// MPEG-4 Audio `channelConfiguration` for the AudioSpecificConfig.
+    pub fn aac_channel_configuration(&self) -> u8 {
+        match self {
+            Self::Mono => 1,
+            Self::Stereo => 2,
+            Self::Surround51 => 6,
+        }
+    }
+
+    /// ISO/IEC 23001-8 `ChannelConfiguration`, used by the `chnl` box.
+    pub fn iso_channel_configuration(&self) -> u8 {
+        // Same values as MPEG-4 Audio for these layouts
+        self.aac_channel_configuration()
+    }
+
+    /// Channel mapping for the `dOps` box, assuming the stream layout of
+    /// libopus's multistream surround encoder.
+    pub fn opus_mapping(&self) -> OpusChannelMapping {
+        match self {
+            Self::Mono => OpusChannelMapping {
+                family: 0,
+                stream_count: 1,
+                coupled_count: 0,
+                mapping: &[0],
+            },
+            Self::Stereo => OpusChannelMapping {
+                family: 0,
+                stream_count: 1,
+                coupled_count: 1,
+                mapping: &[0, 1],
+            },
+            Self::Surround51 => OpusChannelMapping {
+                family: 1,
+                stream_count: 4,
+                coupled_count: 2,
+                mapping: &[0, 4, 1, 2, 3, 5],
+            },
+        }
+    }
+
+    /// Row-major `output x input` gains mixing this layout into `output`.
+    ///
+    /// 5.1 is downmixed as in ITU-R BS.775 (center and surrounds at -3 dB,
+    /// LFE dropped), scaled so a full-scale input cannot clip. Upmixes
+    /// place the input on the front channels, or the center for mono into
+    /// 5.1.
+    pub fn mix_matrix(&self, output: ChannelLayout) -> Vec<f32> {
+        use ChannelLayout::*;
+        const G: f32 = MINUS_3_DB;
+        let downmix_scale = 1.0 / (1.0 + 2.0 * G);
+        match (self, output) {
+            (a, b) if *a == b => {
+                let n = a.channel_count() as usize;
+                (0..n * n)
+                    .map(|i| if i / n == i % n { 1.0 } else { 0.0 })
+                    .collect()
+            }
+            (Mono, Stereo) => vec![1.0, 1.0],
+            (Stereo, Mono) => vec![0.5, 0.5],
+            (Mono, Surround51) => vec![0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+            #[rustfmt::skip]
+            (Stereo, Surround51) => vec![
+                1.0, 0.0,
+                0.0, 1.0,
+                0.0, 0.0,
+                0.0, 0.0,
+                0.0, 0.0,
+                0.0, 0.0,
+            ],
+            #[rustfmt::skip]
+            (Surround51, Stereo) => [
+                1.0, 0.0, G, 0.0, G, 0.0,
+                0.0, 1.0, G, 0.0, 0.0, G,
+            ]
+            .iter()
+            .map(|g| g * downmix_scale)
+            .collect(),
+            (Surround51, Mono) => [1.0, 1.0, 2.0 * G, 0.0, G, G]
+                .iter()
+                .map(|g| g * downmix_scale * 0.5)
+                .collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// ISO/IEC 14496-12 `chnl` (ChannelLayoutBox) payload for an audio
+    /// sample entry.
+    pub(crate) fn chnl_payload(&self) -> Vec<u8> {
+        let mut chnl = vec![0, 0, 0, 0]; // version + flags
+        chnl.push(1); // stream_structure: channel structured
+        chnl.push(self.iso_channel_configuration()); // definedLayout
+        chnl.extend_from_slice(&0u64.to_be_bytes()); // omittedChannelsMap
+        chnl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downmix_keeps_lfe_out_and_does_not_clip() {
+        let matrix = ChannelLayout::Surround51.mix_matrix(ChannelLayout::Stereo);
+        assert_eq!(matrix.len(), 12);
+        // LFE (input 3) feeds neither output; right surround only feeds right
+        assert_eq!((matrix[3], matrix[9]), (0.0, 0.0));
+        assert_eq!(matrix[5], 0.0);
+        for row in matrix.chunks(6) {
+            assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        }
+        assert_eq!(
+            ChannelLayout::Stereo.mix_matrix(ChannelLayout::Stereo),
+            [1.0, 0.0, 0.0, 1.0]
+        );
+        assert_eq!(
+            ChannelLayout::from_channel_count(6),
+            Some(ChannelLayout::Surround51)
+        );
+        assert_eq!(
+            ChannelLayout::Surround51.audio_channel_layout_tag(),
+            0x0079_0006
+        );
+    }
+}
@@ -0,0 +1,480 @@
+//! Chapter markers for recorded MP4 files.
+
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use super::audio_cmaf::write_box;
+use super::metadata::{
+    add_to_size, child_boxes, insert_user_data, invalid, update_moov_with_media,
+};
+use super::mfra::{find_box, read_u32, read_u64, BoxRange};
+use super::timecode::{insert_track_reference, trak_id};
+use super::timed_metadata::{insert_trak, next_track_id};
+
+/// `chpl` start times are in 100 ns units
+const CHPL_TIMESCALE: u128 = 10_000_000;
+
+/// The chapter text track counts in milliseconds
+const CHAPTER_TIMESCALE: u32 = 1000;
+
+/// `encd` value marking a QuickTime text sample as UTF-8
+const TEXT_ENCODING_UTF8: u32 = 0x0000_0100;
+
+/// A named position in a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    /// Offset from the start of the file
+    pub start: Duration,
+    pub title: String,
+}
+
+impl Chapter {
+    pub fn new(start: Duration, title: impl Into<String>) -> Self {
+        Self {
+            start,
+            title: title.into(),
+        }
+    }
+}
+
+/// A Nero-style `chpl` box for a `moov/udta`, listing `chapters` by start
+/// time.
+///
+/// The format holds at most 255 chapters with titles of up to 255 bytes;
+/// later chapters are dropped and longer titles cut at a character
+/// boundary.
+pub fn chpl_box(chapters: &[Chapter]) -> Vec<u8> {
+    let mut sorted = sorted(chapters);
+    sorted.truncate(u8::MAX as usize);
+
+    let mut content = vec![1, 0, 0, 0]; // version 1, flags
+    content.extend_from_slice(&0u32.to_be_bytes()); // reserved
+    content.push(sorted.len() as u8);
+    for chapter in sorted {
+        let start = chapter.start.as_nanos() * CHPL_TIMESCALE / 1_000_000_000;
+        content.extend_from_slice(&(start.min(u64::MAX as u128) as u64).to_be_bytes());
+        let title = truncate_utf8(&chapter.title, u8::MAX as usize);
+        content.push(title.len() as u8);
+        content.extend_from_slice(title.as_bytes());
+    }
+    let mut buf = Vec::new();
+    write_box(&mut buf, b"chpl", &content);
+    buf
+}
+
+fn sorted(chapters: &[Chapter]) -> Vec<&Chapter> {
+    let mut sorted: Vec<&Chapter> = chapters.iter().collect();
+    sorted.sort_by_key(|chapter| chapter.start);
+    sorted
+}
+
+/// Add a QuickTime chapter track listing `chapters` to `moov`, replacing
+/// earlier chapter tracks. Returns the `mdat` holding its samples, one
+/// text sample per title, which must be written at file offset
+/// `media_offset`.
+///
+/// The track is a disabled text track referenced through `tref`/`chap` by
+/// the first video track (or the first track). The first chapter also
+/// covers any time before it, and the last one runs to the end of the
+/// movie. Returns `None` for a malformed `moov`.
+pub(super) fn insert_chapter_track(
+    moov: &mut Vec<u8>,
+    chapters: &[Chapter],
+    media_offset: u64,
+) -> Option<Vec<u8>> {
+    remove_chapter_tracks(moov)?;
+    if chapters.is_empty() {
+        return Some(Vec::new());
+    }
+    let root = find_box(moov, 0, moov.len(), b"moov")?;
+    let (timescale, duration) = movie_duration(moov, root)?;
+    let movie_ms = duration as u128 * CHAPTER_TIMESCALE as u128 / timescale.max(1) as u128;
+
+    let sorted = sorted(chapters);
+    let starts: Vec<u64> = sorted
+        .iter()
+        .map(|chapter| chapter.start.as_millis().min(u64::MAX as u128) as u64)
+        .collect();
+    let last = *starts.last()?;
+    let end = (movie_ms.min(u64::MAX as u128) as u64).max(last.saturating_add(1));
+    let durations: Vec<u32> = (0..starts.len())
+        .map(|i| {
+            let from = if i == 0 { 0 } else { starts[i] };
+            let to = starts.get(i + 1).copied().unwrap_or(end);
+            to.saturating_sub(from).min(u32::MAX as u64) as u32
+        })
+        .collect();
+
+    let mut data = Vec::new();
+    let mut sizes = Vec::with_capacity(sorted.len());
+    for chapter in &sorted {
+        let start = data.len();
+        let title = truncate_utf8(&chapter.title, u16::MAX as usize);
+        data.extend_from_slice(&(title.len() as u16).to_be_bytes());
+        data.extend_from_slice(title.as_bytes());
+        write_box(&mut data, b"encd", &TEXT_ENCODING_UTF8.to_be_bytes());
+        sizes.push((data.len() - start) as u32);
+    }
+    let mut mdat = Vec::new();
+    write_box(&mut mdat, b"mdat", &data);
+
+    let referencing = child_boxes(moov, root, b"trak")
+        .into_iter()
+        .find(|&trak| handler_type(moov, trak) == Some(*b"vide"))
+        .or_else(|| find_box(moov, root.content(), root.end(), b"trak"))
+        .and_then(|trak| trak_id(moov, trak))?;
+    let track_id = next_track_id(moov)?;
+    let total_ms: u64 = durations.iter().map(|&d| d as u64).sum();
+    let track = ChapterTrack {
+        track_id,
+        movie_duration: (total_ms as u128 * timescale as u128 / CHAPTER_TIMESCALE as u128)
+            .min(u32::MAX as u128) as u32,
+        durations,
+        sizes,
+        chunk_offset: media_offset + 8,
+    };
+    insert_track_reference(moov, referencing, b"chap", track_id)?;
+    insert_trak(moov, track_id, &track.trak())?;
+    Some(mdat)
+}
+
+/// Timescale and duration of the movie from the `mvhd`, or from the
+/// `mvex`'s `mehd` for fragmented files, whose `mvhd` has no duration.
+fn movie_duration(moov: &[u8], root: BoxRange) -> Option<(u32, u64)> {
+    let mvhd = find_box(moov, root.content(), root.end(), b"mvhd")?;
+    let (timescale, duration) = match moov.get(mvhd.content())? {
+        1 => (
+            read_u32(moov, mvhd.content() + 20)?,
+            read_u64(moov, mvhd.content() + 24)?,
+        ),
+        _ => (
+            read_u32(moov, mvhd.content() + 12)?,
+            read_u32(moov, mvhd.content() + 16)? as u64,
+        ),
+    };
+    if duration != 0 {
+        return Some((timescale, duration));
+    }
+    let fragment_duration = find_box(moov, root.content(), root.end(), b"mvex")
+        .and_then(|mvex| find_box(moov, mvex.content(), mvex.end(), b"mehd"))
+        .and_then(|mehd| match moov.get(mehd.content())? {
+            1 => read_u64(moov, mehd.content() + 4),
+            _ => read_u32(moov, mehd.content() + 4).map(u64::from),
+        });
+    Some((timescale, fragment_duration.unwrap_or(0)))
+}
+
+/// The `handler_type` in the `mdia/hdlr` of `trak`.
+fn handler_type(data: &[u8], trak: BoxRange) -> Option<[u8; 4]> {
+    let mdia = find_box(data, trak.content(), trak.end(), b"mdia")?;
+    let hdlr = find_box(data, mdia.content(), mdia.end(), b"hdlr")?;
+    data.get(hdlr.content() + 8..hdlr.content() + 12)?
+        .try_into()
+        .ok()
+}
+
+/// Remove the tracks referenced through `tref`/`chap`, their `trex` and
+/// the references, dropping a `tref` left empty.
+fn remove_chapter_tracks(moov: &mut Vec<u8>) -> Option<()> {
+    let mut chapter_tracks = Vec::new();
+    loop {
+        let root = find_box(moov, 0, moov.len(), b"moov")?;
+        let Some((trak, tref, chap)) = chapter_reference(moov, root) else {
+            break;
+        };
+        chapter_tracks.extend(
+            (chap.content()..chap.end())
+                .step_by(4)
+                .filter_map(|at| read_u32(moov, at)),
+        );
+        let only_child = tref.content() == chap.offset && chap.end() == tref.end();
+        let removed = if only_child { tref } else { chap };
+        for range in [root, trak] {
+            add_to_size(moov, range, -(removed.size as isize));
+        }
+        if !only_child {
+            add_to_size(moov, tref, -(chap.size as isize));
+        }
+        moov.drain(removed.offset..removed.end());
+    }
+
+    for track_id in chapter_tracks {
+        let root = find_box(moov, 0, moov.len(), b"moov")?;
+        if let Some(trak) = child_boxes(moov, root, b"trak")
+            .into_iter()
+            .find(|&trak| trak_id(moov, trak) == Some(track_id))
+        {
+            add_to_size(moov, root, -(trak.size as isize));
+            moov.drain(trak.offset..trak.end());
+        }
+        let root = find_box(moov, 0, moov.len(), b"moov")?;
+        let Some(mvex) = find_box(moov, root.content(), root.end(), b"mvex") else {
+            continue;
+        };
+        if let Some(trex) = child_boxes(moov, mvex, b"trex")
+            .into_iter()
+            .find(|&trex| read_u32(moov, trex.content() + 4) == Some(track_id))
+        {
+            for range in [root, mvex] {
+                add_to_size(moov, range, -(trex.size as isize));
+            }
+            moov.drain(trex.offset..trex.end());
+        }
+    }
+    Some(())
+}
+
+/// The first `chap` reference in `moov`, with its `trak` and `tref`.
+fn chapter_reference(moov: &[u8], root: BoxRange) -> Option<(BoxRange, BoxRange, BoxRange)> {
+    child_boxes(moov, root, b"trak")
+        .into_iter()
+        .find_map(|trak| {
+            let tref = find_box(moov, trak.content(), trak.end(), b"tref")?;
+            let chap = find_box(moov, tref.content(), tref.end(), b"chap")?;
+            Some((trak, tref, chap))
+        })
+}
+
+/// A QuickTime chapter text track whose samples are in one chunk.
+struct ChapterTrack {
+    track_id: u32,
+    /// Track duration in the movie timescale
+    movie_duration: u32,
+    /// Sample durations in milliseconds
+    durations: Vec<u32>,
+    sizes: Vec<u32>,
+    chunk_offset: u64,
+}
+
+impl ChapterTrack {
+    fn trak(&self) -> Vec<u8> {
+        let mut content = Vec::new();
+
+        let mut tkhd = vec![0, 0, 0, 2]; // version + flags (in movie, disabled)
+        tkhd.extend_from_slice(&[0; 8]); // creation and modification time
+        tkhd.extend_from_slice(&self.track_id.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 4]); // reserved
+        tkhd.extend_from_slice(&self.movie_duration.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 8]); // reserved
+        tkhd.extend_from_slice(&[0; 8]); // layer, alternate_group, volume, reserved
+        for m in [0x00010000u32, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000] {
+            tkhd.extend_from_slice(&m.to_be_bytes());
+        }
+        tkhd.extend_from_slice(&[0; 8]); // width and height
+        write_box(&mut content, b"tkhd", &tkhd);
+
+        let mut mdia = Vec::new();
+        let total: u64 = self.durations.iter().map(|&d| d as u64).sum();
+        let mut mdhd = vec![0, 0, 0, 0]; // version + flags
+        mdhd.extend_from_slice(&[0; 8]); // creation and modification time
+        mdhd.extend_from_slice(&CHAPTER_TIMESCALE.to_be_bytes());
+        mdhd.extend_from_slice(&(total.min(u32::MAX as u64) as u32).to_be_bytes());
+        mdhd.extend_from_slice(&0x55c4u16.to_be_bytes()); // language (und)
+        mdhd.extend_from_slice(&[0; 2]); // pre_defined
+        write_box(&mut mdia, b"mdhd", &mdhd);
+
+        let mut hdlr = vec![0; 8]; // version + flags, pre_defined
+        hdlr.extend_from_slice(b"text");
+        hdlr.extend_from_slice(&[0; 12]); // reserved
+        hdlr.extend_from_slice(b"ChapterListHandler\0");
+        write_box(&mut mdia, b"hdlr", &hdlr);
+
+        let mut minf = Vec::new();
+        // QuickTime base media header with the text media's matrix
+        let mut gmin = vec![0, 0, 0, 0];
+        gmin.extend_from_slice(&0x40u16.to_be_bytes()); // graphics mode (copy)
+        for _ in 0..3 {
+            gmin.extend_from_slice(&0x8000u16.to_be_bytes()); // opcolor
+        }
+        gmin.extend_from_slice(&[0; 4]); // balance, reserved
+        let mut text = Vec::new();
+        for m in [0x00010000u32, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000] {
+            text.extend_from_slice(&m.to_be_bytes());
+        }
+        let mut gmhd = Vec::new();
+        write_box(&mut gmhd, b"gmin", &gmin);
+        write_box(&mut gmhd, b"text", &text);
+        write_box(&mut minf, b"gmhd", &gmhd);
+
+        let mut dref = vec![0, 0, 0, 0];
+        dref.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        write_box(&mut dref, b"url ", &[0, 0, 0, 1]); // self-contained
+        let mut dinf = Vec::new();
+        write_box(&mut dinf, b"dref", &dref);
+        write_box(&mut minf, b"dinf", &dinf);
+
+        let mut entry = vec![0; 6]; // reserved
+        entry.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+        entry.extend_from_slice(&[0; 4]); // display flags
+        entry.extend_from_slice(&[0; 4]); // text justification (left)
+        entry.extend_from_slice(&[0; 6]); // background color
+        entry.extend_from_slice(&[0; 8]); // default text box
+        entry.extend_from_slice(&[0; 8]); // reserved
+        entry.extend_from_slice(&[0; 4]); // font number, font face
+        entry.extend_from_slice(&[0; 3]); // reserved
+        entry.extend_from_slice(&[0; 6]); // foreground color
+        entry.push(0); // font name (empty Pascal string)
+        let mut stsd = vec![0, 0, 0, 0];
+        stsd.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        write_box(&mut stsd, b"text", &entry);
+
+        let count = (self.durations.len() as u32).to_be_bytes();
+        let mut stts = vec![0, 0, 0, 0];
+        stts.extend_from_slice(&count);
+        for duration in &self.durations {
+            stts.extend_from_slice(&1u32.to_be_bytes());
+            stts.extend_from_slice(&duration.to_be_bytes());
+        }
+        let mut stsc = vec![0, 0, 0, 0];
+        stsc.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        stsc.extend_from_slice(&1u32.to_be_bytes()); // first_chunk
+        stsc.extend_from_slice(&count); // samples_per_chunk
+        stsc.extend_from_slice(&1u32.to_be_bytes()); // sample_description_index
+        let mut stsz = vec![0; 8]; // version + flags, sample_size (varies)
+        stsz.extend_from_slice(&count);
+        for size in &self.sizes {
+            stsz.extend_from_slice(&size.to_be_bytes());
+        }
+        let mut co64 = vec![0, 0, 0, 0];
+        co64.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        co64.extend_from_slice(&self.chunk_offset.to_be_bytes());
+
+        let mut stbl = Vec::new();
+        write_box(&mut stbl, b"stsd", &stsd);
+        write_box(&mut stbl, b"stts", &stts);
+        write_box(&mut stbl, b"stsc", &stsc);
+        write_box(&mut stbl, b"stsz", &stsz);
+        write_box(&mut stbl, b"co64", &co64);
+        write_box(&mut minf, b"stbl", &stbl);
+        write_box(&mut mdia, b"minf", &minf);
+        write_box(&mut content, b"mdia", &mdia);
+
+        let mut buf = Vec::new();
+        write_box(&mut buf, b"trak", &content);
+        buf
+    }
+}
+
+fn truncate_utf8(text: &str, max: usize) -> &str {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Store `chapters` in an MP4 (or MOV) file, replacing earlier chapters.
+///
+/// They are written twice: as a `chpl` box in `moov/udta`, which VLC, mpv,
+/// ffmpeg and most other players read, and as a QuickTime chapter text
+/// track referenced by the video track's `tref`/`chap`, which QuickTime
+/// Player and Apple's frameworks read. The text samples go in a small
+/// `mdat` right before the `moov`; replacing chapters leaves the previous
+/// one unreferenced. The file is updated as described for
+/// [`write_mp4_metadata`](super::write_mp4_metadata): in place when the
+/// `moov` is the last box, otherwise through a temporary copy.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use video_toolbox_sys::helpers::{write_chapters, Chapter};
+///
+/// let chapters = [
+///     Chapter::new(Duration::ZERO, "Intro"),
+///     Chapter::new(Duration::from_secs(95), "Demo"),
+/// ];
+/// write_chapters("recording.mov", &chapters)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn write_chapters(path: impl AsRef<Path>, chapters: &[Chapter]) -> io::Result<()> {
+    let chpl = chpl_box(chapters);
+    update_moov_with_media(path.as_ref(), |moov, media_offset| {
+        insert_user_data(moov, &chpl)
+            .and_then(|()| insert_chapter_track(moov, chapters, media_offset))
+            .ok_or_else(|| invalid("malformed moov box"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{CmafConfig, CmafMuxer};
+
+    #[test]
+    fn test_chpl_sorted_in_100ns_units() {
+        let chpl = chpl_box(&[
+            Chapter::new(Duration::from_millis(1500), "Second"),
+            Chapter::new(Duration::ZERO, "Caf\u{e9}"),
+        ]);
+        let mut expected = vec![0, 0, 0, 46];
+        expected.extend_from_slice(b"chpl");
+        expected.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 2]);
+        expected.extend_from_slice(&0u64.to_be_bytes());
+        expected.push(5);
+        expected.extend_from_slice("Caf\u{e9}".as_bytes());
+        expected.extend_from_slice(&15_000_000u64.to_be_bytes());
+        expected.push(6);
+        expected.extend_from_slice(b"Second");
+        assert_eq!(chpl, expected);
+
+        assert_eq!(truncate_utf8("\u{e9}\u{e9}", 3), "\u{e9}");
+
+        // Replacing the chapters keeps a single chpl in the udta
+        let mut moov = Vec::new();
+        write_box(&mut moov, b"moov", &[0, 0, 0, 8, b'm', b'v', b'h', b'd']);
+        insert_user_data(&mut moov, &chpl_box(&[Chapter::new(Duration::ZERO, "a")])).unwrap();
+        insert_user_data(&mut moov, &chpl).unwrap();
+        let root = find_box(&moov, 0, moov.len(), b"moov").unwrap();
+        let udta = find_box(&moov, root.content(), root.end(), b"udta").unwrap();
+        assert_eq!(root.end(), moov.len());
+        assert_eq!(&moov[udta.content()..udta.end()], &chpl[..]);
+    }
+
+    #[test]
+    fn test_chapter_track_replaced() {
+        let mut muxer = CmafMuxer::new(CmafConfig::default());
+        let init = muxer.create_init_segment(&[0x67, 0x64, 0, 0x1f], &[0x68], 1280, 720);
+        let root = find_box(&init, 0, init.len(), b"moov").unwrap();
+        let mut moov = init[root.offset..root.end()].to_vec();
+        insert_chapter_track(&mut moov, &[Chapter::new(Duration::ZERO, "Old")], 100).unwrap();
+        let chapters = [
+            Chapter::new(Duration::from_secs(2), "Demo"),
+            Chapter::new(Duration::ZERO, "Intro"),
+        ];
+        let mdat = insert_chapter_track(&mut moov, &chapters, 100).unwrap();
+
+        // One chapter track, referenced once by the video track
+        let root = find_box(&moov, 0, moov.len(), b"moov").unwrap();
+        let traks = child_boxes(&moov, root, b"trak");
+        assert_eq!(traks.len(), 2);
+        let (video, text) = (traks[0], traks[1]);
+        assert_eq!(handler_type(&moov, text), Some(*b"text"));
+        let tref = find_box(&moov, video.content(), video.end(), b"tref").unwrap();
+        let chap = find_box(&moov, tref.content(), tref.end(), b"chap").unwrap();
+        assert_eq!(chap.end(), tref.end());
+        assert_eq!(read_u32(&moov, chap.content()), trak_id(&moov, text));
+        let mvex = find_box(&moov, root.content(), root.end(), b"mvex").unwrap();
+        assert_eq!(child_boxes(&moov, mvex, b"trex").len(), 2);
+
+        // Sorted samples in one chunk after the mdat header; without a movie
+        // duration the last chapter lasts 1 ms
+        let stbl = [b"mdia", b"minf", b"stbl"]
+            .iter()
+            .try_fold(text, |parent, kind| {
+                find_box(&moov, parent.content(), parent.end(), kind)
+            })
+            .unwrap();
+        let stts = find_box(&moov, stbl.content(), stbl.end(), b"stts").unwrap();
+        let entries: Vec<u32> = (stts.content() + 4..stts.end())
+            .step_by(4)
+            .map(|at| read_u32(&moov, at).unwrap())
+            .collect();
+        assert_eq!(entries, [2, 1, 2000, 1, 1]);
+        let co64 = find_box(&moov, stbl.content(), stbl.end(), b"co64").unwrap();
+        assert_eq!(read_u64(&moov, co64.content() + 8), Some(108));
+        assert_eq!(&mdat[8..15], b"\0\x05Intro");
+        assert_eq!(&mdat[15..23], b"\0\0\0\x0cencd");
+    }
+}
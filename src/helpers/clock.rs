@@ -0,0 +1,399 @@
+//! Shared clock, timebase and playback pacing utilities.
+//!
+//! Real-time pipelines need a common notion of time for A/V sync:
+//! - [`FrameTimestamper`] maps capture timestamps onto a chosen clock (host time
+//!   by default), relative to the first stamped frame
+//! - [`Timebase`] wraps a CMTimebase whose rate can be changed (pause, 1x, 2x, ...)
+//! - [`PlaybackScheduler`] releases queued frames when the timebase reaches their PTS
+//!
+//! # Example
+//!
+//! ```no_run
+//! use video_toolbox_sys::helpers::{FrameTimestamper, PlaybackScheduler, Timebase};
+//!
+//! let stamper = FrameTimestamper::host_time(90000);
+//! let pts = stamper.stamp_now();
+//!
+//! let timebase = Timebase::with_host_clock().expect("Failed to create timebase");
+//! let mut scheduler = PlaybackScheduler::new(timebase, 90000);
+//! scheduler.push(pts, "frame");
+//! scheduler.play();
+//! while let Some((_pts, _frame)) = scheduler.pop_due() {
+//!     // Present frame
+//! }
+//! ```
+
+use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, OSStatus};
+use core_media_sys::{kCMTimeFlags_Valid, CMClockRef, CMTime};
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::ptr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+use super::nal_extractor::convert_time;
+use crate::cm_sync::{
+    kCMTimeRoundingMethod_Default, CMClockGetHostTimeClock, CMClockGetTime,
+    CMClockOrTimebaseRef, CMSyncConvertTime, CMTimebaseCreateWithSourceClock,
+    CMTimebaseGetRate, CMTimebaseGetTime, CMTimebaseGetTimeWithTimeScale, CMTimebaseRef,
+    CMTimebaseSetRate, CMTimebaseSetTime,
+};
+use crate::errors::status_to_result;
+
+const NANOS_PER_SECOND: i32 = 1_000_000_000;
+const NO_ORIGIN: i64 = i64::MIN;
+
+/// Returns the host time clock (backed by `mach_absolute_time`).
+pub fn host_time_clock() -> CMClockRef {
+    unsafe { CMClockGetHostTimeClock() }
+}
+
+/// Returns the current host time.
+pub fn host_time_now() -> CMTime {
+    unsafe { CMClockGetTime(host_time_clock()) }
+}
+
+/// Build a valid CMTime from a value and timescale.
+pub fn make_time(value: i64, timescale: i32) -> CMTime {
+    CMTime {
+        value,
+        timescale,
+        flags: kCMTimeFlags_Valid,
+        epoch: 0,
+    }
+}
+
+/// Timestamps frames against a reference clock.
+///
+/// Timestamps are relative to the first frame stamped, so audio and video sharing
+/// one `FrameTimestamper` start at zero on a common timeline. Stamping is lock-free
+/// and may be called concurrently from capture callbacks.
+pub struct FrameTimestamper {
+    clock: CMClockRef,
+    timescale: i32,
+    /// Origin on the reference clock, in nanoseconds
+    origin_ns: AtomicI64,
+}
+
+// The clock is an immutable CoreMedia object that is safe to query from any thread.
+unsafe impl Send for FrameTimestamper {}
+unsafe impl Sync for FrameTimestamper {}
+
+impl FrameTimestamper {
+    /// Create a timestamper on the host time clock.
+    pub fn host_time(timescale: i32) -> Self {
+        Self {
+            clock: host_time_clock(),
+            timescale,
+            origin_ns: AtomicI64::new(NO_ORIGIN),
+        }
+    }
+
+    /// Create a timestamper on a specific clock.
+    ///
+    /// # Safety
+    ///
+    /// `clock` must be a valid CMClock that outlives the timestamper.
+    pub unsafe fn with_clock(clock: CMClockRef, timescale: i32) -> Self {
+        Self {
+            clock,
+            timescale,
+            origin_ns: AtomicI64::new(NO_ORIGIN),
+        }
+    }
+
+    /// The output timescale.
+    pub fn timescale(&self) -> i32 {
+        self.timescale
+    }
+
+    /// Timestamp a frame at the current clock time (for synthetic sources).
+    pub fn stamp_now(&self) -> i64 {
+        let now = unsafe { CMClockGetTime(self.clock) };
+        self.stamp_clock_time(now)
+    }
+
+    /// Timestamp a captured frame.
+    ///
+    /// `capture_pts` is the sample buffer's presentation timestamp, expressed on
+    /// `capture_clock` (e.g., the `AVCaptureSession` synchronization clock). It is
+    /// converted to the reference clock before stamping.
+    ///
+    /// # Safety
+    ///
+    /// `capture_clock` must be a valid CMClock or CMTimebase.
+    pub unsafe fn stamp_capture(
+        &self,
+        capture_pts: CMTime,
+        capture_clock: CMClockOrTimebaseRef,
+    ) -> i64 {
+        let time = if ptr::eq(capture_clock, self.clock as CMClockOrTimebaseRef) {
+            capture_pts
+        } else {
+            CMSyncConvertTime(capture_pts, capture_clock, self.clock as CMClockOrTimebaseRef)
+        };
+        self.stamp_clock_time(time)
+    }
+
+    /// Timestamp a time already expressed on the reference clock.
+    pub fn stamp_clock_time(&self, time: CMTime) -> i64 {
+        let ns = convert_time(time, NANOS_PER_SECOND);
+        let origin = match self.origin_ns.compare_exchange(
+            NO_ORIGIN,
+            ns,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => ns,
+            Err(existing) => existing,
+        };
+        convert_time(make_time(ns - origin, NANOS_PER_SECOND), self.timescale)
+    }
+
+    /// The reference clock time stamped as zero, or `None` before the first
+    /// frame. With [`host_time`](Self::host_time), this is the host time of
+    /// pts 0, e.g. to [anchor](super::SegmentTimeIndex::anchor) a
+    /// [`SegmentTimeIndex`](super::SegmentTimeIndex).
+    pub fn origin(&self) -> Option<CMTime> {
+        let origin = self.origin_ns.load(Ordering::Acquire);
+        (origin != NO_ORIGIN).then(|| make_time(origin, NANOS_PER_SECOND))
+    }
+
+    /// Forget the origin; the next stamped frame becomes time zero again.
+    pub fn reset(&self) {
+        self.origin_ns.store(NO_ORIGIN, Ordering::Release);
+    }
+}
+
+/// A CMTimebase driven by a source clock, with adjustable rate.
+///
+/// The timebase is released when dropped.
+pub struct Timebase {
+    timebase: CMTimebaseRef,
+}
+
+unsafe impl Send for Timebase {}
+unsafe impl Sync for Timebase {}
+
+impl Timebase {
+    /// Create a timebase driven by the host time clock.
+    pub fn with_host_clock() -> Result<Self, OSStatus> {
+        unsafe { Self::with_source_clock(host_time_clock()) }
+    }
+
+    /// Create a timebase driven by the given clock.
+    ///
+    /// The timebase starts paused (rate 0) at time zero.
+    ///
+    /// # Safety
+    ///
+    /// `clock` must be a valid CMClock.
+    pub unsafe fn with_source_clock(clock: CMClockRef) -> Result<Self, OSStatus> {
+        let mut timebase: CMTimebaseRef = ptr::null_mut();
+        let status = CMTimebaseCreateWithSourceClock(kCFAllocatorDefault, clock, &mut timebase);
+        status_to_result(status)?;
+        Ok(Self { timebase })
+    }
+
+    /// Current timebase time.
+    pub fn time(&self) -> CMTime {
+        unsafe { CMTimebaseGetTime(self.timebase) }
+    }
+
+    /// Current timebase time in the given timescale.
+    pub fn time_in(&self, timescale: i32) -> i64 {
+        unsafe {
+            CMTimebaseGetTimeWithTimeScale(self.timebase, timescale, kCMTimeRoundingMethod_Default)
+                .value
+        }
+    }
+
+    /// Jump to the given time.
+    pub fn set_time(&self, time: CMTime) -> Result<(), OSStatus> {
+        status_to_result(unsafe { CMTimebaseSetTime(self.timebase, time) })
+    }
+
+    /// Current rate (0.0 = paused, 1.0 = real time).
+    pub fn rate(&self) -> f64 {
+        unsafe { CMTimebaseGetRate(self.timebase) }
+    }
+
+    /// Change the rate.
+    pub fn set_rate(&self, rate: f64) -> Result<(), OSStatus> {
+        status_to_result(unsafe { CMTimebaseSetRate(self.timebase, rate) })
+    }
+
+    /// Get the underlying timebase reference.
+    pub fn as_raw(&self) -> CMTimebaseRef {
+        self.timebase
+    }
+}
+
+impl Drop for Timebase {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.timebase as _) };
+    }
+}
+
+/// An item waiting in the [`PlaybackScheduler`].
+struct Scheduled<T> {
+    pts: i64,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Scheduled<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.pts == other.pts && self.seq == other.seq
+    }
+}
+
+impl<T> Eq for Scheduled<T> {}
+
+impl<T> PartialOrd for Scheduled<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Scheduled<T> {
+    // Reversed so the BinaryHeap pops the earliest PTS first (FIFO for ties)
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        other
+            .pts
+            .cmp(&self.pts)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Releases queued items when a [`Timebase`] reaches their presentation time.
+///
+/// Items may be pushed in decode order; they are released in PTS order.
+pub struct PlaybackScheduler<T> {
+    timebase: Timebase,
+    timescale: i32,
+    queue: BinaryHeap<Scheduled<T>>,
+    next_seq: u64,
+    /// Rate to restore on `play()`
+    play_rate: f64,
+}
+
+impl<T> PlaybackScheduler<T> {
+    /// Create a scheduler for timestamps in `timescale` units.
+    pub fn new(timebase: Timebase, timescale: i32) -> Self {
+        Self {
+            timebase,
+            timescale,
+            queue: BinaryHeap::new(),
+            next_seq: 0,
+            play_rate: 1.0,
+        }
+    }
+
+    /// Queue an item for presentation at `pts`.
+    pub fn push(&mut self, pts: i64, item: T) {
+        self.queue.push(Scheduled {
+            pts,
+            seq: self.next_seq,
+            item,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Pop the next item whose PTS has been reached by the timebase.
+    pub fn pop_due(&mut self) -> Option<(i64, T)> {
+        let now = self.timebase.time_in(self.timescale);
+        if self.queue.peek()?.pts > now {
+            return None;
+        }
+        self.queue.pop().map(|s| (s.pts, s.item))
+    }
+
+    /// Wall-clock time until the next item is due, or `None` if the queue is
+    /// empty or playback is paused.
+    pub fn time_until_next(&self) -> Option<Duration> {
+        let next = self.queue.peek()?.pts;
+        let now = self.timebase.time_in(self.timescale);
+        wait_duration(next, now, self.timescale, self.timebase.rate())
+    }
+
+    /// Start (or resume) playback at the last configured rate.
+    pub fn play(&mut self) {
+        let _ = self.timebase.set_rate(self.play_rate);
+    }
+
+    /// Pause playback.
+    pub fn pause(&mut self) {
+        let _ = self.timebase.set_rate(0.0);
+    }
+
+    /// Set the playback rate (e.g., 0.5 for slow motion, 2.0 for catch-up).
+    pub fn set_rate(&mut self, rate: f64) -> Result<(), OSStatus> {
+        if rate != 0.0 {
+            self.play_rate = rate;
+        }
+        self.timebase.set_rate(rate)
+    }
+
+    /// Jump to `pts`, discarding all queued items.
+    pub fn seek(&mut self, pts: i64) -> Result<(), OSStatus> {
+        self.queue.clear();
+        self.timebase.set_time(make_time(pts, self.timescale))
+    }
+
+    /// Number of queued items.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns true if no items are queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// The timebase driving this scheduler.
+    pub fn timebase(&self) -> &Timebase {
+        &self.timebase
+    }
+}
+
+/// Wall-clock wait until `next` is reached from `now` at the given rate.
+fn wait_duration(next: i64, now: i64, timescale: i32, rate: f64) -> Option<Duration> {
+    if rate <= 0.0 {
+        return None;
+    }
+    if next <= now {
+        return Some(Duration::ZERO);
+    }
+    let media_seconds = (next - now) as f64 / timescale as f64;
+    Some(Duration::from_secs_f64(media_seconds / rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheduled_pops_in_pts_order() {
+        let mut heap = BinaryHeap::new();
+        for (seq, pts) in [3000i64, 0, 6000, 3000].iter().enumerate() {
+            heap.push(Scheduled {
+                pts: *pts,
+                seq: seq as u64,
+                item: seq,
+            });
+        }
+        let order: Vec<usize> = std::iter::from_fn(|| heap.pop().map(|s| s.item)).collect();
+        assert_eq!(order, vec![1, 0, 3, 2]);
+    }
+
+    #[test]
+    fn test_wait_duration() {
+        assert_eq!(wait_duration(90000, 0, 90000, 0.0), None);
+        assert_eq!(wait_duration(0, 90000, 90000, 1.0), Some(Duration::ZERO));
+        assert_eq!(
+            wait_duration(90000, 0, 90000, 2.0),
+            Some(Duration::from_millis(500))
+        );
+    }
+}
@@ -0,0 +1,849 @@
+//! Incremental CMAF (fragmented MP4) demuxer for H.264 video streams.
+//!
+//! The demuxer accepts the stream in arbitrary byte chunks, as they arrive
+//! from the network, and returns each sample as soon as its bytes are in:
+//! a low-latency player can start decoding the first frames of a segment
+//! while the rest is still in flight.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use video_toolbox_sys::helpers::cmaf_demuxer::CmafDemuxer;
+//!
+//! let mut demuxer = CmafDemuxer::new();
+//! # let chunks: Vec<Vec<u8>> = Vec::new();
+//! for chunk in chunks {
+//!     for frame in demuxer.push(&chunk).expect("invalid stream") {
+//!         // decode frame.nal_units
+//!     }
+//! }
+//! ```
+
+use std::collections::VecDeque;
+
+use super::nal_extractor::{parse_avcc, H264ParameterSets, NalError, SampleTiming};
+use super::source::MediaFrame;
+
+/// Error codes for CMAF demuxing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemuxError {
+    /// A box is shorter than its header or contents require
+    InvalidBox([u8; 4]),
+    /// A media segment arrived before the initialization segment
+    MissingInitSegment,
+    /// The initialization segment has no H.264 (avc1/avc3) track
+    NoVideoTrack,
+    /// A sample's data is not valid length-prefixed NAL units
+    Nal(NalError),
+}
+
+impl std::fmt::Display for DemuxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DemuxError::InvalidBox(box_type) => {
+                write!(f, "Invalid '{}' box", String::from_utf8_lossy(box_type))
+            }
+            DemuxError::MissingInitSegment => {
+                write!(
+                    f,
+                    "Media segment received before the initialization segment"
+                )
+            }
+            DemuxError::NoVideoTrack => write!(f, "No H.264 video track in the init segment"),
+            DemuxError::Nal(e) => write!(f, "Invalid sample data: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DemuxError {}
+
+impl From<NalError> for DemuxError {
+    fn from(e: NalError) -> Self {
+        DemuxError::Nal(e)
+    }
+}
+
+/// The video track described by the initialization segment.
+#[derive(Debug, Clone)]
+pub struct DemuxedTrack {
+    pub track_id: u32,
+    /// Media timescale from mdhd
+    pub timescale: u32,
+    pub width: u32,
+    pub height: u32,
+    /// SPS, PPS and NAL length size from avcC
+    pub parameter_sets: H264ParameterSets,
+}
+
+/// trex defaults for the video track.
+#[derive(Debug, Clone, Copy, Default)]
+struct SampleDefaults {
+    duration: u32,
+    size: u32,
+    flags: u32,
+}
+
+/// A sample announced by a moof whose data may not have arrived yet.
+#[derive(Debug, Clone, Copy)]
+struct PendingSample {
+    /// Offset of the sample data from the start of the stream
+    offset: u64,
+    size: u32,
+    dts: i64,
+    duration: u32,
+    composition_offset: i32,
+    is_sync: bool,
+}
+
+/// sample_is_non_sync_sample in the ISO BMFF sample flags
+pub(super) const NON_SYNC_SAMPLE: u32 = 0x0001_0000;
+
+/// Most samples accepted from a trun without per-sample fields, whose
+/// sample count is not bounded by its size
+pub(super) const MAX_TRUN_SAMPLES: u32 = 1 << 16;
+
+/// Fragmented MP4 demuxer that emits samples progressively.
+///
+/// Feed the initialization segment followed by media segments (or
+/// low-latency chunks, each a moof+mdat pair) with [`push`](Self::push), in
+/// chunks of any size. Top-level boxes other than moof and mdat are buffered
+/// until complete; mdat is not: each sample is returned as soon as its last
+/// byte arrives, so only the moof and one sample need to be held in memory.
+///
+/// Samples come out as [`MediaFrame`]s in decode order with timing in the
+/// track's timescale, ready for an
+/// [`AccessUnitAssembler`](super::AccessUnitAssembler) or a decoder.
+#[derive(Debug, Default)]
+pub struct CmafDemuxer {
+    /// Unconsumed stream bytes
+    buffer: Vec<u8>,
+    /// Stream offset of `buffer[0]`
+    buffer_offset: u64,
+    track: Option<DemuxedTrack>,
+    defaults: SampleDefaults,
+    pending: VecDeque<PendingSample>,
+    /// Stream offset where the mdat being streamed ends
+    mdat_end: Option<u64>,
+    /// Decode time following the last fragment, for fragments without tfdt
+    next_dts: i64,
+    sequence_number: Option<u32>,
+}
+
+impl CmafDemuxer {
+    /// Create a demuxer expecting the stream to begin with an init segment.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a demuxer for media segments of a track whose initialization
+    /// segment was parsed elsewhere, e.g. when joining a live stream.
+    pub fn with_track(track: DemuxedTrack) -> Self {
+        Self {
+            track: Some(track),
+            ..Self::default()
+        }
+    }
+
+    /// Add the next chunk of the stream, returning every sample it completes.
+    ///
+    /// On error the demuxer's state is undefined; create a new one (or
+    /// [`reset`](Self::reset)) and continue from the next segment.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<MediaFrame>, DemuxError> {
+        self.buffer.extend_from_slice(data);
+        let mut frames = Vec::new();
+        loop {
+            let progressed = match self.mdat_end {
+                Some(_) => self.read_mdat(&mut frames)?,
+                None => self.read_box()?,
+            };
+            if !progressed {
+                return Ok(frames);
+            }
+        }
+    }
+
+    /// The video track, once the initialization segment has been parsed.
+    pub fn track(&self) -> Option<&DemuxedTrack> {
+        self.track.as_ref()
+    }
+
+    /// Sequence number (mfhd) of the most recent fragment.
+    pub fn sequence_number(&self) -> Option<u32> {
+        self.sequence_number
+    }
+
+    /// Bytes received but not yet consumed.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Discard any partial segment, e.g. after a gap in the stream. The
+    /// track from the initialization segment is kept.
+    pub fn reset(&mut self) {
+        *self = Self {
+            track: self.track.take(),
+            defaults: self.defaults,
+            next_dts: self.next_dts,
+            ..Self::default()
+        };
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.buffer.drain(..len);
+        self.buffer_offset += len as u64;
+    }
+
+    /// Parse a complete top-level box, or start streaming an mdat. Returns
+    /// false if more data is needed.
+    fn read_box(&mut self) -> Result<bool, DemuxError> {
+        let Some((box_type, header_len, size)) = box_header(&self.buffer)? else {
+            return Ok(false);
+        };
+
+        if &box_type == b"mdat" {
+            let start = self.buffer_offset;
+            self.mdat_end = Some(match size {
+                Some(size) => start
+                    .checked_add(size)
+                    .ok_or(DemuxError::InvalidBox(box_type))?,
+                None => u64::MAX,
+            });
+            self.consume(header_len);
+            return Ok(true);
+        }
+
+        let Some(size) = size.map(|s| s as usize) else {
+            // A box other than mdat running to the end of the stream
+            return Err(DemuxError::InvalidBox(box_type));
+        };
+        if self.buffer.len() < size {
+            return Ok(false);
+        }
+        let start = self.buffer_offset;
+        let payload = self
+            .buffer
+            .get(header_len..size)
+            .ok_or(DemuxError::InvalidBox(box_type))?
+            .to_vec();
+        self.consume(size);
+        match &box_type {
+            b"moov" => self.parse_moov(&payload)?,
+            b"moof" => self.parse_moof(&payload, start)?,
+            // ftyp, styp, sidx, prft, emsg, free, ...
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Emit the samples of the current mdat that have fully arrived.
+    fn read_mdat(&mut self, frames: &mut Vec<MediaFrame>) -> Result<bool, DemuxError> {
+        let mdat_end = self.mdat_end.unwrap_or(u64::MAX);
+        let buffered_end = self.buffer_offset + self.buffer.len() as u64;
+
+        let mut progressed = false;
+        while let Some(sample) = self.pending.front().copied() {
+            if sample.offset < self.buffer_offset {
+                // Overlaps a sample already returned, or precedes this mdat
+                return Err(DemuxError::InvalidBox(*b"trun"));
+            }
+            if sample.offset >= mdat_end {
+                // Belongs to a later mdat
+                break;
+            }
+            let end = sample
+                .offset
+                .checked_add(sample.size as u64)
+                .filter(|&end| end <= mdat_end)
+                .ok_or(DemuxError::InvalidBox(*b"mdat"))?;
+            if end > buffered_end {
+                break;
+            }
+            let start = (sample.offset - self.buffer_offset) as usize;
+            let len = start + sample.size as usize;
+            let data = self
+                .buffer
+                .get(start..len)
+                .ok_or(DemuxError::InvalidBox(*b"mdat"))?;
+            frames.push(self.media_frame(&sample, data)?);
+            self.pending.pop_front();
+            self.consume(len);
+            progressed = true;
+        }
+
+        let done = self.pending.front().is_none_or(|s| s.offset >= mdat_end);
+        if done && mdat_end == u64::MAX {
+            // The mdat runs to the end of the stream: nothing more to parse
+            let len = self.buffer.len();
+            self.consume(len);
+            return Ok(progressed);
+        }
+        if done {
+            // Skip whatever follows the last sample in this mdat
+            let remaining = mdat_end.saturating_sub(self.buffer_offset) as usize;
+            if self.buffer.len() < remaining {
+                let len = self.buffer.len();
+                self.consume(len);
+                return Ok(progressed);
+            }
+            self.consume(remaining);
+            self.mdat_end = None;
+            return Ok(true);
+        }
+        Ok(progressed)
+    }
+
+    fn media_frame(&self, sample: &PendingSample, data: &[u8]) -> Result<MediaFrame, DemuxError> {
+        let track = self.track.as_ref().ok_or(DemuxError::MissingInitSegment)?;
+        let nal_length_size = track.parameter_sets.nal_length_size as usize;
+        let pts = sample
+            .dts
+            .checked_add(sample.composition_offset as i64)
+            .ok_or(DemuxError::InvalidBox(*b"trun"))?;
+        let timescale =
+            i32::try_from(track.timescale).map_err(|_| DemuxError::InvalidBox(*b"mdhd"))?;
+        Ok(MediaFrame {
+            nal_units: parse_avcc(data, nal_length_size)?,
+            timing: SampleTiming {
+                pts,
+                dts: sample.dts,
+                duration: sample.duration as i64,
+                timescale,
+            },
+            is_keyframe: sample.is_sync,
+            motion_score: None,
+        })
+    }
+
+    fn parse_moov(&mut self, moov: &[u8]) -> Result<(), DemuxError> {
+        let mut track = None;
+        let mut mvex = None;
+        for child in children(moov) {
+            let (box_type, payload) = child?;
+            match &box_type {
+                b"trak" if track.is_none() => track = parse_trak(payload)?,
+                b"mvex" => mvex = Some(payload),
+                _ => {}
+            }
+        }
+        let track = track.ok_or(DemuxError::NoVideoTrack)?;
+
+        self.defaults = SampleDefaults::default();
+        for child in children(mvex.unwrap_or_default()) {
+            let (box_type, payload) = child?;
+            if &box_type != b"trex" {
+                continue;
+            }
+            let mut r = Reader::new(payload, box_type);
+            r.skip(4)?; // version + flags
+            if r.u32()? == track.track_id {
+                r.skip(4)?; // default_sample_description_index
+                self.defaults = SampleDefaults {
+                    duration: r.u32()?,
+                    size: r.u32()?,
+                    flags: r.u32()?,
+                };
+            }
+        }
+        self.track = Some(track);
+        Ok(())
+    }
+
+    fn parse_moof(&mut self, moof: &[u8], moof_offset: u64) -> Result<(), DemuxError> {
+        let track_id = self
+            .track
+            .as_ref()
+            .ok_or(DemuxError::MissingInitSegment)?
+            .track_id;
+        for child in children(moof) {
+            let (box_type, payload) = child?;
+            match &box_type {
+                b"mfhd" => {
+                    let mut r = Reader::new(payload, box_type);
+                    r.skip(4)?;
+                    self.sequence_number = Some(r.u32()?);
+                }
+                b"traf" => self.parse_traf(payload, moof_offset, track_id)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_traf(
+        &mut self,
+        traf: &[u8],
+        moof_offset: u64,
+        track_id: u32,
+    ) -> Result<(), DemuxError> {
+        let mut defaults = self.defaults;
+        let mut base_offset = moof_offset;
+        let mut base_dts = None;
+        let mut truns = Vec::new();
+
+        for child in children(traf) {
+            let (box_type, payload) = child?;
+            let mut r = Reader::new(payload, box_type);
+            match &box_type {
+                b"tfhd" => {
+                    let flags = r.u32()? & 0x00FF_FFFF;
+                    if r.u32()? != track_id {
+                        return Ok(());
+                    }
+                    if flags & 0x01 != 0 {
+                        base_offset = r.u64()?;
+                    }
+                    if flags & 0x02 != 0 {
+                        r.skip(4)?; // sample_description_index
+                    }
+                    if flags & 0x08 != 0 {
+                        defaults.duration = r.u32()?;
+                    }
+                    if flags & 0x10 != 0 {
+                        defaults.size = r.u32()?;
+                    }
+                    if flags & 0x20 != 0 {
+                        defaults.flags = r.u32()?;
+                    }
+                }
+                b"tfdt" => {
+                    let version = r.u32()? >> 24;
+                    let dts = if version == 1 {
+                        r.u64()?
+                    } else {
+                        r.u32()? as u64
+                    };
+                    base_dts = Some(i64::try_from(dts).map_err(|_| r.invalid())?);
+                }
+                b"trun" => truns.push(payload),
+                _ => {}
+            }
+        }
+
+        let mut dts = base_dts.unwrap_or(self.next_dts);
+        let mut data_offset = base_offset;
+        for trun in truns {
+            let mut r = Reader::new(trun, *b"trun");
+            let flags = r.u32()? & 0x00FF_FFFF;
+            let sample_count = r.u32()?;
+            if flags & 0x01 != 0 {
+                data_offset = base_offset
+                    .checked_add_signed(r.u32()? as i32 as i64)
+                    .ok_or(r.invalid())?;
+            }
+            let first_sample_flags = if flags & 0x04 != 0 {
+                Some(r.u32()?)
+            } else {
+                None
+            };
+            // Duration, size, flags and composition offset, 4 bytes each
+            let sample_len = 4 * (flags & 0xF00).count_ones() as usize;
+            let bounded = if sample_len == 0 {
+                sample_count <= MAX_TRUN_SAMPLES
+            } else {
+                (sample_count as usize)
+                    .checked_mul(sample_len)
+                    .is_some_and(|len| len <= r.remaining())
+            };
+            if !bounded {
+                return Err(r.invalid());
+            }
+            for i in 0..sample_count {
+                let duration = if flags & 0x100 != 0 {
+                    r.u32()?
+                } else {
+                    defaults.duration
+                };
+                let size = if flags & 0x200 != 0 {
+                    r.u32()?
+                } else {
+                    defaults.size
+                };
+                let sample_flags = if flags & 0x400 != 0 {
+                    r.u32()?
+                } else {
+                    first_sample_flags
+                        .filter(|_| i == 0)
+                        .unwrap_or(defaults.flags)
+                };
+                let composition_offset = if flags & 0x800 != 0 {
+                    r.u32()? as i32
+                } else {
+                    0
+                };
+                self.pending.push_back(PendingSample {
+                    offset: data_offset,
+                    size,
+                    dts,
+                    duration,
+                    composition_offset,
+                    is_sync: sample_flags & NON_SYNC_SAMPLE == 0,
+                });
+                data_offset = data_offset.checked_add(size as u64).ok_or(r.invalid())?;
+                dts = dts.checked_add(duration as i64).ok_or(r.invalid())?;
+            }
+        }
+        self.next_dts = dts;
+        Ok(())
+    }
+}
+
+/// Parse a trak, returning `None` if it is not an H.264 video track.
+fn parse_trak(trak: &[u8]) -> Result<Option<DemuxedTrack>, DemuxError> {
+    let mut track_id = 0;
+    let mut timescale = 0;
+    let mut sample_entry = None;
+    for child in children(trak) {
+        let (box_type, payload) = child?;
+        match &box_type {
+            b"tkhd" => {
+                let mut r = Reader::new(payload, box_type);
+                let version = r.u32()? >> 24;
+                r.skip(if version == 1 { 16 } else { 8 })?; // creation/modification time
+                track_id = r.u32()?;
+            }
+            b"mdia" => {
+                for child in children(payload) {
+                    let (box_type, payload) = child?;
+                    match &box_type {
+                        b"mdhd" => {
+                            let mut r = Reader::new(payload, box_type);
+                            let version = r.u32()? >> 24;
+                            r.skip(if version == 1 { 16 } else { 8 })?;
+                            timescale = r.u32()?;
+                        }
+                        b"minf" => sample_entry = find_sample_entry(payload)?.or(sample_entry),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let Some((width, height, parameter_sets)) = sample_entry else {
+        return Ok(None);
+    };
+    Ok(Some(DemuxedTrack {
+        track_id,
+        timescale,
+        width,
+        height,
+        parameter_sets,
+    }))
+}
+
+/// Find the avc1/avc3 sample entry in minf/stbl/stsd.
+fn find_sample_entry(minf: &[u8]) -> Result<Option<(u32, u32, H264ParameterSets)>, DemuxError> {
+    let Some(stbl) = find_child(minf, b"stbl")? else {
+        return Ok(None);
+    };
+    let Some(stsd) = find_child(stbl, b"stsd")? else {
+        return Ok(None);
+    };
+    // version + flags, entry_count
+    let entries = stsd.get(8..).ok_or(DemuxError::InvalidBox(*b"stsd"))?;
+    for child in children(entries) {
+        let (box_type, entry) = child?;
+        if &box_type != b"avc1" && &box_type != b"avc3" {
+            continue;
+        }
+        let mut r = Reader::new(entry, box_type);
+        r.skip(24)?; // reserved, data_reference_index, pre_defined
+        let width = r.u16()? as u32;
+        let height = r.u16()? as u32;
+        // resolution, reserved, frame_count, compressorname, depth, pre_defined
+        let boxes = entry.get(78..).ok_or(DemuxError::InvalidBox(box_type))?;
+        if let Some(avcc) = find_child(boxes, b"avcC")? {
+            return Ok(Some((width, height, parse_avcc_config(avcc)?)));
+        }
+    }
+    Ok(None)
+}
+
+/// Parse an AVCDecoderConfigurationRecord (the avcC payload).
+fn parse_avcc_config(avcc: &[u8]) -> Result<H264ParameterSets, DemuxError> {
+    let mut r = Reader::new(avcc, *b"avcC");
+    r.skip(4)?; // configuration_version, profile, compatibility, level
+    let nal_length_size = (r.u8()? & 0x03) as i32 + 1;
+    let mut sps = Vec::new();
+    for _ in 0..r.u8()? & 0x1F {
+        let len = r.u16()? as usize;
+        let data = r.bytes(len)?;
+        if sps.is_empty() {
+            sps = data.to_vec();
+        }
+    }
+    let mut pps = Vec::new();
+    for _ in 0..r.u8()? {
+        let len = r.u16()? as usize;
+        let data = r.bytes(len)?;
+        if pps.is_empty() {
+            pps = data.to_vec();
+        }
+    }
+    Ok(H264ParameterSets {
+        sps,
+        pps,
+        nal_length_size,
+    })
+}
+
+/// Box type, header length and total size (`None` for a box extending to the
+/// end of the stream).
+type BoxHeader = ([u8; 4], usize, Option<u64>);
+
+/// Read a box header. Returns `None` if incomplete.
+fn box_header(data: &[u8]) -> Result<Option<BoxHeader>, DemuxError> {
+    if data.len() < 8 {
+        return Ok(None);
+    }
+    let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64;
+    let box_type = [data[4], data[5], data[6], data[7]];
+    let (header_len, size) = match size {
+        0 => (8, None),
+        1 => {
+            if data.len() < 16 {
+                return Ok(None);
+            }
+            let mut large = [0; 8];
+            large.copy_from_slice(&data[8..16]);
+            (16, Some(u64::from_be_bytes(large)))
+        }
+        size => (8, Some(size)),
+    };
+    if size.is_some_and(|size| size < header_len as u64) {
+        return Err(DemuxError::InvalidBox(box_type));
+    }
+    Ok(Some((box_type, header_len, size)))
+}
+
+/// Iterate over the child boxes of a container's payload.
+fn children(data: &[u8]) -> impl Iterator<Item = Result<([u8; 4], &[u8]), DemuxError>> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let child = match box_header(rest) {
+            Ok(Some((box_type, header_len, size))) => {
+                let size = size.map_or(rest.len(), |size| size as usize);
+                if size > rest.len() {
+                    Err(DemuxError::InvalidBox(box_type))
+                } else {
+                    let payload = &rest[header_len..size];
+                    rest = &rest[size..];
+                    Ok((box_type, payload))
+                }
+            }
+            Ok(None) => Err(DemuxError::InvalidBox(*b"\0\0\0\0")),
+            Err(e) => Err(e),
+        };
+        if child.is_err() {
+            rest = &[];
+        }
+        Some(child)
+    })
+}
+
+pub(super) fn find_child<'a>(
+    data: &'a [u8],
+    box_type: &[u8; 4],
+) -> Result<Option<&'a [u8]>, DemuxError> {
+    for child in children(data) {
+        let (child_type, payload) = child?;
+        if &child_type == box_type {
+            return Ok(Some(payload));
+        }
+    }
+    Ok(None)
+}
+
+/// Big-endian reader over a box payload.
+struct Reader<'a> {
+    data: &'a [u8],
+    box_type: [u8; 4],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], box_type: [u8; 4]) -> Self {
+        Self { data, box_type }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DemuxError> {
+        if self.data.len() < len {
+            return Err(self.invalid());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn invalid(&self) -> DemuxError {
+        DemuxError::InvalidBox(self.box_type)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), DemuxError> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, DemuxError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DemuxError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DemuxError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, DemuxError> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::cmaf_muxer::{CmafConfig, CmafMuxer, FragmentEmission};
+    use crate::helpers::NalUnit;
+
+    const SPS: [u8; 8] = [0x67, 0x64, 0x00, 0x1F, 0xAC, 0xD9, 0x40, 0x50];
+    const PPS: [u8; 4] = [0x68, 0xEB, 0xE3, 0xCB];
+
+    fn slice(keyframe: bool, index: u8, len: usize) -> NalUnit {
+        let nal_type = if keyframe { 5 } else { 1 };
+        let mut data = vec![0x60 | nal_type, 0x88, index];
+        data.resize(len, index);
+        NalUnit { data, nal_type }
+    }
+
+    /// An init segment and per-GOP segments for six frames, with a B-frame
+    /// style composition offset on the second.
+    fn stream() -> (Vec<u8>, Vec<Vec<NalUnit>>) {
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            fragment_duration_ms: 0,
+            ..Default::default()
+        });
+        let mut bytes = muxer.create_init_segment(&SPS, &PPS, 640, 480);
+        let mut frames = Vec::new();
+        for i in 0..6u8 {
+            let nal_units = vec![slice(i % 3 == 0, i, 50 + i as usize * 100)];
+            let dts = i as i64 * 3000;
+            let pts = if i == 1 { dts + 3000 } else { dts };
+            if let Some(segment) = muxer.add_frame(&nal_units, pts, dts, 3000, i % 3 == 0) {
+                bytes.extend(segment);
+            }
+            frames.push(nal_units);
+        }
+        bytes.extend(muxer.flush().unwrap());
+        (bytes, frames)
+    }
+
+    #[test]
+    fn test_demux_in_arbitrary_chunks() {
+        let (bytes, expected) = stream();
+        for chunk_size in [1, 7, 100, bytes.len()] {
+            let mut demuxer = CmafDemuxer::new();
+            let mut frames = Vec::new();
+            for chunk in bytes.chunks(chunk_size) {
+                frames.extend(demuxer.push(chunk).unwrap());
+            }
+
+            let track = demuxer.track().unwrap();
+            assert_eq!(
+                (track.width, track.height, track.timescale),
+                (640, 480, 90000)
+            );
+            assert_eq!(track.parameter_sets.sps, SPS);
+            assert_eq!(track.parameter_sets.pps, PPS);
+            assert_eq!(demuxer.sequence_number(), Some(2));
+            assert_eq!(demuxer.buffered_len(), 0);
+
+            assert_eq!(frames.len(), expected.len());
+            for (i, (frame, nal_units)) in frames.iter().zip(&expected).enumerate() {
+                assert_eq!(frame.nal_units[0].data, nal_units[0].data);
+                assert_eq!(frame.is_keyframe, i % 3 == 0);
+                assert_eq!(frame.timing.dts, i as i64 * 3000);
+                assert_eq!(frame.timing.duration, 3000);
+            }
+            assert_eq!(frames[1].timing.pts, 6000);
+        }
+    }
+
+    #[test]
+    fn test_samples_emitted_before_segment_completes() {
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            emission: FragmentEmission::PerGop,
+            fragment_duration_ms: 10_000,
+            ..Default::default()
+        });
+        let init = muxer.create_init_segment(&SPS, &PPS, 640, 480);
+        for i in 0..3u8 {
+            muxer.add_frame(&[slice(i == 0, i, 1000)], i as i64, i as i64, 1, i == 0);
+        }
+        let segment = muxer.flush().unwrap();
+
+        let mut demuxer = CmafDemuxer::new();
+        assert!(demuxer.push(&init).unwrap().is_empty());
+        // Everything up to the middle of the second sample
+        let mdat_data = segment.len() - 3 * 1004;
+        let split = mdat_data + 1004 + 500;
+        let first = demuxer.push(&segment[..split]).unwrap();
+        assert_eq!(first.len(), 1);
+        assert!(first[0].is_keyframe);
+        // Only the partial sample is held back
+        assert_eq!(demuxer.buffered_len(), 500);
+
+        let rest = demuxer.push(&segment[split..]).unwrap();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest[1].nal_units[0].data[2], 2);
+
+        // Media before the init segment
+        let mut demuxer = CmafDemuxer::new();
+        assert_eq!(
+            demuxer.push(&segment).unwrap_err(),
+            DemuxError::MissingInitSegment
+        );
+    }
+
+    #[test]
+    fn test_truncated_and_corrupt_input() {
+        let (bytes, _) = stream();
+        // Cut anywhere: frames up to the cut, never a panic
+        for len in 0..bytes.len() {
+            let _ = CmafDemuxer::new().push(&bytes[..len]);
+        }
+        // Any field (sizes, counts, offsets, timestamps) at its extremes
+        for i in 0..bytes.len() {
+            for value in [0x00, 0x80, 0xFF] {
+                let mut corrupt = bytes.clone();
+                corrupt[i] = value;
+                let _ = CmafDemuxer::new().push(&corrupt);
+            }
+        }
+
+        // A trun announcing more samples than it has entries for
+        let moof_at = (0..bytes.len())
+            .find(|&i| &bytes[i + 4..i + 8] == b"moof")
+            .unwrap();
+        let trun_at = (moof_at..bytes.len())
+            .find(|&i| &bytes[i..i + 4] == b"trun")
+            .unwrap();
+        let mut corrupt = bytes.clone();
+        corrupt[trun_at + 8..trun_at + 12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            CmafDemuxer::new().push(&corrupt).unwrap_err(),
+            DemuxError::InvalidBox(*b"trun")
+        );
+    }
+}
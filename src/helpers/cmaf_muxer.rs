@@ -1,5 +1,8 @@
 //! CMAF (Common Media Application Format) muxer for H.264 video streams.
 //!
+//! Other codecs, such as HEVC or AV1 from another source, can be passed
+//! through with a prebuilt configuration record (see [`PassthroughCodec`]).
+//!
 //! This module provides a pure-Rust CMAF muxer suitable for:
 //! - Live streaming (DASH/HLS)
 //! - Media Source Extensions (MSE) in browsers
@@ -26,6 +29,7 @@
 //! let mut muxer = CmafMuxer::new(CmafConfig {
 //!     fragment_duration_ms: 2000,
 //!     timescale: 90000,
+//!     ..Default::default()
 //! });
 //!
 //! // Create initialization segment with SPS/PPS
@@ -37,7 +41,14 @@
 //! // }
 //! ```
 
-use super::nal_extractor::NalUnit;
+use std::time::Duration;
+
+use super::codec_string::{av1_codec_string, h264_codec_string_from_bytes, HevcProfileTierLevel};
+use super::compression_builder::CompressionSessionConfig;
+use super::metadata::Mp4Metadata;
+use super::nal_extractor::{validate_nal_length_size, write_length_prefixed, NalError, NalUnit};
+use super::padding::CleanAperture;
+use super::profile_level::{derive_level, Level, Profile, ProfileLevel, StreamParams};
 
 /// Configuration for the CMAF muxer.
 #[derive(Debug, Clone)]
@@ -47,6 +58,58 @@ pub struct CmafConfig {
     pub fragment_duration_ms: u32,
     /// Timescale for timestamps (e.g., 90000 for standard video).
     pub timescale: u32,
+    /// Size of the big-endian NAL length prefix in mdat (1, 2 or 4 bytes).
+    /// Written to avcC as `lengthSizeMinusOne`.
+    pub nal_length_size: usize,
+    /// Profile and level the encoder was configured with, written to avcC.
+    /// When `None`, they are read from the SPS, or derived from the frame size.
+    pub profile_level: Option<ProfileLevel>,
+    /// When fragments are emitted.
+    pub emission: FragmentEmission,
+    /// Derive each sample's duration from the next frame's decode time
+    /// instead of trusting the duration passed in, for variable frame rate
+    /// sources such as webcams. Frames are held back by one frame until the
+    /// next one arrives; the last frame keeps the duration it was added with.
+    pub variable_frame_rate: bool,
+}
+
+/// When [`CmafMuxer`] cuts fragments.
+///
+/// Fragments keep their frames' sync flags, so with the low-latency
+/// strategies only fragments that start with a keyframe are join points.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FragmentEmission {
+    /// At the first keyframe after `fragment_duration_ms` (one or more GOPs
+    /// per fragment)
+    #[default]
+    PerGop,
+    /// As soon as a frame arrives that is not displayed before any earlier
+    /// frame, i.e. at the end of each B-frame mini-GOP. Frames that
+    /// reference future frames always share a fragment with them; without
+    /// frame reordering this is one frame per fragment, one frame late.
+    PerMiniGop,
+    /// Every frame as its own fragment (a CMAF chunk), emitted as soon as it
+    /// is added. Requires frame reordering to be disabled in the encoder
+    /// (see [`validate`](Self::validate)); a reordered frame is rejected with
+    /// [`NalError::FrameReordering`].
+    PerFrame,
+}
+
+impl FragmentEmission {
+    /// Check that the encoder settings are compatible with this strategy.
+    ///
+    /// [`PerFrame`](Self::PerFrame) needs
+    /// [`allow_frame_reordering`](CompressionSessionConfig::allow_frame_reordering)
+    /// explicitly set to `false`, since VideoToolbox enables B-frames by
+    /// default for profiles that support them.
+    pub fn validate(&self, encoder: &CompressionSessionConfig) -> Result<(), NalError> {
+        match self {
+            FragmentEmission::PerFrame if encoder.allow_frame_reordering != Some(false) => {
+                Err(NalError::FrameReordering)
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Default for CmafConfig {
@@ -54,8 +117,67 @@ impl Default for CmafConfig {
         Self {
             fragment_duration_ms: 2000,
             timescale: 90000,
+            nal_length_size: 4,
+            profile_level: None,
+            emission: FragmentEmission::PerGop,
+            variable_frame_rate: false,
+        }
+    }
+}
+
+/// Sample description for an already-encoded stream the muxer does not
+/// parse, e.g. HEVC or AV1 passed through from another source.
+///
+/// The configuration record is written verbatim as the payload of
+/// `config_box` inside a `sample_entry` visual sample entry, and samples
+/// added with [`CmafMuxer::add_sample`] go into `mdat` unchanged, so they
+/// must already be in the format the sample entry expects (length-prefixed
+/// NAL units for HEVC, OBUs without temporal delimiters for AV1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassthroughCodec {
+    /// Sample entry fourcc, e.g. `hvc1` or `av01`
+    pub sample_entry: [u8; 4],
+    /// Configuration box fourcc, e.g. `hvcC` or `av1C`
+    pub config_box: [u8; 4],
+    /// Configuration box payload (without the box header)
+    pub config_record: Vec<u8>,
+    /// RFC 6381 codec string returned by [`CmafMuxer::codec_string`]
+    pub codec_string: Option<String>,
+}
+
+impl PassthroughCodec {
+    /// A codec with the given sample entry and configuration box.
+    pub fn new(sample_entry: [u8; 4], config_box: [u8; 4], config_record: Vec<u8>) -> Self {
+        Self {
+            sample_entry,
+            config_box,
+            config_record,
+            codec_string: None,
         }
     }
+
+    /// HEVC with parameter sets in the `hvcC` record (`hvc1`).
+    pub fn hevc(hvcc: Vec<u8>) -> Self {
+        let codec_string = HevcProfileTierLevel::from_hvcc(&hvcc).map(|ptl| ptl.codec_string());
+        Self {
+            codec_string,
+            ..Self::new(*b"hvc1", *b"hvcC", hvcc)
+        }
+    }
+
+    /// AV1 with its `av1C` record (`av01`).
+    pub fn av1(av1c: Vec<u8>) -> Self {
+        Self {
+            codec_string: av1_codec_string(&av1c),
+            ..Self::new(*b"av01", *b"av1C", av1c)
+        }
+    }
+
+    /// Set the codec string for manifests and MSE.
+    pub fn with_codec_string(mut self, codec_string: impl Into<String>) -> Self {
+        self.codec_string = Some(codec_string.into());
+        self
+    }
 }
 
 /// A pending frame waiting to be muxed.
@@ -71,7 +193,18 @@ struct PendingFrame {
     composition_offset: i32,
 }
 
-/// Fragmented MP4 muxer for H.264 video streams.
+/// A frame held back until the next one gives its duration.
+#[derive(Debug, Clone)]
+struct HeldFrame {
+    data: Vec<u8>,
+    pts: i64,
+    dts: i64,
+    duration: u32,
+    is_keyframe: bool,
+}
+
+/// Fragmented MP4 muxer for H.264 video streams, or other codecs passed
+/// through with a prebuilt [`PassthroughCodec`].
 pub struct CmafMuxer {
     config: CmafConfig,
     /// Whether initialization segment has been created
@@ -94,12 +227,46 @@ pub struct CmafMuxer {
     last_dts: i64,
     /// Track ID
     track_id: u32,
+    /// End DTS of the last emitted fragment
+    emitted_end_dts: i64,
+    /// Duration of the last emitted fragment in timescale units
+    last_fragment_duration: i64,
+    /// DTS the next frame is mapped to after [`CmafMuxer::resume`]
+    resume_dts: Option<i64>,
+    /// Offset added to incoming timestamps to continue a resumed stream
+    dts_offset: i64,
+    /// Start a new fragment at the next keyframe regardless of duration
+    split_at_next_keyframe: bool,
+    /// Latest presentation time added so far
+    max_pts: Option<i64>,
+    /// Frame waiting for its successor with `variable_frame_rate`
+    held_frame: Option<HeldFrame>,
+    /// Written to the init segment's `moov`
+    metadata: Option<Mp4Metadata>,
+    /// Sample description of a passthrough stream, replacing avc1
+    passthrough: Option<PassthroughCodec>,
+    /// Visible region of padded frames, written as `clap`
+    clean_aperture: Option<CleanAperture>,
 }
 
 impl CmafMuxer {
     /// Create a new CMAF muxer with the given configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.nal_length_size` is not 1, 2 or 4; use
+    /// [`CmafMuxer::try_new`] to handle this as an error.
     pub fn new(config: CmafConfig) -> Self {
-        Self {
+        match Self::try_new(config) {
+            Ok(muxer) => muxer,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Create a new CMAF muxer, validating the configuration.
+    pub fn try_new(config: CmafConfig) -> Result<Self, NalError> {
+        validate_nal_length_size(config.nal_length_size)?;
+        Ok(Self {
             config,
             initialized: false,
             width: 0,
@@ -111,6 +278,104 @@ impl CmafMuxer {
             fragment_base_dts: 0,
             last_dts: 0,
             track_id: 1,
+            emitted_end_dts: 0,
+            last_fragment_duration: 0,
+            resume_dts: None,
+            dts_offset: 0,
+            split_at_next_keyframe: false,
+            max_pts: None,
+            held_frame: None,
+            metadata: None,
+            passthrough: None,
+            clean_aperture: None,
+        })
+    }
+
+    /// Create a muxer that continues a stream from a saved [`MuxerState`].
+    ///
+    /// Sequence numbers continue from the state, and incoming timestamps are
+    /// shifted so the first frame added starts at the state's `next_dts`,
+    /// whatever timeline the restarted encoder uses. The timescale, NAL length
+    /// size and track configuration are taken from the state so the stream's
+    /// init segment is unchanged; if the state includes parameter sets the
+    /// muxer is initialized and [`CmafMuxer::init_segment`] regenerates it.
+    /// A passthrough stream's configuration is not part of the state: call
+    /// [`CmafMuxer::create_passthrough_init_segment`] again after resuming.
+    pub fn resume(config: CmafConfig, state: &MuxerState) -> Result<Self, NalError> {
+        let mut muxer = Self::try_new(CmafConfig {
+            timescale: state.timescale,
+            nal_length_size: state.nal_length_size,
+            ..config
+        })?;
+        muxer.sequence_number = state.sequence_number;
+        muxer.track_id = state.track_id;
+        muxer.emitted_end_dts = state.next_dts;
+        muxer.resume_dts = Some(state.next_dts);
+        muxer.width = state.width;
+        muxer.height = state.height;
+        if !state.sps.is_empty() && !state.pps.is_empty() {
+            muxer.sps = state.sps.clone();
+            muxer.pps = state.pps.clone();
+            muxer.initialized = true;
+        }
+        Ok(muxer)
+    }
+
+    /// Snapshot the state needed to continue this stream after a restart.
+    ///
+    /// Frames still pending in the current fragment are not covered: the
+    /// state resumes right after the last emitted segment. Save it after each
+    /// segment (see [`MuxerState::save`]).
+    pub fn snapshot(&self) -> MuxerState {
+        MuxerState {
+            sequence_number: self.sequence_number,
+            next_dts: self.emitted_end_dts,
+            timescale: self.config.timescale,
+            track_id: self.track_id,
+            nal_length_size: self.config.nal_length_size,
+            width: self.width,
+            height: self.height,
+            sps: self.sps.clone(),
+            pps: self.pps.clone(),
+        }
+    }
+
+    /// Regenerate the initialization segment from the current track
+    /// configuration, or `None` before initialization.
+    pub fn init_segment(&self) -> Option<Vec<u8>> {
+        if !self.initialized {
+            return None;
+        }
+        let mut buf = Vec::new();
+        self.write_ftyp(&mut buf);
+        self.write_moov(&mut buf);
+        Some(buf)
+    }
+
+    /// Metadata for the init segment (see [`Mp4Metadata`]). Set it before
+    /// [`create_init_segment`](Self::create_init_segment), or regenerate the
+    /// segment with [`init_segment`](Self::init_segment).
+    pub fn set_metadata(&mut self, metadata: Mp4Metadata) {
+        self.metadata = (!metadata.is_empty()).then_some(metadata);
+    }
+
+    /// Visible size of frames padded to the encoder's alignment (see
+    /// [`pad_pixel_buffer`](super::pad_pixel_buffer)). The init segment
+    /// signals it with a `clap` box and as the track's display size, while
+    /// the sample entry keeps the coded size. Set it before
+    /// [`create_init_segment`](Self::create_init_segment), or regenerate the
+    /// segment with [`init_segment`](Self::init_segment).
+    pub fn set_clean_aperture(&mut self, aperture: Option<CleanAperture>) {
+        self.clean_aperture = aperture;
+    }
+
+    /// Display size: the clean aperture if it crops the coded size.
+    fn display_size(&self) -> (u32, u32) {
+        match self.clean_aperture {
+            Some(aperture) if aperture.crops(self.width, self.height) => {
+                (aperture.width, aperture.height)
+            }
+            _ => (self.width, self.height),
         }
     }
 
@@ -128,6 +393,7 @@ impl CmafMuxer {
     pub fn create_init_segment(&mut self, sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
         self.sps = sps.to_vec();
         self.pps = pps.to_vec();
+        self.passthrough = None;
         self.width = width;
         self.height = height;
         self.initialized = true;
@@ -143,10 +409,42 @@ impl CmafMuxer {
         buf
     }
 
+    /// Create the initialization segment for a stream of another codec,
+    /// using its prebuilt configuration record instead of SPS/PPS.
+    ///
+    /// Add its samples with [`CmafMuxer::add_sample`]; the H.264-specific
+    /// [`CmafMuxer::add_frame`] does not apply. `nal_length_size` and
+    /// `profile_level` in the configuration are ignored.
+    pub fn create_passthrough_init_segment(
+        &mut self,
+        codec: PassthroughCodec,
+        width: u32,
+        height: u32,
+    ) -> Vec<u8> {
+        self.sps.clear();
+        self.pps.clear();
+        self.passthrough = Some(codec);
+        self.width = width;
+        self.height = height;
+        self.initialized = true;
+
+        let mut buf = Vec::new();
+        self.write_ftyp(&mut buf);
+        self.write_moov(&mut buf);
+        buf
+    }
+
+    /// The passthrough codec set by
+    /// [`create_passthrough_init_segment`](Self::create_passthrough_init_segment).
+    pub fn passthrough_codec(&self) -> Option<&PassthroughCodec> {
+        self.passthrough.as_ref()
+    }
+
     /// Add an encoded frame to the muxer.
     ///
     /// Returns a media segment when enough frames have accumulated or when a
-    /// new keyframe arrives after the target fragment duration.
+    /// new keyframe arrives after the target fragment duration, or for every
+    /// frame or mini-GOP as configured by [`CmafConfig::emission`].
     ///
     /// # Arguments
     /// * `nal_units` - NAL units for this frame (video slices, not SPS/PPS)
@@ -154,6 +452,9 @@ impl CmafMuxer {
     /// * `dts` - Decode timestamp in timescale units
     /// * `duration` - Frame duration in timescale units
     /// * `is_keyframe` - Whether this is a sync sample (IDR frame)
+    ///
+    /// Frames containing a NAL unit too large for the configured
+    /// `nal_length_size` are dropped; use [`CmafMuxer::try_add_frame`] to detect this.
     pub fn add_frame(
         &mut self,
         nal_units: &[NalUnit],
@@ -162,18 +463,125 @@ impl CmafMuxer {
         duration: u32,
         is_keyframe: bool,
     ) -> Option<Vec<u8>> {
+        self.try_add_frame(nal_units, pts, dts, duration, is_keyframe)
+            .unwrap_or(None)
+    }
+
+    /// Add a frame, reporting NAL units that don't fit the length prefix.
+    ///
+    /// The frame is not added when an error is returned. With
+    /// [`variable_frame_rate`](CmafConfig::variable_frame_rate), a
+    /// [`NalError::FrameReordering`] error refers to the previously added frame.
+    pub fn try_add_frame(
+        &mut self,
+        nal_units: &[NalUnit],
+        pts: i64,
+        dts: i64,
+        duration: u32,
+        is_keyframe: bool,
+    ) -> Result<Option<Vec<u8>>, NalError> {
         if !self.initialized {
-            return None;
+            return Ok(None);
+        }
+
+        // Convert NAL units to AVCC format for mdat
+        let data = self.nal_units_to_avcc(nal_units)?;
+        self.push_sample(data, pts, dts, duration, is_keyframe)
+    }
+
+    /// Add a frame whose sample data is already in the stream's sample
+    /// format: length-prefixed NAL units for H.264 and HEVC, or the sample
+    /// format of a [`PassthroughCodec`]. The data is written to `mdat`
+    /// unchanged.
+    ///
+    /// Fragmenting and timing work as for [`CmafMuxer::try_add_frame`].
+    pub fn add_sample(
+        &mut self,
+        data: Vec<u8>,
+        pts: i64,
+        dts: i64,
+        duration: u32,
+        is_keyframe: bool,
+    ) -> Result<Option<Vec<u8>>, NalError> {
+        if !self.initialized {
+            return Ok(None);
+        }
+        self.push_sample(data, pts, dts, duration, is_keyframe)
+    }
+
+    /// Add a sample, holding it back first for variable frame rate.
+    fn push_sample(
+        &mut self,
+        data: Vec<u8>,
+        pts: i64,
+        dts: i64,
+        duration: u32,
+        is_keyframe: bool,
+    ) -> Result<Option<Vec<u8>>, NalError> {
+        if !self.config.variable_frame_rate {
+            return self.add_avcc_frame(data, pts, dts, duration, is_keyframe);
+        }
+        let frame = HeldFrame {
+            data,
+            pts,
+            dts,
+            duration,
+            is_keyframe,
+        };
+        match self.held_frame.replace(frame) {
+            Some(previous) => {
+                // Timestamps that don't advance keep the duration passed in
+                let measured = u32::try_from(dts - previous.dts).ok().filter(|&d| d > 0);
+                let duration = measured.unwrap_or(previous.duration);
+                self.add_avcc_frame(
+                    previous.data,
+                    previous.pts,
+                    previous.dts,
+                    duration,
+                    previous.is_keyframe,
+                )
+            }
+            None => Ok(None),
         }
+    }
+
+    /// Add a frame already converted to length-prefixed NAL units.
+    fn add_avcc_frame(
+        &mut self,
+        data: Vec<u8>,
+        pts: i64,
+        dts: i64,
+        duration: u32,
+        is_keyframe: bool,
+    ) -> Result<Option<Vec<u8>>, NalError> {
+        // Continue a resumed stream's timeline
+        let dts_offset = match self.resume_dts {
+            Some(resume_dts) => resume_dts - dts,
+            None => self.dts_offset,
+        };
+        let pts = pts + dts_offset;
+        let dts = dts + dts_offset;
+
+        // A frame displayed before an earlier one belongs to its mini-GOP
+        let reordered = self.max_pts.is_some_and(|max_pts| pts < max_pts);
+        if reordered && self.config.emission == FragmentEmission::PerFrame {
+            return Err(NalError::FrameReordering);
+        }
+        self.resume_dts = None;
+        self.dts_offset = dts_offset;
 
         // Check if we should start a new fragment
         let should_flush = if self.pending_frames.is_empty() {
             false
+        } else if self.config.emission == FragmentEmission::PerMiniGop {
+            !reordered
         } else {
             // Flush if we have a keyframe and exceeded target duration
             let fragment_duration =
                 (dts - self.fragment_base_dts) * 1000 / self.config.timescale as i64;
-            is_keyframe && fragment_duration >= self.config.fragment_duration_ms as i64
+            is_keyframe
+                && (self.split_at_next_keyframe
+                    || fragment_duration >= self.config.fragment_duration_ms as i64)
         };
 
         let segment = if should_flush {
@@ -182,9 +590,6 @@ impl CmafMuxer {
             None
         };
 
-        // Convert NAL units to AVCC format for mdat
-        let data = self.nal_units_to_avcc(nal_units);
-
         // If this is the first frame in a fragment, record base DTS
         if self.pending_frames.is_empty() {
             self.fragment_base_dts = dts;
@@ -200,37 +605,84 @@ impl CmafMuxer {
         });
 
         self.last_dts = dts;
+        self.max_pts = Some(self.max_pts.map_or(pts, |max_pts| max_pts.max(pts)));
 
-        segment
+        if self.config.emission == FragmentEmission::PerFrame {
+            return Ok(Some(self.flush_fragment()));
+        }
+        Ok(segment)
+    }
+
+    /// Start a new segment at the next keyframe, even if the current fragment
+    /// is shorter than `fragment_duration_ms`.
+    ///
+    /// Call together with [`CompressionSession::force_keyframe`](super::CompressionSession::force_keyframe)
+    /// at scene cuts so segments begin on the new scene.
+    pub fn split_at_next_keyframe(&mut self) {
+        self.split_at_next_keyframe = true;
+    }
+
+    /// Lengthen the most recently added frame by `duration`, e.g. over the
+    /// gap left by a frame the encoder dropped (see
+    /// [`PipelineEvent::FrameDropped`](super::PipelineEvent::FrameDropped)).
+    /// Not needed with [`variable_frame_rate`](CmafConfig::variable_frame_rate),
+    /// which measures durations from the timestamps. The duration saturates
+    /// at `u32::MAX` ticks.
+    ///
+    /// Returns false if the frame has already been emitted in a segment.
+    pub fn extend_last_frame(&mut self, duration: u32) -> bool {
+        if let Some(frame) = self.held_frame.as_mut() {
+            frame.duration = frame.duration.saturating_add(duration);
+            return true;
+        }
+        match self.pending_frames.last_mut() {
+            Some(frame) => {
+                frame.duration = frame.duration.saturating_add(duration);
+                true
+            }
+            None => false,
+        }
     }
 
     /// Flush any remaining frames as a final segment.
     ///
-    /// Call this when encoding is complete to get the last fragment.
+    /// Call this when encoding is complete to get the last fragment. A frame
+    /// held back for [`variable_frame_rate`](CmafConfig::variable_frame_rate)
+    /// that starts a new fragment is returned in the same segment, after the
+    /// fragment it closes.
     pub fn flush(&mut self) -> Option<Vec<u8>> {
-        if self.pending_frames.is_empty() {
-            return None;
+        let mut segment = match self.held_frame.take() {
+            Some(held) => self
+                .add_avcc_frame(held.data, held.pts, held.dts, held.duration, held.is_keyframe)
+                .unwrap_or(None),
+            None => None,
+        };
+        if !self.pending_frames.is_empty() {
+            let last = self.flush_fragment();
+            match segment.as_mut() {
+                Some(segment) => segment.extend(last),
+                None => segment = Some(last),
+            }
         }
-        Some(self.flush_fragment())
+        segment
     }
 
     /// Convert NAL units to AVCC format (length-prefixed).
-    fn nal_units_to_avcc(&self, nal_units: &[NalUnit]) -> Vec<u8> {
+    fn nal_units_to_avcc(&self, nal_units: &[NalUnit]) -> Result<Vec<u8>, NalError> {
+        let length_size = self.config.nal_length_size;
         let total_size: usize = nal_units
             .iter()
             .filter(|n| n.is_slice()) // Only include video slices
-            .map(|n| 4 + n.data.len())
+            .map(|n| length_size + n.data.len())
             .sum();
 
         let mut buf = Vec::with_capacity(total_size);
 
         for nal in nal_units.iter().filter(|n| n.is_slice()) {
-            let len = nal.data.len() as u32;
-            buf.extend_from_slice(&len.to_be_bytes());
-            buf.extend_from_slice(&nal.data);
+            write_length_prefixed(&mut buf, &nal.data, length_size)?;
         }
 
-        buf
+        Ok(buf)
     }
 
     /// Create a media segment from pending frames.
@@ -246,8 +698,12 @@ impl CmafMuxer {
         // mdat box
         self.write_mdat(&mut buf);
 
+        let fragment_duration: i64 = self.pending_frames.iter().map(|f| f.duration as i64).sum();
+        self.emitted_end_dts = self.fragment_base_dts + fragment_duration;
+        self.last_fragment_duration = fragment_duration;
         self.sequence_number += 1;
         self.pending_frames.clear();
+        self.split_at_next_keyframe = false;
 
         buf
     }
@@ -262,7 +718,7 @@ impl CmafMuxer {
             b"iso6", // ISO with fragments
             b"cmfc", // CMAF compliant
             b"cmfv", // CMAF video track
-            b"avc1", // H.264
+            self.sample_entry_type(), // codec brand, e.g. avc1 for H.264
             b"mp41", // MP4 v1
         ];
 
@@ -305,6 +761,11 @@ impl CmafMuxer {
         // mvex (movie extends - required for fragmented MP4)
         self.write_mvex(&mut moov_content);
 
+        // udta (user data) with the title, date and other metadata
+        if let Some(metadata) = &self.metadata {
+            moov_content.extend_from_slice(&metadata.udta_box());
+        }
+
         let size = 8 + moov_content.len();
         buf.extend_from_slice(&(size as u32).to_be_bytes());
         buf.extend_from_slice(b"moov");
@@ -317,8 +778,9 @@ impl CmafMuxer {
         content.push(0); // version
         content.extend_from_slice(&[0, 0, 0]); // flags
 
-        content.extend_from_slice(&0u32.to_be_bytes()); // creation time
-        content.extend_from_slice(&0u32.to_be_bytes()); // modification time
+        let created = self.metadata.as_ref().and_then(|m| m.mp4_creation_time());
+        content.extend_from_slice(&created.unwrap_or(0).to_be_bytes()); // creation time
+        content.extend_from_slice(&created.unwrap_or(0).to_be_bytes()); // modification time
         content.extend_from_slice(&self.config.timescale.to_be_bytes()); // timescale
         content.extend_from_slice(&0u32.to_be_bytes()); // duration (unknown for live)
 
@@ -382,9 +844,10 @@ impl CmafMuxer {
             content.extend_from_slice(&m.to_be_bytes());
         }
 
-        // Width and height as 16.16 fixed point
-        content.extend_from_slice(&((self.width as u32) << 16).to_be_bytes());
-        content.extend_from_slice(&((self.height as u32) << 16).to_be_bytes());
+        // Display width and height as 16.16 fixed point
+        let (width, height) = self.display_size();
+        content.extend_from_slice(&(width << 16).to_be_bytes());
+        content.extend_from_slice(&(height << 16).to_be_bytes());
 
         let size = 8 + content.len();
         buf.extend_from_slice(&(size as u32).to_be_bytes());
@@ -516,8 +979,8 @@ impl CmafMuxer {
         stsd_content.extend_from_slice(&[0, 0, 0]); // flags
         stsd_content.extend_from_slice(&1u32.to_be_bytes()); // entry_count
 
-        // avc1 sample entry
-        self.write_avc1(&mut stsd_content);
+        // avc1 sample entry, or the passthrough codec's
+        self.write_sample_entry(&mut stsd_content);
 
         let size = 8 + stsd_content.len();
         buf.extend_from_slice(&(size as u32).to_be_bytes());
@@ -525,7 +988,7 @@ impl CmafMuxer {
         buf.extend_from_slice(&stsd_content);
     }
 
-    fn write_avc1(&self, buf: &mut Vec<u8>) {
+    fn write_sample_entry(&self, buf: &mut Vec<u8>) {
         let mut avc1_content = Vec::new();
 
         avc1_content.extend_from_slice(&[0; 6]); // reserved
@@ -553,30 +1016,46 @@ impl CmafMuxer {
         avc1_content.extend_from_slice(&0x0018u16.to_be_bytes()); // depth (24-bit)
         avc1_content.extend_from_slice(&(-1i16).to_be_bytes()); // pre_defined
 
-        // avcC box
-        self.write_avcc(&mut avc1_content);
+        // avcC box, or the passthrough configuration box
+        match &self.passthrough {
+            Some(codec) => {
+                let size = 8 + codec.config_record.len();
+                avc1_content.extend_from_slice(&(size as u32).to_be_bytes());
+                avc1_content.extend_from_slice(&codec.config_box);
+                avc1_content.extend_from_slice(&codec.config_record);
+            }
+            None => self.write_avcc(&mut avc1_content),
+        }
+        if let Some(aperture) = self.clean_aperture {
+            if aperture.crops(self.width, self.height) {
+                avc1_content.extend_from_slice(&aperture.clap_box(self.width, self.height));
+            }
+        }
 
         let size = 8 + avc1_content.len();
         buf.extend_from_slice(&(size as u32).to_be_bytes());
-        buf.extend_from_slice(b"avc1");
+        buf.extend_from_slice(self.sample_entry_type());
         buf.extend_from_slice(&avc1_content);
     }
 
+    /// Sample entry fourcc: `avc1`, or the passthrough codec's.
+    fn sample_entry_type(&self) -> &[u8; 4] {
+        match &self.passthrough {
+            Some(codec) => &codec.sample_entry,
+            None => b"avc1",
+        }
+    }
+
     fn write_avcc(&self, buf: &mut Vec<u8>) {
         let mut avcc_content = Vec::new();
 
         avcc_content.push(1); // configuration_version
 
-        // Profile, compatibility, and level from SPS
-        if self.sps.len() >= 4 {
-            avcc_content.push(self.sps[1]); // profile_idc
-            avcc_content.push(self.sps[2]); // profile_compatibility
-            avcc_content.push(self.sps[3]); // level_idc
-        } else {
-            avcc_content.extend_from_slice(&[0x64, 0x00, 0x1f]); // High profile, level 3.1
-        }
+        // profile_idc, profile_compatibility, level_idc
+        avcc_content.extend_from_slice(&self.avcc_profile_level());
 
-        avcc_content.push(0xFF); // length_size_minus_one (3 = 4 bytes) | reserved (0b111111)
+        // reserved (0b111111) | length_size_minus_one
+        avcc_content.push(0xFC | (self.config.nal_length_size as u8 - 1));
 
         // SPS
         avcc_content.push(0xE1); // num_sps | reserved (0b111)
@@ -594,6 +1073,30 @@ impl CmafMuxer {
         buf.extend_from_slice(&avcc_content);
     }
 
+    /// Profile and level bytes for avcC: configured, else from the SPS, else
+    /// derived from the frame size.
+    fn avcc_profile_level(&self) -> [u8; 3] {
+        if let Some(profile_level) = self.config.profile_level {
+            return profile_level.avcc_bytes();
+        }
+        // SPS NAL header (type 7) followed by profile_idc, constraint flags, level_idc
+        if self.sps.len() >= 4 && self.sps[0] & 0x1F == 7 {
+            return [self.sps[1], self.sps[2], self.sps[3]];
+        }
+        let params = StreamParams {
+            width: self.width,
+            height: self.height,
+            frame_rate: 30.0,
+            bitrate: None,
+        };
+        let level = derive_level(Profile::High, &params).unwrap_or(Level::L6_2);
+        ProfileLevel {
+            profile: Profile::High,
+            level,
+        }
+        .avcc_bytes()
+    }
+
     fn write_empty_stts(&self, buf: &mut Vec<u8>) {
         let mut content = Vec::new();
         content.push(0); // version
@@ -829,20 +1332,165 @@ impl CmafMuxer {
         }
     }
 
+    /// The configuration the muxer was created with.
+    pub fn config(&self) -> &CmafConfig {
+        &self.config
+    }
+
     /// Get the current sequence number.
     pub fn sequence_number(&self) -> u32 {
         self.sequence_number
     }
 
+    /// Duration of the most recently emitted fragment.
+    pub fn last_fragment_duration(&self) -> Duration {
+        let timescale = self.config.timescale.max(1) as f64;
+        Duration::from_secs_f64(self.last_fragment_duration.max(0) as f64 / timescale)
+    }
+
+    /// RFC 6381 codec string matching the avcC in the init segment
+    /// (e.g. `avc1.640028`), or `None` before initialization. For a
+    /// passthrough stream, the [`PassthroughCodec::codec_string`].
+    pub fn codec_string(&self) -> Option<String> {
+        if let Some(codec) = &self.passthrough {
+            return codec.codec_string.clone();
+        }
+        self.initialized
+            .then(|| h264_codec_string_from_bytes(self.avcc_profile_level()))
+    }
+
     /// Check if the muxer has been initialized.
     pub fn is_initialized(&self) -> bool {
         self.initialized
     }
 
-    /// Get the number of pending frames.
+    /// Get the number of pending frames, including one held back for
+    /// [`variable_frame_rate`](CmafConfig::variable_frame_rate).
     pub fn pending_frame_count(&self) -> usize {
-        self.pending_frames.len()
+        self.pending_frames.len() + self.held_frame.is_some() as usize
+    }
+}
+
+/// Muxer state persisted across process restarts.
+///
+/// Stored as a small text file of `key value` lines, with parameter sets in hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MuxerState {
+    /// Sequence number of the next fragment
+    pub sequence_number: u32,
+    /// Base decode time of the next fragment, in `timescale` units
+    pub next_dts: i64,
+    pub timescale: u32,
+    pub track_id: u32,
+    pub nal_length_size: usize,
+    pub width: u32,
+    pub height: u32,
+    /// SPS NAL unit (empty if the muxer was not initialized)
+    pub sps: Vec<u8>,
+    /// PPS NAL unit (empty if the muxer was not initialized)
+    pub pps: Vec<u8>,
+}
+
+impl MuxerState {
+    /// Parse the text format. Returns the offending line number on error.
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let mut state = MuxerState {
+            sequence_number: 1,
+            next_dts: 0,
+            timescale: 90000,
+            track_id: 1,
+            nal_length_size: 4,
+            width: 0,
+            height: 0,
+            sps: Vec::new(),
+            pps: Vec::new(),
+        };
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').ok_or(line_no + 1)?;
+            let value = value.trim();
+            let parsed = match key {
+                "sequence_number" => value.parse().map(|v| state.sequence_number = v).is_ok(),
+                "next_dts" => value.parse().map(|v| state.next_dts = v).is_ok(),
+                "timescale" => value.parse().map(|v| state.timescale = v).is_ok(),
+                "track_id" => value.parse().map(|v| state.track_id = v).is_ok(),
+                "nal_length_size" => value.parse().map(|v| state.nal_length_size = v).is_ok(),
+                "width" => value.parse().map(|v| state.width = v).is_ok(),
+                "height" => value.parse().map(|v| state.height = v).is_ok(),
+                "sps" => parse_hex(value).map(|v| state.sps = v).is_some(),
+                "pps" => parse_hex(value).map(|v| state.pps = v).is_some(),
+                // Ignore unknown keys from newer versions
+                _ => true,
+            };
+            if !parsed {
+                return Err(line_no + 1);
+            }
+        }
+        Ok(state)
+    }
+
+    /// Load a state file written by [`MuxerState::save`].
+    pub fn load(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|line| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid muxer state at line {}", line),
+            )
+        })
+    }
+
+    /// Write the state atomically (to a temporary file, then renamed), so a
+    /// crash mid-write leaves the previous state intact.
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, self.to_string())?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+impl std::fmt::Display for MuxerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# CMAF muxer state")?;
+        writeln!(f, "sequence_number {}", self.sequence_number)?;
+        writeln!(f, "next_dts {}", self.next_dts)?;
+        writeln!(f, "timescale {}", self.timescale)?;
+        writeln!(f, "track_id {}", self.track_id)?;
+        writeln!(f, "nal_length_size {}", self.nal_length_size)?;
+        writeln!(f, "width {}", self.width)?;
+        writeln!(f, "height {}", self.height)?;
+        writeln!(f, "sps {}", to_hex(&self.sps))?;
+        writeln!(f, "pps {}", to_hex(&self.pps))
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    if data.is_empty() {
+        return String::from("-");
+    }
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if text == "-" {
+        return Some(Vec::new());
     }
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => Some((hex_digit(*hi)? << 4) | hex_digit(*lo)?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
 }
 
 #[cfg(test)]
@@ -874,6 +1522,278 @@ mod tests {
         assert!(init.windows(4).any(|w| w == b"moov"));
     }
 
+    fn avcc_profile_bytes(init: &[u8]) -> [u8; 3] {
+        let pos = init.windows(4).position(|w| w == b"avcC").unwrap();
+        // fourcc, then configuration_version
+        [init[pos + 5], init[pos + 6], init[pos + 7]]
+    }
+
+    #[test]
+    fn test_avcc_profile_level() {
+        let pps = [0x68, 0xee, 0x3c, 0x80];
+
+        // Well-formed SPS: bytes copied from it
+        let mut muxer = CmafMuxer::new(CmafConfig::default());
+        let init = muxer.create_init_segment(&[0x67, 0x4d, 0x40, 0x28, 0xac], &pps, 1920, 1080);
+        assert_eq!(avcc_profile_bytes(&init), [77, 0x40, 40]);
+        assert_eq!(muxer.codec_string().as_deref(), Some("avc1.4d4028"));
+
+        // Truncated SPS: derived from the frame size
+        let mut muxer = CmafMuxer::new(CmafConfig::default());
+        let init = muxer.create_init_segment(&[0x67], &pps, 1920, 1080);
+        assert_eq!(avcc_profile_bytes(&init), [100, 0x00, 40]);
+
+        // Configured profile/level wins
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            profile_level: Some(ProfileLevel {
+                profile: Profile::Baseline,
+                level: Level::L3_1,
+            }),
+            ..Default::default()
+        });
+        let init = muxer.create_init_segment(&[0x67, 0x64, 0x00, 0x1f], &pps, 1280, 720);
+        assert_eq!(avcc_profile_bytes(&init), [66, 0xC0, 31]);
+    }
+
+    fn slice(keyframe: bool) -> Vec<NalUnit> {
+        let nal_type = if keyframe { 5 } else { 1 };
+        vec![NalUnit {
+            nal_type,
+            data: vec![0x60 | nal_type, 0x88, 0x84],
+        }]
+    }
+
+    /// Decode time from the tfdt box of a media segment.
+    fn tfdt(segment: &[u8]) -> u64 {
+        let pos = segment.windows(4).position(|w| w == b"tfdt").unwrap();
+        u64::from_be_bytes(segment[pos + 8..pos + 16].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_snapshot_and_resume_continue_timeline() {
+        let sps = [0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50];
+        let pps = [0x68, 0xee, 0x3c, 0x80];
+        let config = CmafConfig {
+            fragment_duration_ms: 1000,
+            ..Default::default()
+        };
+
+        let mut muxer = CmafMuxer::new(config.clone());
+        let init = muxer.create_init_segment(&sps, &pps, 1280, 720);
+        let mut last = None;
+        for i in 0..60i64 {
+            let dts = 1_000_000 + i * 3000;
+            if let Some(segment) = muxer.add_frame(&slice(i % 30 == 0), dts, dts, 3000, i % 30 == 0) {
+                last = Some(segment);
+            }
+        }
+        assert_eq!(tfdt(&last.unwrap()), 1_000_000);
+        let state = muxer.snapshot();
+        assert_eq!(state.sequence_number, 2);
+        assert_eq!(state.next_dts, 1_000_000 + 30 * 3000);
+
+        // Round trip through the text format
+        let restored = MuxerState::parse(&state.to_string()).unwrap();
+        assert_eq!(restored, state);
+
+        // Restarted encoder starts its timeline at zero again
+        let mut resumed = CmafMuxer::resume(config, &restored).unwrap();
+        assert!(resumed.is_initialized());
+        assert_eq!(resumed.init_segment().unwrap(), init);
+        resumed.add_frame(&slice(true), 0, 0, 3000, true);
+        let segment = resumed.flush().unwrap();
+        assert_eq!(resumed.sequence_number(), 3);
+        assert_eq!(tfdt(&segment), state.next_dts as u64);
+    }
+
+    #[test]
+    fn test_muxer_state_parse_errors() {
+        assert_eq!(MuxerState::parse("sequence_number x\n"), Err(1));
+        assert_eq!(MuxerState::parse("# comment\nsps 6\n"), Err(2));
+        assert!(MuxerState::parse("future_key 1\n").is_ok());
+    }
+
+    #[test]
+    fn test_split_at_next_keyframe() {
+        let mut muxer = CmafMuxer::new(CmafConfig::default());
+        muxer.create_init_segment(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 1280, 720);
+        assert!(muxer.add_frame(&slice(true), 0, 0, 3000, true).is_none());
+        assert!(muxer.add_frame(&slice(false), 3000, 3000, 3000, false).is_none());
+        // A keyframe well before the 2 s target does not split by default
+        assert!(muxer.add_frame(&slice(true), 6000, 6000, 3000, true).is_none());
+
+        muxer.split_at_next_keyframe();
+        assert!(muxer.add_frame(&slice(false), 9000, 9000, 3000, false).is_none());
+        assert!(muxer.add_frame(&slice(true), 12000, 12000, 3000, true).is_some());
+        assert_eq!(muxer.pending_frame_count(), 1);
+        assert_eq!(muxer.last_fragment_duration(), Duration::from_secs_f64(12000.0 / 90000.0));
+
+        // The frame at 15000 was dropped by the encoder
+        assert!(muxer.extend_last_frame(3000));
+        muxer.flush().unwrap();
+        assert_eq!(muxer.last_fragment_duration(), Duration::from_secs_f64(6000.0 / 90000.0));
+        assert!(!muxer.extend_last_frame(3000));
+
+        muxer.add_frame(&slice(true), 18000, 18000, 3000, true);
+        assert!(muxer.extend_last_frame(u32::MAX));
+        assert_eq!(muxer.pending_frames.last().unwrap().duration, u32::MAX);
+    }
+
+    /// Sample durations from the trun box of a media segment.
+    fn trun_durations(segment: &[u8]) -> Vec<u32> {
+        let pos = segment.windows(4).position(|w| w == b"trun").unwrap() + 4;
+        let count = u32::from_be_bytes(segment[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        (0..count)
+            .map(|i| {
+                let at = pos + 12 + i * 16;
+                u32::from_be_bytes(segment[at..at + 4].try_into().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_variable_frame_rate_durations() {
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            variable_frame_rate: true,
+            ..Default::default()
+        });
+        muxer.create_init_segment(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 1280, 720);
+        // Capture intervals of 33, 40 and 27 ms, each passed in as 3000
+        for (i, dts) in [0i64, 2970, 6570, 9000].into_iter().enumerate() {
+            assert!(muxer.add_frame(&slice(i == 0), dts, dts, 3000, i == 0).is_none());
+        }
+        assert_eq!(muxer.pending_frame_count(), 4);
+        // The keyframe after a long gap closes the fragment one frame later
+        assert!(muxer.add_frame(&slice(true), 200_000, 200_000, 3000, true).is_none());
+        let segment = muxer.add_frame(&slice(false), 203_000, 203_000, 3000, false);
+        assert_eq!(trun_durations(&segment.unwrap()), [2970, 3600, 2430, 191_000]);
+
+        // The held frame is flushed with the duration it was added with
+        let last = muxer.flush().unwrap();
+        assert_eq!(trun_durations(&last), [3000, 3000]);
+        assert_eq!(muxer.pending_frame_count(), 0);
+    }
+
+    #[test]
+    fn test_emission_keeps_mini_gops_together() {
+        let sps = [0x67, 0x64, 0x00, 0x1f];
+        let pps = [0x68, 0xee];
+        // Decode order I0 P3 B1 B2 P6 B4 B5 (display times in frames)
+        let frames = [
+            (0, true),
+            (3, false),
+            (1, false),
+            (2, false),
+            (6, false),
+            (4, false),
+            (5, false),
+        ];
+
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            emission: FragmentEmission::PerMiniGop,
+            ..Default::default()
+        });
+        muxer.create_init_segment(&sps, &pps, 1280, 720);
+        let mut sizes = Vec::new();
+        for (i, &(pts, key)) in frames.iter().enumerate() {
+            let dts = i as i64 * 3000 - 3000;
+            if muxer.add_frame(&slice(key), pts * 3000, dts, 3000, key).is_some() {
+                sizes.push(muxer.last_fragment_duration().as_secs_f64() * 30.0);
+            }
+        }
+        // [I0], [P3 B1 B2], with [P6 B4 B5] still pending
+        assert_eq!(sizes.iter().map(|s| s.round() as u32).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(muxer.pending_frame_count(), 3);
+
+        // Per-frame emission rejects the first B-frame instead of splitting it off
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            emission: FragmentEmission::PerFrame,
+            ..Default::default()
+        });
+        muxer.create_init_segment(&sps, &pps, 1280, 720);
+        assert!(muxer.try_add_frame(&slice(true), 0, -3000, 3000, true).unwrap().is_some());
+        assert!(muxer.try_add_frame(&slice(false), 9000, 0, 3000, false).unwrap().is_some());
+        assert!(matches!(
+            muxer.try_add_frame(&slice(false), 3000, 3000, 3000, false),
+            Err(NalError::FrameReordering)
+        ));
+
+        let mut encoder = CompressionSessionConfig::new(1280, 720, crate::codecs::video::H264);
+        assert!(FragmentEmission::PerFrame.validate(&encoder).is_err());
+        assert!(FragmentEmission::PerMiniGop.validate(&encoder).is_ok());
+        encoder.allow_frame_reordering = Some(false);
+        assert!(FragmentEmission::PerFrame.validate(&encoder).is_ok());
+    }
+
+    #[test]
+    fn test_nal_length_size_config() {
+        let config = CmafConfig {
+            nal_length_size: 3,
+            ..Default::default()
+        };
+        assert!(matches!(
+            CmafMuxer::try_new(config),
+            Err(NalError::InvalidNalLengthSize(3))
+        ));
+
+        let mut muxer = CmafMuxer::try_new(CmafConfig {
+            nal_length_size: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let sps = vec![0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50];
+        let pps = vec![0x68, 0xee, 0x3c, 0x80];
+        let init = muxer.create_init_segment(&sps, &pps, 1920, 1080);
+
+        // lengthSizeMinusOne follows configurationVersion + profile/compat/level
+        let avcc = init.windows(4).position(|w| w == b"avcC").unwrap() + 4;
+        assert_eq!(init[avcc + 4], 0xFD);
+
+        let idr = NalUnit {
+            data: vec![0x65, 0x88, 0x84],
+            nal_type: 5,
+        };
+        muxer.add_frame(&[idr], 0, 0, 3000, true);
+        let segment = muxer.flush().unwrap();
+        assert!(segment.ends_with(&[0x00, 0x03, 0x65, 0x88, 0x84]));
+
+        let huge = NalUnit {
+            data: vec![0x41; 70_000],
+            nal_type: 1,
+        };
+        assert!(muxer.try_add_frame(&[huge], 3000, 3000, 3000, false).is_err());
+        assert_eq!(muxer.pending_frame_count(), 0);
+    }
+
+    #[test]
+    fn test_passthrough_codec() {
+        let av1c = vec![0x81, 0x08, 0x0c, 0x00];
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            emission: FragmentEmission::PerFrame,
+            ..Default::default()
+        });
+        let init = muxer.create_passthrough_init_segment(PassthroughCodec::av1(av1c), 1920, 1080);
+        assert!(init.windows(4).any(|w| w == b"av01"));
+        assert!(!init.windows(4).any(|w| w == b"avc1" || w == b"avcC"));
+        let pos = init.windows(4).position(|w| w == b"av1C").unwrap();
+        assert_eq!(&init[pos - 4..pos], &12u32.to_be_bytes());
+        assert_eq!(&init[pos + 4..pos + 8], &[0x81, 0x08, 0x0c, 0x00]);
+        assert_eq!(muxer.codec_string().as_deref(), Some("av01.0.08M.08"));
+
+        // Sample data goes into mdat unchanged
+        let obus = vec![0x12, 0x00, 0x0a, 0x0b];
+        let segment = muxer
+            .add_sample(obus.clone(), 0, 0, 3000, true)
+            .unwrap()
+            .unwrap();
+        assert!(segment.ends_with(&obus));
+
+        // Back to H.264
+        let init = muxer.create_init_segment(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 1280, 720);
+        assert!(init.windows(4).any(|w| w == b"avcC"));
+        assert_eq!(muxer.codec_string().as_deref(), Some("avc1.64001f"));
+    }
+
     #[test]
     fn test_ftyp_box() {
         let muxer = CmafMuxer::new(CmafConfig::default());
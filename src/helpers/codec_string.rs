@@ -0,0 +1,239 @@
+//! RFC 6381 codec strings for HLS/DASH manifests and MSE.
+//!
+//! H.264 strings (`avc1.PPCCLL`) come from the first three bytes of the SPS.
+//! HEVC strings (`hvc1.1.6.L120.90`) come from the `profile_tier_level`
+//! structure shared by the VPS and SPS, following ISO/IEC 14496-15 Annex E.
+//! AV1 strings (`av01.0.08M.08`) come from the av1C configuration record.
+
+use std::fmt;
+
+/// Codec string for an H.264 SPS (NAL header included, no start code).
+///
+/// Returns `None` if `sps` is not an SPS NAL unit.
+pub fn h264_codec_string(sps: &[u8]) -> Option<String> {
+    if sps.len() < 4 || sps[0] & 0x1F != 7 {
+        return None;
+    }
+    Some(h264_codec_string_from_bytes([sps[1], sps[2], sps[3]]))
+}
+
+/// Codec string from `[profile_idc, constraint flags, level_idc]`, as stored in avcC.
+pub fn h264_codec_string_from_bytes(profile_level: [u8; 3]) -> String {
+    format!(
+        "avc1.{:02x}{:02x}{:02x}",
+        profile_level[0], profile_level[1], profile_level[2]
+    )
+}
+
+/// The general profile, tier and level of an HEVC stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HevcProfileTierLevel {
+    /// `general_profile_space` (0 for all current profiles)
+    pub profile_space: u8,
+    /// `general_tier_flag`: false for Main tier, true for High tier
+    pub high_tier: bool,
+    /// `general_profile_idc` (1 = Main, 2 = Main 10)
+    pub profile_idc: u8,
+    /// `general_profile_compatibility_flags`, flag 0 in the most significant bit
+    pub compatibility_flags: u32,
+    /// The six bytes of general constraint indicator flags
+    pub constraint_flags: [u8; 6],
+    /// `general_level_idc` (30 x level, e.g. 120 for level 4)
+    pub level_idc: u8,
+}
+
+impl HevcProfileTierLevel {
+    /// Parse from a VPS (type 32) or SPS (type 33) NAL unit, including its
+    /// two-byte NAL header.
+    pub fn from_nal(nal: &[u8]) -> Option<Self> {
+        if nal.len() < 2 {
+            return None;
+        }
+        let nal_type = (nal[0] >> 1) & 0x3F;
+        // The profile_tier_level follows a 4-byte prefix in the VPS and a
+        // 1-byte prefix in the SPS.
+        let skip = match nal_type {
+            32 => 4,
+            33 => 1,
+            _ => return None,
+        };
+        let rbsp = remove_emulation_prevention(&nal[2..]);
+        Self::parse(rbsp.get(skip..)?)
+    }
+
+    /// Parse from an `HEVCDecoderConfigurationRecord` (the hvcC payload),
+    /// which starts with a copy of the general profile, tier and level.
+    pub fn from_hvcc(record: &[u8]) -> Option<Self> {
+        Self::parse(record.get(1..)?)
+    }
+
+    /// Parse the general part of `profile_tier_level` (12 bytes).
+    fn parse(data: &[u8]) -> Option<Self> {
+        let ptl = data.get(..12)?;
+        let mut constraint_flags = [0u8; 6];
+        constraint_flags.copy_from_slice(&ptl[5..11]);
+        Some(Self {
+            profile_space: ptl[0] >> 6,
+            high_tier: ptl[0] & 0x20 != 0,
+            profile_idc: ptl[0] & 0x1F,
+            compatibility_flags: u32::from_be_bytes([ptl[1], ptl[2], ptl[3], ptl[4]]),
+            constraint_flags,
+            level_idc: ptl[11],
+        })
+    }
+
+    /// The RFC 6381 codec string using the `hvc1` sample entry.
+    pub fn codec_string(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for HevcProfileTierLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let space = match self.profile_space {
+            1 => "A",
+            2 => "B",
+            3 => "C",
+            _ => "",
+        };
+        let tier = if self.high_tier { 'H' } else { 'L' };
+        // Compatibility flags are written in reverse bit order, without leading zeros
+        write!(
+            f,
+            "hvc1.{}{}.{:X}.{}{}",
+            space,
+            self.profile_idc,
+            self.compatibility_flags.reverse_bits(),
+            tier,
+            self.level_idc
+        )?;
+        // Constraint bytes, omitting trailing zero bytes
+        let used = self
+            .constraint_flags
+            .iter()
+            .rposition(|&b| b != 0)
+            .map_or(0, |last| last + 1);
+        for byte in &self.constraint_flags[..used] {
+            write!(f, ".{:X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Codec string for an HEVC VPS or SPS NAL unit.
+pub fn hevc_codec_string(nal: &[u8]) -> Option<String> {
+    HevcProfileTierLevel::from_nal(nal).map(|ptl| ptl.codec_string())
+}
+
+/// Codec string for an `AV1CodecConfigurationRecord` (the av1C payload),
+/// e.g. `av01.0.08M.08` for Main profile, level 4.0, Main tier, 8-bit.
+pub fn av1_codec_string(record: &[u8]) -> Option<String> {
+    // marker | version, then seq_profile | seq_level_idx_0, then
+    // seq_tier_0 | high_bitdepth | twelve_bit | ...
+    if record.len() < 4 || record[0] != 0x81 {
+        return None;
+    }
+    let profile = record[1] >> 5;
+    let level = record[1] & 0x1F;
+    let tier = if record[2] & 0x80 != 0 { 'H' } else { 'M' };
+    let bit_depth = match (record[2] & 0x40 != 0, record[2] & 0x20 != 0) {
+        (true, true) => 12,
+        (true, false) => 10,
+        _ => 8,
+    };
+    Some(format!("av01.{}.{:02}{}.{:02}", profile, level, tier, bit_depth))
+}
+
+/// MIME type with a `codecs` parameter for MSE `addSourceBuffer`, e.g.
+/// `video/mp4; codecs="avc1.640028"`.
+pub fn mp4_mime_type(codecs: &[&str]) -> String {
+    format!("video/mp4; codecs=\"{}\"", codecs.join(","))
+}
+
+/// Strip `emulation_prevention_three_byte`s (`00 00 03` -> `00 00`).
+fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_h264_codec_string() {
+        let sps = [0x67, 0x64, 0x00, 0x28, 0xac, 0xd9];
+        assert_eq!(h264_codec_string(&sps).as_deref(), Some("avc1.640028"));
+        assert_eq!(
+            h264_codec_string(&[0x67, 0x42, 0xc0, 0x1e]).as_deref(),
+            Some("avc1.42c01e")
+        );
+        // PPS is not an SPS
+        assert_eq!(h264_codec_string(&[0x68, 0xee, 0x3c, 0x80]), None);
+    }
+
+    #[test]
+    fn test_hevc_codec_string_from_vps() {
+        // Main profile, Main tier, level 4, progressive + frame-only flags
+        let vps = [
+            0x40, 0x01, 0x0c, 0x01, 0xff, 0xff, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00,
+            0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x78, 0x95, 0x98, 0x09,
+        ];
+        assert_eq!(hevc_codec_string(&vps).as_deref(), Some("hvc1.1.6.L120.90"));
+    }
+
+    #[test]
+    fn test_hevc_codec_string_from_sps() {
+        // Main 10, High tier, level 5.1
+        let sps = [
+            0x42, 0x01, 0x01, 0x22, 0x20, 0x00, 0x00, 0x03, 0x00, 0xb0, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x99, 0xa0,
+        ];
+        let ptl = HevcProfileTierLevel::from_nal(&sps).unwrap();
+        assert_eq!(ptl.profile_idc, 2);
+        assert!(ptl.high_tier);
+        assert_eq!(ptl.level_idc, 153);
+        assert_eq!(ptl.codec_string(), "hvc1.2.4.H153.B0");
+    }
+
+    #[test]
+    fn test_hevc_codec_string_from_hvcc() {
+        // configurationVersion, then the VPS example's profile_tier_level
+        let hvcc = [
+            0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78, 0xf0,
+        ];
+        let ptl = HevcProfileTierLevel::from_hvcc(&hvcc).unwrap();
+        assert_eq!(ptl.codec_string(), "hvc1.1.6.L120.90");
+    }
+
+    #[test]
+    fn test_av1_codec_string() {
+        assert_eq!(
+            av1_codec_string(&[0x81, 0x08, 0x0c, 0x00]).as_deref(),
+            Some("av01.0.08M.08")
+        );
+        // Professional profile, level 5.1, High tier, 12-bit
+        assert_eq!(
+            av1_codec_string(&[0x81, 0x4d, 0xe0, 0x00]).as_deref(),
+            Some("av01.2.13H.12")
+        );
+        assert_eq!(av1_codec_string(&[0x01, 0x08, 0x0c, 0x00]), None);
+    }
+
+    #[test]
+    fn test_mp4_mime_type() {
+        assert_eq!(
+            mp4_mime_type(&["avc1.640028", "mp4a.40.2"]),
+            "video/mp4; codecs=\"avc1.640028,mp4a.40.2\""
+        );
+    }
+}
@@ -4,26 +4,40 @@
 
 use core_foundation::base::TCFType;
 use core_foundation::boolean::CFBoolean;
-use core_foundation::dictionary::CFDictionary;
 use core_foundation::number::CFNumber;
 use core_foundation::string::CFString;
-use core_foundation_sys::base::{kCFAllocatorDefault, CFTypeRef, OSStatus};
+use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, CFTypeRef, OSStatus};
 use core_foundation_sys::dictionary::CFDictionaryRef;
 use core_foundation_sys::string::CFStringRef;
+use super::callback_target::{offload, CallbackTarget};
+use super::cf_dict::CFDictBuilder;
+use super::compression_property::CompressionProperty;
+use super::compression_session::{
+    output_trampoline, CompressionSession, EncodeCallback, EncodeOutput,
+};
 use super::cv_ffi::kCVPixelBufferPixelFormatTypeKey;
+use super::deterministic::{apply_deterministic, is_deterministic};
+use super::encoder_error::{aligned_dimensions, EncoderError};
+use super::events::{catch_callback_panic, CallbackScope};
+use super::profile_level::{Level, Profile, ProfileLevel, ProfileLevelError, StreamParams};
 use libc::c_void;
 use std::ptr;
+use std::time::Duration;
 
 use crate::codecs;
 use crate::compression::{
-    kVTCompressionPropertyKey_AverageBitRate, kVTCompressionPropertyKey_ExpectedFrameRate,
-    kVTCompressionPropertyKey_MaxKeyFrameInterval, kVTCompressionPropertyKey_ProfileLevel,
+    kVTCompressionPropertyKey_AllowFrameReordering, kVTCompressionPropertyKey_AverageBitRate,
+    kVTCompressionPropertyKey_ExpectedFrameRate, kVTCompressionPropertyKey_Quality,
+    kVTCompressionPropertyKey_MaxH264SliceBytes, kVTCompressionPropertyKey_MaxKeyFrameInterval,
+    kVTCompressionPropertyKey_ProfileLevel,
     kVTCompressionPropertyKey_RealTime,
     kVTVideoEncoderSpecification_EnableHardwareAcceleratedVideoEncoder,
     kVTVideoEncoderSpecification_EnableLowLatencyRateControl,
+    kVTVideoEncoderSpecification_EncoderID,
     VTCompressionSessionCreate, VTCompressionSessionInvalidate,
-    VTCompressionSessionPrepareToEncodeFrames, VTCompressionSessionRef,
+    VTCompressionSessionPrepareToEncodeFrames, VTCompressionSessionRef, EncodeInfoFlags,
 };
+use crate::errors::kVTParameterErr;
 use crate::session::VTSessionSetProperty;
 
 /// Configuration for a compression session.
@@ -39,6 +53,8 @@ pub struct CompressionSessionConfig {
     pub pixel_format: u32,
     /// Enable hardware acceleration
     pub hardware_accelerated: bool,
+    /// Select a specific encoder by ID (see the `list_encoders` example)
+    pub encoder_id: Option<String>,
     /// Enable low latency mode
     pub low_latency: bool,
     /// Enable real-time encoding
@@ -49,8 +65,32 @@ pub struct CompressionSessionConfig {
     pub frame_rate: Option<f64>,
     /// Maximum keyframe interval in frames
     pub keyframe_interval: Option<i32>,
-    /// H.264/HEVC profile level (CFString reference)
+    /// Encoding quality from 0.0 (fastest) to 1.0 (best)
+    pub quality: Option<f32>,
+    /// Allow B-frames (frame reordering)
+    pub allow_frame_reordering: Option<bool>,
+    /// Maximum H.264 slice size in bytes; frames larger than this are split
+    /// into several slices
+    pub max_slice_bytes: Option<i32>,
+    /// H.264/HEVC profile level (CFString reference). Takes precedence over
+    /// `profile`/`level`.
     pub profile_level: Option<CFStringRef>,
+    /// H.264 profile; the level is derived from size, frame rate and bitrate
+    pub profile: Option<Profile>,
+    /// H.264 level override, validated against size, frame rate and bitrate
+    pub level: Option<Level>,
+    /// Skip frames while an earlier frame is pending for longer than this
+    /// (see [`CompressionSession::set_frame_deadline`])
+    pub frame_deadline: Option<Duration>,
+    /// Further properties, set in order after the ones above (so they take
+    /// precedence)
+    pub properties: Vec<CompressionProperty>,
+    /// Where the [`build_session`](CompressionSessionBuilder::build_session)
+    /// output callback runs
+    pub callback_target: CallbackTarget,
+    /// Round `width` and `height` down to multiples of this when creating
+    /// the session (see [`CompressionSessionBuilder::round_dimensions`])
+    pub dimension_alignment: Option<u32>,
 }
 
 impl CompressionSessionConfig {
@@ -62,14 +102,51 @@ impl CompressionSessionConfig {
             codec,
             pixel_format: codecs::pixel::BGRA32,
             hardware_accelerated: true,
+            encoder_id: None,
             low_latency: false,
             real_time: true,
             bitrate: None,
             frame_rate: None,
             keyframe_interval: None,
+            quality: None,
+            allow_frame_reordering: None,
+            max_slice_bytes: None,
             profile_level: None,
+            profile: None,
+            level: None,
+            frame_deadline: None,
+            properties: Vec::new(),
+            callback_target: CallbackTarget::Inline,
+            dimension_alignment: None,
+        }
+    }
+
+    /// The dimensions the session is created with: `width` and `height`,
+    /// rounded to [`dimension_alignment`](Self::dimension_alignment) if set.
+    pub fn encoded_dimensions(&self) -> (i32, i32) {
+        match self.dimension_alignment {
+            Some(alignment) => aligned_dimensions(self.width, self.height, alignment),
+            None => (self.width, self.height),
         }
     }
+
+    /// Resolve the typed H.264 profile and level.
+    ///
+    /// Returns `Ok(None)` if neither is set or the codec is not H.264. A level
+    /// without a profile implies High profile.
+    pub fn resolve_profile_level(&self) -> Result<Option<ProfileLevel>, ProfileLevelError> {
+        if self.codec != codecs::video::H264 || (self.profile.is_none() && self.level.is_none()) {
+            return Ok(None);
+        }
+        let params = StreamParams {
+            width: self.width.max(0) as u32,
+            height: self.height.max(0) as u32,
+            frame_rate: self.frame_rate.unwrap_or(30.0),
+            bitrate: self.bitrate.map(|bps| bps.max(0) as u64),
+        };
+        let profile = self.profile.unwrap_or(Profile::High);
+        ProfileLevel::resolve(profile, self.level, &params).map(Some)
+    }
 }
 
 /// Builder for creating VTCompressionSession instances.
@@ -117,11 +194,20 @@ impl CompressionSessionBuilder {
     }
 
     /// Enable or disable hardware acceleration (default: true).
+    ///
+    /// Overridden in [deterministic mode](super::set_deterministic).
     pub fn hardware_accelerated(mut self, enabled: bool) -> Self {
         self.config.hardware_accelerated = enabled;
         self
     }
 
+    /// Select a specific encoder by ID, e.g. Apple's software H.264 encoder
+    /// `com.apple.videotoolbox.videoencoder.h264`.
+    pub fn encoder_id(mut self, id: impl Into<String>) -> Self {
+        self.config.encoder_id = Some(id.into());
+        self
+    }
+
     /// Enable or disable low latency mode (default: false).
     pub fn low_latency(mut self, enabled: bool) -> Self {
         self.config.low_latency = enabled;
@@ -152,6 +238,30 @@ impl CompressionSessionBuilder {
         self
     }
 
+    /// Set the encoding quality from 0.0 (favor speed) to 1.0 (best quality).
+    pub fn quality(mut self, quality: f32) -> Self {
+        self.config.quality = Some(quality.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Enable or disable frame reordering (B-frames).
+    pub fn allow_frame_reordering(mut self, enabled: bool) -> Self {
+        self.config.allow_frame_reordering = Some(enabled);
+        self
+    }
+
+    /// Limit H.264 slices to `bytes`, so each frame is split into several
+    /// independently decodable slices.
+    ///
+    /// For packetized transports, sizing slices to fit one packet (e.g.
+    /// 1200 bytes for RTP) means a lost packet costs one slice instead of the
+    /// whole frame. VideoToolbox has no direct slice count setting; the
+    /// count follows from the frame size. Ignored for other codecs.
+    pub fn max_slice_bytes(mut self, bytes: i32) -> Self {
+        self.config.max_slice_bytes = Some(bytes);
+        self
+    }
+
     /// Set the profile level (e.g., kVTProfileLevel_H264_High_AutoLevel).
     ///
     /// # Safety
@@ -162,15 +272,84 @@ impl CompressionSessionBuilder {
         self
     }
 
+    /// Set the H.264 profile. The lowest level that fits the frame size, frame
+    /// rate and bitrate is selected unless overridden with [`level`](Self::level).
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.config.profile = Some(profile);
+        self
+    }
+
+    /// Override the H.264 level.
+    ///
+    /// Building fails with `kVTParameterErr` if the level cannot carry the
+    /// configured stream; see [`validate_profile_level`](Self::validate_profile_level).
+    pub fn level(mut self, level: Level) -> Self {
+        self.config.level = Some(level);
+        self
+    }
+
+    /// Skip frames that would queue behind a frame pending for longer than
+    /// `deadline`, keeping latency bounded when the encoder falls behind.
+    pub fn frame_deadline(mut self, deadline: Duration) -> Self {
+        self.config.frame_deadline = Some(deadline);
+        self
+    }
+
+    /// Set any other compression property, e.g.
+    /// [`CompressionProperty::DataRateLimits`]. Applied after the dedicated
+    /// settings, overriding them.
+    ///
+    /// Building fails if the property is invalid or the encoder rejects it.
+    pub fn property(mut self, property: CompressionProperty) -> Self {
+        self.config.properties.push(property);
+        self
+    }
+
+    /// Set several properties; see [`property`](Self::property).
+    pub fn properties(mut self, properties: impl IntoIterator<Item = CompressionProperty>) -> Self {
+        self.config.properties.extend(properties);
+        self
+    }
+
+    /// Run the [`build_session`](Self::build_session) output callback on a
+    /// worker thread or dispatch queue instead of VideoToolbox's thread.
+    pub fn callback_target(mut self, target: CallbackTarget) -> Self {
+        self.config.callback_target = target;
+        self
+    }
+
+    /// Round the frame size down to multiples of `alignment` when building,
+    /// e.g. 2 so that a 1281x721 capture is encoded as 1280x720 instead of
+    /// failing with `kVTParameterErr`. See
+    /// [`encoder_alignment`](super::encoder_alignment) for the codec's
+    /// requirement. Off by default; source frames of the original size are
+    /// scaled to the rounded size by VideoToolbox.
+    pub fn round_dimensions(mut self, alignment: u32) -> Self {
+        self.config.dimension_alignment = Some(alignment);
+        self
+    }
+
+    /// Check the typed profile/level against the stream, returning the
+    /// resolved pair or a detailed error.
+    pub fn validate_profile_level(&self) -> Result<Option<ProfileLevel>, ProfileLevelError> {
+        let resolved = self.config.resolve_profile_level()?;
+        if let Some(profile_level) = resolved {
+            profile_level.vt_profile_level()?;
+        }
+        Ok(resolved)
+    }
+
     /// Build the compression session with the given output callback.
     ///
-    /// The callback is invoked when encoded frames are ready.
+    /// The callback is invoked when encoded frames are ready. A panic in the
+    /// callback is caught and reported as
+    /// [`PipelineEvent::CallbackPanicked`](super::PipelineEvent::CallbackPanicked).
     ///
     /// # Arguments
     ///
     /// * `callback` - Function called for each encoded frame with signature:
     ///   `fn(output_ref: *mut c_void, source_ref: *mut c_void, status: OSStatus,
-    ///      info_flags: u32, sample_buffer: *mut c_void)`
+    ///      info_flags: EncodeInfoFlags, sample_buffer: *mut c_void)`
     ///
     /// # Safety
     ///
@@ -178,7 +357,7 @@ impl CompressionSessionBuilder {
     /// lifetime of the compression session.
     pub fn build<F>(self, callback: F) -> Result<VTCompressionSessionRef, OSStatus>
     where
-        F: Fn(*mut c_void, *mut c_void, OSStatus, u32, *mut c_void) + 'static,
+        F: Fn(*mut c_void, *mut c_void, OSStatus, EncodeInfoFlags, *mut c_void) + 'static,
     {
         // Box the callback and leak it - caller is responsible for cleanup
         let callback_box = Box::new(callback);
@@ -195,6 +374,65 @@ impl CompressionSessionBuilder {
         }
     }
 
+    /// Build an owned [`CompressionSession`] with the given output callback.
+    ///
+    /// Unlike [`build`](Self::build), the callback is freed with the session.
+    pub fn build_session<F>(self, callback: F) -> Result<CompressionSession, OSStatus>
+    where
+        F: Fn(EncodeOutput) + Send + Sync + 'static,
+    {
+        let (width, height) = self.config.encoded_dimensions();
+        let pixel_format = self.config.pixel_format;
+        let frame_deadline = self.config.frame_deadline;
+        let callback = offload(
+            self.config.callback_target,
+            "compression output",
+            encoded_sample_buffer,
+            callback,
+        );
+        let callback: *mut EncodeCallback = Box::into_raw(Box::new(EncodeCallback::new(callback)));
+        match unsafe { self.create_session(Some(output_trampoline), callback as *mut c_void) } {
+            Ok(session) => {
+                let session =
+                    CompressionSession::from_raw(session, callback, width, height, pixel_format);
+                session.set_frame_deadline(frame_deadline);
+                Ok(session)
+            }
+            Err(status) => {
+                drop(unsafe { Box::from_raw(callback) });
+                Err(status)
+            }
+        }
+    }
+
+    /// Like [`build_session`](Self::build_session), but classifying a
+    /// failure as an [`EncoderError`] with a suggested remediation.
+    pub fn try_build_session<F>(self, callback: F) -> Result<CompressionSession, EncoderError>
+    where
+        F: Fn(EncodeOutput) + Send + Sync + 'static,
+    {
+        let mut config = self.config.clone();
+        (config.width, config.height) = config.encoded_dimensions();
+        self.build_session(callback)
+            .map_err(|status| EncoderError::classify(status, &config))
+    }
+
+    /// Build an owned [`CompressionSession`] and warm up the encoder.
+    ///
+    /// See [`CompressionSession::prewarm`]. Returns the session together with
+    /// the measured warm-up time.
+    pub fn build_prewarmed<F>(
+        self,
+        callback: F,
+    ) -> Result<(CompressionSession, Duration), OSStatus>
+    where
+        F: Fn(EncodeOutput) + Send + Sync + 'static,
+    {
+        let session = self.build_session(callback)?;
+        let warm_up = session.prewarm()?;
+        Ok((session, warm_up))
+    }
+
     /// Build the compression session with a raw callback and context pointer.
     ///
     /// This is the low-level API for when you need full control over the callback.
@@ -219,49 +457,40 @@ impl CompressionSessionBuilder {
         >,
         context: *mut c_void,
     ) -> Result<VTCompressionSessionRef, OSStatus> {
-        let config = &self.config;
+        let mut config = self.config.clone();
+        if is_deterministic() {
+            apply_deterministic(&mut config);
+        }
+        (config.width, config.height) = config.encoded_dimensions();
+        let config = &config;
+
+        let profile_level = match config.profile_level {
+            Some(profile_level) => Some(profile_level),
+            None => match self.validate_profile_level() {
+                Ok(resolved) => resolved.and_then(|pl| pl.vt_profile_level().ok()),
+                Err(_) => return Err(kVTParameterErr),
+            },
+        };
 
         // Build encoder specification
-        let mut encoder_spec_pairs = Vec::new();
-
-        let hw_key = CFString::wrap_under_get_rule(
-            kVTVideoEncoderSpecification_EnableHardwareAcceleratedVideoEncoder as CFStringRef,
+        let mut encoder_spec = CFDictBuilder::new().bool(
+            kVTVideoEncoderSpecification_EnableHardwareAcceleratedVideoEncoder,
+            config.hardware_accelerated,
         );
-        let hw_value = if config.hardware_accelerated {
-            CFBoolean::true_value()
-        } else {
-            CFBoolean::false_value()
-        };
-        encoder_spec_pairs.push((hw_key.as_CFType(), hw_value.as_CFType()));
-
+        if let Some(id) = &config.encoder_id {
+            encoder_spec.insert(kVTVideoEncoderSpecification_EncoderID, id);
+        }
         if config.low_latency {
-            let ll_key = CFString::wrap_under_get_rule(
-                kVTVideoEncoderSpecification_EnableLowLatencyRateControl as CFStringRef,
-            );
-            encoder_spec_pairs.push((ll_key.as_CFType(), CFBoolean::true_value().as_CFType()));
+            encoder_spec.insert(kVTVideoEncoderSpecification_EnableLowLatencyRateControl, true);
         }
-
-        let encoder_spec = CFDictionary::from_CFType_pairs(&encoder_spec_pairs);
+        let encoder_spec = encoder_spec.build();
 
         // Build source image buffer attributes
-        let format_key = CFString::wrap_under_get_rule(kCVPixelBufferPixelFormatTypeKey);
-        let width_key = CFString::from_static_string("Width");
-        let height_key = CFString::from_static_string("Height");
-
-        let source_attrs = CFDictionary::from_CFType_pairs(&[
-            (
-                format_key.as_CFType(),
-                CFNumber::from(config.pixel_format as i32).as_CFType(),
-            ),
-            (
-                width_key.as_CFType(),
-                CFNumber::from(config.width).as_CFType(),
-            ),
-            (
-                height_key.as_CFType(),
-                CFNumber::from(config.height).as_CFType(),
-            ),
-        ]);
+        let source_attrs = CFDictBuilder::new()
+            .i32(kCVPixelBufferPixelFormatTypeKey, config.pixel_format as i32)
+            .i32("Width", config.width)
+            .i32("Height", config.height)
+            .build();
 
         let mut session: VTCompressionSessionRef = ptr::null_mut();
 
@@ -283,7 +512,7 @@ impl CompressionSessionBuilder {
         }
 
         // Configure session properties
-        if let Some(profile) = config.profile_level {
+        if let Some(profile) = profile_level {
             let key = CFString::wrap_under_get_rule(
                 kVTCompressionPropertyKey_ProfileLevel as CFStringRef,
             );
@@ -331,6 +560,46 @@ impl CompressionSessionBuilder {
             );
         }
 
+        if let Some(quality) = config.quality {
+            let key =
+                CFString::wrap_under_get_rule(kVTCompressionPropertyKey_Quality as CFStringRef);
+            let value = CFNumber::from(quality);
+            VTSessionSetProperty(
+                session,
+                key.as_concrete_TypeRef(),
+                value.as_concrete_TypeRef() as CFTypeRef,
+            );
+        }
+
+        if let Some(reordering) = config.allow_frame_reordering {
+            let key = CFString::wrap_under_get_rule(
+                kVTCompressionPropertyKey_AllowFrameReordering as CFStringRef,
+            );
+            let value = if reordering {
+                CFBoolean::true_value()
+            } else {
+                CFBoolean::false_value()
+            };
+            VTSessionSetProperty(
+                session,
+                key.as_concrete_TypeRef(),
+                value.as_concrete_TypeRef() as CFTypeRef,
+            );
+        }
+
+        let h264 = config.codec == codecs::video::H264;
+        if let Some(bytes) = config.max_slice_bytes.filter(|_| h264) {
+            let key = CFString::wrap_under_get_rule(
+                kVTCompressionPropertyKey_MaxH264SliceBytes as CFStringRef,
+            );
+            let value = CFNumber::from(bytes);
+            VTSessionSetProperty(
+                session,
+                key.as_concrete_TypeRef(),
+                value.as_concrete_TypeRef() as CFTypeRef,
+            );
+        }
+
         if config.real_time {
             let key =
                 CFString::wrap_under_get_rule(kVTCompressionPropertyKey_RealTime as CFStringRef);
@@ -341,6 +610,14 @@ impl CompressionSessionBuilder {
             );
         }
 
+        for property in &config.properties {
+            if let Err(status) = property.set_on(session) {
+                VTCompressionSessionInvalidate(session);
+                CFRelease(session);
+                return Err(status);
+            }
+        }
+
         // Prepare for encoding
         let prep_status = VTCompressionSessionPrepareToEncodeFrames(session);
         if prep_status != 0 {
@@ -360,10 +637,26 @@ extern "C" fn trampoline<F>(
     info_flags: u32,
     sample_buffer: *mut c_void,
 ) where
-    F: Fn(*mut c_void, *mut c_void, OSStatus, u32, *mut c_void),
+    F: Fn(*mut c_void, *mut c_void, OSStatus, EncodeInfoFlags, *mut c_void),
 {
-    unsafe {
-        let callback = &*(output_ref as *const F);
-        callback(output_ref, source_ref, status, info_flags, sample_buffer);
+    let callback = unsafe { &*(output_ref as *const F) };
+    let _scope = CallbackScope::enter(output_ref);
+    catch_callback_panic("compression output", || {
+        callback(
+            output_ref,
+            source_ref,
+            status,
+            EncodeInfoFlags::from_bits_retain(info_flags),
+            sample_buffer,
+        )
+    });
+}
+
+/// The sample buffer an [`EncodeOutput`] refers to, retained while the output
+/// is queued for another thread.
+fn encoded_sample_buffer(output: &EncodeOutput) -> CFTypeRef {
+    match output {
+        EncodeOutput::Frame { sample_buffer, .. } => *sample_buffer as CFTypeRef,
+        _ => ptr::null(),
     }
 }
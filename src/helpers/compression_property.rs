@@ -0,0 +1,311 @@
+//! Typed compression session properties.
+
+use core_foundation::array::CFArray;
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_foundation_sys::base::OSStatus;
+use core_foundation_sys::string::CFStringRef;
+
+use crate::compression::{
+    kVTCompressionPropertyKey_AllowFrameReordering,
+    kVTCompressionPropertyKey_AllowTemporalCompression, kVTCompressionPropertyKey_AspectRatio16x9,
+    kVTCompressionPropertyKey_AverageBitRate, kVTCompressionPropertyKey_BaseLayerFrameRate,
+    kVTCompressionPropertyKey_ColorPrimaries, kVTCompressionPropertyKey_DataRateLimits,
+    kVTCompressionPropertyKey_ExpectedDuration, kVTCompressionPropertyKey_ExpectedFrameRate,
+    kVTCompressionPropertyKey_FieldCount, kVTCompressionPropertyKey_H264EntropyMode,
+    kVTCompressionPropertyKey_MaxFrameDelayCount, kVTCompressionPropertyKey_MaxH264SliceBytes,
+    kVTCompressionPropertyKey_MaxKeyFrameInterval,
+    kVTCompressionPropertyKey_MaxKeyFrameIntervalDuration,
+    kVTCompressionPropertyKey_MoreFramesAfterEnd, kVTCompressionPropertyKey_MoreFramesBeforeStart,
+    kVTCompressionPropertyKey_PixelAspectRatio, kVTCompressionPropertyKey_ProgressiveScan,
+    kVTCompressionPropertyKey_Quality, kVTCompressionPropertyKey_RealTime,
+    kVTCompressionPropertyKey_SourceFrameCount, kVTCompressionPropertyKey_TransferFunction,
+    kVTCompressionPropertyKey_YCbCrMatrix, kVTH264EntropyMode_CABAC, kVTH264EntropyMode_CAVLC,
+};
+use crate::errors::kVTParameterErr;
+use crate::session::{VTSessionRef, VTSessionSetProperty};
+
+/// H.264 entropy coding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum H264EntropyMode {
+    /// Context-adaptive variable-length coding, the only mode of Baseline
+    /// profile
+    Cavlc,
+    /// Context-adaptive binary arithmetic coding: smaller output, Main
+    /// profile and above
+    Cabac,
+}
+
+/// A compression session property with its value, set through
+/// [`set_on`](Self::set_on) or
+/// [`CompressionSessionBuilder::property`](super::CompressionSessionBuilder::property)
+/// without building CFStrings and CFNumbers by hand.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{
+///     CompressionProperty, CompressionSessionBuilder, H264EntropyMode,
+/// };
+///
+/// let session = CompressionSessionBuilder::new(1280, 720, codecs::video::H264)
+///     .bitrate(2_000_000)
+///     .properties([
+///         // At most 500 KB in any one second
+///         CompressionProperty::DataRateLimits(vec![(500_000, 1.0)]),
+///         CompressionProperty::H264EntropyMode(H264EntropyMode::Cabac),
+///         CompressionProperty::MaxKeyFrameIntervalDuration(2.0),
+///     ])
+///     .build_session(|_output| {})?;
+/// # Ok::<(), i32>(())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompressionProperty {
+    /// Long-term average bitrate in bits per second
+    AverageBitRate(i64),
+    /// Hard limits as `(bytes, seconds)` pairs: at most `bytes` in any
+    /// window of `seconds`
+    DataRateLimits(Vec<(usize, f64)>),
+    /// Allow B-frames
+    AllowFrameReordering(bool),
+    /// `false` encodes every frame as a keyframe
+    AllowTemporalCompression(bool),
+    H264EntropyMode(H264EntropyMode),
+    /// Maximum frames from one keyframe to the next
+    MaxKeyFrameInterval(i32),
+    /// Maximum seconds from one keyframe to the next
+    MaxKeyFrameIntervalDuration(f64),
+    /// From 0.0 (smallest) to 1.0 (best)
+    Quality(f32),
+    ExpectedFrameRate(f64),
+    /// Expected total duration in seconds, for rate control of finite
+    /// sources
+    ExpectedDuration(f64),
+    /// Frame rate of the base temporal layer, for temporal scalability
+    BaseLayerFrameRate(f64),
+    /// Maximum frames the encoder may hold before emitting output
+    MaxFrameDelayCount(i32),
+    MaxH264SliceBytes(i32),
+    RealTime(bool),
+    /// Number of source frames, if known in advance
+    SourceFrameCount(i64),
+    MoreFramesBeforeStart(bool),
+    MoreFramesAfterEnd(bool),
+    PixelAspectRatio {
+        horizontal_spacing: i32,
+        vertical_spacing: i32,
+    },
+    /// 1 for progressive, 2 for interlaced
+    FieldCount(i32),
+    ProgressiveScan(bool),
+    AspectRatio16x9(bool),
+    /// A `kCVImageBufferColorPrimaries_*` value, e.g. `"ITU_R_709_2"`
+    ColorPrimaries(String),
+    /// A `kCVImageBufferTransferFunction_*` value, e.g. `"ITU_R_709_2"`
+    TransferFunction(String),
+    /// A `kCVImageBufferYCbCrMatrix_*` value, e.g. `"ITU_R_709_2"`
+    YCbCrMatrix(String),
+}
+
+impl CompressionProperty {
+    /// The `kVTCompressionPropertyKey_*` this sets.
+    pub fn key(&self) -> CFStringRef {
+        use CompressionProperty::*;
+        unsafe {
+            match self {
+                AverageBitRate(_) => kVTCompressionPropertyKey_AverageBitRate,
+                DataRateLimits(_) => kVTCompressionPropertyKey_DataRateLimits,
+                AllowFrameReordering(_) => kVTCompressionPropertyKey_AllowFrameReordering,
+                AllowTemporalCompression(_) => kVTCompressionPropertyKey_AllowTemporalCompression,
+                H264EntropyMode(_) => kVTCompressionPropertyKey_H264EntropyMode,
+                MaxKeyFrameInterval(_) => kVTCompressionPropertyKey_MaxKeyFrameInterval,
+                MaxKeyFrameIntervalDuration(_) => {
+                    kVTCompressionPropertyKey_MaxKeyFrameIntervalDuration
+                }
+                Quality(_) => kVTCompressionPropertyKey_Quality,
+                ExpectedFrameRate(_) => kVTCompressionPropertyKey_ExpectedFrameRate,
+                ExpectedDuration(_) => kVTCompressionPropertyKey_ExpectedDuration,
+                BaseLayerFrameRate(_) => kVTCompressionPropertyKey_BaseLayerFrameRate,
+                MaxFrameDelayCount(_) => kVTCompressionPropertyKey_MaxFrameDelayCount,
+                MaxH264SliceBytes(_) => kVTCompressionPropertyKey_MaxH264SliceBytes,
+                RealTime(_) => kVTCompressionPropertyKey_RealTime,
+                SourceFrameCount(_) => kVTCompressionPropertyKey_SourceFrameCount,
+                MoreFramesBeforeStart(_) => kVTCompressionPropertyKey_MoreFramesBeforeStart,
+                MoreFramesAfterEnd(_) => kVTCompressionPropertyKey_MoreFramesAfterEnd,
+                PixelAspectRatio { .. } => kVTCompressionPropertyKey_PixelAspectRatio,
+                FieldCount(_) => kVTCompressionPropertyKey_FieldCount,
+                ProgressiveScan(_) => kVTCompressionPropertyKey_ProgressiveScan,
+                AspectRatio16x9(_) => kVTCompressionPropertyKey_AspectRatio16x9,
+                ColorPrimaries(_) => kVTCompressionPropertyKey_ColorPrimaries,
+                TransferFunction(_) => kVTCompressionPropertyKey_TransferFunction,
+                YCbCrMatrix(_) => kVTCompressionPropertyKey_YCbCrMatrix,
+            }
+        }
+    }
+
+    /// Check the value before it reaches the encoder, which often only
+    /// reports a generic parameter error.
+    pub fn validate(&self) -> Result<(), OSStatus> {
+        use CompressionProperty::*;
+        let valid = match self {
+            AverageBitRate(bps) => *bps > 0,
+            DataRateLimits(limits) => {
+                !limits.is_empty()
+                    && limits
+                        .iter()
+                        .all(|&(bytes, seconds)| bytes > 0 && seconds > 0.0)
+            }
+            Quality(quality) => (0.0..=1.0).contains(quality),
+            MaxKeyFrameInterval(frames) => *frames >= 0,
+            MaxKeyFrameIntervalDuration(seconds)
+            | ExpectedFrameRate(seconds)
+            | ExpectedDuration(seconds)
+            | BaseLayerFrameRate(seconds) => seconds.is_finite() && *seconds >= 0.0,
+            MaxFrameDelayCount(frames) => *frames >= -1,
+            MaxH264SliceBytes(bytes) => *bytes > 0,
+            SourceFrameCount(frames) => *frames >= 0,
+            PixelAspectRatio {
+                horizontal_spacing,
+                vertical_spacing,
+            } => *horizontal_spacing > 0 && *vertical_spacing > 0,
+            FieldCount(count) => matches!(count, 1 | 2),
+            _ => true,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(kVTParameterErr)
+        }
+    }
+
+    /// Whether the property conflicts with
+    /// [deterministic mode](super::set_deterministic), which needs software
+    /// encoding without frame reordering or real-time rate control.
+    pub(super) fn breaks_determinism(&self) -> bool {
+        matches!(
+            self,
+            CompressionProperty::RealTime(true) | CompressionProperty::AllowFrameReordering(true)
+        )
+    }
+
+    fn value(&self) -> CFType {
+        use CompressionProperty::*;
+        let boolean = |b: bool| {
+            if b {
+                CFBoolean::true_value().as_CFType()
+            } else {
+                CFBoolean::false_value().as_CFType()
+            }
+        };
+        match self {
+            AverageBitRate(n) | SourceFrameCount(n) => CFNumber::from(*n).as_CFType(),
+            DataRateLimits(limits) => {
+                let values: Vec<CFNumber> = data_rate_limit_values(limits)
+                    .into_iter()
+                    .map(CFNumber::from)
+                    .collect();
+                CFArray::from_CFTypes(&values).as_CFType()
+            }
+            AllowFrameReordering(b)
+            | AllowTemporalCompression(b)
+            | RealTime(b)
+            | MoreFramesBeforeStart(b)
+            | MoreFramesAfterEnd(b)
+            | ProgressiveScan(b)
+            | AspectRatio16x9(b) => boolean(*b),
+            H264EntropyMode(mode) => {
+                let key = unsafe {
+                    match mode {
+                        self::H264EntropyMode::Cavlc => kVTH264EntropyMode_CAVLC,
+                        self::H264EntropyMode::Cabac => kVTH264EntropyMode_CABAC,
+                    }
+                };
+                unsafe { CFString::wrap_under_get_rule(key) }.as_CFType()
+            }
+            MaxKeyFrameInterval(n)
+            | MaxFrameDelayCount(n)
+            | MaxH264SliceBytes(n)
+            | FieldCount(n) => CFNumber::from(*n).as_CFType(),
+            MaxKeyFrameIntervalDuration(f)
+            | ExpectedFrameRate(f)
+            | ExpectedDuration(f)
+            | BaseLayerFrameRate(f) => CFNumber::from(*f).as_CFType(),
+            Quality(quality) => CFNumber::from(*quality).as_CFType(),
+            PixelAspectRatio {
+                horizontal_spacing,
+                vertical_spacing,
+            } => CFDictionary::from_CFType_pairs(&[
+                (
+                    CFString::from_static_string("HorizontalSpacing"),
+                    CFNumber::from(*horizontal_spacing),
+                ),
+                (
+                    CFString::from_static_string("VerticalSpacing"),
+                    CFNumber::from(*vertical_spacing),
+                ),
+            ])
+            .as_CFType(),
+            ColorPrimaries(s) | TransferFunction(s) | YCbCrMatrix(s) => {
+                CFString::new(s).as_CFType()
+            }
+        }
+    }
+
+    /// Validate and set the property on `session`.
+    ///
+    /// # Safety
+    ///
+    /// `session` must be a valid, not invalidated compression session.
+    pub unsafe fn set_on(&self, session: VTSessionRef) -> Result<(), OSStatus> {
+        self.validate()?;
+        let value = self.value();
+        crate::errors::status_to_result(VTSessionSetProperty(
+            session,
+            self.key(),
+            value.as_CFTypeRef(),
+        ))
+    }
+}
+
+/// `DataRateLimits` as VideoToolbox expects it: alternating byte counts and
+/// durations.
+fn data_rate_limit_values(limits: &[(usize, f64)]) -> Vec<f64> {
+    limits
+        .iter()
+        .flat_map(|&(bytes, seconds)| [bytes as f64, seconds])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_and_data_rate_limits() {
+        let limits = vec![(500_000, 1.0), (3_000_000, 10.0)];
+        assert_eq!(
+            data_rate_limit_values(&limits),
+            [500_000.0, 1.0, 3_000_000.0, 10.0]
+        );
+        assert_eq!(
+            CompressionProperty::DataRateLimits(limits).validate(),
+            Ok(())
+        );
+        for invalid in [
+            CompressionProperty::DataRateLimits(Vec::new()),
+            CompressionProperty::DataRateLimits(vec![(500_000, 0.0)]),
+            CompressionProperty::Quality(1.5),
+            CompressionProperty::AverageBitRate(0),
+            CompressionProperty::FieldCount(3),
+            CompressionProperty::MaxKeyFrameIntervalDuration(f64::NAN),
+        ] {
+            assert_eq!(invalid.validate(), Err(kVTParameterErr), "{invalid:?}");
+        }
+        assert!(CompressionProperty::RealTime(true).breaks_determinism());
+        assert!(!CompressionProperty::RealTime(false).breaks_determinism());
+    }
+}
@@ -0,0 +1,671 @@
+//! Safe wrapper around VTCompressionSession.
+
+use core_foundation_sys::base::{CFRelease, OSStatus};
+use core_foundation_sys::string::CFStringRef;
+use core_media_sys::{CMSampleBufferRef, CMTime};
+use libc::c_void;
+use std::collections::BTreeMap;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::cf_dict::{as_dictionary_ref, CFDictBuilder, DictKey, DictValue};
+use super::clock::make_time;
+use super::compression_property::CompressionProperty;
+use super::events::{catch_callback_panic, emit, in_callback_of, CallbackScope, PipelineEvent};
+use super::leak_tracker::{release_pixel_buffer, track, untrack, TrackedKind};
+use super::nal_extractor::convert_time;
+use super::pixel_buffer::{create_pixel_buffer, fill_black, PixelBufferConfig};
+use super::session_props::{
+    copy_serializable_properties, copy_supported_property_dictionary, get_property,
+    set_properties, FromPropertyValue, PropertyBag, PropertyInfo,
+};
+use super::trace::trace_event;
+use crate::compression::{
+    kVTEncodeFrameOptionKey_ForceKeyFrame, EncodeInfoFlags, VTCompressionSessionCompleteFrames,
+    VTCompressionSessionEncodeFrame, VTCompressionSessionInvalidate, VTCompressionSessionRef,
+    VTEncodeInfoFlags,
+};
+use crate::cv_types::CVImageBufferRef;
+use crate::errors::kVTInvalidSessionErr;
+
+/// Result delivered to the compression output callback.
+#[derive(Debug, Clone, Copy)]
+pub enum EncodeOutput {
+    /// An encoded frame. The sample buffer is only valid for the duration of the
+    /// callback unless retained by the caller.
+    Frame {
+        sample_buffer: CMSampleBufferRef,
+        info: EncodeInfoFlags,
+    },
+    /// The encoder dropped the frame. `pts` is the timestamp it was
+    /// submitted with, or `None` for frames not submitted through
+    /// [`CompressionSession::encode_frame`].
+    Dropped { pts: Option<CMTime> },
+    /// Encoding failed with the given status.
+    Error(OSStatus),
+}
+
+/// Per-frame encode options, passed to `VTCompressionSessionEncodeFrame` as
+/// its frame properties dictionary.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{make_time, CompressionSessionBuilder, EncodeOptions};
+/// # let pixel_buffer: video_toolbox_sys::cv_types::CVPixelBufferRef = std::ptr::null_mut();
+///
+/// let session = CompressionSessionBuilder::new(1280, 720, codecs::video::H264)
+///     .build_session(|_output| {})?;
+/// // An IDR frame with a fixed QP, where the encoder supports it
+/// let options = EncodeOptions::new()
+///     .force_keyframe(true)
+///     .option("BaseFrameQP", 24);
+/// let (pts, duration) = (make_time(0, 30), make_time(1, 30));
+/// unsafe { session.encode_frame_with_options(pixel_buffer, pts, duration, &options) }?;
+/// # Ok::<(), i32>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct EncodeOptions {
+    properties: CFDictBuilder,
+}
+
+impl EncodeOptions {
+    /// No per-frame options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set or clear `kVTEncodeFrameOptionKey_ForceKeyFrame`.
+    pub fn force_keyframe(self, enabled: bool) -> Self {
+        self.option(unsafe { kVTEncodeFrameOptionKey_ForceKeyFrame }, enabled)
+    }
+
+    /// Set any `kVTEncodeFrameOptionKey_*` option, given as the framework
+    /// constant or its name, replacing an earlier value.
+    pub fn option(mut self, key: impl DictKey, value: impl DictValue) -> Self {
+        self.properties.insert(key, value);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty()
+    }
+}
+
+/// Options from an existing dictionary builder, e.g. one shared by several
+/// encoders.
+impl From<CFDictBuilder> for EncodeOptions {
+    fn from(properties: CFDictBuilder) -> Self {
+        Self { properties }
+    }
+}
+
+/// `outputCallbackRefCon` of a [`CompressionSession`]: the user callback and
+/// the frames it is waiting for.
+pub(crate) struct EncodeCallback {
+    callback: Box<dyn Fn(EncodeOutput) + Send + Sync>,
+    in_flight: Arc<InFlight>,
+}
+
+impl EncodeCallback {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(EncodeOutput) + Send + Sync + 'static,
+    {
+        Self {
+            callback: Box::new(callback),
+            in_flight: Arc::default(),
+        }
+    }
+}
+
+/// `sourceFrameRefCon` marking the warm-up frame, whose output is discarded.
+const PREWARM_FRAME: usize = 1;
+
+/// Frame counters for a [`CompressionSession`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncodeStats {
+    /// Frames handed to the encoder
+    pub submitted: u64,
+    /// Frames the encoder has emitted, dropped or failed
+    pub completed: u64,
+    /// Frames not submitted because the encoder missed the frame deadline
+    pub skipped: u64,
+    /// Frames submitted but not yet completed
+    pub in_flight: usize,
+}
+
+/// A frame handed to the encoder and not yet emitted.
+#[derive(Debug, Clone, Copy)]
+struct PendingFrame {
+    submitted: Instant,
+    pts: CMTime,
+    duration: CMTime,
+}
+
+#[derive(Debug, Default)]
+struct InFlightState {
+    /// Pending frames by slot, i.e. in submission order
+    pending: BTreeMap<usize, PendingFrame>,
+    /// Slot of the most recently submitted frame
+    last_slot: usize,
+    deadline: Option<Duration>,
+    stats: EncodeStats,
+}
+
+/// Frames in flight, shared with the output callback. Each frame's
+/// `sourceFrameRefCon` is its slot number.
+#[derive(Debug, Default)]
+struct InFlight {
+    state: Mutex<InFlightState>,
+}
+
+impl InFlight {
+    fn lock(&self) -> MutexGuard<'_, InFlightState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record a frame submitted at `now` and return its slot, or `None` if
+    /// the oldest pending frame has been in flight longer than the deadline.
+    fn admit(&self, now: Instant, pts: CMTime, duration: CMTime) -> Option<usize> {
+        let mut state = self.lock();
+        let late = match (state.deadline, state.pending.first_key_value()) {
+            (Some(deadline), Some((_, oldest))) => {
+                now.saturating_duration_since(oldest.submitted) > deadline
+            }
+            _ => false,
+        };
+        if late {
+            state.stats.skipped += 1;
+            return None;
+        }
+        // Slots start above PREWARM_FRAME
+        let slot = state.last_slot.max(PREWARM_FRAME) + 1;
+        state.last_slot = slot;
+        state.pending.insert(
+            slot,
+            PendingFrame {
+                submitted: now,
+                pts,
+                duration,
+            },
+        );
+        state.stats.submitted += 1;
+        Some(slot)
+    }
+
+    /// Forget a frame the encoder rejected.
+    fn reject(&self, slot: usize) {
+        let mut state = self.lock();
+        if state.pending.remove(&slot).is_some() {
+            state.stats.submitted -= 1;
+        }
+    }
+
+    /// Mark a frame as emitted, returning what it was submitted with.
+    fn complete(&self, slot: usize) -> Option<PendingFrame> {
+        let mut state = self.lock();
+        let frame = state.pending.remove(&slot)?;
+        state.stats.completed += 1;
+        Some(frame)
+    }
+
+    fn stats(&self) -> EncodeStats {
+        let state = self.lock();
+        EncodeStats {
+            in_flight: state.pending.len(),
+            ..state.stats.clone()
+        }
+    }
+}
+
+/// A VTCompressionSession that owns its output callback.
+///
+/// Create one with [`CompressionSessionBuilder::build_session`](super::CompressionSessionBuilder::build_session).
+/// Pending frames are completed and the session is invalidated and released
+/// when dropped.
+///
+/// # Reentrancy
+///
+/// The session must not be driven from inside its own output callback:
+/// [`encode_frame`](Self::encode_frame) and [`complete_frames`](Self::complete_frames)
+/// return `kVTInvalidSessionErr` when called there, since VideoToolbox would
+/// wait on the callback that is currently running. Hand work to another thread
+/// instead.
+///
+/// Panics in the callback are caught and reported as
+/// [`PipelineEvent::CallbackPanicked`](super::PipelineEvent::CallbackPanicked).
+///
+/// # Real-time drop policy
+///
+/// When the encoder cannot keep up, queued frames make latency grow without
+/// bound. With a [frame deadline](Self::set_frame_deadline), a frame
+/// submitted while an earlier one has been pending for longer than the
+/// deadline is skipped instead of queued: [`encode_frame`](Self::encode_frame)
+/// returns [`EncodeInfoFlags::FRAME_DROPPED`] without calling the encoder or
+/// the output callback, and the frame is counted in [`stats`](Self::stats).
+///
+/// Every dropped frame, whether skipped here or dropped by the encoder, is
+/// also reported as [`PipelineEvent::FrameDropped`] with the timestamp it was
+/// submitted with, so a muxer can extend the previous frame's duration over
+/// the gap (see [`CmafMuxer::extend_last_frame`](super::CmafMuxer::extend_last_frame)).
+pub struct CompressionSession {
+    session: VTCompressionSessionRef,
+    callback: *mut EncodeCallback,
+    width: i32,
+    height: i32,
+    pixel_format: u32,
+    force_keyframe: AtomicBool,
+    in_flight: Arc<InFlight>,
+}
+
+unsafe impl Send for CompressionSession {}
+unsafe impl Sync for CompressionSession {}
+
+impl CompressionSession {
+    /// Take ownership of a session created with [`output_trampoline`] and `callback`.
+    pub(crate) fn from_raw(
+        session: VTCompressionSessionRef,
+        callback: *mut EncodeCallback,
+        width: i32,
+        height: i32,
+        pixel_format: u32,
+    ) -> Self {
+        track(TrackedKind::CompressionSession, session);
+        // SAFETY: the callback box lives until the session is dropped
+        let in_flight = unsafe { (*callback).in_flight.clone() };
+        Self {
+            session,
+            callback,
+            width,
+            height,
+            pixel_format,
+            force_keyframe: AtomicBool::new(false),
+            in_flight,
+        }
+    }
+
+    /// Submit a frame for encoding.
+    ///
+    /// Returns [`EncodeInfoFlags::FRAME_DROPPED`] without encoding if the
+    /// [frame deadline](Self::set_frame_deadline) has been missed.
+    ///
+    /// # Safety
+    ///
+    /// `image_buffer` must be a valid pixel buffer matching the session's dimensions.
+    pub unsafe fn encode_frame(
+        &self,
+        image_buffer: CVImageBufferRef,
+        pts: CMTime,
+        duration: CMTime,
+    ) -> Result<EncodeInfoFlags, OSStatus> {
+        self.encode_frame_with_options(image_buffer, pts, duration, &EncodeOptions::new())
+    }
+
+    /// Submit a frame for encoding with per-frame [`EncodeOptions`].
+    ///
+    /// A keyframe requested with [`force_keyframe`](Self::force_keyframe) is
+    /// added to `options`.
+    ///
+    /// # Safety
+    ///
+    /// `image_buffer` must be a valid pixel buffer matching the session's dimensions.
+    pub unsafe fn encode_frame_with_options(
+        &self,
+        image_buffer: CVImageBufferRef,
+        pts: CMTime,
+        duration: CMTime,
+        options: &EncodeOptions,
+    ) -> Result<EncodeInfoFlags, OSStatus> {
+        self.check_not_reentrant()?;
+        let Some(slot) = self.in_flight.admit(Instant::now(), pts, duration) else {
+            report_dropped(pts, duration);
+            return Ok(EncodeInfoFlags::FRAME_DROPPED);
+        };
+        let result = self.submit(image_buffer, pts, duration, options, slot as *mut c_void);
+        if result.is_err() {
+            self.in_flight.reject(slot);
+        }
+        result
+    }
+
+    /// Skip new frames while an earlier frame has been in flight for longer
+    /// than `deadline`; `None` (the default) queues every frame.
+    ///
+    /// A deadline of one or two frame intervals keeps latency bounded when
+    /// the encoder falls behind, e.g. 4K on older hardware. May be called
+    /// from any thread.
+    pub fn set_frame_deadline(&self, deadline: Option<Duration>) {
+        self.in_flight.lock().deadline = deadline;
+    }
+
+    pub fn frame_deadline(&self) -> Option<Duration> {
+        self.in_flight.lock().deadline
+    }
+
+    /// Submitted, completed, skipped and in-flight frame counts.
+    pub fn stats(&self) -> EncodeStats {
+        self.in_flight.stats()
+    }
+
+    /// Encode a black frame so the encoder is spun up before real input arrives.
+    ///
+    /// The first frame of a new hardware session can take hundreds of
+    /// milliseconds. Call this right after creating the session; the warm-up
+    /// frame's output is discarded and the next frame is forced to be a
+    /// keyframe, so nothing downstream references it. The warm-up frame is
+    /// stamped just before zero, ahead of any real presentation timestamp.
+    ///
+    /// Returns the measured warm-up time.
+    pub fn prewarm(&self) -> Result<Duration, OSStatus> {
+        self.check_not_reentrant()?;
+        let config = PixelBufferConfig::new(self.width as usize, self.height as usize)
+            .pixel_format(self.pixel_format);
+        let pixel_buffer = create_pixel_buffer(&config)?;
+
+        let started = Instant::now();
+        let result = unsafe {
+            fill_black(pixel_buffer).and_then(|_| {
+                self.submit(
+                    pixel_buffer,
+                    make_time(-1, 1000),
+                    make_time(1, 1000),
+                    &EncodeOptions::new(),
+                    PREWARM_FRAME as *mut c_void,
+                )
+            })
+        };
+        unsafe { release_pixel_buffer(pixel_buffer) };
+        result?;
+        self.complete_frames()?;
+
+        self.force_keyframe.store(true, Ordering::Release);
+        Ok(started.elapsed())
+    }
+
+    unsafe fn submit(
+        &self,
+        image_buffer: CVImageBufferRef,
+        pts: CMTime,
+        duration: CMTime,
+        options: &EncodeOptions,
+        source_ref: *mut c_void,
+    ) -> Result<EncodeInfoFlags, OSStatus> {
+        let forced;
+        let requested = self.force_keyframe.swap(false, Ordering::AcqRel);
+        let options = if requested {
+            forced = options.clone().force_keyframe(true);
+            &forced
+        } else {
+            options
+        };
+        let frame_props = (!options.is_empty()).then(|| options.properties.build());
+        let frame_props_ref = frame_props
+            .as_ref()
+            .map(as_dictionary_ref)
+            .unwrap_or(ptr::null());
+
+        let mut info_flags: VTEncodeInfoFlags = 0;
+        let status = VTCompressionSessionEncodeFrame(
+            self.session,
+            image_buffer,
+            pts,
+            duration,
+            frame_props_ref,
+            source_ref,
+            &mut info_flags,
+        );
+        if status != 0 {
+            // The requested keyframe goes to the next frame instead
+            if requested {
+                self.force_keyframe.store(true, Ordering::Release);
+            }
+            return Err(status);
+        }
+        Ok(EncodeInfoFlags::from_bits_retain(info_flags))
+    }
+
+    /// Force the next submitted frame to be encoded as a keyframe (IDR), by
+    /// setting `kVTEncodeFrameOptionKey_ForceKeyFrame` in its frame options.
+    ///
+    /// Use at scene cuts (see [`SceneChangeDetector`](super::SceneChangeDetector))
+    /// or when a new viewer joins a live stream. Requests made before the
+    /// next frame is submitted are merged into one keyframe. May be called
+    /// from any thread.
+    pub fn force_keyframe(&self) {
+        self.force_keyframe.store(true, Ordering::Release);
+    }
+
+    /// Block until every pending frame has been emitted.
+    pub fn complete_frames(&self) -> Result<(), OSStatus> {
+        self.check_not_reentrant()?;
+        let status = unsafe { VTCompressionSessionCompleteFrames(self.session, invalid_time()) };
+        crate::errors::status_to_result(status)
+    }
+
+    /// Change a property of the running session, e.g. the bitrate or data
+    /// rate limits in response to network conditions.
+    pub fn set_property(&self, property: &CompressionProperty) -> Result<(), OSStatus> {
+        unsafe { property.set_on(self.session) }
+    }
+
+    /// Read back property `key`, e.g. to check the bitrate or frame delay
+    /// the encoder actually accepted. See [`get_property`].
+    ///
+    /// # Safety
+    ///
+    /// `key` must be a valid CFString.
+    pub unsafe fn property<T: FromPropertyValue>(
+        &self,
+        key: CFStringRef,
+    ) -> Result<Option<T>, OSStatus> {
+        get_property(self.session, key)
+    }
+
+    /// Every property the encoder supports. See
+    /// [`copy_supported_property_dictionary`].
+    pub fn supported_properties(&self) -> Result<BTreeMap<String, PropertyInfo>, OSStatus> {
+        unsafe { copy_supported_property_dictionary(self.session) }
+    }
+
+    /// The encoder's serializable configuration, to save and re-apply with
+    /// [`apply_settings`](Self::apply_settings) after a restart or on
+    /// another machine.
+    pub fn export_settings(&self) -> Result<PropertyBag, OSStatus> {
+        unsafe { copy_serializable_properties(self.session) }
+    }
+
+    /// Apply settings exported from this or another encoder.
+    pub fn apply_settings(&self, settings: &PropertyBag) -> Result<(), OSStatus> {
+        unsafe { set_properties(self.session, settings) }
+    }
+
+    /// Get the underlying session reference.
+    pub fn as_raw(&self) -> VTCompressionSessionRef {
+        self.session
+    }
+
+    fn check_not_reentrant(&self) -> Result<(), OSStatus> {
+        if in_callback_of(self.callback as *const c_void) {
+            return Err(kVTInvalidSessionErr);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CompressionSession {
+    fn drop(&mut self) {
+        unsafe {
+            // Completing frames from the output callback would deadlock.
+            if !in_callback_of(self.callback as *const c_void) {
+                VTCompressionSessionCompleteFrames(self.session, invalid_time());
+            }
+            VTCompressionSessionInvalidate(self.session);
+            untrack(self.session);
+            CFRelease(self.session);
+            drop(Box::from_raw(self.callback));
+        }
+    }
+}
+
+/// `kCMTimeInvalid`, which completes all pending frames.
+fn invalid_time() -> CMTime {
+    CMTime {
+        value: 0,
+        timescale: 0,
+        flags: 0,
+        epoch: 0,
+    }
+}
+
+/// Trampoline translating the raw VideoToolbox callback into an [`EncodeOutput`].
+pub(crate) extern "C" fn output_trampoline(
+    output_ref: *mut c_void,
+    source_ref: *mut c_void,
+    status: OSStatus,
+    info_flags: VTEncodeInfoFlags,
+    sample_buffer: *mut c_void,
+) {
+    if source_ref as usize == PREWARM_FRAME {
+        return;
+    }
+    let callback = unsafe { &*(output_ref as *const EncodeCallback) };
+    let frame = callback.in_flight.complete(source_ref as usize);
+    let info = EncodeInfoFlags::from_bits_retain(info_flags);
+    trace_event("vt.encode_output", status as i64, info_flags as i64);
+
+    let output = if status != 0 {
+        EncodeOutput::Error(status)
+    } else if info.contains(EncodeInfoFlags::FRAME_DROPPED) || sample_buffer.is_null() {
+        if let Some(frame) = frame {
+            report_dropped(frame.pts, frame.duration);
+        }
+        EncodeOutput::Dropped {
+            pts: frame.map(|frame| frame.pts),
+        }
+    } else {
+        EncodeOutput::Frame {
+            sample_buffer: sample_buffer as CMSampleBufferRef,
+            info,
+        }
+    };
+
+    let _scope = CallbackScope::enter(output_ref);
+    catch_callback_panic("compression output", || (callback.callback)(output));
+}
+
+pub(super) fn report_dropped(pts: CMTime, duration: CMTime) {
+    // Durations may be submitted in a different timescale than the pts
+    let duration = if duration.timescale > 0 && pts.timescale > 0 {
+        convert_time(duration, pts.timescale)
+    } else {
+        0
+    };
+    emit(PipelineEvent::FrameDropped {
+        pts: pts.value,
+        duration,
+        timescale: pts.timescale,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn admit(in_flight: &InFlight, at: Instant) -> Option<usize> {
+        in_flight.admit(at, make_time(0, 30), make_time(1, 30))
+    }
+
+    #[test]
+    fn test_frame_deadline_skips_late_frames() {
+        let in_flight = InFlight::default();
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+
+        // Without a deadline every frame is queued
+        let a = admit(&in_flight, ms(0)).unwrap();
+        let b = admit(&in_flight, ms(500)).unwrap();
+        assert!(a > PREWARM_FRAME && b > a);
+        in_flight.complete(a);
+        in_flight.complete(b);
+
+        in_flight.lock().deadline = Some(Duration::from_millis(50));
+        let a = admit(&in_flight, ms(1000)).unwrap();
+        let b = admit(&in_flight, ms(1033)).unwrap();
+        // Frame at 1000 still pending after 66 ms
+        assert!(admit(&in_flight, ms(1066)).is_none());
+        assert!(admit(&in_flight, ms(1100)).is_none());
+        in_flight.complete(a);
+        // Oldest pending is now the frame at 1033
+        assert!(admit(&in_flight, ms(1100)).is_none());
+        in_flight.complete(b);
+        let c = admit(&in_flight, ms(1133)).unwrap();
+        in_flight.reject(c);
+
+        assert_eq!(
+            in_flight.stats(),
+            EncodeStats {
+                submitted: 4,
+                completed: 4,
+                skipped: 3,
+                in_flight: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_dropped_frames_report_their_pts() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let seen = dropped.clone();
+        let callback = Box::into_raw(Box::new(EncodeCallback::new(move |output| {
+            if let EncodeOutput::Dropped { pts: Some(pts) } = output {
+                seen.store(pts.value as usize, Ordering::SeqCst);
+            }
+        })));
+        let in_flight = unsafe { (*callback).in_flight.clone() };
+
+        let start = Instant::now();
+        let first = in_flight
+            .admit(start, make_time(3000, 90000), make_time(3000, 90000))
+            .unwrap();
+        let second = in_flight
+            .admit(start, make_time(6000, 90000), make_time(3000, 90000))
+            .unwrap();
+        // Frames may complete out of submission order
+        output_trampoline(
+            callback as *mut c_void,
+            second as *mut c_void,
+            0,
+            EncodeInfoFlags::FRAME_DROPPED.bits(),
+            ptr::null_mut(),
+        );
+        assert_eq!(dropped.load(Ordering::SeqCst), 6000);
+        assert_eq!(in_flight.stats().in_flight, 1);
+        assert!(in_flight.complete(first).is_some());
+
+        drop(unsafe { Box::from_raw(callback) });
+    }
+
+    #[test]
+    fn test_encode_options_dictionary() {
+        use core_foundation::base::TCFType;
+        use core_foundation::boolean::CFBoolean;
+        use core_foundation::string::CFString;
+
+        assert!(EncodeOptions::new().is_empty());
+        let options = EncodeOptions::new()
+            .option("BaseFrameQP", 30)
+            .force_keyframe(false)
+            .option("BaseFrameQP", 24)
+            .force_keyframe(true);
+        let dict = options.properties.build();
+        assert_eq!(dict.len(), 2);
+        let key = unsafe { CFString::wrap_under_get_rule(kVTEncodeFrameOptionKey_ForceKeyFrame) };
+        let forced = dict.find(key).unwrap().downcast::<CFBoolean>().unwrap();
+        assert!(bool::from(forced));
+    }
+}
@@ -0,0 +1,571 @@
+//! Bitstream conformance checks against the signaled H.264 profile/level.
+
+use std::collections::VecDeque;
+
+use super::events::{emit, PipelineEvent};
+use super::nal_extractor::NalUnit;
+use super::profile_level::{validate, Level, Profile, ProfileLevelError, StreamParams};
+use super::source::MediaFrame;
+use crate::cm_sample_buffer::nal_unit_type;
+
+/// Reads bits from an RBSP (emulation prevention bytes removed).
+struct BitReader {
+    data: Vec<u8>,
+    bit: usize,
+}
+
+impl BitReader {
+    fn new(nal: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(nal.len());
+        let mut zeros = 0;
+        for &byte in nal {
+            if zeros >= 2 && byte == 0x03 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            data.push(byte);
+        }
+        Self { data, bit: 0 }
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..count {
+            let byte = *self.data.get(self.bit / 8)?;
+            value = (value << 1) | ((byte >> (7 - self.bit % 8)) & 1) as u32;
+            self.bit += 1;
+        }
+        Some(value)
+    }
+
+    fn flag(&mut self) -> Option<bool> {
+        self.bits(1).map(|bit| bit == 1)
+    }
+
+    /// Unsigned Exp-Golomb code.
+    fn ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while !self.flag()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u64 << leading_zeros) as u32 - 1 + self.bits(leading_zeros)?)
+    }
+
+    /// Signed Exp-Golomb code.
+    fn se(&mut self) -> Option<i32> {
+        let code = self.ue()? as i64;
+        Some(if code % 2 == 1 {
+            (code + 1) / 2
+        } else {
+            -code / 2
+        } as i32)
+    }
+}
+
+/// Fields of an H.264 SPS that determine level conformance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpsInfo {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    /// Coded width in macroblocks
+    pub width_mbs: u32,
+    /// Coded frame height in macroblocks
+    pub height_mbs: u32,
+    /// Display width after cropping
+    pub width: u32,
+    /// Display height after cropping
+    pub height: u32,
+    pub max_num_ref_frames: u32,
+    /// Frame rate from the VUI timing info, if present
+    pub frame_rate: Option<f64>,
+}
+
+impl SpsInfo {
+    /// Parse an SPS NAL unit (starting with its NAL header byte).
+    pub fn parse(sps: &[u8]) -> Option<Self> {
+        let mut r = BitReader::new(sps.get(1..)?);
+        let profile_idc = r.bits(8)? as u8;
+        let constraint_flags = r.bits(8)? as u8;
+        let level_idc = r.bits(8)? as u8;
+        r.ue()?; // seq_parameter_set_id
+
+        let mut chroma_format_idc = 1;
+        if matches!(
+            profile_idc,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+        ) {
+            chroma_format_idc = r.ue()?;
+            if chroma_format_idc == 3 {
+                r.flag()?; // separate_colour_plane_flag
+            }
+            r.ue()?; // bit_depth_luma_minus8
+            r.ue()?; // bit_depth_chroma_minus8
+            r.flag()?; // qpprime_y_zero_transform_bypass_flag
+            if r.flag()? {
+                let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+                for i in 0..lists {
+                    if r.flag()? {
+                        skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+        r.ue()?; // log2_max_frame_num_minus4
+        match r.ue()? {
+            0 => {
+                r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+            }
+            1 => {
+                r.flag()?; // delta_pic_order_always_zero_flag
+                r.se()?; // offset_for_non_ref_pic
+                r.se()?; // offset_for_top_to_bottom_field
+                for _ in 0..r.ue()? {
+                    r.se()?; // offset_for_ref_frame
+                }
+            }
+            _ => {}
+        }
+        let max_num_ref_frames = r.ue()?;
+        r.flag()?; // gaps_in_frame_num_value_allowed_flag
+        let width_mbs = r.ue()? + 1;
+        let height_map_units = r.ue()? + 1;
+        let frame_mbs_only = r.flag()?;
+        if !frame_mbs_only {
+            r.flag()?; // mb_adaptive_frame_field_flag
+        }
+        r.flag()?; // direct_8x8_inference_flag
+        let height_mbs = height_map_units * if frame_mbs_only { 1 } else { 2 };
+
+        let (mut width, mut height) = (width_mbs * 16, height_mbs * 16);
+        if r.flag()? {
+            let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+            let crop_x = if chroma_format_idc == 1 || chroma_format_idc == 2 {
+                2
+            } else {
+                1
+            };
+            let crop_y =
+                (if chroma_format_idc == 1 { 2 } else { 1 }) * if frame_mbs_only { 1 } else { 2 };
+            width = width.saturating_sub(crop_x * (left + right));
+            height = height.saturating_sub(crop_y * (top + bottom));
+        }
+
+        let frame_rate = if r.flag()? {
+            vui_frame_rate(&mut r)
+        } else {
+            None
+        };
+        Some(Self {
+            profile_idc,
+            constraint_flags,
+            level_idc,
+            width_mbs,
+            height_mbs,
+            width,
+            height,
+            max_num_ref_frames,
+            frame_rate,
+        })
+    }
+
+    /// The signaled profile; other High-family profiles (High 10, 4:2:2,
+    /// ...) are checked with High limits.
+    pub fn profile(&self) -> Profile {
+        Profile::from_idc(self.profile_idc).unwrap_or(Profile::High)
+    }
+
+    /// The signaled level, if `level_idc` is valid.
+    pub fn level(&self) -> Option<Level> {
+        Level::from_idc(self.level_idc)
+    }
+
+    pub fn frame_macroblocks(&self) -> u64 {
+        self.width_mbs as u64 * self.height_mbs as u64
+    }
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Option<()> {
+    let (mut last, mut next) = (8i32, 8i32);
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+/// Frame rate from the VUI parameters, up to the timing info.
+fn vui_frame_rate(r: &mut BitReader) -> Option<f64> {
+    if r.flag()? && r.bits(8)? == 255 {
+        r.bits(32)?; // sar_width, sar_height
+    }
+    if r.flag()? {
+        r.flag()?; // overscan_appropriate_flag
+    }
+    if r.flag()? {
+        r.bits(4)?; // video_format, video_full_range_flag
+        if r.flag()? {
+            r.bits(24)?; // colour_primaries, transfer, matrix
+        }
+    }
+    if r.flag()? {
+        r.ue()?; // chroma_sample_loc_type_top_field
+        r.ue()?; // chroma_sample_loc_type_bottom_field
+    }
+    if !r.flag()? {
+        return None;
+    }
+    let num_units_in_tick = r.bits(32)?;
+    let time_scale = r.bits(32)?;
+    // One frame is two ticks (fields)
+    (num_units_in_tick > 0).then(|| time_scale as f64 / (2.0 * num_units_in_tick as f64))
+}
+
+/// Measured stream properties and the level limits they exceed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConformanceReport {
+    pub sps: SpsInfo,
+    /// Highest number of frames within one second of decode time
+    pub peak_frame_rate: f64,
+    /// Highest number of bits within one second of decode time
+    pub peak_bitrate: u64,
+    pub average_bitrate: u64,
+    pub frames: u64,
+    /// Every limit the stream exceeds; empty if it conforms
+    pub violations: Vec<ProfileLevelError>,
+}
+
+impl ConformanceReport {
+    pub fn is_conformant(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Verifies that an encoded stream stays within its signaled H.264 level.
+///
+/// The hardware encoder does not always stay within the level written to the
+/// SPS (for example when the bitrate overshoots, or the frame rate is higher
+/// than configured), and strict decoders such as TV hardware reject such
+/// streams. The checker parses the SPS and measures the stream as frames are
+/// pushed: frame size and reference frames (DPB) against the level, and the
+/// peak frame rate and bitrate over any one-second window against the
+/// macroblock rate and bitrate limits.
+///
+/// Each limit exceeded for the first time is reported as a
+/// [`PipelineEvent::LevelExceeded`] warning and returned from
+/// [`push_frame`](Self::push_frame); [`check`](Self::check) turns any
+/// violation into an error.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{ConformanceChecker, MediaFrame};
+///
+/// # let (sps, frames): (Vec<u8>, Vec<MediaFrame>) = (Vec::new(), Vec::new());
+/// let mut checker = ConformanceChecker::new();
+/// checker.set_sps(&sps);
+/// for frame in &frames {
+///     if let Some(violation) = checker.push_frame(frame) {
+///         eprintln!("stream exceeds its level: {}", violation);
+///     }
+/// }
+/// let report = checker.check()?;
+/// println!("peak bitrate {} bps", report.peak_bitrate);
+/// # Ok::<(), video_toolbox_sys::helpers::ProfileLevelError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConformanceChecker {
+    sps: Option<SpsInfo>,
+    /// (decode time in seconds, bits) of frames in the last second
+    window: VecDeque<(f64, u64)>,
+    window_bits: u64,
+    first_time: Option<f64>,
+    end_time: f64,
+    total_bits: u64,
+    frames: u64,
+    peak_frame_rate: f64,
+    peak_bitrate: u64,
+    reported: Vec<ProfileLevelError>,
+}
+
+impl ConformanceChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `sps` (with its NAL header byte) as the signaled configuration.
+    ///
+    /// Returns `false` if it cannot be parsed. SPS NAL units found in pushed
+    /// frames replace it.
+    pub fn set_sps(&mut self, sps: &[u8]) -> bool {
+        match SpsInfo::parse(sps) {
+            Some(info) => {
+                self.sps = Some(info);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn sps(&self) -> Option<&SpsInfo> {
+        self.sps.as_ref()
+    }
+
+    /// Measure an encoded frame (in decode order), returning a limit the
+    /// stream exceeds for the first time.
+    pub fn push_frame(&mut self, frame: &MediaFrame) -> Option<ProfileLevelError> {
+        for nal in frame
+            .nal_units
+            .iter()
+            .filter(|n| n.nal_type == nal_unit_type::SPS)
+        {
+            self.set_sps(&nal.data);
+        }
+
+        let timescale = frame.timing.timescale.max(1) as f64;
+        let start = frame.timing.dts as f64 / timescale;
+        let end = start + frame.timing.duration.max(0) as f64 / timescale;
+        let bits = frame.nal_units.iter().map(nal_bits).sum::<u64>();
+        self.first_time.get_or_insert(start);
+        self.end_time = self.end_time.max(end);
+        self.total_bits += bits;
+        self.frames += 1;
+
+        // Rates over the last second, or the whole stream if shorter
+        self.window.push_back((start, bits));
+        self.window_bits += bits;
+        while let Some(&(oldest, oldest_bits)) = self.window.front() {
+            if oldest >= end - 1.0 {
+                break;
+            }
+            self.window.pop_front();
+            self.window_bits -= oldest_bits;
+        }
+        let span = (end - self.window[0].0).max(1.0 / 1000.0);
+        self.peak_frame_rate = self.peak_frame_rate.max(self.window.len() as f64 / span);
+        self.peak_bitrate = self
+            .peak_bitrate
+            .max((self.window_bits as f64 / span) as u64);
+
+        let violation = self
+            .violations()
+            .into_iter()
+            .find(|v| !self.reported.iter().any(|r| same_kind(r, v)))?;
+        self.reported.push(violation);
+        emit(PipelineEvent::LevelExceeded { error: violation });
+        Some(violation)
+    }
+
+    fn violations(&self) -> Vec<ProfileLevelError> {
+        let Some(sps) = &self.sps else {
+            return Vec::new();
+        };
+        let Some(level) = sps.level() else {
+            return Vec::new();
+        };
+        let profile = sps.profile();
+        let coded = |frame_rate, bitrate| StreamParams {
+            width: sps.width_mbs * 16,
+            height: sps.height_mbs * 16,
+            frame_rate,
+            bitrate,
+        };
+
+        let mut violations = Vec::new();
+        // Checked separately so every exceeded limit is listed
+        for params in [
+            coded(0.0, None),
+            coded(self.peak_frame_rate, None),
+            coded(0.0, Some(self.peak_bitrate)),
+        ] {
+            if let Err(error) = validate(profile, level, &params) {
+                if !violations.iter().any(|v| same_kind(v, &error)) {
+                    violations.push(error);
+                }
+            }
+        }
+        let max = level.max_dpb_frames(sps.frame_macroblocks());
+        if sps.max_num_ref_frames as u64 > max {
+            violations.push(ProfileLevelError::DpbTooLarge {
+                level,
+                frames: sps.max_num_ref_frames as u64,
+                max,
+            });
+        }
+        violations
+    }
+
+    /// Measurements so far, or `None` before an SPS was seen.
+    pub fn report(&self) -> Option<ConformanceReport> {
+        let duration = self.end_time - self.first_time.unwrap_or(self.end_time);
+        Some(ConformanceReport {
+            sps: self.sps.clone()?,
+            peak_frame_rate: self.peak_frame_rate,
+            peak_bitrate: self.peak_bitrate,
+            average_bitrate: if duration > 0.0 {
+                (self.total_bits as f64 / duration) as u64
+            } else {
+                0
+            },
+            frames: self.frames,
+            violations: self.violations(),
+        })
+    }
+
+    /// The report, or the first exceeded limit as an error.
+    ///
+    /// Fails with [`ProfileLevelError::NoSuitableLevel`] if no valid SPS was
+    /// seen.
+    pub fn check(&self) -> Result<ConformanceReport, ProfileLevelError> {
+        let report = self.report().ok_or(ProfileLevelError::NoSuitableLevel)?;
+        if report.sps.level().is_none() {
+            return Err(ProfileLevelError::NoSuitableLevel);
+        }
+        match report.violations.first() {
+            Some(violation) => Err(*violation),
+            None => Ok(report),
+        }
+    }
+}
+
+/// Size of a NAL unit in the byte stream, with a 4-byte start code or length.
+fn nal_bits(nal: &NalUnit) -> u64 {
+    (nal.data.len() as u64 + 4) * 8
+}
+
+fn same_kind(a: &ProfileLevelError, b: &ProfileLevelError) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::SampleTiming;
+
+    /// Writes bits MSB first, for building test SPS NAL units.
+    struct BitWriter(Vec<u8>, usize);
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, count: u32) {
+            for i in (0..count).rev() {
+                if self.1.is_multiple_of(8) {
+                    self.0.push(0);
+                }
+                let bit = ((value >> i) & 1) as u8;
+                *self.0.last_mut().unwrap() |= bit << (7 - self.1 % 8);
+                self.1 += 1;
+            }
+        }
+
+        fn ue(&mut self, value: u32) {
+            let code = value + 1;
+            let len = 32 - code.leading_zeros();
+            self.bits(0, len - 1);
+            self.bits(code, len);
+        }
+    }
+
+    /// High profile 1920x1080 SPS with cropping and 30 fps VUI timing.
+    fn sps_1080p(level_idc: u32, ref_frames: u32) -> Vec<u8> {
+        let mut w = BitWriter(vec![0x67], 8);
+        w.bits(100, 8);
+        w.bits(0, 8);
+        w.bits(level_idc, 8);
+        w.ue(0); // sps id
+        w.ue(1); // chroma_format_idc 4:2:0
+        w.ue(0);
+        w.ue(0);
+        w.bits(0, 2); // bypass, no scaling matrix
+        w.ue(0); // log2_max_frame_num_minus4
+        w.ue(2); // pic_order_cnt_type
+        w.ue(ref_frames);
+        w.bits(0, 1);
+        w.ue(119); // 120 MBs wide
+        w.ue(67); // 68 MBs high
+        w.bits(0b101, 3); // frame_mbs_only, direct_8x8, frame_cropping
+        w.ue(0);
+        w.ue(0);
+        w.ue(0);
+        w.ue(4); // crop 8 lines at the bottom
+        w.bits(1, 1); // VUI present
+        w.bits(0, 4); // no aspect ratio, overscan, signal type or chroma loc
+        w.bits(1, 1); // timing info
+        w.bits(1, 32);
+        w.bits(60, 32);
+        w.bits(1, 1);
+        w.bits(1, 1); // rbsp stop bit
+        w.0
+    }
+
+    fn frame(index: i64, bytes: usize, fps: i64) -> MediaFrame {
+        MediaFrame {
+            nal_units: vec![NalUnit {
+                nal_type: 1,
+                data: vec![0; bytes],
+            }],
+            timing: SampleTiming {
+                pts: index,
+                dts: index,
+                duration: 1,
+                timescale: fps as i32,
+            },
+            is_keyframe: index == 0,
+            motion_score: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_sps() {
+        let sps = SpsInfo::parse(&sps_1080p(40, 4)).unwrap();
+        assert_eq!((sps.width, sps.height), (1920, 1080));
+        assert_eq!((sps.width_mbs, sps.height_mbs), (120, 68));
+        assert_eq!(sps.level(), Some(Level::L4));
+        assert_eq!(sps.profile(), Profile::High);
+        assert_eq!(sps.max_num_ref_frames, 4);
+        assert_eq!(sps.frame_rate, Some(30.0));
+    }
+
+    #[test]
+    fn test_detects_exceeded_limits() {
+        let mut checker = ConformanceChecker::new();
+        assert!(checker.set_sps(&sps_1080p(40, 4)));
+        // 30 fps at ~10 Mbps conforms to level 4
+        for i in 0..60 {
+            assert_eq!(checker.push_frame(&frame(i, 41_000, 30)), None);
+        }
+        let report = checker.check().unwrap();
+        assert!((29.0..=31.0).contains(&report.peak_frame_rate));
+        assert!(report.average_bitrate > 9_000_000 && report.average_bitrate < 11_000_000);
+
+        // The encoder switching to 60 fps at ~30 Mbps exceeds both limits
+        let mut checker = ConformanceChecker::new();
+        checker.set_sps(&sps_1080p(40, 4));
+        let violations: Vec<_> = (0..120)
+            .filter_map(|i| checker.push_frame(&frame(i, 62_500, 60)))
+            .collect();
+        assert!(matches!(
+            violations[..],
+            [
+                ProfileLevelError::MacroblockRateTooHigh { .. },
+                ProfileLevelError::BitrateTooHigh { .. }
+            ]
+        ));
+        assert!(checker.check().is_err());
+
+        // Too many reference frames for 1080p at level 4 (max 4)
+        let mut checker = ConformanceChecker::new();
+        checker.set_sps(&sps_1080p(40, 5));
+        assert!(matches!(
+            checker.report().unwrap().violations[..],
+            [ProfileLevelError::DpbTooLarge { max: 4, .. }]
+        ));
+    }
+}
@@ -0,0 +1,400 @@
+//! Region-of-interest cropping before encode, with smooth digital pan/zoom.
+
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, CFTypeRef, OSStatus};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use super::pixel_buffer_pool::OutputPool;
+use super::sendable::SendablePixelBuffer;
+use crate::cv_types::{
+    kCVAttachmentMode_ShouldNotPropagate, kCVImageBufferCleanApertureHeightKey,
+    kCVImageBufferCleanApertureHorizontalOffsetKey, kCVImageBufferCleanApertureKey,
+    kCVImageBufferCleanApertureVerticalOffsetKey, kCVImageBufferCleanApertureWidthKey,
+    CVBufferGetAttachment, CVBufferRemoveAttachment, CVBufferSetAttachment, CVPixelBufferGetHeight,
+    CVPixelBufferGetPixelFormatType, CVPixelBufferGetWidth, CVPixelBufferRef,
+};
+use crate::pixel_transfer::{
+    kVTPixelTransferPropertyKey_ScalingMode, kVTScalingMode_CropSourceToCleanAperture,
+    VTPixelTransferSessionCreate, VTPixelTransferSessionInvalidate, VTPixelTransferSessionRef,
+    VTPixelTransferSessionTransferImage,
+};
+use crate::session::VTSessionSetProperty;
+
+/// A region of the source image in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CropRect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl CropRect {
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The whole of a `width` x `height` image.
+    pub fn full(width: usize, height: usize) -> Self {
+        Self::new(0.0, 0.0, width as f64, height as f64)
+    }
+
+    /// Move and shrink the rectangle to fit inside a `width` x `height` image,
+    /// keeping at least 2x2 pixels.
+    pub fn clamp_to(self, width: usize, height: usize) -> Self {
+        let (max_w, max_h) = (width.max(2) as f64, height.max(2) as f64);
+        let w = self.width.clamp(2.0, max_w);
+        let h = self.height.clamp(2.0, max_h);
+        Self::new(
+            self.x.clamp(0.0, max_w - w),
+            self.y.clamp(0.0, max_h - h),
+            w,
+            h,
+        )
+    }
+
+    /// Round down to even pixel positions and sizes, as required for 4:2:0
+    /// chroma subsampling. A clamped rectangle stays inside the image.
+    pub fn align_even(self) -> Self {
+        let even = |v: f64| (v / 2.0).floor() * 2.0;
+        Self::new(
+            even(self.x),
+            even(self.y),
+            even(self.width).max(2.0),
+            even(self.height).max(2.0),
+        )
+    }
+
+    fn lerp(self, to: Self, t: f64) -> Self {
+        let mix = |a: f64, b: f64| a + (b - a) * t;
+        Self::new(
+            mix(self.x, to.x),
+            mix(self.y, to.y),
+            mix(self.width, to.width),
+            mix(self.height, to.height),
+        )
+    }
+}
+
+/// A pan/zoom between two regions; `None` stands for the full source frame.
+#[derive(Debug, Clone, Copy, Default)]
+struct Transition {
+    from: Option<CropRect>,
+    to: Option<CropRect>,
+    frames: u32,
+    elapsed: u32,
+    /// Full frame of the most recent source
+    source: Option<CropRect>,
+}
+
+impl Transition {
+    fn current(&self) -> Option<CropRect> {
+        let to = self.to.or(self.source)?;
+        if self.elapsed >= self.frames {
+            return Some(to);
+        }
+        let from = self.from.or(self.source).unwrap_or(to);
+        let t = self.elapsed as f64 / self.frames as f64;
+        // Smoothstep: ease in and out so pans don't start or stop abruptly
+        Some(from.lerp(to, t * t * (3.0 - 2.0 * t)))
+    }
+}
+
+/// Handle for changing the crop region of a [`RegionCropper`] from another
+/// thread, e.g. a UI or tracking thread.
+#[derive(Debug, Clone, Default)]
+pub struct CropControl {
+    transition: Arc<Mutex<Transition>>,
+}
+
+impl CropControl {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Transition> {
+        self.transition.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Pan/zoom to `rect` over the next `frames` frames; 0 jumps immediately.
+    pub fn set_region(&self, rect: CropRect, frames: u32) {
+        self.transition(Some(rect), frames);
+    }
+
+    /// Go back to the full source frame over the next `frames` frames.
+    pub fn reset(&self, frames: u32) {
+        self.transition(None, frames);
+    }
+
+    fn transition(&self, to: Option<CropRect>, frames: u32) {
+        let mut transition = self.lock();
+        *transition = Transition {
+            from: transition.current(),
+            to,
+            frames,
+            elapsed: 0,
+            source: transition.source,
+        };
+    }
+
+    /// The region being moved towards; `None` for the full source frame.
+    pub fn target(&self) -> Option<CropRect> {
+        self.lock().to
+    }
+
+    /// The region for the next `width` x `height` source frame, advancing any
+    /// transition by one frame.
+    fn advance(&self, width: usize, height: usize) -> CropRect {
+        let mut transition = self.lock();
+        transition.source = Some(CropRect::full(width, height));
+        let rect = transition
+            .current()
+            .unwrap_or(CropRect::full(width, height));
+        transition.elapsed = transition.elapsed.saturating_add(1);
+        rect.clamp_to(width, height).align_even()
+    }
+}
+
+/// Crops each frame to a region of interest and scales it to a fixed output
+/// size before encoding.
+///
+/// The output size never changes, so a 4K capture can be cropped to any
+/// region and encoded by a 1080p [`CompressionSession`](super::CompressionSession)
+/// without re-creating it. The region can be changed at any time through a
+/// [`CropControl`], optionally as a smooth transition for digital pan and
+/// zoom.
+///
+/// Cropping uses a VTPixelTransferSession with
+/// `kVTScalingMode_CropSourceToCleanAperture`: the region is attached to the
+/// source buffer as its clean aperture for the transfer, and the source's
+/// own clean aperture, if any, is put back afterwards. Regions are clamped
+/// to the source and aligned to even pixels. Output buffers come from a
+/// pool and are recycled once dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{CropRect, RegionCropper};
+/// # let pixel_buffer = std::ptr::null_mut();
+///
+/// let mut cropper = RegionCropper::new(1920, 1080)?;
+/// let control = cropper.control();
+///
+/// // Zoom into the top-left quarter of a 4K frame over one second at 30 fps
+/// control.set_region(CropRect::new(0.0, 0.0, 1920.0, 1080.0), 30);
+///
+/// let cropped = unsafe { cropper.crop(pixel_buffer)? };
+/// // encode cropped.as_raw()
+/// # Ok::<(), i32>(())
+/// ```
+pub struct RegionCropper {
+    session: VTPixelTransferSessionRef,
+    width: usize,
+    height: usize,
+    control: CropControl,
+    pool: OutputPool,
+}
+
+// SAFETY: the transfer session is only used through `&mut self`.
+unsafe impl Send for RegionCropper {}
+
+impl RegionCropper {
+    /// Create a cropper producing `width` x `height` frames. Until a region is
+    /// set, the whole source is scaled to the output size.
+    pub fn new(width: usize, height: usize) -> Result<Self, OSStatus> {
+        let mut session: VTPixelTransferSessionRef = ptr::null();
+        unsafe {
+            // The binding takes the out-pointer as an untyped reference
+            let status = VTPixelTransferSessionCreate(
+                kCFAllocatorDefault,
+                &mut session as *mut VTPixelTransferSessionRef as VTPixelTransferSessionRef,
+            );
+            if status != 0 {
+                return Err(status);
+            }
+            let status = VTSessionSetProperty(
+                session,
+                kVTPixelTransferPropertyKey_ScalingMode,
+                kVTScalingMode_CropSourceToCleanAperture as CFTypeRef,
+            );
+            if status != 0 {
+                VTPixelTransferSessionInvalidate(session);
+                CFRelease(session);
+                return Err(status);
+            }
+        }
+        Ok(Self {
+            session,
+            width,
+            height,
+            control: CropControl::default(),
+            pool: OutputPool::default(),
+        })
+    }
+
+    /// Output size in pixels.
+    pub fn output_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// A handle for changing the region, usable from any thread.
+    pub fn control(&self) -> CropControl {
+        self.control.clone()
+    }
+
+    /// Crop `source` to the current region into an output-sized buffer in
+    /// the same pixel format.
+    ///
+    /// # Safety
+    ///
+    /// `source` must be a valid CVPixelBuffer, and nothing else may write
+    /// to it or read or change its attachments during the call.
+    pub unsafe fn crop(
+        &mut self,
+        source: CVPixelBufferRef,
+    ) -> Result<SendablePixelBuffer, OSStatus> {
+        let (src_w, src_h) = (
+            CVPixelBufferGetWidth(source),
+            CVPixelBufferGetHeight(source),
+        );
+        let rect = self.control.advance(src_w, src_h);
+
+        let output = self.pool.acquire(
+            self.width,
+            self.height,
+            CVPixelBufferGetPixelFormatType(source),
+        )?;
+
+        let aperture = clean_aperture(rect, src_w, src_h);
+        let status = with_clean_aperture(source, &aperture, || {
+            VTPixelTransferSessionTransferImage(self.session, source, output.as_raw())
+        });
+        if status != 0 {
+            return Err(status);
+        }
+        Ok(output)
+    }
+}
+
+impl Drop for RegionCropper {
+    fn drop(&mut self) {
+        unsafe {
+            VTPixelTransferSessionInvalidate(self.session);
+            CFRelease(self.session);
+        }
+    }
+}
+
+/// Clean aperture dictionary for `rect`. Offsets are from the center of the
+/// image to the center of the aperture.
+pub(super) fn clean_aperture(rect: CropRect, width: usize, height: usize) -> CFDictionary {
+    let (horizontal, vertical) = aperture_offsets(rect, width, height);
+    let key = |k| unsafe { CFString::wrap_under_get_rule(k) };
+    let pairs = unsafe {
+        [
+            (kCVImageBufferCleanApertureWidthKey, rect.width),
+            (kCVImageBufferCleanApertureHeightKey, rect.height),
+            (kCVImageBufferCleanApertureHorizontalOffsetKey, horizontal),
+            (kCVImageBufferCleanApertureVerticalOffsetKey, vertical),
+        ]
+    };
+    let pairs: Vec<_> = pairs
+        .iter()
+        .map(|&(k, v)| (key(k).as_CFType(), CFNumber::from(v).as_CFType()))
+        .collect();
+    CFDictionary::from_CFType_pairs(&pairs).to_untyped()
+}
+
+/// Run `f` with `aperture` attached to `buffer` as its clean aperture,
+/// then restore the buffer's previous clean aperture and its propagation
+/// mode, or remove the key if it had none.
+///
+/// # Safety
+///
+/// `buffer` must be valid, and nothing else may read or change its
+/// attachments until this returns.
+pub(super) unsafe fn with_clean_aperture<R>(
+    buffer: CVPixelBufferRef,
+    aperture: &CFDictionary,
+    f: impl FnOnce() -> R,
+) -> R {
+    let mut mode = kCVAttachmentMode_ShouldNotPropagate;
+    let previous = CVBufferGetAttachment(buffer, kCVImageBufferCleanApertureKey, &mut mode);
+    // Retained before it is replaced, which releases the buffer's reference
+    let previous = (!previous.is_null()).then(|| CFType::wrap_under_get_rule(previous));
+    CVBufferSetAttachment(
+        buffer,
+        kCVImageBufferCleanApertureKey,
+        aperture.as_CFTypeRef(),
+        kCVAttachmentMode_ShouldNotPropagate,
+    );
+    let result = f();
+    match previous {
+        Some(previous) => CVBufferSetAttachment(
+            buffer,
+            kCVImageBufferCleanApertureKey,
+            previous.as_CFTypeRef(),
+            mode,
+        ),
+        None => CVBufferRemoveAttachment(buffer, kCVImageBufferCleanApertureKey),
+    }
+    result
+}
+
+fn aperture_offsets(rect: CropRect, width: usize, height: usize) -> (f64, f64) {
+    (
+        rect.x + rect.width / 2.0 - width as f64 / 2.0,
+        rect.y + rect.height / 2.0 - height as f64 / 2.0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smooth_transition() {
+        let full = CropRect::full(3840, 2160);
+        let zoomed = CropRect::new(960.0, 540.0, 1920.0, 1080.0);
+        let control = CropControl::default();
+        // Set before the first frame: the pan starts from the full frame
+        control.set_region(zoomed, 4);
+
+        let regions: Vec<CropRect> = (0..6).map(|_| control.advance(3840, 2160)).collect();
+        assert_eq!(regions[0], full);
+        assert_eq!(regions[2], full.lerp(zoomed, 0.5));
+        // Eased: the first step moves less than a linear pan would
+        assert!(regions[1].width > full.lerp(zoomed, 0.25).width);
+        assert_eq!(regions[4], zoomed);
+        assert_eq!(regions[5], zoomed);
+
+        // Retargeting mid-transition continues from the current region
+        control.reset(4);
+        control.advance(3840, 2160);
+        control.advance(3840, 2160);
+        control.set_region(zoomed, 2);
+        assert_eq!(control.advance(3840, 2160), full.lerp(zoomed, 0.5));
+        assert_eq!(control.target(), Some(zoomed));
+    }
+
+    #[test]
+    fn test_clamp_align_and_offsets() {
+        let rect = CropRect::new(3000.0, -20.0, 1921.0, 1081.0)
+            .clamp_to(3840, 2160)
+            .align_even();
+        assert_eq!(rect, CropRect::new(1918.0, 0.0, 1920.0, 1080.0));
+
+        let huge = CropRect::new(10.0, 10.0, 5000.0, 5000.0).clamp_to(1280, 720);
+        assert_eq!(huge, CropRect::full(1280, 720));
+
+        let quarter = CropRect::new(0.0, 0.0, 1920.0, 1080.0);
+        assert_eq!(aperture_offsets(quarter, 3840, 2160), (-960.0, -540.0));
+        let center = CropRect::new(960.0, 540.0, 1920.0, 1080.0);
+        assert_eq!(aperture_offsets(center, 3840, 2160), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,454 @@
+//! Live MPEG-DASH output: a dynamic MPD manifest for CMAF segments.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::cmaf_demuxer::{find_child, CmafDemuxer};
+use super::codec_string::h264_codec_string;
+use super::events::emit;
+use super::hls::SegmentRetention;
+use super::sink::{DirectorySink, Segment, SegmentSink};
+
+/// Timescale assumed when the init segment cannot be parsed.
+const DEFAULT_TIMESCALE: u32 = 90000;
+
+/// DASH manifest settings.
+#[derive(Debug, Clone)]
+pub struct DashConfig {
+    /// Manifest file name in the output directory
+    pub manifest_name: String,
+    /// Segments listed in the manifest; 0 keeps every segment
+    pub window: usize,
+    /// Segments kept on disk after leaving the window, for clients still
+    /// fetching from an older manifest
+    pub safety_margin: usize,
+    /// Duration assumed for segments that do not carry one
+    pub default_duration: Duration,
+    /// How often clients should reload the manifest (`minimumUpdatePeriod`)
+    pub minimum_update_period: Duration,
+    /// Declared bitrate of the representation in bits per second
+    pub bandwidth: u32,
+    /// RFC 6381 codec string; derived from the init segment's SPS if `None`
+    pub codecs: Option<String>,
+}
+
+impl Default for DashConfig {
+    fn default() -> Self {
+        Self {
+            manifest_name: "manifest.mpd".to_string(),
+            window: 6,
+            safety_margin: 2,
+            default_duration: Duration::from_secs(2),
+            minimum_update_period: Duration::from_secs(2),
+            bandwidth: 4_000_000,
+            codecs: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TimelineEntry {
+    sequence_number: u32,
+    /// Start and duration in timescale units
+    start: u64,
+    duration: u64,
+    path: PathBuf,
+}
+
+/// Writes CMAF segments to a directory along with a live-profile DASH
+/// manifest, deleting segments once they fall out of the window.
+///
+/// The manifest uses a `SegmentTemplate` with `$Number$` addressing that
+/// matches [`Segment::file_name`] (`segment_$Number%03d$.m4s`), and a
+/// `SegmentTimeline` with each segment's actual duration, so keyframe-aligned
+/// segments of varying length play correctly. Codec, size and timescale are
+/// read from the init segment.
+///
+/// `availabilityStartTime` is the wall-clock time the first media segment
+/// started. The manifest is rewritten atomically after each segment, and
+/// [`finish`](Self::finish) turns it into a static presentation. Retention
+/// works as in [`HlsSink`](super::HlsSink), including the
+/// [`before_delete`](Self::before_delete) hook.
+///
+/// Sequence numbers must be consecutive, as `$Number$` addressing cannot
+/// skip segments.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{DashConfig, DashSink, HlsConfig, HlsSink};
+///
+/// // DASH alone
+/// let dash = DashSink::new("live", DashConfig::default())?;
+///
+/// // HLS and DASH over the same segment files: the HLS sink writes and
+/// // retires them, the DASH sink only writes its manifest
+/// let hls = HlsSink::new("live", HlsConfig::default())?;
+/// let manifest = DashSink::manifest_only("live", DashConfig::default())?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct DashSink {
+    dir: DirectorySink,
+    config: DashConfig,
+    write_segments: bool,
+    entries: VecDeque<TimelineEntry>,
+    retention: SegmentRetention,
+    timescale: u32,
+    codecs: Option<String>,
+    size: Option<(u32, u32)>,
+    /// Decode time of the first media segment, in timescale units
+    first_start: Option<u64>,
+    next_start: u64,
+    availability_start: Option<SystemTime>,
+    has_init: bool,
+    ended: bool,
+}
+
+impl DashSink {
+    /// Write segments and the manifest into `dir`, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>, config: DashConfig) -> io::Result<Self> {
+        let retention = SegmentRetention::new(config.safety_margin);
+        let codecs = config.codecs.clone();
+        Ok(Self {
+            dir: DirectorySink::new(dir)?,
+            config,
+            write_segments: true,
+            entries: VecDeque::new(),
+            retention,
+            timescale: DEFAULT_TIMESCALE,
+            codecs,
+            size: None,
+            first_start: None,
+            next_start: 0,
+            availability_start: None,
+            has_init: false,
+            ended: false,
+        })
+    }
+
+    /// Write only the manifest into `dir`, for segments written (and
+    /// retired, with the same window) by another sink such as an
+    /// [`HlsSink`](super::HlsSink).
+    pub fn manifest_only(dir: impl Into<PathBuf>, config: DashConfig) -> io::Result<Self> {
+        let mut sink = Self::new(dir, config)?;
+        sink.write_segments = false;
+        Ok(sink)
+    }
+
+    /// Call `hook` with each segment file before it is deleted.
+    ///
+    /// If the hook fails the file is kept and the hook is called again after
+    /// the next segment.
+    pub fn before_delete<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&Path) -> io::Result<()> + Send + 'static,
+    {
+        self.retention.before_delete = Some(Box::new(hook));
+        self
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.dir.dir().join(&self.config.manifest_name)
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.dir()
+    }
+
+    /// Segment files currently listed in the manifest, oldest first.
+    pub fn segments(&self) -> impl Iterator<Item = &Path> {
+        self.entries.iter().map(|entry| entry.path.as_path())
+    }
+
+    /// Mark the stream as ended (a static MPD) and rewrite the manifest.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.ended = true;
+        self.write_manifest()
+    }
+
+    /// Render the current manifest.
+    pub fn manifest(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        out.push_str(concat!(
+            r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011""#,
+            r#" profiles="urn:mpeg:dash:profile:isoff-live:2011""#
+        ));
+        let min_buffer = self.config.default_duration;
+        if self.ended {
+            let total = self.next_start - self.first_start.unwrap_or(0);
+            let _ = write!(
+                out,
+                r#" type="static" mediaPresentationDuration="{}""#,
+                xs_duration(self.ticks_to_duration(total))
+            );
+        } else {
+            let _ = write!(
+                out,
+                r#" type="dynamic" minimumUpdatePeriod="{}""#,
+                xs_duration(self.config.minimum_update_period)
+            );
+            if self.config.window > 0 {
+                let window: u64 = self.entries.iter().map(|e| e.duration).sum();
+                let _ = write!(
+                    out,
+                    r#" timeShiftBufferDepth="{}""#,
+                    xs_duration(self.ticks_to_duration(window))
+                );
+            }
+        }
+        let start = self.availability_start.unwrap_or_else(SystemTime::now);
+        let _ = writeln!(
+            out,
+            r#" availabilityStartTime="{}" publishTime="{}" minBufferTime="{}">"#,
+            xs_date_time(start),
+            xs_date_time(SystemTime::now()),
+            xs_duration(min_buffer)
+        );
+
+        let _ = writeln!(out, r#"  <Period id="0" start="PT0S">"#);
+        out.push_str(concat!(
+            r#"    <AdaptationSet contentType="video" mimeType="video/mp4""#,
+            r#" segmentAlignment="true" startWithSAP="1">"#,
+            "\n"
+        ));
+        let start_number = self.entries.front().map_or(1, |e| e.sequence_number);
+        let initialization = if self.has_init {
+            r#" initialization="init.mp4""#
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            out,
+            concat!(
+                r#"      <SegmentTemplate timescale="{}" presentationTimeOffset="{}"{}"#,
+                r#" media="segment_$Number%03d$.m4s" startNumber="{}">"#
+            ),
+            self.timescale,
+            self.first_start.unwrap_or(0),
+            initialization,
+            start_number
+        );
+        let _ = writeln!(out, "        <SegmentTimeline>");
+        for (start, duration, repeat) in self.timeline() {
+            let repeat = if repeat > 0 {
+                format!(r#" r="{}""#, repeat)
+            } else {
+                String::new()
+            };
+            let _ = writeln!(
+                out,
+                r#"          <S t="{}" d="{}"{}/>"#,
+                start, duration, repeat
+            );
+        }
+        let _ = writeln!(out, "        </SegmentTimeline>");
+        let _ = writeln!(out, "      </SegmentTemplate>");
+
+        let _ = write!(out, r#"      <Representation id="video""#);
+        if let Some(codecs) = &self.codecs {
+            let _ = write!(out, r#" codecs="{}""#, codecs);
+        }
+        let _ = write!(out, r#" bandwidth="{}""#, self.config.bandwidth);
+        if let Some((width, height)) = self.size {
+            let _ = write!(out, r#" width="{}" height="{}""#, width, height);
+        }
+        let _ = writeln!(out, "/>");
+        let _ = writeln!(out, "    </AdaptationSet>");
+        let _ = writeln!(out, "  </Period>");
+        let _ = writeln!(out, "</MPD>");
+        out
+    }
+
+    /// `(t, d, r)` runs of contiguous segments with equal durations.
+    fn timeline(&self) -> Vec<(u64, u64, u32)> {
+        let mut runs: Vec<(u64, u64, u32)> = Vec::new();
+        for entry in &self.entries {
+            match runs.last_mut() {
+                Some((start, duration, repeat))
+                    if *duration == entry.duration
+                        && *start + *duration * (*repeat as u64 + 1) == entry.start =>
+                {
+                    *repeat += 1;
+                }
+                _ => runs.push((entry.start, entry.duration, 0)),
+            }
+        }
+        runs
+    }
+
+    fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        Duration::from_secs_f64(ticks as f64 / self.timescale as f64)
+    }
+
+    fn duration_to_ticks(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * self.timescale as f64).round() as u64
+    }
+
+    fn read_init(&mut self, init: &[u8]) {
+        let mut demuxer = CmafDemuxer::new();
+        if demuxer.push(init).is_err() {
+            return;
+        }
+        if let Some(track) = demuxer.track() {
+            self.timescale = track.timescale;
+            self.size = Some((track.width, track.height));
+            if self.config.codecs.is_none() {
+                self.codecs = h264_codec_string(&track.parameter_sets.sps);
+            }
+        }
+    }
+
+    /// Write the manifest via a temporary file so readers never see a partial one.
+    fn write_manifest(&self) -> io::Result<()> {
+        let path = self.manifest_path();
+        let tmp = path.with_extension("mpd.tmp");
+        fs::write(&tmp, self.manifest())?;
+        fs::rename(&tmp, &path)
+    }
+}
+
+impl SegmentSink for DashSink {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        if self.write_segments {
+            self.dir.write_segment(segment)?;
+        }
+        if segment.is_init() {
+            self.has_init = true;
+            self.read_init(&segment.data);
+            return self.write_manifest();
+        }
+
+        let duration = segment.duration.unwrap_or(self.config.default_duration);
+        let ticks = self.duration_to_ticks(duration);
+        let start = match self.first_start {
+            Some(_) => self.next_start,
+            None => {
+                let start = base_media_decode_time(&segment.data).unwrap_or(0);
+                self.first_start = Some(start);
+                // The first segment has just been completed
+                self.availability_start = SystemTime::now().checked_sub(duration);
+                start
+            }
+        };
+        self.next_start = start + ticks;
+        self.entries.push_back(TimelineEntry {
+            sequence_number: segment.sequence_number,
+            start,
+            duration: ticks,
+            path: self.dir.segment_path(segment),
+        });
+        while self.config.window > 0 && self.entries.len() > self.config.window {
+            let entry = self.entries.pop_front().unwrap();
+            if self.write_segments {
+                self.retention.retire(entry.path);
+            }
+        }
+
+        // Only delete files once the manifest no longer references them
+        self.write_manifest()?;
+        for event in self.retention.collect() {
+            emit(event);
+        }
+        Ok(())
+    }
+}
+
+/// `tfdt` decode time of a media segment's first track fragment.
+fn base_media_decode_time(segment: &[u8]) -> Option<u64> {
+    let moof = find_child(segment, b"moof").ok()??;
+    let traf = find_child(moof, b"traf").ok()??;
+    let tfdt = find_child(traf, b"tfdt").ok()??;
+    match tfdt.first()? {
+        1 => Some(u64::from_be_bytes(tfdt.get(4..12)?.try_into().ok()?)),
+        _ => Some(u32::from_be_bytes(tfdt.get(4..8)?.try_into().ok()?) as u64),
+    }
+}
+
+/// `xs:duration` in seconds, e.g. `PT2.002S`.
+fn xs_duration(duration: Duration) -> String {
+    format!("PT{:.3}S", duration.as_secs_f64())
+}
+
+/// `xs:dateTime` in UTC with millisecond precision.
+pub(super) fn xs_date_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn media(sequence_number: u32, millis: u64) -> Segment {
+        Segment::media(sequence_number, vec![0; 4]).with_duration(Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn test_live_manifest_timeline_and_window() {
+        let dir = std::env::temp_dir().join(format!("vt-dash-{}", std::process::id()));
+        let config = DashConfig {
+            window: 3,
+            safety_margin: 0,
+            ..Default::default()
+        };
+        let mut dash = DashSink::new(&dir, config).unwrap();
+        dash.write_segment(&Segment::init(vec![0; 4])).unwrap();
+        for (seq, millis) in [(1, 2000), (2, 2000), (3, 2000), (4, 2000), (5, 1500)] {
+            dash.write_segment(&media(seq, millis)).unwrap();
+        }
+
+        let manifest = fs::read_to_string(dash.manifest_path()).unwrap();
+        assert!(manifest.contains(r#"type="dynamic" minimumUpdatePeriod="PT2.000S""#));
+        assert!(manifest.contains(r#"timeShiftBufferDepth="PT5.500S""#));
+        assert!(manifest.contains(r#"initialization="init.mp4""#));
+        assert!(manifest.contains(r#"media="segment_$Number%03d$.m4s" startNumber="3""#));
+        // Segments 3 and 4 share a run; 1 and 2 have left the window
+        assert!(manifest.contains(r#"<S t="360000" d="180000" r="1"/>"#));
+        assert!(manifest.contains(r#"<S t="720000" d="135000"/>"#));
+        assert!(!dir.join("segment_002.m4s").exists());
+        assert!(dir.join("segment_003.m4s").exists());
+
+        dash.finish().unwrap();
+        let manifest = fs::read_to_string(dash.manifest_path()).unwrap();
+        assert!(manifest.contains(r#"type="static" mediaPresentationDuration="PT9.500S""#));
+        assert!(!manifest.contains("minimumUpdatePeriod"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_xs_date_time() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(xs_date_time(time), "2024-02-29T12:34:56.789Z");
+        assert_eq!(xs_date_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(xs_duration(Duration::from_millis(2002)), "PT2.002S");
+    }
+}
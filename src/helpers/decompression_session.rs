@@ -0,0 +1,654 @@
+//! Safe wrapper around VTDecompressionSession.
+
+use core_foundation::base::TCFType;
+use core_foundation::boolean::CFBoolean;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, CFRetain, CFTypeRef, OSStatus};
+use core_foundation_sys::dictionary::CFDictionaryRef;
+use core_foundation_sys::number::CFBooleanRef;
+use core_media_sys::{CMSampleBufferRef, CMTime, CMVideoFormatDescriptionRef};
+use libc::c_void;
+use std::ptr;
+use std::sync::mpsc::{self, Receiver};
+#[cfg(feature = "pixel-rotation")]
+use std::sync::Mutex;
+
+use super::access_unit::sample_to_avcc;
+use super::callback_target::{offload, CallbackTarget};
+use super::cv_ffi::kCVPixelBufferPixelFormatTypeKey;
+use super::deterministic::is_deterministic;
+use super::events::{catch_callback_panic, in_callback_of, CallbackScope};
+#[cfg(feature = "pixel-rotation")]
+use super::leak_tracker::release_pixel_buffer;
+use super::leak_tracker::{track, untrack, TrackedKind};
+use super::nal_extractor::{parse_annex_b, H264ParameterSets, NalUnit, SampleTiming};
+#[cfg(feature = "pixel-rotation")]
+use super::rotation::{ImageRotator, OutputOrientation};
+use super::sample_buffer::create_encoded_sample_buffer;
+use super::sendable::SendablePixelBuffer;
+use super::trace::trace_event;
+use crate::cm_sample_buffer::{
+    CMVideoFormatDescriptionCreateFromH264ParameterSets,
+    CMVideoFormatDescriptionGetH264ParameterSetAtIndex,
+    CMVideoFormatDescriptionGetHEVCParameterSetAtIndex,
+};
+use crate::cv_types::CVImageBufferRef;
+use crate::errors::{kVTInvalidSessionErr, kVTParameterErr};
+use crate::session::{VTSessionCopyProperty, VTSessionSetProperty};
+use crate::decompression::{
+    kVTDecompressionPropertyKey_ContentHasInterframeDependencies,
+    kVTDecompressionPropertyKey_PropagatePerFrameHDRDisplayMetadata,
+    kVTVideoDecoderSpecification_EnableHardwareAcceleratedVideoDecoder, DecodeFrameFlags,
+    DecodeInfoFlags, VTDecodeInfoFlags, VTDecompressionOutputCallbackRecord,
+    VTDecompressionSessionCreate, VTDecompressionSessionDecodeFrame,
+    VTDecompressionSessionInvalidate, VTDecompressionSessionRef,
+    VTDecompressionSessionWaitForAsynchronousFrames,
+};
+
+/// Configuration for a decompression session.
+#[derive(Debug, Clone)]
+pub struct DecompressionSessionConfig {
+    /// Enable hardware accelerated decoding
+    pub hardware_accelerated: bool,
+    /// Requested output pixel format (FourCC), or `None` for the decoder's native format
+    pub pixel_format: Option<u32>,
+    /// Rotate and mirror decoded images before they reach the output callback,
+    /// e.g. so portrait phone footage is delivered upright (macOS 13, iOS 16)
+    #[cfg(feature = "pixel-rotation")]
+    pub orientation: OutputOrientation,
+    /// Attach per-frame HDR display metadata to decoded images (default: true)
+    pub propagate_hdr_metadata: bool,
+    /// Where the output callback runs
+    pub callback_target: CallbackTarget,
+}
+
+impl Default for DecompressionSessionConfig {
+    fn default() -> Self {
+        Self {
+            hardware_accelerated: true,
+            pixel_format: None,
+            #[cfg(feature = "pixel-rotation")]
+            orientation: OutputOrientation::default(),
+            propagate_hdr_metadata: true,
+            callback_target: CallbackTarget::Inline,
+        }
+    }
+}
+
+/// Per-frame decode options.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Flags passed to `VTDecompressionSessionDecodeFrame`.
+    pub flags: DecodeFrameFlags,
+}
+
+impl DecodeOptions {
+    /// Synchronous decode with output (no flags set).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode the frame without emitting an image buffer.
+    ///
+    /// Use this to feed reference frames to the decoder while seeking. The output
+    /// callback receives [`DecodeOutput::Suppressed`] for such frames.
+    pub fn suppressed() -> Self {
+        Self::new().suppress_output(true)
+    }
+
+    /// Asynchronous decode with temporal processing and the 1x real-time
+    /// playback hint, as used by [`PlaybackDecoder`](super::PlaybackDecoder).
+    pub fn playback() -> Self {
+        Self::new()
+            .asynchronous(true)
+            .temporal_processing(true)
+            .real_time_playback(true)
+    }
+
+    /// Set or clear `kVTDecodeFrame_DoNotOutputFrame`.
+    pub fn suppress_output(mut self, enabled: bool) -> Self {
+        self.flags
+            .set(DecodeFrameFlags::DO_NOT_OUTPUT_FRAME, enabled);
+        self
+    }
+
+    /// Set or clear `kVTDecodeFrame_EnableAsynchronousDecompression`.
+    pub fn asynchronous(mut self, enabled: bool) -> Self {
+        self.flags
+            .set(DecodeFrameFlags::ENABLE_ASYNCHRONOUS_DECOMPRESSION, enabled);
+        self
+    }
+
+    /// Set or clear `kVTDecodeFrame_1xRealTimePlayback`.
+    pub fn real_time_playback(mut self, enabled: bool) -> Self {
+        self.flags
+            .set(DecodeFrameFlags::REAL_TIME_PLAYBACK_1X, enabled);
+        self
+    }
+
+    /// Set or clear `kVTDecodeFrame_EnableTemporalProcessing`, allowing the
+    /// decoder to delay output for processing that spans several frames.
+    pub fn temporal_processing(mut self, enabled: bool) -> Self {
+        self.flags
+            .set(DecodeFrameFlags::ENABLE_TEMPORAL_PROCESSING, enabled);
+        self
+    }
+}
+
+/// Result delivered to the decompression output callback.
+#[derive(Debug, Clone, Copy)]
+pub enum DecodeOutput {
+    /// A decoded image. The buffer is only valid for the duration of the callback
+    /// unless retained by the caller.
+    Frame {
+        image_buffer: CVImageBufferRef,
+        pts: CMTime,
+        duration: CMTime,
+        info: DecodeInfoFlags,
+    },
+    /// The frame was decoded with [`DecodeOptions::suppressed`] and produced no image.
+    Suppressed { pts: CMTime },
+    /// The decoder dropped the frame.
+    Dropped { pts: CMTime },
+    /// Decoding failed with the given status.
+    Error(OSStatus),
+}
+
+/// A decoded frame, retained for as long as it is kept.
+///
+/// Read the pixels with [`SendablePixelBuffer::lock`], whose guard cannot
+/// outlive the frame.
+#[derive(Debug)]
+pub struct DecodedFrame {
+    /// The decoded image
+    pub pixel_buffer: SendablePixelBuffer,
+    pub pts: CMTime,
+    pub duration: CMTime,
+}
+
+type OutputCallback = Box<dyn Fn(DecodeOutput) + Send + Sync>;
+
+/// A VTDecompressionSession that owns its output callback.
+///
+/// The session is waited on, invalidated and released when dropped.
+///
+/// # Reentrancy
+///
+/// The session must not be driven from inside its own output callback:
+/// [`decode`](Self::decode), [`prime`](Self::prime) and
+/// [`wait_for_asynchronous_frames`](Self::wait_for_asynchronous_frames) return
+/// `kVTInvalidSessionErr` when called there. Panics in the callback are caught
+/// and reported as [`PipelineEvent::CallbackPanicked`](super::PipelineEvent::CallbackPanicked).
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{
+///     parse_annex_b, DecodeOptions, DecompressionSession, DecompressionSessionConfig,
+///     H264ParameterSets, SampleTiming,
+/// };
+///
+/// # let parameter_sets = H264ParameterSets { sps: vec![], pps: vec![], nal_length_size: 4 };
+/// # let stream: Vec<u8> = Vec::new();
+/// let config = DecompressionSessionConfig::default();
+/// let (session, frames) = DecompressionSession::h264_channel(&parameter_sets, &config).unwrap();
+///
+/// let timing = SampleTiming { pts: 0, dts: 0, duration: 3000, timescale: 90000 };
+/// session.decode_annex_b(&stream, timing, DecodeOptions::new()).unwrap();
+/// for frame in frames.try_iter() {
+///     let frame = frame.unwrap();
+///     let pixels = frame.pixel_buffer.lock().unwrap();
+///     println!("{} bytes per row at {:?}", pixels.bytes_per_row(), frame.pts);
+/// }
+/// ```
+pub struct DecompressionSession {
+    session: VTDecompressionSessionRef,
+    callback: *mut OutputCallback,
+    /// Retained; used to wrap encoded data passed to the `decode_*` methods
+    format_desc: CMVideoFormatDescriptionRef,
+    nal_length_size: usize,
+}
+
+unsafe impl Send for DecompressionSession {}
+unsafe impl Sync for DecompressionSession {}
+
+impl DecompressionSession {
+    /// Create a decompression session for the given format description.
+    ///
+    /// # Safety
+    ///
+    /// `format_desc` must be a valid video format description.
+    pub unsafe fn new<F>(
+        format_desc: CMVideoFormatDescriptionRef,
+        config: &DecompressionSessionConfig,
+        callback: F,
+    ) -> Result<Self, OSStatus>
+    where
+        F: Fn(DecodeOutput) + Send + Sync + 'static,
+    {
+        let hw_key = CFString::wrap_under_get_rule(
+            kVTVideoDecoderSpecification_EnableHardwareAcceleratedVideoDecoder,
+        );
+        // Deterministic mode forces the software decoder
+        let hw_value = if config.hardware_accelerated && !is_deterministic() {
+            CFBoolean::true_value()
+        } else {
+            CFBoolean::false_value()
+        };
+        let decoder_spec =
+            CFDictionary::from_CFType_pairs(&[(hw_key.as_CFType(), hw_value.as_CFType())]);
+
+        let dest_attrs = config.pixel_format.map(|format| {
+            let format_key = CFString::wrap_under_get_rule(kCVPixelBufferPixelFormatTypeKey);
+            CFDictionary::from_CFType_pairs(&[(
+                format_key.as_CFType(),
+                CFNumber::from(format as i32).as_CFType(),
+            )])
+        });
+        let dest_attrs_ref = dest_attrs
+            .as_ref()
+            .map(|d| d.as_concrete_TypeRef() as CFDictionaryRef)
+            .unwrap_or(ptr::null());
+
+        #[cfg(feature = "pixel-rotation")]
+        let callback: OutputCallback = if config.orientation.is_identity() {
+            Box::new(callback)
+        } else {
+            let rotator = Mutex::new(ImageRotator::new(config.orientation)?);
+            Box::new(move |output| rotate_output(&rotator, output, &callback))
+        };
+        #[cfg(not(feature = "pixel-rotation"))]
+        let callback: OutputCallback = Box::new(callback);
+        // Rotation runs on the callback target too
+        let callback = offload(
+            config.callback_target,
+            "decompression output",
+            decoded_image_buffer,
+            callback,
+        );
+        let callback: *mut OutputCallback = Box::into_raw(Box::new(callback));
+        let record = VTDecompressionOutputCallbackRecord {
+            decompressionOutputCallback: output_trampoline,
+            decompressionOutputRefCon: callback as *mut c_void,
+        };
+
+        let mut session: VTDecompressionSessionRef = ptr::null_mut();
+        let status = VTDecompressionSessionCreate(
+            kCFAllocatorDefault,
+            format_desc,
+            decoder_spec.as_concrete_TypeRef() as CFDictionaryRef,
+            dest_attrs_ref,
+            &record,
+            &mut session,
+        );
+
+        if status != 0 {
+            drop(Box::from_raw(callback));
+            return Err(status);
+        }
+
+        track(TrackedKind::DecompressionSession, session);
+        CFRetain(format_desc as *const c_void);
+        let session = Self {
+            session,
+            callback,
+            format_desc,
+            nal_length_size: nal_length_size(format_desc),
+        };
+        if !config.propagate_hdr_metadata {
+            let status = VTSessionSetProperty(
+                session.session,
+                kVTDecompressionPropertyKey_PropagatePerFrameHDRDisplayMetadata,
+                CFBoolean::false_value().as_concrete_TypeRef() as CFTypeRef,
+            );
+            crate::errors::status_to_result(status)?;
+        }
+        Ok(session)
+    }
+
+    /// Create an H.264 session from the stream's SPS and PPS, delivering
+    /// decoded frames to `callback`.
+    ///
+    /// Frames are delivered in decode order. Failed decodes are reported as
+    /// errors; dropped and suppressed frames are skipped.
+    pub fn h264<F>(
+        parameter_sets: &H264ParameterSets,
+        config: &DecompressionSessionConfig,
+        callback: F,
+    ) -> Result<Self, OSStatus>
+    where
+        F: Fn(Result<DecodedFrame, OSStatus>) + Send + Sync + 'static,
+    {
+        let pointers = [parameter_sets.sps.as_ptr(), parameter_sets.pps.as_ptr()];
+        let sizes = [parameter_sets.sps.len(), parameter_sets.pps.len()];
+        unsafe {
+            let mut format_desc = ptr::null_mut();
+            let status = CMVideoFormatDescriptionCreateFromH264ParameterSets(
+                kCFAllocatorDefault,
+                pointers.len(),
+                pointers.as_ptr(),
+                sizes.as_ptr(),
+                parameter_sets.nal_length_size,
+                &mut format_desc,
+            );
+            if status != 0 {
+                return Err(status);
+            }
+            let session = Self::new(format_desc, config, move |output| match output {
+                DecodeOutput::Frame {
+                    image_buffer,
+                    pts,
+                    duration,
+                    ..
+                } => callback(Ok(DecodedFrame {
+                    pixel_buffer: SendablePixelBuffer::retain(image_buffer),
+                    pts,
+                    duration,
+                })),
+                DecodeOutput::Error(status) => callback(Err(status)),
+                DecodeOutput::Suppressed { .. } | DecodeOutput::Dropped { .. } => {}
+            });
+            // The session holds its own reference
+            CFRelease(format_desc as *const c_void);
+            session
+        }
+    }
+
+    /// Like [`h264`](Self::h264), but delivers decoded frames through a channel.
+    pub fn h264_channel(
+        parameter_sets: &H264ParameterSets,
+        config: &DecompressionSessionConfig,
+    ) -> Result<(Self, Receiver<Result<DecodedFrame, OSStatus>>), OSStatus> {
+        let (sender, receiver) = mpsc::channel();
+        let session = Self::h264(parameter_sets, config, move |frame| {
+            // The receiver may have been dropped; the decoder carries on
+            let _ = sender.send(frame);
+        })?;
+        Ok((session, receiver))
+    }
+
+    /// Decode a single sample buffer.
+    ///
+    /// The decode flags are carried to the output callback, so frames decoded with
+    /// [`DecodeOptions::suppressed`] are reported as [`DecodeOutput::Suppressed`].
+    ///
+    /// # Safety
+    ///
+    /// `sample_buffer` must be a valid sample buffer matching the session's format.
+    pub unsafe fn decode(
+        &self,
+        sample_buffer: CMSampleBufferRef,
+        options: DecodeOptions,
+    ) -> Result<DecodeInfoFlags, OSStatus> {
+        self.check_not_reentrant()?;
+        let mut info_flags: VTDecodeInfoFlags = 0;
+        let status = VTDecompressionSessionDecodeFrame(
+            self.session,
+            sample_buffer,
+            options.flags.bits(),
+            options.flags.bits() as usize as *mut c_void,
+            &mut info_flags,
+        );
+        if status != 0 {
+            return Err(status);
+        }
+        Ok(DecodeInfoFlags::from_bits_retain(info_flags))
+    }
+
+    /// Decode one frame given as a length-prefixed (AVCC) sample, using the
+    /// length prefix size of the session's format description.
+    pub fn decode_avcc(
+        &self,
+        sample: &[u8],
+        timing: SampleTiming,
+        options: DecodeOptions,
+    ) -> Result<DecodeInfoFlags, OSStatus> {
+        unsafe {
+            let sample_buffer = create_encoded_sample_buffer(sample, self.format_desc, timing)?;
+            self.decode(sample_buffer.as_raw(), options)
+        }
+    }
+
+    /// Decode the NAL units of one frame, e.g. an
+    /// [`AccessUnit`](super::AccessUnit)'s. Parameter sets and AUDs are left
+    /// out, as the decoder takes them from the format description.
+    pub fn decode_nal_units(
+        &self,
+        nal_units: &[NalUnit],
+        timing: SampleTiming,
+        options: DecodeOptions,
+    ) -> Result<DecodeInfoFlags, OSStatus> {
+        let sample =
+            sample_to_avcc(nal_units, self.nal_length_size).map_err(|_| kVTParameterErr)?;
+        self.decode_avcc(&sample, timing, options)
+    }
+
+    /// Decode one frame given in Annex B format (start code delimited).
+    pub fn decode_annex_b(
+        &self,
+        data: &[u8],
+        timing: SampleTiming,
+        options: DecodeOptions,
+    ) -> Result<DecodeInfoFlags, OSStatus> {
+        self.decode_nal_units(&parse_annex_b(data), timing, options)
+    }
+
+    /// Prime the decoder with reference frames ahead of a seek target.
+    ///
+    /// Every sample buffer is decoded with output suppressed; decode the target
+    /// frame afterwards with [`DecompressionSession::decode`] to display it.
+    ///
+    /// # Safety
+    ///
+    /// All sample buffers must be valid and in decode order, starting at a keyframe.
+    pub unsafe fn prime(&self, sample_buffers: &[CMSampleBufferRef]) -> Result<(), OSStatus> {
+        for &sample_buffer in sample_buffers {
+            self.decode(sample_buffer, DecodeOptions::suppressed())?;
+        }
+        self.wait_for_asynchronous_frames()
+    }
+
+    /// Block until all pending asynchronous frames have been emitted.
+    pub fn wait_for_asynchronous_frames(&self) -> Result<(), OSStatus> {
+        self.check_not_reentrant()?;
+        let status = unsafe { VTDecompressionSessionWaitForAsynchronousFrames(self.session) };
+        crate::errors::status_to_result(status)
+    }
+
+    /// Whether the decoder reports that frames reference each other, as
+    /// opposed to an intra-only stream. `None` if the decoder does not say.
+    pub fn content_has_interframe_dependencies(&self) -> Option<bool> {
+        let mut value: CFTypeRef = ptr::null();
+        let status = unsafe {
+            VTSessionCopyProperty(
+                self.session,
+                kVTDecompressionPropertyKey_ContentHasInterframeDependencies,
+                kCFAllocatorDefault,
+                &mut value as *mut CFTypeRef as *mut c_void,
+            )
+        };
+        if status != 0 || value.is_null() {
+            return None;
+        }
+        let value = unsafe { CFBoolean::wrap_under_create_rule(value as CFBooleanRef) };
+        Some(value.into())
+    }
+
+    /// Get the underlying session reference.
+    pub fn as_raw(&self) -> VTDecompressionSessionRef {
+        self.session
+    }
+
+    fn check_not_reentrant(&self) -> Result<(), OSStatus> {
+        if in_callback_of(self.callback as *const c_void) {
+            return Err(kVTInvalidSessionErr);
+        }
+        Ok(())
+    }
+}
+
+/// The image buffer a [`DecodeOutput`] refers to, retained while the output
+/// is queued for another thread.
+fn decoded_image_buffer(output: &DecodeOutput) -> CFTypeRef {
+    match output {
+        DecodeOutput::Frame { image_buffer, .. } => *image_buffer as CFTypeRef,
+        _ => ptr::null(),
+    }
+}
+
+/// Pass `output` to `callback` with its image rotated.
+#[cfg(feature = "pixel-rotation")]
+fn rotate_output<F>(rotator: &Mutex<ImageRotator>, output: DecodeOutput, callback: &F)
+where
+    F: Fn(DecodeOutput),
+{
+    let DecodeOutput::Frame {
+        image_buffer,
+        pts,
+        duration,
+        info,
+    } = output
+    else {
+        return callback(output);
+    };
+    let mut rotator = rotator.lock().unwrap_or_else(|e| e.into_inner());
+    match unsafe { rotator.rotate(image_buffer) } {
+        Ok(rotated) => {
+            drop(rotator);
+            callback(DecodeOutput::Frame {
+                image_buffer: rotated,
+                pts,
+                duration,
+                info,
+            });
+            unsafe { release_pixel_buffer(rotated) };
+        }
+        Err(status) => {
+            drop(rotator);
+            callback(DecodeOutput::Error(status));
+        }
+    }
+}
+
+/// The sample length prefix size of an H.264 or HEVC format description,
+/// defaulting to 4.
+unsafe fn nal_length_size(format_desc: CMVideoFormatDescriptionRef) -> usize {
+    let mut header_length = 0;
+    let status = CMVideoFormatDescriptionGetH264ParameterSetAtIndex(
+        format_desc,
+        0,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        &mut header_length,
+    );
+    if status != 0 {
+        CMVideoFormatDescriptionGetHEVCParameterSetAtIndex(
+            format_desc,
+            0,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut header_length,
+        );
+    }
+    match header_length {
+        1 | 2 | 4 => header_length as usize,
+        _ => 4,
+    }
+}
+
+impl Drop for DecompressionSession {
+    fn drop(&mut self) {
+        unsafe {
+            // Waiting from the output callback would deadlock.
+            if !in_callback_of(self.callback as *const c_void) {
+                VTDecompressionSessionWaitForAsynchronousFrames(self.session);
+            }
+            VTDecompressionSessionInvalidate(self.session);
+            untrack(self.session);
+            CFRelease(self.session);
+            CFRelease(self.format_desc as *const c_void);
+            drop(Box::from_raw(self.callback));
+        }
+    }
+}
+
+/// Trampoline translating the raw VideoToolbox callback into a [`DecodeOutput`].
+extern "C" fn output_trampoline(
+    output_ref: *mut c_void,
+    source_ref: *mut c_void,
+    status: OSStatus,
+    info_flags: VTDecodeInfoFlags,
+    image_buffer: CVImageBufferRef,
+    pts: CMTime,
+    duration: CMTime,
+) {
+    let callback = unsafe { &*(output_ref as *const OutputCallback) };
+    let requested = DecodeFrameFlags::from_bits_retain(source_ref as usize as u32);
+    let output = decode_output(requested, status, info_flags, image_buffer, pts, duration);
+
+    let _scope = CallbackScope::enter(output_ref);
+    catch_callback_panic("decompression output", || callback(output));
+}
+
+/// The [`DecodeOutput`] for a decoder output of a frame decoded with `requested`.
+pub(super) fn decode_output(
+    requested: DecodeFrameFlags,
+    status: OSStatus,
+    info_flags: VTDecodeInfoFlags,
+    image_buffer: CVImageBufferRef,
+    pts: CMTime,
+    duration: CMTime,
+) -> DecodeOutput {
+    let info = DecodeInfoFlags::from_bits_retain(info_flags);
+    trace_event("vt.decode_output", status as i64, pts.value);
+
+    if status != 0 {
+        DecodeOutput::Error(status)
+    } else if info.contains(DecodeInfoFlags::FRAME_DROPPED) {
+        DecodeOutput::Dropped { pts }
+    } else if image_buffer.is_null() && requested.contains(DecodeFrameFlags::DO_NOT_OUTPUT_FRAME) {
+        DecodeOutput::Suppressed { pts }
+    } else if image_buffer.is_null() {
+        DecodeOutput::Dropped { pts }
+    } else {
+        DecodeOutput::Frame {
+            image_buffer,
+            pts,
+            duration,
+            info,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_options_flags() {
+        assert!(DecodeOptions::new().flags.is_empty());
+        assert_eq!(
+            DecodeOptions::suppressed().flags,
+            DecodeFrameFlags::DO_NOT_OUTPUT_FRAME
+        );
+
+        let options = DecodeOptions::new()
+            .asynchronous(true)
+            .real_time_playback(true)
+            .suppress_output(true)
+            .suppress_output(false);
+        assert_eq!(
+            options.flags,
+            DecodeFrameFlags::ENABLE_ASYNCHRONOUS_DECOMPRESSION
+                | DecodeFrameFlags::REAL_TIME_PLAYBACK_1X
+        );
+        assert_eq!(
+            DecodeOptions::playback().flags,
+            options.flags | DecodeFrameFlags::ENABLE_TEMPORAL_PROCESSING
+        );
+    }
+}
@@ -5,17 +5,51 @@
 use libc::c_void;
 use objc2::declare::ClassBuilder;
 use objc2::rc::Retained;
-use objc2::runtime::{AnyProtocol, Bool, Sel};
+use objc2::runtime::{AnyClass, AnyObject, AnyProtocol, Bool, Sel};
 use objc2::{sel, ClassType};
 use objc2_foundation::NSObject;
+use std::collections::BTreeMap;
 use std::ffi::CStr;
 use std::ptr;
+use std::sync::RwLock;
+
+use super::events::catch_callback_panic;
 
 /// Callback function signature for capture delegate methods.
 ///
 /// Arguments: (self, _cmd, output, sample_buffer, connection)
+///
+/// The callback is called through a shim that catches panics and reports
+/// them as [`PipelineEvent::CallbackPanicked`](super::PipelineEvent::CallbackPanicked)
+/// instead of unwinding into the Objective-C runtime. Declare it
+/// `extern "C-unwind"` so the panic can reach the shim.
 pub type DelegateCallback =
-    extern "C" fn(*mut c_void, Sel, *mut c_void, *mut c_void, *mut c_void);
+    extern "C-unwind" fn(*mut c_void, Sel, *mut c_void, *mut c_void, *mut c_void);
+
+/// User callbacks of the registered delegate classes, by class address.
+static CALLBACKS: RwLock<BTreeMap<usize, DelegateCallback>> = RwLock::new(BTreeMap::new());
+
+/// The method implementation of every delegate class: runs the class's
+/// callback, catching panics.
+extern "C" fn delegate_trampoline(
+    this: *mut c_void,
+    cmd: Sel,
+    output: *mut c_void,
+    sample_buffer: *mut c_void,
+    connection: *mut c_void,
+) {
+    let class = unsafe { &*(this as *const AnyObject) }.class() as *const AnyClass as usize;
+    let callback = CALLBACKS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&class)
+        .copied();
+    if let Some(callback) = callback {
+        catch_callback_panic("capture delegate", || {
+            callback(this, cmd, output, sample_buffer, connection)
+        });
+    }
+}
 
 // Dispatch queue creation
 #[link(name = "System")]
@@ -43,7 +77,7 @@ extern "C" {
 /// use objc2::runtime::Sel;
 /// use libc::c_void;
 ///
-/// extern "C" fn my_callback(
+/// extern "C-unwind" fn my_callback(
 ///     _this: *mut c_void,
 ///     _cmd: Sel,
 ///     _output: *mut c_void,
@@ -85,6 +119,25 @@ pub fn create_capture_delegate_cstr(
     class_name: &CStr,
     protocol_name: &CStr,
     callback: DelegateCallback,
+) -> Result<Retained<NSObject>, &'static str> {
+    // Method signature: v@:@@@ (void, self, _cmd, output, sampleBuffer, connection)
+    create_delegate_with_method(
+        class_name,
+        protocol_name,
+        sel!(captureOutput:didOutputSampleBuffer:fromConnection:),
+        c"v@:@@@",
+        callback,
+    )
+}
+
+/// Register a delegate class implementing `protocol_name` with a single
+/// method backed by `callback`, and create an instance of it.
+pub(super) fn create_delegate_with_method(
+    class_name: &CStr,
+    protocol_name: &CStr,
+    method_sel: Sel,
+    method_types: &CStr,
+    callback: DelegateCallback,
 ) -> Result<Retained<NSObject>, &'static str> {
     let protocol = AnyProtocol::get(protocol_name).ok_or("Protocol not found")?;
 
@@ -94,19 +147,20 @@ pub fn create_capture_delegate_cstr(
     let delegate_class = builder.register();
 
     unsafe {
-        let method_sel = sel!(captureOutput:didOutputSampleBuffer:fromConnection:);
-        // Method signature: v@:@@@ (void, self, _cmd, output, sampleBuffer, connection)
-        let method_types = b"v@:@@@\0";
         let added = class_addMethod(
             delegate_class as *const _ as *const c_void,
             method_sel,
-            callback as *const c_void,
-            method_types.as_ptr() as *const i8,
+            delegate_trampoline as *const c_void,
+            method_types.as_ptr(),
         );
 
         if !added.as_bool() {
             return Err("Failed to add method to delegate class");
         }
+        CALLBACKS
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(delegate_class as *const AnyClass as usize, callback);
 
         let delegate: Retained<NSObject> = objc2::msg_send![delegate_class, new];
         Ok(delegate)
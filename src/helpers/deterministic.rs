@@ -0,0 +1,114 @@
+//! Deterministic software-only mode for reproducible test output.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use super::compression_builder::CompressionSessionConfig;
+use super::encoder_comparison::software_encoder_id;
+
+/// Environment variable enabling deterministic mode (`1`, `true`, `yes` or `on`).
+pub const DETERMINISTIC_ENV: &str = "VIDEO_TOOLBOX_DETERMINISTIC";
+
+/// Keyframe interval used when a deterministic encoder config leaves it unset.
+pub const DETERMINISTIC_KEYFRAME_INTERVAL: i32 = 30;
+
+const UNSET: u8 = 0;
+const DISABLED: u8 = 1;
+const ENABLED: u8 = 2;
+
+static OVERRIDE: AtomicU8 = AtomicU8::new(UNSET);
+
+/// Force deterministic mode on or off for the whole process, taking precedence
+/// over [`DETERMINISTIC_ENV`].
+///
+/// Hardware encoders differ between Mac models and OS versions, so byte-exact
+/// or hash-based tests only hold up across CI machines on the software paths.
+/// While enabled, sessions built with [`CompressionSessionBuilder`](super::CompressionSessionBuilder)
+/// and [`DecompressionSession`](super::DecompressionSession) are adjusted as
+/// described in [`apply_deterministic`].
+pub fn set_deterministic(enabled: bool) {
+    OVERRIDE.store(if enabled { ENABLED } else { DISABLED }, Ordering::Relaxed);
+}
+
+/// Remove a [`set_deterministic`] override, deferring to [`DETERMINISTIC_ENV`] again.
+pub fn clear_deterministic() {
+    OVERRIDE.store(UNSET, Ordering::Relaxed);
+}
+
+/// Whether deterministic mode is enabled.
+pub fn is_deterministic() -> bool {
+    match OVERRIDE.load(Ordering::Relaxed) {
+        ENABLED => true,
+        DISABLED => false,
+        _ => std::env::var(DETERMINISTIC_ENV)
+            .map(|value| parse_flag(&value))
+            .unwrap_or(false),
+    }
+}
+
+fn parse_flag(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes" | "on"
+    )
+}
+
+/// Adjust an encoder config for reproducible output.
+///
+/// - the software encoder is selected explicitly (for codecs that have one)
+/// - real-time and low-latency rate control, which adapt to system load, are
+///   turned off
+/// - frame reordering is disabled
+/// - the keyframe interval defaults to [`DETERMINISTIC_KEYFRAME_INTERVAL`]
+///
+/// Bitrate, quality and profile are left as configured.
+pub fn apply_deterministic(config: &mut CompressionSessionConfig) {
+    config.hardware_accelerated = false;
+    if config.encoder_id.is_none() {
+        config.encoder_id = software_encoder_id(config.codec).map(str::to_owned);
+    }
+    config.real_time = false;
+    config.low_latency = false;
+    config.allow_frame_reordering = Some(false);
+    config
+        .keyframe_interval
+        .get_or_insert(DETERMINISTIC_KEYFRAME_INTERVAL);
+    config
+        .properties
+        .retain(|property| !property.breaks_determinism());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs;
+
+    #[test]
+    fn test_parse_flag() {
+        assert!(parse_flag("1"));
+        assert!(parse_flag(" TRUE "));
+        assert!(parse_flag("on"));
+        assert!(!parse_flag("0"));
+        assert!(!parse_flag(""));
+    }
+
+    #[test]
+    fn test_apply_deterministic() {
+        let mut config = CompressionSessionConfig::new(640, 480, codecs::video::H264);
+        config.low_latency = true;
+        config.bitrate = Some(1_000_000);
+        apply_deterministic(&mut config);
+
+        assert!(!config.hardware_accelerated);
+        assert_eq!(
+            config.encoder_id.as_deref(),
+            Some("com.apple.videotoolbox.videoencoder.h264")
+        );
+        assert!(!config.real_time && !config.low_latency);
+        assert_eq!(config.allow_frame_reordering, Some(false));
+        assert_eq!(
+            config.keyframe_interval,
+            Some(DETERMINISTIC_KEYFRAME_INTERVAL)
+        );
+        assert_eq!(config.bitrate, Some(1_000_000));
+    }
+}
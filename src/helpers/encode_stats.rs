@@ -0,0 +1,375 @@
+//! Per-segment encoding statistics carried inside the segments, for QoE
+//! monitoring without a side channel.
+//!
+//! [`EncodeStatsSink`] adds a box before each media segment's `moof` with
+//! the segment's frame count, size, bitrate, dropped frames and average QP
+//! (when the application reports it). Players skip the box; a monitoring
+//! backend reads it back with [`read_encode_stats`].
+
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::audio_cmaf::write_box;
+use super::mfra::{find_box, read_u32};
+use super::sink::{Segment, SegmentSink};
+
+/// `scheme_id_uri` of the stats `emsg`, also the prefix of the `free` box payload.
+pub const ENCODE_STATS_SCHEME: &str = "urn:video-toolbox-sys:encode-stats";
+
+/// Encoding statistics of one media segment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SegmentEncodeStats {
+    /// Frames in the segment
+    pub frames: u32,
+    /// Frames dropped by the encoder since the previous segment
+    pub dropped_frames: u32,
+    /// Segment size in bytes, without the stats box
+    pub bytes: u64,
+    /// Media duration, zero if the segment did not carry one
+    pub duration: Duration,
+    /// Mean of the QP values reported for the segment's frames
+    pub average_qp: Option<f32>,
+}
+
+impl SegmentEncodeStats {
+    /// Bitrate of the segment, or 0 if its duration is unknown.
+    pub fn bitrate_bps(&self) -> u64 {
+        let secs = self.duration.as_secs_f64();
+        if secs > 0.0 {
+            (self.bytes as f64 * 8.0 / secs).round() as u64
+        } else {
+            0
+        }
+    }
+
+    /// Parse the `key=value` text written by `Display`. Unknown keys are ignored.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut stats = Self::default();
+        for field in text.split_whitespace() {
+            let (key, value) = field.split_once('=')?;
+            match key {
+                "frames" => stats.frames = value.parse().ok()?,
+                "dropped" => stats.dropped_frames = value.parse().ok()?,
+                "bytes" => stats.bytes = value.parse().ok()?,
+                "duration_ms" => stats.duration = Duration::from_millis(value.parse().ok()?),
+                "qp" => stats.average_qp = Some(value.parse().ok()?),
+                _ => {}
+            }
+        }
+        Some(stats)
+    }
+}
+
+impl fmt::Display for SegmentEncodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frames={} dropped={} bytes={} duration_ms={} bitrate_bps={}",
+            self.frames,
+            self.dropped_frames,
+            self.bytes,
+            self.duration.as_millis(),
+            self.bitrate_bps()
+        )?;
+        if let Some(qp) = self.average_qp {
+            write!(f, " qp={:.1}", qp)?;
+        }
+        Ok(())
+    }
+}
+
+/// Box that carries the stats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatsBox {
+    /// A version 0 `emsg` with [`ENCODE_STATS_SCHEME`], spanning the
+    /// segment; surfaced to applications by players that expose in-band
+    /// events (e.g. dash.js, Shaka)
+    #[default]
+    Emsg,
+    /// A `free` box, ignored by every player
+    Free,
+}
+
+#[derive(Debug, Default)]
+struct PendingStats {
+    dropped_frames: u32,
+    qp_sum: f64,
+    qp_count: u32,
+}
+
+/// Handle for reporting per-frame values from the encoder callback to an
+/// [`EncodeStatsSink`]. Clones share the same counters.
+#[derive(Debug, Clone, Default)]
+pub struct EncodeStatsRecorder {
+    pending: Arc<Mutex<PendingStats>>,
+}
+
+impl EncodeStatsRecorder {
+    /// Count a frame dropped by the encoder.
+    pub fn record_dropped(&self) {
+        self.lock().dropped_frames += 1;
+    }
+
+    /// Report the QP of an encoded frame, if the encoder exposes it.
+    pub fn record_qp(&self, qp: f32) {
+        let mut pending = self.lock();
+        pending.qp_sum += qp as f64;
+        pending.qp_count += 1;
+    }
+
+    /// Dropped frames and average QP since the last call.
+    fn take(&self) -> (u32, Option<f32>) {
+        let pending = std::mem::take(&mut *self.lock());
+        let qp = (pending.qp_count > 0).then(|| (pending.qp_sum / pending.qp_count as f64) as f32);
+        (pending.dropped_frames, qp)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PendingStats> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Adds a stats box to every media segment before passing it on.
+///
+/// Frame count and size come from the segment; dropped frames and QP from
+/// the [`recorder`](Self::recorder), whose values since the previous
+/// segment are attributed to this one. Init segments pass through unchanged.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{DirectorySink, EncodeStatsSink, StatsBox};
+///
+/// let mut sink = EncodeStatsSink::new(DirectorySink::new("out")?, StatsBox::Emsg);
+/// let recorder = sink.recorder();
+/// // In the encoder callback, on a dropped frame:
+/// recorder.record_dropped();
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct EncodeStatsSink<S> {
+    inner: S,
+    kind: StatsBox,
+    recorder: EncodeStatsRecorder,
+    last: Option<SegmentEncodeStats>,
+}
+
+impl<S: SegmentSink> EncodeStatsSink<S> {
+    pub fn new(inner: S, kind: StatsBox) -> Self {
+        Self {
+            inner,
+            kind,
+            recorder: EncodeStatsRecorder::default(),
+            last: None,
+        }
+    }
+
+    /// A handle for reporting dropped frames and QP values.
+    pub fn recorder(&self) -> EncodeStatsRecorder {
+        self.recorder.clone()
+    }
+
+    /// Stats of the last media segment written.
+    pub fn last_stats(&self) -> Option<&SegmentEncodeStats> {
+        self.last.as_ref()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: SegmentSink> SegmentSink for EncodeStatsSink<S> {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        if segment.is_init() {
+            return self.inner.write_segment(segment);
+        }
+        let (dropped_frames, average_qp) = self.recorder.take();
+        let stats = SegmentEncodeStats {
+            frames: sample_count(&segment.data),
+            dropped_frames,
+            bytes: segment.data.len() as u64,
+            duration: segment.duration.unwrap_or_default(),
+            average_qp,
+        };
+        let data = insert_stats_box(&segment.data, &stats, self.kind, segment.sequence_number);
+        self.last = Some(stats);
+        self.inner.write_segment(&Segment {
+            data,
+            ..segment.clone()
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Samples in the segment's first track run.
+fn sample_count(data: &[u8]) -> u32 {
+    let count = || {
+        let moof = find_box(data, 0, data.len(), b"moof")?;
+        let traf = find_box(data, moof.offset + 8, moof.end(), b"traf")?;
+        let trun = find_box(data, traf.offset + 8, traf.end(), b"trun")?;
+        // Header, then version and flags
+        read_u32(data, trun.offset + 12)
+    };
+    count().unwrap_or(0)
+}
+
+/// `data` with the stats box after its `styp` (or at the start). Sample
+/// data offsets are relative to `moof`, so they stay valid.
+fn insert_stats_box(
+    data: &[u8],
+    stats: &SegmentEncodeStats,
+    kind: StatsBox,
+    sequence_number: u32,
+) -> Vec<u8> {
+    let text = stats.to_string();
+    let mut payload = Vec::new();
+    match kind {
+        StatsBox::Emsg => {
+            payload.extend_from_slice(&[0, 0, 0, 0]); // version 0, flags
+            payload.extend_from_slice(ENCODE_STATS_SCHEME.as_bytes());
+            payload.push(0);
+            payload.push(0); // value
+            payload.extend_from_slice(&1000u32.to_be_bytes()); // timescale
+            payload.extend_from_slice(&0u32.to_be_bytes()); // presentation_time_delta
+            let duration_ms = stats.duration.as_millis() as u32;
+            payload.extend_from_slice(&duration_ms.to_be_bytes()); // event_duration
+            payload.extend_from_slice(&sequence_number.to_be_bytes()); // id
+        }
+        StatsBox::Free => {
+            payload.extend_from_slice(ENCODE_STATS_SCHEME.as_bytes());
+            payload.push(0);
+        }
+    }
+    payload.extend_from_slice(text.as_bytes());
+
+    let at = find_box(data, 0, data.len(), b"styp").map_or(0, |styp| styp.end());
+    let mut out = Vec::with_capacity(data.len() + payload.len() + 8);
+    out.extend_from_slice(&data[..at]);
+    let kind = match kind {
+        StatsBox::Emsg => b"emsg",
+        StatsBox::Free => b"free",
+    };
+    write_box(&mut out, kind, &payload);
+    out.extend_from_slice(&data[at..]);
+    out
+}
+
+/// Read the stats written by [`EncodeStatsSink`] from a media segment.
+pub fn read_encode_stats(segment: &[u8]) -> Option<SegmentEncodeStats> {
+    let mut offset = 0;
+    while offset + 8 <= segment.len() {
+        let size = read_u32(segment, offset)? as usize;
+        if size < 8 || offset + size > segment.len() {
+            return None;
+        }
+        let kind = &segment[offset + 4..offset + 8];
+        let payload = &segment[offset + 8..offset + size];
+        if let Some(text) = stats_text(kind, payload) {
+            return SegmentEncodeStats::parse(std::str::from_utf8(text).ok()?);
+        }
+        offset += size;
+    }
+    None
+}
+
+/// The stats text of a box written by [`insert_stats_box`], if it is one.
+fn stats_text<'a>(kind: &[u8], payload: &'a [u8]) -> Option<&'a [u8]> {
+    let scheme = ENCODE_STATS_SCHEME.as_bytes();
+    match kind {
+        b"emsg" if payload.first() == Some(&0) => {
+            let rest = payload.get(4..)?.strip_prefix(scheme)?.strip_prefix(&[0])?;
+            let value_end = rest.iter().position(|&b| b == 0)?;
+            // timescale, presentation_time_delta, event_duration, id
+            rest.get(value_end + 1 + 16..)
+        }
+        b"free" => payload.strip_prefix(scheme)?.strip_prefix(&[0]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{CmafConfig, CmafMuxer, FragmentEmission, NalUnit};
+
+    #[derive(Default)]
+    struct Collect(Vec<Segment>);
+
+    impl SegmentSink for Collect {
+        fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+            self.0.push(segment.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stats_box_round_trip() {
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            emission: FragmentEmission::PerFrame,
+            ..Default::default()
+        });
+        muxer.create_init_segment(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 1280, 720);
+        let idr = NalUnit {
+            data: vec![0x65, 0x88, 0x84],
+            nal_type: 5,
+        };
+        let fragment = muxer.add_frame(&[idr], 0, 0, 3000, true).unwrap();
+
+        for kind in [StatsBox::Emsg, StatsBox::Free] {
+            let mut sink = EncodeStatsSink::new(Collect::default(), kind);
+            let recorder = sink.recorder();
+            recorder.record_dropped();
+            recorder.record_qp(24.0);
+            recorder.record_qp(27.0);
+            let segment =
+                Segment::media(1, fragment.clone()).with_duration(Duration::from_millis(33));
+            sink.write_segment(&Segment::init(vec![0; 8])).unwrap();
+            sink.write_segment(&segment).unwrap();
+
+            let written = &sink.get_ref().0;
+            assert_eq!(written[0].data, vec![0; 8]);
+            let stats = read_encode_stats(&written[1].data).unwrap();
+            assert_eq!(&stats, sink.last_stats().unwrap());
+            assert_eq!(stats.frames, 1);
+            assert_eq!(stats.dropped_frames, 1);
+            assert_eq!(stats.bytes, fragment.len() as u64);
+            assert_eq!(stats.average_qp, Some(25.5));
+            assert_eq!(
+                stats.bitrate_bps(),
+                (fragment.len() as f64 * 8.0 / 0.033).round() as u64
+            );
+            // The rest of the segment is unchanged, moof still follows
+            assert_eq!(
+                written[1].data.len(),
+                fragment.len() + stats_box_len(&written[1].data)
+            );
+            assert!(find_box(&written[1].data, 0, written[1].data.len(), b"moof").is_some());
+
+            // Counters start over for the next segment
+            sink.write_segment(&segment).unwrap();
+            let stats = read_encode_stats(&sink.get_ref().0[2].data).unwrap();
+            assert_eq!((stats.dropped_frames, stats.average_qp), (0, None));
+        }
+        assert!(read_encode_stats(&fragment).is_none());
+    }
+
+    fn stats_box_len(data: &[u8]) -> usize {
+        [b"emsg", b"free"]
+            .iter()
+            .find_map(|kind| find_box(data, 0, data.len(), kind))
+            .map_or(0, |range| range.size)
+    }
+}
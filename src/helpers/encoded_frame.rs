@@ -0,0 +1,203 @@
+//! Encoded frames that borrow their data from the encoder's sample buffer.
+
+use core_foundation_sys::base::{CFRelease, CFRetain};
+use core_media_sys::CMSampleBufferRef;
+use libc::c_void;
+use std::ptr;
+use std::sync::OnceLock;
+
+use super::nal_extractor::{
+    nal_length_size, split_avcc, NalError, NalExtractor, NalUnit, SampleTiming,
+};
+use crate::cm_sample_buffer::{
+    CMBlockBufferCopyDataBytes, CMBlockBufferGetDataLength, CMBlockBufferGetDataPointer,
+    CMSampleBufferGetDataBuffer, CMSampleBufferGetFormatDescription,
+};
+
+/// Where the frame's bytes live.
+enum FrameData {
+    /// Contiguous memory inside the sample buffer's block buffer
+    Block { data: *const u8, len: usize },
+    /// A copy of a block buffer made of several memory blocks
+    Copied(Vec<u8>),
+}
+
+/// An encoded frame that keeps the encoder's CMSampleBuffer alive instead
+/// of copying its NAL units out.
+///
+/// Creating one only retains the sample buffer, so it is cheap enough to
+/// do in the output callback and hand the frame to another thread. The data
+/// is read in place on first use; only block buffers spread over several
+/// memory blocks (rare for encoder output) are copied. The sample buffer is
+/// released when the last clone is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::mpsc;
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{CompressionSessionBuilder, EncodeOutput, EncodedFrame};
+///
+/// let (tx, rx) = mpsc::channel();
+/// let session = CompressionSessionBuilder::new(1920, 1080, codecs::video::H264)
+///     .build_session(move |output| {
+///         if let EncodeOutput::Frame { sample_buffer, .. } = output {
+///             tx.send(unsafe { EncodedFrame::retain(sample_buffer) }).ok();
+///         }
+///     })?;
+///
+/// for frame in rx {
+///     for nal in frame.nal_units().expect("invalid frame") {
+///         // write `nal` without copying it
+///     }
+/// }
+/// # Ok::<(), i32>(())
+/// ```
+pub struct EncodedFrame {
+    sample_buffer: CMSampleBufferRef,
+    data: OnceLock<Result<FrameData, NalError>>,
+}
+
+// SAFETY: encoded sample buffers are immutable once emitted, their retain
+// count is atomic, and the lazily read data is guarded by the OnceLock.
+unsafe impl Send for EncodedFrame {}
+unsafe impl Sync for EncodedFrame {}
+
+impl EncodedFrame {
+    /// Retain an encoded sample buffer, e.g. from
+    /// [`EncodeOutput::Frame`](super::EncodeOutput::Frame).
+    ///
+    /// # Safety
+    ///
+    /// `sample_buffer` must be a valid, ready, encoded sample buffer.
+    pub unsafe fn retain(sample_buffer: CMSampleBufferRef) -> Self {
+        CFRetain(sample_buffer as *const c_void);
+        Self {
+            sample_buffer,
+            data: OnceLock::new(),
+        }
+    }
+
+    pub fn as_raw(&self) -> CMSampleBufferRef {
+        self.sample_buffer
+    }
+
+    /// The frame as length-prefixed (AVCC) NAL units, as stored in MP4
+    /// samples.
+    pub fn as_bytes(&self) -> Result<&[u8], NalError> {
+        let data = self
+            .data
+            .get_or_init(|| unsafe { read_data(self.sample_buffer) });
+        match data {
+            Ok(FrameData::Block { data, len }) => Ok(if *len == 0 {
+                &[]
+            } else {
+                unsafe { std::slice::from_raw_parts(*data, *len) }
+            }),
+            Ok(FrameData::Copied(bytes)) => Ok(bytes),
+            Err(e) => Err(*e),
+        }
+    }
+
+    /// NAL length prefix size from the sample's format description.
+    pub fn nal_length_size(&self) -> usize {
+        unsafe { nal_length_size(CMSampleBufferGetFormatDescription(self.sample_buffer)) }
+    }
+
+    /// The frame's NAL units, without length prefixes, borrowed from the
+    /// sample buffer.
+    pub fn nal_units(&self) -> Result<Vec<&[u8]>, NalError> {
+        split_avcc(self.as_bytes()?, self.nal_length_size())
+    }
+
+    /// Owned copies of the NAL units, for APIs that take [`NalUnit`]s.
+    pub fn to_nal_units(&self) -> Result<Vec<NalUnit>, NalError> {
+        Ok(self
+            .nal_units()?
+            .into_iter()
+            .map(|nal| NalUnit {
+                data: nal.to_vec(),
+                nal_type: nal[0] & 0x1F,
+            })
+            .collect())
+    }
+
+    /// Presentation/decode timing in `timescale` units.
+    pub fn timing(&self) -> SampleTiming {
+        unsafe { NalExtractor::new().get_timing(self.sample_buffer) }
+    }
+
+    /// Whether this is a sync sample (IDR frame).
+    pub fn is_keyframe(&self) -> bool {
+        unsafe { NalExtractor::new().is_keyframe(self.sample_buffer) }
+    }
+}
+
+impl Clone for EncodedFrame {
+    /// Retains the sample buffer again; the data is not copied.
+    fn clone(&self) -> Self {
+        unsafe { Self::retain(self.sample_buffer) }
+    }
+}
+
+impl Drop for EncodedFrame {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.sample_buffer as *const c_void) };
+    }
+}
+
+impl std::fmt::Debug for EncodedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncodedFrame")
+            .field("sample_buffer", &self.sample_buffer)
+            .field("timing", &self.timing())
+            .field("is_keyframe", &self.is_keyframe())
+            .finish()
+    }
+}
+
+/// Locate the sample's data, copying it only if it is not contiguous.
+unsafe fn read_data(sample_buffer: CMSampleBufferRef) -> Result<FrameData, NalError> {
+    let block_buffer = CMSampleBufferGetDataBuffer(sample_buffer);
+    if block_buffer.is_null() {
+        return Err(NalError::NoDataBuffer);
+    }
+    let total_length = CMBlockBufferGetDataLength(block_buffer);
+    if total_length == 0 {
+        return Ok(FrameData::Block {
+            data: ptr::null(),
+            len: 0,
+        });
+    }
+
+    let mut data: *mut u8 = ptr::null_mut();
+    let mut length_at_offset: usize = 0;
+    let status = CMBlockBufferGetDataPointer(
+        block_buffer,
+        0,
+        &mut length_at_offset,
+        ptr::null_mut(),
+        &mut data,
+    );
+    if status != 0 {
+        return Err(NalError::DataPointerFailed(status));
+    }
+    if length_at_offset >= total_length {
+        return Ok(FrameData::Block {
+            data,
+            len: total_length,
+        });
+    }
+
+    let mut bytes = vec![0u8; total_length];
+    let status = CMBlockBufferCopyDataBytes(
+        block_buffer,
+        0,
+        total_length,
+        bytes.as_mut_ptr() as *mut c_void,
+    );
+    if status != 0 {
+        return Err(NalError::DataPointerFailed(status));
+    }
+    Ok(FrameData::Copied(bytes))
+}
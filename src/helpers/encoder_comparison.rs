@@ -0,0 +1,456 @@
+//! Side-by-side comparison of the hardware and software encoders.
+//!
+//! Hardware encoders differ between chips and OS releases, so the right preset
+//! for one machine is not necessarily right for another. [`EncoderComparison`]
+//! encodes the same frames through both encoders with identical settings and
+//! reports, per GOP, how much larger, slower or worse one is than the other.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use core_foundation_sys::base::OSStatus;
+use core_media_sys::{CMSampleBufferRef, CMTime};
+
+use super::compression_builder::{CompressionSessionBuilder, CompressionSessionConfig};
+use super::compression_session::{CompressionSession, EncodeOutput};
+use super::decompression_session::{
+    DecodeOptions, DecodeOutput, DecompressionSession, DecompressionSessionConfig,
+};
+use super::frame_hash::FrameSnapshot;
+use super::nal_extractor::NalExtractor;
+use crate::cm_sample_buffer::{
+    CMSampleBufferGetFormatDescription, CMSampleBufferGetPresentationTimeStamp,
+    CMSampleBufferGetTotalSampleSize,
+};
+use crate::codecs;
+use crate::cv_types::CVPixelBufferRef;
+use crate::errors::kVTParameterErr;
+
+/// ID of Apple's software encoder for `codec`, if there is one.
+pub fn software_encoder_id(codec: u32) -> Option<&'static str> {
+    match codec {
+        codecs::video::H264 => Some("com.apple.videotoolbox.videoencoder.h264"),
+        codecs::video::HEVC => Some("com.apple.videotoolbox.videoencoder.hevc"),
+        _ => None,
+    }
+}
+
+/// Measurements of one GOP from one encoder.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GopStats {
+    /// Frames encoded
+    pub frames: u32,
+    /// Total encoded size in bytes
+    pub bytes: u64,
+    /// Mean time from submitting a frame to receiving its output
+    pub mean_latency: Duration,
+    pub max_latency: Duration,
+    /// Mean PSNR of the decoded frames against the source, in dB (`None`
+    /// until a frame has been decoded; infinite for lossless GOPs)
+    pub psnr: Option<f64>,
+}
+
+/// One GOP encoded by both encoders.
+///
+/// GOPs are paired by position, so both sessions should use the same
+/// keyframe interval (they do when created by [`EncoderComparison`]).
+#[derive(Debug, Clone, PartialEq)]
+pub struct GopComparison {
+    pub index: usize,
+    pub hardware: GopStats,
+    pub software: GopStats,
+}
+
+impl GopComparison {
+    /// Software size relative to hardware (above 1.0: software is larger).
+    pub fn size_ratio(&self) -> f64 {
+        self.software.bytes as f64 / self.hardware.bytes.max(1) as f64
+    }
+
+    /// Software mean latency minus hardware mean latency, in milliseconds.
+    pub fn latency_delta_ms(&self) -> f64 {
+        (self.software.mean_latency.as_secs_f64() - self.hardware.mean_latency.as_secs_f64())
+            * 1000.0
+    }
+
+    /// Software PSNR minus hardware PSNR, in dB.
+    pub fn psnr_delta(&self) -> Option<f64> {
+        Some(self.software.psnr? - self.hardware.psnr?)
+    }
+}
+
+impl fmt::Display for GopComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "GOP {:>3}: size hw {:>8} B sw {:>8} B ({:+.1}%), latency hw {:>6.1} ms sw {:>6.1} ms ({:+.1} ms)",
+            self.index,
+            self.hardware.bytes,
+            self.software.bytes,
+            (self.size_ratio() - 1.0) * 100.0,
+            self.hardware.mean_latency.as_secs_f64() * 1000.0,
+            self.software.mean_latency.as_secs_f64() * 1000.0,
+            self.latency_delta_ms(),
+        )?;
+        match (self.hardware.psnr, self.software.psnr) {
+            (Some(hw), Some(sw)) => {
+                write!(
+                    f,
+                    ", PSNR hw {:.2} dB sw {:.2} dB ({:+.2} dB)",
+                    hw,
+                    sw,
+                    sw - hw
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct GopAccumulator {
+    frames: u32,
+    bytes: u64,
+    latency: Duration,
+    max_latency: Duration,
+    psnr_sum: f64,
+    psnr_frames: u32,
+}
+
+impl GopAccumulator {
+    fn add_frame(&mut self, bytes: u64, latency: Duration) {
+        self.frames += 1;
+        self.bytes += bytes;
+        self.latency += latency;
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    fn add_psnr(&mut self, psnr: f64) {
+        self.psnr_sum += psnr;
+        self.psnr_frames += 1;
+    }
+
+    fn stats(&self) -> GopStats {
+        GopStats {
+            frames: self.frames,
+            bytes: self.bytes,
+            mean_latency: self.latency / self.frames.max(1),
+            max_latency: self.max_latency,
+            psnr: (self.psnr_frames > 0).then(|| self.psnr_sum / self.psnr_frames as f64),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SideState {
+    gops: Vec<GopAccumulator>,
+    /// Submission time and source image of frames not yet decoded, by PTS value
+    pending: HashMap<i64, (Instant, Arc<FrameSnapshot>)>,
+    /// GOP each encoded-but-not-decoded frame belongs to
+    frame_gop: HashMap<i64, usize>,
+    error: Option<OSStatus>,
+}
+
+impl SideState {
+    fn on_encoded(&mut self, pts: i64, bytes: u64, keyframe: bool) {
+        if keyframe || self.gops.is_empty() {
+            self.gops.push(GopAccumulator::default());
+        }
+        let latency = self
+            .pending
+            .get(&pts)
+            .map_or(Duration::ZERO, |(submitted, _)| submitted.elapsed());
+        let index = self.gops.len() - 1;
+        self.gops[index].add_frame(bytes, latency);
+        self.frame_gop.insert(pts, index);
+    }
+
+    fn on_decoded(&mut self, pts: i64, decoded: &FrameSnapshot) {
+        let source = self.pending.remove(&pts);
+        let gop = self.frame_gop.remove(&pts);
+        if let (Some((_, source)), Some(gop)) = (source, gop) {
+            if let Some(psnr) = source.psnr(decoded) {
+                self.gops[gop].add_psnr(psnr);
+            }
+        }
+    }
+}
+
+struct Side {
+    session: CompressionSession,
+    state: Arc<Mutex<SideState>>,
+}
+
+impl Side {
+    fn new(
+        builder: CompressionSessionBuilder,
+        hardware: bool,
+        pixel_format: u32,
+    ) -> Result<Self, OSStatus> {
+        let state = Arc::new(Mutex::new(SideState::default()));
+        let decoder: Arc<Mutex<Option<DecompressionSession>>> = Arc::new(Mutex::new(None));
+        let callback_state = state.clone();
+        let session = builder.build_session(move |output| match output {
+            EncodeOutput::Frame { sample_buffer, .. } => unsafe {
+                on_encoded(
+                    &callback_state,
+                    &decoder,
+                    sample_buffer,
+                    hardware,
+                    pixel_format,
+                );
+            },
+            EncodeOutput::Dropped { .. } => {}
+            EncodeOutput::Error(status) => lock(&callback_state).error = Some(status),
+        })?;
+        Ok(Self { session, state })
+    }
+}
+
+unsafe fn on_encoded(
+    state: &Arc<Mutex<SideState>>,
+    decoder: &Mutex<Option<DecompressionSession>>,
+    sample_buffer: CMSampleBufferRef,
+    hardware: bool,
+    pixel_format: u32,
+) {
+    let pts = CMSampleBufferGetPresentationTimeStamp(sample_buffer).value;
+    let bytes = CMSampleBufferGetTotalSampleSize(sample_buffer) as u64;
+    let keyframe = NalExtractor::new().is_keyframe(sample_buffer);
+    lock(state).on_encoded(pts, bytes, keyframe);
+
+    // Decode with the matching decoder to measure quality against the source
+    let mut decoder = decoder.lock().unwrap_or_else(|e| e.into_inner());
+    if decoder.is_none() {
+        let config = DecompressionSessionConfig {
+            hardware_accelerated: hardware,
+            pixel_format: Some(pixel_format),
+            ..Default::default()
+        };
+        let decode_state = state.clone();
+        let format_desc = CMSampleBufferGetFormatDescription(sample_buffer);
+        let created = DecompressionSession::new(format_desc, &config, move |output| {
+            if let DecodeOutput::Frame {
+                image_buffer, pts, ..
+            } = output
+            {
+                if let Ok(decoded) = FrameSnapshot::from_pixel_buffer(image_buffer) {
+                    lock(&decode_state).on_decoded(pts.value, &decoded);
+                }
+            }
+        });
+        match created {
+            Ok(session) => *decoder = Some(session),
+            Err(status) => lock(state).error = Some(status),
+        }
+    }
+    if let Some(session) = decoder.as_ref() {
+        if let Err(status) = session.decode(sample_buffer, DecodeOptions::new()) {
+            lock(state).error = Some(status);
+        }
+    }
+}
+
+fn lock(state: &Mutex<SideState>) -> MutexGuard<'_, SideState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Encodes the same frames with the hardware and the software encoder.
+///
+/// Both sessions share the configuration; only the encoder differs. Each
+/// output frame is decoded again to measure PSNR against the source, which
+/// makes this a diagnostic mode rather than something to ship in a live
+/// pipeline.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{CompressionSessionConfig, EncoderComparison};
+/// # fn frames() -> Vec<(video_toolbox_sys::cv_types::CVPixelBufferRef, core_media_sys::CMTime, core_media_sys::CMTime)> { Vec::new() }
+///
+/// let mut config = CompressionSessionConfig::new(1920, 1080, codecs::video::H264);
+/// config.bitrate = Some(6_000_000);
+/// config.keyframe_interval = Some(60);
+/// let comparison = EncoderComparison::new(config)?;
+/// for (pixel_buffer, pts, duration) in frames() {
+///     unsafe { comparison.encode_frame(pixel_buffer, pts, duration)? };
+/// }
+/// for gop in comparison.finish()? {
+///     println!("{}", gop);
+/// }
+/// # Ok::<(), i32>(())
+/// ```
+pub struct EncoderComparison {
+    hardware: Side,
+    software: Side,
+}
+
+impl EncoderComparison {
+    /// Create both sessions, using Apple's software encoder for the codec.
+    ///
+    /// Fails with `kVTParameterErr` if there is no software encoder for the
+    /// codec.
+    pub fn new(config: CompressionSessionConfig) -> Result<Self, OSStatus> {
+        let id = software_encoder_id(config.codec).ok_or(kVTParameterErr)?;
+        Self::with_software_encoder(config, id)
+    }
+
+    /// Create both sessions, using the encoder with `software_encoder_id` as
+    /// the software side.
+    pub fn with_software_encoder(
+        config: CompressionSessionConfig,
+        software_encoder_id: &str,
+    ) -> Result<Self, OSStatus> {
+        let pixel_format = config.pixel_format;
+        let mut hardware = config.clone();
+        hardware.hardware_accelerated = true;
+        hardware.encoder_id = None;
+        let mut software = config;
+        software.hardware_accelerated = false;
+        software.encoder_id = Some(software_encoder_id.to_string());
+
+        Ok(Self {
+            hardware: Side::new(
+                CompressionSessionBuilder::from_config(hardware),
+                true,
+                pixel_format,
+            )?,
+            software: Side::new(
+                CompressionSessionBuilder::from_config(software),
+                false,
+                pixel_format,
+            )?,
+        })
+    }
+
+    /// Submit a frame to both encoders.
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid pixel buffer matching the configuration.
+    pub unsafe fn encode_frame(
+        &self,
+        pixel_buffer: CVPixelBufferRef,
+        pts: CMTime,
+        duration: CMTime,
+    ) -> Result<(), OSStatus> {
+        let source = Arc::new(FrameSnapshot::from_pixel_buffer(pixel_buffer)?);
+        for side in [&self.hardware, &self.software] {
+            lock(&side.state)
+                .pending
+                .insert(pts.value, (Instant::now(), source.clone()));
+            side.session.encode_frame(pixel_buffer, pts, duration)?;
+        }
+        Ok(())
+    }
+
+    /// Per-GOP comparison of the frames output so far.
+    ///
+    /// Returns the first error either encoder or decoder reported.
+    pub fn report(&self) -> Result<Vec<GopComparison>, OSStatus> {
+        let hardware = lock(&self.hardware.state);
+        let software = lock(&self.software.state);
+        if let Some(status) = hardware.error.or(software.error) {
+            return Err(status);
+        }
+        Ok(hardware
+            .gops
+            .iter()
+            .zip(&software.gops)
+            .enumerate()
+            .map(|(index, (hw, sw))| GopComparison {
+                index,
+                hardware: hw.stats(),
+                software: sw.stats(),
+            })
+            .collect())
+    }
+
+    /// Complete all pending frames and return the final comparison.
+    pub fn finish(&self) -> Result<Vec<GopComparison>, OSStatus> {
+        self.hardware.session.complete_frames()?;
+        self.software.session.complete_frames()?;
+        self.report()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::Plane;
+
+    fn side(gops: &[&[(u64, u64)]]) -> SideState {
+        let mut state = SideState::default();
+        let mut pts = 0;
+        for gop in gops {
+            for (i, &(bytes, latency_ms)) in gop.iter().enumerate() {
+                let submitted = Instant::now() - Duration::from_millis(latency_ms);
+                state
+                    .pending
+                    .insert(pts, (submitted, Arc::new(snapshot(0))));
+                state.on_encoded(pts, bytes, i == 0);
+                pts += 1;
+            }
+        }
+        state
+    }
+
+    fn snapshot(value: u8) -> FrameSnapshot {
+        FrameSnapshot {
+            pixel_format: codecs::pixel::BGRA32,
+            width: 1,
+            height: 1,
+            planes: vec![Plane::from_padded(&[value; 4], 4, 1, 4).unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_gop_accumulation() {
+        let mut state = side(&[&[(1000, 20), (100, 10)], &[(900, 30)]]);
+        assert_eq!(state.gops.len(), 2);
+        let stats = state.gops[0].stats();
+        assert_eq!((stats.frames, stats.bytes), (2, 1100));
+        assert!(stats.max_latency >= Duration::from_millis(20));
+        assert!(stats.mean_latency >= Duration::from_millis(15));
+        assert_eq!(stats.psnr, None);
+
+        // Decoded frame differs from the source by 10 in every sample
+        state.on_decoded(0, &snapshot(10));
+        let psnr = state.gops[0].stats().psnr.unwrap();
+        assert!((psnr - 10.0 * (255.0f64 * 255.0 / 100.0).log10()).abs() < 1e-9);
+        assert!(!state.pending.contains_key(&0));
+    }
+
+    #[test]
+    fn test_gop_comparison_deltas() {
+        let comparison = GopComparison {
+            index: 0,
+            hardware: GopStats {
+                frames: 30,
+                bytes: 100_000,
+                mean_latency: Duration::from_millis(5),
+                max_latency: Duration::from_millis(9),
+                psnr: Some(38.0),
+            },
+            software: GopStats {
+                frames: 30,
+                bytes: 80_000,
+                mean_latency: Duration::from_millis(25),
+                max_latency: Duration::from_millis(40),
+                psnr: Some(39.5),
+            },
+        };
+        assert!((comparison.size_ratio() - 0.8).abs() < 1e-9);
+        assert!((comparison.latency_delta_ms() - 20.0).abs() < 1e-6);
+        assert_eq!(comparison.psnr_delta(), Some(1.5));
+        let line = comparison.to_string();
+        assert!(
+            line.contains("(-20.0%)") && line.contains("(+1.50 dB)"),
+            "{}",
+            line
+        );
+    }
+}
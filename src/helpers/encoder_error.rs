@@ -0,0 +1,240 @@
+//! Typed encoder failures with suggested remediation.
+//!
+//! VideoToolbox reports encoder problems as bare `OSStatus` codes, and the
+//! same code can mean different things depending on the configuration: a
+//! `kVTParameterErr` from a session with an odd width has a different fix
+//! than one from an unsupported bitrate. [`EncoderError::classify`] maps the
+//! common real-world failures to a typed error, and
+//! [`EncoderError::remediation`] says what to do about it.
+
+use core_foundation_sys::base::OSStatus;
+use std::time::Duration;
+
+use super::compression_builder::CompressionSessionConfig;
+use super::encoder_comparison::software_encoder_id;
+use crate::codecs;
+use crate::errors::{
+    kVTCouldNotFindVideoEncoderErr, kVTInvalidSessionErr, kVTParameterErr,
+    kVTVideoEncoderMalfunctionErr, kVTVideoEncoderNotAvailableNowErr, vt_error_to_string,
+};
+
+/// How long to wait before retrying an encoder that is busy.
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// A classified encoder failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncoderError {
+    /// No encoder supports the codec with this configuration
+    /// (`kVTCouldNotFindVideoEncoderErr`), e.g. HEVC on a Mac without a
+    /// hardware HEVC encoder and software fallback disabled
+    UnsupportedCodec { codec: u32, hardware_only: bool },
+    /// The session was invalidated (`kVTInvalidSessionErr`), typically after
+    /// the system slept or the GPU changed
+    SessionInvalidated,
+    /// The encoder rejected odd or misaligned frame dimensions
+    /// (`kVTParameterErr`)
+    InvalidDimensions { width: i32, height: i32 },
+    /// The encoder rejected another parameter (`kVTParameterErr`)
+    InvalidParameter,
+    /// The hardware encoder is temporarily in use by other sessions
+    /// (`kVTVideoEncoderNotAvailableNowErr`)
+    EncoderBusy,
+    /// The encoder failed internally (`kVTVideoEncoderMalfunctionErr`)
+    EncoderMalfunction,
+    /// Any other status
+    Other(OSStatus),
+}
+
+/// What to do about an [`EncoderError`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Remediation {
+    /// Create a new session with the same configuration and continue from a
+    /// keyframe
+    RecreateSession,
+    /// Try again after a delay
+    RetryAfter(Duration),
+    /// Use the software encoder with this ID, or allow software fallback
+    UseSoftwareEncoder(&'static str),
+    /// Use these dimensions instead, e.g. with
+    /// [`round_dimensions`](super::CompressionSessionBuilder::round_dimensions)
+    RoundDimensions { width: i32, height: i32 },
+    /// Change the configuration; retrying as is will fail again
+    FixConfiguration,
+}
+
+impl EncoderError {
+    /// Classify a status returned while creating or using a session with
+    /// `config`.
+    pub fn classify(status: OSStatus, config: &CompressionSessionConfig) -> Self {
+        match status {
+            kVTCouldNotFindVideoEncoderErr => EncoderError::UnsupportedCodec {
+                codec: config.codec,
+                hardware_only: config.hardware_accelerated && config.encoder_id.is_none(),
+            },
+            kVTInvalidSessionErr => EncoderError::SessionInvalidated,
+            kVTParameterErr => {
+                let alignment = encoder_alignment(config.codec);
+                let (width, height) = aligned_dimensions(config.width, config.height, alignment);
+                if (width, height) != (config.width, config.height) {
+                    EncoderError::InvalidDimensions {
+                        width: config.width,
+                        height: config.height,
+                    }
+                } else {
+                    EncoderError::InvalidParameter
+                }
+            }
+            kVTVideoEncoderNotAvailableNowErr => EncoderError::EncoderBusy,
+            kVTVideoEncoderMalfunctionErr => EncoderError::EncoderMalfunction,
+            status => EncoderError::Other(status),
+        }
+    }
+
+    /// The VideoToolbox status.
+    pub fn status(&self) -> OSStatus {
+        match self {
+            EncoderError::UnsupportedCodec { .. } => kVTCouldNotFindVideoEncoderErr,
+            EncoderError::SessionInvalidated => kVTInvalidSessionErr,
+            EncoderError::InvalidDimensions { .. } | EncoderError::InvalidParameter => {
+                kVTParameterErr
+            }
+            EncoderError::EncoderBusy => kVTVideoEncoderNotAvailableNowErr,
+            EncoderError::EncoderMalfunction => kVTVideoEncoderMalfunctionErr,
+            EncoderError::Other(status) => *status,
+        }
+    }
+
+    /// Whether retrying, possibly with a new session, can succeed without
+    /// changing the configuration.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.remediation(),
+            Remediation::RecreateSession | Remediation::RetryAfter(_)
+        )
+    }
+
+    /// The suggested fix.
+    pub fn remediation(&self) -> Remediation {
+        match self {
+            EncoderError::UnsupportedCodec { codec, .. } => match software_encoder_id(*codec) {
+                Some(id) => Remediation::UseSoftwareEncoder(id),
+                None => Remediation::FixConfiguration,
+            },
+            EncoderError::SessionInvalidated | EncoderError::EncoderMalfunction => {
+                Remediation::RecreateSession
+            }
+            EncoderError::InvalidDimensions { width, height } => {
+                let (width, height) = aligned_dimensions(*width, *height, 2);
+                Remediation::RoundDimensions { width, height }
+            }
+            EncoderError::EncoderBusy => Remediation::RetryAfter(BUSY_RETRY_DELAY),
+            EncoderError::InvalidParameter | EncoderError::Other(_) => {
+                Remediation::FixConfiguration
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for EncoderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncoderError::UnsupportedCodec {
+                codec,
+                hardware_only,
+            } => {
+                let fourcc = String::from_utf8_lossy(&codec.to_be_bytes()).into_owned();
+                write!(f, "No encoder for codec '{}'", fourcc)?;
+                if *hardware_only {
+                    write!(f, " with hardware acceleration")?;
+                }
+                match self.remediation() {
+                    Remediation::UseSoftwareEncoder(id) => {
+                        write!(f, "; use the software encoder {}", id)
+                    }
+                    _ => write!(f, "; choose another codec"),
+                }
+            }
+            EncoderError::SessionInvalidated => write!(
+                f,
+                "Encoder session invalidated (e.g. after sleep); recreate the session"
+            ),
+            EncoderError::InvalidDimensions { width, height } => {
+                let (w, h) = aligned_dimensions(*width, *height, 2);
+                write!(
+                    f,
+                    "Encoder rejected {}x{}; use even dimensions such as {}x{}",
+                    width, height, w, h
+                )
+            }
+            EncoderError::InvalidParameter => write!(
+                f,
+                "Encoder rejected a parameter; check bitrate, frame rate and properties"
+            ),
+            EncoderError::EncoderBusy => write!(
+                f,
+                "Hardware encoder busy; retry after {} ms",
+                BUSY_RETRY_DELAY.as_millis()
+            ),
+            EncoderError::EncoderMalfunction => {
+                write!(f, "Encoder malfunction; recreate the session")
+            }
+            EncoderError::Other(status) => {
+                write!(f, "{} ({})", vt_error_to_string(*status), status)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EncoderError {}
+
+/// Dimension alignment the codec's encoders require: 2 for the 4:2:0
+/// chroma subsampling of H.264 and HEVC, none otherwise.
+pub fn encoder_alignment(codec: u32) -> u32 {
+    match codec {
+        codecs::video::H264 | codecs::video::HEVC => 2,
+        _ => 1,
+    }
+}
+
+/// `width` and `height` rounded down to multiples of `alignment`, but not
+/// below it.
+pub fn aligned_dimensions(width: i32, height: i32, alignment: u32) -> (i32, i32) {
+    let alignment = alignment.max(1) as i32;
+    let align = |value: i32| (value - value % alignment).max(alignment);
+    (align(width), align(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_parameter_error_by_dimensions() {
+        let odd = CompressionSessionConfig::new(1281, 721, codecs::video::H264);
+        let error = EncoderError::classify(kVTParameterErr, &odd);
+        assert_eq!(
+            error,
+            EncoderError::InvalidDimensions {
+                width: 1281,
+                height: 721
+            }
+        );
+        assert_eq!(
+            error.remediation(),
+            Remediation::RoundDimensions {
+                width: 1280,
+                height: 720
+            }
+        );
+        assert!(!error.is_retryable());
+
+        let even = CompressionSessionConfig::new(1280, 720, codecs::video::H264);
+        assert_eq!(
+            EncoderError::classify(kVTParameterErr, &even),
+            EncoderError::InvalidParameter
+        );
+        let invalidated = EncoderError::classify(kVTInvalidSessionErr, &even);
+        assert!(invalidated.is_retryable());
+        assert_eq!(invalidated.status(), kVTInvalidSessionErr);
+    }
+}
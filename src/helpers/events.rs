@@ -0,0 +1,267 @@
+//! Pipeline events and callback safety shared by the session wrappers.
+//!
+//! VideoToolbox invokes output callbacks through `extern "C"` shims. A panic
+//! must not unwind across that boundary, so every shim runs the user callback
+//! under [`catch_callback_panic`] and reports the panic as
+//! [`PipelineEvent::CallbackPanicked`] instead.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, RwLock};
+
+use libc::c_void;
+
+/// Events reported by helper sessions outside the normal output path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PipelineEvent {
+    /// A user callback panicked. The panic was caught at the FFI boundary and
+    /// the output that triggered it was discarded.
+    CallbackPanicked {
+        /// Which callback panicked (e.g. `"compression output"`)
+        callback: &'static str,
+        /// The panic payload, if it was a string
+        message: String,
+    },
+    /// Video has been black for at least the configured number of frames.
+    BlackVideoStarted {
+        /// Index of the first black frame
+        frame_index: u64,
+    },
+    /// Black video ended.
+    BlackVideoEnded {
+        /// Index of the first non-black frame
+        frame_index: u64,
+        /// Number of black frames
+        frames: u64,
+    },
+    /// Video content has been identical for at least the configured number of frames.
+    FrozenVideoStarted {
+        /// Index of the first repeated frame
+        frame_index: u64,
+    },
+    /// Frozen video ended.
+    FrozenVideoEnded {
+        /// Index of the first changed frame
+        frame_index: u64,
+        /// Number of repeated frames
+        frames: u64,
+    },
+    /// Audio has been below the silence threshold for at least the configured
+    /// duration. Frame indices count audio frames (one sample per channel).
+    SilenceStarted {
+        /// Index of the first silent audio frame
+        frame_index: u64,
+    },
+    /// Audio silence ended.
+    SilenceEnded {
+        /// Index of the first audio frame of the non-silent buffer
+        frame_index: u64,
+        /// Number of silent audio frames
+        frames: u64,
+    },
+    /// Samples in an audio buffer reached the clipping threshold.
+    ClippingDetected {
+        /// Index of the first audio frame of the buffer
+        frame_index: u64,
+        /// Channel that clipped
+        channel: u32,
+        /// Number of clipped samples in the buffer
+        samples: u64,
+    },
+    /// A triggered recording started writing to `path`.
+    RecordingStarted {
+        path: std::path::PathBuf,
+    },
+    /// A triggered recording was completed.
+    RecordingFinished {
+        path: std::path::PathBuf,
+        /// Video frames written, including pre-roll
+        frames: u64,
+    },
+    /// A segment that left the live window could not be retired (the
+    /// before-delete hook or the deletion failed); it is retried later.
+    SegmentRetentionFailed {
+        path: std::path::PathBuf,
+        error: String,
+    },
+    /// A frame submitted for encoding was dropped, by the encoder or by the
+    /// session's frame deadline. Times are in `timescale` units, that of the
+    /// submitted pts.
+    FrameDropped {
+        pts: i64,
+        duration: i64,
+        timescale: i32,
+    },
+    /// The encoded stream exceeds a limit of the level signaled in its SPS
+    /// (see [`ConformanceChecker`](super::ConformanceChecker)).
+    LevelExceeded {
+        error: super::ProfileLevelError,
+    },
+    /// The parameter sets of an encoded stream were found, either in the
+    /// format description or in-band in the first keyframe.
+    ParameterSetsFound {
+        source: super::ParameterSetSource,
+    },
+    /// A capture session chose how frames reach the encoder (see
+    /// [`CaptureSessionBuilder::negotiate_input`](super::CaptureSessionBuilder::negotiate_input)).
+    InputFormatNegotiated {
+        path: super::InputPath,
+    },
+    /// A receive-side [`ReorderBuffer`](super::ReorderBuffer) gave up waiting
+    /// for fragments with sequence numbers `from..to`.
+    SegmentsSkipped {
+        from: u32,
+        to: u32,
+    },
+    /// A receiver's [`StartupGate`](super::StartupGate) changed state, e.g.
+    /// from waiting for a keyframe to synced.
+    StartupStateChanged {
+        state: super::StartupState,
+    },
+}
+
+type EventHandler = Arc<dyn Fn(&PipelineEvent) + Send + Sync>;
+
+static EVENT_HANDLER: RwLock<Option<EventHandler>> = RwLock::new(None);
+
+/// Install a process-wide handler for [`PipelineEvent`]s.
+///
+/// Replaces any previous handler. Events are discarded while no handler is set.
+/// The handler may run on VideoToolbox's callback threads.
+pub fn set_event_handler<F>(handler: F)
+where
+    F: Fn(&PipelineEvent) + Send + Sync + 'static,
+{
+    *EVENT_HANDLER.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(handler));
+}
+
+/// Remove the process-wide event handler.
+pub fn clear_event_handler() {
+    *EVENT_HANDLER.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Deliver an event to the installed handler.
+pub(crate) fn emit(event: PipelineEvent) {
+    let handler = EVENT_HANDLER
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    if let Some(handler) = handler {
+        // A panicking handler must not unwind into the callback shim either.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(&event)));
+    }
+}
+
+/// Run `f`, converting a panic into [`PipelineEvent::CallbackPanicked`].
+///
+/// Returns `None` if `f` panicked.
+pub(crate) fn catch_callback_panic<R>(callback: &'static str, f: impl FnOnce() -> R) -> Option<R> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => Some(result),
+        Err(payload) => {
+            emit(PipelineEvent::CallbackPanicked {
+                callback,
+                message: panic_message(payload.as_ref()),
+            });
+            None
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("<non-string panic payload>")
+    }
+}
+
+thread_local! {
+    /// Sessions whose output callback is running on this thread.
+    static ACTIVE_CALLBACKS: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Marks a session's output callback as running on the current thread.
+pub(crate) struct CallbackScope {
+    session: usize,
+}
+
+impl CallbackScope {
+    pub(crate) fn enter(session: *const c_void) -> Self {
+        let session = session as usize;
+        ACTIVE_CALLBACKS.with(|active| active.borrow_mut().push(session));
+        Self { session }
+    }
+}
+
+impl Drop for CallbackScope {
+    fn drop(&mut self) {
+        ACTIVE_CALLBACKS.with(|active| {
+            let mut active = active.borrow_mut();
+            if let Some(pos) = active.iter().rposition(|&s| s == self.session) {
+                active.remove(pos);
+            }
+        });
+    }
+}
+
+/// Returns true if called from within `session`'s own output callback.
+///
+/// Session wrappers refuse blocking calls (encode, decode, flush) in this
+/// state, since VideoToolbox would deadlock waiting on the running callback.
+pub(crate) fn in_callback_of(session: *const c_void) -> bool {
+    let session = session as usize;
+    ACTIVE_CALLBACKS.with(|active| active.borrow().contains(&session))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_catch_callback_panic_emits_event() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        set_event_handler(move |event| sink.lock().unwrap().push(event.clone()));
+
+        assert_eq!(catch_callback_panic("test", || 7), Some(7));
+        let result: Option<()> = catch_callback_panic("test", || panic!("boom"));
+        assert!(result.is_none());
+        clear_event_handler();
+
+        let events = events.lock().unwrap();
+        assert!(events.contains(&PipelineEvent::CallbackPanicked {
+            callback: "test",
+            message: String::from("boom"),
+        }));
+    }
+
+    #[test]
+    fn test_callback_scope_tracks_reentrancy() {
+        let a = 0x1000 as *const c_void;
+        let b = 0x2000 as *const c_void;
+        assert!(!in_callback_of(a));
+        {
+            let _outer = CallbackScope::enter(a);
+            assert!(in_callback_of(a));
+            assert!(!in_callback_of(b));
+            {
+                let _inner = CallbackScope::enter(b);
+                assert!(in_callback_of(a) && in_callback_of(b));
+            }
+            assert!(!in_callback_of(b));
+        }
+        assert!(!in_callback_of(a));
+        let addr = a as usize;
+        assert!(
+            !std::thread::spawn(move || in_callback_of(addr as *const c_void))
+                .join()
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,531 @@
+//! Crash-safe local recording to fragmented MP4.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::chapters::{write_chapters, Chapter};
+use super::cmaf_muxer::{CmafConfig, CmafMuxer};
+use super::metadata::Mp4Metadata;
+use super::mfra::{find_box, RandomAccessIndex, RandomAccessPoint};
+use super::source::MediaFrame;
+use super::timecode::{Timecode, TimecodeFormat, TimecodeMuxer, TimecodeTrack};
+use super::timed_metadata::{TimedMetadataMuxer, TimedMetadataTrack};
+
+/// Add an empty 64-bit `mehd` to the init segment's `mvex`, returning the
+/// offset of its `fragment_duration` field.
+fn insert_mehd(init: &mut Vec<u8>) -> Option<usize> {
+    let moov = find_box(init, 0, init.len(), b"moov")?;
+    let mvex = find_box(init, moov.content(), moov.end(), b"mvex")?;
+
+    let mut mehd = Vec::with_capacity(20);
+    mehd.extend_from_slice(&20u32.to_be_bytes());
+    mehd.extend_from_slice(b"mehd");
+    mehd.extend_from_slice(&[1, 0, 0, 0]); // version 1 (64-bit duration)
+    mehd.extend_from_slice(&0u64.to_be_bytes());
+    init.splice(mvex.content()..mvex.content(), mehd);
+
+    for (offset, size) in [(moov.offset, moov.size), (mvex.offset, mvex.size)] {
+        init[offset..offset + 4].copy_from_slice(&((size + 20) as u32).to_be_bytes());
+    }
+    Some(mvex.content() + 12)
+}
+
+/// Records encoded video to a fragmented MP4 file that stays playable if the
+/// process dies mid-recording.
+///
+/// Unlike a regular MP4 (or an AVAssetWriter MOV), whose sample tables are
+/// only written when the file is closed, every fragment here is
+/// self-describing. The init segment is written as soon as the parameter
+/// sets are known, and each fragment (`moof` + `mdat`, cut at the first
+/// keyframe after [`CmafConfig::fragment_duration_ms`]) is flushed to the
+/// file as soon as it is complete, so a crash loses at most the fragment in
+/// progress. Use a short fragment duration and keyframe interval to bound
+/// that loss, and [`sync_fragments`](Self::sync_fragments) to survive power
+/// loss as well.
+///
+/// [`finish`](Self::finish) (or dropping the recorder) writes the final
+/// fragment, records the total duration in the `moov` (`mehd`) and appends
+/// an `mfra` random-access index for fast seeking, plus the chapters from
+/// [`add_chapter`](Self::add_chapter).
+///
+/// With [`timed_metadata`](Self::timed_metadata), telemetry such as GPS
+/// fixes or poses from [`add_timed_metadata`](Self::add_timed_metadata) is
+/// recorded as a second track on the frames' clock, each batch written
+/// after the video fragment it belongs to. See
+/// [`read_timed_metadata`](super::read_timed_metadata) to extract it.
+///
+/// With [`timecode`](Self::timecode), a QuickTime `tmcd` track gives
+/// editors the SMPTE timecode of every frame, counted from a start timecode
+/// at the first frame.
+///
+/// Frames before the first keyframe are skipped so the file starts decodable.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{CmafConfig, Fmp4Recorder};
+///
+/// let config = CmafConfig {
+///     fragment_duration_ms: 1000,
+///     ..Default::default()
+/// };
+/// let mut recorder = Fmp4Recorder::create("capture.mp4", config)?.sync_fragments(true);
+/// # let (sps, pps) = (Vec::new(), Vec::new());
+/// recorder.set_parameter_sets(&sps, &pps, 1920, 1080)?;
+/// // for frame in frames { recorder.push(&frame)?; }
+/// recorder.finish()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Fmp4Recorder {
+    file: File,
+    path: PathBuf,
+    muxer: CmafMuxer,
+    timescale: u32,
+    sync: bool,
+    /// Bytes written so far
+    position: u64,
+    /// File offset of the `mehd` duration field
+    mehd_offset: Option<u64>,
+    random_access: RandomAccessIndex,
+    first_dts: Option<i64>,
+    end_dts: i64,
+    frames: u64,
+    /// Chapter starts (frame clock) and titles
+    chapters: Vec<(i64, String)>,
+    telemetry: Option<TimedMetadataMuxer>,
+    timecode: Option<TimecodeMuxer>,
+    /// End of the timecode written so far (frame clock)
+    timecode_end: Option<i64>,
+    finished: bool,
+}
+
+impl Fmp4Recorder {
+    /// Create (or truncate) the output file.
+    pub fn create(path: impl AsRef<Path>, config: CmafConfig) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let timescale = config.timescale.max(1);
+        let muxer = CmafMuxer::try_new(config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(Self {
+            file: File::create(&path)?,
+            path,
+            muxer,
+            timescale,
+            sync: false,
+            position: 0,
+            mehd_offset: None,
+            random_access: RandomAccessIndex::new(1),
+            first_dts: None,
+            end_dts: 0,
+            frames: 0,
+            chapters: Vec::new(),
+            telemetry: None,
+            timecode: None,
+            timecode_end: None,
+            finished: false,
+        })
+    }
+
+    /// Also `fsync` the file after each fragment, so recordings survive power
+    /// loss and kernel panics, not just a crashed process (default: off).
+    pub fn sync_fragments(mut self, enabled: bool) -> Self {
+        self.sync = enabled;
+        self
+    }
+
+    /// Title, date, location and other metadata for the file (see
+    /// [`Mp4Metadata`]). Only takes effect before
+    /// [`set_parameter_sets`](Self::set_parameter_sets).
+    pub fn metadata(mut self, metadata: Mp4Metadata) -> Self {
+        self.muxer.set_metadata(metadata);
+        self
+    }
+
+    /// Record a timed metadata track of `mime_type` samples, e.g.
+    /// `application/json`. Only takes effect before
+    /// [`set_parameter_sets`](Self::set_parameter_sets).
+    pub fn timed_metadata(mut self, mime_type: impl Into<String>) -> Self {
+        let mut track = TimedMetadataTrack::new(mime_type);
+        track.timescale = self.timescale;
+        self.telemetry = Some(TimedMetadataMuxer::new(track));
+        self
+    }
+
+    /// Record a timecode track counting `format` frames from `start` at the
+    /// first frame, e.g. a timecode entered by the user or
+    /// [`Timecode::now`] for time of day. Only takes effect before
+    /// [`set_parameter_sets`](Self::set_parameter_sets).
+    pub fn timecode(mut self, format: TimecodeFormat, start: Timecode) -> Self {
+        let mut track = TimecodeTrack::new(format);
+        track.timescale = self.timescale;
+        self.timecode = Some(TimecodeMuxer::new(track, start));
+        self
+    }
+
+    /// Add a telemetry sample applying from `pts` for `duration`, on the same
+    /// clock and timescale as the pushed frames. Samples are written with
+    /// the video fragment covering them, so they may arrive slightly late or
+    /// out of order. Ignored without [`timed_metadata`](Self::timed_metadata).
+    pub fn add_timed_metadata(&mut self, pts: i64, duration: u32, data: impl Into<Vec<u8>>) {
+        if let Some(telemetry) = &mut self.telemetry {
+            telemetry.add_sample(pts, duration, data.into());
+        }
+    }
+
+    /// Write the init segment. Frames pushed before this are dropped; later
+    /// calls are ignored.
+    pub fn set_parameter_sets(
+        &mut self,
+        sps: &[u8],
+        pps: &[u8],
+        width: u32,
+        height: u32,
+    ) -> io::Result<()> {
+        if self.muxer.is_initialized() {
+            return Ok(());
+        }
+        let mut init = self.muxer.create_init_segment(sps, pps, width, height);
+        if let Some(telemetry) = &self.telemetry {
+            telemetry
+                .insert_track(&mut init)
+                .ok_or_else(|| io::Error::other("cannot add timed metadata track"))?;
+        }
+        if let Some(timecode) = &mut self.timecode {
+            timecode
+                .insert_track(&mut init)
+                .ok_or_else(|| io::Error::other("cannot add timecode track"))?;
+        }
+        self.mehd_offset = insert_mehd(&mut init).map(|offset| self.position + offset as u64);
+        self.write(&init)
+    }
+
+    /// Fragments written so far that start with a keyframe, as indexed in
+    /// the final `mfra`.
+    pub fn random_access_points(&self) -> &[RandomAccessPoint] {
+        self.random_access.points()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Frames written so far, including the fragment in progress.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Media duration written so far, including the fragment in progress.
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.duration_ticks() as f64 / self.timescale as f64)
+    }
+
+    fn duration_ticks(&self) -> u64 {
+        (self.end_dts - self.first_dts.unwrap_or(self.end_dts)).max(0) as u64
+    }
+
+    /// Add the next encoded frame (in decode order).
+    pub fn push(&mut self, frame: &MediaFrame) -> io::Result<()> {
+        if self.finished {
+            return Err(io::Error::other("recording already finished"));
+        }
+        if !self.muxer.is_initialized() || (self.first_dts.is_none() && !frame.is_keyframe) {
+            return Ok(());
+        }
+
+        let dts = frame.timing.dts;
+        let segment = self
+            .muxer
+            .try_add_frame(
+                &frame.nal_units,
+                frame.timing.pts,
+                dts,
+                frame.timing.duration as u32,
+                frame.is_keyframe,
+            )
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(data) = segment {
+            self.write_fragment(&data, Some(dts))?;
+        }
+
+        self.first_dts.get_or_insert(dts);
+        self.end_dts = self.end_dts.max(dts + frame.timing.duration);
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Mark a chapter starting at `pts`, on the same clock and timescale as
+    /// the pushed frames' timestamps.
+    ///
+    /// Chapters are written as a `chpl` box and a QuickTime chapter track
+    /// (see [`write_chapters`]) by [`finish`](Self::finish), relative to the
+    /// first recorded frame; ones
+    /// before it start at zero. As the `moov` is at the start of the file,
+    /// that rewrites the file once, so it is not crash-safe like the
+    /// fragments: a crashed recording keeps its media but has no chapters.
+    pub fn add_chapter(&mut self, pts: i64, title: impl Into<String>) {
+        self.chapters.push((pts, title.into()));
+    }
+
+    /// Write the last fragment, the total duration, the `mfra` index and
+    /// any chapters.
+    ///
+    /// Called automatically on drop; calling it again does nothing.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if let Some(data) = self.muxer.flush() {
+            self.write_fragment(&data, None)?;
+        }
+        if !self.muxer.is_initialized() {
+            return self.file.sync_all();
+        }
+        self.write_telemetry(None)?;
+
+        let mfra = self.random_access.to_mfra();
+        self.write(&mfra)?;
+
+        if let Some(offset) = self.mehd_offset {
+            let duration = self.duration_ticks();
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&duration.to_be_bytes())?;
+            self.file.seek(SeekFrom::Start(self.position))?;
+        }
+        self.file.sync_all()?;
+        self.write_chapters()
+    }
+
+    /// Add the chapters to the finished file, then reopen it, as it was
+    /// replaced by a rewritten copy, and reload the random-access points
+    /// from its shifted `mfra`.
+    fn write_chapters(&mut self) -> io::Result<()> {
+        if self.chapters.is_empty() {
+            return Ok(());
+        }
+        let start = self.first_dts.unwrap_or(0);
+        let chapters: Vec<Chapter> = self
+            .chapters
+            .iter()
+            .map(|(pts, title)| {
+                let ticks = (pts - start).max(0) as f64;
+                Chapter::new(
+                    Duration::from_secs_f64(ticks / self.timescale as f64),
+                    title.clone(),
+                )
+            })
+            .collect();
+        write_chapters(&self.path, &chapters)?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.position = self.file.metadata()?.len();
+
+        let mfra_size = self.random_access.to_mfra().len();
+        let mut mfra = vec![0u8; mfra_size];
+        self.file.seek(SeekFrom::End(-(mfra_size as i64)))?;
+        self.file.read_exact(&mut mfra)?;
+        self.random_access = RandomAccessIndex::from_file_end(&mfra)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed mfra box"))?;
+        Ok(())
+    }
+
+    /// Write a fragment just emitted by the muxer and index it, followed by
+    /// its timecode and the telemetry starting before `end`.
+    fn write_fragment(&mut self, data: &[u8], end: Option<i64>) -> io::Result<()> {
+        self.random_access.add_fragment(self.position, data);
+        self.write(data)?;
+        self.write_timecode(end.unwrap_or(self.end_dts))?;
+        self.write_telemetry(end)?;
+        if self.sync {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Write the timecode from the end of the previous fragment to `end`.
+    fn write_timecode(&mut self, end: i64) -> io::Result<()> {
+        let Some(timecode) = &mut self.timecode else {
+            return Ok(());
+        };
+        let Some(start) = self.timecode_end.or(self.first_dts) else {
+            return Ok(());
+        };
+        self.timecode_end = Some(end.max(start));
+        match timecode.fragment(start, end) {
+            Some(data) => self.write(&data),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the telemetry samples starting before `end`, or all of them.
+    fn write_telemetry(&mut self, end: Option<i64>) -> io::Result<()> {
+        let Some(telemetry) = &mut self.telemetry else {
+            return Ok(());
+        };
+        match telemetry.fragment_until(end.unwrap_or(i64::MAX)) {
+            Some(data) => self.write(&data),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+}
+
+impl Drop for Fmp4Recorder {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{NalUnit, SampleTiming};
+
+    /// 10 fps at timescale 1000, keyframe every 5 frames.
+    fn frame(index: i64) -> MediaFrame {
+        let keyframe = index % 5 == 0;
+        let nal_type = if keyframe { 5 } else { 1 };
+        MediaFrame {
+            nal_units: vec![NalUnit {
+                nal_type,
+                data: vec![0x60 | nal_type, 0x88, index as u8],
+            }],
+            timing: SampleTiming {
+                pts: index * 100,
+                dts: index * 100,
+                duration: 100,
+                timescale: 1000,
+            },
+            is_keyframe: keyframe,
+            motion_score: None,
+        }
+    }
+
+    fn top_level_boxes(data: &[u8]) -> Vec<&[u8; 4]> {
+        let mut boxes = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            boxes.push(data[offset + 4..offset + 8].try_into().unwrap());
+            offset += size;
+        }
+        boxes
+    }
+
+    #[test]
+    fn test_fragments_flushed_and_finalized() {
+        let path = std::env::temp_dir().join(format!("vt-fmp4-{}.mp4", std::process::id()));
+        let config = CmafConfig {
+            fragment_duration_ms: 500,
+            timescale: 1000,
+            ..Default::default()
+        };
+        let mut recorder = Fmp4Recorder::create(&path, config).unwrap();
+        // Frames before the parameter sets and the first keyframe are dropped
+        recorder.push(&frame(0)).unwrap();
+        recorder
+            .set_parameter_sets(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 64, 64)
+            .unwrap();
+        for i in 4..16 {
+            recorder.push(&frame(i)).unwrap();
+        }
+        recorder.add_chapter(900, "Second half");
+
+        // Fragments 5..10 and 10..15 are already on disk, as after a crash
+        let partial = std::fs::read(&path).unwrap();
+        let fragment: [&[u8; 4]; 3] = [b"styp", b"moof", b"mdat"];
+        let mut expected = vec![b"ftyp", b"moov"];
+        expected.extend(fragment.repeat(2));
+        assert_eq!(top_level_boxes(&partial), expected);
+
+        recorder.finish().unwrap();
+        assert_eq!(recorder.frames(), 11);
+        assert_eq!(recorder.duration(), Duration::from_millis(1100));
+        let file = std::fs::read(&path).unwrap();
+        // The chapter track's sample sits right before the moov
+        expected.insert(1, b"mdat");
+        expected.extend(fragment);
+        expected.push(b"mfra");
+        assert_eq!(top_level_boxes(&file), expected);
+
+        let moov = find_box(&file, 0, file.len(), b"moov").unwrap();
+        let mvex = find_box(&file, moov.content(), moov.end(), b"mvex").unwrap();
+        let mehd = find_box(&file, mvex.content(), mvex.end(), b"mehd").unwrap();
+        assert_eq!(
+            &file[mehd.content() + 4..mehd.end()],
+            &1100u64.to_be_bytes()
+        );
+
+        // The mfra read back through the mfro matches the collected points
+        let index = RandomAccessIndex::from_file_end(&file).unwrap();
+        assert_eq!(index.points(), recorder.random_access_points());
+        let times: Vec<u64> = index.points().iter().map(|point| point.time).collect();
+        assert_eq!(times, [500, 1000, 1500]);
+        for point in index.points() {
+            let moof = point.moof_offset as usize;
+            assert_eq!(&file[moof + 4..moof + 8], b"moof");
+        }
+
+        // The chapter is relative to the first keyframe (dts 500), in 100 ns
+        let udta = find_box(&file, moov.content(), moov.end(), b"udta").unwrap();
+        let chpl = find_box(&file, udta.content(), udta.end(), b"chpl").unwrap();
+        let start = &file[chpl.content() + 9..chpl.content() + 17];
+        assert_eq!(start, &4_000_000u64.to_be_bytes());
+
+        // QuickTime finds the chapter text track through the video track
+        let video = find_box(&file, moov.content(), moov.end(), b"trak").unwrap();
+        let tref = find_box(&file, video.content(), video.end(), b"tref").unwrap();
+        let chap = find_box(&file, tref.content(), tref.end(), b"chap").unwrap();
+        assert_eq!(&file[chap.content()..chap.end()], &2u32.to_be_bytes());
+        let mdat = find_box(&file, 0, file.len(), b"mdat").unwrap();
+        assert_eq!(
+            &file[mdat.content() + 2..mdat.content() + 13],
+            b"Second half"
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_timed_metadata_written_with_fragments() {
+        let path = std::env::temp_dir().join(format!("vt-fmp4-meta-{}.mp4", std::process::id()));
+        let config = CmafConfig {
+            fragment_duration_ms: 500,
+            timescale: 1000,
+            ..Default::default()
+        };
+        let mut recorder = Fmp4Recorder::create(&path, config)
+            .unwrap()
+            .timed_metadata("application/json");
+        recorder
+            .set_parameter_sets(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 64, 64)
+            .unwrap();
+        for i in 0..12 {
+            recorder.add_timed_metadata(i * 100 + 50, 100, format!("{{\"frame\":{i}}}"));
+            recorder.push(&frame(i)).unwrap();
+        }
+        recorder.finish().unwrap();
+        let file = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        // Each video fragment is followed by the telemetry it covers
+        let boxes = top_level_boxes(&file);
+        assert_eq!(&boxes[2..6], [b"styp", b"moof", b"mdat", b"moof"]);
+        let telemetry = crate::helpers::read_timed_metadata(&file).unwrap().unwrap();
+        assert_eq!(telemetry.track.timescale, 1000);
+        assert_eq!(telemetry.samples.len(), 12);
+        assert_eq!(telemetry.samples[11].pts, 1150);
+        assert_eq!(telemetry.samples[11].data, b"{\"frame\":11}");
+        // The video track and random-access index are unaffected
+        let index = RandomAccessIndex::from_file_end(&file).unwrap();
+        assert_eq!(index.points(), recorder.random_access_points());
+    }
+}
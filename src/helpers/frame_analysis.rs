@@ -0,0 +1,255 @@
+//! Background analysis (e.g. Vision or CoreML requests) of decoded or
+//! captured frames.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::events::catch_callback_panic;
+use super::frame_broadcast::{FrameBroadcaster, FrameSubscriber};
+use super::playback_decoder::PlaybackFrame;
+use super::tee_sink::Backpressure;
+
+/// How often the worker checks whether the stage has been dropped.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A frame with the result of analyzing it.
+#[derive(Debug)]
+pub struct AnalyzedFrame<R, T = PlaybackFrame> {
+    pub frame: Arc<T>,
+    pub result: R,
+    /// Time spent in the analyzer
+    pub elapsed: Duration,
+}
+
+/// Counters for an [`AnalysisStage`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AnalysisStats {
+    /// Frames that produced a result
+    pub analyzed: u64,
+    /// Frames for which the analyzer returned `None`
+    pub skipped: u64,
+    /// Analyzer calls that panicked
+    pub panicked: u64,
+    /// Frames dropped from the input queue while the analyzer was busy
+    pub dropped: u64,
+}
+
+struct Shared<R, T> {
+    /// Taken by the worker when it exits, ending result subscriptions
+    output: Mutex<Option<FrameBroadcaster<AnalyzedFrame<R, T>>>>,
+    latest: Mutex<Option<Arc<AnalyzedFrame<R, T>>>>,
+    stats: Mutex<AnalysisStats>,
+    stop: AtomicBool,
+}
+
+/// Runs a caller-provided analyzer, such as a Vision face or object detection
+/// request, on frames from a [`FrameBroadcaster`] on a background thread.
+///
+/// The stage reads from its own subscription, so a slow model never holds
+/// up display or recording. Subscribe with [`Backpressure::DropOldest`] and a
+/// capacity of 1 to always analyze the most recent frame. Results are
+/// published as [`AnalyzedFrame`]s, pairing each frame with its result, and
+/// the most recent one is available from [`latest`](Self::latest).
+///
+/// The analyzer gets the whole frame, so it can hand
+/// `frame.pixel_buffer.as_raw()` to `VNImageRequestHandler` or a CoreML
+/// model. Captured frames can be analyzed too by publishing them into the
+/// broadcaster from the capture callback. Analyzer panics are caught and
+/// reported as [`PipelineEvent::CallbackPanicked`](super::PipelineEvent::CallbackPanicked);
+/// the stage carries on with the next frame.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{AnalysisStage, Backpressure, FrameBroadcaster};
+///
+/// let broadcaster = FrameBroadcaster::new();
+/// let input = broadcaster.subscribe(1, Backpressure::DropOldest);
+/// let faces = AnalysisStage::spawn(input, |frame| {
+///     let _pixel_buffer = frame.pixel_buffer.as_raw();
+///     // perform a VNDetectFaceRectanglesRequest and return its observations
+///     Some(0usize)
+/// });
+///
+/// let results = faces.subscribe(8, Backpressure::DropOldest);
+/// while let Some(analyzed) = results.recv() {
+///     println!("{} faces at {:?}", analyzed.result, analyzed.frame.pts);
+/// }
+/// ```
+pub struct AnalysisStage<R, T = PlaybackFrame> {
+    shared: Arc<Shared<R, T>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<R, T> AnalysisStage<R, T>
+where
+    R: Send + Sync + 'static,
+    T: Send + Sync + 'static,
+{
+    /// Start analyzing frames from `input` with `analyze`, which returns
+    /// `None` for frames without a result.
+    pub fn spawn<F>(input: FrameSubscriber<T>, mut analyze: F) -> Self
+    where
+        F: FnMut(&T) -> Option<R> + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            output: Mutex::new(Some(FrameBroadcaster::new())),
+            latest: Mutex::new(None),
+            stats: Mutex::new(AnalysisStats::default()),
+            stop: AtomicBool::new(false),
+        });
+
+        let worker_shared = shared.clone();
+        let worker = thread::Builder::new()
+            .name("frame-analysis".to_string())
+            .spawn(move || {
+                let shared = worker_shared;
+                while !shared.stop.load(Ordering::Relaxed) {
+                    let frame = match input.recv_timeout(POLL_INTERVAL) {
+                        Some(frame) => frame,
+                        None if input.is_closed() => break,
+                        None => continue,
+                    };
+                    let start = Instant::now();
+                    let result = catch_callback_panic("frame analysis", || analyze(&frame));
+                    let elapsed = start.elapsed();
+
+                    let mut stats = lock(&shared.stats);
+                    stats.dropped = input.stats().dropped;
+                    match result {
+                        Some(Some(result)) => {
+                            stats.analyzed += 1;
+                            drop(stats);
+                            let analyzed = Arc::new(AnalyzedFrame {
+                                frame,
+                                result,
+                                elapsed,
+                            });
+                            *lock(&shared.latest) = Some(analyzed.clone());
+                            // Publish without the lock, which drop needs
+                            let output = lock(&shared.output).clone();
+                            if let Some(output) = output {
+                                output.publish_shared(analyzed);
+                            }
+                        }
+                        Some(None) => stats.skipped += 1,
+                        None => stats.panicked += 1,
+                    }
+                }
+                lock(&shared.output).take();
+            })
+            .expect("failed to spawn frame analysis worker");
+
+        Self {
+            shared,
+            worker: Some(worker),
+        }
+    }
+
+    /// Receive analyzed frames, queueing up to `capacity` of them.
+    ///
+    /// The subscription ends once the input has ended.
+    pub fn subscribe(
+        &self,
+        capacity: usize,
+        policy: Backpressure,
+    ) -> FrameSubscriber<AnalyzedFrame<R, T>> {
+        // A finished stage hands out an already closed subscription
+        let output = lock(&self.shared.output).clone().unwrap_or_default();
+        output.subscribe(capacity, policy)
+    }
+
+    /// The most recent result, e.g. to overlay on the frame being displayed.
+    pub fn latest(&self) -> Option<Arc<AnalyzedFrame<R, T>>> {
+        lock(&self.shared.latest).clone()
+    }
+
+    pub fn stats(&self) -> AnalysisStats {
+        lock(&self.shared.stats).clone()
+    }
+
+    /// True once the input has ended and every frame has been analyzed.
+    pub fn is_finished(&self) -> bool {
+        self.worker
+            .as_ref()
+            .is_none_or(|worker| worker.is_finished())
+    }
+}
+
+impl<R, T> Drop for AnalysisStage<R, T> {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        // A full Block subscriber would otherwise keep the worker waiting
+        if let Some(output) = lock(&self.shared.output).take() {
+            output.close();
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn lock<V>(mutex: &Mutex<V>) -> std::sync::MutexGuard<'_, V> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analysis_results_and_panics() {
+        let broadcaster = FrameBroadcaster::<u32>::new();
+        let stage = AnalysisStage::spawn(
+            broadcaster.subscribe(16, Backpressure::Block),
+            |n| match n {
+                3 => panic!("model failed"),
+                n if n % 2 == 0 => Some(n * 10),
+                _ => None,
+            },
+        );
+        let results = stage.subscribe(16, Backpressure::Block);
+
+        for n in 0..6 {
+            broadcaster.publish(n);
+        }
+        drop(broadcaster);
+
+        let mut received = Vec::new();
+        while let Some(analyzed) = results.recv() {
+            assert_eq!(analyzed.result, *analyzed.frame * 10);
+            received.push(analyzed.result);
+        }
+        assert_eq!(received, [0, 20, 40]);
+        assert_eq!(stage.latest().unwrap().result, 40);
+        assert_eq!(
+            stage.stats(),
+            AnalysisStats {
+                analyzed: 3,
+                skipped: 2,
+                panicked: 1,
+                dropped: 0
+            }
+        );
+    }
+    #[test]
+    fn test_drop_with_full_blocking_subscriber() {
+        let broadcaster = FrameBroadcaster::<u32>::new();
+        let stage =
+            AnalysisStage::spawn(broadcaster.subscribe(16, Backpressure::Block), |&n| Some(n));
+        let results = stage.subscribe(1, Backpressure::Block);
+        for n in 0..4 {
+            broadcaster.publish(n);
+        }
+        // The worker blocks publishing the second result until it is dropped
+        while stage.stats().analyzed < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(stage);
+
+        assert_eq!(*results.recv().unwrap().frame, 0);
+        assert!(results.recv().is_none());
+    }
+}
@@ -0,0 +1,400 @@
+//! Fan-out of decoded frames to several consumers without re-decoding.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::decompression_session::DecodeOutput;
+use super::playback_decoder::PlaybackFrame;
+use super::sendable::SendablePixelBuffer;
+use super::tee_sink::Backpressure;
+
+/// Delivery counters for one [`FrameSubscriber`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriberStats {
+    /// Frames received from the broadcaster
+    pub delivered: u64,
+    /// Frames discarded by the backpressure policy
+    pub dropped: u64,
+    /// Frames waiting to be received
+    pub queued: usize,
+}
+
+struct QueueState<T> {
+    queue: VecDeque<Arc<T>>,
+    /// The broadcaster is gone; no more frames will arrive
+    closed: bool,
+    /// The subscriber is gone; stop queueing for it
+    unsubscribed: bool,
+    stats: SubscriberStats,
+}
+
+struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    changed: Condvar,
+    policy: Backpressure,
+    capacity: usize,
+}
+
+impl<T> Queue<T> {
+    fn lock(&self) -> MutexGuard<'_, QueueState<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, QueueState<T>>) -> MutexGuard<'a, QueueState<T>> {
+        self.changed.wait(guard).unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue a frame according to the policy. Returns false once the
+    /// subscriber has gone away or the queue has been closed.
+    fn push(&self, frame: &Arc<T>) -> bool {
+        let mut state = self.lock();
+        if state.queue.len() >= self.capacity {
+            match self.policy {
+                Backpressure::Block => {
+                    while state.queue.len() >= self.capacity && !state.unsubscribed && !state.closed
+                    {
+                        state = self.wait(state);
+                    }
+                }
+                Backpressure::DropOldest => {
+                    state.queue.pop_front();
+                    state.stats.dropped += 1;
+                }
+                Backpressure::DropNewest => {
+                    state.stats.dropped += 1;
+                    return !state.unsubscribed;
+                }
+            }
+        }
+        if state.unsubscribed || state.closed {
+            return false;
+        }
+        state.queue.push_back(frame.clone());
+        state.stats.delivered += 1;
+        self.changed.notify_all();
+        true
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+struct Shared<T> {
+    subscribers: Mutex<Vec<Arc<Queue<T>>>>,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, Vec<Arc<Queue<T>>>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Runs once the last broadcaster clone is gone
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        for queue in self.lock().iter() {
+            queue.close();
+        }
+    }
+}
+
+/// Shares each decoded frame with several subscribers, e.g. display and ML
+/// inference, so the stream is decoded once.
+///
+/// Frames are wrapped in an [`Arc`] once and every subscriber gets a clone,
+/// so pixel buffers are never copied. Each subscriber has its own bounded
+/// queue and [`Backpressure`] policy: a slow inference thread using
+/// [`Backpressure::DropOldest`] sees only recent frames without holding up
+/// the display. [`Backpressure::Block`] stalls
+/// [`publish`](Self::publish), and with it the decoder callback, until that
+/// subscriber catches up.
+///
+/// Clones share the same subscribers. Subscribers see the end of the stream
+/// once every clone has been dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{
+///     Backpressure, DecompressionSession, DecompressionSessionConfig, FrameBroadcaster,
+/// };
+///
+/// let broadcaster = FrameBroadcaster::new();
+/// let display = broadcaster.subscribe(2, Backpressure::Block);
+/// let inference = broadcaster.subscribe(1, Backpressure::DropOldest);
+///
+/// # let format_desc = std::ptr::null_mut();
+/// let publisher = broadcaster.clone();
+/// let config = DecompressionSessionConfig::default();
+/// let session = unsafe {
+///     DecompressionSession::new(format_desc, &config, move |output| {
+///         // SAFETY: the output comes straight from the decoder callback
+///         unsafe { publisher.publish_decoded(&output) };
+///     })
+/// };
+///
+/// std::thread::spawn(move || {
+///     while let Some(frame) = inference.recv() {
+///         // run the model on frame.pixel_buffer
+///     }
+/// });
+/// ```
+pub struct FrameBroadcaster<T = PlaybackFrame> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for FrameBroadcaster<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Default for FrameBroadcaster<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> FrameBroadcaster<T> {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                subscribers: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Add a subscriber that queues up to `capacity` frames.
+    ///
+    /// It receives frames published from now on.
+    pub fn subscribe(&self, capacity: usize, policy: Backpressure) -> FrameSubscriber<T> {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState {
+                queue: VecDeque::new(),
+                closed: false,
+                unsubscribed: false,
+                stats: SubscriberStats::default(),
+            }),
+            changed: Condvar::new(),
+            policy,
+            capacity: capacity.max(1),
+        });
+        self.shared.lock().push(queue.clone());
+        FrameSubscriber { queue }
+    }
+
+    /// End every subscription now, even while clones of the broadcaster
+    /// remain, waking a publisher blocked on a full queue.
+    pub(super) fn close(&self) {
+        for queue in self.shared.lock().drain(..) {
+            queue.close();
+        }
+    }
+
+    /// Number of live subscribers.
+    pub fn subscriber_count(&self) -> usize {
+        let mut subscribers = self.shared.lock();
+        subscribers.retain(|queue| !queue.lock().unsubscribed);
+        subscribers.len()
+    }
+
+    /// Send a frame to every subscriber. Returns how many are still
+    /// subscribed.
+    pub fn publish(&self, frame: T) -> usize {
+        self.publish_shared(Arc::new(frame))
+    }
+
+    /// Like [`publish`](Self::publish), for a frame that is already shared.
+    pub fn publish_shared(&self, frame: Arc<T>) -> usize {
+        // Don't hold the list lock while a blocking subscriber waits
+        let subscribers = self.shared.lock().clone();
+        let mut gone = false;
+        let mut live = 0;
+        for queue in &subscribers {
+            if queue.push(&frame) {
+                live += 1;
+            } else {
+                gone = true;
+            }
+        }
+        if gone {
+            self.shared
+                .lock()
+                .retain(|queue| !queue.lock().unsubscribed);
+        }
+        live
+    }
+}
+
+impl FrameBroadcaster<PlaybackFrame> {
+    /// Publish the image from a decoder callback, retaining its pixel buffer.
+    /// Outputs without an image are ignored.
+    ///
+    /// # Safety
+    ///
+    /// `output` must come from a [`DecompressionSession`](super::DecompressionSession)
+    /// output callback that has not yet returned.
+    pub unsafe fn publish_decoded(&self, output: &DecodeOutput) -> usize {
+        match *output {
+            DecodeOutput::Frame {
+                image_buffer,
+                pts,
+                duration,
+                ..
+            } if !image_buffer.is_null() => self.publish(PlaybackFrame {
+                pixel_buffer: SendablePixelBuffer::retain(image_buffer),
+                pts,
+                duration,
+            }),
+            _ => 0,
+        }
+    }
+}
+
+/// Receiving end of a [`FrameBroadcaster`] subscription.
+///
+/// Dropping it unsubscribes.
+pub struct FrameSubscriber<T = PlaybackFrame> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> FrameSubscriber<T> {
+    /// Wait for the next frame. Returns `None` once the broadcaster is gone
+    /// and the queue is empty.
+    pub fn recv(&self) -> Option<Arc<T>> {
+        let mut state = self.queue.lock();
+        loop {
+            if let Some(frame) = state.queue.pop_front() {
+                self.queue.changed.notify_all();
+                return Some(frame);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.queue.wait(state);
+        }
+    }
+
+    /// Wait up to `timeout` for the next frame.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Arc<T>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.queue.lock();
+        loop {
+            if let Some(frame) = state.queue.pop_front() {
+                self.queue.changed.notify_all();
+                return Some(frame);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if state.closed || remaining.is_zero() {
+                return None;
+            }
+            state = self
+                .queue
+                .changed
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Take the next frame if one is queued.
+    pub fn try_recv(&self) -> Option<Arc<T>> {
+        let frame = self.queue.lock().queue.pop_front();
+        if frame.is_some() {
+            self.queue.changed.notify_all();
+        }
+        frame
+    }
+
+    /// True once the broadcaster is gone; queued frames can still be received.
+    pub fn is_closed(&self) -> bool {
+        self.queue.lock().closed
+    }
+
+    pub fn stats(&self) -> SubscriberStats {
+        let state = self.queue.lock();
+        let mut stats = state.stats.clone();
+        stats.queued = state.queue.len();
+        stats
+    }
+}
+
+impl<T> Drop for FrameSubscriber<T> {
+    fn drop(&mut self) {
+        let mut state = self.queue.lock();
+        state.unsubscribed = true;
+        state.queue.clear();
+        // Wake a publisher blocked on this queue
+        self.queue.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_independent_policies_share_frames() {
+        let broadcaster = FrameBroadcaster::<Vec<u8>>::new();
+        let newest = broadcaster.subscribe(2, Backpressure::DropOldest);
+        let oldest = broadcaster.subscribe(2, Backpressure::DropNewest);
+        for i in 0..5u8 {
+            assert_eq!(broadcaster.publish(vec![i; 1024]), 2);
+        }
+
+        let a = newest.try_recv().unwrap();
+        let b = oldest.try_recv().unwrap();
+        assert_eq!((a[0], b[0]), (3, 0));
+        assert_eq!(newest.try_recv().unwrap()[0], 4);
+        assert_eq!(oldest.try_recv().unwrap()[0], 1);
+        assert_eq!(
+            newest.stats(),
+            SubscriberStats {
+                delivered: 5,
+                dropped: 3,
+                queued: 0
+            }
+        );
+        assert_eq!(oldest.stats().dropped, 3);
+
+        // Frames are shared, not copied
+        let other = broadcaster.subscribe(1, Backpressure::DropOldest);
+        broadcaster.publish(vec![7]);
+        assert!(Arc::ptr_eq(
+            &newest.try_recv().unwrap(),
+            &other.try_recv().unwrap()
+        ));
+
+        drop(oldest);
+        assert_eq!(broadcaster.publish(vec![9]), 2);
+        assert_eq!(broadcaster.subscriber_count(), 2);
+    }
+
+    #[test]
+    fn test_blocking_subscriber_and_end_of_stream() {
+        let broadcaster = FrameBroadcaster::<u32>::new();
+        let subscriber = broadcaster.subscribe(1, Backpressure::Block);
+        let publisher = broadcaster.clone();
+        let producer = thread::spawn(move || {
+            for i in 0..100 {
+                publisher.publish(i);
+            }
+        });
+        drop(broadcaster);
+
+        let mut received = Vec::new();
+        while let Some(frame) = subscriber.recv() {
+            received.push(*frame);
+        }
+        producer.join().unwrap();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+        assert!(subscriber.is_closed());
+        assert_eq!(subscriber.stats().dropped, 0);
+    }
+}
@@ -0,0 +1,408 @@
+//! Frame hashing and golden-frame comparison for decoder regression tests.
+//!
+//! Hashes cover only the visible bytes of each plane, so row padding (which
+//! differs between devices and OS releases) does not affect the result. When
+//! decoders are not bit-exact across macOS versions, [`compare_frame`] falls back
+//! to a PSNR check against a stored reference snapshot.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::codecs;
+use crate::cv_types::{
+    kCVPixelBufferLock_ReadOnly, kCVReturnSuccess, CVPixelBufferGetBaseAddress,
+    CVPixelBufferGetBaseAddressOfPlane, CVPixelBufferGetBytesPerRow,
+    CVPixelBufferGetBytesPerRowOfPlane, CVPixelBufferGetHeight, CVPixelBufferGetHeightOfPlane,
+    CVPixelBufferGetPixelFormatType, CVPixelBufferGetPlaneCount, CVPixelBufferGetWidth,
+    CVPixelBufferGetWidthOfPlane, CVPixelBufferIsPlanar, CVPixelBufferLockBaseAddress,
+    CVPixelBufferRef, CVPixelBufferUnlockBaseAddress,
+};
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Bytes per pixel of a plane for known pixel formats.
+fn bytes_per_pixel(pixel_format: u32, plane: usize) -> Option<usize> {
+    match (pixel_format, plane) {
+        (codecs::pixel::BGRA32 | codecs::pixel::ARGB32 | codecs::pixel::RGBA32, 0) => Some(4),
+        (codecs::pixel::RGB24, 0) => Some(3),
+        (codecs::pixel::YUV422, 0) => Some(2),
+        (
+            codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE | codecs::pixel::YUV420_BIPLANAR_FULL_RANGE,
+            0,
+        ) => Some(1),
+        (
+            codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE | codecs::pixel::YUV420_BIPLANAR_FULL_RANGE,
+            1,
+        ) => Some(2), // interleaved CbCr
+        _ => None,
+    }
+}
+
+/// A single image plane with row padding removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plane {
+    /// Visible bytes per row
+    pub row_bytes: usize,
+    /// Number of rows
+    pub height: usize,
+    /// `row_bytes * height` bytes of pixel data
+    pub data: Vec<u8>,
+}
+
+impl Plane {
+    /// Copy a plane out of a padded buffer.
+    ///
+    /// Returns `None` if `data` is too short for the given geometry.
+    pub fn from_padded(
+        data: &[u8],
+        row_bytes: usize,
+        height: usize,
+        bytes_per_row: usize,
+    ) -> Option<Self> {
+        if row_bytes > bytes_per_row
+            || (height > 0 && data.len() < (height - 1) * bytes_per_row + row_bytes)
+        {
+            return None;
+        }
+        let mut packed = Vec::with_capacity(row_bytes * height);
+        for row in 0..height {
+            let start = row * bytes_per_row;
+            packed.extend_from_slice(&data[start..start + row_bytes]);
+        }
+        Some(Self {
+            row_bytes,
+            height,
+            data: packed,
+        })
+    }
+
+    /// Stable 64-bit FNV-1a hash of the plane geometry and pixels.
+    pub fn hash(&self) -> u64 {
+        let mut hash = FNV_OFFSET;
+        let geometry = [self.row_bytes as u64, self.height as u64];
+        for byte in geometry
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .chain(self.data.iter().copied())
+        {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+        hash
+    }
+}
+
+/// Per-plane hashes of a decoded frame.
+///
+/// Formats as colon-separated hex (`"a1b2...:c3d4..."`) for storage in golden files.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FrameHash {
+    pub planes: Vec<u64>,
+}
+
+impl fmt::Display for FrameHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, hash) in self.planes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:016x}", hash)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FrameHash {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let planes = s
+            .trim()
+            .split(':')
+            .map(|p| u64::from_str_radix(p, 16))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { planes })
+    }
+}
+
+/// An owned, padding-free copy of a decoded frame's planes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameSnapshot {
+    pub pixel_format: u32,
+    pub width: usize,
+    pub height: usize,
+    pub planes: Vec<Plane>,
+}
+
+impl FrameSnapshot {
+    /// Copy the visible pixels of a pixel buffer.
+    ///
+    /// For pixel formats without a known bytes-per-pixel, the full padded row
+    /// is kept, so hashes of such formats are not padding tolerant.
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid `CVPixelBufferRef`.
+    pub unsafe fn from_pixel_buffer(pixel_buffer: CVPixelBufferRef) -> Result<Self, i32> {
+        let status = CVPixelBufferLockBaseAddress(pixel_buffer, kCVPixelBufferLock_ReadOnly);
+        if status != kCVReturnSuccess {
+            return Err(status);
+        }
+
+        let pixel_format = CVPixelBufferGetPixelFormatType(pixel_buffer);
+        let mut planes = Vec::new();
+        let mut copy_plane =
+            |plane: usize, base: *const u8, width: usize, height: usize, bytes_per_row: usize| {
+                if base.is_null() {
+                    return;
+                }
+                let row_bytes = bytes_per_pixel(pixel_format, plane)
+                    .map(|bpp| bpp * width)
+                    .unwrap_or(bytes_per_row);
+                let data = std::slice::from_raw_parts(base, bytes_per_row * height);
+                if let Some(p) = Plane::from_padded(data, row_bytes, height, bytes_per_row) {
+                    planes.push(p);
+                }
+            };
+
+        if CVPixelBufferIsPlanar(pixel_buffer) != 0 {
+            for plane in 0..CVPixelBufferGetPlaneCount(pixel_buffer) {
+                copy_plane(
+                    plane,
+                    CVPixelBufferGetBaseAddressOfPlane(pixel_buffer, plane) as *const u8,
+                    CVPixelBufferGetWidthOfPlane(pixel_buffer, plane),
+                    CVPixelBufferGetHeightOfPlane(pixel_buffer, plane),
+                    CVPixelBufferGetBytesPerRowOfPlane(pixel_buffer, plane),
+                );
+            }
+        } else {
+            copy_plane(
+                0,
+                CVPixelBufferGetBaseAddress(pixel_buffer) as *const u8,
+                CVPixelBufferGetWidth(pixel_buffer),
+                CVPixelBufferGetHeight(pixel_buffer),
+                CVPixelBufferGetBytesPerRow(pixel_buffer),
+            );
+        }
+
+        CVPixelBufferUnlockBaseAddress(pixel_buffer, kCVPixelBufferLock_ReadOnly);
+
+        Ok(Self {
+            pixel_format,
+            width: CVPixelBufferGetWidth(pixel_buffer),
+            height: CVPixelBufferGetHeight(pixel_buffer),
+            planes,
+        })
+    }
+
+    /// Hash every plane.
+    pub fn hash(&self) -> FrameHash {
+        FrameHash {
+            planes: self.planes.iter().map(Plane::hash).collect(),
+        }
+    }
+
+    /// Peak signal-to-noise ratio (8-bit samples) against another snapshot.
+    ///
+    /// Returns `None` if the frames have different geometry, and
+    /// `f64::INFINITY` for identical frames.
+    pub fn psnr(&self, other: &FrameSnapshot) -> Option<f64> {
+        if self.planes.len() != other.planes.len() {
+            return None;
+        }
+        let mut squared_error = 0u64;
+        let mut samples = 0u64;
+        for (a, b) in self.planes.iter().zip(&other.planes) {
+            if a.row_bytes != b.row_bytes || a.height != b.height {
+                return None;
+            }
+            for (&x, &y) in a.data.iter().zip(&b.data) {
+                let diff = x as i64 - y as i64;
+                squared_error += (diff * diff) as u64;
+            }
+            samples += a.data.len() as u64;
+        }
+        if squared_error == 0 {
+            return Some(f64::INFINITY);
+        }
+        let mse = squared_error as f64 / samples as f64;
+        Some(10.0 * (255.0 * 255.0 / mse).log10())
+    }
+}
+
+/// Outcome of comparing a decoded frame against its golden hash.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameMatch {
+    /// Hashes are identical.
+    Exact,
+    /// Hashes differ, but PSNR against the reference meets the threshold.
+    WithinTolerance { psnr: f64 },
+    /// Hashes differ and no reference was given, or PSNR is below the threshold.
+    Mismatch { psnr: Option<f64> },
+}
+
+impl FrameMatch {
+    /// Returns true for [`FrameMatch::Exact`] and [`FrameMatch::WithinTolerance`].
+    pub fn is_match(&self) -> bool {
+        !matches!(self, FrameMatch::Mismatch { .. })
+    }
+}
+
+/// Compare a decoded frame against a golden hash, falling back to PSNR.
+///
+/// `reference` is the stored golden frame used when the hash differs; frames
+/// with a PSNR of at least `min_psnr` dB are accepted.
+pub fn compare_frame(
+    actual: &FrameSnapshot,
+    golden: &FrameHash,
+    reference: Option<&FrameSnapshot>,
+    min_psnr: f64,
+) -> FrameMatch {
+    if actual.hash() == *golden {
+        return FrameMatch::Exact;
+    }
+    match reference.and_then(|r| actual.psnr(r)) {
+        Some(psnr) if psnr >= min_psnr => FrameMatch::WithinTolerance { psnr },
+        psnr => FrameMatch::Mismatch { psnr },
+    }
+}
+
+/// Golden hashes keyed by frame index.
+///
+/// Stored as text, one `<index> <hash>` pair per line; `#` starts a comment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GoldenHashes {
+    frames: BTreeMap<u64, FrameHash>,
+}
+
+impl GoldenHashes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the text format. Returns the offending line number on error.
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let mut frames = BTreeMap::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let index = fields.next().and_then(|f| f.parse().ok());
+            let hash = fields.next().and_then(|f| f.parse().ok());
+            match (index, hash, fields.next()) {
+                (Some(index), Some(hash), None) => {
+                    frames.insert(index, hash);
+                }
+                _ => return Err(line_no + 1),
+            }
+        }
+        Ok(Self { frames })
+    }
+
+    pub fn insert(&mut self, index: u64, hash: FrameHash) {
+        self.frames.insert(index, hash);
+    }
+
+    pub fn get(&self, index: u64) -> Option<&FrameHash> {
+        self.frames.get(&index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl fmt::Display for GoldenHashes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, hash) in &self.frames {
+            writeln!(f, "{} {}", index, hash)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(luma: Vec<u8>) -> FrameSnapshot {
+        FrameSnapshot {
+            pixel_format: codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE,
+            width: 4,
+            height: 2,
+            planes: vec![
+                Plane::from_padded(&luma, 4, 2, 4).unwrap(),
+                Plane::from_padded(&[128; 4], 4, 1, 4).unwrap(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_hash_ignores_row_padding() {
+        let tight = [1, 2, 3, 4, 5, 6, 7, 8];
+        let padded = [1, 2, 3, 4, 0xAA, 0xAA, 5, 6, 7, 8, 0xBB, 0xBB];
+        let a = Plane::from_padded(&tight, 4, 2, 4).unwrap();
+        let b = Plane::from_padded(&padded, 4, 2, 6).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.hash(), b.hash());
+
+        // Same bytes, different geometry
+        let c = Plane::from_padded(&tight, 2, 4, 2).unwrap();
+        assert_ne!(a.hash(), c.hash());
+
+        assert!(Plane::from_padded(&tight, 4, 3, 4).is_none());
+    }
+
+    #[test]
+    fn test_frame_hash_round_trip() {
+        let hash = snapshot(vec![16; 8]).hash();
+        assert_eq!(hash.planes.len(), 2);
+        let text = hash.to_string();
+        assert_eq!(text.len(), 33);
+        assert_eq!(text.parse::<FrameHash>().unwrap(), hash);
+        assert!("xyz".parse::<FrameHash>().is_err());
+    }
+
+    #[test]
+    fn test_compare_frame_psnr_fallback() {
+        let reference = snapshot(vec![100; 8]);
+        let golden = reference.hash();
+        assert_eq!(
+            compare_frame(&reference, &golden, None, 40.0),
+            FrameMatch::Exact
+        );
+
+        // One sample off by one: tiny error, high PSNR
+        let close = snapshot(vec![100, 100, 100, 100, 100, 100, 100, 101]);
+        let result = compare_frame(&close, &golden, Some(&reference), 40.0);
+        assert!(matches!(result, FrameMatch::WithinTolerance { psnr } if psnr > 40.0));
+        assert!(!compare_frame(&close, &golden, None, 40.0).is_match());
+
+        let far = snapshot(vec![0; 8]);
+        let result = compare_frame(&far, &golden, Some(&reference), 40.0);
+        assert!(matches!(result, FrameMatch::Mismatch { psnr: Some(p) } if p < 40.0));
+    }
+
+    #[test]
+    fn test_golden_hashes_parse() {
+        let mut golden = GoldenHashes::new();
+        golden.insert(0, snapshot(vec![1; 8]).hash());
+        golden.insert(7, snapshot(vec![2; 8]).hash());
+
+        let text = format!("# decoder goldens\n\n{}", golden);
+        let parsed = GoldenHashes::parse(&text).unwrap();
+        assert_eq!(parsed, golden);
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed.get(3).is_none());
+
+        assert_eq!(GoldenHashes::parse("0 abc\n1\n"), Err(2));
+    }
+}
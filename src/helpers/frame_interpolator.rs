@@ -0,0 +1,280 @@
+//! Frame rate up-conversion with VTFrameProcessor, e.g. 30 fps to 60 fps.
+
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::string::CFString;
+use core_foundation_sys::base::OSStatus;
+use core_foundation_sys::dictionary::CFDictionaryRef;
+use core_foundation_sys::string::CFStringRef;
+use core_media_sys::CMTime;
+use objc2::rc::Retained;
+use objc2::runtime::AnyObject;
+use objc2_foundation::{NSArray, NSNumber};
+
+use super::cf_dict::CFDictBuilder;
+use super::clock::make_time;
+use super::pixel_buffer_pool::PixelBufferPool;
+use super::sendable::SendablePixelBuffer;
+use crate::cv_types::{
+    kCVPixelBufferHeightKey, kCVPixelBufferPixelFormatTypeKey, kCVPixelBufferWidthKey,
+    CVPixelBufferGetHeight, CVPixelBufferGetPixelFormatType, CVPixelBufferGetWidth,
+    CVPixelBufferRef,
+};
+use crate::errors::{
+    kVTParameterErr, VTFrameProcessorSessionNotStarted, VTFrameProcessorUnsupportedInput,
+};
+use crate::frame_processor::{
+    VTFrameProcessorFrame_init, VTFrameProcessor_endSession, VTFrameProcessor_new,
+    VTFrameProcessor_process, VTFrameProcessor_startSession,
+    VTFrameRateConversionConfigurationQualityPrioritizationNormal,
+    VTFrameRateConversionConfigurationRevision1,
+    VTFrameRateConversionConfiguration_destinationPixelBufferAttributes,
+    VTFrameRateConversionConfiguration_init, VTFrameRateConversionConfiguration_isSupported,
+    VTFrameRateConversionParametersSubmissionModeSequential, VTFrameRateConversionParameters_init,
+};
+
+/// Raises the frame rate of a stream by synthesizing frames between each
+/// pair of input frames with VTFrameProcessor's motion-compensated frame
+/// rate conversion (macOS 15.4+).
+///
+/// With a factor of 2 (30 fps to 60 fps), each pushed frame returns the
+/// frame halfway between it and the previous one, followed by the frame
+/// itself; the first frame is returned on its own. Interpolated frames are
+/// timestamped evenly between their neighbours, so variable frame rate
+/// input stays in sync. Input frames must all have the configured size and
+/// one of the pixel formats the processor accepts (e.g. 420v or BGRA).
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::FrameInterpolator;
+/// # let (frame, pts) = (std::ptr::null_mut(), video_toolbox_sys::helpers::make_time(0, 30));
+///
+/// if FrameInterpolator::is_supported() {
+///     let mut interpolator = FrameInterpolator::new(1920, 1080, 2)?;
+///     // For each 30 fps capture frame:
+///     for (buffer, pts) in unsafe { interpolator.push(frame, pts)? } {
+///         // encode at 60 fps
+///     }
+///     interpolator.finish();
+/// }
+/// # Ok::<(), i32>(())
+/// ```
+pub struct FrameInterpolator {
+    processor: Retained<AnyObject>,
+    destination_attributes: Option<Retained<AnyObject>>,
+    /// Interpolated frames, for the pixel format of the input
+    pool: Option<(u32, PixelBufferPool)>,
+    width: usize,
+    height: usize,
+    factor: u32,
+    previous: Option<(SendablePixelBuffer, CMTime)>,
+    active: bool,
+}
+
+impl FrameInterpolator {
+    /// Whether this system supports frame rate conversion.
+    pub fn is_supported() -> bool {
+        unsafe { VTFrameRateConversionConfiguration_isSupported() }
+    }
+
+    /// Start a conversion session for `width` x `height` frames that
+    /// multiplies the frame rate by `factor` (at least 2).
+    pub fn new(width: usize, height: usize, factor: u32) -> Result<Self, OSStatus> {
+        if factor < 2 {
+            return Err(kVTParameterErr);
+        }
+        unsafe {
+            let configuration = VTFrameRateConversionConfiguration_init(
+                width as isize,
+                height as isize,
+                false,
+                VTFrameRateConversionConfigurationQualityPrioritizationNormal,
+                VTFrameRateConversionConfigurationRevision1,
+            )
+            .ok_or(VTFrameProcessorUnsupportedInput)?;
+            let processor = VTFrameProcessor_new().ok_or(VTFrameProcessorUnsupportedInput)?;
+            VTFrameProcessor_startSession(&processor, &configuration)?;
+            Ok(Self {
+                destination_attributes:
+                    VTFrameRateConversionConfiguration_destinationPixelBufferAttributes(
+                        &configuration,
+                    ),
+                processor,
+                pool: None,
+                width,
+                height,
+                factor,
+                previous: None,
+                active: true,
+            })
+        }
+    }
+
+    pub fn factor(&self) -> u32 {
+        self.factor
+    }
+
+    /// Add the next input frame, returning the output frames it completes
+    /// in presentation order: the interpolated frames since the previous
+    /// input, then `frame` itself.
+    ///
+    /// # Safety
+    ///
+    /// `frame` must be a valid CVPixelBuffer that is not written to while
+    /// it is retained by the interpolator (until the next push).
+    pub unsafe fn push(
+        &mut self,
+        frame: CVPixelBufferRef,
+        pts: CMTime,
+    ) -> Result<Vec<(SendablePixelBuffer, CMTime)>, OSStatus> {
+        if !self.active {
+            return Err(VTFrameProcessorSessionNotStarted);
+        }
+        if CVPixelBufferGetWidth(frame) != self.width
+            || CVPixelBufferGetHeight(frame) != self.height
+        {
+            return Err(kVTParameterErr);
+        }
+        let current = SendablePixelBuffer::retain(frame);
+        let mut output = match self.previous.clone() {
+            Some((previous, previous_pts)) => {
+                self.interpolate(&previous, previous_pts, &current, pts)?
+            }
+            None => Vec::new(),
+        };
+        output.push((current.clone(), pts));
+        self.previous = Some((current, pts));
+        Ok(output)
+    }
+
+    /// Frames between `previous` and `next`, at evenly spaced phases.
+    unsafe fn interpolate(
+        &mut self,
+        previous: &SendablePixelBuffer,
+        previous_pts: CMTime,
+        next: &SendablePixelBuffer,
+        next_pts: CMTime,
+    ) -> Result<Vec<(SendablePixelBuffer, CMTime)>, OSStatus> {
+        let times = interpolated_times(previous_pts, next_pts, self.factor);
+        if times.is_empty() {
+            // Timestamps that don't advance leave no room for new frames
+            return Ok(Vec::new());
+        }
+        let format = CVPixelBufferGetPixelFormatType(previous.as_raw());
+        let mut destinations = Vec::with_capacity(times.len());
+        let mut frames = Vec::with_capacity(times.len());
+        for &time in &times {
+            let buffer = self.create_destination(format)?;
+            frames.push(
+                VTFrameProcessorFrame_init(buffer.as_raw(), time.into())
+                    .ok_or(VTFrameProcessorUnsupportedInput)?,
+            );
+            destinations.push((buffer, time));
+        }
+
+        let source = VTFrameProcessorFrame_init(previous.as_raw(), previous_pts.into())
+            .ok_or(VTFrameProcessorUnsupportedInput)?;
+        let next = VTFrameProcessorFrame_init(next.as_raw(), next_pts.into())
+            .ok_or(VTFrameProcessorUnsupportedInput)?;
+        let phases: Vec<Retained<NSNumber>> = (1..self.factor)
+            .map(|i| NSNumber::new_f32(i as f32 / self.factor as f32))
+            .collect();
+        let parameters = VTFrameRateConversionParameters_init(
+            &source,
+            &next,
+            None,
+            &NSArray::from_retained_slice(&phases),
+            VTFrameRateConversionParametersSubmissionModeSequential,
+            &NSArray::from_retained_slice(&frames),
+        )
+        .ok_or(kVTParameterErr)?;
+        VTFrameProcessor_process(&self.processor, &parameters)?;
+        Ok(destinations)
+    }
+
+    /// A buffer for an interpolated frame from the pool, re-created with
+    /// the processor's destination attributes when the format changes.
+    unsafe fn create_destination(&mut self, format: u32) -> Result<SendablePixelBuffer, OSStatus> {
+        if let Some((current, pool)) = &self.pool {
+            if *current == format {
+                return pool.acquire();
+            }
+        }
+        let mut attributes = CFDictBuilder::new();
+        if let Some(destination) = &self.destination_attributes {
+            let destination: CFDictionary =
+                CFDictionary::wrap_under_get_rule(Retained::as_ptr(destination) as CFDictionaryRef);
+            let (keys, values) = destination.get_keys_and_values();
+            for (key, value) in keys.into_iter().zip(values) {
+                attributes.insert(
+                    CFString::wrap_under_get_rule(key as CFStringRef),
+                    CFType::wrap_under_get_rule(value),
+                );
+            }
+        }
+        attributes.insert(kCVPixelBufferPixelFormatTypeKey, format as i32);
+        attributes.insert(kCVPixelBufferWidthKey, self.width as i32);
+        attributes.insert(kCVPixelBufferHeightKey, self.height as i32);
+        let pool = PixelBufferPool::with_attributes(&attributes, 0)?;
+        let buffer = pool.acquire()?;
+        self.pool = Some((format, pool));
+        Ok(buffer)
+    }
+
+    /// End the conversion session. Later pushes fail.
+    pub fn finish(&mut self) {
+        if self.active {
+            unsafe { VTFrameProcessor_endSession(&self.processor) };
+            self.active = false;
+        }
+        self.previous = None;
+    }
+}
+
+impl Drop for FrameInterpolator {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Timestamps of the `factor - 1` frames evenly spaced between `previous`
+/// and `next`, in `next`'s timescale. Empty if `next` is not later.
+fn interpolated_times(previous: CMTime, next: CMTime, factor: u32) -> Vec<CMTime> {
+    let timescale = next.timescale.max(1);
+    let start = if previous.timescale == timescale {
+        previous.value
+    } else {
+        (previous.value as i128 * timescale as i128 / previous.timescale.max(1) as i128) as i64
+    };
+    let span = next.value - start;
+    if span <= 0 {
+        return Vec::new();
+    }
+    (1..factor as i64)
+        .map(|i| make_time(start + span * i / factor as i64, timescale))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(times: &[CMTime]) -> Vec<i64> {
+        times.iter().map(|t| t.value).collect()
+    }
+
+    #[test]
+    fn test_interpolated_times() {
+        // 30 fps to 60 fps at 90 kHz
+        let times = interpolated_times(make_time(3000, 90000), make_time(6000, 90000), 2);
+        assert_eq!(values(&times), [4500]);
+        assert_eq!(times[0].timescale, 90000);
+
+        // 4x over an uneven gap, previous frame in milliseconds
+        let times = interpolated_times(make_time(100, 1000), make_time(12600, 90000), 4);
+        assert_eq!(values(&times), [9900, 10800, 11700]);
+
+        assert!(interpolated_times(make_time(6000, 90000), make_time(6000, 90000), 2).is_empty());
+    }
+}
@@ -0,0 +1,683 @@
+//! Live HLS output: a sliding-window playlist with segment retention and
+//! optional Low-Latency HLS partial segments.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::cmaf_demuxer::{find_child, NON_SYNC_SAMPLE};
+use super::events::{emit, PipelineEvent};
+use super::sink::{DirectorySink, Segment, SegmentSink};
+
+type BeforeDeleteFn = Box<dyn FnMut(&Path) -> io::Result<()> + Send>;
+
+/// HLS playlist settings.
+#[derive(Debug, Clone)]
+pub struct HlsConfig {
+    /// Playlist file name in the output directory
+    pub playlist_name: String,
+    /// Segments listed in the playlist; 0 keeps every segment (event playlist)
+    pub window: usize,
+    /// Segments kept on disk after leaving the window, for clients still
+    /// fetching from an older playlist
+    pub safety_margin: usize,
+    /// Duration assumed for segments that do not carry one
+    pub default_duration: Duration,
+    /// Treat incoming media segments as Low-Latency HLS partial segments
+    pub parts: Option<HlsPartConfig>,
+}
+
+impl Default for HlsConfig {
+    fn default() -> Self {
+        Self {
+            playlist_name: "playlist.m3u8".to_string(),
+            window: 6,
+            safety_margin: 2,
+            default_duration: Duration::from_secs(2),
+            parts: None,
+        }
+    }
+}
+
+/// Low-Latency HLS settings (see [`HlsConfig::parts`]).
+#[derive(Debug, Clone)]
+pub struct HlsPartConfig {
+    /// Declared part duration (`PART-TARGET`); raised if a longer part arrives
+    pub part_target: Duration,
+    /// A new segment starts at the first independent part once the current
+    /// one lasts at least this long
+    pub segment_duration: Duration,
+    /// Advertise `CAN-BLOCK-RELOAD=YES`, for a server that holds playlist
+    /// requests carrying `_HLS_msn`/`_HLS_part` until that part exists
+    pub can_block_reload: bool,
+}
+
+impl Default for HlsPartConfig {
+    fn default() -> Self {
+        Self {
+            part_target: Duration::from_millis(500),
+            segment_duration: Duration::from_secs(2),
+            can_block_reload: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PlaylistEntry {
+    sequence_number: u32,
+    duration: Duration,
+    path: PathBuf,
+    /// Partial segments, in Low-Latency HLS mode
+    parts: Vec<PartEntry>,
+}
+
+#[derive(Debug, Clone)]
+struct PartEntry {
+    duration: Duration,
+    independent: bool,
+    path: PathBuf,
+}
+
+/// The Low-Latency HLS segment whose parts are still arriving.
+struct OpenSegment {
+    entry: PlaylistEntry,
+    data: Vec<u8>,
+}
+
+/// Segment files that have left the playlist window, deleted once more than
+/// the safety margin have accumulated.
+pub(super) struct SegmentRetention {
+    margin: usize,
+    retired: VecDeque<PathBuf>,
+    pub(super) before_delete: Option<BeforeDeleteFn>,
+}
+
+impl SegmentRetention {
+    pub(super) fn new(margin: usize) -> Self {
+        Self {
+            margin,
+            retired: VecDeque::new(),
+            before_delete: None,
+        }
+    }
+
+    /// Queue a file that has left the playlist.
+    pub(super) fn retire(&mut self, path: PathBuf) {
+        self.retired.push_back(path);
+    }
+
+    /// Delete retired segments beyond the margin, oldest first.
+    ///
+    /// A segment whose hook or deletion fails stays queued and is retried on
+    /// the next call; later segments wait behind it.
+    pub(super) fn collect(&mut self) -> Vec<PipelineEvent> {
+        let mut events = Vec::new();
+        while self.retired.len() > self.margin {
+            let path = &self.retired[0];
+            let result = match self.before_delete.as_mut() {
+                Some(hook) => hook(path),
+                None => Ok(()),
+            }
+            .and_then(|_| match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            });
+            if let Err(e) = result {
+                events.push(PipelineEvent::SegmentRetentionFailed {
+                    path: path.clone(),
+                    error: e.to_string(),
+                });
+                break;
+            }
+            self.retired.pop_front();
+        }
+        events
+    }
+}
+
+/// Writes CMAF segments to a directory along with a live HLS media playlist,
+/// deleting segments once they fall out of the sliding window.
+///
+/// The playlist lists the last [`window`](HlsConfig::window) media segments
+/// and is rewritten atomically after each one. Segments that leave the
+/// window stay on disk for [`safety_margin`](HlsConfig::safety_margin) more
+/// segments, then are passed to the [`before_delete`](Self::before_delete)
+/// hook (e.g. to upload them to archival storage) and deleted. Deletion only
+/// happens after the hook succeeds; failures are reported as
+/// [`PipelineEvent::SegmentRetentionFailed`] and retried.
+///
+/// Segment durations come from [`Segment::duration`] (see
+/// [`CmafMuxer::last_fragment_duration`](super::CmafMuxer::last_fragment_duration)),
+/// so audio-only streams from [`AudioCmafMuxer`](super::AudioCmafMuxer) work
+/// the same way as video.
+///
+/// With [`HlsConfig::parts`] set, the sink produces a Low-Latency HLS
+/// playlist instead: every media segment it receives is a sub-second part,
+/// such as a [`LowLatencyChunk`](super::LowLatencyChunk) or a short CMAF
+/// fragment, and is written as `segment_NNN.K.m4s` right away. Parts are
+/// grouped into full segments (`segment_NNN.m4s`, numbered by the sink)
+/// that start with an independent part. Recent segments are listed with
+/// their `EXT-X-PART`s, followed by an `EXT-X-PRELOAD-HINT` for the next
+/// part.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{HlsConfig, HlsSink};
+///
+/// let mut hls = HlsSink::new("live", HlsConfig::default())?.before_delete(|path| {
+///     println!("archiving {}", path.display());
+///     Ok(())
+/// });
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct HlsSink {
+    dir: DirectorySink,
+    config: HlsConfig,
+    entries: VecDeque<PlaylistEntry>,
+    retention: SegmentRetention,
+    target_duration: u64,
+    /// Low-Latency HLS segment being assembled from parts
+    open: Option<OpenSegment>,
+    next_number: u32,
+    part_target: Duration,
+    has_init: bool,
+    ended: bool,
+}
+
+impl HlsSink {
+    /// Write into `dir`, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>, config: HlsConfig) -> io::Result<Self> {
+        let retention = SegmentRetention::new(config.safety_margin);
+        let part_target = config
+            .parts
+            .as_ref()
+            .map_or(Duration::ZERO, |parts| parts.part_target);
+        Ok(Self {
+            dir: DirectorySink::new(dir)?,
+            config,
+            entries: VecDeque::new(),
+            retention,
+            target_duration: 1,
+            open: None,
+            next_number: 1,
+            part_target,
+            has_init: false,
+            ended: false,
+        })
+    }
+
+    /// Call `hook` with each segment file before it is deleted.
+    ///
+    /// If the hook fails the file is kept and the hook is called again after
+    /// the next segment.
+    pub fn before_delete<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&Path) -> io::Result<()> + Send + 'static,
+    {
+        self.retention.before_delete = Some(Box::new(hook));
+        self
+    }
+
+    pub fn playlist_path(&self) -> PathBuf {
+        self.dir.dir().join(&self.config.playlist_name)
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.dir()
+    }
+
+    /// Segment files currently listed in the playlist, oldest first.
+    pub fn segments(&self) -> impl Iterator<Item = &Path> {
+        self.entries.iter().map(|entry| entry.path.as_path())
+    }
+
+    /// Retired segment files awaiting deletion.
+    pub fn retired_segments(&self) -> impl Iterator<Item = &Path> {
+        self.retention.retired.iter().map(|path| path.as_path())
+    }
+
+    /// Mark the stream as ended (`#EXT-X-ENDLIST`) and rewrite the playlist.
+    ///
+    /// In Low-Latency HLS mode the segment still receiving parts is
+    /// completed first.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.close_open_segment()?;
+        self.ended = true;
+        self.write_playlist()
+    }
+
+    /// Render the current playlist.
+    pub fn playlist(&self) -> String {
+        let mut out = String::new();
+        let media_sequence = self
+            .entries
+            .front()
+            .or(self.open.as_ref().map(|open| &open.entry))
+            .map_or(0, |e| e.sequence_number);
+        let _ = writeln!(out, "#EXTM3U");
+        let version = if self.config.parts.is_some() { 9 } else { 7 };
+        let _ = writeln!(out, "#EXT-X-VERSION:{}", version);
+        let _ = writeln!(out, "#EXT-X-TARGETDURATION:{}", self.target_duration);
+        if let Some(parts) = &self.config.parts {
+            let block = if parts.can_block_reload {
+                "CAN-BLOCK-RELOAD=YES,"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                out,
+                "#EXT-X-SERVER-CONTROL:{}PART-HOLD-BACK={:.3}",
+                block,
+                3.0 * self.part_target.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "#EXT-X-PART-INF:PART-TARGET={:.3}",
+                self.part_target.as_secs_f64()
+            );
+        }
+        let _ = writeln!(out, "#EXT-X-MEDIA-SEQUENCE:{}", media_sequence);
+        if self.config.window == 0 {
+            let _ = writeln!(out, "#EXT-X-PLAYLIST-TYPE:EVENT");
+        }
+        if self.has_init {
+            let _ = writeln!(out, "#EXT-X-MAP:URI=\"init.mp4\"");
+        }
+
+        // Parts are listed for segments within three target durations of
+        // the live edge
+        let part_horizon = Duration::from_secs(3 * self.target_duration);
+        let mut from_end = self
+            .open
+            .as_ref()
+            .map_or(Duration::ZERO, |o| o.entry.duration);
+        let mut with_parts = 0;
+        for entry in self.entries.iter().rev() {
+            if self.ended || from_end >= part_horizon {
+                break;
+            }
+            from_end += entry.duration;
+            with_parts += 1;
+        }
+        let listed_parts = self.entries.len() - with_parts;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i >= listed_parts {
+                write_parts(&mut out, &entry.parts);
+            }
+            let _ = writeln!(out, "#EXTINF:{:.3},", entry.duration.as_secs_f64());
+            let _ = writeln!(out, "{}", file_name(&entry.path));
+        }
+        if let Some(open) = &self.open {
+            write_parts(&mut out, &open.entry.parts);
+        }
+        if self.config.parts.is_some() && !self.ended {
+            let _ = writeln!(
+                out,
+                "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{}\"",
+                self.next_part_name()
+            );
+        }
+        if self.ended {
+            let _ = writeln!(out, "#EXT-X-ENDLIST");
+        }
+        out
+    }
+
+    /// Write the playlist via a temporary file so readers never see a partial one.
+    fn write_playlist(&self) -> io::Result<()> {
+        let path = self.playlist_path();
+        let tmp = path.with_extension("m3u8.tmp");
+        fs::write(&tmp, self.playlist())?;
+        fs::rename(&tmp, &path)
+    }
+
+    /// Name of the part that will be written next.
+    fn next_part_name(&self) -> String {
+        match &self.open {
+            Some(open) => part_name(open.entry.sequence_number, open.entry.parts.len()),
+            None => part_name(self.next_number, 0),
+        }
+    }
+
+    /// Add a Low-Latency HLS part, completing the current segment first if
+    /// this part may start a new one.
+    fn write_part(&mut self, segment: &Segment, duration: Duration) -> io::Result<()> {
+        let segment_duration = self
+            .config
+            .parts
+            .as_ref()
+            .map_or(Duration::ZERO, |parts| parts.segment_duration);
+        let independent = starts_with_sync_sample(&segment.data).unwrap_or(true);
+        if independent
+            && self
+                .open
+                .as_ref()
+                .is_some_and(|open| open.entry.duration >= segment_duration)
+        {
+            self.close_open_segment()?;
+        }
+        let open = match &mut self.open {
+            Some(open) => open,
+            None => {
+                let number = self.next_number;
+                self.next_number += 1;
+                self.open.insert(OpenSegment {
+                    entry: PlaylistEntry {
+                        sequence_number: number,
+                        duration: Duration::ZERO,
+                        path: self.dir.dir().join(format!("segment_{:03}.m4s", number)),
+                        parts: Vec::new(),
+                    },
+                    data: Vec::new(),
+                })
+            }
+        };
+
+        let path = self.dir.dir().join(part_name(
+            open.entry.sequence_number,
+            open.entry.parts.len(),
+        ));
+        write_atomically(&path, &segment.data)?;
+        open.data.extend_from_slice(&segment.data);
+        open.entry.duration += duration;
+        open.entry.parts.push(PartEntry {
+            duration,
+            independent,
+            path,
+        });
+        self.part_target = self.part_target.max(duration);
+        self.target_duration = self
+            .target_duration
+            .max(open.entry.duration.as_secs_f64().round() as u64);
+        Ok(())
+    }
+
+    /// Write the segment assembled from parts and add it to the playlist.
+    fn close_open_segment(&mut self) -> io::Result<()> {
+        let Some(open) = self.open.take() else {
+            return Ok(());
+        };
+        write_atomically(&open.entry.path, &open.data)?;
+        self.push_entry(open.entry);
+        Ok(())
+    }
+
+    fn push_entry(&mut self, entry: PlaylistEntry) {
+        // EXTINF rounded to the nearest second must not exceed the target
+        self.target_duration = self
+            .target_duration
+            .max(entry.duration.as_secs_f64().round() as u64);
+        self.entries.push_back(entry);
+        while self.config.window > 0 && self.entries.len() > self.config.window {
+            let entry = self.entries.pop_front().unwrap();
+            for part in entry.parts {
+                self.retention.retire(part.path);
+            }
+            self.retention.retire(entry.path);
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn part_name(segment: u32, index: usize) -> String {
+    format!("segment_{:03}.{}.m4s", segment, index)
+}
+
+fn write_parts(out: &mut String, parts: &[PartEntry]) {
+    for part in parts {
+        let independent = if part.independent {
+            ",INDEPENDENT=YES"
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            out,
+            "#EXT-X-PART:DURATION={:.3},URI=\"{}\"{}",
+            part.duration.as_secs_f64(),
+            file_name(&part.path),
+            independent
+        );
+    }
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("m4s.tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+/// Whether a fragment's first sample is a sync sample, from the sample
+/// flags in its `trun` or the `tfhd` defaults. `None` if they are absent.
+pub(super) fn starts_with_sync_sample(fragment: &[u8]) -> Option<bool> {
+    let moof = find_child(fragment, b"moof").ok()??;
+    let traf = find_child(moof, b"traf").ok()??;
+    let field = |data: &[u8], offset: usize| -> Option<u32> {
+        Some(u32::from_be_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+
+    let mut default_flags = None;
+    if let Some(tfhd) = find_child(traf, b"tfhd").ok()? {
+        let flags = field(tfhd, 0)? & 0x00FF_FFFF;
+        if flags & 0x20 != 0 {
+            let mut offset = 8;
+            for (flag, size) in [(0x01, 8), (0x02, 4), (0x08, 4), (0x10, 4)] {
+                if flags & flag != 0 {
+                    offset += size;
+                }
+            }
+            default_flags = Some(field(tfhd, offset)?);
+        }
+    }
+
+    let trun = find_child(traf, b"trun").ok()??;
+    let flags = field(trun, 0)? & 0x00FF_FFFF;
+    let mut offset = 8;
+    if flags & 0x01 != 0 {
+        offset += 4;
+    }
+    let sample_flags = if flags & 0x04 != 0 {
+        Some(field(trun, offset)?)
+    } else if flags & 0x400 != 0 {
+        for flag in [0x100, 0x200] {
+            if flags & flag != 0 {
+                offset += 4;
+            }
+        }
+        Some(field(trun, offset)?)
+    } else {
+        default_flags
+    };
+    sample_flags.map(|flags| flags & NON_SYNC_SAMPLE == 0)
+}
+
+impl SegmentSink for HlsSink {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        if segment.is_init() || self.config.parts.is_none() {
+            self.dir.write_segment(segment)?;
+        }
+        if segment.is_init() {
+            self.has_init = true;
+            return self.write_playlist();
+        }
+
+        let duration = segment.duration.unwrap_or(self.config.default_duration);
+        if self.config.parts.is_some() {
+            self.write_part(segment, duration)?;
+        } else {
+            self.push_entry(PlaylistEntry {
+                sequence_number: segment.sequence_number,
+                duration,
+                path: self.dir.segment_path(segment),
+                parts: Vec::new(),
+            });
+        }
+
+        // Only delete files once the playlist no longer references them
+        self.write_playlist()?;
+        for event in self.retention.collect() {
+            emit(event);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn media(sequence_number: u32) -> Segment {
+        Segment::media(sequence_number, vec![0; 4]).with_duration(Duration::from_millis(2002))
+    }
+
+    #[test]
+    fn test_sliding_window_and_retention() {
+        let dir = std::env::temp_dir().join(format!("vt-hls-{}", std::process::id()));
+        let archived = Arc::new(Mutex::new(Vec::new()));
+        let log = archived.clone();
+        let config = HlsConfig {
+            window: 3,
+            safety_margin: 1,
+            ..Default::default()
+        };
+        let mut hls = HlsSink::new(&dir, config)
+            .unwrap()
+            .before_delete(move |path| {
+                log.lock().unwrap().push(file_name(path));
+                Ok(())
+            });
+
+        hls.write_segment(&Segment::init(vec![0; 4])).unwrap();
+        for seq in 1..=6 {
+            hls.write_segment(&media(seq)).unwrap();
+        }
+
+        // Window 4..=6, segment 3 kept as the margin, 1 and 2 deleted
+        let playlist = fs::read_to_string(hls.playlist_path()).unwrap();
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:4\n"));
+        assert!(playlist.contains("#EXT-X-TARGETDURATION:2\n"));
+        assert!(playlist.contains("#EXT-X-MAP:URI=\"init.mp4\"\n"));
+        assert!(playlist.contains("#EXTINF:2.002,\nsegment_004.m4s\n"));
+        assert!(!playlist.contains("segment_003.m4s"));
+        assert_eq!(
+            *archived.lock().unwrap(),
+            ["segment_001.m4s", "segment_002.m4s"]
+        );
+        assert!(!dir.join("segment_002.m4s").exists());
+        assert!(dir.join("segment_003.m4s").exists());
+        assert!(dir.join("init.mp4").exists());
+
+        hls.finish().unwrap();
+        assert!(fs::read_to_string(hls.playlist_path())
+            .unwrap()
+            .ends_with("#EXT-X-ENDLIST\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_hook_keeps_segment() {
+        let dir = std::env::temp_dir().join(format!("vt-hls-fail-{}", std::process::id()));
+        let config = HlsConfig {
+            window: 1,
+            safety_margin: 0,
+            ..Default::default()
+        };
+        let fail = Arc::new(Mutex::new(true));
+        let flag = fail.clone();
+        let mut hls = HlsSink::new(&dir, config).unwrap().before_delete(move |_| {
+            if *flag.lock().unwrap() {
+                Err(io::Error::other("upload failed"))
+            } else {
+                Ok(())
+            }
+        });
+
+        hls.write_segment(&media(1)).unwrap();
+        hls.write_segment(&media(2)).unwrap();
+        assert!(dir.join("segment_001.m4s").exists());
+        assert_eq!(hls.retired_segments().count(), 1);
+
+        *fail.lock().unwrap() = false;
+        hls.write_segment(&media(3)).unwrap();
+        assert!(!dir.join("segment_001.m4s").exists());
+        assert!(!dir.join("segment_002.m4s").exists());
+        assert_eq!(hls.segments().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A fragment whose single sample is or is not a sync sample.
+    fn part(sync: bool) -> Segment {
+        fn boxed(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+            let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+            out.extend_from_slice(box_type);
+            out.extend_from_slice(payload);
+            out
+        }
+        let tfhd = boxed(b"tfhd", &[0, 0, 0, 0, 0, 0, 0, 1]);
+        let sample_flags: u32 = if sync { 0x0200_0000 } else { 0x0101_0000 };
+        let mut trun = vec![0, 0, 0, 0x04, 0, 0, 0, 1];
+        trun.extend_from_slice(&sample_flags.to_be_bytes());
+        let traf = boxed(b"traf", &[tfhd, boxed(b"trun", &trun)].concat());
+        let mut data = boxed(b"moof", &traf);
+        data.extend(boxed(b"mdat", &[sync as u8]));
+        Segment::media(0, data).with_duration(Duration::from_millis(400))
+    }
+
+    #[test]
+    fn test_low_latency_parts() {
+        let dir = std::env::temp_dir().join(format!("vt-hls-parts-{}", std::process::id()));
+        let config = HlsConfig {
+            parts: Some(HlsPartConfig {
+                segment_duration: Duration::from_secs(1),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut hls = HlsSink::new(&dir, config).unwrap();
+        hls.write_segment(&Segment::init(vec![0; 4])).unwrap();
+        let parts = [
+            part(true),
+            part(false),
+            part(false),
+            part(true),
+            part(false),
+        ];
+        for part in &parts {
+            hls.write_segment(part).unwrap();
+        }
+
+        let playlist = hls.playlist();
+        assert!(playlist.contains("#EXT-X-SERVER-CONTROL:PART-HOLD-BACK=1.500\n"));
+        assert!(playlist.contains("#EXT-X-PART-INF:PART-TARGET=0.500\n"));
+        assert!(playlist.contains(concat!(
+            "#EXT-X-PART:DURATION=0.400,URI=\"segment_001.0.m4s\",INDEPENDENT=YES\n",
+            "#EXT-X-PART:DURATION=0.400,URI=\"segment_001.1.m4s\"\n",
+            "#EXT-X-PART:DURATION=0.400,URI=\"segment_001.2.m4s\"\n",
+            "#EXTINF:1.200,\nsegment_001.m4s\n",
+            "#EXT-X-PART:DURATION=0.400,URI=\"segment_002.0.m4s\",INDEPENDENT=YES\n",
+            "#EXT-X-PART:DURATION=0.400,URI=\"segment_002.1.m4s\"\n",
+            "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"segment_002.2.m4s\"\n",
+        )));
+        let full: Vec<u8> = parts[..3].iter().flat_map(|p| p.data.clone()).collect();
+        assert_eq!(fs::read(dir.join("segment_001.m4s")).unwrap(), full);
+        assert!(dir.join("segment_002.1.m4s").exists());
+        assert!(!dir.join("segment_002.m4s").exists());
+
+        hls.finish().unwrap();
+        let playlist = fs::read_to_string(hls.playlist_path()).unwrap();
+        assert!(playlist.ends_with("#EXTINF:0.800,\nsegment_002.m4s\n#EXT-X-ENDLIST\n"));
+        assert!(!playlist.contains("#EXT-X-PART:"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
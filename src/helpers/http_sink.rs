@@ -0,0 +1,524 @@
+//! Segment upload to object storage or an origin server via HTTP PUT.
+//!
+//! Enabled with the `http-upload` feature. The built-in [`HttpTransport`]
+//! speaks plain HTTP/1.1 using only the standard library; for HTTPS (e.g. S3
+//! directly) plug in a [`PutTransport`] backed by the HTTP client of your
+//! choice, or upload through a TLS-terminating proxy.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::sink::{Segment, SegmentSink};
+
+/// A single object upload.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadRequest<'a> {
+    /// Full object URL
+    pub url: &'a str,
+    pub content_type: &'a str,
+    pub body: &'a [u8],
+}
+
+/// Performs HTTP PUT requests for an [`HttpPutSink`].
+pub trait PutTransport: Send + Sync {
+    /// Upload `request` with the extra `headers`, returning the HTTP status code.
+    fn put(&self, request: &UploadRequest<'_>, headers: &[(String, String)]) -> io::Result<u16>;
+}
+
+/// Plain HTTP/1.1 transport, one connection per request.
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    timeout: Duration,
+}
+
+impl Default for HttpTransport {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(30))
+    }
+}
+
+impl HttpTransport {
+    /// Use `timeout` for connecting, sending and receiving.
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+/// Split an `http://host[:port]/path` URL into authority and path.
+fn split_http_url(url: &str) -> io::Result<(&str, &str)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("HttpTransport only supports http:// URLs: {}", url),
+        )
+    })?;
+    Ok(match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    })
+}
+
+impl PutTransport for HttpTransport {
+    fn put(&self, request: &UploadRequest<'_>, headers: &[(String, String)]) -> io::Result<u16> {
+        let (authority, path) = split_http_url(request.url)?;
+        let address = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+
+        let mut stream = TcpStream::connect(&address)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut head = format!(
+            "PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            path,
+            authority,
+            request.content_type,
+            request.body.len()
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(request.body)?;
+        stream.flush()?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed HTTP status line: {:?}", status_line.trim_end()),
+                )
+            })
+    }
+}
+
+/// Retry schedule for failed uploads.
+///
+/// Network errors, `408`, `429` and `5xx` responses are retried; other
+/// statuses fail immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per upload, including the first
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubles after each attempt
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (starting at 0).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1u32 << retry.min(16))
+            .min(self.max_backoff)
+    }
+}
+
+fn is_retryable(status: u16) -> bool {
+    status == 408 || status == 429 || (500..600).contains(&status)
+}
+
+/// Upload counters for an [`HttpPutSink`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadStats {
+    /// Objects uploaded successfully
+    pub uploaded: u64,
+    /// Objects that failed after all retries
+    pub failed: u64,
+    /// Retried attempts across all objects
+    pub retries: u64,
+    /// Body bytes uploaded successfully
+    pub bytes: u64,
+}
+
+type AuthFn = Box<dyn Fn(&UploadRequest<'_>) -> Vec<(String, String)> + Send + Sync>;
+
+struct Job {
+    name: String,
+    content_type: &'static str,
+    body: Arc<Vec<u8>>,
+}
+
+struct UploadState {
+    queue: VecDeque<Job>,
+    in_flight: usize,
+    closed: bool,
+    error: Option<(io::ErrorKind, String)>,
+    stats: UploadStats,
+}
+
+struct Uploader {
+    base_url: String,
+    transport: Box<dyn PutTransport>,
+    auth: Option<AuthFn>,
+    retry: RetryPolicy,
+    state: Mutex<UploadState>,
+    changed: Condvar,
+}
+
+impl Uploader {
+    fn lock(&self) -> MutexGuard<'_, UploadState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, UploadState>) -> MutexGuard<'a, UploadState> {
+        self.changed.wait(guard).unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Upload one object with retries; returns the error after the last attempt.
+    fn upload(&self, job: &Job) -> io::Result<()> {
+        let url = format!("{}{}", self.base_url, job.name);
+        let request = UploadRequest {
+            url: &url,
+            content_type: job.content_type,
+            body: &job.body,
+        };
+        let headers = self
+            .auth
+            .as_ref()
+            .map(|auth| auth(&request))
+            .unwrap_or_default();
+
+        let mut retry = 0;
+        loop {
+            let error = match self.transport.put(&request, &headers) {
+                Ok(status) if (200..300).contains(&status) => return Ok(()),
+                Ok(status) => {
+                    let error = io::Error::other(format!("PUT {} returned {}", url, status));
+                    if !is_retryable(status) {
+                        return Err(error);
+                    }
+                    error
+                }
+                Err(e) => e,
+            };
+            if retry + 1 >= self.retry.max_attempts {
+                return Err(error);
+            }
+            self.lock().stats.retries += 1;
+            thread::sleep(self.retry.backoff(retry));
+            retry += 1;
+        }
+    }
+
+    fn run(&self) {
+        let mut state = self.lock();
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                state.in_flight += 1;
+                drop(state);
+                let result = self.upload(&job);
+                state = self.lock();
+                state.in_flight -= 1;
+                match result {
+                    Ok(()) => {
+                        state.stats.uploaded += 1;
+                        state.stats.bytes += job.body.len() as u64;
+                    }
+                    Err(e) => {
+                        state.stats.failed += 1;
+                        if state.error.is_none() {
+                            state.error = Some((e.kind(), format!("{}: {}", job.name, e)));
+                        }
+                    }
+                }
+                self.changed.notify_all();
+            } else if state.closed {
+                break;
+            } else {
+                state = self.wait(state);
+            }
+        }
+    }
+}
+
+/// A [`SegmentSink`] that uploads segments with HTTP PUT.
+///
+/// Segments are uploaded to `<base_url><file name>` (`init.mp4`,
+/// `segment_NNN.m4s`) by up to [`max_in_flight`](Self::max_in_flight)
+/// parallel workers; `write_segment` blocks once that many uploads are
+/// queued. Failed uploads are retried per the [`RetryPolicy`]; an upload
+/// that still fails is reported by the next `write_segment` or `flush`.
+///
+/// Playlists and other objects that reference segments are uploaded with
+/// [`put_after_pending`](Self::put_after_pending), which waits for queued
+/// segments first so clients never see a reference to a missing object.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::HttpPutSink;
+///
+/// let sink = HttpPutSink::new("http://origin.example.com/live/cam1/")
+///     .max_in_flight(4)
+///     .auth(|_request| vec![("Authorization".to_string(), "Bearer token".to_string())]);
+/// ```
+pub struct HttpPutSink {
+    uploader: Arc<Uploader>,
+    max_in_flight: usize,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl HttpPutSink {
+    /// Upload under `base_url` using the built-in [`HttpTransport`].
+    ///
+    /// A trailing `/` is added to `base_url` if missing.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_transport(base_url, HttpTransport::default())
+    }
+
+    /// Upload under `base_url` using a custom transport (e.g. an HTTPS client).
+    pub fn with_transport<T>(base_url: impl Into<String>, transport: T) -> Self
+    where
+        T: PutTransport + 'static,
+    {
+        let mut base_url = base_url.into();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        Self {
+            uploader: Arc::new(Uploader {
+                base_url,
+                transport: Box::new(transport),
+                auth: None,
+                retry: RetryPolicy::default(),
+                state: Mutex::new(UploadState {
+                    queue: VecDeque::new(),
+                    in_flight: 0,
+                    closed: false,
+                    error: None,
+                    stats: UploadStats::default(),
+                }),
+                changed: Condvar::new(),
+            }),
+            max_in_flight: 2,
+            workers: Vec::new(),
+        }
+    }
+
+    /// Compute extra headers (e.g. `Authorization`, or S3 signature headers)
+    /// for each request.
+    pub fn auth<F>(mut self, headers: F) -> Self
+    where
+        F: Fn(&UploadRequest<'_>) -> Vec<(String, String)> + Send + Sync + 'static,
+    {
+        self.configure().auth = Some(Box::new(headers));
+        self
+    }
+
+    /// Retry schedule for failed uploads (default: 4 attempts from 250 ms).
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.configure().retry = policy;
+        self
+    }
+
+    /// Maximum parallel uploads (default: 2).
+    pub fn max_in_flight(mut self, uploads: usize) -> Self {
+        self.max_in_flight = uploads.max(1);
+        self
+    }
+
+    /// Builder access to the uploader before any worker holds it.
+    fn configure(&mut self) -> &mut Uploader {
+        Arc::get_mut(&mut self.uploader).expect("configure HttpPutSink before writing segments")
+    }
+
+    /// Base URL objects are uploaded under.
+    pub fn base_url(&self) -> &str {
+        &self.uploader.base_url
+    }
+
+    pub fn stats(&self) -> UploadStats {
+        self.uploader.lock().stats.clone()
+    }
+
+    /// Queue an arbitrary object (e.g. a DASH manifest) for upload.
+    pub fn put(&mut self, name: &str, content_type: &'static str, body: Vec<u8>) -> io::Result<()> {
+        self.enqueue(Job {
+            name: name.to_string(),
+            content_type,
+            body: Arc::new(body),
+        })
+    }
+
+    /// Wait for all queued uploads, then upload an object that references
+    /// them (e.g. an HLS playlist).
+    pub fn put_after_pending(
+        &mut self,
+        name: &str,
+        content_type: &'static str,
+        body: Vec<u8>,
+    ) -> io::Result<()> {
+        self.flush()?;
+        self.put(name, content_type, body)
+    }
+
+    fn enqueue(&mut self, job: Job) -> io::Result<()> {
+        while self.workers.len() < self.max_in_flight {
+            let uploader = self.uploader.clone();
+            let worker = thread::Builder::new()
+                .name(format!("http-put-{}", self.workers.len()))
+                .spawn(move || uploader.run())?;
+            self.workers.push(worker);
+        }
+
+        let uploader = &self.uploader;
+        let mut state = uploader.lock();
+        take_error(&mut state)?;
+        while state.queue.len() >= self.max_in_flight {
+            state = uploader.wait(state);
+        }
+        state.queue.push_back(job);
+        uploader.changed.notify_all();
+        Ok(())
+    }
+}
+
+/// Report (and clear) the first failed upload.
+fn take_error(state: &mut UploadState) -> io::Result<()> {
+    match state.error.take() {
+        Some((kind, message)) => Err(io::Error::new(kind, message)),
+        None => Ok(()),
+    }
+}
+
+fn content_type(segment: &Segment) -> &'static str {
+    if segment.is_init() {
+        "video/mp4"
+    } else {
+        "video/iso.segment"
+    }
+}
+
+impl SegmentSink for HttpPutSink {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        self.enqueue(Job {
+            name: segment.file_name(),
+            content_type: content_type(segment),
+            body: Arc::new(segment.data.clone()),
+        })
+    }
+
+    /// Wait for all queued uploads to finish.
+    fn flush(&mut self) -> io::Result<()> {
+        let uploader = &self.uploader;
+        let mut state = uploader.lock();
+        while !state.queue.is_empty() || state.in_flight > 0 {
+            state = uploader.wait(state);
+        }
+        take_error(&mut state)
+    }
+}
+
+impl Drop for HttpPutSink {
+    fn drop(&mut self) {
+        self.uploader.lock().closed = true;
+        self.uploader.changed.notify_all();
+        // Workers finish queued uploads before exiting
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    /// Answers each connection with the next status, recording request heads.
+    fn serve(statuses: Vec<u16>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/bucket/live", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut heads = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                // Read until the head and the 4-byte body have arrived
+                while !request.ends_with(b"\r\n\r\nbody") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                heads.push(String::from_utf8_lossy(&request).into_owned());
+                write!(stream, "HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+            heads
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_upload_retries_and_auth() {
+        let (url, server) = serve(vec![503, 200]);
+        let mut sink = HttpPutSink::new(url)
+            .max_in_flight(1)
+            .retry(RetryPolicy {
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            })
+            .auth(|request| vec![("X-Length".to_string(), request.body.len().to_string())]);
+
+        sink.write_segment(&Segment::media(7, b"body".to_vec()))
+            .unwrap();
+        sink.flush().unwrap();
+
+        let heads = server.join().unwrap();
+        assert_eq!(heads.len(), 2);
+        assert!(heads[1].starts_with("PUT /bucket/live/segment_007.m4s HTTP/1.1\r\n"));
+        assert!(heads[1].contains("Content-Type: video/iso.segment\r\n"));
+        assert!(heads[1].contains("X-Length: 4\r\n"));
+        let stats = sink.stats();
+        assert_eq!((stats.uploaded, stats.retries, stats.bytes), (1, 1, 4));
+    }
+
+    #[test]
+    fn test_client_error_is_not_retried() {
+        let (url, server) = serve(vec![403]);
+        let mut sink = HttpPutSink::new(url);
+        sink.write_segment(&Segment::init(b"body".to_vec()))
+            .unwrap();
+        let error = sink.flush().unwrap_err();
+        assert!(error.to_string().contains("init.mp4"));
+        assert_eq!(server.join().unwrap().len(), 1);
+        assert_eq!(sink.stats().failed, 1);
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(3), Duration::from_millis(500));
+        assert!(split_http_url("https://s3.amazonaws.com/b").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! Pixel format negotiation between a capture device and an encoder.
+//!
+//! Cameras deliver their native format (usually NV12, sometimes packed
+//! 4:2:2), the capture output can convert to a few others, and the encoder
+//! works in NV12 internally. Picking the capture output and the encoder's
+//! source format together keeps frames in one format end to end, and leaves
+//! an explicit [`PixelTransfer`] stage for the rare case no common format
+//! exists.
+
+use core_foundation_sys::base::OSStatus;
+use std::fmt;
+
+use super::pixel_transfer::{PixelTransfer, PixelTransferBuilder};
+use super::sendable::SendablePixelBuffer;
+use crate::codecs;
+use crate::cv_types::CVPixelBufferRef;
+
+/// Source formats VideoToolbox encoders take without converting, best first:
+/// NV12 in video and full range, then BGRA.
+pub const ENCODER_INPUT_FORMATS: [u32; 3] = [
+    codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE,
+    codecs::pixel::YUV420_BIPLANAR_FULL_RANGE,
+    codecs::pixel::BGRA32,
+];
+
+/// How frames get from the camera's native format to the encoder's source
+/// format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputPath {
+    /// The camera's native format goes to the encoder unchanged
+    Native { format: u32 },
+    /// The capture output converts the native format in its own pipeline
+    CaptureConversion { native: u32, format: u32 },
+    /// No common format: capture delivers `capture` and a [`PixelTransfer`]
+    /// stage converts each frame to `encoder`
+    PixelTransfer { capture: u32, encoder: u32 },
+}
+
+impl InputPath {
+    /// Pixel format the capture output delivers.
+    pub fn capture_format(&self) -> u32 {
+        match *self {
+            InputPath::Native { format } | InputPath::CaptureConversion { format, .. } => format,
+            InputPath::PixelTransfer { capture, .. } => capture,
+        }
+    }
+
+    /// Pixel format to build the compression session with
+    /// ([`CompressionSessionBuilder::pixel_format`](super::CompressionSessionBuilder::pixel_format)).
+    pub fn encoder_format(&self) -> u32 {
+        match *self {
+            InputPath::Native { format } | InputPath::CaptureConversion { format, .. } => format,
+            InputPath::PixelTransfer { encoder, .. } => encoder,
+        }
+    }
+
+    pub fn needs_transfer(&self) -> bool {
+        matches!(self, InputPath::PixelTransfer { .. })
+    }
+}
+
+impl fmt::Display for InputPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            InputPath::Native { format } => write!(f, "native {}", FourCc(format)),
+            InputPath::CaptureConversion { native, format } => write!(
+                f,
+                "capture output converts {} to {}",
+                FourCc(native),
+                FourCc(format)
+            ),
+            InputPath::PixelTransfer { capture, encoder } => write!(
+                f,
+                "pixel transfer converts {} to {}",
+                FourCc(capture),
+                FourCc(encoder)
+            ),
+        }
+    }
+}
+
+/// Prints a pixel format as its four characters, e.g. `'420v'`.
+struct FourCc(u32);
+
+impl fmt::Display for FourCc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.0.to_be_bytes();
+        if bytes
+            .iter()
+            .all(|byte| byte.is_ascii_graphic() || *byte == b' ')
+        {
+            write!(f, "'{}'", String::from_utf8_lossy(&bytes))
+        } else {
+            write!(f, "{:#010x}", self.0)
+        }
+    }
+}
+
+/// Choose how frames in the camera's `native` format reach an encoder
+/// accepting `encoder_formats` (best first, e.g. [`ENCODER_INPUT_FORMATS`]),
+/// given the formats the capture output can deliver.
+///
+/// The native format is used if the encoder takes it; otherwise the
+/// encoder's most preferred format the capture output can convert to.
+/// Only when there is none is a pixel transfer stage needed.
+pub fn negotiate_input_format(
+    native: u32,
+    capture_formats: &[u32],
+    encoder_formats: &[u32],
+) -> InputPath {
+    if encoder_formats.contains(&native) {
+        return InputPath::Native { format: native };
+    }
+    if let Some(&format) = encoder_formats
+        .iter()
+        .find(|format| capture_formats.contains(format))
+    {
+        return InputPath::CaptureConversion { native, format };
+    }
+    let capture = if capture_formats.is_empty() || capture_formats.contains(&native) {
+        native
+    } else {
+        capture_formats[0]
+    };
+    InputPath::PixelTransfer {
+        capture,
+        encoder: encoder_formats
+            .first()
+            .copied()
+            .unwrap_or(codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE),
+    }
+}
+
+/// Hands captured frames to the encoder along a negotiated [`InputPath`],
+/// converting them only on the pixel transfer path.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{
+///     CaptureSessionBuilder, CompressionSessionBuilder, InputConverter, ENCODER_INPUT_FORMATS,
+/// };
+/// # extern "C-unwind" fn on_frame(_: *mut libc::c_void, _: objc2::runtime::Sel, _: *mut libc::c_void, _: *mut libc::c_void, _: *mut libc::c_void) {}
+///
+/// let capture = CaptureSessionBuilder::new()
+///     .resolution(1920, 1080)
+///     .negotiate_input(&ENCODER_INPUT_FORMATS)
+///     .build(on_frame)?;
+/// let path = capture.input_path();
+/// println!("input path: {}", path);
+/// let session = CompressionSessionBuilder::new(1920, 1080, codecs::video::H264)
+///     .pixel_format(path.encoder_format())
+///     .build_session(|_output| {})
+///     .expect("failed to create encoder");
+/// let mut converter = InputConverter::new(path).expect("failed to create pixel transfer");
+/// // In the capture callback:
+/// # let frame = std::ptr::null_mut();
+/// let input = unsafe { converter.convert(frame) }.expect("conversion failed");
+/// // encode input.as_raw()
+/// # Ok::<(), video_toolbox_sys::helpers::CaptureError>(())
+/// ```
+pub struct InputConverter {
+    path: InputPath,
+    transfer: Option<PixelTransfer>,
+}
+
+impl InputConverter {
+    /// Prepare for `path`, creating the pixel transfer session if it needs one.
+    pub fn new(path: InputPath) -> Result<Self, OSStatus> {
+        let transfer = match path {
+            InputPath::PixelTransfer { encoder, .. } => {
+                Some(PixelTransferBuilder::new().pixel_format(encoder).build()?)
+            }
+            _ => None,
+        };
+        Ok(Self { path, transfer })
+    }
+
+    pub fn path(&self) -> InputPath {
+        self.path
+    }
+
+    /// The frame to encode for a captured `frame`: the frame itself, or a
+    /// converted copy on the pixel transfer path.
+    ///
+    /// # Safety
+    ///
+    /// `frame` must be a valid CVPixelBuffer, e.g. the image buffer of a
+    /// captured sample buffer.
+    pub unsafe fn convert(
+        &mut self,
+        frame: CVPixelBufferRef,
+    ) -> Result<SendablePixelBuffer, OSStatus> {
+        match &mut self.transfer {
+            Some(transfer) => transfer.transfer(frame),
+            None => Ok(SendablePixelBuffer::retain(frame)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NV12: u32 = codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE;
+    const BGRA: u32 = codecs::pixel::BGRA32;
+    /// Packed 4:2:2 ('yuvs'), native to some USB cameras
+    const YUVS: u32 = 0x79757673;
+    /// 10-bit 4:2:0 ('x420'), e.g. for HEVC Main 10
+    const X420: u32 = 0x78343230;
+
+    #[test]
+    fn test_negotiate_input_format() {
+        let capture_formats = [NV12, BGRA, YUVS];
+        assert_eq!(
+            negotiate_input_format(NV12, &capture_formats, &ENCODER_INPUT_FORMATS),
+            InputPath::Native { format: NV12 }
+        );
+        assert_eq!(
+            negotiate_input_format(YUVS, &capture_formats, &ENCODER_INPUT_FORMATS),
+            InputPath::CaptureConversion {
+                native: YUVS,
+                format: NV12
+            }
+        );
+        let path = negotiate_input_format(NV12, &capture_formats, &[X420]);
+        assert_eq!(
+            path,
+            InputPath::PixelTransfer {
+                capture: NV12,
+                encoder: X420
+            }
+        );
+        assert_eq!(path.to_string(), "pixel transfer converts '420v' to 'x420'");
+    }
+}
@@ -0,0 +1,454 @@
+//! Crash-consistent journal of a streaming pipeline's position.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::cmaf_muxer::{CmafConfig, CmafMuxer, FragmentEmission, MuxerState};
+use super::sink::Segment;
+
+/// One journal record: where the stream was and how it was configured.
+///
+/// Stored in the [`MuxerState`] text format with extra keys, so the journal
+/// file can also be read with [`MuxerState::load`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Muxer position: next sequence number and decode time, parameter sets
+    pub muxer: MuxerState,
+    /// Target fragment duration the muxer was configured with
+    pub fragment_duration_ms: u32,
+    pub emission: FragmentEmission,
+    pub variable_frame_rate: bool,
+    /// Application settings (bitrate, encoder, URL, ...) set with
+    /// [`StreamJournal::set_config`]
+    pub config: BTreeMap<String, String>,
+    /// Sequence number of the last media segment emitted, if any
+    pub last_segment: Option<u32>,
+    /// Total media duration emitted so far
+    pub media_time: Duration,
+    /// Wall clock time the entry was written
+    pub written_at: SystemTime,
+}
+
+impl JournalEntry {
+    fn new(muxer: MuxerState, config: &CmafConfig) -> Self {
+        Self {
+            muxer,
+            fragment_duration_ms: config.fragment_duration_ms,
+            emission: config.emission,
+            variable_frame_rate: config.variable_frame_rate,
+            config: BTreeMap::new(),
+            last_segment: None,
+            media_time: Duration::ZERO,
+            written_at: UNIX_EPOCH,
+        }
+    }
+
+    /// The muxer configuration recorded in the journal.
+    pub fn cmaf_config(&self) -> CmafConfig {
+        CmafConfig {
+            fragment_duration_ms: self.fragment_duration_ms,
+            timescale: self.muxer.timescale,
+            nal_length_size: self.muxer.nal_length_size,
+            emission: self.emission,
+            variable_frame_rate: self.variable_frame_rate,
+            ..Default::default()
+        }
+    }
+
+    /// Parse the text format. Returns the offending line number on error.
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let muxer = MuxerState::parse(text)?;
+        let mut entry = Self::new(muxer, &CmafConfig::default());
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').ok_or(line_no + 1)?;
+            let value = value.trim();
+            let parsed = match key {
+                "fragment_duration_ms" => value
+                    .parse()
+                    .map(|v| entry.fragment_duration_ms = v)
+                    .is_ok(),
+                "emission" => parse_emission(value).map(|v| entry.emission = v).is_some(),
+                "variable_frame_rate" => {
+                    value.parse().map(|v| entry.variable_frame_rate = v).is_ok()
+                }
+                "last_segment" => match value {
+                    "-" => true,
+                    _ => value.parse().map(|v| entry.last_segment = Some(v)).is_ok(),
+                },
+                "media_time_us" => value
+                    .parse()
+                    .map(|v| entry.media_time = Duration::from_micros(v))
+                    .is_ok(),
+                "written_at_ms" => value
+                    .parse()
+                    .map(|v| entry.written_at = UNIX_EPOCH + Duration::from_millis(v))
+                    .is_ok(),
+                _ => match key.strip_prefix("config.") {
+                    Some(name) if !name.is_empty() => {
+                        entry.config.insert(name.to_string(), value.to_string());
+                        true
+                    }
+                    Some(_) => false,
+                    // Muxer state keys, or unknown keys from newer versions
+                    None => true,
+                },
+            };
+            if !parsed {
+                return Err(line_no + 1);
+            }
+        }
+        Ok(entry)
+    }
+
+    /// Load a journal written by [`JournalEntry::save`] or [`StreamJournal`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|line| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid journal at line {}", line),
+            )
+        })
+    }
+
+    /// Write the entry durably: to a temporary file that is synced, then
+    /// renamed over `path`, and the rename synced. After a crash or power
+    /// loss `path` holds either this entry or the previous one.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(self.to_string().as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, path)?;
+        sync_parent(path)
+    }
+}
+
+impl std::fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.muxer)?;
+        writeln!(f, "# Stream journal")?;
+        writeln!(f, "fragment_duration_ms {}", self.fragment_duration_ms)?;
+        writeln!(f, "emission {}", emission_name(self.emission))?;
+        writeln!(f, "variable_frame_rate {}", self.variable_frame_rate)?;
+        match self.last_segment {
+            Some(sequence_number) => writeln!(f, "last_segment {}", sequence_number)?,
+            None => writeln!(f, "last_segment -")?,
+        }
+        writeln!(f, "media_time_us {}", self.media_time.as_micros())?;
+        let written_at = self
+            .written_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        writeln!(f, "written_at_ms {}", written_at.as_millis())?;
+        for (key, value) in &self.config {
+            writeln!(f, "config.{} {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+fn emission_name(emission: FragmentEmission) -> &'static str {
+    match emission {
+        FragmentEmission::PerGop => "per_gop",
+        FragmentEmission::PerMiniGop => "per_mini_gop",
+        FragmentEmission::PerFrame => "per_frame",
+    }
+}
+
+fn parse_emission(name: &str) -> Option<FragmentEmission> {
+    match name {
+        "per_gop" => Some(FragmentEmission::PerGop),
+        "per_mini_gop" => Some(FragmentEmission::PerMiniGop),
+        "per_frame" => Some(FragmentEmission::PerFrame),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Periodically journals a streaming pipeline's configuration and position
+/// so an unattended streamer can continue the same stream after a crash or
+/// power loss.
+///
+/// Call [`record`](Self::record) with each segment the muxer emits; the
+/// journal is rewritten at most once per `interval` (every segment with
+/// [`Duration::ZERO`]). Each write is atomic and synced, so the file always
+/// holds a complete entry. On restart,
+/// [`resume_from_journal`](Self::resume_from_journal) rebuilds the muxer so
+/// sequence numbers and decode times continue where the journal left off.
+///
+/// Segments emitted after the last write are not covered: with an interval
+/// longer than a segment, a restart can repeat up to `interval` worth of
+/// sequence numbers. Players and sinks that key on sequence numbers should
+/// use an interval of zero.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use video_toolbox_sys::helpers::{CmafConfig, CmafMuxer, Segment, StreamJournal};
+///
+/// let path = "/var/lib/streamer/journal";
+/// let (mut journal, mut muxer) = match StreamJournal::resume_from_journal(path, Duration::ZERO) {
+///     Ok(resumed) => resumed,
+///     Err(_) => {
+///         let mut journal = StreamJournal::new(path, Duration::ZERO);
+///         journal.set_config("bitrate", "4000000")?;
+///         (journal, CmafMuxer::new(CmafConfig::default()))
+///     }
+/// };
+/// let bitrate: Option<i64> = journal.config().get("bitrate").and_then(|v| v.parse().ok());
+/// // if let Some(data) = muxer.add_frame(&nal_units, pts, dts, is_keyframe) {
+/// //     let segment = Segment::media(muxer.sequence_number() - 1, data)
+/// //         .with_duration(muxer.last_fragment_duration());
+/// //     journal.record(&muxer, &segment)?;
+/// // }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct StreamJournal {
+    path: PathBuf,
+    interval: Duration,
+    entry: Option<JournalEntry>,
+    config: BTreeMap<String, String>,
+    last_write: Option<Instant>,
+    dirty: bool,
+}
+
+impl StreamJournal {
+    /// A new journal at `path`, written at most once per `interval`.
+    ///
+    /// Nothing is written until the first [`record`](Self::record).
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        Self {
+            path: path.into(),
+            interval,
+            entry: None,
+            config: BTreeMap::new(),
+            last_write: None,
+            dirty: false,
+        }
+    }
+
+    /// Continue the stream recorded in the journal at `path`.
+    ///
+    /// Returns the journal, carrying over the recorded configuration and
+    /// position, and a muxer resumed with [`CmafMuxer::resume`] from the
+    /// recorded state and muxer configuration. Fails if the journal is
+    /// missing or unreadable; start a new stream in that case.
+    pub fn resume_from_journal(
+        path: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> io::Result<(Self, CmafMuxer)> {
+        let path = path.into();
+        let entry = JournalEntry::load(&path)?;
+        let muxer = CmafMuxer::resume(entry.cmaf_config(), &entry.muxer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let journal = Self {
+            path,
+            interval,
+            config: entry.config.clone(),
+            entry: Some(entry),
+            last_write: None,
+            dirty: false,
+        };
+        Ok((journal, muxer))
+    }
+
+    /// Record an application setting, e.g. the encoder bitrate, so it can be
+    /// restored from [`JournalEntry::config`] after a restart.
+    ///
+    /// Written with the next journal write. Fails with
+    /// [`io::ErrorKind::InvalidInput`] if the key is empty or contains
+    /// whitespace, or the value is empty, contains `#` or a line break, or
+    /// starts or ends with whitespace, none of which the text format can
+    /// hold.
+    pub fn set_config(&mut self, key: impl Into<String>, value: impl ToString) -> io::Result<()> {
+        let key = key.into();
+        let value = value.to_string();
+        let invalid = |what| Err(io::Error::new(io::ErrorKind::InvalidInput, what));
+        if key.is_empty() || key.contains(|c: char| c.is_whitespace() || c == '#') {
+            return invalid(format!("invalid journal config key {:?}", key));
+        }
+        if value.is_empty() || value.contains(['#', '\n', '\r']) || value.trim() != value {
+            return invalid(format!("invalid journal config value {:?}", value));
+        }
+        self.config.insert(key, value);
+        if let Some(entry) = &mut self.entry {
+            entry.config = self.config.clone();
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Record a segment emitted by `muxer`, writing the journal if
+    /// `interval` has passed since the last write.
+    ///
+    /// Call it after the segment has been handed to the sink. Returns
+    /// whether the journal was written.
+    pub fn record(&mut self, muxer: &CmafMuxer, segment: &Segment) -> io::Result<bool> {
+        let config = &self.config;
+        let entry = self.entry.get_or_insert_with(|| {
+            let mut entry = JournalEntry::new(muxer.snapshot(), muxer.config());
+            entry.config = config.clone();
+            entry
+        });
+        entry.muxer = muxer.snapshot();
+        if !segment.is_init() {
+            entry.last_segment = Some(segment.sequence_number);
+            entry.media_time += segment.duration.unwrap_or_default();
+        }
+        self.dirty = true;
+
+        let due = self
+            .last_write
+            .is_none_or(|last| last.elapsed() >= self.interval);
+        if !due {
+            return Ok(false);
+        }
+        self.sync()?;
+        Ok(true)
+    }
+
+    /// Write the journal now if anything changed since the last write, e.g.
+    /// before a planned shutdown.
+    pub fn sync(&mut self) -> io::Result<()> {
+        let Some(entry) = &mut self.entry else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        entry.written_at = SystemTime::now();
+        entry.save(&self.path)?;
+        self.last_write = Some(Instant::now());
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Application settings, as recorded by [`set_config`](Self::set_config)
+    /// or restored from the journal.
+    pub fn config(&self) -> &BTreeMap<String, String> {
+        &self.config
+    }
+
+    /// The latest entry, including changes not yet written, or `None` before
+    /// the first segment of a new stream.
+    pub fn entry(&self) -> Option<&JournalEntry> {
+        self.entry.as_ref()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StreamJournal {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn muxer() -> CmafMuxer {
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            fragment_duration_ms: 1000,
+            emission: FragmentEmission::PerMiniGop,
+            ..Default::default()
+        });
+        let sps = vec![0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50];
+        let pps = vec![0x68, 0xee, 0x3c, 0x80];
+        muxer.create_init_segment(&sps, &pps, 1280, 720);
+        muxer
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let mut entry = JournalEntry::new(muxer().snapshot(), &CmafConfig::default());
+        entry.emission = FragmentEmission::PerFrame;
+        entry.last_segment = Some(42);
+        entry.media_time = Duration::from_micros(84_084_000);
+        entry.written_at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        entry.config.insert("bitrate".into(), "4000000".into());
+        entry
+            .config
+            .insert("url".into(), "https://relay.example/live".into());
+
+        let text = entry.to_string();
+        assert_eq!(JournalEntry::parse(&text), Ok(entry.clone()));
+        // Still readable as a plain muxer state
+        assert_eq!(MuxerState::parse(&text), Ok(entry.muxer));
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_values() {
+        assert_eq!(JournalEntry::parse("emission sometimes\n").unwrap_err(), 1);
+        assert_eq!(
+            JournalEntry::parse("timescale 90000\nlast_segment x\n").unwrap_err(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_record_interval_and_resume() {
+        let path = std::env::temp_dir().join(format!("vt-journal-{}", std::process::id()));
+        let muxer = muxer();
+        let mut journal = StreamJournal::new(&path, Duration::from_secs(3600));
+        journal.set_config("bitrate", 4_000_000).unwrap();
+        for (key, value) in [
+            ("", "1"),
+            ("a b", "1"),
+            ("url", "a#b"),
+            ("url", "a\nb"),
+            ("url", " a"),
+        ] {
+            let err = journal.set_config(key, value).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let media = |seq| Segment::media(seq, vec![0; 4]).with_duration(Duration::from_secs(1));
+        assert!(journal.record(&muxer, &Segment::init(vec![0; 4])).unwrap());
+        // Within the interval: kept in memory only
+        assert!(!journal.record(&muxer, &media(1)).unwrap());
+        assert_eq!(JournalEntry::load(&path).unwrap().last_segment, None);
+
+        journal.sync().unwrap();
+        let written = JournalEntry::load(&path).unwrap();
+        assert_eq!(written.last_segment, Some(1));
+        assert_eq!(written.media_time, Duration::from_secs(1));
+        assert_eq!(written.config["bitrate"], "4000000");
+        drop(journal);
+
+        let (journal, resumed) = StreamJournal::resume_from_journal(&path, Duration::ZERO).unwrap();
+        assert!(resumed.is_initialized());
+        assert_eq!(resumed.sequence_number(), muxer.sequence_number());
+        assert_eq!(resumed.config().fragment_duration_ms, 1000);
+        assert_eq!(resumed.config().emission, FragmentEmission::PerMiniGop);
+        assert_eq!(journal.config()["bitrate"], "4000000");
+        fs::remove_file(&path).unwrap();
+    }
+}
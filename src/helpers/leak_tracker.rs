@@ -0,0 +1,307 @@
+//! Retain/release tracking for objects created by the helpers.
+//!
+//! With the `leak-tracking` feature, every pixel buffer returned by
+//! [`create_pixel_buffer`](super::create_pixel_buffer) and every owned
+//! [`CompressionSession`](super::CompressionSession) or
+//! [`DecompressionSession`](super::DecompressionSession) is registered along
+//! with a backtrace of where it was created. Without the feature the API is
+//! still available but nothing is recorded, so code using it builds either way.
+
+use core_foundation_sys::base::{CFRelease, CFRetain};
+use libc::c_void;
+use std::fmt;
+
+use crate::cv_types::CVPixelBufferRef;
+
+/// Kind of a tracked object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackedKind {
+    PixelBuffer,
+    CompressionSession,
+    DecompressionSession,
+}
+
+/// A tracked object that has not been fully released.
+#[derive(Debug, Clone)]
+pub struct LiveObject {
+    pub kind: TrackedKind,
+    /// Address of the CoreFoundation object
+    pub address: usize,
+    /// Outstanding references taken through the helpers
+    pub retain_count: u32,
+    /// Where the object was created
+    pub backtrace: String,
+    id: u64,
+}
+
+impl fmt::Display for LiveObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:?} at {:#x} ({} outstanding reference{}), created at:",
+            self.kind,
+            self.address,
+            self.retain_count,
+            if self.retain_count == 1 { "" } else { "s" }
+        )?;
+        write!(f, "{}", self.backtrace)
+    }
+}
+
+/// Whether the crate was built with the `leak-tracking` feature.
+pub fn leak_tracking_enabled() -> bool {
+    cfg!(feature = "leak-tracking")
+}
+
+/// Tracked objects that are still alive, oldest first.
+pub fn live_objects() -> Vec<LiveObject> {
+    imp::live_objects()
+}
+
+/// Print all live tracked objects to stderr and return them.
+///
+/// Call at teardown, once every helper-created object should be gone.
+pub fn report_leaks() -> Vec<LiveObject> {
+    let leaks = live_objects();
+    print_leaks(&leaks);
+    leaks
+}
+
+fn print_leaks(leaks: &[LiveObject]) {
+    if leaks.is_empty() {
+        return;
+    }
+    eprintln!("video-toolbox-sys: {} leaked object(s)", leaks.len());
+    for leak in leaks {
+        eprintln!("{}", leak);
+    }
+}
+
+/// Take an additional reference to a pixel buffer, recording it if the
+/// buffer is tracked.
+///
+/// # Safety
+///
+/// `pixel_buffer` must be a valid CVPixelBuffer.
+pub unsafe fn retain_pixel_buffer(pixel_buffer: CVPixelBufferRef) -> CVPixelBufferRef {
+    CFRetain(pixel_buffer as *const c_void);
+    imp::retain(pixel_buffer as usize);
+    pixel_buffer
+}
+
+/// Release a reference to a pixel buffer, recording it if the buffer is tracked.
+///
+/// Use this instead of `CFRelease` for buffers from
+/// [`create_pixel_buffer`](super::create_pixel_buffer); buffers released with
+/// `CFRelease` directly are still reported as live.
+///
+/// # Safety
+///
+/// `pixel_buffer` must be a valid CVPixelBuffer owned by the caller.
+pub unsafe fn release_pixel_buffer(pixel_buffer: CVPixelBufferRef) {
+    imp::release(pixel_buffer as usize);
+    CFRelease(pixel_buffer as *const c_void);
+}
+
+/// Record a newly created object holding one reference.
+pub(crate) fn track(kind: TrackedKind, object: *const c_void) {
+    imp::track(kind, object as usize);
+}
+
+/// Record that the last reference to `object` was dropped.
+pub(crate) fn untrack(object: *const c_void) {
+    imp::untrack(object as usize);
+}
+
+/// Scope guard reporting objects created during its lifetime that are still
+/// alive when it is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{
+///     create_pixel_buffer, release_pixel_buffer, LeakCheck, PixelBufferConfig,
+/// };
+///
+/// let check = LeakCheck::new();
+/// let pixel_buffer = create_pixel_buffer(&PixelBufferConfig::new(640, 480)).unwrap();
+/// // ... encode ...
+/// unsafe { release_pixel_buffer(pixel_buffer) };
+/// check.assert_no_leaks();
+/// ```
+#[must_use = "leaks are reported when the LeakCheck is dropped"]
+pub struct LeakCheck {
+    first_id: u64,
+}
+
+impl Default for LeakCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LeakCheck {
+    pub fn new() -> Self {
+        Self {
+            first_id: imp::next_id(),
+        }
+    }
+
+    /// Objects created since this check started that are still alive.
+    pub fn leaks(&self) -> Vec<LiveObject> {
+        let mut leaks = live_objects();
+        leaks.retain(|object| object.id >= self.first_id);
+        leaks
+    }
+
+    /// Panic with creation backtraces if anything created since this check
+    /// started is still alive.
+    pub fn assert_no_leaks(self) {
+        let leaks = self.leaks();
+        std::mem::forget(self);
+        if !leaks.is_empty() {
+            let report: Vec<String> = leaks.iter().map(|leak| leak.to_string()).collect();
+            panic!("{} leaked object(s):\n{}", leaks.len(), report.join("\n"));
+        }
+    }
+}
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        print_leaks(&self.leaks());
+    }
+}
+
+#[cfg(feature = "leak-tracking")]
+mod imp {
+    use std::backtrace::Backtrace;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    use super::{LiveObject, TrackedKind};
+
+    struct Entry {
+        id: u64,
+        kind: TrackedKind,
+        retain_count: u32,
+        backtrace: Backtrace,
+    }
+
+    static LIVE: Mutex<BTreeMap<usize, Entry>> = Mutex::new(BTreeMap::new());
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    fn with_live<R>(f: impl FnOnce(&mut BTreeMap<usize, Entry>) -> R) -> R {
+        let mut live = LIVE.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut live)
+    }
+
+    pub(super) fn next_id() -> u64 {
+        NEXT_ID.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn track(kind: TrackedKind, address: usize) {
+        let entry = Entry {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            kind,
+            retain_count: 1,
+            backtrace: Backtrace::force_capture(),
+        };
+        // A reused address means the previous object was freed without us
+        // seeing it (e.g. a raw CFRelease); the new object replaces it.
+        with_live(|live| live.insert(address, entry));
+    }
+
+    pub(super) fn untrack(address: usize) {
+        with_live(|live| live.remove(&address));
+    }
+
+    pub(super) fn retain(address: usize) {
+        with_live(|live| {
+            if let Some(entry) = live.get_mut(&address) {
+                entry.retain_count += 1;
+            }
+        });
+    }
+
+    pub(super) fn release(address: usize) {
+        with_live(|live| {
+            if let Some(entry) = live.get_mut(&address) {
+                entry.retain_count -= 1;
+                if entry.retain_count == 0 {
+                    live.remove(&address);
+                }
+            }
+        });
+    }
+
+    pub(super) fn live_objects() -> Vec<LiveObject> {
+        let mut objects: Vec<LiveObject> = with_live(|live| {
+            live.iter()
+                .map(|(&address, entry)| LiveObject {
+                    kind: entry.kind,
+                    address,
+                    retain_count: entry.retain_count,
+                    backtrace: entry.backtrace.to_string(),
+                    id: entry.id,
+                })
+                .collect()
+        });
+        objects.sort_by_key(|object| object.id);
+        objects
+    }
+}
+
+#[cfg(not(feature = "leak-tracking"))]
+mod imp {
+    use super::{LiveObject, TrackedKind};
+
+    pub(super) fn next_id() -> u64 {
+        0
+    }
+
+    pub(super) fn track(_kind: TrackedKind, _address: usize) {}
+
+    pub(super) fn untrack(_address: usize) {}
+
+    pub(super) fn retain(_address: usize) {}
+
+    pub(super) fn release(_address: usize) {}
+
+    pub(super) fn live_objects() -> Vec<LiveObject> {
+        Vec::new()
+    }
+}
+
+#[cfg(all(test, feature = "leak-tracking"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retain_release_balance() {
+        let check = LeakCheck::new();
+        let (a, b) = (0x1000usize, 0x2000usize);
+        track(TrackedKind::PixelBuffer, a as *const c_void);
+        track(TrackedKind::CompressionSession, b as *const c_void);
+        imp::retain(a);
+        imp::release(a);
+
+        let leaks = check.leaks();
+        assert_eq!(leaks.len(), 2);
+        assert_eq!(leaks[0].kind, TrackedKind::PixelBuffer);
+        assert_eq!(leaks[0].retain_count, 1);
+        assert!(leaks[0].to_string().contains("PixelBuffer at 0x1000"));
+
+        imp::release(a);
+        let result = std::panic::catch_unwind(|| {
+            LeakCheck {
+                first_id: check.first_id,
+            }
+            .assert_no_leaks()
+        });
+        assert!(result.is_err());
+
+        untrack(b as *const c_void);
+        check.assert_no_leaks();
+    }
+}
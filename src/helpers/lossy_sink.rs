@@ -0,0 +1,352 @@
+//! Seedable segment loss simulation for loss-resilience tests.
+
+use std::io;
+
+use super::sink::{Segment, SegmentSink};
+
+/// Probabilities of each impairment, applied independently per media segment.
+///
+/// The same seed always produces the same impairments for the same input,
+/// so failures found in a test can be replayed exactly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LossModel {
+    /// Probability that a segment is never delivered
+    pub drop: f64,
+    /// Segments dropped in a row once a drop starts (bursty loss); 1 for
+    /// independent drops
+    pub burst_length: u32,
+    /// Probability that a segment is delivered twice
+    pub duplicate: f64,
+    /// Probability that a segment is held back and delivered after the next one
+    pub reorder: f64,
+    /// Probability that a segment loses a random-length tail
+    pub truncate: f64,
+    /// Also impair init segments. Off by default, since a stream without its
+    /// init segment tests nothing but the missing init segment.
+    pub impair_init: bool,
+    /// Random generator seed
+    pub seed: u64,
+}
+
+impl Default for LossModel {
+    fn default() -> Self {
+        Self {
+            drop: 0.0,
+            burst_length: 1,
+            duplicate: 0.0,
+            reorder: 0.0,
+            truncate: 0.0,
+            impair_init: false,
+            seed: 0,
+        }
+    }
+}
+
+/// What a [`LossySink`] did to one segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Impairment {
+    Dropped,
+    Duplicated,
+    /// Held back and delivered after the following segment
+    Reordered,
+    /// Delivered with only the first `len` bytes
+    Truncated {
+        len: usize,
+    },
+}
+
+/// An impairment applied to the segment with `sequence_number`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LossEvent {
+    pub sequence_number: u32,
+    pub is_init: bool,
+    pub impairment: Impairment,
+}
+
+/// Counters for a [`LossySink`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LossStats {
+    /// Segments received from the caller
+    pub received: u64,
+    /// Segments passed to the inner sink, including duplicates
+    pub delivered: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub truncated: u64,
+}
+
+type ImpairmentFn = Box<dyn FnMut(&LossEvent) + Send>;
+
+/// SplitMix64, which is small, fast and good enough for loss simulation.
+pub(super) struct SplitMix64(pub(super) u64);
+
+impl SplitMix64 {
+    pub(super) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub(super) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+}
+
+/// A [`SegmentSink`] wrapper that drops, duplicates, reorders and truncates
+/// segments according to a seeded [`LossModel`].
+///
+/// Put it in front of the sink or transport a player reads from to check
+/// that decoding recovers from loss. Every impairment is recorded in
+/// [`events`](Self::events) and passed to the
+/// [`on_impairment`](Self::on_impairment) hook, which can stand in for the
+/// player's IDR-request feedback, e.g. by calling
+/// [`CompressionSession::force_keyframe`](super::CompressionSession::force_keyframe).
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{DirectorySink, LossModel, LossySink};
+///
+/// let model = LossModel {
+///     drop: 0.05,
+///     reorder: 0.02,
+///     seed: 42,
+///     ..Default::default()
+/// };
+/// let sink = LossySink::new(DirectorySink::new("lossy")?, model)
+///     .on_impairment(|event| println!("{:?}", event));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct LossySink<S> {
+    inner: S,
+    model: LossModel,
+    rng: SplitMix64,
+    burst_remaining: u32,
+    held: Option<Segment>,
+    events: Vec<LossEvent>,
+    stats: LossStats,
+    on_impairment: Option<ImpairmentFn>,
+}
+
+impl<S: SegmentSink> LossySink<S> {
+    pub fn new(inner: S, model: LossModel) -> Self {
+        let rng = SplitMix64(model.seed);
+        Self {
+            inner,
+            model,
+            rng,
+            burst_remaining: 0,
+            held: None,
+            events: Vec::new(),
+            stats: LossStats::default(),
+            on_impairment: None,
+        }
+    }
+
+    /// Call `hook` with each impairment as it is applied.
+    pub fn on_impairment<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&LossEvent) + Send + 'static,
+    {
+        self.on_impairment = Some(Box::new(hook));
+        self
+    }
+
+    pub fn model(&self) -> &LossModel {
+        &self.model
+    }
+
+    /// Every impairment applied so far, in order.
+    pub fn events(&self) -> &[LossEvent] {
+        &self.events
+    }
+
+    pub fn stats(&self) -> &LossStats {
+        &self.stats
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// The inner sink. A segment still held back for reordering is lost.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn record(&mut self, segment: &Segment, impairment: Impairment) {
+        let event = LossEvent {
+            sequence_number: segment.sequence_number,
+            is_init: segment.is_init(),
+            impairment,
+        };
+        match impairment {
+            Impairment::Dropped => self.stats.dropped += 1,
+            Impairment::Duplicated => self.stats.duplicated += 1,
+            Impairment::Reordered => self.stats.reordered += 1,
+            Impairment::Truncated { .. } => self.stats.truncated += 1,
+        }
+        if let Some(hook) = self.on_impairment.as_mut() {
+            hook(&event);
+        }
+        self.events.push(event);
+    }
+
+    fn deliver(&mut self, segment: &Segment) -> io::Result<()> {
+        self.inner.write_segment(segment)?;
+        self.stats.delivered += 1;
+        Ok(())
+    }
+
+    /// Deliver `segment`, then the segment held back before it, if any.
+    fn deliver_with_held(&mut self, segment: &Segment) -> io::Result<()> {
+        self.deliver(segment)?;
+        match self.held.take() {
+            Some(held) => self.deliver(&held),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S: SegmentSink> SegmentSink for LossySink<S> {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        self.stats.received += 1;
+        if segment.is_init() && !self.model.impair_init {
+            return self.deliver_with_held(segment);
+        }
+
+        if self.burst_remaining > 0 || self.rng.chance(self.model.drop) {
+            self.burst_remaining = match self.burst_remaining {
+                0 => self.model.burst_length.max(1) - 1,
+                n => n - 1,
+            };
+            self.record(segment, Impairment::Dropped);
+            return Ok(());
+        }
+
+        let mut segment = segment.clone();
+        if !segment.data.is_empty() && self.rng.chance(self.model.truncate) {
+            let len = (self.rng.next_u64() % segment.data.len() as u64) as usize;
+            segment.data.truncate(len);
+            self.record(&segment, Impairment::Truncated { len });
+        }
+        if self.held.is_none() && self.rng.chance(self.model.reorder) {
+            self.record(&segment, Impairment::Reordered);
+            self.held = Some(segment);
+            return Ok(());
+        }
+        if self.rng.chance(self.model.duplicate) {
+            self.record(&segment, Impairment::Duplicated);
+            self.deliver(&segment)?;
+        }
+        self.deliver_with_held(&segment)
+    }
+
+    /// Deliver any held-back segment, then flush the inner sink.
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(held) = self.held.take() {
+            self.deliver(&held)?;
+        }
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Collect(Vec<Segment>);
+
+    impl SegmentSink for Collect {
+        fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+            self.0.push(segment.clone());
+            Ok(())
+        }
+    }
+
+    fn run(model: LossModel) -> LossySink<Collect> {
+        let mut sink = LossySink::new(Collect::default(), model);
+        sink.write_segment(&Segment::init(vec![0; 8])).unwrap();
+        for seq in 1..=200 {
+            sink.write_segment(&Segment::media(seq, vec![seq as u8; 64]))
+                .unwrap();
+        }
+        sink.flush().unwrap();
+        sink
+    }
+
+    #[test]
+    fn test_same_seed_same_impairments() {
+        let model = LossModel {
+            drop: 0.1,
+            burst_length: 2,
+            duplicate: 0.05,
+            reorder: 0.05,
+            truncate: 0.05,
+            seed: 7,
+            ..Default::default()
+        };
+        let a = run(model.clone());
+        let b = run(model.clone());
+        assert_eq!(a.events(), b.events());
+        assert_eq!(a.get_ref().0, b.get_ref().0);
+        let other = run(LossModel { seed: 8, ..model });
+        assert_ne!(a.events(), other.events());
+
+        let stats = a.stats();
+        assert!(stats.dropped > 0 && stats.duplicated > 0);
+        assert!(stats.reordered > 0 && stats.truncated > 0);
+        assert_eq!(stats.received, 201);
+        assert_eq!(
+            stats.delivered,
+            stats.received - stats.dropped + stats.duplicated
+        );
+        // Drops come in bursts of two
+        assert_eq!(stats.dropped % 2, 0);
+        // The init segment is left alone and still arrives first
+        assert!(a.get_ref().0[0].is_init());
+        assert!(a.events().iter().all(|event| !event.is_init));
+    }
+
+    #[test]
+    fn test_reorder_and_truncate() {
+        let reordered = run(LossModel {
+            reorder: 1.0,
+            seed: 1,
+            ..Default::default()
+        });
+        let order: Vec<u32> = reordered.get_ref().0[1..7]
+            .iter()
+            .map(|segment| segment.sequence_number)
+            .collect();
+        assert_eq!(order, [2, 1, 4, 3, 6, 5]);
+
+        let truncated = run(LossModel {
+            truncate: 1.0,
+            seed: 1,
+            ..Default::default()
+        });
+        for (segment, event) in truncated.get_ref().0[1..].iter().zip(truncated.events()) {
+            assert_eq!(
+                event.impairment,
+                Impairment::Truncated {
+                    len: segment.data.len()
+                }
+            );
+            assert!(segment.data.len() < 64);
+        }
+    }
+}
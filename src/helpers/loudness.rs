@@ -0,0 +1,533 @@
+//! EBU R128 loudness measurement and MP4 loudness metadata.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use super::audio_resampler::{decode_samples, AudioFormat};
+use super::channel_layout::ChannelLayout;
+use super::metadata::{add_to_size, child_boxes, invalid, update_moov};
+use super::mfra::{find_box, BoxRange};
+
+/// Absolute gate for integrated loudness, in LUFS
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Relative gate below the absolute-gated loudness, in LU
+const RELATIVE_GATE: f64 = -10.0;
+/// Oversampling factor of the true-peak interpolator
+const OVERSAMPLING: usize = 4;
+/// Input samples per interpolator phase
+const INTERPOLATOR_TAPS: usize = 12;
+
+/// Loudness in LUFS of a mean square (K-weighted and channel-weighted).
+fn to_lufs(power: f64) -> f64 {
+    if power <= 0.0 {
+        f64::NEG_INFINITY
+    } else {
+        -0.691 + 10.0 * power.log10()
+    }
+}
+
+fn from_lufs(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+fn to_db(level: f64) -> f64 {
+    if level <= 0.0 {
+        f64::NEG_INFINITY
+    } else {
+        20.0 * level.log10()
+    }
+}
+
+/// Biquad in transposed direct form II.
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two stages of the ITU-R BS.1770 K-weighting filter, derived from
+/// their analog prototypes so that any sample rate gets the same response
+/// as the 48 kHz coefficients in the standard.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    // High shelf modelling the acoustic effect of the head
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    // RLB high-pass
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+    [shelf, high_pass]
+}
+
+/// Polyphase coefficients interpolating `OVERSAMPLING - 1` points between
+/// samples with a Hann-windowed sinc, each phase normalized to unity gain.
+fn interpolator() -> Vec<[f64; INTERPOLATOR_TAPS]> {
+    let half = (INTERPOLATOR_TAPS / 2) as f64;
+    (1..OVERSAMPLING)
+        .map(|phase| {
+            let mut taps = [0.0; INTERPOLATOR_TAPS];
+            for (j, tap) in taps.iter_mut().enumerate() {
+                let u = j as f64 - half + phase as f64 / OVERSAMPLING as f64;
+                let sinc = (PI * u).sin() / (PI * u);
+                *tap = sinc * 0.5 * (1.0 + (PI * u / half).cos());
+            }
+            let sum: f64 = taps.iter().sum();
+            taps.iter_mut().for_each(|tap| *tap /= sum);
+            taps
+        })
+        .collect()
+}
+
+/// BS.1770 channel weights: surround channels +1.5 dB, LFE excluded.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    match ChannelLayout::from_channel_count(channels as u32) {
+        Some(ChannelLayout::Surround51) => vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+        _ => vec![1.0; channels],
+    }
+}
+
+/// Loudness and peak values of a whole recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessMeasurement {
+    /// Gated integrated loudness (EBU R128), negative infinity for silence
+    pub integrated_lufs: f64,
+    /// Highest 4x-oversampled sample level, in dBTP
+    pub true_peak_dbtp: f64,
+    /// Highest sample level, in dBFS
+    pub sample_peak_dbfs: f64,
+    /// Measured audio duration
+    pub duration: Duration,
+}
+
+impl LoudnessMeasurement {
+    /// ISO/IEC 14496-12 `ludt` (LoudnessBox) holding a `tlou` with these
+    /// values, for the `udta` of an audio track.
+    ///
+    /// Peaks and program loudness use the ISO/IEC 23003-4 encodings
+    /// (1/32 dB peak steps from +20 dB, 1/4 LU loudness steps from
+    /// -57.75 LUFS), clamped to their ranges; silence stores no loudness.
+    pub fn ludt_box(&self) -> Vec<u8> {
+        const EBU_R128: u8 = 1;
+        const ACCURATE: u8 = 3;
+        const PROGRAM_LOUDNESS: u8 = 1;
+
+        let peak = |db: f64| {
+            if db.is_nan() {
+                0 // not present
+            } else {
+                ((20.0 - db) * 32.0).round().clamp(1.0, 4095.0) as u32
+            }
+        };
+        let mut tlou = vec![0, 0, 0, 0]; // version + flags
+        tlou.extend_from_slice(&[0, 0]); // downmix_ID, DRC_set_ID: the base layout
+        let peaks = (peak(self.sample_peak_dbfs) << 12) | peak(self.true_peak_dbtp);
+        tlou.extend_from_slice(&peaks.to_be_bytes()[1..]);
+        tlou.push((EBU_R128 << 4) | ACCURATE); // true peak measurement system
+        if self.integrated_lufs.is_finite() {
+            let value = ((self.integrated_lufs + 57.75) * 4.0)
+                .round()
+                .clamp(0.0, 255.0);
+            tlou.push(1); // measurement_count
+            tlou.extend_from_slice(&[PROGRAM_LOUDNESS, value as u8, (EBU_R128 << 4) | ACCURATE]);
+        } else {
+            tlou.push(0);
+        }
+
+        let ludt_size = 16 + tlou.len();
+        let mut ludt = Vec::with_capacity(ludt_size);
+        ludt.extend_from_slice(&(ludt_size as u32).to_be_bytes());
+        ludt.extend_from_slice(b"ludt");
+        ludt.extend_from_slice(&((8 + tlou.len()) as u32).to_be_bytes());
+        ludt.extend_from_slice(b"tlou");
+        ludt.extend_from_slice(&tlou);
+        ludt
+    }
+}
+
+/// Measures integrated loudness and true peak of interleaved PCM as in
+/// EBU R128 / ITU-R BS.1770-4.
+///
+/// Audio is K-weighted and its power collected in 400 ms blocks overlapping
+/// by 75%. The integrated loudness gates out blocks below -70 LUFS and then
+/// blocks more than 10 LU below the remaining average, so pauses do not
+/// lower it. True peak is measured on a 4x oversampled signal. Memory grows
+/// by one `f64` per 100 ms of audio.
+///
+/// Mono, stereo and 5.1 (`L R C LFE Ls Rs`) are weighted per the standard;
+/// other channel counts weight every channel equally.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{
+///     write_loudness_metadata, AudioFormat, LoudnessMeter, SampleFormat,
+/// };
+///
+/// let mut meter = LoudnessMeter::new(AudioFormat::new(48_000.0, 2, SampleFormat::F32));
+/// # let pcm = [0u8; 0];
+/// meter.process(&pcm);
+/// let measurement = meter.measurement();
+/// println!(
+///     "{:.1} LUFS, {:.1} dBTP",
+///     measurement.integrated_lufs, measurement.true_peak_dbtp
+/// );
+/// write_loudness_metadata("recording.mp4", &measurement)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    format: AudioFormat,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    interpolator: Vec<[f64; INTERPOLATOR_TAPS]>,
+    /// Last input samples per channel, most recent at `history_pos`
+    history: Vec<[f64; INTERPOLATOR_TAPS]>,
+    history_pos: usize,
+    /// Frames per 100 ms sub-block
+    subblock_frames: usize,
+    subblock_filled: usize,
+    subblock_sums: Vec<f64>,
+    /// Weighted powers of the last four sub-blocks
+    recent: VecDeque<f64>,
+    /// Weighted power of every 400 ms block so far
+    blocks: Vec<f64>,
+    sample_peak: f64,
+    true_peak: f64,
+    frames: u64,
+}
+
+impl LoudnessMeter {
+    /// Create a meter for interleaved PCM in `format`.
+    pub fn new(format: AudioFormat) -> Self {
+        let channels = format.channels.max(1) as usize;
+        Self {
+            format,
+            weights: channel_weights(channels),
+            filters: vec![k_weighting(format.sample_rate); channels],
+            interpolator: interpolator(),
+            history: vec![[0.0; INTERPOLATOR_TAPS]; channels],
+            history_pos: 0,
+            subblock_frames: ((format.sample_rate / 10.0).round() as usize).max(1),
+            subblock_filled: 0,
+            subblock_sums: vec![0.0; channels],
+            recent: VecDeque::with_capacity(4),
+            blocks: Vec::new(),
+            sample_peak: 0.0,
+            true_peak: 0.0,
+            frames: 0,
+        }
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Number of audio frames measured.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Measure a buffer of interleaved PCM in the meter's format.
+    pub fn process(&mut self, pcm: &[u8]) {
+        let samples = decode_samples(pcm, self.format.sample_format);
+        self.process_samples(&samples);
+    }
+
+    /// Measure interleaved `f32` samples.
+    pub fn process_samples(&mut self, samples: &[f32]) {
+        let channels = self.weights.len();
+        for frame in samples.chunks_exact(channels) {
+            self.history_pos = (self.history_pos + 1) % INTERPOLATOR_TAPS;
+            for (ch, &sample) in frame.iter().enumerate() {
+                let x = sample as f64;
+                self.sample_peak = self.sample_peak.max(x.abs());
+                self.history[ch][self.history_pos] = x;
+                for taps in &self.interpolator {
+                    let mut y = 0.0;
+                    for (j, tap) in taps.iter().enumerate() {
+                        let at = (self.history_pos + INTERPOLATOR_TAPS - j) % INTERPOLATOR_TAPS;
+                        y += tap * self.history[ch][at];
+                    }
+                    self.true_peak = self.true_peak.max(y.abs());
+                }
+
+                let [shelf, high_pass] = &mut self.filters[ch];
+                let weighted = high_pass.process(shelf.process(x));
+                self.subblock_sums[ch] += weighted * weighted;
+            }
+
+            self.frames += 1;
+            self.subblock_filled += 1;
+            if self.subblock_filled == self.subblock_frames {
+                self.finish_subblock();
+            }
+        }
+    }
+
+    fn finish_subblock(&mut self) {
+        let power: f64 = self
+            .subblock_sums
+            .iter()
+            .zip(&self.weights)
+            .map(|(sum, weight)| weight * sum / self.subblock_frames as f64)
+            .sum();
+        self.subblock_sums.iter_mut().for_each(|sum| *sum = 0.0);
+        self.subblock_filled = 0;
+
+        if self.recent.len() == 4 {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(power);
+        if self.recent.len() == 4 {
+            self.blocks.push(self.recent.iter().sum::<f64>() / 4.0);
+        }
+    }
+
+    /// Loudness of the last complete 400 ms block, in LUFS.
+    pub fn momentary_lufs(&self) -> f64 {
+        to_lufs(self.blocks.last().copied().unwrap_or(0.0))
+    }
+
+    /// Gated integrated loudness of everything measured, in LUFS.
+    pub fn integrated_lufs(&self) -> f64 {
+        let absolute = from_lufs(ABSOLUTE_GATE);
+        let mean_above = |threshold: f64| {
+            let (sum, count) = self
+                .blocks
+                .iter()
+                .filter(|&&power| power > threshold)
+                .fold((0.0, 0usize), |(sum, count), power| {
+                    (sum + power, count + 1)
+                });
+            (count > 0).then(|| sum / count as f64)
+        };
+        let Some(ungated) = mean_above(absolute) else {
+            return f64::NEG_INFINITY;
+        };
+        let relative = from_lufs(to_lufs(ungated) + RELATIVE_GATE);
+        to_lufs(mean_above(absolute.max(relative)).unwrap_or(0.0))
+    }
+
+    /// Highest true-peak level so far, in dBTP.
+    pub fn true_peak_dbtp(&self) -> f64 {
+        to_db(self.true_peak.max(self.sample_peak))
+    }
+
+    /// Highest sample level so far, in dBFS.
+    pub fn sample_peak_dbfs(&self) -> f64 {
+        to_db(self.sample_peak)
+    }
+
+    pub fn measurement(&self) -> LoudnessMeasurement {
+        LoudnessMeasurement {
+            integrated_lufs: self.integrated_lufs(),
+            true_peak_dbtp: self.true_peak_dbtp(),
+            sample_peak_dbfs: self.sample_peak_dbfs(),
+            duration: Duration::from_secs_f64(self.frames as f64 / self.format.sample_rate),
+        }
+    }
+
+    /// Discard all measurements, e.g. between recordings.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.format);
+    }
+}
+fn is_audio_track(moov: &[u8], trak: BoxRange) -> bool {
+    find_box(moov, trak.content(), trak.end(), b"mdia")
+        .and_then(|mdia| find_box(moov, mdia.content(), mdia.end(), b"hdlr"))
+        .is_some_and(|hdlr| moov.get(hdlr.content() + 8..hdlr.content() + 12) == Some(&b"soun"[..]))
+}
+
+/// Put `ludt` in the first audio track's `udta`, replacing an earlier one.
+fn insert_track_loudness(moov: &mut Vec<u8>, ludt: &[u8]) -> Option<()> {
+    let root = find_box(moov, 0, moov.len(), b"moov")?;
+    let trak = child_boxes(moov, root, b"trak")
+        .into_iter()
+        .find(|&trak| is_audio_track(moov, trak))?;
+
+    match find_box(moov, trak.content(), trak.end(), b"udta") {
+        Some(udta) => {
+            let mut end = udta.end();
+            let mut delta = ludt.len() as isize;
+            if let Some(old) = find_box(moov, udta.content(), udta.end(), b"ludt") {
+                moov.drain(old.offset..old.end());
+                end -= old.size;
+                delta -= old.size as isize;
+            }
+            moov.splice(end..end, ludt.iter().copied());
+            for range in [root, trak, udta] {
+                add_to_size(moov, range, delta);
+            }
+        }
+        None => {
+            let mut udta = Vec::with_capacity(8 + ludt.len());
+            udta.extend_from_slice(&((8 + ludt.len()) as u32).to_be_bytes());
+            udta.extend_from_slice(b"udta");
+            udta.extend_from_slice(ludt);
+            let delta = udta.len() as isize;
+            moov.splice(trak.end()..trak.end(), udta);
+            for range in [root, trak] {
+                add_to_size(moov, range, delta);
+            }
+        }
+    }
+    Some(())
+}
+
+/// Store `measurement` in the first audio track of an MP4 (or MOV) file as
+/// an ISO/IEC 14496-12 loudness box (`trak/udta/ludt/tlou`), replacing any
+/// earlier one. See [`write_mp4_metadata`](super::write_mp4_metadata) for
+/// how the file is updated.
+pub fn write_loudness_metadata(
+    path: impl AsRef<Path>,
+    measurement: &LoudnessMeasurement,
+) -> io::Result<()> {
+    let ludt = measurement.ludt_box();
+    update_moov(path.as_ref(), |moov| {
+        insert_track_loudness(moov, &ludt).ok_or_else(|| invalid("no audio track"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::audio_resampler::SampleFormat;
+    use super::super::mfra::read_u32;
+    use super::*;
+    use std::fs;
+
+    fn sine(frequency: f64, amplitude: f64, phase: f64, seconds: f64) -> Vec<f32> {
+        let frames = (48_000.0 * seconds) as usize;
+        (0..frames)
+            .flat_map(|n| {
+                let t = n as f64 / 48_000.0;
+                let v = (amplitude * (2.0 * PI * frequency * t + phase).sin()) as f32;
+                [v, v]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_integrated_loudness_and_gating() {
+        // EBU Tech 3341 case 1: stereo 1 kHz sine at -23 dBFS reads -23 LUFS
+        let format = AudioFormat::new(48_000.0, 2, SampleFormat::F32);
+        let mut meter = LoudnessMeter::new(format);
+        meter.process_samples(&sine(1000.0, 10f64.powf(-23.0 / 20.0), 0.0, 5.0));
+        assert!((meter.integrated_lufs() + 23.0).abs() < 0.1);
+        assert!((meter.momentary_lufs() + 23.0).abs() < 0.1);
+
+        // Silence is gated out rather than averaged in; only the few blocks
+        // overlapping the transition lower the result
+        meter.process_samples(&vec![0.0; 48_000 * 2 * 5]);
+        assert!((meter.integrated_lufs() + 23.0).abs() < 0.2);
+        assert_eq!(meter.measurement().duration, Duration::from_secs(10));
+
+        meter.reset();
+        meter.process_samples(&vec![0.0; 48_000 * 2]);
+        assert_eq!(meter.integrated_lufs(), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_true_peak_between_samples() {
+        // fs/4 sine sampled at +-45 degrees: samples reach -3 dB, the wave 0 dB
+        let mut meter = LoudnessMeter::new(AudioFormat::new(48_000.0, 2, SampleFormat::F32));
+        meter.process_samples(&sine(12_000.0, 1.0, PI / 4.0, 0.5));
+        assert!((meter.sample_peak_dbfs() + 3.01).abs() < 0.01);
+        assert!(meter.true_peak_dbtp().abs() < 0.2);
+
+        let measurement = LoudnessMeasurement {
+            integrated_lufs: -23.0,
+            true_peak_dbtp: -1.0,
+            sample_peak_dbfs: -3.0,
+            duration: Duration::from_secs(1),
+        };
+        let ludt = measurement.ludt_box();
+        assert_eq!(&ludt[4..8], b"ludt");
+        assert_eq!(&ludt[12..16], b"tlou");
+        // Peaks as 20 - n/32 dB: 736 and 672
+        assert_eq!(&ludt[22..25], &[0x2e, 0x02, 0xa0]);
+        // Program loudness as -57.75 + n/4 LUFS
+        assert_eq!(&ludt[26..30], &[1, 1, 139, 0x13]);
+    }
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((8 + payload.len()) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_write_metadata_shifts_chunk_offsets() {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend_from_slice(b"soun");
+        hdlr.extend_from_slice(&[0; 13]);
+        let build = |chunk_offset: u32| {
+            let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco.extend_from_slice(&chunk_offset.to_be_bytes());
+            let stbl = mp4_box(b"stbl", &mp4_box(b"stco", &stco));
+            let minf = mp4_box(b"minf", &stbl);
+            let mdia = mp4_box(b"mdia", &[mp4_box(b"hdlr", &hdlr), minf].concat());
+            mp4_box(b"moov", &mp4_box(b"trak", &mdia))
+        };
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let moov_len = build(0).len();
+        let mdat_at = (ftyp.len() + moov_len + 8) as u32;
+        let file = [ftyp.clone(), build(mdat_at), mp4_box(b"mdat", b"audio")].concat();
+
+        let path = std::env::temp_dir().join(format!("vt-loudness-{}.mp4", std::process::id()));
+        fs::write(&path, &file).unwrap();
+        let measurement = LoudnessMeasurement {
+            integrated_lufs: -16.0,
+            true_peak_dbtp: -1.0,
+            sample_peak_dbfs: -1.5,
+            duration: Duration::from_secs(1),
+        };
+        write_loudness_metadata(&path, &measurement).unwrap();
+        // A second write replaces the first measurement
+        write_loudness_metadata(&path, &measurement).unwrap();
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let ludt = measurement.ludt_box();
+        assert_eq!(written.len(), file.len() + 8 + ludt.len());
+        let moov = find_box(&written, 0, written.len(), b"moov").unwrap();
+        let trak = find_box(&written, moov.content(), moov.end(), b"trak").unwrap();
+        let udta = find_box(&written, trak.content(), trak.end(), b"udta").unwrap();
+        assert_eq!(&written[udta.content()..udta.end()], &ludt[..]);
+        // The chunk offset still points at the mdat payload
+        let stco_entry = udta.offset - 4;
+        let offset = read_u32(&written, stco_entry).unwrap() as usize;
+        assert_eq!(&written[offset..offset + 5], b"audio");
+    }
+}
@@ -0,0 +1,294 @@
+//! Low-latency live streaming: per-frame CMAF chunks with periodic join points.
+
+use core_media_sys::CMSampleBufferRef;
+use std::time::Duration;
+
+use super::cmaf_muxer::{CmafConfig, CmafMuxer, FragmentEmission};
+use super::compression_builder::CompressionSessionConfig;
+use super::nal_extractor::{NalError, NalExtractor};
+use super::sink::Segment;
+use super::source::MediaFrame;
+
+/// Settings for [`LowLatencyMuxer`].
+#[derive(Debug, Clone)]
+pub struct LowLatencyConfig {
+    /// Time between keyframes, i.e. how long a new viewer may wait for a
+    /// join point. Latency does not depend on it.
+    pub keyframe_interval: Duration,
+    /// Prepend the init segment to every chunk that starts with a keyframe,
+    /// so viewers can join from any keyframe without a separate request
+    pub repeat_init: bool,
+    /// Media timescale of the output
+    pub timescale: u32,
+}
+
+impl Default for LowLatencyConfig {
+    fn default() -> Self {
+        Self {
+            keyframe_interval: Duration::from_secs(2),
+            repeat_init: true,
+            timescale: 90000,
+        }
+    }
+}
+
+/// One frame, muxed as a CMAF chunk (`styp` + `moof` + `mdat`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LowLatencyChunk {
+    /// Init segment for viewers joining here; set on the first chunk and,
+    /// with [`LowLatencyConfig::repeat_init`], on every keyframe chunk
+    pub init_segment: Option<Vec<u8>>,
+    pub fragment: Vec<u8>,
+    pub sequence_number: u32,
+    pub is_keyframe: bool,
+    pub duration: Duration,
+}
+
+impl LowLatencyChunk {
+    /// The chunk as sent on the wire: the init segment, if any, then the
+    /// fragment.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.init_segment.clone().unwrap_or_default();
+        data.extend_from_slice(&self.fragment);
+        data
+    }
+
+    /// The fragment as a media [`Segment`] for a [`SegmentSink`](super::SegmentSink).
+    pub fn to_segment(&self) -> Segment {
+        Segment::media(self.sequence_number, self.fragment.clone()).with_duration(self.duration)
+    }
+}
+
+/// Encoder-to-wire glue for sub-frame-latency streaming.
+///
+/// Every encoded frame is muxed into its own fragment as soon as it arrives
+/// (see [`FragmentEmission::PerFrame`]), so latency is one frame no
+/// matter how far apart keyframes are. P-frame chunks stay small; only
+/// keyframe chunks are join points, and they carry the init segment so a
+/// viewer can start from any of them.
+///
+/// VideoToolbox has no public intra-refresh setting, so join points come
+/// from regular keyframes every [`LowLatencyConfig::keyframe_interval`].
+/// Call [`CompressionSession::force_keyframe`](super::CompressionSession::force_keyframe)
+/// when a viewer joins to give them one immediately.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Mutex;
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{
+///     CompressionSessionBuilder, CompressionSessionConfig, EncodeOutput, LowLatencyConfig,
+///     LowLatencyMuxer,
+/// };
+///
+/// let muxer = LowLatencyMuxer::new(LowLatencyConfig::default());
+/// let mut config = CompressionSessionConfig::new(1280, 720, codecs::video::H264);
+/// config.frame_rate = Some(30.0);
+/// muxer.configure(&mut config);
+/// let muxer = Mutex::new(muxer);
+/// let session = CompressionSessionBuilder::from_config(config)
+///     .build_session(move |output| {
+///         if let EncodeOutput::Frame { sample_buffer, .. } = output {
+///             let mut muxer = muxer.lock().unwrap();
+///             if let Ok(Some(chunk)) = unsafe { muxer.push_sample_buffer(sample_buffer) } {
+///                 // send chunk.to_bytes()
+///             }
+///         }
+///     })
+///     .unwrap();
+/// ```
+pub struct LowLatencyMuxer {
+    config: LowLatencyConfig,
+    muxer: CmafMuxer,
+    extractor: NalExtractor,
+    init_segment: Option<Vec<u8>>,
+    init_sent: bool,
+}
+
+impl LowLatencyMuxer {
+    pub fn new(config: LowLatencyConfig) -> Self {
+        let muxer = CmafMuxer::new(CmafConfig {
+            timescale: config.timescale.max(1),
+            emission: FragmentEmission::PerFrame,
+            ..Default::default()
+        });
+        Self {
+            config,
+            muxer,
+            extractor: NalExtractor::new(),
+            init_segment: None,
+            init_sent: false,
+        }
+    }
+
+    pub fn config(&self) -> &LowLatencyConfig {
+        &self.config
+    }
+
+    /// Low-latency encoder settings.
+    ///
+    /// - low-latency rate control and real-time encoding are enabled
+    /// - frame reordering is disabled, as B-frames cannot be split into
+    ///   per-frame fragments
+    /// - the keyframe interval is set from
+    ///   [`keyframe_interval`](LowLatencyConfig::keyframe_interval) and the
+    ///   frame rate (30 fps if unset), unless already configured
+    pub fn configure(&self, config: &mut CompressionSessionConfig) {
+        config.low_latency = true;
+        config.real_time = true;
+        config.allow_frame_reordering = Some(false);
+        let fps = config.frame_rate.unwrap_or(30.0);
+        let frames = (self.config.keyframe_interval.as_secs_f64() * fps).round() as i32;
+        config.keyframe_interval.get_or_insert(frames.max(1));
+    }
+
+    /// The init segment, once the parameter sets are known.
+    pub fn init_segment(&self) -> Option<&[u8]> {
+        self.init_segment.as_deref()
+    }
+
+    /// Set the stream's parameter sets; later calls are ignored.
+    pub fn set_parameter_sets(&mut self, sps: &[u8], pps: &[u8], width: u32, height: u32) {
+        if self.init_segment.is_none() {
+            self.init_segment = Some(self.muxer.create_init_segment(sps, pps, width, height));
+        }
+    }
+
+    /// Mux an encoded frame from the encoder callback, taking the parameter
+    /// sets from its format description, or from the frame itself when the
+    /// encoder sends them in-band, the first time.
+    ///
+    /// # Safety
+    ///
+    /// `sample_buffer` must be a valid encoded H.264 sample buffer.
+    pub unsafe fn push_sample_buffer(
+        &mut self,
+        sample_buffer: CMSampleBufferRef,
+    ) -> Result<Option<LowLatencyChunk>, NalError> {
+        if sample_buffer.is_null() {
+            return Ok(None);
+        }
+        if self.init_segment.is_none() {
+            let format_desc = self
+                .extractor
+                .get_format_description(sample_buffer)
+                .ok_or(NalError::NoFormatDescription)?;
+            let (params, _) = self.extractor.resolve_parameter_sets(sample_buffer)?;
+            let dims = self.extractor.get_dimensions(format_desc)?;
+            self.set_parameter_sets(&params.sps, &params.pps, dims.width, dims.height);
+        }
+        let frame = MediaFrame {
+            nal_units: self.extractor.extract_nal_units(sample_buffer)?,
+            timing: self.extractor.get_timing(sample_buffer),
+            is_keyframe: self.extractor.is_keyframe(sample_buffer),
+            motion_score: None,
+        };
+        self.push_frame(&frame)
+    }
+
+    /// Mux an encoded frame.
+    ///
+    /// Returns `None` before [`set_parameter_sets`](Self::set_parameter_sets)
+    /// and until the first keyframe, which is the first chunk a viewer can
+    /// decode.
+    pub fn push_frame(&mut self, frame: &MediaFrame) -> Result<Option<LowLatencyChunk>, NalError> {
+        if self.init_segment.is_none() || (!self.init_sent && !frame.is_keyframe) {
+            return Ok(None);
+        }
+        let timing = frame.timing;
+        let source = timing.timescale.max(1) as i64;
+        let target = self.config.timescale.max(1) as i64;
+        let rescale = |value: i64| (value as i128 * target as i128 / source as i128) as i64;
+
+        let sequence_number = self.muxer.sequence_number();
+        let fragment = match self.muxer.try_add_frame(
+            &frame.nal_units,
+            rescale(timing.pts),
+            rescale(timing.dts),
+            rescale(timing.duration) as u32,
+            frame.is_keyframe,
+        )? {
+            Some(fragment) => fragment,
+            None => return Ok(None),
+        };
+
+        let with_init = !self.init_sent || (frame.is_keyframe && self.config.repeat_init);
+        self.init_sent = true;
+        Ok(Some(LowLatencyChunk {
+            init_segment: with_init.then(|| self.init_segment.clone()).flatten(),
+            fragment,
+            sequence_number,
+            is_keyframe: frame.is_keyframe,
+            duration: self.muxer.last_fragment_duration(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs;
+    use crate::helpers::{NalUnit, SampleTiming};
+
+    fn frame(index: i64, is_keyframe: bool) -> MediaFrame {
+        let nal_type = if is_keyframe { 5 } else { 1 };
+        MediaFrame {
+            nal_units: vec![NalUnit {
+                nal_type,
+                data: vec![0x60 | nal_type, 0x88, index as u8],
+            }],
+            timing: SampleTiming {
+                pts: index * 1000,
+                dts: index * 1000,
+                duration: 1000,
+                timescale: 30000,
+            },
+            is_keyframe,
+            motion_score: None,
+        }
+    }
+
+    #[test]
+    fn test_chunk_per_frame_with_join_points() {
+        let mut muxer = LowLatencyMuxer::new(LowLatencyConfig::default());
+        assert_eq!(muxer.push_frame(&frame(0, true)).unwrap(), None);
+        muxer.set_parameter_sets(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 1280, 720);
+        // Frames before the first keyframe cannot be decoded
+        assert_eq!(muxer.push_frame(&frame(1, false)).unwrap(), None);
+
+        let chunks: Vec<LowLatencyChunk> = (2..8)
+            .map(|i| muxer.push_frame(&frame(i, i % 3 == 2)).unwrap().unwrap())
+            .collect();
+        let init = muxer.init_segment().unwrap();
+        let keyframes: Vec<bool> = chunks.iter().map(|chunk| chunk.is_keyframe).collect();
+        assert_eq!(keyframes, [true, false, false, true, false, false]);
+        for chunk in &chunks {
+            assert_eq!(chunk.init_segment.is_some(), chunk.is_keyframe);
+            assert_eq!(chunk.duration, Duration::from_secs_f64(3000.0 / 90000.0));
+        }
+        assert!(chunks[3].to_bytes().starts_with(init));
+        assert_eq!(chunks[1].to_bytes(), chunks[1].fragment);
+        assert_eq!(chunks[5].to_segment().sequence_number, 6);
+
+        // P-frame chunks keep their non-sync flags
+        let trun = chunks[1]
+            .fragment
+            .windows(4)
+            .position(|w| w == b"trun")
+            .unwrap();
+        let flags = &chunks[1].fragment[trun + 24..trun + 28];
+        assert_eq!(flags, &0x01010000u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_configure() {
+        let muxer = LowLatencyMuxer::new(LowLatencyConfig::default());
+        let mut config = CompressionSessionConfig::new(1280, 720, codecs::video::H264);
+        config.frame_rate = Some(60.0);
+        muxer.configure(&mut config);
+        assert!(config.low_latency && config.real_time);
+        assert_eq!(config.keyframe_interval, Some(120));
+        assert_eq!(config.allow_frame_reordering, Some(false));
+    }
+}
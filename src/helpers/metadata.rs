@@ -0,0 +1,549 @@
+//! MP4 metadata: title, date, location, encoder and custom tags.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::audio_cmaf::write_box;
+use super::dash::xs_date_time;
+use super::mfra::{find_box, read_u32, read_u64, BoxRange};
+
+/// Seconds from the MP4 epoch (1904-01-01) to the Unix epoch
+const MP4_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+/// Where a recording was made (WGS 84).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Meters above sea level
+    pub altitude: Option<f64>,
+}
+
+impl Location {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Self {
+            latitude,
+            longitude,
+            altitude: None,
+        }
+    }
+
+    pub fn altitude(mut self, meters: f64) -> Self {
+        self.altitude = Some(meters);
+        self
+    }
+
+    /// ISO 6709 form used by QuickTime and ffmpeg, e.g.
+    /// `+37.3318-122.0312+045.000/`.
+    pub fn iso6709(&self) -> String {
+        let mut text = format!("{:+08.4}{:+09.4}", self.latitude, self.longitude);
+        if let Some(altitude) = self.altitude {
+            text.push_str(&format!("{:+08.3}", altitude));
+        }
+        text.push('/');
+        text
+    }
+}
+
+/// Descriptive metadata for an MP4 file, written as an iTunes-style
+/// `udta/meta/ilst` box, which QuickTime, ffmpeg and most players read.
+///
+/// Title, creation date, location and encoder map to the standard `©nam`,
+/// `©day`, `©xyz` and `©too` items; [`tag`](Self::tag) adds freeform
+/// (`----`) items in the `com.apple.iTunes` namespace, which ffmpeg shows
+/// under their plain key.
+///
+/// Pass it to [`CmafMuxer::set_metadata`](super::CmafMuxer::set_metadata),
+/// [`AudioCmafMuxer::set_metadata`](super::AudioCmafMuxer::set_metadata) or
+/// [`Fmp4Recorder::metadata`](super::Fmp4Recorder::metadata) to put it in
+/// the init segment, or to [`write_mp4_metadata`] for a finished file such
+/// as an AVAssetWriter recording.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::SystemTime;
+/// use video_toolbox_sys::helpers::{write_mp4_metadata, Location, Mp4Metadata};
+///
+/// let metadata = Mp4Metadata::new()
+///     .title("Lobby camera")
+///     .creation_time(SystemTime::now())
+///     .location(Location::new(48.8584, 2.2945).altitude(35.0))
+///     .encoder("video-toolbox-sys")
+///     .tag("camera_id", "lobby-2");
+/// write_mp4_metadata("recording.mov", &metadata)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mp4Metadata {
+    title: Option<String>,
+    creation_time: Option<SystemTime>,
+    location: Option<Location>,
+    encoder: Option<String>,
+    tags: Vec<(String, String)>,
+}
+
+impl Mp4Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Recording date; also written to the `mvhd` of init segments.
+    pub fn creation_time(mut self, time: SystemTime) -> Self {
+        self.creation_time = Some(time);
+        self
+    }
+
+    pub fn location(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// Name of the encoding software.
+    pub fn encoder(mut self, name: impl Into<String>) -> Self {
+        self.encoder = Some(name.into());
+        self
+    }
+
+    /// Add a custom key-value pair. Setting a key again replaces its value.
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let (key, value) = (key.into(), value.into());
+        match self.tags.iter_mut().find(|(k, _)| *k == key) {
+            Some(tag) => tag.1 = value,
+            None => self.tags.push((key, value)),
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Creation time in seconds since 1904, for `mvhd`/`tkhd`.
+    pub(super) fn mp4_creation_time(&self) -> Option<u32> {
+        let since_unix = self.creation_time?.duration_since(UNIX_EPOCH).ok()?;
+        u32::try_from(since_unix.as_secs() + MP4_EPOCH_OFFSET).ok()
+    }
+
+    /// The `meta` box: an `mdir` handler and the `ilst` items.
+    pub fn meta_box(&self) -> Vec<u8> {
+        let mut ilst = Vec::new();
+        let mut text_item = |kind: &[u8; 4], text: &str| {
+            write_box(&mut ilst, kind, &data_box(text));
+        };
+        if let Some(title) = &self.title {
+            text_item(b"\xa9nam", title);
+        }
+        if let Some(time) = self.creation_time {
+            text_item(b"\xa9day", &xs_date_time(time));
+        }
+        if let Some(location) = &self.location {
+            text_item(b"\xa9xyz", &location.iso6709());
+        }
+        if let Some(encoder) = &self.encoder {
+            text_item(b"\xa9too", encoder);
+        }
+        for (key, value) in &self.tags {
+            let mut item = Vec::new();
+            write_box(&mut item, b"mean", &full_box_text("com.apple.iTunes"));
+            write_box(&mut item, b"name", &full_box_text(key));
+            item.extend_from_slice(&data_box(value));
+            write_box(&mut ilst, b"----", &item);
+        }
+
+        let mut hdlr = vec![0; 8]; // version + flags, pre_defined
+        hdlr.extend_from_slice(b"mdir");
+        hdlr.extend_from_slice(b"appl"); // reserved, as written by iTunes
+        hdlr.extend_from_slice(&[0; 8]);
+        hdlr.push(0); // empty name
+
+        let mut meta = vec![0, 0, 0, 0]; // version + flags
+        write_box(&mut meta, b"hdlr", &hdlr);
+        write_box(&mut meta, b"ilst", &ilst);
+        let mut buf = Vec::new();
+        write_box(&mut buf, b"meta", &meta);
+        buf
+    }
+
+    /// A `udta` box holding [`meta_box`](Self::meta_box), for a `moov`.
+    pub fn udta_box(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_box(&mut buf, b"udta", &self.meta_box());
+        buf
+    }
+}
+
+/// `data` box with a UTF-8 value.
+fn data_box(text: &str) -> Vec<u8> {
+    let mut content = 1u32.to_be_bytes().to_vec(); // well-known type: UTF-8
+    content.extend_from_slice(&0u32.to_be_bytes()); // locale
+    content.extend_from_slice(text.as_bytes());
+    let mut buf = Vec::new();
+    write_box(&mut buf, b"data", &content);
+    buf
+}
+
+fn full_box_text(text: &str) -> Vec<u8> {
+    let mut content = vec![0, 0, 0, 0]; // version + flags
+    content.extend_from_slice(text.as_bytes());
+    content
+}
+
+/// Put `child` (a complete box) in the `moov`'s `udta`, replacing an
+/// earlier box of the same kind and creating the `udta` if needed.
+pub(super) fn insert_user_data(moov: &mut Vec<u8>, child: &[u8]) -> Option<()> {
+    let kind: [u8; 4] = child.get(4..8)?.try_into().ok()?;
+    let root = find_box(moov, 0, moov.len(), b"moov")?;
+    match find_box(moov, root.content(), root.end(), b"udta") {
+        Some(udta) => {
+            let mut end = udta.end();
+            let mut delta = child.len() as isize;
+            if let Some(old) = find_box(moov, udta.content(), udta.end(), &kind) {
+                moov.drain(old.offset..old.end());
+                end -= old.size;
+                delta -= old.size as isize;
+            }
+            moov.splice(end..end, child.iter().copied());
+            for range in [root, udta] {
+                add_to_size(moov, range, delta);
+            }
+        }
+        None => {
+            let mut udta = Vec::new();
+            write_box(&mut udta, b"udta", child);
+            let delta = udta.len() as isize;
+            moov.splice(root.end()..root.end(), udta);
+            add_to_size(moov, root, delta);
+        }
+    }
+    Some(())
+}
+
+/// Store `metadata` in the `moov/udta/meta` of an MP4 (or MOV) file,
+/// replacing earlier iTunes-style metadata and keeping other user data.
+///
+/// When the `moov` is the last box, as AVAssetWriter and most recorders
+/// write it, the file is updated in place. Otherwise the media after the
+/// `moov` moves, so the file is rewritten through a temporary file with its
+/// chunk and fragment offsets adjusted.
+pub fn write_mp4_metadata(path: impl AsRef<Path>, metadata: &Mp4Metadata) -> io::Result<()> {
+    let meta = metadata.meta_box();
+    update_moov(path.as_ref(), |moov| {
+        insert_user_data(moov, &meta).ok_or_else(|| invalid("malformed moov box"))
+    })
+}
+
+pub(super) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A top-level box of a file.
+struct TopLevelBox {
+    kind: [u8; 4],
+    offset: u64,
+    size: u64,
+}
+
+fn scan_top_level(file: &mut File, len: u64) -> io::Result<Vec<TopLevelBox>> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= len {
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header[..8])?;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => len - offset,
+            1 => {
+                file.read_exact(&mut header[8..])?;
+                u64::from_be_bytes(header[8..].try_into().unwrap())
+            }
+            size => size as u64,
+        };
+        if size < 8 || offset + size > len {
+            return Err(invalid("truncated MP4 box"));
+        }
+        boxes.push(TopLevelBox {
+            kind: header[4..8].try_into().unwrap(),
+            offset,
+            size,
+        });
+        offset += size;
+    }
+    Ok(boxes)
+}
+
+pub(super) fn add_to_size(data: &mut [u8], range: BoxRange, delta: isize) {
+    let size = (range.size as isize + delta) as u32;
+    data[range.offset..range.offset + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+/// `BoxRange`s of every `kind` child in `parent`.
+pub(super) fn child_boxes(data: &[u8], parent: BoxRange, kind: &[u8; 4]) -> Vec<BoxRange> {
+    let mut found = Vec::new();
+    let mut at = parent.content();
+    while let Some(child) = find_box(data, at, parent.end(), kind) {
+        at = child.end();
+        found.push(child);
+    }
+    found
+}
+
+/// Shift `stco`/`co64` chunk offsets at or after `from` by `delta`.
+/// Fails if a 32-bit offset would overflow.
+fn shift_chunk_offsets(moov: &mut [u8], from: u64, delta: i64) -> Option<()> {
+    let root = find_box(moov, 0, moov.len(), b"moov")?;
+    for trak in child_boxes(moov, root, b"trak") {
+        let Some(stbl) = find_box(moov, trak.content(), trak.end(), b"mdia")
+            .and_then(|mdia| find_box(moov, mdia.content(), mdia.end(), b"minf"))
+            .and_then(|minf| find_box(moov, minf.content(), minf.end(), b"stbl"))
+        else {
+            continue;
+        };
+        for (kind, width) in [(b"stco", 4), (b"co64", 8)] {
+            let Some(table) = find_box(moov, stbl.content(), stbl.end(), kind) else {
+                continue;
+            };
+            let count = read_u32(moov, table.content() + 4)? as usize;
+            for i in 0..count {
+                let at = table.content() + 8 + i * width;
+                let offset = if width == 4 {
+                    read_u32(moov, at)? as u64
+                } else {
+                    read_u64(moov, at)?
+                };
+                if offset < from {
+                    continue;
+                }
+                let shifted = offset.checked_add_signed(delta)?;
+                if width == 4 {
+                    let shifted = u32::try_from(shifted).ok()?;
+                    moov[at..at + 4].copy_from_slice(&shifted.to_be_bytes());
+                } else {
+                    moov[at..at + 8].copy_from_slice(&shifted.to_be_bytes());
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Shift absolute offsets in a `moof` (`tfhd` base data offsets) or `mfra`
+/// (`tfra` fragment offsets) at or after `from` by `delta`.
+fn shift_fragment_offsets(data: &mut [u8], from: u64, delta: i64) -> Option<()> {
+    let shift = |data: &mut [u8], at: usize| -> Option<()> {
+        let offset = read_u64(data, at)?;
+        if offset >= from {
+            let shifted = offset.checked_add_signed(delta)?;
+            data[at..at + 8].copy_from_slice(&shifted.to_be_bytes());
+        }
+        Some(())
+    };
+
+    if let Some(moof) = find_box(data, 0, data.len(), b"moof") {
+        for traf in child_boxes(data, moof, b"traf") {
+            let Some(tfhd) = find_box(data, traf.content(), traf.end(), b"tfhd") else {
+                continue;
+            };
+            // base-data-offset-present
+            if read_u32(data, tfhd.content())? & 1 != 0 {
+                shift(data, tfhd.content() + 8)?;
+            }
+        }
+    } else if let Some(mfra) = find_box(data, 0, data.len(), b"mfra") {
+        for tfra in child_boxes(data, mfra, b"tfra") {
+            let version = data[tfra.content()];
+            let sizes = read_u32(data, tfra.content() + 8)?;
+            let count = read_u32(data, tfra.content() + 12)? as usize;
+            let numbers: usize = [4, 2, 0]
+                .iter()
+                .map(|bits| ((sizes >> bits) & 3) as usize + 1)
+                .sum();
+            let mut at = tfra.content() + 16;
+            for _ in 0..count {
+                if version == 1 {
+                    shift(data, at + 8)?;
+                    at += 16 + numbers;
+                } else {
+                    let offset = read_u32(data, at + 4)? as u64;
+                    if offset >= from {
+                        let shifted = u32::try_from(offset.checked_add_signed(delta)?).ok()?;
+                        data[at + 4..at + 8].copy_from_slice(&shifted.to_be_bytes());
+                    }
+                    at += 8 + numbers;
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Replace the `moov` of an MP4 (or MOV) file with the result of `edit`,
+/// as described for [`write_mp4_metadata`].
+pub(super) fn update_moov<F>(path: &Path, edit: F) -> io::Result<()>
+where
+    F: FnOnce(&mut Vec<u8>) -> io::Result<()>,
+{
+    update_moov_with_media(path, |moov, _| edit(moov).map(|()| Vec::new()))
+}
+
+/// Like [`update_moov`], also placing the boxes `edit` returns (e.g. an
+/// `mdat` with samples of a track it adds) right before the `moov`. `edit`
+/// gets the file offset they will start at, which does not move.
+pub(super) fn update_moov_with_media<F>(path: &Path, edit: F) -> io::Result<()>
+where
+    F: FnOnce(&mut Vec<u8>, u64) -> io::Result<Vec<u8>>,
+{
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let boxes = scan_top_level(&mut file, len)?;
+    let index = boxes
+        .iter()
+        .position(|b| &b.kind == b"moov")
+        .ok_or_else(|| invalid("no moov box"))?;
+    let (moov_offset, moov_size) = (boxes[index].offset, boxes[index].size);
+
+    let mut moov = vec![0u8; moov_size as usize];
+    file.seek(SeekFrom::Start(moov_offset))?;
+    file.read_exact(&mut moov)?;
+    let media = edit(&mut moov, moov_offset)?;
+
+    let moov_end = moov_offset + moov_size;
+    if moov_end == len {
+        file.seek(SeekFrom::Start(moov_offset))?;
+        file.write_all(&media)?;
+        file.write_all(&moov)?;
+        file.set_len(moov_offset + (media.len() + moov.len()) as u64)?;
+        return file.sync_all();
+    }
+
+    let delta = (media.len() + moov.len()) as i64 - moov_size as i64;
+    shift_chunk_offsets(&mut moov, moov_end, delta)
+        .ok_or_else(|| invalid("chunk offsets overflow 32 bits"))?;
+
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let result = File::create(&temp)
+        .and_then(|mut out| rewrite(&mut file, &mut out, &boxes, index, &[&media, &moov], delta));
+    match result {
+        Ok(()) => fs::rename(&temp, path),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Copy `file` to `out` with `replacement` replacing `boxes[index]`,
+/// shifting the offsets in fragments after it by `delta`.
+fn rewrite(
+    file: &mut File,
+    out: &mut File,
+    boxes: &[TopLevelBox],
+    index: usize,
+    replacement: &[&[u8]],
+    delta: i64,
+) -> io::Result<()> {
+    let moov_end = boxes[index].offset + boxes[index].size;
+    file.seek(SeekFrom::Start(0))?;
+    io::copy(&mut file.take(boxes[index].offset), out)?;
+    for data in replacement {
+        out.write_all(data)?;
+    }
+    for top in &boxes[index + 1..] {
+        file.seek(SeekFrom::Start(top.offset))?;
+        if &top.kind == b"moof" || &top.kind == b"mfra" {
+            let mut data = vec![0u8; top.size as usize];
+            file.read_exact(&mut data)?;
+            shift_fragment_offsets(&mut data, moov_end, delta)
+                .ok_or_else(|| invalid("malformed fragment"))?;
+            out.write_all(&data)?;
+        } else {
+            io::copy(&mut file.take(top.size), out)?;
+        }
+    }
+    out.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{CmafConfig, CmafMuxer};
+    use std::time::Duration;
+
+    fn sample() -> Mp4Metadata {
+        Mp4Metadata::new()
+            .title("Lobby")
+            .creation_time(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .location(Location::new(37.33182, -122.03118).altitude(45.0))
+            .tag("camera", "a")
+            .tag("camera", "b")
+    }
+
+    #[test]
+    fn test_metadata_items_and_init_segment() {
+        assert_eq!(
+            Location::new(37.33182, -122.03118).altitude(45.0).iso6709(),
+            "+37.3318-122.0312+045.000/"
+        );
+        let metadata = sample();
+        assert_eq!(
+            metadata.mp4_creation_time(),
+            Some(1_700_000_000 + 2_082_844_800)
+        );
+
+        let udta = metadata.udta_box();
+        let meta = find_box(&udta, 8, udta.len(), b"meta").unwrap();
+        let ilst = find_box(&udta, meta.content() + 4, meta.end(), b"ilst").unwrap();
+        let title = find_box(&udta, ilst.content(), ilst.end(), b"\xa9nam").unwrap();
+        assert_eq!(&udta[title.content() + 16..title.end()], b"Lobby");
+        let day = find_box(&udta, ilst.content(), ilst.end(), b"\xa9day").unwrap();
+        assert_eq!(
+            &udta[day.content() + 16..day.end()],
+            b"2023-11-14T22:13:20.000Z"
+        );
+        let custom = find_box(&udta, ilst.content(), ilst.end(), b"----").unwrap();
+        let item = &udta[custom.content()..custom.end()];
+        assert!(item.ends_with(b"camera\0\0\0\x11data\0\0\0\x01\0\0\0\0b"));
+        assert!(Mp4Metadata::new().is_empty());
+
+        let mut muxer = CmafMuxer::new(CmafConfig::default());
+        muxer.set_metadata(metadata);
+        let init = muxer.create_init_segment(&[0x67, 0x64, 0, 0x1f], &[0x68], 1280, 720);
+        let moov = find_box(&init, 0, init.len(), b"moov").unwrap();
+        let found = find_box(&init, moov.content(), moov.end(), b"udta").unwrap();
+        assert_eq!(&init[found.offset..found.end()], &udta[..]);
+    }
+
+    #[test]
+    fn test_write_metadata_in_place() {
+        let mut file = Vec::new();
+        write_box(&mut file, b"ftyp", b"qt  \0\0\0\0");
+        write_box(&mut file, b"mdat", b"media");
+        let mut moov = Vec::new();
+        write_box(&mut moov, b"mvhd", &[0; 100]);
+        write_box(&mut file, b"moov", &moov);
+
+        let path = std::env::temp_dir().join(format!("vt-metadata-{}.mov", std::process::id()));
+        fs::write(&path, &file).unwrap();
+        write_mp4_metadata(&path, &Mp4Metadata::new().title("first")).unwrap();
+        write_mp4_metadata(&path, &sample()).unwrap();
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Only the moov at the end changed, and the old meta was replaced
+        assert_eq!(
+            &written[..file.len() - moov.len() - 8],
+            &file[..file.len() - moov.len() - 8]
+        );
+        assert_eq!(written.len(), file.len() + sample().udta_box().len());
+        assert!(written.ends_with(&sample().udta_box()));
+    }
+}
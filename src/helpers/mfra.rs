@@ -0,0 +1,302 @@
+//! Random-access index (`mfra`/`tfra`) for fragmented MP4 files.
+
+/// Byte range of a box within a buffer.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct BoxRange {
+    pub(super) offset: usize,
+    pub(super) size: usize,
+    /// 16 for boxes with a 64-bit `largesize`, 8 otherwise
+    header: usize,
+}
+
+impl BoxRange {
+    pub(super) fn content(&self) -> usize {
+        self.offset + self.header
+    }
+
+    pub(super) fn end(&self) -> usize {
+        self.offset + self.size
+    }
+}
+
+/// Find the first box of type `kind` among the boxes in `data[start..end]`.
+/// Boxes may use a 64-bit `largesize` (size 1) or extend to `end` (size 0).
+pub(super) fn find_box(data: &[u8], start: usize, end: usize, kind: &[u8; 4]) -> Option<BoxRange> {
+    let mut offset = start;
+    while offset + 8 <= end {
+        let (size, header) = match read_u32(data, offset)? {
+            0 => (end - offset, 8),
+            1 => (usize::try_from(read_u64(data, offset + 8)?).ok()?, 16),
+            size => (size as usize, 8),
+        };
+        if size < header || size > end - offset {
+            return None;
+        }
+        if &data[offset + 4..offset + 8] == kind {
+            return Some(BoxRange {
+                offset,
+                size,
+                header,
+            });
+        }
+        offset += size;
+    }
+    None
+}
+
+pub(super) fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+pub(super) fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// `sample_is_non_sync_sample` in ISO BMFF sample flags.
+const SAMPLE_IS_NON_SYNC: u32 = 0x0001_0000;
+
+/// A fragment that starts with a sync sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomAccessPoint {
+    /// Presentation time of the sync sample, in track timescale units
+    pub time: u64,
+    /// File offset of the fragment's `moof` box
+    pub moof_offset: u64,
+}
+
+/// Collects random-access points while fragments are written and serializes
+/// them as an `mfra` box (one `tfra` plus the trailing `mfro`).
+///
+/// Appending the `mfra` at the end of a fragmented MP4 lets players seek in
+/// long files without scanning every `moof`.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{CmafMuxer, CmafConfig, RandomAccessIndex};
+///
+/// let mut muxer = CmafMuxer::new(CmafConfig::default());
+/// let mut index = RandomAccessIndex::new(1);
+/// let mut file = muxer.create_init_segment(&[0x67], &[0x68], 1920, 1080);
+/// if let Some(fragment) = muxer.flush() {
+///     index.add_fragment(file.len() as u64, &fragment);
+///     file.extend_from_slice(&fragment);
+/// }
+/// file.extend_from_slice(&index.to_mfra());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RandomAccessIndex {
+    track_id: u32,
+    points: Vec<RandomAccessPoint>,
+}
+
+impl RandomAccessIndex {
+    /// An empty index for `track_id` (1 for [`CmafMuxer`](super::CmafMuxer) output).
+    pub fn new(track_id: u32) -> Self {
+        Self {
+            track_id,
+            points: Vec::new(),
+        }
+    }
+
+    pub fn track_id(&self) -> u32 {
+        self.track_id
+    }
+
+    /// Collected points, in the order added.
+    pub fn points(&self) -> &[RandomAccessPoint] {
+        &self.points
+    }
+
+    pub fn push(&mut self, point: RandomAccessPoint) {
+        self.points.push(point);
+    }
+
+    /// Index a media segment (optional `styp`, `moof`, `mdat`) written at
+    /// `offset` in the file.
+    ///
+    /// The time comes from the fragment's `tfdt` and first sample's
+    /// composition offset. Returns the point added, or `None` if the fragment
+    /// does not start with a sync sample or cannot be parsed.
+    pub fn add_fragment(&mut self, offset: u64, fragment: &[u8]) -> Option<RandomAccessPoint> {
+        let moof = find_box(fragment, 0, fragment.len(), b"moof")?;
+        let traf = find_box(fragment, moof.content(), moof.end(), b"traf")?;
+        let (is_sync, composition_offset) = first_sample(fragment, traf)?;
+        if !is_sync {
+            return None;
+        }
+        let tfdt = find_box(fragment, traf.content(), traf.end(), b"tfdt")?;
+        let decode_time = match fragment.get(tfdt.content()).copied()? {
+            1 => read_u64(fragment, tfdt.content() + 4)?,
+            _ => read_u32(fragment, tfdt.content() + 4)? as u64,
+        };
+
+        let point = RandomAccessPoint {
+            time: decode_time.saturating_add_signed(composition_offset),
+            moof_offset: offset + moof.offset as u64,
+        };
+        self.points.push(point);
+        Some(point)
+    }
+
+    /// Serialize as an `mfra` box.
+    pub fn to_mfra(&self) -> Vec<u8> {
+        let tfra_size = 8 + 4 + 12 + self.points.len() * 19;
+        let mfra_size = 8 + tfra_size + 16;
+        let mut buf = Vec::with_capacity(mfra_size);
+
+        buf.extend_from_slice(&(mfra_size as u32).to_be_bytes());
+        buf.extend_from_slice(b"mfra");
+
+        buf.extend_from_slice(&(tfra_size as u32).to_be_bytes());
+        buf.extend_from_slice(b"tfra");
+        buf.extend_from_slice(&[1, 0, 0, 0]); // version 1 (64-bit time and offset)
+        buf.extend_from_slice(&self.track_id.to_be_bytes());
+        buf.extend_from_slice(&0u32.to_be_bytes()); // 1-byte traf/trun/sample numbers
+        buf.extend_from_slice(&(self.points.len() as u32).to_be_bytes());
+        for point in &self.points {
+            buf.extend_from_slice(&point.time.to_be_bytes());
+            buf.extend_from_slice(&point.moof_offset.to_be_bytes());
+            buf.extend_from_slice(&[1, 1, 1]); // first sample of the first trun
+        }
+
+        buf.extend_from_slice(&16u32.to_be_bytes());
+        buf.extend_from_slice(b"mfro");
+        buf.extend_from_slice(&[0, 0, 0, 0]);
+        buf.extend_from_slice(&(mfra_size as u32).to_be_bytes());
+        buf
+    }
+
+    /// Read the index from the `mfra` at the end of a file, located through
+    /// the trailing `mfro`.
+    pub fn from_file_end(data: &[u8]) -> Option<Self> {
+        let mfra_size = read_u32(data, data.len().checked_sub(4)?)? as usize;
+        let start = data.len().checked_sub(mfra_size)?;
+        let mfra = find_box(data, start, data.len(), b"mfra")?;
+        let tfra = find_box(data, mfra.content(), mfra.end(), b"tfra")?;
+
+        let version = data.get(tfra.content()).copied()?;
+        let track_id = read_u32(data, tfra.content() + 4)?;
+        let sizes = read_u32(data, tfra.content() + 8)?;
+        let count = read_u32(data, tfra.content() + 12)? as usize;
+        // traf, trun and sample numbers are 1-4 bytes each
+        let numbers: usize = [4, 2, 0]
+            .iter()
+            .map(|shift| ((sizes >> shift) & 3) as usize + 1)
+            .sum();
+        let field = if version == 1 { 8 } else { 4 };
+
+        let mut index = Self::new(track_id);
+        let mut at = tfra.content() + 16;
+        for _ in 0..count {
+            let (time, moof_offset) = if version == 1 {
+                (read_u64(data, at)?, read_u64(data, at + 8)?)
+            } else {
+                (read_u32(data, at)? as u64, read_u32(data, at + 4)? as u64)
+            };
+            index.push(RandomAccessPoint { time, moof_offset });
+            at += 2 * field + numbers;
+        }
+        Some(index)
+    }
+}
+
+/// Sync flag and composition offset of the first sample in a `traf`.
+fn first_sample(data: &[u8], traf: BoxRange) -> Option<(bool, i64)> {
+    let trun = find_box(data, traf.content(), traf.end(), b"trun")?;
+    let version = data.get(trun.content()).copied()?;
+    let flags = read_u32(data, trun.content())? & 0x00FF_FFFF;
+    let mut at = trun.content() + 8;
+    if flags & 0x01 != 0 {
+        at += 4; // data_offset
+    }
+
+    let mut sample_flags = None;
+    if flags & 0x04 != 0 {
+        sample_flags = Some(read_u32(data, at)?);
+        at += 4;
+    }
+    if flags & 0x100 != 0 {
+        at += 4; // sample_duration
+    }
+    if flags & 0x200 != 0 {
+        at += 4; // sample_size
+    }
+    if flags & 0x400 != 0 {
+        let per_sample = read_u32(data, at)?;
+        sample_flags.get_or_insert(per_sample);
+        at += 4;
+    }
+    let composition_offset = if flags & 0x800 != 0 {
+        let raw = read_u32(data, at)?;
+        if version == 1 {
+            raw as i32 as i64
+        } else {
+            raw as i64
+        }
+    } else {
+        0
+    };
+    // Without sample flags (tfhd/trex defaults) the fragment is not indexed
+    Some((sample_flags? & SAMPLE_IS_NON_SYNC == 0, composition_offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{CmafConfig, CmafMuxer, NalUnit};
+
+    #[test]
+    fn test_index_muxer_fragments_and_read_back() {
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            fragment_duration_ms: 1000,
+            timescale: 1000,
+            ..Default::default()
+        });
+        let mut file = muxer.create_init_segment(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 64, 64);
+        let mut index = RandomAccessIndex::new(1);
+        let mut write = |file: &mut Vec<u8>, fragment: Vec<u8>| {
+            let point = index.add_fragment(file.len() as u64, &fragment);
+            file.extend_from_slice(&fragment);
+            point
+        };
+
+        // A fragment starting with a P frame is not a random-access point
+        let p = [NalUnit {
+            nal_type: 1,
+            data: vec![0x61, 1],
+        }];
+        let idr = [NalUnit {
+            nal_type: 5,
+            data: vec![0x65, 2],
+        }];
+        muxer.add_frame(&p, 0, 0, 500, false);
+        let first = muxer.add_frame(&idr, 1000, 1000, 500, true).unwrap();
+        assert_eq!(write(&mut file, first), None);
+        muxer.add_frame(&p, 1500, 1500, 500, false);
+        let second = muxer.add_frame(&idr, 2000, 2000, 500, true).unwrap();
+        let point = write(&mut file, second).unwrap();
+        assert_eq!(point.time, 1000);
+        assert_eq!(&file[point.moof_offset as usize + 4..][..4], b"moof");
+
+        file.extend_from_slice(&index.to_mfra());
+        assert_eq!(RandomAccessIndex::from_file_end(&file), Some(index));
+    }
+
+    #[test]
+    fn test_index_rejects_truncated_trun() {
+        // moof > traf > trun where the trun header ends the buffer
+        let mut fragment = Vec::new();
+        for (size, name) in [(24u32, b"moof"), (16, b"traf"), (8, b"trun")] {
+            fragment.extend_from_slice(&size.to_be_bytes());
+            fragment.extend_from_slice(name);
+        }
+        let mut index = RandomAccessIndex::new(1);
+        assert_eq!(index.add_fragment(0, &fragment), None);
+        assert_eq!(RandomAccessIndex::from_file_end(&fragment), None);
+    }
+}
@@ -6,9 +6,96 @@
 //! # Features
 //!
 //! - [`CompressionSessionBuilder`] - Fluent API for creating compression sessions
+//! - [`EncoderError`] / [`Remediation`] - Typed encoder failures (unsupported codec, invalidated session, odd dimensions) with suggested fixes
+//! - [`CompressionProperty`] - Typed values for any compression property, e.g. data rate limits or entropy mode
+//! - [`CFDictBuilder`] / [`cfdict!`](crate::cfdict) - Typed CFDictionary construction for encoder specifications and buffer attributes
+//! - [`CompressionSession`] - Owned encoder session with panic-safe output callback, on-demand keyframes
+//!   and per-frame [`EncodeOptions`]
+//! - [`DecompressionSession`] - Owned decoder session with per-frame [`DecodeOptions`],
+//!   decoding AVCC, NAL unit or Annex B input into [`DecodedFrame`]s
+//! - `AsyncEncoder` / `AsyncDecoder` - Awaitable per-frame encodes and a `Stream` of decoded frames (`async` feature)
+//! - [`MultiPassEncoder`] - Offline multi-pass encoding through VTMultiPassStorage and a VTFrameSilo
+//! - [`encode_frame_with_handler`] / [`decode_frame_with_handler`] - Per-frame output closures via the block-based encode and decode calls
+//! - [`EncodedFrame`] - Encoder output that retains its CMSampleBuffer and reads NAL units in place
+//! - [`create_encoded_sample_buffer`] / [`SampleBufferGuard`] - Owned CMSampleBuffers built from encoded frame data
+//! - [`AnnexBReader`] / [`AvccWriter`] - Chunked Annex B stream splitting and AVCC length-prefixing
+//! - [`CallbackTarget`] / [`CallbackWorker`] - Session output callbacks on a worker thread (lock-free handoff) or dispatch queue
+//! - [`AccessUnitAssembler`] - Groups received NAL units into complete frames (multi-slice, SEI) before decoding
+//! - [`PlaybackDecoder`] - Asynchronous, real-time paced decoding delivered in presentation order
+//! - [`StartupGate`] - Wait-for-keyframe or decode-immediately startup when joining a stream mid-GOP
+//! - [`FrameBroadcaster`] - Shares decoded frames with several subscribers through per-subscriber bounded queues
+//! - [`AnalysisStage`] - Background Vision/CoreML-style analysis of decoded or captured frames
 //! - [`PixelBufferConfig`] / [`create_pixel_buffer`] - Utilities for creating CVPixelBuffers
+//! - [`pad_pixel_buffer`] / [`CleanAperture`] - Odd-sized frames (e.g. 1279x719) padded to the encoder's alignment, with the visible size signaled to players
+//! - [`PixelBufferPool`] - Recycled encoder input buffers from a session's pool or a standalone CVPixelBufferPool
+//! - [`SimulcastEncoder`] / [`Rendition`] - One capture feed encoded at several resolutions (e.g. 1080p/720p/360p) with a shared downscaler
+//! - [`FrameInterpolator`] - Motion-compensated frame rate up-conversion (e.g. 30 to 60 fps) with VTFrameProcessor
+//! - [`PixelTransfer`] - Pixel format conversion, scaling and cropping, e.g. NV12 to BGRA or 4K to 720p
+//! - `ImageRotator` / `OutputOrientation` - Rotation and mirroring of decoded images, e.g. upright portrait footage (`pixel-rotation` feature)
+//! - [`RegionCropper`] / [`CropControl`] - Runtime region-of-interest crop with smooth pan/zoom before encode
+//! - [`OverlayStage`] - Alpha-blended watermark/logo overlay on frames before encoding
+//! - [`list_video_devices`] / [`CaptureSessionBuilder`] - Camera enumeration and capture in a chosen device, resolution, frame rate and pixel format
+//! - [`InputPath`] / [`InputConverter`] - Capture-to-encoder pixel format negotiation, converting only when no common format exists
 //! - [`create_capture_delegate`] - Safe ObjC delegate creation for AVFoundation
+//! - `ScreenCapture` / `ShareableContent` - ScreenCaptureKit display and window capture through the same callback shape (`screen-capture` feature)
 //! - [`run_for_duration`] / [`run_while`] - CoreFoundation run loop helpers
+//! - [`set_deterministic`] / [`DETERMINISTIC_ENV`] - Software-only, fixed rate control sessions for reproducible CI output
+//! - [`EncoderComparison`] - Hardware vs software encoder size/quality/latency per GOP
+//! - [`AudioResampler`] / [`ChannelMapper`] - Audio rate, channel and sample format conversion
+//! - [`ChannelLayout`] - Mono/stereo/5.1 layouts for capture settings, downmixing and audio sample entries
+//! - [`AudioMeter`] - Per-channel RMS/peak levels with silence and clipping detection
+//! - [`Mp4Metadata`] / [`write_mp4_metadata`] - Title, date, GPS location, encoder and custom tags in init segments and finished MP4/MOV files
+//! - [`TimedMetadataMuxer`] / [`read_timed_metadata`] - GPS, pose and sensor telemetry as a timed metadata track next to the video
+//! - [`TimecodeMuxer`] / [`Timecode`] - SMPTE timecode (`tmcd`) tracks with drop-frame counting for editors
+//! - [`Chapter`] / [`write_chapters`] - Chapter markers for recordings as a Nero `chpl` box and a QuickTime chapter track, also via [`Fmp4Recorder::add_chapter`]
+//! - [`LoudnessMeter`] / [`write_loudness_metadata`] - EBU R128 integrated loudness and true peak, stored in the MP4 `udta`
+//! - [`AudioCmafMuxer`] - Audio-only (AAC or Opus) CMAF segments for audio-only HLS
+//! - [`Profile`] / [`Level`] / [`derive_level`] - Typed H.264 profile/level with validation
+//! - [`ConformanceChecker`] - Checks encoded streams against the level signaled in their SPS
+//! - [`StreamAnalyzer`] / [`analyze_file`] - GOP structure, bitrate over time, keyframe intervals and timestamp gaps of fragmented MP4 files
+//! - [`h264_codec_string`] / [`hevc_codec_string`] / [`av1_codec_string`] - RFC 6381 `codecs=` strings for manifests and MSE
+//! - [`SceneAnalysis`] / [`FirstPass`] - First-pass scene complexity and per-segment bitrate suggestions
+//! - [`SceneChangeDetector`] - Scene-cut detection for keyframe and segment placement
+//! - [`MotionEstimator`] - Per-frame motion scores attached to encoded frames
+//! - [`CmafDemuxer`] - Incremental fragmented MP4 demuxing that emits samples while segments are still arriving
+//! - [`ReorderBuffer`] - Receive-side fragment reordering by `mfhd` sequence number, with a bounded wait before skipping gaps
+//! - [`LowLatencyMuxer`] - Per-frame CMAF chunks with keyframe join points for sub-frame-latency streaming
+//! - [`MoqPrioritizer`] - MoQ groups, priorities and deadline hints so relays drop stale P-frames before init segments and keyframes
+//! - [`ReplayBuffer`] / [`TriggeredRecorder`] - Rolling keyframe-aligned buffer and pre-roll triggered recording
+//! - [`SegmentSink`] / [`TeeSink`] - Segment destinations, with fan-out to several sinks
+//! - [`Fmp4Recorder`] - Crash-safe local recording to fragmented MP4
+//! - [`trim`] - Frame-accurate cuts of recordings, re-encoding only the partial GOPs at the edges
+//! - [`Mp4Writer`] - AVAssetWriter MP4/MOV recording of passthrough video and AAC audio, with an awaitable finish
+//! - [`mp4_muxer::Mp4Writer`] - Pure-Rust progressive MP4 with full sample tables for H.264 and HEVC recordings
+//! - [`StreamJournal`] - Durable journal of stream configuration and position for resuming after power loss
+//! - [`RandomAccessIndex`] - `mfra`/`tfra` random-access index for seeking in fragmented MP4
+//! - [`SegmentTimeIndex`] / [`TimeIndexedSink`] - Capture host time and wall clock of each segment, with a JSON sidecar for correlating external logs
+//! - [`HlsSink`] - Live HLS playlist with sliding-window segment retention, before-delete hooks
+//!   and Low-Latency HLS partial segments ([`HlsPartConfig`])
+//! - [`DashSink`] - Live-profile DASH MPD (SegmentTemplate + SegmentTimeline) for CMAF segments
+//! - [`MsePage`] - Self-contained Media Source Extensions test page for checking CMAF output in a browser
+//! - `MseServer` - Live fMP4 segments broadcast to browsers over WebSocket for MSE playback, with the player page on the same port (`mse-server` feature)
+//! - [`VttCueWriter`] - Live WebVTT subtitle segments and playlist aligned with media segments
+//! - `HttpPutSink` - Segment and playlist upload via HTTP PUT with retries (`http-upload` feature)
+//! - [`LossySink`] / [`LossModel`] - Seeded segment drop/reorder/duplicate/truncate simulation for loss-resilience tests
+//! - [`ShapedSink`] - Token-bucket bandwidth shaping with latency and jitter for transport tests
+//! - [`EncodeStatsSink`] - Per-segment bitrate, dropped frame and QP stats in an `emsg` or `free` box for QoE monitoring
+//! - [`UdpTsSink`] - MPEG-TS output over UDP multicast with 7-packet datagrams
+//! - [`RtspClient`] / [`H264Depacketizer`] - IP camera input over RTSP with RTP/H.264 depacketization
+//! - [`H264Packetizer`] - RTP/H.264 packetization (single NAL, STAP-A, FU-A) of encoder output for WebRTC and RTSP stacks
+//! - [`TimeLapse`] - Frame decimation and timestamp compression for time-lapse encoding
+//! - [`FrameSource`] / [`LoopingSource`] - Encoded frame sources, including endless replay for soak tests
+//! - [`FrameSnapshot`] / [`GoldenHashes`] / [`compare_frame`] - Frame hashing for decoder regression tests
+//! - [`VideoMonitor`] - Black and frozen video detection for broadcast monitoring
+//! - [`SendableSession`] / [`SendablePixelBuffer`] - Audited `Send`/`Sync` wrappers for raw sessions and pixel buffers
+//! - [`SharedSurface`] - Hand IOSurface-backed frames to another process as Mach ports, zero-copy
+//! - [`get_property`] / [`copy_supported_property_dictionary`] - Read back what a session actually accepted as `i64`, `f64`, `bool` or `String`
+//! - [`PropertyBag`] - Export a session's serializable settings to a file and apply them to another session
+//! - [`LeakCheck`] / [`release_pixel_buffer`] - Retain/release leak reports with creation backtraces (`leak-tracking` feature)
+//! - [`PipelineEvent`] / [`set_event_handler`] - Out-of-band events such as caught callback panics
+//! - [`TraceRecorder`] / [`TraceLogger`] - Lock-free ring buffer of per-frame events drained off the hot path
+//! - [`TimestampFilter`] - Capture clock drift compensation against the wall clock
+//! - [`FrameTimestamper`] / [`Timebase`] / [`PlaybackScheduler`] - Clock-based A/V sync and pacing
 //!
 //! # Example
 //!
@@ -28,29 +115,269 @@
 //!     .expect("Failed to create compression session");
 //! ```
 
+mod access_unit;
+mod annex_b;
+#[cfg(feature = "async")]
+mod async_session;
+mod audio_cmaf;
+mod audio_meter;
+mod audio_resampler;
+mod callback_target;
+mod capture;
+mod cf_dict;
+mod channel_layout;
+mod chapters;
+mod clock;
+mod codec_string;
 mod compression_builder;
+mod compression_property;
+mod compression_session;
+mod conformance;
+mod crop;
 mod cv_ffi;
+mod dash;
+mod decompression_session;
 mod delegate;
+mod deterministic;
+mod encode_stats;
+mod encoded_frame;
+mod encoder_comparison;
+mod encoder_error;
+mod events;
+mod fmp4_recorder;
+mod frame_analysis;
+mod frame_broadcast;
+mod frame_hash;
+mod frame_interpolator;
+mod hls;
+mod input_format;
+mod journal;
+#[cfg(feature = "http-upload")]
+mod http_sink;
+mod leak_tracker;
+mod lossy_sink;
+mod loudness;
+mod low_latency;
+mod metadata;
+mod mfra;
+mod moq_priority;
+mod motion;
+mod mp4_writer;
+mod mse_page;
+#[cfg(feature = "mse-server")]
+mod mse_server;
+mod multi_pass;
+mod output_handler;
+mod overlay;
+mod padding;
 mod pixel_buffer;
+mod pixel_buffer_pool;
+mod pixel_transfer;
+mod playback_decoder;
+mod profile_level;
+mod replay_buffer;
+#[cfg(feature = "pixel-rotation")]
+mod rotation;
+mod rtp;
+mod rtsp;
 mod runloop;
+mod sample_buffer;
+mod scene_analysis;
+mod scene_change;
+#[cfg(feature = "screen-capture")]
+mod screen_capture;
+mod segment_reorder;
+mod segment_times;
+mod sendable;
+mod session_props;
+mod shaped_sink;
+mod shared_surface;
+mod simulcast;
+mod sink;
+mod source;
+mod startup;
+mod stream_analyzer;
+mod tee_sink;
+mod time_lapse;
+mod timecode;
+mod timed_metadata;
+mod timestamp_filter;
+mod trace;
+mod trim;
+mod triggered_recorder;
+mod udp_sink;
+mod video_monitor;
+mod webvtt;
 
 // NAL extraction and CMAF muxing for streaming
 pub mod nal_extractor;
 pub mod cmaf_muxer;
+pub mod cmaf_demuxer;
+pub mod mp4_muxer;
 
+pub use access_unit::{AccessUnit, AccessUnitAssembler};
+pub use annex_b::{annex_b_to_avcc, avcc_to_annex_b, AnnexBReader, AvccWriter};
+#[cfg(feature = "async")]
+pub use async_session::{AsyncDecoder, AsyncEncoder, EncodeFuture, EncodeResult};
+pub use audio_cmaf::{
+    aac_audio_specific_config, AudioCmafMuxer, AudioCodec, AudioTrackConfig,
+};
+pub use audio_meter::{to_dbfs, AudioLevels, AudioMeter};
+pub use audio_resampler::{AudioFormat, AudioResampler, ChannelMapper, SampleFormat};
+pub use callback_target::{CallbackQueue, CallbackTarget, CallbackWorker};
+pub use capture::{
+    list_video_devices, CameraFormat, CameraInfo, CaptureError, CaptureSession,
+    CaptureSessionBuilder, FrameRateRange,
+};
+pub use cf_dict::{as_dictionary_ref, CFDictBuilder, DictKey, DictValue};
+pub use channel_layout::{ChannelLayout, OpusChannelMapping};
+pub use chapters::{chpl_box, write_chapters, Chapter};
+pub use clock::{
+    host_time_clock, host_time_now, make_time, FrameTimestamper, PlaybackScheduler, Timebase,
+};
+pub use codec_string::{
+    av1_codec_string, h264_codec_string, h264_codec_string_from_bytes, hevc_codec_string,
+    mp4_mime_type, HevcProfileTierLevel,
+};
 pub use compression_builder::{CompressionSessionBuilder, CompressionSessionConfig};
+pub use compression_property::{CompressionProperty, H264EntropyMode};
+pub use compression_session::{CompressionSession, EncodeOptions, EncodeOutput, EncodeStats};
+pub use conformance::{ConformanceChecker, ConformanceReport, SpsInfo};
+pub use crop::{CropControl, CropRect, RegionCropper};
+pub use dash::{DashConfig, DashSink};
+pub use decompression_session::{
+    DecodeOptions, DecodeOutput, DecodedFrame, DecompressionSession, DecompressionSessionConfig,
+};
 pub use delegate::{
     create_capture_delegate, create_dispatch_queue, set_sample_buffer_delegate, CaptureDelegate,
     DelegateCallback,
 };
-pub use pixel_buffer::{create_pixel_buffer, PixelBufferConfig, PixelBufferGuard};
+pub use deterministic::{
+    apply_deterministic, clear_deterministic, is_deterministic, set_deterministic,
+    DETERMINISTIC_ENV, DETERMINISTIC_KEYFRAME_INTERVAL,
+};
+pub use encode_stats::{
+    read_encode_stats, EncodeStatsRecorder, EncodeStatsSink, SegmentEncodeStats, StatsBox,
+    ENCODE_STATS_SCHEME,
+};
+pub use encoded_frame::EncodedFrame;
+pub use encoder_comparison::{software_encoder_id, EncoderComparison, GopComparison, GopStats};
+pub use encoder_error::{aligned_dimensions, encoder_alignment, EncoderError, Remediation};
+pub use events::{clear_event_handler, set_event_handler, PipelineEvent};
+pub use fmp4_recorder::Fmp4Recorder;
+pub use frame_analysis::{AnalysisStage, AnalysisStats, AnalyzedFrame};
+pub use frame_broadcast::{FrameBroadcaster, FrameSubscriber, SubscriberStats};
+pub use frame_interpolator::FrameInterpolator;
+pub use frame_hash::{compare_frame, FrameHash, FrameMatch, FrameSnapshot, GoldenHashes, Plane};
+pub use hls::{HlsConfig, HlsPartConfig, HlsSink};
+pub use input_format::{negotiate_input_format, InputConverter, InputPath, ENCODER_INPUT_FORMATS};
+#[cfg(feature = "http-upload")]
+pub use http_sink::{
+    HttpPutSink, HttpTransport, PutTransport, RetryPolicy, UploadRequest, UploadStats,
+};
+pub use journal::{JournalEntry, StreamJournal};
+pub use leak_tracker::{
+    leak_tracking_enabled, live_objects, release_pixel_buffer, report_leaks, retain_pixel_buffer,
+    LeakCheck, LiveObject, TrackedKind,
+};
+pub use lossy_sink::{Impairment, LossEvent, LossModel, LossStats, LossySink};
+pub use loudness::{write_loudness_metadata, LoudnessMeasurement, LoudnessMeter};
+pub use low_latency::{LowLatencyChunk, LowLatencyConfig, LowLatencyMuxer};
+pub use metadata::{write_mp4_metadata, Location, Mp4Metadata};
+pub use mfra::{RandomAccessIndex, RandomAccessPoint};
+pub use moq_priority::{MoqPrioritizer, MoqPriorityConfig, MoqPublishHint};
+pub use motion::MotionEstimator;
+pub use mp4_writer::{AacTrackConfig, Container, FinishWriting, Mp4Writer, Mp4WriterError};
+pub use mse_page::{MsePage, MseTransport};
+#[cfg(feature = "mse-server")]
+pub use mse_server::{MseServer, MseServerConfig, MseServerStats};
+pub use multi_pass::{MultiPassEncoder, PassEncoder};
+pub use output_handler::{decode_frame_with_handler, encode_frame_with_handler};
+pub use overlay::{OverlayImage, OverlayStage};
+pub use padding::{pad_pixel_buffer, padded_dimensions, CleanAperture};
+pub use pixel_buffer::{
+    create_pixel_buffer, fill_black, PixelBufferConfig, PixelBufferGuard, PlaneView,
+};
+pub use pixel_buffer_pool::PixelBufferPool;
+pub use pixel_transfer::{DownsamplingMode, PixelTransfer, PixelTransferBuilder, ScalingMode};
+pub use playback_decoder::{PlaybackDecoder, PlaybackFrame};
+pub use profile_level::{
+    derive_level, validate as validate_profile_level, Level, Profile, ProfileLevel,
+    ProfileLevelError, StreamParams,
+};
+pub use replay_buffer::ReplayBuffer;
+#[cfg(feature = "pixel-rotation")]
+pub use rotation::{ImageRotator, OutputOrientation, Rotation};
+pub use rtp::{
+    H264Depacketizer, H264Packetizer, RtpError, RtpPacket, RtpPacketizerConfig, H264_CLOCK_RATE,
+};
+pub use rtsp::RtspClient;
 pub use runloop::{run_for_duration, run_until_some, run_while};
+pub use sample_buffer::{create_encoded_sample_buffer, SampleBufferGuard};
+pub use scene_analysis::{
+    BitratePlan, FirstPass, FramePassStats, Scene, SceneAnalysis, SegmentBitrate,
+};
+pub use scene_change::{LumaThumbnail, SceneChangeDetector, SceneChangeScore};
+#[cfg(feature = "screen-capture")]
+pub use screen_capture::{
+    create_screen_output_delegate, screen_frame_pixel_buffer, Display, ScreenCapture,
+    ScreenCaptureConfig, ShareableContent, Window,
+};
+pub use segment_reorder::{
+    fragment_sequence_number, ReorderBuffer, ReorderConfig, ReorderStats,
+};
+pub use segment_times::{
+    earliest_presentation_time, SegmentTime, SegmentTimeIndex, TimeIndexedSink,
+};
+pub use sendable::{SendablePixelBuffer, SendablePixelBufferLock, SendableSession};
+pub use session_props::{
+    copy_serializable_properties, copy_supported_property_dictionary, get_property,
+    set_properties, FromPropertyValue, PropertyBag, PropertyInfo, PropertyType, PropertyValue,
+};
+pub use shaped_sink::{Delivery, ShapedSink, ShaperClock, ShaperConfig};
+pub use shared_surface::{MachPort, SharedSurface};
+pub use simulcast::{Rendition, SimulcastEncoder};
+pub use sink::{DirectorySink, Segment, SegmentKind, SegmentSink, WriterSink};
+pub use source::{FrameSource, LoopingSource, MediaFrame, VecSource};
+pub use startup::{StartupGate, StartupPolicy, StartupState};
+pub use stream_analyzer::{
+    analyze_file, BitrateBucket, GopInfo, StreamAnalyzer, StreamReport, TimestampGap,
+    MAX_EMPTY_WINDOWS,
+};
+pub use tee_sink::{Backpressure, TeeBranchStats, TeeSink};
+pub use time_lapse::{FrameSelection, TimeLapse};
+pub use timecode::{
+    read_timecode, RecordedTimecode, Timecode, TimecodeFormat, TimecodeMuxer, TimecodeTrack,
+};
+pub use timed_metadata::{
+    read_timed_metadata, TimedMetadata, TimedMetadataMuxer, TimedMetadataSample,
+    TimedMetadataTrack,
+};
+pub use timestamp_filter::TimestampFilter;
+pub use trace::{
+    clear_trace_recorder, set_trace_recorder, trace_event, TraceEvent, TraceLogger, TraceRecorder,
+};
+pub use trim::{trim, TrimError, TrimStats};
+pub use triggered_recorder::TriggeredRecorder;
+pub use udp_sink::{UdpTsConfig, UdpTsSink, TS_PACKETS_PER_DATAGRAM, TS_PACKET_SIZE};
+pub use video_monitor::VideoMonitor;
+pub use webvtt::{VttCue, VttCueQueue, VttCueWriter};
 
 // Re-export NAL extractor types
 pub use nal_extractor::{
-    convert_time, H264ParameterSets, NalError, NalExtractor, NalUnit, SampleTiming,
-    VideoDimensions,
+    convert_time, parse_annex_b, parse_avcc, validate_nal_length_size, write_length_prefixed,
+    H264ParameterSets, HevcParameterSets, NalError, NalExtractor, NalUnit, ParameterSetSource,
+    SampleTiming, VideoDimensions,
 };
 
 // Re-export CMAF muxer types
-pub use cmaf_muxer::{CmafConfig, CmafMuxer};
+pub use cmaf_muxer::{
+    CmafConfig, CmafMuxer, FragmentEmission, MuxerState, PassthroughCodec,
+};
+
+// Re-export CMAF demuxer types
+pub use cmaf_demuxer::{CmafDemuxer, DemuxError, DemuxedTrack};
+
+// Re-export progressive MP4 muxer types; its writer stays at
+// `mp4_muxer::Mp4Writer`, apart from the AVAssetWriter-based `Mp4Writer`
+pub use mp4_muxer::{Mp4Codec, Mp4TrackConfig};
@@ -0,0 +1,194 @@
+//! Publish priorities and deadline hints for MoQ objects.
+//!
+//! A MoQ relay under congestion keeps the objects with the highest priority
+//! and drops those past their deadline. Without hints every object looks
+//! alike, so the relay may starve the init segment or a keyframe while it
+//! forwards P-frames that nobody can decode. [`MoqPrioritizer`] maps each
+//! [`LowLatencyChunk`] to a group, a priority and a deadline: init segments
+//! first, then keyframes, then the frames depending on them, with each group
+//! expiring once the next keyframe has superseded it.
+
+use std::time::Duration;
+
+use super::low_latency::LowLatencyChunk;
+
+/// Priorities (higher is more important) and deadline slack for
+/// [`MoqPrioritizer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoqPriorityConfig {
+    /// Init segments and chunks carrying one; nothing decodes without them
+    pub init: u8,
+    /// Keyframes starting a group
+    pub keyframe: u8,
+    /// Frames depending on the keyframe of their group
+    pub delta: u8,
+    /// How long a group stays worth delivering after the next keyframe
+    /// supersedes it
+    pub latency_budget: Duration,
+}
+
+impl Default for MoqPriorityConfig {
+    fn default() -> Self {
+        Self {
+            init: 255,
+            keyframe: 192,
+            delta: 128,
+            latency_budget: Duration::from_millis(500),
+        }
+    }
+}
+
+/// How to publish one object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoqPublishHint {
+    /// Group sequence number
+    pub group: u64,
+    /// The object is the first of a new group
+    pub starts_group: bool,
+    pub priority: u8,
+    /// How long after publishing the object is still worth delivering;
+    /// `None` never expires
+    pub deadline: Option<Duration>,
+}
+
+/// Assigns MoQ groups, priorities and deadline hints to published objects.
+///
+/// Each keyframe starts a group, so a relay can drop a whole stale group
+/// and a viewer can join at any group. The group's deadline is the
+/// keyframe interval plus [`MoqPriorityConfig::latency_budget`], counted
+/// down as its frames are published.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{
+///     LowLatencyConfig, LowLatencyMuxer, MoqPrioritizer, MoqPriorityConfig,
+/// };
+///
+/// let config = LowLatencyConfig::default();
+/// let interval = config.keyframe_interval;
+/// let mut prioritizer = MoqPrioritizer::new(MoqPriorityConfig::default(), interval);
+/// let mut muxer = LowLatencyMuxer::new(config);
+/// # let frame: video_toolbox_sys::helpers::MediaFrame = unimplemented!();
+/// if let Ok(Some(chunk)) = muxer.push_frame(&frame) {
+///     let hint = prioritizer.chunk(&chunk);
+///     // open group `hint.group` if `hint.starts_group`, then write
+///     // chunk.to_bytes() at `hint.priority`, expiring after `hint.deadline`
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MoqPrioritizer {
+    config: MoqPriorityConfig,
+    keyframe_interval: Duration,
+    next_group: u64,
+    current_group: Option<u64>,
+    /// Time until the current group is superseded by the next keyframe
+    group_remaining: Duration,
+}
+
+impl MoqPrioritizer {
+    /// `keyframe_interval` is the encoder's, e.g.
+    /// [`LowLatencyConfig::keyframe_interval`](super::LowLatencyConfig::keyframe_interval).
+    pub fn new(config: MoqPriorityConfig, keyframe_interval: Duration) -> Self {
+        Self {
+            config,
+            keyframe_interval,
+            next_group: 0,
+            current_group: None,
+            group_remaining: Duration::ZERO,
+        }
+    }
+
+    pub fn config(&self) -> &MoqPriorityConfig {
+        &self.config
+    }
+
+    /// Priority to create the track with: that of its keyframes, for
+    /// transports that only prioritize whole tracks.
+    pub fn track_priority(&self) -> u8 {
+        self.config.keyframe
+    }
+
+    /// A standalone init segment, published in a group of its own that
+    /// never expires.
+    pub fn init(&mut self) -> MoqPublishHint {
+        let group = self.next_group;
+        self.next_group += 1;
+        self.current_group = None;
+        MoqPublishHint {
+            group,
+            starts_group: true,
+            priority: self.config.init,
+            deadline: None,
+        }
+    }
+
+    /// A media object lasting `duration`.
+    pub fn frame(&mut self, is_keyframe: bool, duration: Duration) -> MoqPublishHint {
+        let starts_group = is_keyframe || self.current_group.is_none();
+        if starts_group {
+            self.current_group = Some(self.next_group);
+            self.next_group += 1;
+            self.group_remaining = self.keyframe_interval;
+        }
+        let deadline = self.group_remaining + self.config.latency_budget;
+        self.group_remaining = self.group_remaining.saturating_sub(duration);
+        MoqPublishHint {
+            group: self.current_group.unwrap_or_default(),
+            starts_group,
+            priority: if is_keyframe {
+                self.config.keyframe
+            } else {
+                self.config.delta
+            },
+            deadline: Some(deadline),
+        }
+    }
+
+    /// A chunk from a [`LowLatencyMuxer`](super::LowLatencyMuxer); chunks
+    /// carrying the init segment are published at the init priority.
+    pub fn chunk(&mut self, chunk: &LowLatencyChunk) -> MoqPublishHint {
+        let mut hint = self.frame(chunk.is_keyframe, chunk.duration);
+        if chunk.init_segment.is_some() {
+            hint.priority = hint.priority.max(self.config.init);
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_groups_priorities_and_deadlines() {
+        let frame = Duration::from_millis(100);
+        let mut prioritizer =
+            MoqPrioritizer::new(MoqPriorityConfig::default(), Duration::from_secs(1));
+
+        let init = prioritizer.init();
+        assert_eq!((init.group, init.priority, init.deadline), (0, 255, None));
+
+        let key = prioritizer.frame(true, frame);
+        assert!(key.starts_group);
+        assert_eq!((key.group, key.priority), (1, 192));
+        assert_eq!(key.deadline, Some(Duration::from_millis(1500)));
+
+        let delta = prioritizer.frame(false, frame);
+        assert!(!delta.starts_group);
+        assert_eq!((delta.group, delta.priority), (1, 128));
+        assert_eq!(delta.deadline, Some(Duration::from_millis(1400)));
+
+        let chunk = LowLatencyChunk {
+            init_segment: Some(vec![0; 8]),
+            fragment: vec![0; 8],
+            sequence_number: 3,
+            is_keyframe: true,
+            duration: frame,
+        };
+        let next = prioritizer.chunk(&chunk);
+        assert!(next.starts_group);
+        assert_eq!((next.group, next.priority), (2, 255));
+        assert_eq!(next.deadline, Some(Duration::from_millis(1500)));
+    }
+}
@@ -0,0 +1,191 @@
+//! Cheap per-frame motion scoring.
+//!
+//! Motion is the mean absolute difference (SAD) between downscaled luma of
+//! consecutive frames. At 64x36 this costs a few microseconds per frame, cheap
+//! enough to run on every captured frame.
+
+use std::collections::VecDeque;
+
+use super::scene_change::{mean_abs_diff, LumaThumbnail};
+use super::source::MediaFrame;
+use crate::cv_types::CVPixelBufferRef;
+
+/// Scores frames awaiting their encoded output, enough for encoder delay and
+/// reordering.
+const PENDING_CAPACITY: usize = 64;
+
+/// Measures motion between consecutive raw frames and attaches it to the
+/// corresponding encoded frames.
+///
+/// Scores range from 0 (static) to 1; sensor noise alone typically scores
+/// below 0.01. Measure each frame before encoding it, then
+/// [`annotate`](Self::annotate) the encoded [`MediaFrame`] with the same
+/// presentation timestamp, so consumers downstream of the encoder (e.g. a
+/// [`TriggeredRecorder`](super::TriggeredRecorder) trigger or a bitrate
+/// controller lowering the rate for static scenes) see the score as frame
+/// metadata.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{MediaFrame, MotionEstimator};
+/// # fn frame(pixel_buffer: video_toolbox_sys::cv_types::CVPixelBufferRef, pts: i64, mut encoded: MediaFrame) {
+/// let mut motion = MotionEstimator::new();
+///
+/// // Capture path, before encoding:
+/// let score = unsafe { motion.push_pixel_buffer(pts, pixel_buffer) };
+///
+/// // Encoder output path:
+/// motion.annotate(&mut encoded);
+/// println!("motion {:?}", encoded.motion_score);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MotionEstimator {
+    thumb_width: usize,
+    thumb_height: usize,
+    previous: Option<LumaThumbnail>,
+    pending: VecDeque<(i64, f32)>,
+    last_score: Option<f32>,
+}
+
+impl Default for MotionEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MotionEstimator {
+    /// Create an estimator comparing 64x36 thumbnails.
+    pub fn new() -> Self {
+        Self {
+            thumb_width: 64,
+            thumb_height: 36,
+            previous: None,
+            pending: VecDeque::new(),
+            last_score: None,
+        }
+    }
+
+    /// Thumbnail size used for comparison (default: 64x36).
+    pub fn thumbnail_size(mut self, width: usize, height: usize) -> Self {
+        self.thumb_width = width.max(1);
+        self.thumb_height = height.max(1);
+        self
+    }
+
+    /// Score of the most recent frame.
+    pub fn last_score(&self) -> Option<f32> {
+        self.last_score
+    }
+
+    /// Score a thumbnail of the frame with presentation time `pts` against
+    /// the previous frame. The first frame (and one after a size change)
+    /// scores 0.
+    pub fn push_thumbnail(&mut self, pts: i64, thumbnail: LumaThumbnail) -> f32 {
+        let score = match &self.previous {
+            Some(previous) if previous.data.len() == thumbnail.data.len() => {
+                mean_abs_diff(previous, &thumbnail)
+            }
+            _ => 0.0,
+        };
+        self.previous = Some(thumbnail);
+        self.last_score = Some(score);
+
+        if self.pending.len() == PENDING_CAPACITY {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((pts, score));
+        score
+    }
+
+    /// Score a pixel buffer with presentation time `pts`.
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid CVPixelBuffer.
+    pub unsafe fn push_pixel_buffer(
+        &mut self,
+        pts: i64,
+        pixel_buffer: CVPixelBufferRef,
+    ) -> Result<f32, i32> {
+        let thumbnail =
+            LumaThumbnail::from_pixel_buffer(pixel_buffer, self.thumb_width, self.thumb_height)?;
+        Ok(self.push_thumbnail(pts, thumbnail))
+    }
+
+    /// Attach the score measured for `frame`'s presentation timestamp.
+    ///
+    /// Returns the score, or `None` if the frame was never measured (or was
+    /// measured too long ago).
+    pub fn annotate(&mut self, frame: &mut MediaFrame) -> Option<f32> {
+        let pos = self
+            .pending
+            .iter()
+            .position(|&(pts, _)| pts == frame.timing.pts)?;
+        let (_, score) = self.pending.remove(pos)?;
+        frame.motion_score = Some(score);
+        Some(score)
+    }
+
+    /// Forget the previous frame and pending scores (e.g., after a source switch).
+    pub fn reset(&mut self) {
+        self.previous = None;
+        self.pending.clear();
+        self.last_score = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::SampleTiming;
+
+    fn uniform(value: u8) -> LumaThumbnail {
+        LumaThumbnail {
+            width: 4,
+            height: 4,
+            data: vec![value; 16],
+        }
+    }
+
+    fn encoded(pts: i64) -> MediaFrame {
+        MediaFrame {
+            nal_units: Vec::new(),
+            timing: SampleTiming {
+                pts,
+                dts: pts,
+                duration: 1,
+                timescale: 30,
+            },
+            is_keyframe: false,
+            motion_score: None,
+        }
+    }
+
+    #[test]
+    fn test_motion_score_is_mean_abs_diff() {
+        let mut motion = MotionEstimator::new();
+        assert_eq!(motion.push_thumbnail(0, uniform(100)), 0.0);
+        assert_eq!(motion.push_thumbnail(1, uniform(100)), 0.0);
+        let score = motion.push_thumbnail(2, uniform(151));
+        assert!((score - 0.2).abs() < 1e-6);
+        assert_eq!(motion.last_score(), Some(score));
+    }
+
+    #[test]
+    fn test_annotate_matches_reordered_output() {
+        let mut motion = MotionEstimator::new();
+        motion.push_thumbnail(0, uniform(0));
+        motion.push_thumbnail(1, uniform(51));
+        motion.push_thumbnail(2, uniform(51));
+
+        // B-frame reordering: output order 0, 2, 1
+        let mut frame = encoded(2);
+        assert_eq!(motion.annotate(&mut frame), Some(0.0));
+        let mut frame = encoded(1);
+        motion.annotate(&mut frame);
+        assert!((frame.motion_score.unwrap() - 0.2).abs() < 1e-6);
+        assert_eq!(motion.annotate(&mut encoded(1)), None);
+    }
+}
@@ -0,0 +1,731 @@
+//! Progressive (non-fragmented) MP4 muxer for H.264 and HEVC.
+//!
+//! [`CmafMuxer`](super::CmafMuxer) writes self-contained fragments for
+//! streaming; this module writes a regular MP4 instead, with a single `mdat`
+//! and a `moov` whose sample tables (`stts`, `ctts`, `stss`, `stsc`, `stsz`
+//! and `stco`/`co64`) describe every sample. Players and editors that expect
+//! a plain file handle it without fragment support, and no AVAssetWriter
+//! (see [`Mp4Writer`](super::Mp4Writer)) is involved.
+//!
+//! ```text
+//! ftyp
+//! mdat (samples, appended as they arrive)
+//! moov (sample tables, written by finish())
+//! ```
+//!
+//! # Example
+//!
+//! ```no_run
+//! use video_toolbox_sys::helpers::mp4_muxer::{Mp4Codec, Mp4TrackConfig, Mp4Writer};
+//!
+//! # let parameter_sets: video_toolbox_sys::helpers::H264ParameterSets = unimplemented!();
+//! let track = Mp4TrackConfig::new(Mp4Codec::H264(parameter_sets), 1920, 1080);
+//! let mut writer = Mp4Writer::create("recording.mp4", track)?;
+//! // for frame in frames { writer.push(&frame)?; }
+//! writer.finish()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use super::audio_cmaf::write_box;
+use super::metadata::Mp4Metadata;
+use super::nal_extractor::{write_length_prefixed, H264ParameterSets, HevcParameterSets};
+use super::padding::CleanAperture;
+use super::source::MediaFrame;
+
+/// Codec and parameter sets of the video track.
+#[derive(Debug, Clone)]
+pub enum Mp4Codec {
+    H264(H264ParameterSets),
+    Hevc(HevcParameterSets),
+}
+
+impl Mp4Codec {
+    fn nal_length_size(&self) -> usize {
+        let size = match self {
+            Mp4Codec::H264(sets) => sets.nal_length_size,
+            Mp4Codec::Hevc(sets) => sets.nal_length_size,
+        };
+        size.clamp(1, 4) as usize
+    }
+
+    /// Whether a NAL unit belongs in the samples; parameter sets are carried
+    /// in the sample entry instead.
+    fn is_sample_nal(&self, nal: &[u8]) -> bool {
+        let Some(&header) = nal.first() else {
+            return false;
+        };
+        match self {
+            Mp4Codec::H264(_) => !matches!(header & 0x1F, 7..=9),
+            // VPS, SPS, PPS and access unit delimiters
+            Mp4Codec::Hevc(_) => !matches!((header >> 1) & 0x3F, 32..=35),
+        }
+    }
+}
+
+/// The video track of an [`Mp4Writer`].
+#[derive(Debug, Clone)]
+pub struct Mp4TrackConfig {
+    pub codec: Mp4Codec,
+    pub width: u32,
+    pub height: u32,
+    /// Timescale of the pushed timestamps (default 90000)
+    pub timescale: u32,
+    /// Visible size of frames padded to the encoder's alignment
+    pub clean_aperture: Option<CleanAperture>,
+}
+
+impl Mp4TrackConfig {
+    pub fn new(codec: Mp4Codec, width: u32, height: u32) -> Self {
+        Self {
+            codec,
+            width,
+            height,
+            timescale: 90000,
+            clean_aperture: None,
+        }
+    }
+
+    /// Crop padded frames to `width` x `height` on playback (see
+    /// [`pad_pixel_buffer`](crate::helpers::pad_pixel_buffer)).
+    pub fn clean_aperture(mut self, width: u32, height: u32) -> Self {
+        self.clean_aperture = Some(CleanAperture::new(width, height));
+        self
+    }
+
+    /// Display size: the clean aperture if it crops the coded size.
+    fn display_size(&self) -> (u32, u32) {
+        match self.clean_aperture {
+            Some(aperture) if aperture.crops(self.width, self.height) => {
+                (aperture.width, aperture.height)
+            }
+            _ => (self.width, self.height),
+        }
+    }
+}
+
+/// A sample as recorded in the sample tables.
+#[derive(Debug, Clone, Copy)]
+struct SampleEntry {
+    size: u32,
+    duration: u32,
+    /// Presentation minus decode time
+    composition_offset: i32,
+    is_keyframe: bool,
+}
+
+/// A run of samples stored back to back in `mdat`.
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    offset: u64,
+    samples: u32,
+    /// 1-based index into the sample descriptions
+    description: u32,
+}
+
+/// Writes encoded video to a progressive MP4.
+///
+/// Sample data goes to the `mdat` as each frame is pushed, so memory use is
+/// bounded by the sample tables; the `moov` is appended by
+/// [`finish`](Self::finish) (or when the writer is dropped). Until then the
+/// file is not playable: use [`Fmp4Recorder`](super::Fmp4Recorder) when the
+/// recording must survive a crash.
+///
+/// Frames must be pushed in decode order. Frames before the first keyframe
+/// are skipped so the file starts decodable, and a new chunk starts at each
+/// keyframe.
+pub struct Mp4Writer<W: Write + Seek = BufWriter<File>> {
+    writer: W,
+    track: Mp4TrackConfig,
+    /// Sample descriptions after the track's own, added by
+    /// [`set_codec`](Self::set_codec)
+    codecs: Vec<Mp4Codec>,
+    metadata: Option<Mp4Metadata>,
+    /// File offset of the `mdat` header
+    mdat_offset: u64,
+    /// Current write position
+    position: u64,
+    samples: Vec<SampleEntry>,
+    chunks: Vec<Chunk>,
+    first_dts: Option<i64>,
+    last_dts: i64,
+    finished: bool,
+}
+
+impl Mp4Writer {
+    /// Create (or truncate) the output file.
+    pub fn create(path: impl AsRef<Path>, track: Mp4TrackConfig) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), track)
+    }
+}
+
+impl<W: Write + Seek> Mp4Writer<W> {
+    /// Write the file header to `writer` at its current position.
+    pub fn new(mut writer: W, track: Mp4TrackConfig) -> io::Result<Self> {
+        let start = writer.stream_position()?;
+        let brand = match track.codec {
+            Mp4Codec::H264(_) => b"avc1",
+            Mp4Codec::Hevc(_) => b"hvc1",
+        };
+        let mut header = Vec::new();
+        let mut ftyp = Vec::new();
+        ftyp.extend_from_slice(b"isom"); // major brand
+        ftyp.extend_from_slice(&0x200u32.to_be_bytes()); // minor version
+        for compatible in [b"isom", b"iso2", brand, b"mp41"] {
+            ftyp.extend_from_slice(compatible);
+        }
+        write_box(&mut header, b"ftyp", &ftyp);
+
+        // 64-bit mdat header, its size patched by finish()
+        let mdat_offset = start + header.len() as u64;
+        header.extend_from_slice(&1u32.to_be_bytes());
+        header.extend_from_slice(b"mdat");
+        header.extend_from_slice(&0u64.to_be_bytes());
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer,
+            track,
+            codecs: Vec::new(),
+            metadata: None,
+            mdat_offset,
+            position: start + header.len() as u64,
+            samples: Vec::new(),
+            chunks: Vec::new(),
+            first_dts: None,
+            last_dts: 0,
+            finished: false,
+        })
+    }
+
+    /// Title, date, location and other metadata for the file (see
+    /// [`Mp4Metadata`]).
+    pub fn metadata(mut self, metadata: Mp4Metadata) -> Self {
+        self.metadata = (!metadata.is_empty()).then_some(metadata);
+        self
+    }
+
+    pub fn track(&self) -> &Mp4TrackConfig {
+        &self.track
+    }
+
+    /// Frames written so far.
+    pub fn frames(&self) -> u64 {
+        self.samples.len() as u64
+    }
+
+    /// Media duration written so far.
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.duration_ticks() as f64 / self.track.timescale.max(1) as f64)
+    }
+
+    fn duration_ticks(&self) -> u64 {
+        self.samples.iter().map(|s| s.duration as u64).sum()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Describe the following frames with other parameter sets, e.g. a
+    /// re-encoded section in a stream-copied file. Each call adds a sample
+    /// description; the next frame starts a new chunk and must be a
+    /// keyframe for decoders to switch cleanly.
+    pub fn set_codec(&mut self, codec: Mp4Codec) {
+        self.codecs.push(codec);
+    }
+
+    /// Codec of the frames pushed next.
+    fn codec(&self) -> &Mp4Codec {
+        self.codecs.last().unwrap_or(&self.track.codec)
+    }
+
+    /// 1-based index of the current sample description.
+    fn description(&self) -> u32 {
+        self.codecs.len() as u32 + 1
+    }
+
+    /// Add the next encoded frame (in decode order). Parameter set NAL
+    /// units are left out; they are in the sample entry.
+    pub fn push(&mut self, frame: &MediaFrame) -> io::Result<()> {
+        let codec = self.codec();
+        let length_size = codec.nal_length_size();
+        let mut data = Vec::new();
+        for nal in &frame.nal_units {
+            if codec.is_sample_nal(&nal.data) {
+                write_length_prefixed(&mut data, &nal.data, length_size)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+        }
+        self.add_sample(
+            &data,
+            frame.timing.pts,
+            frame.timing.dts,
+            frame.timing.duration as u32,
+            frame.is_keyframe,
+        )
+    }
+
+    /// Add a frame whose data is already length-prefixed NAL units, with
+    /// the current codec's NAL length size. Timestamps and `duration` are in the
+    /// track's timescale.
+    pub fn add_sample(
+        &mut self,
+        data: &[u8],
+        pts: i64,
+        dts: i64,
+        duration: u32,
+        is_keyframe: bool,
+    ) -> io::Result<()> {
+        if self.finished {
+            return Err(io::Error::other("recording already finished"));
+        }
+        if self.first_dts.is_none() && !is_keyframe {
+            return Ok(());
+        }
+        if self.first_dts.is_some() && dts < self.last_dts {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frames must be added in decode order",
+            ));
+        }
+
+        self.writer.write_all(data)?;
+        let description = self.description();
+        match self.chunks.last_mut() {
+            Some(chunk) if !is_keyframe && chunk.description == description => chunk.samples += 1,
+            _ => self.chunks.push(Chunk {
+                offset: self.position,
+                samples: 1,
+                description,
+            }),
+        }
+        self.position += data.len() as u64;
+        self.first_dts.get_or_insert(dts);
+        self.last_dts = dts;
+        self.samples.push(SampleEntry {
+            size: data.len() as u32,
+            duration,
+            composition_offset: (pts - dts) as i32,
+            is_keyframe,
+        });
+        Ok(())
+    }
+
+    /// Patch the `mdat` size and append the `moov`. Later calls do nothing.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let mdat_size = self.position - self.mdat_offset;
+        self.writer.seek(SeekFrom::Start(self.mdat_offset + 8))?;
+        self.writer.write_all(&mdat_size.to_be_bytes())?;
+        self.writer.seek(SeekFrom::Start(self.position))?;
+
+        let mut moov = Vec::new();
+        self.write_moov(&mut moov)?;
+        self.writer.write_all(&moov)?;
+        self.position += moov.len() as u64;
+        self.writer.flush()
+    }
+
+    fn write_moov(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let duration = self.duration_ticks();
+        let mut content = Vec::new();
+
+        let mut mvhd = vec![1, 0, 0, 0]; // version 1 (64-bit times)
+        let created = self.metadata.as_ref().and_then(|m| m.mp4_creation_time());
+        let created = created.unwrap_or(0) as u64;
+        mvhd.extend_from_slice(&created.to_be_bytes()); // creation time
+        mvhd.extend_from_slice(&created.to_be_bytes()); // modification time
+        mvhd.extend_from_slice(&self.track.timescale.to_be_bytes());
+        mvhd.extend_from_slice(&duration.to_be_bytes());
+        mvhd.extend_from_slice(&0x00010000u32.to_be_bytes()); // rate (1.0)
+        mvhd.extend_from_slice(&0x0100u16.to_be_bytes()); // volume (1.0)
+        mvhd.extend_from_slice(&[0; 10]); // reserved
+        write_matrix(&mut mvhd);
+        mvhd.extend_from_slice(&[0; 24]); // pre_defined
+        mvhd.extend_from_slice(&2u32.to_be_bytes()); // next_track_id
+        write_box(&mut content, b"mvhd", &mvhd);
+
+        self.write_trak(&mut content, duration)?;
+
+        if let Some(metadata) = &self.metadata {
+            content.extend_from_slice(&metadata.udta_box());
+        }
+        write_box(buf, b"moov", &content);
+        Ok(())
+    }
+
+    fn write_trak(&self, buf: &mut Vec<u8>, duration: u64) -> io::Result<()> {
+        let mut content = Vec::new();
+
+        let mut tkhd = vec![1, 0, 0, 3]; // version 1, track enabled and in movie
+        tkhd.extend_from_slice(&[0; 16]); // creation and modification time
+        tkhd.extend_from_slice(&1u32.to_be_bytes()); // track id
+        tkhd.extend_from_slice(&0u32.to_be_bytes()); // reserved
+        tkhd.extend_from_slice(&duration.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 8]); // reserved
+        tkhd.extend_from_slice(&[0; 8]); // layer, alternate_group, volume, reserved
+        write_matrix(&mut tkhd);
+        let (width, height) = self.track.display_size();
+        tkhd.extend_from_slice(&(width << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(height << 16).to_be_bytes());
+        write_box(&mut content, b"tkhd", &tkhd);
+
+        // Start presentation at the first frame's composition time, so
+        // B-frame reordering delay does not show as an initial gap
+        let initial_delay = self.samples.first().map_or(0, |s| s.composition_offset);
+        if initial_delay > 0 {
+            let mut elst = vec![1, 0, 0, 0]; // version 1
+            elst.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+            elst.extend_from_slice(&duration.to_be_bytes()); // segment_duration
+            elst.extend_from_slice(&(initial_delay as i64).to_be_bytes()); // media_time
+            elst.extend_from_slice(&0x00010000u32.to_be_bytes()); // media_rate (1.0)
+            let mut edts = Vec::new();
+            write_box(&mut edts, b"elst", &elst);
+            write_box(&mut content, b"edts", &edts);
+        }
+
+        let mut mdia = Vec::new();
+        let mut mdhd = vec![1, 0, 0, 0]; // version 1
+        mdhd.extend_from_slice(&[0; 16]); // creation and modification time
+        mdhd.extend_from_slice(&self.track.timescale.to_be_bytes());
+        mdhd.extend_from_slice(&duration.to_be_bytes());
+        mdhd.extend_from_slice(&0x55c4u16.to_be_bytes()); // language (und)
+        mdhd.extend_from_slice(&0u16.to_be_bytes()); // pre_defined
+        write_box(&mut mdia, b"mdhd", &mdhd);
+
+        let mut hdlr = vec![0; 8]; // version, flags, pre_defined
+        hdlr.extend_from_slice(b"vide");
+        hdlr.extend_from_slice(&[0; 12]); // reserved
+        hdlr.extend_from_slice(b"VideoHandler\0");
+        write_box(&mut mdia, b"hdlr", &hdlr);
+
+        let mut minf = Vec::new();
+        write_box(&mut minf, b"vmhd", &[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut dref = vec![0, 0, 0, 0];
+        dref.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        write_box(&mut dref, b"url ", &[0, 0, 0, 1]); // self-contained
+        let mut dinf = Vec::new();
+        write_box(&mut dinf, b"dref", &dref);
+        write_box(&mut minf, b"dinf", &dinf);
+        self.write_stbl(&mut minf)?;
+        write_box(&mut mdia, b"minf", &minf);
+
+        write_box(&mut content, b"mdia", &mdia);
+        write_box(buf, b"trak", &content);
+        Ok(())
+    }
+
+    fn write_stbl(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let mut content = Vec::new();
+
+        let mut stsd = vec![0, 0, 0, 0];
+        stsd.extend_from_slice(&self.description().to_be_bytes()); // entry_count
+        for codec in std::iter::once(&self.track.codec).chain(&self.codecs) {
+            self.write_sample_entry(&mut stsd, codec)?;
+        }
+        write_box(&mut content, b"stsd", &stsd);
+
+        let durations = runs(self.samples.iter().map(|s| s.duration));
+        write_box(&mut content, b"stts", &table(0, &durations));
+
+        if self.samples.iter().any(|s| s.composition_offset != 0) {
+            // Version 1: signed offsets
+            let offsets = runs(self.samples.iter().map(|s| s.composition_offset as u32));
+            write_box(&mut content, b"ctts", &table(1, &offsets));
+        }
+
+        if self.samples.iter().any(|s| !s.is_keyframe) {
+            let sync: Vec<u32> = (1..)
+                .zip(&self.samples)
+                .filter(|(_, s)| s.is_keyframe)
+                .map(|(number, _)| number)
+                .collect();
+            let mut stss = vec![0, 0, 0, 0];
+            stss.extend_from_slice(&(sync.len() as u32).to_be_bytes());
+            for number in sync {
+                stss.extend_from_slice(&number.to_be_bytes());
+            }
+            write_box(&mut content, b"stss", &stss);
+        }
+
+        // One entry per change in samples per chunk or sample description:
+        // first_chunk, samples_per_chunk, sample_description_index
+        let mut stsc = vec![0, 0, 0, 0];
+        let mut entries: Vec<(u32, u32, u32)> = Vec::new();
+        for (number, chunk) in (1u32..).zip(&self.chunks) {
+            let run = Some((chunk.samples, chunk.description));
+            if entries.last().map(|&(_, samples, index)| (samples, index)) != run {
+                entries.push((number, chunk.samples, chunk.description));
+            }
+        }
+        stsc.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for (first_chunk, samples, description) in entries {
+            stsc.extend_from_slice(&first_chunk.to_be_bytes());
+            stsc.extend_from_slice(&samples.to_be_bytes());
+            stsc.extend_from_slice(&description.to_be_bytes());
+        }
+        write_box(&mut content, b"stsc", &stsc);
+
+        let mut stsz = vec![0; 8]; // version, flags, sample_size (varies)
+        stsz.extend_from_slice(&(self.samples.len() as u32).to_be_bytes());
+        for sample in &self.samples {
+            stsz.extend_from_slice(&sample.size.to_be_bytes());
+        }
+        write_box(&mut content, b"stsz", &stsz);
+
+        let mut offsets = vec![0, 0, 0, 0];
+        offsets.extend_from_slice(&(self.chunks.len() as u32).to_be_bytes());
+        if self.position > u32::MAX as u64 {
+            for chunk in &self.chunks {
+                offsets.extend_from_slice(&chunk.offset.to_be_bytes());
+            }
+            write_box(&mut content, b"co64", &offsets);
+        } else {
+            for chunk in &self.chunks {
+                offsets.extend_from_slice(&(chunk.offset as u32).to_be_bytes());
+            }
+            write_box(&mut content, b"stco", &offsets);
+        }
+
+        write_box(buf, b"stbl", &content);
+        Ok(())
+    }
+
+    fn write_sample_entry(&self, buf: &mut Vec<u8>, codec: &Mp4Codec) -> io::Result<()> {
+        let mut content = Vec::new();
+        content.extend_from_slice(&[0; 6]); // reserved
+        content.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+        content.extend_from_slice(&[0; 16]); // pre_defined, reserved
+        content.extend_from_slice(&(self.track.width as u16).to_be_bytes());
+        content.extend_from_slice(&(self.track.height as u16).to_be_bytes());
+        content.extend_from_slice(&0x00480000u32.to_be_bytes()); // horiz resolution 72 dpi
+        content.extend_from_slice(&0x00480000u32.to_be_bytes()); // vert resolution 72 dpi
+        content.extend_from_slice(&0u32.to_be_bytes()); // reserved
+        content.extend_from_slice(&1u16.to_be_bytes()); // frame_count
+        let mut compressor = [0u8; 32];
+        let name = b"video-toolbox-sys";
+        compressor[0] = name.len() as u8;
+        compressor[1..1 + name.len()].copy_from_slice(name);
+        content.extend_from_slice(&compressor);
+        content.extend_from_slice(&0x0018u16.to_be_bytes()); // depth (24-bit)
+        content.extend_from_slice(&(-1i16).to_be_bytes()); // pre_defined
+
+        let sample_entry = match codec {
+            Mp4Codec::H264(sets) => {
+                write_box(&mut content, b"avcC", &avcc_record(sets));
+                b"avc1"
+            }
+            Mp4Codec::Hevc(sets) => {
+                let hvcc = sets.hvcc_record().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "malformed HEVC SPS")
+                })?;
+                write_box(&mut content, b"hvcC", &hvcc);
+                b"hvc1"
+            }
+        };
+        if let Some(aperture) = self.track.clean_aperture {
+            if aperture.crops(self.track.width, self.track.height) {
+                content.extend_from_slice(&aperture.clap_box(self.track.width, self.track.height));
+            }
+        }
+        write_box(buf, sample_entry, &content);
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for Mp4Writer<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// An `AVCDecoderConfigurationRecord` (the avcC payload).
+fn avcc_record(sets: &H264ParameterSets) -> Vec<u8> {
+    let profile_level = sets.sps.get(1..4).unwrap_or(&[0x64, 0, 0x1f]);
+    let mut record = vec![1];
+    record.extend_from_slice(profile_level);
+    record.push(0xFC | (sets.nal_length_size.clamp(1, 4) as u8 - 1));
+    record.push(0xE1); // one SPS
+    record.extend_from_slice(&(sets.sps.len() as u16).to_be_bytes());
+    record.extend_from_slice(&sets.sps);
+    record.push(1); // one PPS
+    record.extend_from_slice(&(sets.pps.len() as u16).to_be_bytes());
+    record.extend_from_slice(&sets.pps);
+    record
+}
+
+fn write_matrix(buf: &mut Vec<u8>) {
+    let matrix: [u32; 9] = [0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000];
+    for m in &matrix {
+        buf.extend_from_slice(&m.to_be_bytes());
+    }
+}
+
+/// Run-length encode values as (count, value) pairs.
+fn runs(values: impl Iterator<Item = u32>) -> Vec<(u32, u32)> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+/// Full box content of an `stts`/`ctts` style (count, value) table.
+fn table(version: u8, entries: &[(u32, u32)]) -> Vec<u8> {
+    let mut content = vec![version, 0, 0, 0];
+    content.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for (count, value) in entries {
+        content.extend_from_slice(&count.to_be_bytes());
+        content.extend_from_slice(&value.to_be_bytes());
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::mfra::{find_box, read_u32};
+    use crate::helpers::{NalUnit, SampleTiming};
+    use std::io::Cursor;
+
+    fn frame(index: i64) -> MediaFrame {
+        let is_keyframe = index % 5 == 0;
+        let header = if is_keyframe { 0x65 } else { 0x41 };
+        MediaFrame {
+            nal_units: vec![
+                NalUnit {
+                    data: vec![0x09, 0xf0],
+                    nal_type: 9,
+                },
+                NalUnit {
+                    data: vec![header, index as u8, 0xaa],
+                    nal_type: header & 0x1F,
+                },
+            ],
+            timing: SampleTiming {
+                pts: index * 100,
+                dts: index * 100,
+                duration: 100,
+                timescale: 1000,
+            },
+            is_keyframe,
+            motion_score: None,
+        }
+    }
+
+    #[test]
+    fn test_sample_tables() {
+        let sets = H264ParameterSets {
+            sps: vec![0x67, 0x64, 0x00, 0x1f],
+            pps: vec![0x68, 0xee],
+            nal_length_size: 4,
+        };
+        let mut track = Mp4TrackConfig::new(Mp4Codec::H264(sets), 64, 64);
+        track.timescale = 1000;
+        let mut writer = Mp4Writer::new(Cursor::new(Vec::new()), track).unwrap();
+        // The first frame is not a keyframe and is skipped
+        for i in 4..15 {
+            writer.push(&frame(i)).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(writer.frames(), 10);
+        assert_eq!(writer.duration(), Duration::from_secs(1));
+
+        let file = writer.get_ref().get_ref().clone();
+        let mdat = find_box(&file, 0, file.len(), b"mdat").unwrap();
+        // 64-bit size, and each sample is one length-prefixed slice
+        assert_eq!(mdat.size, 16 + 10 * 7);
+        let moov = find_box(&file, 0, file.len(), b"moov").unwrap();
+        let stbl = [b"trak", b"mdia", b"minf", b"stbl"]
+            .into_iter()
+            .try_fold(moov, |parent, kind| {
+                find_box(&file, parent.content(), parent.end(), kind)
+            })
+            .unwrap();
+        let field = |kind: &[u8; 4], offset: usize| {
+            let b = find_box(&file, stbl.content(), stbl.end(), kind).unwrap();
+            read_u32(&file, b.content() + offset).unwrap()
+        };
+        // stts: one run of 10 samples of 100
+        assert_eq!(
+            (field(b"stts", 4), field(b"stts", 8), field(b"stts", 12)),
+            (1, 10, 100)
+        );
+        // stss: samples 1 and 6 (frames 5 and 10)
+        assert_eq!(
+            (field(b"stss", 4), field(b"stss", 8), field(b"stss", 12)),
+            (2, 1, 6)
+        );
+        // Two chunks of 5 samples, each starting at a keyframe
+        assert_eq!(field(b"stsz", 8), 10);
+        assert_eq!(field(b"stco", 4), 2);
+        assert_eq!(field(b"stco", 8) as usize, mdat.offset + 16);
+        assert_eq!((field(b"stsc", 4), field(b"stsc", 12)), (1, 5));
+        assert!(find_box(&file, stbl.content(), stbl.end(), b"ctts").is_none());
+    }
+
+    #[test]
+    fn test_switch_sample_description() {
+        let sets = |pps: u8| H264ParameterSets {
+            sps: vec![0x67, 0x64, 0x00, 0x1f],
+            pps: vec![0x68, pps],
+            nal_length_size: 4,
+        };
+        let mut track = Mp4TrackConfig::new(Mp4Codec::H264(sets(0xee)), 64, 64);
+        track.timescale = 1000;
+        let mut writer = Mp4Writer::new(Cursor::new(Vec::new()), track).unwrap();
+        for i in 0..5 {
+            writer.push(&frame(i)).unwrap();
+        }
+        writer.set_codec(Mp4Codec::H264(sets(0xce)));
+        for i in 5..15 {
+            writer.push(&frame(i)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let file = writer.get_ref().get_ref().clone();
+        let moov = find_box(&file, 0, file.len(), b"moov").unwrap();
+        let stbl = [b"trak", b"mdia", b"minf", b"stbl"]
+            .into_iter()
+            .try_fold(moov, |parent, kind| {
+                find_box(&file, parent.content(), parent.end(), kind)
+            })
+            .unwrap();
+        let field = |kind: &[u8; 4], offset: usize| {
+            let b = find_box(&file, stbl.content(), stbl.end(), kind).unwrap();
+            read_u32(&file, b.content() + offset).unwrap()
+        };
+        // Two avc1 entries; chunk 1 uses the first, chunks 2 and 3 the second
+        assert_eq!(field(b"stsd", 4), 2);
+        let stsd = find_box(&file, stbl.content(), stbl.end(), b"stsd").unwrap();
+        let first = find_box(&file, stsd.content() + 8, stsd.end(), b"avc1").unwrap();
+        let second = find_box(&file, first.end(), stsd.end(), b"avc1").unwrap();
+        for (entry, pps) in [(first, 0xee), (second, 0xce)] {
+            // avcC follows the 78 bytes of visual sample entry fields
+            let avcc = find_box(&file, entry.content() + 78, entry.end(), b"avcC").unwrap();
+            assert_eq!(file[avcc.end() - 1], pps);
+        }
+        assert_eq!(field(b"stco", 4), 3);
+        assert_eq!(field(b"stsc", 4), 2);
+        assert_eq!(
+            (field(b"stsc", 8), field(b"stsc", 12), field(b"stsc", 16)),
+            (1, 5, 1)
+        );
+        assert_eq!(
+            (field(b"stsc", 20), field(b"stsc", 24), field(b"stsc", 28)),
+            (2, 5, 2)
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! MP4/MOV file writing through AVAssetWriter.
+//!
+//! Encoded video is written as is (passthrough); captured audio is encoded
+//! to AAC by the writer. Unlike [`Fmp4Recorder`](super::Fmp4Recorder), the
+//! sample tables are only written by [`Mp4Writer::finish`], so a recording
+//! cut short by a crash is lost.
+
+use block2::RcBlock;
+use core_foundation::base::TCFType;
+use core_foundation_sys::base::OSStatus;
+use core_media_sys::{CMFormatDescriptionRef, CMSampleBufferRef};
+use objc2::rc::Retained;
+use objc2::runtime::AnyObject;
+use objc2_foundation::{NSString, NSURL};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use super::cf_dict::CFDictBuilder;
+use super::channel_layout::ChannelLayout;
+use crate::av_asset_writer::{
+    AVAssetWriterInput_appendSampleBuffer, AVAssetWriterInput_assetWriterInputWithMediaType,
+    AVAssetWriterInput_assetWriterInputWithMediaType_sourceFormatHint,
+    AVAssetWriterInput_isReadyForMoreMediaData, AVAssetWriterInput_markAsFinished,
+    AVAssetWriterInput_setExpectsMediaDataInRealTime, AVAssetWriterStatusCompleted,
+    AVAssetWriterStatusFailed, AVAssetWriter_addInput, AVAssetWriter_assetWriterWithURL,
+    AVAssetWriter_error, AVAssetWriter_finishWriting, AVAssetWriter_startSessionAtSourceTime,
+    AVAssetWriter_startWriting, AVAssetWriter_status, AVErrorUnknown, AVFileTypeAppleM4A,
+    AVFileTypeMPEG4, AVFileTypeQuickTimeMovie,
+};
+use crate::av_capture::{AVMediaTypeAudio, AVMediaTypeVideo};
+use crate::cm_sample_buffer::{
+    CMFormatDescriptionGetMediaType, CMSampleBufferGetFormatDescription,
+    CMSampleBufferGetPresentationTimeStamp,
+};
+use crate::codecs;
+
+/// `kCMMediaType_Video`
+const MEDIA_TYPE_VIDEO: u32 = 0x76696465;
+/// `kCMMediaType_Audio`
+const MEDIA_TYPE_AUDIO: u32 = 0x736f756e;
+
+/// File format written by [`Mp4Writer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Container {
+    /// QuickTime movie (`.mov`)
+    #[default]
+    Mov,
+    /// MPEG-4 (`.mp4`)
+    Mp4,
+    /// Audio-only MPEG-4 (`.m4a`)
+    M4a,
+}
+
+impl Container {
+    fn file_type(&self) -> &'static NSString {
+        unsafe {
+            match self {
+                Container::Mov => AVFileTypeQuickTimeMovie,
+                Container::Mp4 => AVFileTypeMPEG4,
+                Container::M4a => AVFileTypeAppleM4A,
+            }
+        }
+    }
+}
+
+/// AAC encoding settings for [`Mp4Writer::add_audio_track_aac`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AacTrackConfig {
+    /// Sample rate of the captured audio in Hz
+    pub sample_rate: f64,
+    pub channel_layout: ChannelLayout,
+    /// Bits per second
+    pub bitrate: i32,
+}
+
+impl Default for AacTrackConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48000.0,
+            channel_layout: ChannelLayout::Stereo,
+            bitrate: 128_000,
+        }
+    }
+}
+
+/// Errors from [`Mp4Writer`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mp4WriterError {
+    /// AVAssetWriter failed with this `AVError` code
+    Writer(OSStatus),
+    /// The writer does not accept this track, e.g. a second video track
+    TrackRejected,
+    /// Tracks must be added before the first sample is appended
+    AlreadyStarted,
+    /// No track for the sample buffer's media type (the FourCC)
+    NoTrack(u32),
+}
+
+impl std::fmt::Display for Mp4WriterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mp4WriterError::Writer(code) => write!(f, "asset writer failed: {}", code),
+            Mp4WriterError::TrackRejected => write!(f, "asset writer rejected the track"),
+            Mp4WriterError::AlreadyStarted => {
+                write!(f, "tracks must be added before the first sample")
+            }
+            Mp4WriterError::NoTrack(media_type) => {
+                write!(f, "no track for media type {:#010x}", media_type)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Mp4WriterError {}
+
+/// Writes encoded video and captured audio sample buffers to an MP4 or MOV
+/// file.
+///
+/// Add the tracks, then [`append`](Self::append) sample buffers from the
+/// encoder and capture callbacks; each goes to the track of its media type.
+/// The file starts at the first appended sample, or with a passthrough video
+/// track, at the first video sample. [`finish`](Self::finish)
+/// writes the sample tables and resolves once the file is complete.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{AacTrackConfig, Container, Mp4Writer};
+///
+/// let mut writer = Mp4Writer::new("recording.mov", Container::Mov)?;
+/// writer.add_video_track_passthrough()?;
+/// writer.add_audio_track_aac(&AacTrackConfig::default())?;
+/// // From the encoder output and audio capture callbacks:
+/// # let sample_buffer = std::ptr::null_mut();
+/// unsafe { writer.append(sample_buffer)? };
+/// // When done, await the future or block on it:
+/// writer.finish().wait()?;
+/// # Ok::<(), video_toolbox_sys::helpers::Mp4WriterError>(())
+/// ```
+pub struct Mp4Writer {
+    writer: Retained<AnyObject>,
+    path: PathBuf,
+    video: Option<Retained<AnyObject>>,
+    /// A passthrough video track waiting for its first sample's format
+    video_pending: bool,
+    audio: Option<Retained<AnyObject>>,
+    started: bool,
+}
+
+// AVAssetWriter and its inputs may be used from any thread, one at a time.
+unsafe impl Send for Mp4Writer {}
+
+impl Mp4Writer {
+    /// Create a writer for `path`, replacing any existing file.
+    pub fn new(path: impl AsRef<Path>, container: Container) -> Result<Self, Mp4WriterError> {
+        let path = path.as_ref().to_path_buf();
+        // AVAssetWriter refuses to overwrite
+        let _ = std::fs::remove_file(&path);
+        let url = NSURL::fileURLWithPath(&NSString::from_str(&path.to_string_lossy()));
+        let writer = unsafe { AVAssetWriter_assetWriterWithURL(&url, container.file_type()) }
+            .map_err(Mp4WriterError::Writer)?;
+        Ok(Self {
+            writer,
+            path,
+            video: None,
+            video_pending: false,
+            audio: None,
+            started: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add a video track taking encoded (e.g. H.264 or HEVC) sample buffers
+    /// as they are.
+    ///
+    /// MPEG-4 files need the format of the passthrough samples up front, so
+    /// the track is created from the first video sample's format
+    /// description, and writing starts with it: samples of other tracks
+    /// appended before it are dropped.
+    pub fn add_video_track_passthrough(&mut self) -> Result<(), Mp4WriterError> {
+        if self.started {
+            return Err(Mp4WriterError::AlreadyStarted);
+        }
+        if self.video.is_some() || self.video_pending {
+            return Err(Mp4WriterError::TrackRejected);
+        }
+        self.video_pending = true;
+        Ok(())
+    }
+
+    /// Add an audio track encoding captured PCM sample buffers to AAC.
+    pub fn add_audio_track_aac(&mut self, config: &AacTrackConfig) -> Result<(), Mp4WriterError> {
+        let settings = CFDictBuilder::new()
+            .value("AVFormatIDKey", codecs::audio::AAC)
+            .value("AVSampleRateKey", config.sample_rate)
+            .value(
+                "AVNumberOfChannelsKey",
+                config.channel_layout.channel_count(),
+            )
+            .value("AVEncoderBitRateKey", config.bitrate)
+            // Without an explicit layout, more than two channels are rejected
+            .value(
+                "AVChannelLayoutKey",
+                config.channel_layout.audio_channel_layout(),
+            )
+            .build();
+        // CFDictionary is toll-free bridged to NSDictionary
+        let settings_object = unsafe { &*(settings.as_concrete_TypeRef() as *const AnyObject) };
+        let input =
+            unsafe { self.add_input(AVMediaTypeAudio, Some(settings_object), ptr::null_mut())? };
+        self.audio = Some(input);
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// `format_hint` must be null or a valid format description of
+    /// `media_type`.
+    unsafe fn add_input(
+        &mut self,
+        media_type: &NSString,
+        settings: Option<&AnyObject>,
+        format_hint: CMFormatDescriptionRef,
+    ) -> Result<Retained<AnyObject>, Mp4WriterError> {
+        if self.started {
+            return Err(Mp4WriterError::AlreadyStarted);
+        }
+        let input = if format_hint.is_null() {
+            AVAssetWriterInput_assetWriterInputWithMediaType(media_type, settings)
+        } else {
+            AVAssetWriterInput_assetWriterInputWithMediaType_sourceFormatHint(
+                media_type,
+                settings,
+                format_hint,
+            )
+        };
+        AVAssetWriterInput_setExpectsMediaDataInRealTime(&input, true);
+        if !AVAssetWriter_addInput(&self.writer, &input) {
+            return Err(Mp4WriterError::TrackRejected);
+        }
+        Ok(input)
+    }
+
+    /// Append a sample buffer to the track of its media type.
+    ///
+    /// Returns `Ok(false)` if the track is not ready for more data and the
+    /// sample was dropped, as real-time sources cannot wait, or if writing
+    /// waits for the first sample of a passthrough video track.
+    ///
+    /// # Safety
+    ///
+    /// `sample_buffer` must be a valid CMSampleBuffer.
+    pub unsafe fn append(
+        &mut self,
+        sample_buffer: CMSampleBufferRef,
+    ) -> Result<bool, Mp4WriterError> {
+        let format = CMSampleBufferGetFormatDescription(sample_buffer);
+        let media_type = if format.is_null() {
+            0
+        } else {
+            CMFormatDescriptionGetMediaType(format)
+        };
+        if self.video_pending && media_type == MEDIA_TYPE_VIDEO {
+            self.video = Some(self.add_input(AVMediaTypeVideo, None, format)?);
+            self.video_pending = false;
+        }
+        let input = match media_type {
+            MEDIA_TYPE_VIDEO => self.video.as_ref(),
+            MEDIA_TYPE_AUDIO => self.audio.as_ref(),
+            _ => None,
+        }
+        .ok_or(Mp4WriterError::NoTrack(media_type))?;
+        if self.video_pending {
+            // Writing starts with the first video sample
+            return Ok(false);
+        }
+
+        if !self.started {
+            if !AVAssetWriter_startWriting(&self.writer) {
+                return Err(self.error());
+            }
+            let start = CMSampleBufferGetPresentationTimeStamp(sample_buffer);
+            AVAssetWriter_startSessionAtSourceTime(&self.writer, start.into());
+            self.started = true;
+        }
+        if AVAssetWriter_status(&self.writer) == AVAssetWriterStatusFailed {
+            return Err(self.error());
+        }
+        if !AVAssetWriterInput_isReadyForMoreMediaData(input) {
+            return Ok(false);
+        }
+        if !AVAssetWriterInput_appendSampleBuffer(input, sample_buffer) {
+            return Err(self.error());
+        }
+        Ok(true)
+    }
+
+    fn error(&self) -> Mp4WriterError {
+        let code = unsafe { AVAssetWriter_error(&self.writer) };
+        Mp4WriterError::Writer(code.unwrap_or(AVErrorUnknown))
+    }
+
+    /// Finish the tracks and write the file.
+    ///
+    /// The returned future resolves once the file is complete; call
+    /// [`FinishWriting::wait`] from synchronous code. If no sample was
+    /// appended, no file is written.
+    pub fn finish(self) -> FinishWriting {
+        let shared = Arc::new(FinishShared::default());
+        if !self.started {
+            shared.complete(Ok(()));
+            return FinishWriting { shared };
+        }
+        unsafe {
+            for input in self.video.iter().chain(self.audio.iter()) {
+                AVAssetWriterInput_markAsFinished(input);
+            }
+            let writer = self.writer.clone();
+            let handler_shared = shared.clone();
+            let handler: RcBlock<dyn Fn()> = RcBlock::new(move || {
+                let result = if AVAssetWriter_status(&writer) == AVAssetWriterStatusCompleted {
+                    Ok(())
+                } else {
+                    Err(Mp4WriterError::Writer(
+                        AVAssetWriter_error(&writer).unwrap_or(AVErrorUnknown),
+                    ))
+                };
+                handler_shared.complete(result);
+            });
+            AVAssetWriter_finishWriting(&self.writer, &handler);
+        }
+        FinishWriting { shared }
+    }
+}
+
+#[derive(Default)]
+struct FinishShared {
+    state: Mutex<FinishState>,
+    done: Condvar,
+}
+
+#[derive(Default)]
+struct FinishState {
+    result: Option<Result<(), Mp4WriterError>>,
+    waker: Option<Waker>,
+}
+
+impl FinishShared {
+    fn complete(&self, result: Result<(), Mp4WriterError>) {
+        let mut state = self.state.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.done.notify_all();
+    }
+}
+
+/// Completion of [`Mp4Writer::finish`], as a future or by blocking with
+/// [`wait`](Self::wait).
+pub struct FinishWriting {
+    shared: Arc<FinishShared>,
+}
+
+impl FinishWriting {
+    /// Block until the file is complete.
+    pub fn wait(self) -> Result<(), Mp4WriterError> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self.shared.done.wait(state).unwrap();
+        }
+    }
+}
+
+impl Future for FinishWriting {
+    type Output = Result<(), Mp4WriterError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+
+    struct CountingWaker(Mutex<usize>);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            *self.0.lock().unwrap() += 1;
+        }
+    }
+
+    #[test]
+    fn test_finish_writing_future() {
+        let shared = Arc::new(FinishShared::default());
+        let mut finish = FinishWriting {
+            shared: shared.clone(),
+        };
+        let counter = Arc::new(CountingWaker(Mutex::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut finish).poll(&mut cx).is_pending());
+
+        shared.complete(Err(Mp4WriterError::Writer(-11823)));
+        assert_eq!(*counter.0.lock().unwrap(), 1);
+        assert_eq!(
+            Pin::new(&mut finish).poll(&mut cx),
+            Poll::Ready(Err(Mp4WriterError::Writer(-11823)))
+        );
+
+        let shared = Arc::new(FinishShared::default());
+        shared.complete(Ok(()));
+        assert_eq!(FinishWriting { shared }.wait(), Ok(()));
+    }
+}
@@ -0,0 +1,401 @@
+//! A self-contained Media Source Extensions test page for CMAF output.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::cmaf_demuxer::CmafDemuxer;
+use super::codec_string::{h264_codec_string, mp4_mime_type};
+use super::sink::{Segment, SegmentSink};
+
+/// How the page receives segments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MseTransport {
+    /// Fetch the init segment, then numbered media segments one after
+    /// another, retrying a segment that does not exist yet every
+    /// `poll_interval`.
+    ///
+    /// With a `playlist` (an [`HlsSink`](super::HlsSink) playlist next to
+    /// the segments) playback starts at the newest listed segment, so the
+    /// page can join a live stream whose early segments were deleted.
+    /// Otherwise it starts at segment 1.
+    Fetch {
+        playlist: Option<String>,
+        poll_interval: Duration,
+    },
+    /// Receive segments as binary WebSocket messages from `url`, init
+    /// segment first.
+    WebSocket { url: String },
+}
+
+impl Default for MseTransport {
+    fn default() -> Self {
+        Self::Fetch {
+            playlist: Some("playlist.m3u8".to_string()),
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Writes an HTML page that plays the CMAF segments next to it through
+/// Media Source Extensions (`ManagedMediaSource` on Safari for iOS).
+///
+/// It is meant to check generated segments in a browser without setting up
+/// a player: serve the output directory over HTTP (`python3 -m http.server`)
+/// and open the page. The page checks `isTypeSupported` for the stream's
+/// codec string, appends segments as they appear, jumps back to the live
+/// edge when playback falls behind, and logs each step below the video.
+///
+/// Used as a [`SegmentSink`], the page is written when the init segment
+/// arrives, with the codec string derived from its SPS unless one was set. Segment names
+/// default to those of [`Segment::file_name`].
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{Backpressure, HlsConfig, HlsSink, MsePage, TeeSink};
+///
+/// let sink = TeeSink::new()
+///     .add_sink(HlsSink::new("live", HlsConfig::default())?, Backpressure::Block, 8)
+///     .add_sink(MsePage::new("live"), Backpressure::Block, 1);
+/// // feed segments to `sink`, then open http://localhost:8000/player.html
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MsePage {
+    dir: PathBuf,
+    file_name: String,
+    title: String,
+    codecs: Option<String>,
+    init_name: String,
+    segment_prefix: String,
+    segment_digits: usize,
+    segment_suffix: String,
+    transport: MseTransport,
+}
+
+impl MsePage {
+    /// A page named `player.html` in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            file_name: "player.html".to_string(),
+            title: "CMAF preview".to_string(),
+            codecs: None,
+            init_name: "init.mp4".to_string(),
+            segment_prefix: "segment_".to_string(),
+            segment_digits: 3,
+            segment_suffix: ".m4s".to_string(),
+            transport: MseTransport::default(),
+        }
+    }
+
+    pub fn file_name(mut self, name: impl Into<String>) -> Self {
+        self.file_name = name.into();
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// RFC 6381 codec string, e.g. `avc1.640028`. Derived from the init
+    /// segment when the page is used as a sink.
+    pub fn codecs(mut self, codecs: impl Into<String>) -> Self {
+        self.codecs = Some(codecs.into());
+        self
+    }
+
+    /// Read the codec string from an init segment's SPS.
+    pub fn codecs_from_init(mut self, init: &[u8]) -> Self {
+        if let Some(codecs) = codecs_from_init(init) {
+            self.codecs = Some(codecs);
+        }
+        self
+    }
+
+    /// File name of the init segment.
+    pub fn init_name(mut self, name: impl Into<String>) -> Self {
+        self.init_name = name.into();
+        self
+    }
+
+    /// Media segment names: `prefix`, the sequence number zero-padded to
+    /// `digits`, then `suffix`.
+    pub fn segment_naming(
+        mut self,
+        prefix: impl Into<String>,
+        digits: usize,
+        suffix: impl Into<String>,
+    ) -> Self {
+        self.segment_prefix = prefix.into();
+        self.segment_digits = digits;
+        self.segment_suffix = suffix.into();
+        self
+    }
+
+    pub fn transport(mut self, transport: MseTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(&self.file_name)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Render the page.
+    pub fn render(&self) -> String {
+        let codecs = self.codecs.as_deref().unwrap_or("avc1.42E01E");
+        let (playlist, poll_ms, ws_url) = match &self.transport {
+            MseTransport::Fetch {
+                playlist,
+                poll_interval,
+            } => (
+                playlist.as_deref().map(js_string),
+                poll_interval.as_millis(),
+                None,
+            ),
+            MseTransport::WebSocket { url } => (None, 0, Some(js_string(url))),
+        };
+        let null = || "null".to_string();
+        PAGE.replace("{{TITLE}}", &html_escape(&self.title))
+            .replace("{{MIME}}", &js_string(&mp4_mime_type(&[codecs])))
+            .replace("{{INIT}}", &js_string(&self.init_name))
+            .replace("{{PREFIX}}", &js_string(&self.segment_prefix))
+            .replace("{{DIGITS}}", &self.segment_digits.to_string())
+            .replace("{{SUFFIX}}", &js_string(&self.segment_suffix))
+            .replace("{{PLAYLIST}}", &playlist.unwrap_or_else(null))
+            .replace("{{POLL_MS}}", &poll_ms.to_string())
+            .replace("{{WS_URL}}", &ws_url.unwrap_or_else(null))
+    }
+
+    /// Write the page into its directory, creating it if needed.
+    pub fn write(&self) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path();
+        fs::write(&path, self.render())?;
+        Ok(path)
+    }
+}
+
+impl SegmentSink for MsePage {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        if !segment.is_init() {
+            return Ok(());
+        }
+        if self.codecs.is_none() {
+            self.codecs = codecs_from_init(&segment.data);
+        }
+        self.write().map(|_| ())
+    }
+}
+
+fn codecs_from_init(init: &[u8]) -> Option<String> {
+    let mut demuxer = CmafDemuxer::new();
+    demuxer.push(init).ok()?;
+    h264_codec_string(&demuxer.track()?.parameter_sets.sps)
+}
+
+/// A double-quoted JavaScript string literal, safe inside a `<script>`.
+fn js_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '<' => out.push_str("\\u003c"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{TITLE}}</title>
+<style>
+  body { font-family: sans-serif; background: #111; color: #ddd; margin: 1em; }
+  video { max-width: 100%; background: #000; }
+  pre { font-size: 12px; max-height: 40vh; overflow: auto; }
+</style>
+</head>
+<body>
+<video id="video" controls autoplay muted playsinline></video>
+<pre id="log"></pre>
+<script>
+const MIME = {{MIME}};
+const INIT = {{INIT}};
+const PREFIX = {{PREFIX}};
+const DIGITS = {{DIGITS}};
+const SUFFIX = {{SUFFIX}};
+const PLAYLIST = {{PLAYLIST}};
+const POLL_MS = {{POLL_MS}};
+const WS_URL = {{WS_URL}};
+const MAX_LATENCY = 3;
+const KEEP_BEHIND = 20;
+
+const video = document.getElementById("video");
+const logEl = document.getElementById("log");
+function log(message) {
+  const time = new Date().toISOString().slice(11, 23);
+  logEl.textContent = time + " " + message + "\n" + logEl.textContent.slice(0, 20000);
+}
+const sleep = ms => new Promise(resolve => setTimeout(resolve, ms));
+const segmentName = n => PREFIX + String(n).padStart(DIGITS, "0") + SUFFIX;
+
+function whenUpdated(sb) {
+  return new Promise((resolve, reject) => {
+    sb.addEventListener("updateend", resolve, { once: true });
+    sb.addEventListener("error", () => reject(new Error("SourceBuffer error")), { once: true });
+  });
+}
+
+let chain = Promise.resolve();
+function append(sb, data) {
+  chain = chain.then(async () => {
+    sb.appendBuffer(data);
+    await whenUpdated(sb);
+    await keepUp(sb);
+  });
+  return chain;
+}
+
+async function keepUp(sb) {
+  const buffered = video.buffered;
+  if (!buffered.length) return;
+  const end = buffered.end(buffered.length - 1);
+  if (end - video.currentTime > MAX_LATENCY) {
+    log("behind live edge by " + (end - video.currentTime).toFixed(2) + "s, jumping");
+    video.currentTime = end - 0.5;
+  }
+  const start = buffered.start(0);
+  if (video.currentTime - start > 2 * KEEP_BEHIND) {
+    sb.remove(start, video.currentTime - KEEP_BEHIND);
+    await whenUpdated(sb);
+  }
+}
+
+async function fetchBytes(name) {
+  const response = await fetch(name, { cache: "no-store" });
+  return response.ok ? response.arrayBuffer() : null;
+}
+
+async function firstSegment() {
+  if (!PLAYLIST) return 1;
+  for (;;) {
+    const response = await fetch(PLAYLIST, { cache: "no-store" });
+    if (response.ok) {
+      const uris = (await response.text()).split("\n").filter(l => l.startsWith(PREFIX));
+      if (uris.length) {
+        const last = uris[uris.length - 1];
+        return parseInt(last.slice(PREFIX.length, last.length - SUFFIX.length), 10);
+      }
+    }
+    await sleep(POLL_MS);
+  }
+}
+
+async function fetchLoop(sb) {
+  let init;
+  while (!(init = await fetchBytes(INIT))) await sleep(POLL_MS);
+  await append(sb, init);
+  log("appended " + INIT);
+  let n = await firstSegment();
+  for (;;) {
+    const data = await fetchBytes(segmentName(n));
+    if (!data) {
+      await sleep(POLL_MS);
+      continue;
+    }
+    await append(sb, data);
+    log("appended " + segmentName(n));
+    n++;
+  }
+}
+
+function webSocketLoop(sb) {
+  const ws = new WebSocket(WS_URL);
+  ws.binaryType = "arraybuffer";
+  let count = 0;
+  ws.onopen = () => log("connected to " + WS_URL);
+  ws.onclose = () => log("connection closed");
+  ws.onmessage = event => {
+    const index = count++;
+    append(sb, event.data).then(() => log("appended message " + index));
+  };
+}
+
+const Source = window.ManagedMediaSource || window.MediaSource;
+if (!Source) {
+  log("Media Source Extensions are not available");
+} else if (!Source.isTypeSupported(MIME)) {
+  log("unsupported type: " + MIME);
+} else {
+  const source = new Source();
+  video.disableRemotePlayback = true;
+  video.src = URL.createObjectURL(source);
+  source.addEventListener("sourceopen", () => {
+    log("playing " + MIME);
+    const sb = source.addSourceBuffer(MIME);
+    if (WS_URL) {
+      webSocketLoop(sb);
+    } else {
+      fetchLoop(sb).catch(error => log("error: " + error.message));
+    }
+  });
+}
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_is_templated() {
+        let page = MsePage::new("out")
+            .codecs("avc1.640028")
+            .title("Cam <1>")
+            .render();
+        assert!(page.contains(r#"const MIME = "video/mp4; codecs=\"avc1.640028\"";"#));
+        assert!(page.contains(r#"const PLAYLIST = "playlist.m3u8";"#));
+        assert!(page.contains("const POLL_MS = 500;"));
+        assert!(page.contains("const WS_URL = null;"));
+        assert!(page.contains("<title>Cam &lt;1&gt;</title>"));
+        assert!(!page.contains("{{"));
+
+        let page = MsePage::new("out")
+            .segment_naming("chunk-", 5, ".mp4")
+            .transport(MseTransport::WebSocket {
+                url: "ws://localhost:9000/</script>".to_string(),
+            })
+            .render();
+        assert!(page.contains(r#"const PREFIX = "chunk-";"#));
+        assert!(page.contains("const DIGITS = 5;"));
+        assert!(page.contains(r#"const WS_URL = "ws://localhost:9000/\u003c/script>";"#));
+        assert!(page.contains("const PLAYLIST = null;"));
+    }
+}
@@ -0,0 +1,498 @@
+//! Live fMP4 streaming to browsers over WebSocket.
+//!
+//! Enabled with the `mse-server` feature. [`MseServer`] accepts WebSocket
+//! connections, sends each new client the init segment and then broadcasts
+//! media segments from a [`CmafMuxer`](super::CmafMuxer) to every client for
+//! Media Source Extensions playback. Plain HTTP requests to the same port get
+//! an [`MsePage`] that connects back to it, so a browser pointed at
+//! [`MseServer::url`] plays the stream without any other server.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tungstenite::{Message, WebSocket};
+
+use super::hls::starts_with_sync_sample;
+use super::mse_page::{MsePage, MseTransport};
+use super::sink::{Segment, SegmentSink};
+
+/// Longest HTTP request head accepted.
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// Settings for [`MseServer`].
+#[derive(Debug, Clone)]
+pub struct MseServerConfig {
+    /// Segments queued for a client at most; a client that falls further
+    /// behind is disconnected and can reconnect at the live edge
+    pub max_queued: usize,
+    /// Page served to plain HTTP requests for `/`, or `None` to answer them
+    /// with 404. Its transport is replaced with this server's WebSocket, and
+    /// its codec string is derived from the init segment.
+    pub page: Option<MsePage>,
+    /// Timeout for reading a request and for sending to a client
+    pub timeout: Duration,
+}
+
+impl Default for MseServerConfig {
+    fn default() -> Self {
+        Self {
+            max_queued: 32,
+            page: Some(MsePage::new(".").title("Live stream")),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Counters of an [`MseServer`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MseServerStats {
+    /// Clients currently connected
+    pub clients: usize,
+    /// WebSocket connections accepted in total
+    pub connections: u64,
+    /// Clients disconnected for falling more than
+    /// [`MseServerConfig::max_queued`] segments behind
+    pub slow_disconnects: u64,
+    /// Media segments broadcast
+    pub segments: u64,
+    /// Connections that failed with an I/O or handshake error, not counting
+    /// clients that closed them
+    pub connection_errors: u64,
+}
+
+/// A connected client.
+struct Client {
+    sender: SyncSender<Arc<[u8]>>,
+    /// Whether the client has been sent a segment starting with a keyframe
+    /// since the last init segment
+    synced: bool,
+}
+
+#[derive(Default)]
+struct State {
+    init: Option<Arc<[u8]>>,
+    clients: Vec<Client>,
+    stats: MseServerStats,
+}
+
+struct Shared {
+    config: MseServerConfig,
+    state: Mutex<State>,
+    stopped: AtomicBool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Serves an fMP4 stream to browsers over WebSocket.
+///
+/// Each binary message is one segment, as expected by
+/// [`MseTransport::WebSocket`]. A client joining mid-stream gets the latest
+/// init segment, then media segments from the next one that starts with a
+/// keyframe, so use fragments that start at keyframes for join points (the
+/// default [`FragmentEmission::PerGop`](super::FragmentEmission::PerGop)).
+/// Segments are queued per client, so a slow client cannot stall the others.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{MseServer, Segment, SegmentSink};
+///
+/// let mut server = MseServer::bind("0.0.0.0:8080")?;
+/// println!("open {}", server.url());
+/// # let (init, fragments): (Vec<u8>, Vec<(u32, Vec<u8>)>) = (Vec::new(), Vec::new());
+/// server.write_segment(&Segment::init(init))?;
+/// for (sequence_number, fragment) in fragments {
+///     server.write_segment(&Segment::media(sequence_number, fragment))?;
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct MseServer {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl MseServer {
+    /// Listen on `addr` with the default configuration.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::with_config(addr, MseServerConfig::default())
+    }
+
+    pub fn with_config(addr: impl ToSocketAddrs, config: MseServerConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            config,
+            state: Mutex::new(State::default()),
+            stopped: AtomicBool::new(false),
+        });
+        let acceptor = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("mse-server".to_string())
+                .spawn(move || accept_loop(shared, listener))?
+        };
+        Ok(Self {
+            shared,
+            local_addr,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// URL of the player page.
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.local_addr)
+    }
+
+    pub fn stats(&self) -> MseServerStats {
+        let state = self.shared.lock();
+        MseServerStats {
+            clients: state.clients.len(),
+            ..state.stats
+        }
+    }
+
+    /// Set the init segment, sending it to connected clients. Clients then
+    /// wait for the next keyframe before receiving media again.
+    pub fn set_init_segment(&self, data: Vec<u8>) {
+        let data: Arc<[u8]> = data.into();
+        let mut state = self.shared.lock();
+        state.init = Some(Arc::clone(&data));
+        let mut slow = 0;
+        state.clients.retain_mut(|client| {
+            client.synced = false;
+            send(client, &data, &mut slow)
+        });
+        state.stats.slow_disconnects += slow;
+    }
+
+    /// Send a media segment to every client that has joined at a keyframe.
+    pub fn broadcast(&self, data: Vec<u8>) {
+        // Segments without sample flags are treated as join points
+        let is_sync = starts_with_sync_sample(&data).unwrap_or(true);
+        let data: Arc<[u8]> = data.into();
+        let mut state = self.shared.lock();
+        state.stats.segments += 1;
+        let mut slow = 0;
+        state.clients.retain_mut(|client| {
+            if !client.synced && !is_sync {
+                return true;
+            }
+            client.synced = true;
+            send(client, &data, &mut slow)
+        });
+        state.stats.slow_disconnects += slow;
+    }
+}
+
+/// Queue `data` for `client`, returning whether it stays connected.
+fn send(client: &Client, data: &Arc<[u8]>, slow: &mut u64) -> bool {
+    match client.sender.try_send(Arc::clone(data)) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            *slow += 1;
+            false
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    }
+}
+
+fn add_client(shared: &Shared, sender: SyncSender<Arc<[u8]>>) {
+    let mut state = shared.lock();
+    state.stats.connections += 1;
+    if let Some(init) = &state.init {
+        // The queue is empty, so this only fails if the client already left
+        if sender.try_send(Arc::clone(init)).is_err() {
+            return;
+        }
+    }
+    state.clients.push(Client {
+        sender,
+        synced: false,
+    });
+}
+
+impl SegmentSink for MseServer {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        if segment.is_init() {
+            self.set_init_segment(segment.data.clone());
+        } else {
+            self.broadcast(segment.data.clone());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MseServer {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        // Wake the acceptor blocked in accept()
+        let mut wake = self.local_addr;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake {
+                SocketAddr::V4(_) => [127, 0, 0, 1].into(),
+                SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&wake, Duration::from_secs(1));
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        // Dropping the senders ends the client threads
+        self.shared.lock().clients.clear();
+    }
+}
+
+fn accept_loop(shared: Arc<Shared>, listener: TcpListener) {
+    for stream in listener.incoming() {
+        if shared.stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(stream) = stream else { continue };
+        let shared = Arc::clone(&shared);
+        let _ = thread::Builder::new()
+            .name("mse-client".to_string())
+            .spawn(move || {
+                if let Err(e) = serve_connection(&shared, stream) {
+                    if e.kind() != io::ErrorKind::UnexpectedEof {
+                        shared.lock().stats.connection_errors += 1;
+                    }
+                }
+            });
+    }
+}
+
+/// The parts of an HTTP request head the server looks at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RequestHead {
+    path: String,
+    host: Option<String>,
+    websocket: bool,
+}
+
+fn parse_request_head(head: &str) -> Option<RequestHead> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let (_method, path) = (request_line.next()?, request_line.next()?);
+    let mut request = RequestHead {
+        path: path.to_string(),
+        host: None,
+        websocket: false,
+    };
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("host") {
+            request.host = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("upgrade") {
+            request.websocket = value.eq_ignore_ascii_case("websocket");
+        }
+    }
+    Some(request)
+}
+
+/// Peek at the request head without consuming it, so a WebSocket handshake
+/// can still read it. Fails if the whole head has not arrived within
+/// `timeout`, however slowly it trickles in.
+fn peek_request_head(stream: &TcpStream, timeout: Duration) -> io::Result<String> {
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0; MAX_REQUEST_HEAD];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request head not received in time",
+            ));
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        let len = stream.peek(&mut buf)?;
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if let Some(end) = buf[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+            return Ok(String::from_utf8_lossy(&buf[..end + 4]).into_owned());
+        }
+        if len == buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too large",
+            ));
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn serve_connection(shared: &Shared, stream: TcpStream) -> io::Result<()> {
+    stream.set_write_timeout(Some(shared.config.timeout))?;
+    let head = peek_request_head(&stream, shared.config.timeout)?;
+    stream.set_read_timeout(Some(shared.config.timeout))?;
+    let request = parse_request_head(&head)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed request"))?;
+    if !request.websocket {
+        return serve_page(shared, stream, head.len(), &request);
+    }
+
+    let websocket = tungstenite::accept(stream)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let (sender, receiver) = mpsc::sync_channel(shared.config.max_queued.max(1));
+    add_client(shared, sender);
+    stream_segments(websocket, receiver)
+}
+
+/// Send queued segments until the server drops the client or it goes away.
+fn stream_segments(
+    mut websocket: WebSocket<TcpStream>,
+    receiver: Receiver<Arc<[u8]>>,
+) -> io::Result<()> {
+    for data in receiver {
+        websocket
+            .send(Message::binary(data.to_vec()))
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))?;
+    }
+    let _ = websocket.close(None);
+    let _ = websocket.flush();
+    Ok(())
+}
+
+fn serve_page(
+    shared: &Shared,
+    mut stream: TcpStream,
+    head_len: usize,
+    request: &RequestHead,
+) -> io::Result<()> {
+    stream.read_exact(&mut vec![0; head_len])?;
+    let page = match (&shared.config.page, request.path.as_str()) {
+        (Some(page), "/" | "/index.html") => {
+            let host = request.host.as_deref().unwrap_or("localhost");
+            let mut page = page.clone().transport(MseTransport::WebSocket {
+                url: format!("ws://{}/", host),
+            });
+            if let Some(init) = &shared.lock().init {
+                page = page.codecs_from_init(init);
+            }
+            Some(page.render())
+        }
+        _ => None,
+    };
+    let (status, body) = match &page {
+        Some(body) => ("200 OK", body.as_str()),
+        None => ("404 Not Found", "Not Found"),
+    };
+    let content_type = if page.is_some() {
+        "text/html; charset=utf-8"
+    } else {
+        "text/plain"
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{CmafConfig, CmafMuxer, NalUnit};
+
+    #[test]
+    fn test_parse_request_head() {
+        let head = "GET / HTTP/1.1\r\nHost: example.com:8080\r\nUpgrade: WebSocket\r\n\r\n";
+        assert_eq!(
+            parse_request_head(head),
+            Some(RequestHead {
+                path: "/".to_string(),
+                host: Some("example.com:8080".to_string()),
+                websocket: true,
+            })
+        );
+        let page = parse_request_head("GET /index.html HTTP/1.1\r\n\r\n").unwrap();
+        assert!(!page.websocket);
+        assert_eq!(page.host, None);
+    }
+
+    #[test]
+    fn test_request_head_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let start = Instant::now();
+        assert!(peek_request_head(&stream, Duration::from_millis(100)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        client.write_all(b"Host: localhost\r\n\r\n").unwrap();
+        let head = peek_request_head(&stream, Duration::from_secs(5)).unwrap();
+        assert!(head.starts_with("GET / ") && head.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_late_client_joins_at_keyframe() {
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            fragment_duration_ms: 1000,
+            timescale: 1000,
+            ..Default::default()
+        });
+        let init = muxer.create_init_segment(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 64, 64);
+        let p = [NalUnit {
+            nal_type: 1,
+            data: vec![0x61, 1],
+        }];
+        let idr = [NalUnit {
+            nal_type: 5,
+            data: vec![0x65, 2],
+        }];
+        // The first fragment starts with a P frame, the second with an IDR
+        muxer.add_frame(&p, 0, 0, 500, false);
+        let first = muxer.add_frame(&idr, 1000, 1000, 500, true).unwrap();
+        muxer.add_frame(&p, 1500, 1500, 500, false);
+        let second = muxer.add_frame(&idr, 2000, 2000, 500, true).unwrap();
+
+        let server = MseServer::with_config(
+            "127.0.0.1:0",
+            MseServerConfig {
+                max_queued: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        server.set_init_segment(init.clone());
+        let (sender, receiver) = mpsc::sync_channel(2);
+        add_client(&server.shared, sender);
+        server.broadcast(first);
+        server.broadcast(second.clone());
+        assert_eq!(&*receiver.try_recv().unwrap(), &init[..]);
+        assert_eq!(&*receiver.try_recv().unwrap(), &second[..]);
+
+        // A full queue disconnects the client
+        server.broadcast(second.clone());
+        server.broadcast(second.clone());
+        server.broadcast(second);
+        let stats = server.stats();
+        assert_eq!((stats.clients, stats.slow_disconnects), (0, 1));
+        assert_eq!(stats.segments, 5);
+    }
+}
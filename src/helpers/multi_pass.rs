@@ -0,0 +1,362 @@
+//! Offline multi-pass encoding with VTMultiPassStorage and VTFrameSilo.
+
+use core_foundation_sys::base::{kCFAllocatorDefault, Boolean, CFRelease, OSStatus};
+use core_media_sys::{CMItemCount, CMSampleBufferRef, CMTime, CMTimeRange};
+use libc::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+use super::callback_target::CallbackTarget;
+use super::compression_builder::CompressionSessionBuilder;
+use super::compression_session::{CompressionSession, EncodeOutput};
+use crate::compression::{
+    kVTCompressionPropertyKey_MultiPassStorage, kVTCompressionSessionBeginFinalPass,
+    VTCompressionSessionBeginPass, VTCompressionSessionEndPass,
+    VTCompressionSessionGetTimeRangesForNextPass,
+};
+use crate::cv_types::CVImageBufferRef;
+use crate::errors::{kVTParameterErr, status_to_result};
+use crate::frame_silo::{
+    VTFrameSiloAddSampleBuffer, VTFrameSiloCallFunctionForEachSampleBuffer, VTFrameSiloCreate,
+    VTFrameSiloRef, VTFrameSiloSetTimeRangesForNextPass,
+};
+use crate::multi_pass_storage::{
+    VTMultiPassStorageClose, VTMultiPassStorageCreate, VTMultiPassStorageRef,
+};
+use crate::session::VTSessionSetProperty;
+
+/// A VTFrameSilo shared with the session's output callback.
+struct Silo(VTFrameSiloRef);
+
+// SAFETY: VTFrameSilo is a CF object; the output callback only adds
+// samples while a pass runs, and the encoder only reads it between passes.
+unsafe impl Send for Silo {}
+unsafe impl Sync for Silo {}
+
+impl Drop for Silo {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0) };
+    }
+}
+
+/// Closes and releases the multi-pass storage after the session is gone.
+struct Storage(VTMultiPassStorageRef);
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        unsafe {
+            VTMultiPassStorageClose(self.0);
+            CFRelease(self.0);
+        }
+    }
+}
+
+/// Encodes a finite source in several passes, letting the encoder
+/// re-encode the ranges where a first pass allocated bits poorly.
+///
+/// Each pass asks the source for its frames; in passes after the first,
+/// frames outside the ranges the encoder wants again are skipped by the
+/// [`PassEncoder`], so the source can simply replay everything. Encoded
+/// samples collect in a temporary VTFrameSilo, and once the encoder needs
+/// no further pass (or `max_passes` is reached) the final samples are
+/// emitted in decode order.
+///
+/// Multi-pass encoding is for offline transcoding: configure the builder
+/// without `real_time`, and expect the source to be decoded once per pass.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{make_time, CompressionSessionBuilder, MultiPassEncoder};
+/// # let frames: Vec<video_toolbox_sys::cv_types::CVPixelBufferRef> = Vec::new();
+///
+/// let builder =
+///     CompressionSessionBuilder::new(1920, 1080, codecs::video::H264).bitrate(6_000_000);
+/// let mut encoder = MultiPassEncoder::new(builder, 2)?;
+/// let passes = encoder.run(
+///     |pass| {
+///         for (i, &frame) in frames.iter().enumerate() {
+///             let pts = make_time(i as i64 * 3000, 90000);
+///             unsafe { pass.encode_frame(frame, pts, make_time(3000, 90000))? };
+///         }
+///         Ok(())
+///     },
+///     |sample_buffer| {
+///         // mux the final sample
+///     },
+/// )?;
+/// println!("encoded in {} passes", passes);
+/// # Ok::<(), i32>(())
+/// ```
+pub struct MultiPassEncoder {
+    session: CompressionSession,
+    silo: Arc<Silo>,
+    error: Arc<AtomicI32>,
+    max_passes: u32,
+    _storage: Storage,
+}
+
+impl MultiPassEncoder {
+    /// Build a session from `builder` that runs at most `max_passes` passes
+    /// (at least 1). The builder's callback target is ignored: samples go
+    /// to the silo on the callback thread.
+    pub fn new(builder: CompressionSessionBuilder, max_passes: u32) -> Result<Self, OSStatus> {
+        if max_passes == 0 {
+            return Err(kVTParameterErr);
+        }
+        unsafe {
+            let mut silo: VTFrameSiloRef = ptr::null();
+            let status = VTFrameSiloCreate(
+                kCFAllocatorDefault,
+                ptr::null(),
+                invalid_range(),
+                ptr::null(),
+                &mut silo,
+            );
+            status_to_result(status)?;
+            let silo = Arc::new(Silo(silo));
+
+            let mut storage: VTMultiPassStorageRef = ptr::null();
+            let status = VTMultiPassStorageCreate(
+                kCFAllocatorDefault,
+                ptr::null(),
+                invalid_range(),
+                ptr::null(),
+                &mut storage,
+            );
+            status_to_result(status)?;
+            let storage = Storage(storage);
+
+            let error = Arc::new(AtomicI32::new(0));
+            let callback_silo = silo.clone();
+            let callback_error = error.clone();
+            let session = builder
+                .callback_target(CallbackTarget::Inline)
+                .build_session(move |output| {
+                    let status = match output {
+                        EncodeOutput::Frame { sample_buffer, .. } => {
+                            VTFrameSiloAddSampleBuffer(callback_silo.0, sample_buffer)
+                        }
+                        EncodeOutput::Dropped { .. } => 0,
+                        EncodeOutput::Error(status) => status,
+                    };
+                    if status != 0 {
+                        let _ = callback_error.compare_exchange(
+                            0,
+                            status,
+                            Ordering::AcqRel,
+                            Ordering::Acquire,
+                        );
+                    }
+                })?;
+            let status = VTSessionSetProperty(
+                session.as_raw(),
+                kVTCompressionPropertyKey_MultiPassStorage,
+                storage.0,
+            );
+            status_to_result(status)?;
+
+            Ok(Self {
+                session,
+                silo,
+                error,
+                max_passes,
+                _storage: storage,
+            })
+        }
+    }
+
+    /// The underlying session, e.g. to read back properties.
+    pub fn session(&self) -> &CompressionSession {
+        &self.session
+    }
+
+    /// Run the passes, calling `source` once per pass to submit the frames,
+    /// then `output` with each final sample buffer in decode order (valid
+    /// for the duration of the call). Returns the number of passes run.
+    pub fn run<S, O>(&mut self, mut source: S, mut output: O) -> Result<u32, OSStatus>
+    where
+        S: FnMut(&mut PassEncoder<'_>) -> Result<(), OSStatus>,
+        O: FnMut(CMSampleBufferRef),
+    {
+        let session = self.session.as_raw();
+        let mut ranges: Option<Vec<CMTimeRange>> = None;
+        let mut pass = 1;
+        loop {
+            let final_pass = pass == self.max_passes;
+            let flags = if final_pass {
+                kVTCompressionSessionBeginFinalPass
+            } else {
+                0
+            };
+            unsafe {
+                status_to_result(VTCompressionSessionBeginPass(
+                    session,
+                    flags,
+                    ptr::null_mut(),
+                ))?;
+            }
+            source(&mut PassEncoder {
+                session: &self.session,
+                ranges: ranges.as_deref(),
+                pass,
+            })?;
+            self.session.complete_frames()?;
+
+            let mut further: Boolean = 0;
+            unsafe {
+                status_to_result(VTCompressionSessionEndPass(
+                    session,
+                    &mut further,
+                    ptr::null_mut(),
+                ))?;
+            }
+            self.take_error()?;
+            if final_pass || further == 0 {
+                break;
+            }
+
+            let next = unsafe {
+                let mut count: CMItemCount = 0;
+                let mut array: *const CMTimeRange = ptr::null();
+                let status =
+                    VTCompressionSessionGetTimeRangesForNextPass(session, &mut count, &mut array);
+                status_to_result(status)?;
+                status_to_result(VTFrameSiloSetTimeRangesForNextPass(
+                    self.silo.0,
+                    count,
+                    array,
+                ))?;
+                std::slice::from_raw_parts(array, count as usize).to_vec()
+            };
+            ranges = Some(next);
+            pass += 1;
+        }
+
+        let status = unsafe {
+            VTFrameSiloCallFunctionForEachSampleBuffer(
+                self.silo.0,
+                invalid_range(),
+                &mut output as *mut O as *mut c_void,
+                each_sample::<O>,
+            )
+        };
+        status_to_result(status)?;
+        Ok(pass)
+    }
+
+    fn take_error(&self) -> Result<(), OSStatus> {
+        status_to_result(self.error.swap(0, Ordering::AcqRel))
+    }
+}
+
+/// Submits the frames of one pass of a [`MultiPassEncoder`].
+pub struct PassEncoder<'a> {
+    session: &'a CompressionSession,
+    /// Ranges to re-encode, `None` in the first pass
+    ranges: Option<&'a [CMTimeRange]>,
+    pass: u32,
+}
+
+impl PassEncoder<'_> {
+    /// The pass number, starting at 1.
+    pub fn pass(&self) -> u32 {
+        self.pass
+    }
+
+    /// Whether this pass encodes the frame at `pts`. Sources that are
+    /// expensive to decode can use this to skip frames themselves.
+    pub fn wants(&self, pts: CMTime) -> bool {
+        self.ranges.is_none_or(|ranges| in_ranges(ranges, pts))
+    }
+
+    /// Encode a frame if this pass wants it. Returns whether it was submitted.
+    ///
+    /// # Safety
+    ///
+    /// `image_buffer` must be a valid pixel buffer matching the session's dimensions.
+    pub unsafe fn encode_frame(
+        &mut self,
+        image_buffer: CVImageBufferRef,
+        pts: CMTime,
+        duration: CMTime,
+    ) -> Result<bool, OSStatus> {
+        if !self.wants(pts) {
+            return Ok(false);
+        }
+        self.session.encode_frame(image_buffer, pts, duration)?;
+        Ok(true)
+    }
+}
+
+extern "C" fn each_sample<O: FnMut(CMSampleBufferRef)>(
+    refcon: *mut c_void,
+    sample_buffer: CMSampleBufferRef,
+) -> OSStatus {
+    let output = unsafe { &mut *(refcon as *mut O) };
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| output(sample_buffer))) {
+        Ok(()) => 0,
+        Err(_) => kVTParameterErr,
+    }
+}
+
+/// `kCMTimeRangeInvalid`: the whole source for silos and storage.
+fn invalid_range() -> CMTimeRange {
+    let invalid = CMTime {
+        value: 0,
+        timescale: 0,
+        flags: 0,
+        epoch: 0,
+    };
+    CMTimeRange {
+        start: invalid,
+        duration: invalid,
+    }
+}
+
+/// Whether `pts` lies in one of `ranges` (start inclusive, end exclusive).
+fn in_ranges(ranges: &[CMTimeRange], pts: CMTime) -> bool {
+    // Times are fractions value / timescale, compared by cross-multiplying
+    let scale = |time: CMTime| time.timescale.max(1) as i128;
+    let value = pts.value as i128;
+    ranges.iter().any(|range| {
+        let (start, duration) = (range.start, range.duration);
+        let after_start = start.value as i128 * scale(pts) <= value * scale(start);
+        // start + duration over the denominator scale(start) * scale(duration)
+        let end = start.value as i128 * scale(duration) + duration.value as i128 * scale(start);
+        let before_end = value * scale(start) * scale(duration) < end * scale(pts);
+        after_start && before_end
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::make_time;
+
+    #[test]
+    fn test_in_ranges() {
+        let ranges = [
+            CMTimeRange {
+                start: make_time(90000, 90000),
+                duration: make_time(30000, 90000),
+            },
+            // 2.5 s to 3 s in milliseconds
+            CMTimeRange {
+                start: make_time(2500, 1000),
+                duration: make_time(500, 1000),
+            },
+        ];
+        assert!(!in_ranges(&ranges, make_time(87000, 90000)));
+        assert!(in_ranges(&ranges, make_time(90000, 90000)));
+        assert!(in_ranges(&ranges, make_time(117000, 90000)));
+        // End is exclusive
+        assert!(!in_ranges(&ranges, make_time(120000, 90000)));
+        assert!(in_ranges(&ranges, make_time(25, 10)));
+        assert!(in_ranges(&ranges, make_time(267000, 90000)));
+        assert!(!in_ranges(&ranges, make_time(270000, 90000)));
+        assert!(!in_ranges(&[], make_time(0, 90000)));
+    }
+}
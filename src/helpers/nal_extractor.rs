@@ -29,18 +29,25 @@
 //! ```
 
 use crate::cm_sample_buffer::{
-    nal_unit_type, CMBlockBufferGetDataLength, CMBlockBufferGetDataPointer,
+    nal_unit_type, CMBlockBufferCopyDataBytes, CMBlockBufferGetDataLength,
+    CMBlockBufferGetDataPointer, CMFormatDescriptionGetMediaSubType,
     CMSampleBufferGetDataBuffer, CMSampleBufferGetDecodeTimeStamp, CMSampleBufferGetDuration,
     CMSampleBufferGetFormatDescription, CMSampleBufferGetPresentationTimeStamp,
     CMSampleBufferGetSampleAttachmentsArray, CMVideoFormatDescriptionGetDimensions,
-    CMVideoFormatDescriptionGetH264ParameterSetAtIndex, kCMSampleAttachmentKey_NotSync,
+    CMVideoFormatDescriptionGetH264ParameterSetAtIndex,
+    CMVideoFormatDescriptionGetHEVCParameterSetAtIndex, kCMSampleAttachmentKey_NotSync,
 };
 use core_foundation_sys::array::CFArrayGetValueAtIndex;
 use core_foundation_sys::base::CFTypeRef;
 use core_foundation_sys::dictionary::CFDictionaryGetValue;
 use core_media_sys::{CMFormatDescriptionRef, CMSampleBufferRef, CMTime};
+use libc::c_void;
 use std::ptr;
 
+use super::codec_string::HevcProfileTierLevel;
+use super::events::{emit, PipelineEvent};
+use crate::codecs;
+
 /// Error codes for NAL extraction operations.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NalError {
@@ -56,6 +63,18 @@ pub enum NalError {
     InvalidNalLength,
     /// Buffer too small for NAL data
     BufferTooSmall,
+    /// NAL length prefix size is not 1, 2 or 4 bytes
+    InvalidNalLengthSize(usize),
+    /// NAL unit does not fit in the configured length prefix
+    NalTooLarge {
+        size: usize,
+        nal_length_size: usize,
+    },
+    /// A reordered (B-) frame would be split from the frames it references
+    FrameReordering,
+    /// NAL unit contains `00 00 00`, `00 00 01` or `00 00 02` at the given
+    /// offset, i.e. it lacks emulation prevention bytes
+    MissingEmulationPrevention { offset: usize },
 }
 
 impl std::fmt::Display for NalError {
@@ -71,6 +90,28 @@ impl std::fmt::Display for NalError {
             }
             NalError::InvalidNalLength => write!(f, "Invalid NAL unit length"),
             NalError::BufferTooSmall => write!(f, "Buffer too small for NAL data"),
+            NalError::InvalidNalLengthSize(size) => {
+                write!(f, "Invalid NAL length size: {} (expected 1, 2 or 4)", size)
+            }
+            NalError::NalTooLarge {
+                size,
+                nal_length_size,
+            } => write!(
+                f,
+                "NAL unit of {} bytes does not fit a {}-byte length prefix",
+                size, nal_length_size
+            ),
+            NalError::FrameReordering => write!(
+                f,
+                "Per-frame fragments require frame reordering to be disabled in the encoder \
+                 (B-frames must share a fragment with the frames they reference)"
+            ),
+            NalError::MissingEmulationPrevention { offset } => write!(
+                f,
+                "NAL unit contains a start code prefix at offset {} \
+                 (missing emulation prevention bytes)",
+                offset
+            ),
         }
     }
 }
@@ -92,6 +133,13 @@ impl NalUnit {
         self.nal_type == nal_unit_type::IDR_SLICE
     }
 
+    /// Returns true if this HEVC NAL unit starts a random access point (BLA,
+    /// IDR or CRA, types 16-21). HEVC NAL types are read from the two-byte
+    /// NAL header, not [`nal_type`](Self::nal_type).
+    pub fn is_hevc_irap(&self) -> bool {
+        matches!(self.data.first().map(|b| (b >> 1) & 0x3F), Some(16..=21))
+    }
+
     /// Returns true if this NAL unit is an SPS.
     pub fn is_sps(&self) -> bool {
         self.nal_type == nal_unit_type::SPS
@@ -157,6 +205,117 @@ pub struct H264ParameterSets {
     pub nal_length_size: i32,
 }
 
+impl H264ParameterSets {
+    /// The first SPS and PPS among `nal_units`, for encoders that send
+    /// parameter sets in-band with keyframes. The length prefix size is
+    /// reported as 4, the size the muxers write.
+    pub fn from_nal_units(nal_units: &[NalUnit]) -> Option<Self> {
+        let sps = nal_units.iter().find(|nal| nal.is_sps())?;
+        let pps = nal_units.iter().find(|nal| nal.is_pps())?;
+        Some(Self {
+            sps: sps.data.clone(),
+            pps: pps.data.clone(),
+            nal_length_size: 4,
+        })
+    }
+}
+
+/// HEVC parameter sets (VPS, SPS and PPS).
+#[derive(Debug, Clone)]
+pub struct HevcParameterSets {
+    /// Video Parameter Set
+    pub vps: Vec<u8>,
+    /// Sequence Parameter Set
+    pub sps: Vec<u8>,
+    /// Picture Parameter Set
+    pub pps: Vec<u8>,
+    /// NAL unit length field size (typically 4 bytes).
+    pub nal_length_size: i32,
+}
+
+impl HevcParameterSets {
+    /// The first VPS, SPS and PPS among `nal_units`. HEVC NAL types are
+    /// read from the two-byte NAL header, not [`NalUnit::nal_type`].
+    pub fn from_nal_units(nal_units: &[NalUnit]) -> Option<Self> {
+        let find = |wanted: u8| {
+            nal_units
+                .iter()
+                .find(|nal| nal.data.first().map(|b| (b >> 1) & 0x3F) == Some(wanted))
+                .map(|nal| nal.data.clone())
+        };
+        Some(Self {
+            vps: find(HEVC_VPS)?,
+            sps: find(HEVC_SPS)?,
+            pps: find(HEVC_PPS)?,
+            nal_length_size: 4,
+        })
+    }
+
+    /// An `HEVCDecoderConfigurationRecord` (the hvcC payload) carrying the
+    /// parameter sets, for [`PassthroughCodec::hevc`](super::PassthroughCodec::hevc).
+    ///
+    /// Profile, tier and level come from the SPS. Chroma format and bit
+    /// depth are not parsed: 4:2:0 is assumed, with 10-bit samples for the
+    /// Main 10 profile and 8-bit otherwise. Returns `None` if the SPS is
+    /// malformed.
+    pub fn hvcc_record(&self) -> Option<Vec<u8>> {
+        let ptl = HevcProfileTierLevel::from_nal(&self.sps)?;
+        let bit_depth_minus_8 = if ptl.profile_idc == 2 { 2 } else { 0 };
+        let length_size_minus_one = (self.nal_length_size.clamp(1, 4) - 1) as u8;
+
+        let mut record = vec![1];
+        record.push(ptl.profile_space << 6 | (ptl.high_tier as u8) << 5 | ptl.profile_idc);
+        record.extend_from_slice(&ptl.compatibility_flags.to_be_bytes());
+        record.extend_from_slice(&ptl.constraint_flags);
+        record.push(ptl.level_idc);
+        // min_spatial_segmentation_idc, parallelismType
+        record.extend_from_slice(&[0xF0, 0x00, 0xFC]);
+        // chroma_format_idc = 1 (4:2:0), luma and chroma bit depths
+        record.extend_from_slice(&[0xFD, 0xF8 | bit_depth_minus_8, 0xF8 | bit_depth_minus_8]);
+        // avgFrameRate = 0, one temporal layer, temporalIdNested, length size
+        record.extend_from_slice(&[0x00, 0x00, 0x0C | length_size_minus_one]);
+        // numOfArrays, each with one NAL unit
+        record.push(3);
+        let arrays = [
+            (HEVC_VPS, &self.vps),
+            (HEVC_SPS, &self.sps),
+            (HEVC_PPS, &self.pps),
+        ];
+        for (nal_type, nal) in arrays {
+            record.push(0x80 | nal_type); // array_completeness
+            record.extend_from_slice(&1u16.to_be_bytes());
+            record.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            record.extend_from_slice(nal);
+        }
+        Some(record)
+    }
+}
+
+const HEVC_VPS: u8 = 32;
+const HEVC_SPS: u8 = 33;
+const HEVC_PPS: u8 = 34;
+
+/// HEVC with parameter sets in-band ('hev1').
+const HEVC_IN_BAND: u32 = 0x68657631;
+
+/// Whether `nal_units` of a `codec` frame contain a random access point.
+fn contains_sync_nal(nal_units: &[NalUnit], codec: u32) -> bool {
+    if codec == codecs::video::HEVC || codec == HEVC_IN_BAND {
+        nal_units.iter().any(NalUnit::is_hevc_irap)
+    } else {
+        nal_units.iter().any(NalUnit::is_idr)
+    }
+}
+
+/// Where the parameter sets of a stream were found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterSetSource {
+    /// The sample buffer's format description
+    FormatDescription,
+    /// NAL units of the first keyframe, for encoders that send them in-band
+    InBand,
+}
+
 /// Video dimensions.
 #[derive(Debug, Clone, Copy)]
 pub struct VideoDimensions {
@@ -170,7 +329,8 @@ pub struct VideoDimensions {
 /// encoded sample buffers. The data can then be used for fMP4 muxing or
 /// other streaming purposes.
 pub struct NalExtractor {
-    _private: (),
+    /// Overrides the NAL length size from the format description
+    nal_length_size: Option<usize>,
 }
 
 impl Default for NalExtractor {
@@ -182,7 +342,20 @@ impl Default for NalExtractor {
 impl NalExtractor {
     /// Create a new NAL extractor.
     pub fn new() -> Self {
-        Self { _private: () }
+        Self {
+            nal_length_size: None,
+        }
+    }
+
+    /// Create an extractor that ignores the format description's NAL length size.
+    ///
+    /// Use this for sources whose format description misreports the length
+    /// prefix size. Must be 1, 2 or 4.
+    pub fn with_nal_length_size(nal_length_size: usize) -> Result<Self, NalError> {
+        validate_nal_length_size(nal_length_size)?;
+        Ok(Self {
+            nal_length_size: Some(nal_length_size),
+        })
     }
 
     /// Extract H.264 parameter sets (SPS and PPS) from a format description.
@@ -248,6 +421,103 @@ impl NalExtractor {
         })
     }
 
+    /// Extract HEVC parameter sets (VPS, SPS and PPS) from a format description.
+    ///
+    /// # Safety
+    ///
+    /// The format description must be a valid HEVC video format description.
+    pub unsafe fn extract_hevc_parameter_sets(
+        &self,
+        format_desc: CMFormatDescriptionRef,
+    ) -> Result<HevcParameterSets, NalError> {
+        if format_desc.is_null() {
+            return Err(NalError::NoFormatDescription);
+        }
+
+        let mut sets = [Vec::new(), Vec::new(), Vec::new()];
+        let mut nal_length_size: i32 = 0;
+        for (index, set) in sets.iter_mut().enumerate() {
+            let mut set_ptr: *const u8 = ptr::null();
+            let mut set_size: usize = 0;
+            let status = CMVideoFormatDescriptionGetHEVCParameterSetAtIndex(
+                format_desc,
+                index,
+                &mut set_ptr,
+                &mut set_size,
+                ptr::null_mut(),
+                &mut nal_length_size,
+            );
+            if status != 0 {
+                return Err(NalError::ParameterSetFailed(status));
+            }
+            *set = std::slice::from_raw_parts(set_ptr, set_size).to_vec();
+        }
+        let [vps, sps, pps] = sets;
+        Ok(HevcParameterSets {
+            vps,
+            sps,
+            pps,
+            nal_length_size,
+        })
+    }
+
+    /// The H.264 parameter sets of an encoded frame, from its format
+    /// description or, if that has none, from the frame's own NAL units.
+    ///
+    /// Some encoder configurations only send SPS and PPS in-band with
+    /// keyframes, so [`extract_parameter_sets`](Self::extract_parameter_sets)
+    /// fails for them. Pass each frame until this succeeds; the source used
+    /// is returned and reported as
+    /// [`PipelineEvent::ParameterSetsFound`](super::PipelineEvent::ParameterSetsFound).
+    ///
+    /// # Safety
+    ///
+    /// The sample buffer must be a valid encoded H.264 sample buffer.
+    pub unsafe fn resolve_parameter_sets(
+        &self,
+        sample_buffer: CMSampleBufferRef,
+    ) -> Result<(H264ParameterSets, ParameterSetSource), NalError> {
+        let format_desc = self
+            .get_format_description(sample_buffer)
+            .unwrap_or(ptr::null_mut());
+        let resolved = match self.extract_parameter_sets(format_desc) {
+            Ok(params) => (params, ParameterSetSource::FormatDescription),
+            Err(error) => {
+                let nal_units = self.extract_nal_units(sample_buffer)?;
+                let params = H264ParameterSets::from_nal_units(&nal_units).ok_or(error)?;
+                (params, ParameterSetSource::InBand)
+            }
+        };
+        emit(PipelineEvent::ParameterSetsFound { source: resolved.1 });
+        Ok(resolved)
+    }
+
+    /// The HEVC parameter sets of an encoded frame, from its format
+    /// description or its own NAL units. See
+    /// [`resolve_parameter_sets`](Self::resolve_parameter_sets).
+    ///
+    /// # Safety
+    ///
+    /// The sample buffer must be a valid encoded HEVC sample buffer.
+    pub unsafe fn resolve_hevc_parameter_sets(
+        &self,
+        sample_buffer: CMSampleBufferRef,
+    ) -> Result<(HevcParameterSets, ParameterSetSource), NalError> {
+        let format_desc = self
+            .get_format_description(sample_buffer)
+            .unwrap_or(ptr::null_mut());
+        let resolved = match self.extract_hevc_parameter_sets(format_desc) {
+            Ok(params) => (params, ParameterSetSource::FormatDescription),
+            Err(error) => {
+                let nal_units = self.extract_nal_units(sample_buffer)?;
+                let params = HevcParameterSets::from_nal_units(&nal_units).ok_or(error)?;
+                (params, ParameterSetSource::InBand)
+            }
+        };
+        emit(PipelineEvent::ParameterSetsFound { source: resolved.1 });
+        Ok(resolved)
+    }
+
     /// Extract video dimensions from a format description.
     ///
     /// # Safety
@@ -271,7 +541,11 @@ impl NalExtractor {
     /// Extract NAL units from an encoded sample buffer.
     ///
     /// VideoToolbox encodes H.264 in AVCC format where each NAL unit is
-    /// prefixed with its length (typically 4 bytes, big-endian).
+    /// prefixed with its length (typically 4 bytes, big-endian). Frames
+    /// encoded with several slices (see
+    /// [`max_slice_bytes`](super::CompressionSessionBuilder::max_slice_bytes))
+    /// yield one NAL unit per slice, and their data may be spread over
+    /// several memory blocks, which are copied together first.
     ///
     /// # Safety
     ///
@@ -306,70 +580,34 @@ impl NalExtractor {
             return Err(NalError::DataPointerFailed(status));
         }
 
-        // Get NAL unit length size from format description
-        let format_desc = CMSampleBufferGetFormatDescription(sample_buffer);
-        let nal_length_size = if !format_desc.is_null() {
-            let mut length_size: i32 = 4;
-            CMVideoFormatDescriptionGetH264ParameterSetAtIndex(
-                format_desc,
-                0,
-                ptr::null_mut(),
-                ptr::null_mut(),
-                ptr::null_mut(),
-                &mut length_size,
-            );
-            length_size as usize
-        } else {
-            4 // Default to 4 bytes
+        // Get NAL unit length size from the override or format description
+        let nal_length_size = match self.nal_length_size {
+            Some(size) => size,
+            None => nal_length_size(CMSampleBufferGetFormatDescription(sample_buffer)),
         };
 
-        self.parse_avcc_data(data_ptr, total_length, nal_length_size)
-    }
-
-    /// Parse AVCC format data into NAL units.
-    unsafe fn parse_avcc_data(
-        &self,
-        data_ptr: *const u8,
-        total_length: usize,
-        nal_length_size: usize,
-    ) -> Result<Vec<NalUnit>, NalError> {
-        let mut nal_units = Vec::new();
-        let mut offset = 0;
-
-        while offset + nal_length_size <= total_length {
-            // Read NAL unit length (big-endian)
-            let nal_length = match nal_length_size {
-                4 => {
-                    let bytes = std::slice::from_raw_parts(data_ptr.add(offset), 4);
-                    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
-                }
-                2 => {
-                    let bytes = std::slice::from_raw_parts(data_ptr.add(offset), 2);
-                    u16::from_be_bytes([bytes[0], bytes[1]]) as usize
-                }
-                1 => *data_ptr.add(offset) as usize,
-                _ => return Err(NalError::InvalidNalLength),
-            };
-
-            offset += nal_length_size;
-
-            if offset + nal_length > total_length {
-                return Err(NalError::BufferTooSmall);
+        let copied;
+        let data = if length_at_offset >= total_length {
+            std::slice::from_raw_parts(data_ptr, total_length)
+        } else {
+            let mut bytes = vec![0u8; total_length];
+            let status = CMBlockBufferCopyDataBytes(
+                block_buffer,
+                0,
+                total_length,
+                bytes.as_mut_ptr() as *mut c_void,
+            );
+            if status != 0 {
+                return Err(NalError::DataPointerFailed(status));
             }
-
-            // Read NAL unit data
-            let nal_data = std::slice::from_raw_parts(data_ptr.add(offset), nal_length);
-            let nal_type = nal_data[0] & 0x1F;
-
-            nal_units.push(NalUnit {
-                data: nal_data.to_vec(),
-                nal_type,
-            });
-
-            offset += nal_length;
+            copied = bytes;
+            &copied[..]
+        };
+        match parse_avcc(data, nal_length_size) {
+            // Some sources deliver Annex B data despite an AVCC format description
+            Err(_) if has_start_code(data) => Ok(parse_annex_b(data)),
+            result => result,
         }
-
-        Ok(nal_units)
     }
 
     /// Extract timing information from a sample buffer.
@@ -399,10 +637,36 @@ impl NalExtractor {
 
     /// Check if a sample buffer represents a keyframe (sync sample).
     ///
+    /// The codec used to inspect the NAL units of samples without sync
+    /// attachments is taken from the sample's format description.
+    ///
     /// # Safety
     ///
     /// The sample buffer must be a valid sample buffer.
     pub unsafe fn is_keyframe(&self, sample_buffer: CMSampleBufferRef) -> bool {
+        let codec = self
+            .get_format_description(sample_buffer)
+            .map_or(codecs::video::H264, |desc| {
+                CMFormatDescriptionGetMediaSubType(desc)
+            });
+        self.is_keyframe_for_codec(sample_buffer, codec)
+    }
+
+    /// Check if a sample buffer of the given codec (`codecs::video::H264` or
+    /// `HEVC`) represents a keyframe.
+    ///
+    /// Sync attachments are used when present. Samples without them, such
+    /// as those from [`create_encoded_sample_buffer`](super::create_encoded_sample_buffer),
+    /// are keyframes if they contain an H.264 IDR or an HEVC IRAP NAL unit.
+    ///
+    /// # Safety
+    ///
+    /// The sample buffer must be a valid sample buffer.
+    pub unsafe fn is_keyframe_for_codec(
+        &self,
+        sample_buffer: CMSampleBufferRef,
+        codec: u32,
+    ) -> bool {
         // Method 1: Check sample attachments for kCMSampleAttachmentKey_NotSync
         let attachments = CMSampleBufferGetSampleAttachmentsArray(sample_buffer, 0);
         if !attachments.is_null() {
@@ -421,9 +685,9 @@ impl NalExtractor {
             }
         }
 
-        // Method 2: Fallback - check if we have an IDR NAL unit
+        // Method 2: Fallback - check if we have an IDR (or HEVC IRAP) NAL unit
         if let Ok(nals) = self.extract_nal_units(sample_buffer) {
-            return nals.iter().any(|nal| nal.is_idr());
+            return contains_sync_nal(&nals, codec);
         }
 
         false
@@ -447,6 +711,28 @@ impl NalExtractor {
     }
 }
 
+/// NAL unit length size from a video format description, or 4 if it has
+/// none.
+///
+/// # Safety
+///
+/// `format_desc` must be null or a valid format description.
+pub(super) unsafe fn nal_length_size(format_desc: CMFormatDescriptionRef) -> usize {
+    if format_desc.is_null() {
+        return 4; // Default to 4 bytes
+    }
+    let mut length_size: i32 = 4;
+    CMVideoFormatDescriptionGetH264ParameterSetAtIndex(
+        format_desc,
+        0,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        &mut length_size,
+    );
+    length_size as usize
+}
+
 /// Check if a CFTypeRef is CFBoolean false.
 unsafe fn is_cf_boolean_false(value: CFTypeRef) -> bool {
     extern "C" {
@@ -455,6 +741,117 @@ unsafe fn is_cf_boolean_false(value: CFTypeRef) -> bool {
     value == kCFBooleanFalse
 }
 
+/// Check that a NAL length prefix size is valid (1, 2 or 4 bytes).
+///
+/// These correspond to avcC `lengthSizeMinusOne` values of 0, 1 and 3.
+pub fn validate_nal_length_size(nal_length_size: usize) -> Result<usize, NalError> {
+    match nal_length_size {
+        1 | 2 | 4 => Ok(nal_length_size),
+        _ => Err(NalError::InvalidNalLengthSize(nal_length_size)),
+    }
+}
+
+/// Parse length-prefixed (AVCC) data into NAL units.
+pub fn parse_avcc(data: &[u8], nal_length_size: usize) -> Result<Vec<NalUnit>, NalError> {
+    Ok(split_avcc(data, nal_length_size)?
+        .into_iter()
+        .map(|nal_data| NalUnit {
+            data: nal_data.to_vec(),
+            nal_type: nal_data[0] & 0x1F,
+        })
+        .collect())
+}
+
+/// Split length-prefixed (AVCC) data into NAL unit slices without copying.
+pub(super) fn split_avcc(data: &[u8], nal_length_size: usize) -> Result<Vec<&[u8]>, NalError> {
+    validate_nal_length_size(nal_length_size)?;
+
+    let mut nal_units = Vec::new();
+    let mut offset = 0;
+
+    while offset + nal_length_size <= data.len() {
+        // Read NAL unit length (big-endian)
+        let nal_length = data[offset..offset + nal_length_size]
+            .iter()
+            .fold(0usize, |len, &b| (len << 8) | b as usize);
+
+        offset += nal_length_size;
+
+        if nal_length == 0 {
+            return Err(NalError::InvalidNalLength);
+        }
+        if offset + nal_length > data.len() {
+            return Err(NalError::BufferTooSmall);
+        }
+
+        nal_units.push(&data[offset..offset + nal_length]);
+        offset += nal_length;
+    }
+
+    Ok(nal_units)
+}
+
+/// Append a NAL unit to `buf` with a big-endian length prefix.
+pub fn write_length_prefixed(
+    buf: &mut Vec<u8>,
+    nal: &[u8],
+    nal_length_size: usize,
+) -> Result<(), NalError> {
+    validate_nal_length_size(nal_length_size)?;
+    if nal_length_size < 4 && nal.len() >= 1 << (8 * nal_length_size) {
+        return Err(NalError::NalTooLarge {
+            size: nal.len(),
+            nal_length_size,
+        });
+    }
+    let len = (nal.len() as u32).to_be_bytes();
+    buf.extend_from_slice(&len[4 - nal_length_size..]);
+    buf.extend_from_slice(nal);
+    Ok(())
+}
+
+/// Returns true if `data` begins with a 3- or 4-byte Annex B start code.
+fn has_start_code(data: &[u8]) -> bool {
+    data.starts_with(&[0, 0, 1]) || data.starts_with(&[0, 0, 0, 1])
+}
+
+/// Split an Annex B byte stream on 3- or 4-byte start codes.
+///
+/// Trailing zero bytes before a start code are treated as padding.
+pub fn parse_annex_b(data: &[u8]) -> Vec<NalUnit> {
+    let mut nal_units = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(s) = start {
+                push_annex_b_nal(&mut nal_units, &data[s..i]);
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(s) = start {
+        push_annex_b_nal(&mut nal_units, &data[s..]);
+    }
+
+    nal_units
+}
+
+pub(crate) fn push_annex_b_nal(nal_units: &mut Vec<NalUnit>, nal: &[u8]) {
+    // Zero bytes before a start code belong to the start code (or trailing_zero_8bits)
+    let end = nal.iter().rposition(|&b| b != 0).map_or(0, |p| p + 1);
+    if end > 0 {
+        nal_units.push(NalUnit {
+            data: nal[..end].to_vec(),
+            nal_type: nal[0] & 0x1F,
+        });
+    }
+}
+
 /// Convert a CMTime to a value in the given timescale.
 pub fn convert_time(time: CMTime, target_timescale: i32) -> i64 {
     if time.timescale == target_timescale {
@@ -505,6 +902,54 @@ mod tests {
         assert_eq!(&annex_b[4..], &[0x67, 0x64, 0x00, 0x1f]);
     }
 
+    #[test]
+    fn test_nal_length_sizes_round_trip() {
+        let nals: [&[u8]; 2] = [&[0x65, 0x88, 0x84], &[0x41, 0x9a]];
+        for size in [1, 2, 4] {
+            let mut buf = Vec::new();
+            for nal in nals {
+                write_length_prefixed(&mut buf, nal, size).unwrap();
+            }
+            assert_eq!(buf.len(), 5 + 2 * size);
+
+            let parsed = parse_avcc(&buf, size).unwrap();
+            assert_eq!(parsed.len(), 2);
+            assert_eq!(parsed[0].data, nals[0]);
+            assert_eq!(parsed[0].nal_type, 5);
+            assert_eq!(parsed[1].nal_type, 1);
+        }
+
+        assert_eq!(
+            validate_nal_length_size(3),
+            Err(NalError::InvalidNalLengthSize(3))
+        );
+        assert!(parse_avcc(&[0, 0, 0, 9, 0x65], 4).is_err());
+        assert_eq!(
+            write_length_prefixed(&mut Vec::new(), &[0u8; 256], 1),
+            Err(NalError::NalTooLarge {
+                size: 256,
+                nal_length_size: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_annex_b_mixed_start_codes() {
+        let data = [
+            0, 0, 0, 1, 0x67, 0x64, // SPS, 4-byte start code
+            0, 0, 1, 0x68, 0xee, // PPS, 3-byte start code
+            0, 0, 1, 0x65, 0x88, 0x00, 0x00, // IDR with trailing zeros
+        ];
+        let nals = parse_annex_b(&data);
+        assert_eq!(nals.len(), 3);
+        assert!(nals[0].is_sps());
+        assert_eq!(nals[1].data, vec![0x68, 0xee]);
+        assert!(nals[2].is_idr());
+        assert_eq!(nals[2].data, vec![0x65, 0x88]);
+
+        assert!(parse_annex_b(&[0x65, 0x88]).is_empty());
+    }
+
     #[test]
     fn test_sample_timing_conversions() {
         let timing = SampleTiming {
@@ -516,4 +961,78 @@ mod tests {
         assert!((timing.pts_seconds() - 1.0).abs() < 0.0001);
         assert!((timing.duration_seconds() - 0.0333).abs() < 0.001);
     }
+
+    #[test]
+    fn test_in_band_parameter_sets() {
+        // Keyframe carrying its parameter sets: AUD, SPS, PPS, IDR
+        let nals = parse_annex_b(&[
+            0, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1, 0x67, 0x64, 0x00, 0x28, 0, 0, 0, 1, 0x68, 0xee, 0,
+            0, 0, 1, 0x65, 0x88,
+        ]);
+        let params = H264ParameterSets::from_nal_units(&nals).unwrap();
+        assert_eq!(params.sps, vec![0x67, 0x64, 0x00, 0x28]);
+        assert_eq!(params.pps, vec![0x68, 0xee]);
+        assert_eq!(params.nal_length_size, 4);
+
+        // A delta frame has none
+        assert!(
+            H264ParameterSets::from_nal_units(&parse_annex_b(&[0, 0, 1, 0x41, 0x9a])).is_none()
+        );
+    }
+
+    #[test]
+    fn test_hevc_sync_nal() {
+        let nal = |data: Vec<u8>| NalUnit {
+            nal_type: data[0] & 0x1F,
+            data,
+        };
+        let idr_w_radl = nal(vec![0x26, 0x01, 0xaf]);
+        let cra = nal(vec![0x2a, 0x01, 0xaf]);
+        let trail_r = nal(vec![0x02, 0x01, 0xd0]);
+        let vps = nal(vec![0x40, 0x01, 0x0c]);
+        assert!(idr_w_radl.is_hevc_irap());
+        assert!(cra.is_hevc_irap());
+        assert!(!trail_r.is_hevc_irap());
+        assert!(!vps.is_hevc_irap());
+
+        // An HEVC IDR's first byte reads as H.264 type 6 (SEI)
+        assert!(!idr_w_radl.is_idr());
+        let keyframe = [vps.clone(), idr_w_radl];
+        assert!(contains_sync_nal(&keyframe, codecs::video::HEVC));
+        assert!(contains_sync_nal(&keyframe, HEVC_IN_BAND));
+        assert!(!contains_sync_nal(&keyframe, codecs::video::H264));
+        assert!(contains_sync_nal(&[cra], codecs::video::HEVC));
+        assert!(!contains_sync_nal(&[vps, trail_r], codecs::video::HEVC));
+    }
+
+    #[test]
+    fn test_hevc_in_band_parameter_sets() {
+        // Main 10, High tier, level 5.1
+        let sps = vec![
+            0x42, 0x01, 0x01, 0x22, 0x20, 0x00, 0x00, 0x03, 0x00, 0xb0, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x99, 0xa0,
+        ];
+        let nal = |data: Vec<u8>| NalUnit {
+            nal_type: data[0] & 0x1F,
+            data,
+        };
+        let nals = [
+            nal(vec![0x40, 0x01, 0x0c]),
+            nal(sps.clone()),
+            nal(vec![0x44, 0x01, 0xc1]),
+            nal(vec![0x26, 0x01, 0xaf]),
+        ];
+        let params = HevcParameterSets::from_nal_units(&nals).unwrap();
+        assert_eq!(params.vps, vec![0x40, 0x01, 0x0c]);
+        assert_eq!(params.sps, sps);
+        assert_eq!(params.pps, vec![0x44, 0x01, 0xc1]);
+
+        let record = params.hvcc_record().unwrap();
+        let ptl = HevcProfileTierLevel::from_hvcc(&record).unwrap();
+        assert_eq!(ptl.codec_string(), "hvc1.2.4.H153.B0");
+        // 10-bit luma and chroma, 4-byte lengths, three arrays
+        assert_eq!(&record[17..23], &[0xFA, 0xFA, 0x00, 0x00, 0x0F, 3]);
+        assert_eq!(&record[23..28], &[0xA0, 0x00, 0x01, 0x00, 0x03]);
+        assert_eq!(record.len(), 23 + 3 * 5 + 3 + sps.len() + 3);
+    }
 }
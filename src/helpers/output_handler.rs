@@ -0,0 +1,177 @@
+//! Per-frame output handlers for the block-based encode and decode calls.
+//!
+//! `VTCompressionSessionEncodeFrameWithOutputHandler` and
+//! `VTDecompressionSessionDecodeFrameWithOutputHandler` deliver each frame's
+//! output to a block passed with the frame instead of a session-wide
+//! callback. These helpers wrap a Rust closure in that block, so the closure
+//! can own whatever per-frame state it needs (a channel, a timestamp, a
+//! buffer to fill) without statics or refcon pointers.
+//!
+//! The handler calls only work on sessions created *without* an output
+//! callback, e.g. with
+//! [`CompressionSessionBuilder::build_with_context(None, ptr::null_mut())`](super::CompressionSessionBuilder::build_with_context)
+//! or `VTDecompressionSessionCreate` with a null callback record.
+
+use block2::RcBlock;
+use core_foundation_sys::base::OSStatus;
+use core_foundation_sys::dictionary::CFDictionaryRef;
+use core_media_sys::{CMSampleBufferRef, CMTime};
+use libc::c_void;
+use std::cell::Cell;
+
+use super::compression_session::{report_dropped, EncodeOutput};
+use super::decompression_session::{decode_output, DecodeOptions, DecodeOutput};
+use super::events::catch_callback_panic;
+use crate::base::EncodedCMTime;
+use crate::compression::{
+    EncodeInfoFlags, VTCompressionSessionEncodeFrameWithOutputHandler, VTCompressionSessionRef,
+    VTEncodeInfoFlags,
+};
+use crate::cv_types::CVImageBufferRef;
+use crate::decompression::{
+    DecodeInfoFlags, VTDecodeInfoFlags, VTDecompressionSessionDecodeFrameWithOutputHandler,
+    VTDecompressionSessionRef,
+};
+
+/// Encode one frame, passing its output to `handler`.
+///
+/// `handler` is called once, possibly on another thread, with the same
+/// [`EncodeOutput`] a [`CompressionSession`](super::CompressionSession)
+/// callback would receive; a dropped frame reports `pts`. Panics in the
+/// handler are caught and reported as
+/// [`PipelineEvent::CallbackPanicked`](super::PipelineEvent::CallbackPanicked).
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::mpsc;
+/// use video_toolbox_sys::helpers::{encode_frame_with_handler, make_time, EncodeOutput};
+/// # let (session, pixel_buffer) = (std::ptr::null_mut(), std::ptr::null_mut());
+///
+/// let (tx, rx) = mpsc::channel();
+/// let (pts, duration) = (make_time(3000, 90000), make_time(3000, 90000));
+/// let handler = move |output| {
+///     // The closure owns this frame's timestamp; no refcon lookup needed
+///     if let EncodeOutput::Frame { .. } = output {
+///         tx.send(pts.value).ok();
+///     }
+/// };
+/// unsafe {
+///     encode_frame_with_handler(session, pixel_buffer, pts, duration, std::ptr::null(), handler)?;
+/// }
+/// let encoded_pts = rx.recv().unwrap();
+/// # Ok::<(), i32>(())
+/// ```
+///
+/// # Safety
+///
+/// `session` must be a valid compression session created without an output
+/// callback, and `image_buffer` a pixel buffer matching its dimensions.
+/// `frame_properties` may be null.
+pub unsafe fn encode_frame_with_handler<F>(
+    session: VTCompressionSessionRef,
+    image_buffer: CVImageBufferRef,
+    pts: CMTime,
+    duration: CMTime,
+    frame_properties: CFDictionaryRef,
+    handler: F,
+) -> Result<EncodeInfoFlags, OSStatus>
+where
+    F: FnOnce(EncodeOutput) + Send + 'static,
+{
+    let handler = Cell::new(Some(handler));
+    let block = RcBlock::new(
+        move |status: OSStatus, info_flags: VTEncodeInfoFlags, sample_buffer: *mut c_void| {
+            let Some(handler) = handler.take() else {
+                return;
+            };
+            let info = EncodeInfoFlags::from_bits_retain(info_flags);
+            let output = if status != 0 {
+                EncodeOutput::Error(status)
+            } else if info.contains(EncodeInfoFlags::FRAME_DROPPED) || sample_buffer.is_null() {
+                report_dropped(pts, duration);
+                EncodeOutput::Dropped { pts: Some(pts) }
+            } else {
+                EncodeOutput::Frame {
+                    sample_buffer: sample_buffer as CMSampleBufferRef,
+                    info,
+                }
+            };
+            catch_callback_panic("compression output handler", || handler(output));
+        },
+    );
+
+    let mut info_flags: VTEncodeInfoFlags = 0;
+    let status = VTCompressionSessionEncodeFrameWithOutputHandler(
+        session,
+        image_buffer,
+        pts,
+        duration,
+        frame_properties,
+        &mut info_flags,
+        &*block,
+    );
+    if status != 0 {
+        return Err(status);
+    }
+    Ok(EncodeInfoFlags::from_bits_retain(info_flags))
+}
+
+/// Decode one frame, passing its output to `handler`.
+///
+/// `handler` is called once, possibly on another thread, with the same
+/// [`DecodeOutput`] a [`DecompressionSession`](super::DecompressionSession)
+/// callback would receive, so frames decoded with
+/// [`DecodeOptions::suppressed`] are reported as [`DecodeOutput::Suppressed`].
+/// Panics in the handler are caught and reported as
+/// [`PipelineEvent::CallbackPanicked`](super::PipelineEvent::CallbackPanicked).
+///
+/// # Safety
+///
+/// `session` must be a valid decompression session created without an
+/// output callback, and `sample_buffer` a sample buffer matching its format.
+pub unsafe fn decode_frame_with_handler<F>(
+    session: VTDecompressionSessionRef,
+    sample_buffer: CMSampleBufferRef,
+    options: DecodeOptions,
+    handler: F,
+) -> Result<DecodeInfoFlags, OSStatus>
+where
+    F: FnOnce(DecodeOutput) + Send + 'static,
+{
+    let handler = Cell::new(Some(handler));
+    let requested = options.flags;
+    let block = RcBlock::new(
+        move |status: OSStatus,
+              info_flags: VTDecodeInfoFlags,
+              image_buffer: CVImageBufferRef,
+              pts: EncodedCMTime,
+              duration: EncodedCMTime| {
+            let Some(handler) = handler.take() else {
+                return;
+            };
+            let output = decode_output(
+                requested,
+                status,
+                info_flags,
+                image_buffer,
+                pts.into(),
+                duration.into(),
+            );
+            catch_callback_panic("decompression output handler", || handler(output));
+        },
+    );
+
+    let mut info_flags: VTDecodeInfoFlags = 0;
+    let status = VTDecompressionSessionDecodeFrameWithOutputHandler(
+        session,
+        sample_buffer,
+        options.flags.bits(),
+        &mut info_flags,
+        &*block,
+    );
+    if status != 0 {
+        return Err(status);
+    }
+    Ok(DecodeInfoFlags::from_bits_retain(info_flags))
+}
@@ -0,0 +1,433 @@
+//! Watermark/logo overlay composited onto frames before encoding.
+
+use libc::c_void;
+
+use super::pixel_buffer::PixelBufferGuard;
+use crate::codecs;
+use crate::cv_types::{
+    kCVReturnInvalidPixelFormat, CVPixelBufferGetBaseAddressOfPlane,
+    CVPixelBufferGetBytesPerRowOfPlane, CVPixelBufferGetHeight, CVPixelBufferGetPixelFormatType,
+    CVPixelBufferGetWidth, CVPixelBufferRef,
+};
+
+/// vImage_Buffer from Accelerate.
+#[repr(C)]
+struct VImageBuffer {
+    data: *mut c_void,
+    height: usize,
+    width: usize,
+    row_bytes: usize,
+}
+
+#[link(name = "Accelerate", kind = "framework")]
+extern "C" {
+    fn vImagePremultipliedAlphaBlend_BGRA8888(
+        src_top: *const VImageBuffer,
+        src_bottom: *const VImageBuffer,
+        dest: *const VImageBuffer,
+        flags: u32,
+    ) -> isize;
+}
+
+/// A decoded image with straight (non-premultiplied) alpha, in RGBA order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlayImage {
+    width: usize,
+    height: usize,
+    rgba: Vec<u8>,
+}
+
+impl OverlayImage {
+    /// Wrap tightly packed RGBA pixels. Returns `None` if `rgba` is not
+    /// `width * height * 4` bytes.
+    pub fn new(width: usize, height: usize, rgba: Vec<u8>) -> Option<Self> {
+        (rgba.len() == width * height * 4).then_some(Self {
+            width,
+            height,
+            rgba,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+}
+
+/// The overlay converted for one pixel format and opacity.
+#[derive(Debug, Clone)]
+enum Converted {
+    /// Premultiplied BGRA for vImage
+    Bgra(Vec<u8>),
+    /// Luma and interleaved CbCr with their (premultiplied-opacity) alpha,
+    /// chroma subsampled 2x2
+    Nv12 {
+        luma: Vec<u8>,
+        alpha: Vec<u8>,
+        chroma: Vec<u8>,
+        chroma_alpha: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Cache {
+    pixel_format: u32,
+    opacity: f32,
+    converted: Converted,
+}
+
+/// One plane of a locked frame.
+struct PlaneMut<'a> {
+    data: &'a mut [u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+}
+
+/// Alpha-blends a logo or watermark onto each frame at a configurable
+/// position and opacity.
+///
+/// The image is converted once for the frame's pixel format and cached,
+/// so per-frame cost is the blend itself: BGRA frames are blended with
+/// vImage, bi-planar 4:2:0 frames (`420v`/`420f`, as delivered by cameras)
+/// directly in their luma and chroma planes using BT.709 coefficients.
+/// Changing the opacity reconverts the image on the next frame; moving it
+/// does not. Parts of the image outside the frame are clipped.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{OverlayImage, OverlayStage};
+/// # let (logo_rgba, pixel_buffer) = (vec![0u8; 64 * 32 * 4], std::ptr::null_mut());
+///
+/// let logo = OverlayImage::new(64, 32, logo_rgba).unwrap();
+/// let mut overlay = OverlayStage::new(logo).position(16, 16).opacity(0.8);
+///
+/// // In the capture callback, before encoding:
+/// unsafe { overlay.apply(pixel_buffer)? };
+/// # Ok::<(), i32>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct OverlayStage {
+    image: OverlayImage,
+    x: usize,
+    y: usize,
+    opacity: f32,
+    cache: Option<Cache>,
+}
+
+impl OverlayStage {
+    /// Overlay `image` at the top-left corner, fully opaque.
+    pub fn new(image: OverlayImage) -> Self {
+        Self {
+            image,
+            x: 0,
+            y: 0,
+            opacity: 1.0,
+            cache: None,
+        }
+    }
+
+    /// Top-left corner of the image in frame pixels. Bi-planar frames round
+    /// it down to even coordinates.
+    pub fn position(mut self, x: usize, y: usize) -> Self {
+        self.set_position(x, y);
+        self
+    }
+
+    /// Opacity from 0 (invisible) to 1, multiplied with the image's alpha.
+    pub fn opacity(mut self, opacity: f32) -> Self {
+        self.set_opacity(opacity);
+        self
+    }
+
+    pub fn set_position(&mut self, x: usize, y: usize) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    pub fn image(&self) -> &OverlayImage {
+        &self.image
+    }
+
+    /// Blend the overlay into `pixel_buffer` in place.
+    ///
+    /// Returns `kCVReturnInvalidPixelFormat` for formats other than BGRA and
+    /// bi-planar 4:2:0.
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid CVPixelBuffer not accessed concurrently.
+    pub unsafe fn apply(&mut self, pixel_buffer: CVPixelBufferRef) -> Result<(), i32> {
+        let pixel_format = CVPixelBufferGetPixelFormatType(pixel_buffer);
+        let (width, height) = (
+            CVPixelBufferGetWidth(pixel_buffer),
+            CVPixelBufferGetHeight(pixel_buffer),
+        );
+        let guard = PixelBufferGuard::lock(pixel_buffer)?;
+        let (x, y) = (self.x, self.y);
+        let (image_width, image_height) = (self.image.width, self.image.height);
+        match self.converted(pixel_format)? {
+            Converted::Bgra(premultiplied) => {
+                let (w, h) = (
+                    image_width.min(width.saturating_sub(x)),
+                    image_height.min(height.saturating_sub(y)),
+                );
+                if w == 0 || h == 0 || guard.base_address().is_null() {
+                    return Ok(());
+                }
+                let top = VImageBuffer {
+                    data: premultiplied.as_ptr() as *mut c_void,
+                    height: h,
+                    width: w,
+                    row_bytes: image_width * 4,
+                };
+                let frame = VImageBuffer {
+                    data: guard.base_address().add(y * guard.bytes_per_row() + x * 4)
+                        as *mut c_void,
+                    height: h,
+                    width: w,
+                    row_bytes: guard.bytes_per_row(),
+                };
+                // Blending in place into the bottom image is supported
+                if vImagePremultipliedAlphaBlend_BGRA8888(&top, &frame, &frame, 0) != 0 {
+                    return Err(kCVReturnInvalidPixelFormat);
+                }
+            }
+            converted @ Converted::Nv12 { .. } => {
+                let luma = plane_mut(pixel_buffer, 0, width, height);
+                let chroma = plane_mut(pixel_buffer, 1, width.div_ceil(2), height.div_ceil(2));
+                if let (Some(luma), Some(chroma)) = (luma, chroma) {
+                    blend_nv12(converted, image_width, luma, chroma, x, y);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The image converted for `pixel_format`, converting it if needed.
+    fn converted(&mut self, pixel_format: u32) -> Result<&Converted, i32> {
+        let stale = self
+            .cache
+            .as_ref()
+            .is_none_or(|c| c.pixel_format != pixel_format || c.opacity != self.opacity);
+        if stale {
+            let converted = match pixel_format {
+                codecs::pixel::BGRA32 => {
+                    Converted::Bgra(to_premultiplied_bgra(&self.image, self.opacity))
+                }
+                codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE => {
+                    to_nv12(&self.image, self.opacity, false)
+                }
+                codecs::pixel::YUV420_BIPLANAR_FULL_RANGE => {
+                    to_nv12(&self.image, self.opacity, true)
+                }
+                _ => return Err(kCVReturnInvalidPixelFormat),
+            };
+            self.cache = Some(Cache {
+                pixel_format,
+                opacity: self.opacity,
+                converted,
+            });
+        }
+        Ok(&self.cache.as_ref().unwrap().converted)
+    }
+}
+
+/// Plane `plane` of a locked pixel buffer, `width` samples by `height` rows.
+unsafe fn plane_mut<'a>(
+    pixel_buffer: CVPixelBufferRef,
+    plane: usize,
+    width: usize,
+    height: usize,
+) -> Option<PlaneMut<'a>> {
+    let base = CVPixelBufferGetBaseAddressOfPlane(pixel_buffer, plane) as *mut u8;
+    if base.is_null() {
+        return None;
+    }
+    let stride = CVPixelBufferGetBytesPerRowOfPlane(pixel_buffer, plane);
+    Some(PlaneMut {
+        data: std::slice::from_raw_parts_mut(base, stride * height),
+        stride,
+        width,
+        height,
+    })
+}
+
+fn scaled_alpha(alpha: u8, opacity: f32) -> u8 {
+    (alpha as f32 * opacity).round() as u8
+}
+
+fn to_premultiplied_bgra(image: &OverlayImage, opacity: f32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(image.rgba.len());
+    for px in image.rgba.chunks_exact(4) {
+        let a = scaled_alpha(px[3], opacity);
+        let mul = |c: u8| ((c as u32 * a as u32 + 127) / 255) as u8;
+        out.extend_from_slice(&[mul(px[2]), mul(px[1]), mul(px[0]), a]);
+    }
+    out
+}
+
+/// BT.709 Y'CbCr of an RGB pixel, as (luma, cb, cr) in the given range.
+fn rgb_to_ycbcr(r: u8, g: u8, b: u8, full_range: bool) -> (u8, u8, u8) {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let cb = (b - y) / 1.8556;
+    let cr = (r - y) / 1.5748;
+    let (y, cb, cr) = if full_range {
+        (y, 128.0 + cb, 128.0 + cr)
+    } else {
+        (
+            16.0 + y * 219.0 / 255.0,
+            128.0 + cb * 224.0 / 255.0,
+            128.0 + cr * 224.0 / 255.0,
+        )
+    };
+    let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    (clamp(y), clamp(cb), clamp(cr))
+}
+
+fn to_nv12(image: &OverlayImage, opacity: f32, full_range: bool) -> Converted {
+    let (w, h) = (image.width, image.height);
+    let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+    let mut luma = vec![0; w * h];
+    let mut alpha = vec![0; w * h];
+    let mut chroma = vec![0; cw * ch * 2];
+    let mut chroma_alpha = vec![0; cw * ch];
+    let mut sums = vec![[0u32; 4]; cw * ch];
+
+    for row in 0..h {
+        for col in 0..w {
+            let px = &image.rgba[(row * w + col) * 4..][..4];
+            let (y, cb, cr) = rgb_to_ycbcr(px[0], px[1], px[2], full_range);
+            let a = scaled_alpha(px[3], opacity);
+            luma[row * w + col] = y;
+            alpha[row * w + col] = a;
+            // Alpha-weighted so transparent pixels don't tint the chroma
+            let sum = &mut sums[(row / 2) * cw + col / 2];
+            sum[0] += cb as u32 * a as u32;
+            sum[1] += cr as u32 * a as u32;
+            sum[2] += a as u32;
+            sum[3] += 1;
+        }
+    }
+    for (i, [cb, cr, a, n]) in sums.into_iter().enumerate() {
+        let average = |sum: u32| (sum + a / 2).checked_div(a).unwrap_or(0) as u8;
+        chroma[i * 2] = average(cb);
+        chroma[i * 2 + 1] = average(cr);
+        chroma_alpha[i] = ((a + n / 2) / n) as u8;
+    }
+    Converted::Nv12 {
+        luma,
+        alpha,
+        chroma,
+        chroma_alpha,
+    }
+}
+
+fn mix(under: u8, over: u8, alpha: u8) -> u8 {
+    let a = alpha as u32;
+    ((under as u32 * (255 - a) + over as u32 * a + 127) / 255) as u8
+}
+
+/// Blend converted overlay rows of `image_width` pixels into the planes at
+/// (`x`, `y`), rounded down to even coordinates.
+fn blend_nv12(
+    converted: &Converted,
+    image_width: usize,
+    luma_plane: PlaneMut,
+    chroma_plane: PlaneMut,
+    x: usize,
+    y: usize,
+) {
+    let Converted::Nv12 {
+        luma,
+        alpha,
+        chroma,
+        chroma_alpha,
+    } = converted
+    else {
+        return;
+    };
+    let (x, y) = (x & !1, y & !1);
+    let image_height = luma.len() / image_width.max(1);
+
+    let w = image_width.min(luma_plane.width.saturating_sub(x));
+    let h = image_height.min(luma_plane.height.saturating_sub(y));
+    for row in 0..h {
+        let dst = &mut luma_plane.data[(y + row) * luma_plane.stride + x..][..w];
+        let src = row * image_width;
+        for (col, px) in dst.iter_mut().enumerate() {
+            *px = mix(*px, luma[src + col], alpha[src + col]);
+        }
+    }
+
+    let chroma_width = image_width.div_ceil(2);
+    let (cx, cy) = (x / 2, y / 2);
+    let w = chroma_width.min(chroma_plane.width.saturating_sub(cx));
+    let h = image_height
+        .div_ceil(2)
+        .min(chroma_plane.height.saturating_sub(cy));
+    for row in 0..h {
+        let dst = &mut chroma_plane.data[(cy + row) * chroma_plane.stride + cx * 2..][..w * 2];
+        let src = row * chroma_width;
+        for (col, pair) in dst.chunks_exact_mut(2).enumerate() {
+            let a = chroma_alpha[src + col];
+            pair[0] = mix(pair[0], chroma[(src + col) * 2], a);
+            pair[1] = mix(pair[1], chroma[(src + col) * 2 + 1], a);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion() {
+        // Opaque white, half-transparent red
+        let image = OverlayImage::new(2, 1, vec![255, 255, 255, 255, 255, 0, 0, 128]).unwrap();
+        assert_eq!(
+            to_premultiplied_bgra(&image, 0.5),
+            [128, 128, 128, 128, 0, 0, 64, 64]
+        );
+        assert_eq!(rgb_to_ycbcr(255, 255, 255, false), (235, 128, 128));
+        assert_eq!(rgb_to_ycbcr(0, 0, 0, true), (0, 128, 128));
+        assert!(OverlayImage::new(2, 2, vec![0; 8]).is_none());
+    }
+
+    #[test]
+    fn test_nv12_blend_is_clipped() {
+        // 4x4 opaque white logo over a 4x4 black video-range frame, hanging
+        // off the bottom-right corner
+        let image = OverlayImage::new(4, 4, vec![255; 64]).unwrap();
+        let converted = to_nv12(&image, 1.0, false);
+        let mut luma = vec![16u8; 6 * 4];
+        let mut chroma = vec![128u8; 6 * 2];
+        let luma_plane = PlaneMut {
+            data: &mut luma,
+            stride: 6,
+            width: 4,
+            height: 4,
+        };
+        let chroma_plane = PlaneMut {
+            data: &mut chroma,
+            stride: 6,
+            width: 2,
+            height: 2,
+        };
+        blend_nv12(&converted, 4, luma_plane, chroma_plane, 3, 2);
+        // Position rounds down to (2, 2); row padding is left alone
+        assert_eq!(&luma[..6], &[16, 16, 16, 16, 16, 16]);
+        assert_eq!(&luma[12..18], &[16, 16, 235, 235, 16, 16]);
+        assert_eq!(&luma[18..], &[16, 16, 235, 235, 16, 16]);
+        assert_eq!(chroma, vec![128; 12]);
+    }
+}
@@ -0,0 +1,298 @@
+//! Encoding frames whose size the encoder cannot take as is.
+//!
+//! H.264 and HEVC encoders need even dimensions for 4:2:0 chroma, so a
+//! 1279x719 capture is rejected or comes out skewed. [`pad_pixel_buffer`]
+//! copies such a frame into an aligned buffer, row by row so the source
+//! stride is respected, and marks the original size as the clean aperture.
+//! The muxers signal the same size with a [`CleanAperture`] so players crop
+//! the padding away again.
+
+use core_foundation::base::TCFType;
+
+use super::audio_cmaf::write_box;
+use super::crop::{clean_aperture, CropRect};
+use super::leak_tracker::release_pixel_buffer;
+use super::pixel_buffer::{create_pixel_buffer, PixelBufferConfig, PixelBufferGuard};
+use crate::codecs;
+use crate::cv_types::{
+    kCVAttachmentMode_ShouldPropagate, kCVImageBufferCleanApertureKey, kCVReturnInvalidPixelFormat,
+    CVBufferSetAttachment, CVPixelBufferGetBaseAddressOfPlane, CVPixelBufferGetBytesPerRowOfPlane,
+    CVPixelBufferGetHeight, CVPixelBufferGetHeightOfPlane, CVPixelBufferGetPixelFormatType,
+    CVPixelBufferGetWidth, CVPixelBufferGetWidthOfPlane, CVPixelBufferIsPlanar, CVPixelBufferRef,
+};
+
+/// The visible region of padded frames: the top-left `width` x `height`
+/// pixels of the coded picture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CleanAperture {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CleanAperture {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+
+    /// Whether a `coded_width` x `coded_height` picture has padding to crop.
+    pub fn crops(&self, coded_width: u32, coded_height: u32) -> bool {
+        self.width < coded_width || self.height < coded_height
+    }
+
+    /// `clap` box for a `coded_width` x `coded_height` sample entry, followed
+    /// by the square-pixel `pasp` box players expect next to it. Offsets are
+    /// from the center of the picture to the center of the aperture, in
+    /// halves of a pixel.
+    pub fn clap_box(&self, coded_width: u32, coded_height: u32) -> Vec<u8> {
+        let horizontal = self.width as i32 - coded_width as i32;
+        let vertical = self.height as i32 - coded_height as i32;
+        let mut clap = Vec::with_capacity(32);
+        for (numerator, denominator) in [
+            (self.width, 1u32),
+            (self.height, 1),
+            (horizontal as u32, 2),
+            (vertical as u32, 2),
+        ] {
+            clap.extend_from_slice(&numerator.to_be_bytes());
+            clap.extend_from_slice(&denominator.to_be_bytes());
+        }
+        let mut buf = Vec::new();
+        write_box(&mut buf, b"clap", &clap);
+        write_box(&mut buf, b"pasp", &[0, 0, 0, 1, 0, 0, 0, 1]);
+        buf
+    }
+}
+
+/// `width` and `height` rounded up to multiples of `alignment`.
+pub fn padded_dimensions(width: usize, height: usize, alignment: u32) -> (usize, usize) {
+    let alignment = alignment.max(1) as usize;
+    (
+        width.div_ceil(alignment) * alignment,
+        height.div_ceil(alignment) * alignment,
+    )
+}
+
+/// Bytes per sample in `plane` of the formats [`pad_pixel_buffer`] handles.
+fn bytes_per_sample(pixel_format: u32, plane: usize) -> Option<usize> {
+    match (pixel_format, plane) {
+        (
+            codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE | codecs::pixel::YUV420_BIPLANAR_FULL_RANGE,
+            0,
+        ) => Some(1),
+        (
+            codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE | codecs::pixel::YUV420_BIPLANAR_FULL_RANGE,
+            1,
+        ) => Some(2),
+        (codecs::pixel::YUV420_PLANAR | codecs::pixel::YUV420_PLANAR_FULL_RANGE, 0..=2) => Some(1),
+        (codecs::pixel::BGRA32 | codecs::pixel::ARGB32, 0) => Some(4),
+        _ => None,
+    }
+}
+
+/// A plane's layout: `width` and `height` in samples, `stride` in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PlaneLayout {
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+/// Copy a `src` plane into a larger `dst` plane row by row, repeating the
+/// last column and row into the padding so the encoder does not smear a
+/// dark edge into the visible picture.
+fn copy_plane_padded(
+    src: &[u8],
+    src_layout: PlaneLayout,
+    dst: &mut [u8],
+    dst_layout: PlaneLayout,
+    bytes_per_sample: usize,
+) {
+    let row_bytes = src_layout.width * bytes_per_sample;
+    let padded_row_bytes = dst_layout.width * bytes_per_sample;
+    for y in 0..src_layout.height {
+        let src_start = y * src_layout.stride;
+        let dst_start = y * dst_layout.stride;
+        let dst_row = &mut dst[dst_start..dst_start + padded_row_bytes];
+        dst_row[..row_bytes].copy_from_slice(&src[src_start..src_start + row_bytes]);
+        if row_bytes >= bytes_per_sample {
+            let last_sample = row_bytes - bytes_per_sample;
+            for x in (row_bytes..padded_row_bytes).step_by(bytes_per_sample) {
+                dst_row.copy_within(last_sample..row_bytes, x);
+            }
+        }
+    }
+    if src_layout.height == 0 {
+        return;
+    }
+    let last_row = (src_layout.height - 1) * dst_layout.stride;
+    for y in src_layout.height..dst_layout.height {
+        dst.copy_within(last_row..last_row + padded_row_bytes, y * dst_layout.stride);
+    }
+}
+
+/// Copy `source` into a new buffer whose dimensions are multiples of
+/// `alignment` (see [`encoder_alignment`](super::encoder_alignment)), or
+/// `None` if it is aligned already and can be encoded as is.
+///
+/// Rows are copied respecting both buffers' strides, the padding repeats the
+/// edge pixels, and the original size is attached as the clean aperture,
+/// which the encoder carries into its format description. Pass the same size
+/// to the muxer as a [`CleanAperture`], e.g. with
+/// [`CmafMuxer::set_clean_aperture`](super::CmafMuxer::set_clean_aperture).
+///
+/// Supports NV12, I420 and 32-bit RGB formats; others fail with
+/// `kCVReturnInvalidPixelFormat`.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{encoder_alignment, pad_pixel_buffer, release_pixel_buffer};
+/// use video_toolbox_sys::codecs;
+/// # let captured: video_toolbox_sys::cv_types::CVPixelBufferRef = std::ptr::null_mut();
+///
+/// let alignment = encoder_alignment(codecs::video::H264);
+/// match unsafe { pad_pixel_buffer(captured, alignment) }.expect("Failed to pad frame") {
+///     Some(padded) => {
+///         // encode padded
+///         unsafe { release_pixel_buffer(padded) };
+///     }
+///     None => {
+///         // encode captured
+///     }
+/// }
+/// ```
+///
+/// # Safety
+///
+/// `source` must be a valid `CVPixelBufferRef`. The returned buffer must be
+/// released by the caller, like one from
+/// [`create_pixel_buffer`](super::create_pixel_buffer).
+pub unsafe fn pad_pixel_buffer(
+    source: CVPixelBufferRef,
+    alignment: u32,
+) -> Result<Option<CVPixelBufferRef>, i32> {
+    let (width, height) = (
+        CVPixelBufferGetWidth(source),
+        CVPixelBufferGetHeight(source),
+    );
+    let (padded_width, padded_height) = padded_dimensions(width, height, alignment);
+    if (padded_width, padded_height) == (width, height) {
+        return Ok(None);
+    }
+    let pixel_format = CVPixelBufferGetPixelFormatType(source);
+    if bytes_per_sample(pixel_format, 0).is_none() {
+        return Err(kCVReturnInvalidPixelFormat);
+    }
+
+    let config = PixelBufferConfig::new(padded_width, padded_height)
+        .pixel_format(pixel_format)
+        .cg_compatible(false)
+        .cg_bitmap_compatible(false);
+    let padded = create_pixel_buffer(&config)?;
+    let result = copy_padded(source, padded, pixel_format);
+    if let Err(status) = result {
+        release_pixel_buffer(padded);
+        return Err(status);
+    }
+
+    let aperture = clean_aperture(CropRect::full(width, height), padded_width, padded_height);
+    CVBufferSetAttachment(
+        padded,
+        kCVImageBufferCleanApertureKey,
+        aperture.as_CFTypeRef(),
+        kCVAttachmentMode_ShouldPropagate,
+    );
+    Ok(Some(padded))
+}
+
+unsafe fn copy_padded(
+    source: CVPixelBufferRef,
+    padded: CVPixelBufferRef,
+    pixel_format: u32,
+) -> Result<(), i32> {
+    let src_guard = PixelBufferGuard::lock(source)?;
+    let dst_guard = PixelBufferGuard::lock(padded)?;
+    for index in 0..src_guard.plane_count() {
+        let bytes_per_sample =
+            bytes_per_sample(pixel_format, index).ok_or(kCVReturnInvalidPixelFormat)?;
+        let src = src_guard.plane(index).ok_or(kCVReturnInvalidPixelFormat)?;
+        let (data, dst_layout) = if CVPixelBufferIsPlanar(padded) != 0 {
+            (
+                CVPixelBufferGetBaseAddressOfPlane(padded, index) as *mut u8,
+                PlaneLayout {
+                    width: CVPixelBufferGetWidthOfPlane(padded, index),
+                    height: CVPixelBufferGetHeightOfPlane(padded, index),
+                    stride: CVPixelBufferGetBytesPerRowOfPlane(padded, index),
+                },
+            )
+        } else {
+            (
+                dst_guard.base_address(),
+                PlaneLayout {
+                    width: CVPixelBufferGetWidth(padded),
+                    height: CVPixelBufferGetHeight(padded),
+                    stride: dst_guard.bytes_per_row(),
+                },
+            )
+        };
+        if data.is_null() {
+            return Err(kCVReturnInvalidPixelFormat);
+        }
+        let dst = std::slice::from_raw_parts_mut(data, dst_layout.stride * dst_layout.height);
+        let src_layout = PlaneLayout {
+            width: src.width,
+            height: src.height,
+            stride: src.stride,
+        };
+        copy_plane_padded(src.data, src_layout, dst, dst_layout, bytes_per_sample);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pad_odd_plane_respecting_stride() {
+        assert_eq!(padded_dimensions(1279, 719, 2), (1280, 720));
+        assert_eq!(padded_dimensions(1280, 720, 2), (1280, 720));
+
+        // 3x3 samples with a 5-byte source stride into a 4x4 plane
+        let src = [1, 2, 3, 0, 0, 4, 5, 6, 0, 0, 7, 8, 9, 0, 0];
+        let src_layout = PlaneLayout {
+            width: 3,
+            height: 3,
+            stride: 5,
+        };
+        let dst_layout = PlaneLayout {
+            width: 4,
+            height: 4,
+            stride: 6,
+        };
+        let mut dst = [0u8; 24];
+        copy_plane_padded(&src, src_layout, &mut dst, dst_layout, 1);
+        let rows: Vec<_> = dst.chunks(6).map(|row| &row[..4]).collect();
+        assert_eq!(
+            rows,
+            [[1, 2, 3, 3], [4, 5, 6, 6], [7, 8, 9, 9], [7, 8, 9, 9]]
+        );
+    }
+
+    #[test]
+    fn test_clap_box_offsets() {
+        let aperture = CleanAperture::new(1279, 719);
+        assert!(aperture.crops(1280, 720));
+        assert!(!CleanAperture::new(1280, 720).crops(1280, 720));
+
+        let boxes = aperture.clap_box(1280, 720);
+        assert_eq!(&boxes[4..8], b"clap");
+        let field = |i: usize| u32::from_be_bytes(boxes[8 + i * 4..12 + i * 4].try_into().unwrap());
+        assert_eq!((field(0), field(1)), (1279, 1));
+        assert_eq!((field(2), field(3)), (719, 1));
+        // Half a pixel left and up of the picture center
+        assert_eq!((field(4) as i32, field(5)), (-1, 2));
+        assert_eq!((field(6) as i32, field(7)), (-1, 2));
+        assert_eq!(&boxes[44..48], b"pasp");
+    }
+}
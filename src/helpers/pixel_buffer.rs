@@ -1,12 +1,9 @@
 //! CVPixelBuffer creation and manipulation utilities.
 
 use core_foundation::base::TCFType;
-use core_foundation::boolean::CFBoolean;
-use core_foundation::dictionary::CFDictionary;
-use core_foundation::number::CFNumber;
-use core_foundation::string::CFString;
 use core_foundation_sys::base::kCFAllocatorDefault;
 use core_foundation_sys::dictionary::CFDictionaryRef;
+use libc::c_void;
 use std::ptr;
 
 use super::cv_ffi::{
@@ -15,8 +12,15 @@ use super::cv_ffi::{
     kCVReturnSuccess, CVPixelBufferCreate, CVPixelBufferGetBaseAddress,
     CVPixelBufferGetBytesPerRow, CVPixelBufferLockBaseAddress, CVPixelBufferUnlockBaseAddress,
 };
+use super::cf_dict::CFDictBuilder;
+use super::leak_tracker::{track, TrackedKind};
 use crate::codecs;
-use crate::cv_types::CVPixelBufferRef;
+use crate::cv_types::{
+    kCVReturnInvalidArgument, kCVReturnInvalidPixelFormat, CVPixelBufferGetBaseAddressOfPlane,
+    CVPixelBufferGetBytesPerRowOfPlane, CVPixelBufferGetHeight, CVPixelBufferGetHeightOfPlane,
+    CVPixelBufferGetPixelFormatType, CVPixelBufferGetPlaneCount, CVPixelBufferGetWidth,
+    CVPixelBufferGetWidthOfPlane, CVPixelBufferIsPlanar, CVPixelBufferRef,
+};
 
 /// Configuration for creating a CVPixelBuffer.
 #[derive(Clone)]
@@ -62,6 +66,23 @@ impl PixelBufferConfig {
         self.cg_bitmap_compatible = enabled;
         self
     }
+
+    /// Pixel buffer attributes for this configuration.
+    pub(super) fn attributes(&self) -> CFDictBuilder {
+        let mut attributes = unsafe {
+            CFDictBuilder::new()
+                .i32(kCVPixelBufferPixelFormatTypeKey, self.pixel_format as i32)
+                .i32(kCVPixelBufferWidthKey, self.width as i32)
+                .i32(kCVPixelBufferHeightKey, self.height as i32)
+        };
+        if self.cg_compatible {
+            attributes.insert(unsafe { kCVPixelBufferCGImageCompatibilityKey }, true);
+        }
+        if self.cg_bitmap_compatible {
+            attributes.insert(unsafe { kCVPixelBufferCGBitmapContextCompatibilityKey }, true);
+        }
+        attributes
+    }
 }
 
 /// Create a CVPixelBuffer with the given configuration.
@@ -77,46 +98,14 @@ impl PixelBufferConfig {
 ///
 /// # Safety
 ///
-/// The returned `CVPixelBufferRef` must be released by the caller using `CFRelease`.
+/// The returned `CVPixelBufferRef` must be released by the caller using `CFRelease`,
+/// or [`release_pixel_buffer`](super::release_pixel_buffer) to keep
+/// [leak tracking](super::LeakCheck) balanced.
 pub fn create_pixel_buffer(config: &PixelBufferConfig) -> Result<CVPixelBufferRef, i32> {
     unsafe {
         let mut pixel_buffer: CVPixelBufferRef = ptr::null_mut();
 
-        // Build attributes dictionary
-        let format_key = CFString::wrap_under_get_rule(kCVPixelBufferPixelFormatTypeKey);
-        let width_key = CFString::wrap_under_get_rule(kCVPixelBufferWidthKey);
-        let height_key = CFString::wrap_under_get_rule(kCVPixelBufferHeightKey);
-
-        let mut pairs = vec![
-            (
-                format_key.as_CFType(),
-                CFNumber::from(config.pixel_format as i32).as_CFType(),
-            ),
-            (
-                width_key.as_CFType(),
-                CFNumber::from(config.width as i32).as_CFType(),
-            ),
-            (
-                height_key.as_CFType(),
-                CFNumber::from(config.height as i32).as_CFType(),
-            ),
-        ];
-
-        if config.cg_compatible {
-            let cg_key = CFString::wrap_under_get_rule(kCVPixelBufferCGImageCompatibilityKey);
-            pairs.push((cg_key.as_CFType(), CFBoolean::true_value().as_CFType()));
-        }
-
-        if config.cg_bitmap_compatible {
-            let cg_bitmap_key =
-                CFString::wrap_under_get_rule(kCVPixelBufferCGBitmapContextCompatibilityKey);
-            pairs.push((
-                cg_bitmap_key.as_CFType(),
-                CFBoolean::true_value().as_CFType(),
-            ));
-        }
-
-        let attrs = CFDictionary::from_CFType_pairs(&pairs);
+        let attrs = config.attributes().build();
 
         let status = CVPixelBufferCreate(
             kCFAllocatorDefault,
@@ -131,10 +120,52 @@ pub fn create_pixel_buffer(config: &PixelBufferConfig) -> Result<CVPixelBufferRe
             return Err(status);
         }
 
+        track(TrackedKind::PixelBuffer, pixel_buffer as *const c_void);
         Ok(pixel_buffer)
     }
 }
 
+/// Byte value that renders black in the given plane of a pixel format.
+fn black_level(pixel_format: u32, plane: usize) -> u8 {
+    match (pixel_format, plane) {
+        (codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE, 0) => 16,
+        (codecs::pixel::YUV420_BIPLANAR_FULL_RANGE, 0) => 0,
+        (
+            codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE | codecs::pixel::YUV420_BIPLANAR_FULL_RANGE,
+            _,
+        ) => 128,
+        _ => 0,
+    }
+}
+
+/// Fill a pixel buffer with black, including any row padding.
+///
+/// RGB formats are zeroed; bi-planar Y'CbCr formats get black luma and neutral chroma.
+///
+/// # Safety
+///
+/// The `pixel_buffer` must be a valid `CVPixelBufferRef`.
+pub unsafe fn fill_black(pixel_buffer: CVPixelBufferRef) -> Result<(), i32> {
+    let guard = PixelBufferGuard::lock(pixel_buffer)?;
+    let pixel_format = CVPixelBufferGetPixelFormatType(pixel_buffer);
+
+    if CVPixelBufferIsPlanar(pixel_buffer) != 0 {
+        for plane in 0..CVPixelBufferGetPlaneCount(pixel_buffer) {
+            let base = CVPixelBufferGetBaseAddressOfPlane(pixel_buffer, plane) as *mut u8;
+            let len = CVPixelBufferGetBytesPerRowOfPlane(pixel_buffer, plane)
+                * CVPixelBufferGetHeightOfPlane(pixel_buffer, plane);
+            if !base.is_null() {
+                ptr::write_bytes(base, black_level(pixel_format, plane), len);
+            }
+        }
+    } else if !guard.base_address().is_null() {
+        let len = guard.bytes_per_row() * CVPixelBufferGetHeight(pixel_buffer);
+        ptr::write_bytes(guard.base_address(), black_level(pixel_format, 0), len);
+    }
+
+    Ok(())
+}
+
 /// RAII guard for locked CVPixelBuffer access.
 ///
 /// Automatically unlocks the pixel buffer when dropped.
@@ -196,6 +227,143 @@ impl PixelBufferGuard {
     pub fn pixel_buffer(&self) -> CVPixelBufferRef {
         self.pixel_buffer
     }
+
+    /// Number of planes: 2 for NV12 ('420v'/'420f'), 3 for I420, and 1 for
+    /// packed formats such as BGRA.
+    pub fn plane_count(&self) -> usize {
+        unsafe {
+            if CVPixelBufferIsPlanar(self.pixel_buffer) != 0 {
+                CVPixelBufferGetPlaneCount(self.pixel_buffer)
+            } else {
+                1
+            }
+        }
+    }
+
+    /// Plane `index`, or `None` if there is no such plane. Plane 0 of a
+    /// packed format is the whole image.
+    pub fn plane(&self, index: usize) -> Option<PlaneView<'_>> {
+        if index >= self.plane_count() {
+            return None;
+        }
+        unsafe {
+            let (data, stride, width, height) = if CVPixelBufferIsPlanar(self.pixel_buffer) != 0 {
+                (
+                    CVPixelBufferGetBaseAddressOfPlane(self.pixel_buffer, index) as *const u8,
+                    CVPixelBufferGetBytesPerRowOfPlane(self.pixel_buffer, index),
+                    CVPixelBufferGetWidthOfPlane(self.pixel_buffer, index),
+                    CVPixelBufferGetHeightOfPlane(self.pixel_buffer, index),
+                )
+            } else {
+                (
+                    self.base_address as *const u8,
+                    self.bytes_per_row,
+                    CVPixelBufferGetWidth(self.pixel_buffer),
+                    CVPixelBufferGetHeight(self.pixel_buffer),
+                )
+            };
+            if data.is_null() {
+                return None;
+            }
+            Some(PlaneView {
+                data: std::slice::from_raw_parts(data, stride * height),
+                stride,
+                width,
+                height,
+            })
+        }
+    }
+
+    /// Bytes [`copy_to_i420`](Self::copy_to_i420) writes: the full-size
+    /// luma plane followed by two quarter-size chroma planes.
+    pub fn i420_len(&self) -> usize {
+        let (width, height) = unsafe {
+            (
+                CVPixelBufferGetWidth(self.pixel_buffer),
+                CVPixelBufferGetHeight(self.pixel_buffer),
+            )
+        };
+        width * height + 2 * width.div_ceil(2) * height.div_ceil(2)
+    }
+
+    /// Copy a 4:2:0 frame (NV12 or I420) into `out` as tightly packed I420
+    /// (Y, then U, then V), e.g. for a software consumer.
+    ///
+    /// Fails with `kCVReturnInvalidPixelFormat` for other formats and
+    /// `kCVReturnInvalidArgument` if `out` is shorter than
+    /// [`i420_len`](Self::i420_len).
+    pub fn copy_to_i420(&self, out: &mut [u8]) -> Result<(), i32> {
+        if out.len() < self.i420_len() {
+            return Err(kCVReturnInvalidArgument);
+        }
+        let pixel_format = unsafe { CVPixelBufferGetPixelFormatType(self.pixel_buffer) };
+        let plane = |index| self.plane(index).ok_or(kCVReturnInvalidPixelFormat);
+        match pixel_format {
+            codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE
+            | codecs::pixel::YUV420_BIPLANAR_FULL_RANGE => {
+                nv12_to_i420(&plane(0)?, &plane(1)?, out);
+            }
+            codecs::pixel::YUV420_PLANAR | codecs::pixel::YUV420_PLANAR_FULL_RANGE => {
+                let luma = plane(0)?;
+                let (luma_out, chroma_out) = out.split_at_mut(luma.width * luma.height);
+                luma.copy_to(luma_out, 1);
+                let u = plane(1)?;
+                let (u_out, v_out) = chroma_out.split_at_mut(u.width * u.height);
+                u.copy_to(u_out, 1);
+                plane(2)?.copy_to(v_out, 1);
+            }
+            _ => return Err(kCVReturnInvalidPixelFormat),
+        }
+        Ok(())
+    }
+}
+
+/// One plane of a locked pixel buffer, borrowed from its guard.
+#[derive(Debug, Clone, Copy)]
+pub struct PlaneView<'a> {
+    /// `stride * height` bytes, including row padding
+    pub data: &'a [u8],
+    /// Bytes per row, including padding
+    pub stride: usize,
+    /// Width in samples; a sample is one byte for Y'CbCr planes, a Cb/Cr
+    /// pair in the NV12 chroma plane and four bytes for BGRA
+    pub width: usize,
+    /// Number of rows
+    pub height: usize,
+}
+
+impl<'a> PlaneView<'a> {
+    /// Row `y`, without padding. `bytes_per_sample` is 2 for the NV12 chroma
+    /// plane and 4 for BGRA.
+    pub fn row(&self, y: usize, bytes_per_sample: usize) -> &'a [u8] {
+        let start = y * self.stride;
+        &self.data[start..start + self.width * bytes_per_sample]
+    }
+
+    /// Copy the rows into `out` without padding.
+    fn copy_to(&self, out: &mut [u8], bytes_per_sample: usize) {
+        let row_bytes = self.width * bytes_per_sample;
+        for y in 0..self.height {
+            let start = y * row_bytes;
+            out[start..start + row_bytes].copy_from_slice(self.row(y, bytes_per_sample));
+        }
+    }
+}
+
+/// Pack NV12 planes into I420, splitting the interleaved chroma. `out` must
+/// hold the luma plane plus two chroma planes of `chroma.width * chroma.height`.
+fn nv12_to_i420(luma: &PlaneView, chroma: &PlaneView, out: &mut [u8]) {
+    let (luma_out, chroma_out) = out.split_at_mut(luma.width * luma.height);
+    luma.copy_to(luma_out, 1);
+    let (u_out, v_out) = chroma_out.split_at_mut(chroma.width * chroma.height);
+    for y in 0..chroma.height {
+        let row = chroma.row(y, 2);
+        let start = y * chroma.width;
+        for (x, pair) in row.chunks_exact(2).enumerate() {
+            u_out[start + x] = pair[0];
+            v_out[start + x] = pair[1];
+        }
+    }
 }
 
 impl Drop for PixelBufferGuard {
@@ -205,3 +373,41 @@ impl Drop for PixelBufferGuard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_black_level() {
+        assert_eq!(black_level(codecs::pixel::BGRA32, 0), 0);
+        assert_eq!(black_level(codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE, 0), 16);
+        assert_eq!(black_level(codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE, 1), 128);
+        assert_eq!(black_level(codecs::pixel::YUV420_BIPLANAR_FULL_RANGE, 0), 0);
+        assert_eq!(black_level(codecs::pixel::YUV420_BIPLANAR_FULL_RANGE, 1), 128);
+    }
+
+    #[test]
+    fn test_nv12_to_i420_drops_padding_and_splits_chroma() {
+        // 3x2 frame: luma stride 4, one chroma row of two Cb/Cr pairs, stride 6
+        let luma_data = [1, 2, 3, 0, 4, 5, 6, 0];
+        let chroma_data = [10, 20, 11, 21, 0, 0];
+        let luma = PlaneView {
+            data: &luma_data,
+            stride: 4,
+            width: 3,
+            height: 2,
+        };
+        let chroma = PlaneView {
+            data: &chroma_data,
+            stride: 6,
+            width: 2,
+            height: 1,
+        };
+        assert_eq!(chroma.row(0, 2), [10, 20, 11, 21]);
+
+        let mut out = [0; 10];
+        nv12_to_i420(&luma, &chroma, &mut out);
+        assert_eq!(out, [1, 2, 3, 4, 5, 6, 10, 11, 20, 21]);
+    }
+}
@@ -0,0 +1,222 @@
+//! Recycled CVPixelBuffers for encoder input.
+
+use core_foundation::base::TCFType;
+use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, CFRetain};
+use libc::c_void;
+use std::ptr;
+
+use super::cf_dict::CFDictBuilder;
+use super::leak_tracker::{track, TrackedKind};
+use super::pixel_buffer::PixelBufferConfig;
+use super::sendable::SendablePixelBuffer;
+use crate::compression::{VTCompressionSessionGetPixelBufferPool, VTCompressionSessionRef};
+use crate::cv_types::{
+    kCVPixelBufferIOSurfacePropertiesKey, kCVPixelBufferPoolAllocationThresholdKey,
+    kCVPixelBufferPoolFlushExcessBuffers, kCVPixelBufferPoolMinimumBufferCountKey,
+    kCVReturnInvalidArgument, kCVReturnSuccess, CVPixelBufferPoolCreate,
+    CVPixelBufferPoolCreatePixelBuffer, CVPixelBufferPoolCreatePixelBufferWithAuxAttributes,
+    CVPixelBufferPoolFlush, CVPixelBufferPoolRef, CVPixelBufferRef,
+};
+
+/// A pool of reusable pixel buffers.
+///
+/// Allocating a CVPixelBuffer per frame maps and zeroes fresh memory every
+/// time; a pool hands out buffers the encoder has finished with instead.
+/// The pool of a compression session
+/// ([`from_compression_session`](Self::from_compression_session)) also
+/// produces buffers in the layout the encoder prefers, so they need no
+/// conversion. Buffers return to the pool when their last reference is
+/// dropped, i.e. after both the [`SendablePixelBuffer`] and the encoder
+/// are done with them.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{make_time, CompressionSessionBuilder, PixelBufferPool};
+///
+/// let session = CompressionSessionBuilder::new(1920, 1080, codecs::video::H264)
+///     .build_session(|_output| {})?;
+/// let pool = unsafe { PixelBufferPool::from_compression_session(session.as_raw())? };
+/// for i in 0..900 {
+///     let frame = pool.acquire()?;
+///     {
+///         let lock = frame.lock()?;
+///         // draw into lock.base_address()
+///     }
+///     unsafe {
+///         session.encode_frame(frame.as_raw(), make_time(i * 3000, 90000), make_time(3000, 90000))?;
+///     }
+/// }
+/// # Ok::<(), i32>(())
+/// ```
+#[derive(Debug)]
+pub struct PixelBufferPool {
+    raw: CVPixelBufferPoolRef,
+    /// Most buffers outstanding at once, if limited
+    allocation_threshold: Option<u32>,
+}
+
+// SAFETY: CVPixelBufferPool is thread-safe; buffers can be created from and
+// returned to it on any thread.
+unsafe impl Send for PixelBufferPool {}
+unsafe impl Sync for PixelBufferPool {}
+
+impl PixelBufferPool {
+    /// Create a standalone pool of IOSurface-backed buffers matching
+    /// `config`, keeping at least `minimum_buffers` allocated.
+    pub fn new(config: &PixelBufferConfig, minimum_buffers: u32) -> Result<Self, i32> {
+        let attributes = unsafe {
+            config
+                .attributes()
+                .dict(kCVPixelBufferIOSurfacePropertiesKey, CFDictBuilder::new())
+        };
+        Self::with_attributes(&attributes, minimum_buffers)
+    }
+
+    /// Create a standalone pool of buffers with the given attributes, which
+    /// must include the pixel format, width and height.
+    pub(super) fn with_attributes(
+        attributes: &CFDictBuilder,
+        minimum_buffers: u32,
+    ) -> Result<Self, i32> {
+        let pool_attributes = unsafe {
+            CFDictBuilder::new().value(kCVPixelBufferPoolMinimumBufferCountKey, minimum_buffers)
+        }
+        .build();
+        let buffer_attributes = attributes.build();
+
+        let mut raw: CVPixelBufferPoolRef = ptr::null();
+        let status = unsafe {
+            CVPixelBufferPoolCreate(
+                kCFAllocatorDefault,
+                pool_attributes.as_concrete_TypeRef(),
+                buffer_attributes.as_concrete_TypeRef(),
+                &mut raw,
+            )
+        };
+        if status != kCVReturnSuccess {
+            return Err(status);
+        }
+        Ok(Self {
+            raw,
+            allocation_threshold: None,
+        })
+    }
+
+    /// Use the pool of a compression session, which matches its source
+    /// image buffer attributes.
+    ///
+    /// Fails with `kCVReturnInvalidArgument` if the session has no pool,
+    /// e.g. because it was created without source buffer attributes.
+    ///
+    /// # Safety
+    ///
+    /// `session` must be a valid compression session.
+    pub unsafe fn from_compression_session(session: VTCompressionSessionRef) -> Result<Self, i32> {
+        let raw = VTCompressionSessionGetPixelBufferPool(session);
+        if raw.is_null() {
+            return Err(kCVReturnInvalidArgument);
+        }
+        CFRetain(raw);
+        Ok(Self {
+            raw,
+            allocation_threshold: None,
+        })
+    }
+
+    /// Limit the buffers outstanding at once. Beyond it, [`acquire`](Self::acquire)
+    /// fails with `kCVReturnWouldExceedAllocationThreshold` instead of
+    /// allocating, e.g. to drop frames when the encoder falls behind.
+    pub fn allocation_threshold(mut self, buffers: u32) -> Self {
+        self.allocation_threshold = Some(buffers);
+        self
+    }
+
+    /// Take a buffer from the pool, allocating one if none is free.
+    ///
+    /// Recycled buffers keep their previous contents.
+    pub fn acquire(&self) -> Result<SendablePixelBuffer, i32> {
+        let mut buffer: CVPixelBufferRef = ptr::null_mut();
+        let status = unsafe {
+            match self.allocation_threshold {
+                Some(threshold) => {
+                    let aux = CFDictBuilder::new()
+                        .value(kCVPixelBufferPoolAllocationThresholdKey, threshold)
+                        .build();
+                    CVPixelBufferPoolCreatePixelBufferWithAuxAttributes(
+                        kCFAllocatorDefault,
+                        self.raw,
+                        aux.as_concrete_TypeRef(),
+                        &mut buffer,
+                    )
+                }
+                None => {
+                    CVPixelBufferPoolCreatePixelBuffer(kCFAllocatorDefault, self.raw, &mut buffer)
+                }
+            }
+        };
+        if status != kCVReturnSuccess {
+            return Err(status);
+        }
+        track(TrackedKind::PixelBuffer, buffer as *const c_void);
+        Ok(unsafe { SendablePixelBuffer::new(buffer) })
+    }
+
+    /// Free the buffers not currently in use, e.g. after a burst.
+    pub fn flush(&self) {
+        unsafe { CVPixelBufferPoolFlush(self.raw, kCVPixelBufferPoolFlushExcessBuffers) };
+    }
+
+    pub fn as_raw(&self) -> CVPixelBufferPoolRef {
+        self.raw
+    }
+}
+
+/// Pool for helpers whose output size or pixel format follows the source,
+/// re-created whenever either changes.
+#[derive(Debug, Default)]
+pub(super) struct OutputPool {
+    pool: Option<((usize, usize, u32), PixelBufferPool)>,
+}
+
+impl OutputPool {
+    /// Take a `width` x `height` buffer in `pixel_format`.
+    pub(super) fn acquire(
+        &mut self,
+        width: usize,
+        height: usize,
+        pixel_format: u32,
+    ) -> Result<SendablePixelBuffer, i32> {
+        let key = (width, height, pixel_format);
+        match &self.pool {
+            Some((current, pool)) if *current == key => pool.acquire(),
+            _ => {
+                let config = PixelBufferConfig::new(width, height)
+                    .pixel_format(pixel_format)
+                    .cg_compatible(false)
+                    .cg_bitmap_compatible(false);
+                let pool = PixelBufferPool::new(&config, 0)?;
+                let buffer = pool.acquire()?;
+                self.pool = Some((key, pool));
+                Ok(buffer)
+            }
+        }
+    }
+}
+
+impl Clone for PixelBufferPool {
+    fn clone(&self) -> Self {
+        unsafe { CFRetain(self.raw) };
+        Self {
+            raw: self.raw,
+            allocation_threshold: self.allocation_threshold,
+        }
+    }
+}
+
+impl Drop for PixelBufferPool {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.raw) };
+    }
+}
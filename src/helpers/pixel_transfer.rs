@@ -0,0 +1,327 @@
+//! Pixel format conversion, scaling and cropping with VTPixelTransferSession.
+
+use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, CFTypeRef, OSStatus};
+use core_foundation_sys::string::CFStringRef;
+use std::ptr;
+
+use super::crop::{clean_aperture, with_clean_aperture, CropRect};
+use super::pixel_buffer_pool::OutputPool;
+use super::sendable::SendablePixelBuffer;
+use crate::cv_types::{
+    CVPixelBufferGetHeight, CVPixelBufferGetPixelFormatType, CVPixelBufferGetWidth,
+    CVPixelBufferRef,
+};
+use crate::pixel_transfer::{
+    kVTDownsamplingMode_Average, kVTDownsamplingMode_Decimate,
+    kVTPixelTransferPropertyKey_DownsamplingMode, kVTPixelTransferPropertyKey_ScalingMode,
+    kVTScalingMode_CropSourceToCleanAperture, kVTScalingMode_Letterbox, kVTScalingMode_Normal,
+    kVTScalingMode_Trim, VTPixelTransferSessionCreate, VTPixelTransferSessionInvalidate,
+    VTPixelTransferSessionRef, VTPixelTransferSessionTransferImage,
+};
+use crate::session::VTSessionSetProperty;
+
+/// How the source is fitted into a destination of a different size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScalingMode {
+    /// Stretch the source to the destination, ignoring aspect ratio
+    #[default]
+    Normal,
+    /// Scale the source's clean aperture to the destination
+    CropSourceToCleanAperture,
+    /// Keep the aspect ratio, padding with black bars
+    Letterbox,
+    /// Keep the aspect ratio, cropping the source to fill the destination
+    Trim,
+}
+
+impl ScalingMode {
+    fn key(self) -> CFStringRef {
+        unsafe {
+            match self {
+                ScalingMode::Normal => kVTScalingMode_Normal,
+                ScalingMode::CropSourceToCleanAperture => kVTScalingMode_CropSourceToCleanAperture,
+                ScalingMode::Letterbox => kVTScalingMode_Letterbox,
+                ScalingMode::Trim => kVTScalingMode_Trim,
+            }
+        }
+    }
+}
+
+/// How pixels are combined when downscaling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DownsamplingMode {
+    /// Average neighboring pixels (higher quality)
+    #[default]
+    Average,
+    /// Drop pixels (faster)
+    Decimate,
+}
+
+/// Builder for a [`PixelTransfer`].
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{PixelTransferBuilder, ScalingMode};
+/// # let nv12_frame = std::ptr::null_mut();
+///
+/// // NV12 camera frames to 720p BGRA, keeping the aspect ratio
+/// let mut transfer = PixelTransferBuilder::new()
+///     .pixel_format(codecs::pixel::BGRA32)
+///     .output_size(1280, 720)
+///     .scaling_mode(ScalingMode::Letterbox)
+///     .build()?;
+/// let bgra = unsafe { transfer.transfer(nv12_frame)? };
+/// # Ok::<(), i32>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PixelTransferBuilder {
+    pixel_format: Option<u32>,
+    output_size: Option<(usize, usize)>,
+    scaling_mode: ScalingMode,
+    downsampling_mode: Option<DownsamplingMode>,
+    crop: Option<CropRect>,
+}
+
+impl PixelTransferBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Destination pixel format (FourCC, see [`codecs::pixel`](crate::codecs::pixel));
+    /// defaults to the source's.
+    pub fn pixel_format(mut self, format: u32) -> Self {
+        self.pixel_format = Some(format);
+        self
+    }
+
+    /// Destination size in pixels; defaults to the crop region's size, or
+    /// the source's.
+    pub fn output_size(mut self, width: usize, height: usize) -> Self {
+        self.output_size = Some((width, height));
+        self
+    }
+
+    pub fn scaling_mode(mut self, mode: ScalingMode) -> Self {
+        self.scaling_mode = mode;
+        self
+    }
+
+    pub fn downsampling_mode(mut self, mode: DownsamplingMode) -> Self {
+        self.downsampling_mode = Some(mode);
+        self
+    }
+
+    /// Only transfer `region` of the source. Implies
+    /// [`ScalingMode::CropSourceToCleanAperture`].
+    pub fn crop(mut self, region: CropRect) -> Self {
+        self.crop = Some(region);
+        self
+    }
+
+    /// Create the transfer session.
+    pub fn build(self) -> Result<PixelTransfer, OSStatus> {
+        let mut session: VTPixelTransferSessionRef = ptr::null();
+        unsafe {
+            // The binding takes the out-pointer as an untyped reference
+            let status = VTPixelTransferSessionCreate(
+                kCFAllocatorDefault,
+                &mut session as *mut VTPixelTransferSessionRef as VTPixelTransferSessionRef,
+            );
+            if status != 0 {
+                return Err(status);
+            }
+            // From here on, Drop releases the session
+            let mut transfer = PixelTransfer {
+                session,
+                pixel_format: self.pixel_format,
+                output_size: self.output_size,
+                scaling_mode: ScalingMode::Normal,
+                crop: None,
+                pool: OutputPool::default(),
+            };
+            transfer.set_scaling_mode(self.scaling_mode)?;
+            if let Some(mode) = self.downsampling_mode {
+                let key = match mode {
+                    DownsamplingMode::Average => kVTDownsamplingMode_Average,
+                    DownsamplingMode::Decimate => kVTDownsamplingMode_Decimate,
+                };
+                transfer.set_property(
+                    kVTPixelTransferPropertyKey_DownsamplingMode,
+                    key as CFTypeRef,
+                )?;
+            }
+            transfer.set_crop(self.crop)?;
+            Ok(transfer)
+        }
+    }
+}
+
+/// Owned VTPixelTransferSession converting pixel buffers between formats
+/// and sizes, e.g. NV12 capture frames to BGRA for display, or 4K to 720p
+/// before encoding.
+///
+/// Each [`transfer`](Self::transfer) takes a destination buffer with the
+/// configured format and size from an internal pool;
+/// [`transfer_into`](Self::transfer_into) writes into an existing one.
+/// Cropping attaches the region to the source as its clean aperture for the
+/// duration of the transfer and puts the source's own back afterwards, as
+/// [`RegionCropper`](super::RegionCropper) does.
+pub struct PixelTransfer {
+    session: VTPixelTransferSessionRef,
+    pixel_format: Option<u32>,
+    output_size: Option<(usize, usize)>,
+    scaling_mode: ScalingMode,
+    crop: Option<CropRect>,
+    pool: OutputPool,
+}
+
+// SAFETY: the transfer session is only used through `&mut self`.
+unsafe impl Send for PixelTransfer {}
+
+impl PixelTransfer {
+    pub fn builder() -> PixelTransferBuilder {
+        PixelTransferBuilder::new()
+    }
+
+    pub fn scaling_mode(&self) -> ScalingMode {
+        self.scaling_mode
+    }
+
+    pub fn set_scaling_mode(&mut self, mode: ScalingMode) -> Result<(), OSStatus> {
+        let effective = if self.crop.is_some() {
+            ScalingMode::CropSourceToCleanAperture
+        } else {
+            mode
+        };
+        unsafe {
+            self.set_property(
+                kVTPixelTransferPropertyKey_ScalingMode,
+                effective.key() as CFTypeRef,
+            )?;
+        }
+        self.scaling_mode = mode;
+        Ok(())
+    }
+
+    pub fn crop(&self) -> Option<CropRect> {
+        self.crop
+    }
+
+    /// Change or remove the crop region for later transfers.
+    pub fn set_crop(&mut self, region: Option<CropRect>) -> Result<(), OSStatus> {
+        self.crop = region;
+        self.set_scaling_mode(self.scaling_mode)
+    }
+
+    /// Size of the buffers [`transfer`](Self::transfer) creates for a
+    /// `width` x `height` source.
+    pub fn output_size_for(&self, width: usize, height: usize) -> (usize, usize) {
+        output_size(self.output_size, self.crop, width, height)
+    }
+
+    /// Transfer `source` into a pooled buffer of the configured format and
+    /// size.
+    ///
+    /// # Safety
+    ///
+    /// `source` must be a valid CVPixelBuffer not being written to
+    /// concurrently. When cropping, nothing else may read or change its
+    /// attachments during the call either.
+    pub unsafe fn transfer(
+        &mut self,
+        source: CVPixelBufferRef,
+    ) -> Result<SendablePixelBuffer, OSStatus> {
+        let (width, height) = self.output_size_for(
+            CVPixelBufferGetWidth(source),
+            CVPixelBufferGetHeight(source),
+        );
+        let format = self
+            .pixel_format
+            .unwrap_or_else(|| CVPixelBufferGetPixelFormatType(source));
+        let output = self.pool.acquire(width, height, format)?;
+        self.transfer_into(source, output.as_raw())?;
+        Ok(output)
+    }
+
+    /// Transfer `source` into `destination`, converting to its format and
+    /// size.
+    ///
+    /// # Safety
+    ///
+    /// Both buffers must be valid, and nothing else may access them during
+    /// the transfer.
+    pub unsafe fn transfer_into(
+        &mut self,
+        source: CVPixelBufferRef,
+        destination: CVPixelBufferRef,
+    ) -> Result<(), OSStatus> {
+        let Some(region) = self.crop else {
+            return crate::errors::status_to_result(VTPixelTransferSessionTransferImage(
+                self.session,
+                source,
+                destination,
+            ));
+        };
+        let (width, height) = (
+            CVPixelBufferGetWidth(source),
+            CVPixelBufferGetHeight(source),
+        );
+        let aperture = clean_aperture(region.clamp_to(width, height).align_even(), width, height);
+        let status = with_clean_aperture(source, &aperture, || {
+            VTPixelTransferSessionTransferImage(self.session, source, destination)
+        });
+        crate::errors::status_to_result(status)
+    }
+
+    unsafe fn set_property(&self, key: CFStringRef, value: CFTypeRef) -> Result<(), OSStatus> {
+        crate::errors::status_to_result(VTSessionSetProperty(self.session, key, value))
+    }
+}
+
+impl Drop for PixelTransfer {
+    fn drop(&mut self) {
+        unsafe {
+            VTPixelTransferSessionInvalidate(self.session);
+            CFRelease(self.session);
+        }
+    }
+}
+
+fn output_size(
+    configured: Option<(usize, usize)>,
+    crop: Option<CropRect>,
+    width: usize,
+    height: usize,
+) -> (usize, usize) {
+    configured
+        .or_else(|| {
+            crop.map(|region| {
+                let region = region.clamp_to(width, height).align_even();
+                (region.width as usize, region.height as usize)
+            })
+        })
+        .unwrap_or((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_size_defaults() {
+        assert_eq!(output_size(None, None, 1920, 1080), (1920, 1080));
+        assert_eq!(
+            output_size(Some((1280, 720)), None, 1920, 1080),
+            (1280, 720)
+        );
+        // The crop region's size, made even (clamping only moves it inside the source)
+        let crop = CropRect::new(1000.0, 500.0, 1001.0, 801.0);
+        assert_eq!(output_size(None, Some(crop), 1920, 1080), (1000, 800));
+        assert_eq!(
+            output_size(Some((640, 360)), Some(crop), 1920, 1080),
+            (640, 360)
+        );
+    }
+}
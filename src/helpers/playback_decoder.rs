@@ -0,0 +1,349 @@
+//! Asynchronous decoder preset for streaming playback.
+
+use core_foundation_sys::base::OSStatus;
+use core_media_sys::{CMSampleBufferRef, CMTime, CMVideoFormatDescriptionRef};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::decompression_session::{
+    DecodeOptions, DecodeOutput, DecodedFrame, DecompressionSession, DecompressionSessionConfig,
+};
+use super::nal_extractor::NalExtractor;
+use super::sendable::SendablePixelBuffer;
+use super::startup::{StartupGate, StartupPolicy, StartupState};
+use crate::cm_sample_buffer::CMFormatDescriptionGetMediaSubType;
+use crate::decompression::DecodeInfoFlags;
+
+/// Frames held back to restore presentation order, enough for B-pyramids.
+const DEFAULT_REORDER_DEPTH: usize = 4;
+
+/// A decoded frame in presentation order.
+pub type PlaybackFrame = DecodedFrame;
+
+type PlaybackCallback = Box<dyn Fn(Result<PlaybackFrame, OSStatus>) + Send + Sync>;
+
+/// Restores presentation order for frames emitted in decode order.
+///
+/// Holds up to `depth` frames and releases the earliest once more arrive.
+/// Streams reordering further than `depth` come out partly out of order.
+#[derive(Debug)]
+struct ReorderBuffer<T> {
+    depth: usize,
+    pending: Vec<(i128, T)>,
+}
+
+impl<T> ReorderBuffer<T> {
+    fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Add a frame, returning the ones now due.
+    fn push(&mut self, key: i128, item: T) -> Vec<T> {
+        self.pending.push((key, item));
+        let mut ready = Vec::new();
+        while self.pending.len() > self.depth {
+            ready.push(self.pop_earliest());
+        }
+        ready
+    }
+
+    fn pop_earliest(&mut self) -> T {
+        let (index, _) = self
+            .pending
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (key, _))| *key)
+            .unwrap();
+        self.pending.swap_remove(index).1
+    }
+
+    /// Remove all frames in presentation order.
+    fn drain(&mut self) -> Vec<T> {
+        self.pending.sort_by_key(|(key, _)| *key);
+        self.pending.drain(..).map(|(_, item)| item).collect()
+    }
+}
+
+/// Presentation time in nanoseconds, for ordering across timescales.
+fn sort_key(time: CMTime) -> i128 {
+    if time.timescale == 0 {
+        return 0;
+    }
+    time.value as i128 * 1_000_000_000 / time.timescale as i128
+}
+
+struct Shared {
+    reorder: Mutex<ReorderBuffer<PlaybackFrame>>,
+    callback: PlaybackCallback,
+    discarding: AtomicBool,
+    dropped: AtomicU64,
+}
+
+impl Shared {
+    fn emit(&self, frames: Vec<PlaybackFrame>) {
+        for frame in frames {
+            (self.callback)(Ok(frame));
+        }
+    }
+}
+
+/// A [`DecompressionSession`] tuned for streaming players.
+///
+/// Frames are decoded asynchronously with temporal processing and the 1x
+/// real-time playback hint ([`DecodeOptions::playback`]), so decode calls
+/// return immediately and the decoder can pace itself for display.
+/// VideoToolbox emits frames in decode order; they are passed through a
+/// small reorder buffer and delivered to the callback in presentation order,
+/// ready for a [`PlaybackScheduler`](super::PlaybackScheduler).
+///
+/// Samples before the first keyframe are discarded, so a player joining
+/// mid-stream shows nothing rather than gray smear; see
+/// [`set_startup_policy`](Self::set_startup_policy) and
+/// [`startup_state`](Self::startup_state).
+///
+/// Call [`flush`](Self::flush) at the end of the stream to wait for pending
+/// frames and deliver the ones still held back, and [`reset`](Self::reset)
+/// when seeking. Dropping the decoder flushes it.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{DecompressionSessionConfig, PlaybackDecoder};
+/// # unsafe fn play(
+/// #     format_desc: core_media_sys::CMVideoFormatDescriptionRef,
+/// #     samples: &[core_media_sys::CMSampleBufferRef],
+/// # ) -> Result<(), i32> {
+/// let config = DecompressionSessionConfig::default();
+/// let decoder = PlaybackDecoder::new(format_desc, &config, |frame| {
+///     if let Ok(frame) = frame {
+///         println!("display {}/{}", frame.pts.value, frame.pts.timescale);
+///     }
+/// })?;
+/// for &sample in samples {
+///     decoder.decode(sample)?;
+/// }
+/// decoder.flush()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct PlaybackDecoder {
+    session: DecompressionSession,
+    /// Codec of the session's format description, for spotting keyframes.
+    codec: u32,
+    shared: Arc<Shared>,
+    options: Mutex<DecodeOptions>,
+    startup: Mutex<StartupGate>,
+}
+
+impl PlaybackDecoder {
+    /// Create a playback decoder for the given format description.
+    ///
+    /// The callback receives frames in presentation order, or the status of
+    /// a failed decode. Dropped frames are counted in
+    /// [`dropped_frames`](Self::dropped_frames).
+    ///
+    /// # Safety
+    ///
+    /// `format_desc` must be a valid video format description.
+    pub unsafe fn new<F>(
+        format_desc: CMVideoFormatDescriptionRef,
+        config: &DecompressionSessionConfig,
+        callback: F,
+    ) -> Result<Self, OSStatus>
+    where
+        F: Fn(Result<PlaybackFrame, OSStatus>) + Send + Sync + 'static,
+    {
+        let shared = Arc::new(Shared {
+            reorder: Mutex::new(ReorderBuffer::new(DEFAULT_REORDER_DEPTH)),
+            callback: Box::new(callback),
+            discarding: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+        });
+
+        let state = shared.clone();
+        let session = DecompressionSession::new(format_desc, config, move |output| match output {
+            DecodeOutput::Frame {
+                image_buffer,
+                pts,
+                duration,
+                ..
+            } => {
+                if state.discarding.load(Ordering::Acquire) {
+                    return;
+                }
+                let frame = PlaybackFrame {
+                    pixel_buffer: unsafe { SendablePixelBuffer::retain(image_buffer) },
+                    pts,
+                    duration,
+                };
+                // Deliver under the lock so frames from concurrent outputs stay ordered
+                let mut reorder = state.reorder.lock().unwrap_or_else(|e| e.into_inner());
+                let ready = reorder.push(sort_key(pts), frame);
+                state.emit(ready);
+            }
+            DecodeOutput::Dropped { .. } => {
+                state.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            DecodeOutput::Suppressed { .. } => {}
+            DecodeOutput::Error(status) => (state.callback)(Err(status)),
+        })?;
+
+        Ok(Self {
+            session,
+            codec: CMFormatDescriptionGetMediaSubType(format_desc),
+            shared,
+            options: Mutex::new(DecodeOptions::playback()),
+            startup: Mutex::new(StartupGate::default()),
+        })
+    }
+
+    /// Frames held back to restore presentation order (default: 4). Use 0
+    /// for streams without frame reordering to avoid the added latency.
+    pub fn set_reorder_depth(&self, frames: usize) {
+        let mut reorder = self
+            .shared
+            .reorder
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        reorder.depth = frames;
+        let count = reorder.pending.len().saturating_sub(frames);
+        let ready = (0..count).map(|_| reorder.pop_earliest()).collect();
+        self.shared.emit(ready);
+    }
+
+    /// Enable or disable the 1x real-time playback hint (default: enabled).
+    ///
+    /// Disable it while scrubbing or decoding faster than real time.
+    pub fn set_real_time(&self, enabled: bool) {
+        let mut options = self.options.lock().unwrap_or_else(|e| e.into_inner());
+        *options = options.real_time_playback(enabled);
+    }
+
+    /// How samples before the first keyframe are handled (default:
+    /// [`StartupPolicy::WaitForKeyframe`]).
+    pub fn set_startup_policy(&self, policy: StartupPolicy) {
+        self.startup
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .set_policy(policy);
+    }
+
+    /// Whether decoding has started from a keyframe, for showing a waiting
+    /// indicator. Changes are also reported as
+    /// [`PipelineEvent::StartupStateChanged`](super::PipelineEvent::StartupStateChanged).
+    pub fn startup_state(&self) -> StartupState {
+        self.startup.lock().unwrap_or_else(|e| e.into_inner()).state()
+    }
+
+    /// Options used for each decode.
+    pub fn options(&self) -> DecodeOptions {
+        *self.options.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Submit a sample buffer for asynchronous decoding.
+    ///
+    /// Samples discarded by the startup policy return
+    /// [`DecodeInfoFlags::FRAME_DROPPED`] without being decoded.
+    ///
+    /// # Safety
+    ///
+    /// `sample_buffer` must be a valid sample buffer matching the session's format.
+    pub unsafe fn decode(
+        &self,
+        sample_buffer: CMSampleBufferRef,
+    ) -> Result<DecodeInfoFlags, OSStatus> {
+        let is_keyframe = NalExtractor::new().is_keyframe_for_codec(sample_buffer, self.codec);
+        let admitted = self
+            .startup
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .admit(is_keyframe);
+        if !admitted {
+            return Ok(DecodeInfoFlags::FRAME_DROPPED);
+        }
+        self.session.decode(sample_buffer, self.options())
+    }
+
+    /// Wait for all pending frames, then deliver the frames held for
+    /// reordering.
+    pub fn flush(&self) -> Result<(), OSStatus> {
+        self.session.wait_for_asynchronous_frames()?;
+        let mut reorder = self
+            .shared
+            .reorder
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let ready = reorder.drain();
+        self.shared.emit(ready);
+        Ok(())
+    }
+
+    /// Wait for pending frames and discard them along with the frames held
+    /// for reordering, e.g. before decoding from a new seek position. The
+    /// startup policy applies again until the next keyframe.
+    pub fn reset(&self) -> Result<(), OSStatus> {
+        self.startup
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .reset();
+        self.shared.discarding.store(true, Ordering::Release);
+        let result = self.session.wait_for_asynchronous_frames();
+        self.shared.discarding.store(false, Ordering::Release);
+        self.shared
+            .reorder
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain();
+        result
+    }
+
+    /// Frames the decoder dropped, e.g. to keep up with real time.
+    pub fn dropped_frames(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The underlying session, e.g. to [`prime`](DecompressionSession::prime)
+    /// reference frames after a [`reset`](Self::reset).
+    pub fn session(&self) -> &DecompressionSession {
+        &self.session
+    }
+}
+
+impl Drop for PlaybackDecoder {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reorder_buffer_restores_presentation_order() {
+        // Decode order of an IBBP GOP: I0 P3 B1 B2 P6 B4 B5
+        let mut reorder = ReorderBuffer::new(2);
+        let mut output = Vec::new();
+        for pts in [0, 3, 1, 2, 6, 4, 5] {
+            output.extend(reorder.push(pts, pts));
+        }
+        output.extend(reorder.drain());
+        assert_eq!(output, vec![0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_sort_key_across_timescales() {
+        let time = |value, timescale| CMTime {
+            value,
+            timescale,
+            flags: 1,
+            epoch: 0,
+        };
+        assert_eq!(sort_key(time(1, 30)), sort_key(time(1000, 30_000)));
+        assert!(sort_key(time(1, 30)) < sort_key(time(34, 1000)));
+        assert_eq!(sort_key(time(5, 0)), 0);
+    }
+}
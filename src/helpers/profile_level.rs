@@ -0,0 +1,416 @@
+//! Typed H.264 profiles and levels with level derivation and validation.
+//!
+//! Level limits follow ITU-T H.264 Table A-1: maximum macroblock rate, frame
+//! size, bitrate and decoded picture buffer size. [`derive_level`] picks the lowest level that fits a
+//! stream, and [`validate`] reports why a requested profile/level cannot carry it.
+
+use core_foundation_sys::string::CFStringRef;
+
+use crate::compression::*;
+
+/// H.264 profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Profile {
+    Baseline,
+    Main,
+    High,
+}
+
+impl Profile {
+    /// `profile_idc` as written to the SPS and avcC.
+    pub fn profile_idc(&self) -> u8 {
+        match self {
+            Profile::Baseline => 66,
+            Profile::Main => 77,
+            Profile::High => 100,
+        }
+    }
+
+    /// `constraint_set` flags byte (profile_compatibility in avcC).
+    pub fn constraint_flags(&self) -> u8 {
+        match self {
+            // Constrained Baseline: constraint_set0 and constraint_set1
+            Profile::Baseline => 0xC0,
+            // constraint_set1: decodable by Main decoders
+            Profile::Main => 0x40,
+            Profile::High => 0x00,
+        }
+    }
+
+    /// Look up a profile from its `profile_idc`.
+    pub fn from_idc(profile_idc: u8) -> Option<Self> {
+        match profile_idc {
+            66 => Some(Profile::Baseline),
+            77 => Some(Profile::Main),
+            100 => Some(Profile::High),
+            _ => None,
+        }
+    }
+
+    /// `cpbBrVclFactor`: bits per MaxBR unit (High allows 25% more).
+    fn bitrate_factor(&self) -> u64 {
+        match self {
+            Profile::Baseline | Profile::Main => 1000,
+            Profile::High => 1250,
+        }
+    }
+}
+
+/// H.264 level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    L1,
+    L1_1,
+    L1_2,
+    L1_3,
+    L2,
+    L2_1,
+    L2_2,
+    L3,
+    L3_1,
+    L3_2,
+    L4,
+    L4_1,
+    L4_2,
+    L5,
+    L5_1,
+    L5_2,
+    L6,
+    L6_1,
+    L6_2,
+}
+
+/// Per-level limits: (level, level_idc, MaxMBPS, MaxFS, MaxBR in kbit/s, MaxDpbMbs)
+const LEVEL_LIMITS: [(Level, u8, u64, u64, u64, u64); 19] = [
+    (Level::L1, 10, 1_485, 99, 64, 396),
+    (Level::L1_1, 11, 3_000, 396, 192, 900),
+    (Level::L1_2, 12, 6_000, 396, 384, 2_376),
+    (Level::L1_3, 13, 11_880, 396, 768, 2_376),
+    (Level::L2, 20, 11_880, 396, 2_000, 2_376),
+    (Level::L2_1, 21, 19_800, 792, 4_000, 4_752),
+    (Level::L2_2, 22, 20_250, 1_620, 4_000, 8_100),
+    (Level::L3, 30, 40_500, 1_620, 10_000, 8_100),
+    (Level::L3_1, 31, 108_000, 3_600, 14_000, 18_000),
+    (Level::L3_2, 32, 216_000, 5_120, 20_000, 20_480),
+    (Level::L4, 40, 245_760, 8_192, 20_000, 32_768),
+    (Level::L4_1, 41, 245_760, 8_192, 50_000, 32_768),
+    (Level::L4_2, 42, 522_240, 8_704, 50_000, 34_816),
+    (Level::L5, 50, 589_824, 22_080, 135_000, 110_400),
+    (Level::L5_1, 51, 983_040, 36_864, 240_000, 184_320),
+    (Level::L5_2, 52, 2_073_600, 36_864, 240_000, 184_320),
+    (Level::L6, 60, 4_177_920, 139_264, 240_000, 696_320),
+    (Level::L6_1, 61, 8_355_840, 139_264, 480_000, 696_320),
+    (Level::L6_2, 62, 16_711_680, 139_264, 800_000, 696_320),
+];
+
+impl Level {
+    fn limits(&self) -> (u8, u64, u64, u64, u64) {
+        let (_, idc, mbps, fs, br, dpb) = LEVEL_LIMITS[*self as usize];
+        (idc, mbps, fs, br, dpb)
+    }
+
+    /// `level_idc` as written to the SPS and avcC (e.g. 31 for level 3.1).
+    pub fn level_idc(&self) -> u8 {
+        self.limits().0
+    }
+
+    /// Look up a level from its `level_idc`.
+    pub fn from_idc(level_idc: u8) -> Option<Self> {
+        LEVEL_LIMITS
+            .iter()
+            .find(|(_, idc, ..)| *idc == level_idc)
+            .map(|(level, ..)| *level)
+    }
+
+    /// Maximum macroblocks per second.
+    pub fn max_macroblock_rate(&self) -> u64 {
+        self.limits().1
+    }
+
+    /// Maximum frame size in macroblocks.
+    pub fn max_frame_size(&self) -> u64 {
+        self.limits().2
+    }
+
+    /// Maximum video bitrate in bits per second for the given profile.
+    pub fn max_bitrate(&self, profile: Profile) -> u64 {
+        self.limits().3 * profile.bitrate_factor()
+    }
+
+    /// Maximum decoded picture buffer size in macroblocks.
+    pub fn max_dpb_macroblocks(&self) -> u64 {
+        self.limits().4
+    }
+
+    /// Maximum number of frames of `frame_macroblocks` each the decoded
+    /// picture buffer can hold (at most 16).
+    pub fn max_dpb_frames(&self, frame_macroblocks: u64) -> u64 {
+        (self.max_dpb_macroblocks() / frame_macroblocks.max(1)).min(16)
+    }
+}
+
+/// Stream parameters that constrain the level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamParams {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f64,
+    /// Average bitrate in bits per second, if known
+    pub bitrate: Option<u64>,
+}
+
+/// Errors from profile/level validation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileLevelError {
+    /// Frame has more macroblocks, or is wider/taller, than the level allows
+    FrameTooLarge { level: Level, macroblocks: u64 },
+    /// Macroblock rate (frame size x frame rate) exceeds the level
+    MacroblockRateTooHigh { level: Level, rate: u64 },
+    /// Bitrate exceeds the level's maximum for the profile
+    BitrateTooHigh { level: Level, bitrate: u64 },
+    /// More reference frames than the level's decoded picture buffer holds
+    DpbTooLarge { level: Level, frames: u64, max: u64 },
+    /// No H.264 level can carry the stream
+    NoSuitableLevel,
+    /// VideoToolbox has no constant for this profile/level combination
+    UnsupportedByEncoder { profile: Profile, level: Level },
+}
+
+impl std::fmt::Display for ProfileLevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileLevelError::FrameTooLarge { level, macroblocks } => write!(
+                f,
+                "Frame of {} macroblocks exceeds level {:?} (max {})",
+                macroblocks,
+                level,
+                level.max_frame_size()
+            ),
+            ProfileLevelError::MacroblockRateTooHigh { level, rate } => write!(
+                f,
+                "Macroblock rate {}/s exceeds level {:?} (max {})",
+                rate,
+                level,
+                level.max_macroblock_rate()
+            ),
+            ProfileLevelError::BitrateTooHigh { level, bitrate } => {
+                write!(f, "Bitrate {} bps exceeds level {:?}", bitrate, level)
+            }
+            ProfileLevelError::DpbTooLarge { level, frames, max } => write!(
+                f,
+                "{} reference frames exceed level {:?} DPB (max {})",
+                frames, level, max
+            ),
+            ProfileLevelError::NoSuitableLevel => write!(f, "No H.264 level fits the stream"),
+            ProfileLevelError::UnsupportedByEncoder { profile, level } => write!(
+                f,
+                "VideoToolbox does not support {:?} profile at level {:?}",
+                profile, level
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProfileLevelError {}
+
+/// Check that `level` can carry the stream under `profile`.
+pub fn validate(
+    profile: Profile,
+    level: Level,
+    params: &StreamParams,
+) -> Result<(), ProfileLevelError> {
+    let mbs_wide = (params.width as u64).div_ceil(16);
+    let mbs_high = (params.height as u64).div_ceil(16);
+    let macroblocks = mbs_wide * mbs_high;
+
+    // Each dimension is limited to sqrt(8 * MaxFS) macroblocks
+    let max_dimension = ((8 * level.max_frame_size()) as f64).sqrt() as u64;
+    if macroblocks > level.max_frame_size() || mbs_wide > max_dimension || mbs_high > max_dimension
+    {
+        return Err(ProfileLevelError::FrameTooLarge { level, macroblocks });
+    }
+
+    let rate = (macroblocks as f64 * params.frame_rate).ceil() as u64;
+    if rate > level.max_macroblock_rate() {
+        return Err(ProfileLevelError::MacroblockRateTooHigh { level, rate });
+    }
+
+    if let Some(bitrate) = params.bitrate {
+        if bitrate > level.max_bitrate(profile) {
+            return Err(ProfileLevelError::BitrateTooHigh { level, bitrate });
+        }
+    }
+
+    Ok(())
+}
+
+/// Derive the lowest level that can carry the stream under `profile`.
+pub fn derive_level(profile: Profile, params: &StreamParams) -> Result<Level, ProfileLevelError> {
+    LEVEL_LIMITS
+        .iter()
+        .map(|(level, ..)| *level)
+        .find(|level| validate(profile, *level, params).is_ok())
+        .ok_or(ProfileLevelError::NoSuitableLevel)
+}
+
+/// A resolved profile and level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProfileLevel {
+    pub profile: Profile,
+    pub level: Level,
+}
+
+impl ProfileLevel {
+    /// Resolve a profile and level for a stream.
+    ///
+    /// Uses `level` if given (after validating it), otherwise derives the
+    /// lowest level that fits.
+    pub fn resolve(
+        profile: Profile,
+        level: Option<Level>,
+        params: &StreamParams,
+    ) -> Result<Self, ProfileLevelError> {
+        let level = match level {
+            Some(level) => {
+                validate(profile, level, params)?;
+                level
+            }
+            None => derive_level(profile, params)?,
+        };
+        Ok(Self { profile, level })
+    }
+
+    /// `[profile_idc, profile_compatibility, level_idc]` for the avcC box.
+    pub fn avcc_bytes(&self) -> [u8; 3] {
+        [
+            self.profile.profile_idc(),
+            self.profile.constraint_flags(),
+            self.level.level_idc(),
+        ]
+    }
+
+    /// The matching `kVTProfileLevel_H264_*` constant.
+    ///
+    /// VideoToolbox only defines levels 3.0 to 5.2 (plus Baseline 1.3), so lower
+    /// levels map to the smallest defined level (limits only grow with level).
+    pub fn vt_profile_level(&self) -> Result<CFStringRef, ProfileLevelError> {
+        let unsupported = ProfileLevelError::UnsupportedByEncoder {
+            profile: self.profile,
+            level: self.level,
+        };
+        unsafe {
+            let value = match (self.profile, self.level) {
+                (Profile::Baseline, l) if l <= Level::L1_3 => kVTProfileLevel_H264_Baseline_1_3,
+                (Profile::Baseline, l) if l <= Level::L3 => kVTProfileLevel_H264_Baseline_3_0,
+                (Profile::Baseline, Level::L3_1) => kVTProfileLevel_H264_Baseline_3_1,
+                (Profile::Baseline, Level::L3_2) => kVTProfileLevel_H264_Baseline_3_2,
+                (Profile::Baseline, Level::L4) => kVTProfileLevel_H264_Baseline_4_0,
+                (Profile::Baseline, Level::L4_1) => kVTProfileLevel_H264_Baseline_4_1,
+                (Profile::Baseline, Level::L4_2) => kVTProfileLevel_H264_Baseline_4_2,
+                (Profile::Baseline, Level::L5) => kVTProfileLevel_H264_Baseline_5_0,
+                (Profile::Baseline, Level::L5_1) => kVTProfileLevel_H264_Baseline_5_1,
+                (Profile::Baseline, Level::L5_2) => kVTProfileLevel_H264_Baseline_5_2,
+                (Profile::Main, l) if l <= Level::L3 => kVTProfileLevel_H264_Main_3_0,
+                (Profile::Main, Level::L3_1) => kVTProfileLevel_H264_Main_3_1,
+                (Profile::Main, Level::L3_2) => kVTProfileLevel_H264_Main_3_2,
+                (Profile::Main, Level::L4) => kVTProfileLevel_H264_Main_4_0,
+                (Profile::Main, Level::L4_1) => kVTProfileLevel_H264_Main_4_1,
+                (Profile::Main, Level::L4_2) => kVTProfileLevel_H264_Main_4_2,
+                (Profile::Main, Level::L5) => kVTProfileLevel_H264_Main_5_0,
+                (Profile::Main, Level::L5_1) => kVTProfileLevel_H264_Main_5_1,
+                (Profile::Main, Level::L5_2) => kVTProfileLevel_H264_Main_5_2,
+                (Profile::High, l) if l <= Level::L3 => kVTProfileLevel_H264_High_3_0,
+                (Profile::High, Level::L3_1) => kVTProfileLevel_H264_High_3_1,
+                (Profile::High, Level::L3_2) => kVTProfileLevel_H264_High_3_2,
+                (Profile::High, Level::L4) => kVTProfileLevel_H264_High_4_0,
+                (Profile::High, Level::L4_1) => kVTProfileLevel_H264_High_4_1,
+                (Profile::High, Level::L4_2) => kVTProfileLevel_H264_High_4_2,
+                (Profile::High, Level::L5) => kVTProfileLevel_H264_High_5_0,
+                (Profile::High, Level::L5_1) => kVTProfileLevel_H264_High_5_1,
+                (Profile::High, Level::L5_2) => kVTProfileLevel_H264_High_5_2,
+                _ => return Err(unsupported),
+            };
+            Ok(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(width: u32, height: u32, frame_rate: f64, bitrate: Option<u64>) -> StreamParams {
+        StreamParams {
+            width,
+            height,
+            frame_rate,
+            bitrate,
+        }
+    }
+
+    #[test]
+    fn test_level_idc_round_trip() {
+        for (level, idc, ..) in LEVEL_LIMITS {
+            assert_eq!(level.level_idc(), idc);
+            assert_eq!(Level::from_idc(idc), Some(level));
+        }
+        assert_eq!(Profile::from_idc(100), Some(Profile::High));
+        assert_eq!(Profile::from_idc(1), None);
+    }
+
+    #[test]
+    fn test_derive_level_common_formats() {
+        let derive = |w, h, fps| derive_level(Profile::High, &params(w, h, fps, None)).unwrap();
+        assert_eq!(derive(640, 480, 30.0), Level::L3);
+        assert_eq!(derive(1280, 720, 30.0), Level::L3_1);
+        assert_eq!(derive(1280, 720, 60.0), Level::L3_2);
+        assert_eq!(derive(1920, 1080, 30.0), Level::L4);
+        assert_eq!(derive(1920, 1080, 60.0), Level::L4_2);
+        assert_eq!(derive(3840, 2160, 30.0), Level::L5_1);
+    }
+
+    #[test]
+    fn test_bitrate_raises_level() {
+        let p = params(1920, 1080, 30.0, Some(25_000_000));
+        // Level 4 allows 20 Mbps for Main but 25 Mbps for High
+        assert_eq!(derive_level(Profile::High, &p).unwrap(), Level::L4);
+        assert_eq!(derive_level(Profile::Main, &p).unwrap(), Level::L4_1);
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let p = params(1920, 1080, 30.0, None);
+        assert!(matches!(
+            validate(Profile::High, Level::L3_1, &p),
+            Err(ProfileLevelError::FrameTooLarge { .. })
+        ));
+        assert!(matches!(
+            validate(Profile::High, Level::L4, &params(1920, 1080, 60.0, None)),
+            Err(ProfileLevelError::MacroblockRateTooHigh { .. })
+        ));
+        assert!(matches!(
+            ProfileLevel::resolve(
+                Profile::Main,
+                Some(Level::L4),
+                &params(1920, 1080, 30.0, Some(30_000_000))
+            ),
+            Err(ProfileLevelError::BitrateTooHigh { .. })
+        ));
+        assert_eq!(
+            derive_level(Profile::High, &params(16384, 16384, 120.0, None)),
+            Err(ProfileLevelError::NoSuitableLevel)
+        );
+    }
+
+    #[test]
+    fn test_avcc_bytes() {
+        let pl =
+            ProfileLevel::resolve(Profile::High, None, &params(1280, 720, 30.0, None)).unwrap();
+        assert_eq!(pl.avcc_bytes(), [100, 0x00, 31]);
+        let pl = ProfileLevel {
+            profile: Profile::Baseline,
+            level: Level::L3,
+        };
+        assert_eq!(pl.avcc_bytes(), [66, 0xC0, 30]);
+    }
+}
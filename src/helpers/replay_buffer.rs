@@ -0,0 +1,142 @@
+//! Rolling buffer of recent encoded frames.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::source::{FrameSource, MediaFrame};
+
+/// Holds the most recent frames covering at least a configured duration.
+///
+/// Frames are evicted a whole GOP at a time, so the buffer always starts at a
+/// keyframe and its contents can be muxed into a decodable file at any time
+/// (e.g. "save the last 30 seconds", or the pre-roll of a triggered
+/// recording). Leading frames before the first keyframe are discarded.
+///
+/// The buffer spans between `duration` and `duration` plus one GOP.
+#[derive(Debug, Clone)]
+pub struct ReplayBuffer {
+    duration: Duration,
+    frames: VecDeque<MediaFrame>,
+}
+
+impl ReplayBuffer {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Append a frame (in decode order), evicting GOPs older than the duration.
+    pub fn push(&mut self, frame: MediaFrame) {
+        if self.frames.is_empty() && !frame.is_keyframe {
+            return;
+        }
+        self.frames.push_back(frame);
+
+        // Drop the oldest GOP while the remaining frames still cover the duration
+        while let Some(pos) = self.frames.iter().skip(1).position(|f| f.is_keyframe) {
+            let next = pos + 1;
+            if span(&self.frames[next], self.frames.back().unwrap()) < self.duration {
+                break;
+            }
+            self.frames.drain(..next);
+        }
+    }
+
+    /// Configured minimum duration.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Duration of the buffered frames.
+    pub fn buffered(&self) -> Duration {
+        match (self.frames.front(), self.frames.back()) {
+            (Some(first), Some(last)) => span(first, last),
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Buffered frames in decode order, starting at a keyframe.
+    pub fn frames(&self) -> impl Iterator<Item = &MediaFrame> {
+        self.frames.iter()
+    }
+
+    /// Remove and return all buffered frames.
+    pub fn drain(&mut self) -> Vec<MediaFrame> {
+        self.frames.drain(..).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+/// Time from the start of `first` to the end of `last`.
+fn span(first: &MediaFrame, last: &MediaFrame) -> Duration {
+    let ticks = last.timing.dts + last.timing.duration - first.timing.dts;
+    Duration::from_secs_f64(ticks.max(0) as f64 / last.timing.timescale.max(1) as f64)
+}
+
+/// Drains the buffer front to back.
+impl FrameSource for ReplayBuffer {
+    fn next_frame(&mut self) -> Option<MediaFrame> {
+        self.frames.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::SampleTiming;
+
+    /// 1 fps at timescale 1, keyframe every `gop` frames.
+    fn frame(index: i64, gop: i64) -> MediaFrame {
+        MediaFrame {
+            nal_units: Vec::new(),
+            timing: SampleTiming {
+                pts: index,
+                dts: index,
+                duration: 1,
+                timescale: 1,
+            },
+            is_keyframe: index % gop == 0,
+            motion_score: None,
+        }
+    }
+
+    #[test]
+    fn test_replay_buffer_evicts_whole_gops() {
+        let mut buffer = ReplayBuffer::new(Duration::from_secs(5));
+        for i in 0..20 {
+            buffer.push(frame(i, 4));
+            assert!(buffer.frames().next().unwrap().is_keyframe);
+            assert!(i < 5 || buffer.buffered() >= Duration::from_secs(5));
+            assert!(buffer.buffered() < Duration::from_secs(5 + 4));
+        }
+        // Frames 12..20: the GOP at 16 alone would only cover 4 seconds
+        let dts: Vec<i64> = buffer.frames().map(|f| f.timing.dts).collect();
+        assert_eq!(dts, (12..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_replay_buffer_starts_at_keyframe() {
+        let mut buffer = ReplayBuffer::new(Duration::from_secs(2));
+        buffer.push(frame(1, 4));
+        buffer.push(frame(2, 4));
+        assert!(buffer.is_empty());
+        buffer.push(frame(4, 4));
+        buffer.push(frame(5, 4));
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.drain().len(), 2);
+        assert!(buffer.next_frame().is_none());
+    }
+}
@@ -0,0 +1,209 @@
+//! Rotation and mirroring of decoded images.
+
+use core_foundation::base::TCFType;
+use core_foundation::boolean::CFBoolean;
+use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, CFTypeRef, OSStatus};
+use core_foundation_sys::string::CFStringRef;
+use std::ptr;
+
+use super::pixel_buffer_pool::OutputPool;
+use crate::cv_types::{
+    CVPixelBufferGetHeight, CVPixelBufferGetPixelFormatType, CVPixelBufferGetWidth,
+    CVPixelBufferRef,
+};
+use crate::pixel_rotation::{
+    kVTPixelRotationPropertyKey_FlipHorizontalOrientation,
+    kVTPixelRotationPropertyKey_FlipVerticalOrientation, kVTPixelRotationPropertyKey_Rotation,
+    kVTRotation_0, kVTRotation_180, kVTRotation_CCW90, kVTRotation_CW90,
+    VTPixelRotationSessionCreate, VTPixelRotationSessionInvalidate, VTPixelRotationSessionRef,
+    VTPixelRotationSessionRotateImage,
+};
+use crate::session::VTSessionSetProperty;
+
+/// Clockwise rotation of an image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Rotate180,
+    Clockwise270,
+}
+
+impl Rotation {
+    /// The rotation for an angle in degrees, such as the rotation recorded
+    /// in a phone video's track matrix. Returns `None` for angles that are
+    /// not a multiple of 90.
+    pub fn from_degrees(degrees: i32) -> Option<Self> {
+        match degrees.rem_euclid(360) {
+            0 => Some(Self::None),
+            90 => Some(Self::Clockwise90),
+            180 => Some(Self::Rotate180),
+            270 => Some(Self::Clockwise270),
+            _ => None,
+        }
+    }
+
+    /// True for quarter turns, which swap width and height.
+    pub fn swaps_dimensions(self) -> bool {
+        matches!(self, Self::Clockwise90 | Self::Clockwise270)
+    }
+
+    fn key(self) -> CFStringRef {
+        unsafe {
+            match self {
+                Self::None => kVTRotation_0,
+                Self::Clockwise90 => kVTRotation_CW90,
+                Self::Rotate180 => kVTRotation_180,
+                Self::Clockwise270 => kVTRotation_CCW90,
+            }
+        }
+    }
+}
+
+/// Rotation and mirroring applied to output images. Mirroring is applied
+/// after rotation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputOrientation {
+    pub rotation: Rotation,
+    /// Mirror left to right
+    pub flip_horizontal: bool,
+    /// Mirror top to bottom
+    pub flip_vertical: bool,
+}
+
+impl OutputOrientation {
+    /// True if images are passed through unchanged.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Output size for a `width` x `height` source image.
+    pub fn output_size(&self, width: usize, height: usize) -> (usize, usize) {
+        if self.rotation.swaps_dimensions() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+}
+
+/// Rotates and mirrors pixel buffers with a VTPixelRotationSession.
+///
+/// Requires macOS 13 or iOS 16, hence the `pixel-rotation` feature. Used by
+/// [`DecompressionSession`](super::DecompressionSession) when its config has
+/// an [`OutputOrientation`], so portrait footage is delivered upright.
+/// Rotated images come from a pool and are recycled once released.
+pub struct ImageRotator {
+    session: VTPixelRotationSessionRef,
+    orientation: OutputOrientation,
+    pool: OutputPool,
+}
+
+// SAFETY: the rotation session is only used through `&mut self`.
+unsafe impl Send for ImageRotator {}
+
+impl ImageRotator {
+    pub fn new(orientation: OutputOrientation) -> Result<Self, OSStatus> {
+        let mut session: VTPixelRotationSessionRef = ptr::null();
+        unsafe {
+            let status = VTPixelRotationSessionCreate(kCFAllocatorDefault, &mut session);
+            if status != 0 {
+                return Err(status);
+            }
+            // From here on, Drop releases the session
+            let rotator = Self {
+                session,
+                orientation,
+                pool: OutputPool::default(),
+            };
+            rotator.set_property(
+                kVTPixelRotationPropertyKey_Rotation,
+                orientation.rotation.key() as CFTypeRef,
+            )?;
+            for (key, enabled) in [
+                (
+                    kVTPixelRotationPropertyKey_FlipHorizontalOrientation,
+                    orientation.flip_horizontal,
+                ),
+                (
+                    kVTPixelRotationPropertyKey_FlipVerticalOrientation,
+                    orientation.flip_vertical,
+                ),
+            ] {
+                let value = CFBoolean::from(enabled);
+                rotator.set_property(key, value.as_concrete_TypeRef() as CFTypeRef)?;
+            }
+            Ok(rotator)
+        }
+    }
+
+    pub fn orientation(&self) -> OutputOrientation {
+        self.orientation
+    }
+
+    /// Rotate `source` into a pooled pixel buffer of the same pixel format.
+    ///
+    /// # Safety
+    ///
+    /// `source` must be a valid pixel buffer. The returned buffer is +1
+    /// retained and must be released with
+    /// [`release_pixel_buffer`](super::release_pixel_buffer).
+    pub unsafe fn rotate(
+        &mut self,
+        source: CVPixelBufferRef,
+    ) -> Result<CVPixelBufferRef, OSStatus> {
+        let (width, height) = self.orientation.output_size(
+            CVPixelBufferGetWidth(source),
+            CVPixelBufferGetHeight(source),
+        );
+        let format = CVPixelBufferGetPixelFormatType(source);
+        let destination = self.pool.acquire(width, height, format)?;
+        let status = VTPixelRotationSessionRotateImage(self.session, source, destination.as_raw());
+        if status != 0 {
+            return Err(status);
+        }
+        Ok(destination.into_raw())
+    }
+
+    unsafe fn set_property(&self, key: CFStringRef, value: CFTypeRef) -> Result<(), OSStatus> {
+        crate::errors::status_to_result(VTSessionSetProperty(self.session, key, value))
+    }
+}
+
+impl Drop for ImageRotator {
+    fn drop(&mut self) {
+        unsafe {
+            VTPixelRotationSessionInvalidate(self.session);
+            CFRelease(self.session);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_from_degrees_and_output_size() {
+        assert_eq!(Rotation::from_degrees(0), Some(Rotation::None));
+        assert_eq!(Rotation::from_degrees(90), Some(Rotation::Clockwise90));
+        assert_eq!(Rotation::from_degrees(-90), Some(Rotation::Clockwise270));
+        assert_eq!(Rotation::from_degrees(540), Some(Rotation::Rotate180));
+        assert_eq!(Rotation::from_degrees(45), None);
+
+        let portrait = OutputOrientation {
+            rotation: Rotation::Clockwise90,
+            ..Default::default()
+        };
+        assert_eq!(portrait.output_size(1920, 1080), (1080, 1920));
+        assert!(!portrait.is_identity());
+
+        let mirrored = OutputOrientation {
+            flip_horizontal: true,
+            ..Default::default()
+        };
+        assert_eq!(mirrored.output_size(1920, 1080), (1920, 1080));
+        assert!(OutputOrientation::default().is_identity());
+    }
+}
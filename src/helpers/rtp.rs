@@ -0,0 +1,728 @@
+//! RTP packet parsing and H.264 payload handling (RFC 3550, RFC 6184).
+//!
+//! [`H264Packetizer`] turns encoded frames into RTP packets for WebRTC or
+//! RTSP stacks, and [`H264Depacketizer`] reassembles received ones.
+
+use super::nal_extractor::{H264ParameterSets, NalUnit, SampleTiming};
+use super::source::MediaFrame;
+use crate::cm_sample_buffer::nal_unit_type;
+
+/// RTP clock rate for H.264 video.
+pub const H264_CLOCK_RATE: i32 = 90_000;
+
+const RTP_VERSION: u8 = 2;
+const RTP_HEADER_LEN: usize = 12;
+const NAL_STAP_A: u8 = 24;
+const NAL_FU_A: u8 = 28;
+
+/// Errors parsing RTP packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtpError {
+    /// Packet shorter than its headers claim
+    Truncated,
+    /// RTP version field is not 2
+    UnsupportedVersion(u8),
+    /// Padding length exceeds the payload
+    InvalidPadding,
+}
+
+impl std::fmt::Display for RtpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RtpError::Truncated => write!(f, "Truncated RTP packet"),
+            RtpError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported RTP version: {}", version)
+            }
+            RtpError::InvalidPadding => write!(f, "Invalid RTP padding length"),
+        }
+    }
+}
+
+impl std::error::Error for RtpError {}
+
+/// A parsed RTP packet borrowing its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpPacket<'a> {
+    /// Marker bit; for H.264, set on the last packet of an access unit
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence_number: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    /// Payload with CSRCs, header extension and padding removed
+    pub payload: &'a [u8],
+}
+
+impl<'a> RtpPacket<'a> {
+    /// Parse a packet from its wire format.
+    pub fn parse(data: &'a [u8]) -> Result<Self, RtpError> {
+        if data.len() < RTP_HEADER_LEN {
+            return Err(RtpError::Truncated);
+        }
+        let version = data[0] >> 6;
+        if version != RTP_VERSION {
+            return Err(RtpError::UnsupportedVersion(version));
+        }
+        let has_padding = data[0] & 0x20 != 0;
+        let has_extension = data[0] & 0x10 != 0;
+        let csrc_count = (data[0] & 0x0F) as usize;
+
+        let mut start = RTP_HEADER_LEN + csrc_count * 4;
+        if has_extension {
+            let header = data.get(start..start + 4).ok_or(RtpError::Truncated)?;
+            let words = u16::from_be_bytes([header[2], header[3]]) as usize;
+            start += 4 + words * 4;
+        }
+        let mut end = data.len();
+        if start > end {
+            return Err(RtpError::Truncated);
+        }
+        if has_padding {
+            let padding = data[end - 1] as usize;
+            if padding == 0 || padding > end - start {
+                return Err(RtpError::InvalidPadding);
+            }
+            end -= padding;
+        }
+
+        Ok(Self {
+            marker: data[1] & 0x80 != 0,
+            payload_type: data[1] & 0x7F,
+            sequence_number: u16::from_be_bytes([data[2], data[3]]),
+            timestamp: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            ssrc: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+            payload: &data[start..end],
+        })
+    }
+
+    /// Serialize with a 12-byte header, without CSRCs or extensions.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RTP_HEADER_LEN + self.payload.len());
+        data.push(RTP_VERSION << 6);
+        data.push(((self.marker as u8) << 7) | (self.payload_type & 0x7F));
+        data.extend_from_slice(&self.sequence_number.to_be_bytes());
+        data.extend_from_slice(&self.timestamp.to_be_bytes());
+        data.extend_from_slice(&self.ssrc.to_be_bytes());
+        data.extend_from_slice(self.payload);
+        data
+    }
+}
+
+/// Settings for [`H264Packetizer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpPacketizerConfig {
+    /// Maximum packet size including the RTP header (default: 1200, which
+    /// fits typical WebRTC paths)
+    pub mtu: usize,
+    pub ssrc: u32,
+    /// Dynamic payload type negotiated in the SDP (default: 96)
+    pub payload_type: u8,
+    /// Sequence number of the first packet
+    pub initial_sequence_number: u16,
+    /// RTP timestamp of presentation time 0; RFC 3550 recommends a random one
+    pub initial_timestamp: u32,
+}
+
+impl Default for RtpPacketizerConfig {
+    fn default() -> Self {
+        Self {
+            mtu: 1200,
+            ssrc: 0,
+            payload_type: 96,
+            initial_sequence_number: 0,
+            initial_timestamp: 0,
+        }
+    }
+}
+
+/// Splits H.264 frames into RTP packets (RFC 6184, packetization mode 1).
+///
+/// NAL units that fit the MTU are sent as single NAL unit packets, or
+/// aggregated with their neighbors into STAP-A packets; larger ones are
+/// fragmented into FU-A packets. The marker bit is set on the last packet of
+/// each frame, and timestamps are on the 90 kHz [`H264_CLOCK_RATE`].
+///
+/// With [`set_parameter_sets`](Self::set_parameter_sets), SPS and PPS are
+/// sent ahead of every keyframe that does not carry them in band, so
+/// receivers joining mid-stream can start decoding at the next keyframe.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{H264Packetizer, RtpPacketizerConfig};
+///
+/// let mut packetizer = H264Packetizer::new(RtpPacketizerConfig {
+///     ssrc: 0x1234_5678,
+///     ..Default::default()
+/// });
+/// # let frame: video_toolbox_sys::helpers::MediaFrame = unimplemented!();
+/// for packet in packetizer.packetize_frame(&frame) {
+///     // socket.send(&packet)?;
+/// }
+/// ```
+#[derive(Debug)]
+pub struct H264Packetizer {
+    config: RtpPacketizerConfig,
+    sequence_number: u16,
+    parameter_sets: Option<H264ParameterSets>,
+}
+
+impl H264Packetizer {
+    pub fn new(config: RtpPacketizerConfig) -> Self {
+        Self {
+            sequence_number: config.initial_sequence_number,
+            config,
+            parameter_sets: None,
+        }
+    }
+
+    pub fn config(&self) -> &RtpPacketizerConfig {
+        &self.config
+    }
+
+    /// Sequence number of the next packet.
+    pub fn sequence_number(&self) -> u16 {
+        self.sequence_number
+    }
+
+    /// SPS and PPS to send ahead of keyframes, e.g. from
+    /// [`NalExtractor::extract_parameter_sets`](super::NalExtractor::extract_parameter_sets).
+    pub fn set_parameter_sets(&mut self, parameter_sets: H264ParameterSets) {
+        self.parameter_sets = Some(parameter_sets);
+    }
+
+    /// RTP timestamp of a frame's presentation time.
+    pub fn rtp_timestamp(&self, timing: &SampleTiming) -> u32 {
+        let pts = if timing.timescale == H264_CLOCK_RATE || timing.timescale == 0 {
+            timing.pts
+        } else {
+            // i128 so nanosecond host-clock timestamps do not overflow
+            (timing.pts as i128 * H264_CLOCK_RATE as i128 / timing.timescale as i128) as i64
+        };
+        self.config.initial_timestamp.wrapping_add(pts as u32)
+    }
+
+    /// Packetize a frame at its presentation time.
+    pub fn packetize_frame(&mut self, frame: &MediaFrame) -> Vec<Vec<u8>> {
+        let timestamp = self.rtp_timestamp(&frame.timing);
+        let has_sps = frame.nal_units.iter().any(NalUnit::is_sps);
+        let parameter_sets = (frame.is_keyframe && !has_sps)
+            .then(|| self.parameter_sets.clone())
+            .flatten();
+        let mut nal_units: Vec<&[u8]> = Vec::new();
+        if let Some(sets) = &parameter_sets {
+            nal_units.extend([sets.sps.as_slice(), sets.pps.as_slice()]);
+        }
+        nal_units.extend(frame.nal_units.iter().map(|nal| nal.data.as_slice()));
+        self.packetize_nal_units(&nal_units, timestamp)
+    }
+
+    /// Packetize the NAL units of one access unit (without start codes or
+    /// length prefixes) at an RTP timestamp. Access unit delimiters are
+    /// left out, as RFC 6184 recommends.
+    pub fn packetize(&mut self, nal_units: &[NalUnit], timestamp: u32) -> Vec<Vec<u8>> {
+        let nal_units: Vec<&[u8]> = nal_units.iter().map(|nal| nal.data.as_slice()).collect();
+        self.packetize_nal_units(&nal_units, timestamp)
+    }
+
+    fn packetize_nal_units(&mut self, nal_units: &[&[u8]], timestamp: u32) -> Vec<Vec<u8>> {
+        let max_payload = self.config.mtu.saturating_sub(RTP_HEADER_LEN).max(3);
+        let mut payloads: Vec<Vec<u8>> = Vec::new();
+        // NAL units waiting to be sent alone or aggregated into a STAP-A
+        let mut pending: Vec<&[u8]> = Vec::new();
+        let mut pending_size = 1;
+
+        let nal_units = nal_units
+            .iter()
+            .filter(|nal| nal.first().is_some_and(|b| b & 0x1F != nal_unit_type::AUD));
+        for &nal in nal_units {
+            if nal.len() > max_payload {
+                flush_aggregate(&mut payloads, &mut pending);
+                pending_size = 1;
+                fragment(&mut payloads, nal, max_payload);
+                continue;
+            }
+            if pending_size + 2 + nal.len() > max_payload {
+                flush_aggregate(&mut payloads, &mut pending);
+                pending_size = 1;
+            }
+            pending.push(nal);
+            pending_size += 2 + nal.len();
+        }
+        flush_aggregate(&mut payloads, &mut pending);
+
+        let count = payloads.len();
+        payloads
+            .iter()
+            .enumerate()
+            .map(|(index, payload)| {
+                let packet = RtpPacket {
+                    marker: index + 1 == count,
+                    payload_type: self.config.payload_type,
+                    sequence_number: self.sequence_number,
+                    timestamp,
+                    ssrc: self.config.ssrc,
+                    payload,
+                };
+                self.sequence_number = self.sequence_number.wrapping_add(1);
+                packet.to_bytes()
+            })
+            .collect()
+    }
+}
+
+/// Emit the pending NAL units as a single NAL unit packet, or a STAP-A when
+/// there are several.
+fn flush_aggregate(payloads: &mut Vec<Vec<u8>>, pending: &mut Vec<&[u8]>) {
+    match pending.len() {
+        0 => {}
+        1 => payloads.push(pending[0].to_vec()),
+        _ => {
+            // F is set if any unit has it, NRI is the highest of the units
+            let f = pending.iter().fold(0, |f, nal| f | (nal[0] & 0x80));
+            let nri = pending.iter().map(|nal| nal[0] & 0x60).max().unwrap_or(0);
+            let mut payload = vec![f | nri | NAL_STAP_A];
+            for nal in pending.iter() {
+                payload.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+                payload.extend_from_slice(nal);
+            }
+            payloads.push(payload);
+        }
+    }
+    pending.clear();
+}
+
+/// Split a NAL unit into FU-A packets of at most `max_payload` bytes.
+fn fragment(payloads: &mut Vec<Vec<u8>>, nal: &[u8], max_payload: usize) {
+    let indicator = (nal[0] & 0xE0) | NAL_FU_A;
+    let nal_type = nal[0] & 0x1F;
+    let chunks: Vec<&[u8]> = nal[1..].chunks(max_payload - 2).collect();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let mut header = nal_type;
+        if index == 0 {
+            header |= 0x80;
+        }
+        if index + 1 == chunks.len() {
+            header |= 0x40;
+        }
+        let mut payload = vec![indicator, header];
+        payload.extend_from_slice(chunk);
+        payloads.push(payload);
+    }
+}
+
+fn nal(data: Vec<u8>) -> NalUnit {
+    let nal_type = data.first().map_or(0, |b| b & 0x1F);
+    NalUnit { data, nal_type }
+}
+
+/// Reassembles H.264 access units from RTP packets (RFC 6184).
+///
+/// Handles single NAL unit packets, STAP-A aggregation and FU-A
+/// fragmentation in non-interleaved mode. An access unit ends at the packet
+/// with the marker bit set, or when a packet with a new timestamp arrives.
+///
+/// SPS and PPS NAL units are removed from the frames and kept as the
+/// current [`parameter_sets`](Self::parameter_sets), which can be seeded
+/// from the SDP `sprop-parameter-sets`.
+///
+/// After packet loss the damaged access unit is dropped and frames are
+/// skipped until the next IDR, so a decoder never sees frames with missing
+/// references. Streams encoded with several slices per frame (see
+/// [`max_slice_bytes`](super::CompressionSessionBuilder::max_slice_bytes))
+/// can instead [keep partial frames](Self::keep_partial_frames): the slices
+/// that arrived intact are delivered and the decoder conceals the rest,
+/// so a lost packet costs part of one frame rather than everything up to
+/// the next IDR.
+///
+/// RTP carries presentation timestamps only, so frames have `dts == pts`
+/// and a duration equal to the previous frame interval.
+#[derive(Debug, Default)]
+pub struct H264Depacketizer {
+    nal_units: Vec<NalUnit>,
+    fragment: Option<Vec<u8>>,
+    timestamp: Option<u32>,
+    damaged: bool,
+    waiting_for_keyframe: bool,
+    next_sequence: Option<u16>,
+    // Last RTP timestamp and its unwrapped value
+    extended_base: Option<(u32, i64)>,
+    last_pts: Option<i64>,
+    last_duration: i64,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    lost_packets: u64,
+    dropped_frames: u64,
+    keep_partial: bool,
+    partial_frames: u64,
+}
+
+impl H264Depacketizer {
+    pub fn new() -> Self {
+        Self {
+            waiting_for_keyframe: true,
+            ..Default::default()
+        }
+    }
+
+    /// Deliver access units with missing slices instead of dropping them and
+    /// waiting for the next IDR.
+    pub fn keep_partial_frames(mut self, enabled: bool) -> Self {
+        self.keep_partial = enabled;
+        self
+    }
+
+    /// Current SPS/PPS, once both have been seen in band or set.
+    pub fn parameter_sets(&self) -> Option<H264ParameterSets> {
+        Some(H264ParameterSets {
+            sps: self.sps.clone()?,
+            pps: self.pps.clone()?,
+            nal_length_size: 4,
+        })
+    }
+
+    /// Seed the parameter sets, e.g. from the SDP `sprop-parameter-sets`.
+    pub fn set_parameter_sets(&mut self, sps: Vec<u8>, pps: Vec<u8>) {
+        self.sps = Some(sps);
+        self.pps = Some(pps);
+    }
+
+    /// Packets missing from the sequence number series.
+    pub fn lost_packets(&self) -> u64 {
+        self.lost_packets
+    }
+
+    /// Access units dropped because of loss or while waiting for an IDR.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    /// Access units delivered with slices missing, with
+    /// [`keep_partial_frames`](Self::keep_partial_frames).
+    pub fn partial_frames(&self) -> u64 {
+        self.partial_frames
+    }
+
+    /// Add a packet, returning the access units it completes.
+    pub fn push(&mut self, packet: &RtpPacket<'_>) -> Vec<MediaFrame> {
+        let mut frames = Vec::new();
+
+        if let Some(expected) = self.next_sequence {
+            let gap = packet.sequence_number.wrapping_sub(expected);
+            // Large gaps are late or duplicate packets; skip them
+            if gap >= 0x8000 {
+                return frames;
+            }
+            if gap > 0 {
+                self.lost_packets += gap as u64;
+                self.damaged = true;
+                self.fragment = None;
+            }
+        }
+        self.next_sequence = Some(packet.sequence_number.wrapping_add(1));
+
+        if self.timestamp.is_some_and(|ts| ts != packet.timestamp) {
+            frames.extend(self.finish());
+        }
+        self.timestamp = Some(packet.timestamp);
+        self.depacketize(packet.payload);
+        if packet.marker {
+            frames.extend(self.finish());
+        }
+        frames
+    }
+
+    /// Complete the pending access unit, e.g. at end of stream.
+    pub fn finish(&mut self) -> Option<MediaFrame> {
+        let timestamp = self.timestamp.take()?;
+        let nal_units = std::mem::take(&mut self.nal_units);
+        let damaged = std::mem::take(&mut self.damaged) || self.fragment.take().is_some();
+        if nal_units.is_empty() {
+            if damaged {
+                self.waiting_for_keyframe |= !self.keep_partial;
+                self.dropped_frames += 1;
+            }
+            return None;
+        }
+
+        let is_keyframe = nal_units.iter().any(NalUnit::is_idr);
+        let unusable = if self.keep_partial { false } else { damaged };
+        if unusable || (self.waiting_for_keyframe && !is_keyframe) {
+            self.waiting_for_keyframe = !is_keyframe || damaged;
+            self.dropped_frames += 1;
+            return None;
+        }
+        if damaged {
+            self.partial_frames += 1;
+        }
+        self.waiting_for_keyframe = false;
+
+        let pts = self.extend_timestamp(timestamp);
+        if let Some(last) = self.last_pts {
+            if pts > last {
+                self.last_duration = pts - last;
+            }
+        }
+        self.last_pts = Some(pts);
+        Some(MediaFrame {
+            nal_units,
+            timing: SampleTiming {
+                pts,
+                dts: pts,
+                duration: self.last_duration,
+                timescale: H264_CLOCK_RATE,
+            },
+            is_keyframe,
+            motion_score: None,
+        })
+    }
+
+    /// Unwrap a 32-bit RTP timestamp, starting from 0 at the first frame.
+    fn extend_timestamp(&mut self, timestamp: u32) -> i64 {
+        let extended = match self.extended_base {
+            Some((last, extended)) => extended + timestamp.wrapping_sub(last) as i32 as i64,
+            None => 0,
+        };
+        self.extended_base = Some((timestamp, extended));
+        extended
+    }
+
+    fn depacketize(&mut self, payload: &[u8]) {
+        let Some(&header) = payload.first() else {
+            return;
+        };
+        match header & 0x1F {
+            NAL_STAP_A => {
+                let mut rest = &payload[1..];
+                while rest.len() >= 2 {
+                    let size = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+                    let Some(data) = rest.get(2..2 + size) else {
+                        self.damaged = true;
+                        break;
+                    };
+                    self.add_nal(data.to_vec());
+                    rest = &rest[2 + size..];
+                }
+            }
+            NAL_FU_A => {
+                if payload.len() < 2 {
+                    self.damaged = true;
+                    return;
+                }
+                let fu_header = payload[1];
+                let (start, end) = (fu_header & 0x80 != 0, fu_header & 0x40 != 0);
+                if start {
+                    let mut data = vec![(header & 0xE0) | (fu_header & 0x1F)];
+                    data.extend_from_slice(&payload[2..]);
+                    self.fragment = Some(data);
+                } else if let Some(fragment) = self.fragment.as_mut() {
+                    fragment.extend_from_slice(&payload[2..]);
+                } else {
+                    // Continuation without a start: the start was lost
+                    self.damaged = true;
+                    return;
+                }
+                if end {
+                    let data = self.fragment.take().unwrap_or_default();
+                    self.add_nal(data);
+                }
+            }
+            1..=23 => self.add_nal(payload.to_vec()),
+            // STAP-B, MTAP and FU-B are only used in interleaved mode
+            _ => {}
+        }
+    }
+
+    fn add_nal(&mut self, data: Vec<u8>) {
+        let unit = nal(data);
+        match unit.nal_type {
+            nal_unit_type::SPS => self.sps = Some(unit.data),
+            nal_unit_type::PPS => self.pps = Some(unit.data),
+            nal_unit_type::AUD => {}
+            _ => self.nal_units.push(unit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(seq: u16, timestamp: u32, marker: bool, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x80, if marker { 0xE0 } else { 0x60 }];
+        data.extend_from_slice(&seq.to_be_bytes());
+        data.extend_from_slice(&timestamp.to_be_bytes());
+        data.extend_from_slice(&0x1234u32.to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn push(depacketizer: &mut H264Depacketizer, data: &[u8]) -> Vec<MediaFrame> {
+        depacketizer.push(&RtpPacket::parse(data).unwrap())
+    }
+
+    #[test]
+    fn test_parse_header_extension_and_padding() {
+        let mut data = vec![0xB1, 0x60, 0, 1, 0, 0, 0, 9, 0, 0, 0, 7];
+        data.extend_from_slice(&[1, 2, 3, 4]); // CSRC
+        data.extend_from_slice(&[0xBE, 0xDE, 0, 1, 9, 9, 9, 9]); // extension
+        data.extend_from_slice(&[0x41, 0xAA, 0, 2]); // payload + 2 bytes padding
+        let packet = RtpPacket::parse(&data).unwrap();
+        assert_eq!(packet.payload, &[0x41, 0xAA]);
+        assert_eq!(
+            (packet.payload_type, packet.timestamp, packet.ssrc),
+            (96, 9, 7)
+        );
+        assert!(!packet.marker);
+        assert_eq!(
+            RtpPacket::parse(&[0x40; 12]),
+            Err(RtpError::UnsupportedVersion(1))
+        );
+    }
+
+    #[test]
+    fn test_stap_a_and_fu_a_reassembly() {
+        let mut depacketizer = H264Depacketizer::new();
+        // STAP-A with SPS and PPS, then an IDR split into three FU-A fragments
+        let stap = [24, 0, 2, 0x67, 0x42, 0, 2, 0x68, 0xCE];
+        assert!(push(&mut depacketizer, &packet(10, 3000, false, &stap)).is_empty());
+        push(
+            &mut depacketizer,
+            &packet(11, 3000, false, &[0x7C, 0x85, 1, 2]),
+        );
+        push(
+            &mut depacketizer,
+            &packet(12, 3000, false, &[0x7C, 0x05, 3]),
+        );
+        let frames = push(&mut depacketizer, &packet(13, 3000, true, &[0x7C, 0x45, 4]));
+
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].is_keyframe);
+        assert_eq!(frames[0].nal_units[0].data, [0x65, 1, 2, 3, 4]);
+        let sets = depacketizer.parameter_sets().unwrap();
+        assert_eq!((sets.sps, sets.pps), (vec![0x67, 0x42], vec![0x68, 0xCE]));
+
+        // A P frame arriving without a marker is completed by the next timestamp
+        push(&mut depacketizer, &packet(14, 6000, false, &[0x41, 9]));
+        let frames = push(&mut depacketizer, &packet(15, 9000, true, &[0x41, 8]));
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            (frames[0].timing.pts, frames[0].timing.duration),
+            (3000, 3000)
+        );
+        assert_eq!(frames[1].timing.pts, 6000);
+    }
+
+    #[test]
+    fn test_packetizer_round_trip() {
+        let mut packetizer = H264Packetizer::new(RtpPacketizerConfig {
+            mtu: 100,
+            ssrc: 7,
+            initial_sequence_number: 65534,
+            ..Default::default()
+        });
+        packetizer.set_parameter_sets(H264ParameterSets {
+            sps: vec![0x67, 0x42, 0x00, 0x1f],
+            pps: vec![0x68, 0xce],
+            nal_length_size: 4,
+        });
+        let mut idr = vec![0x65];
+        idr.extend((0..250).map(|i| i as u8));
+        let frame = MediaFrame {
+            nal_units: vec![nal(vec![0x09, 0xf0]), nal(idr.clone())],
+            timing: SampleTiming {
+                pts: 1,
+                dts: 1,
+                duration: 1,
+                timescale: 30,
+            },
+            is_keyframe: true,
+            motion_score: None,
+        };
+
+        // STAP-A with SPS and PPS, then the IDR in three FU-A fragments
+        let packets = packetizer.packetize_frame(&frame);
+        assert_eq!(packets.len(), 4);
+        assert!(packets.iter().all(|p| p.len() <= 100));
+        let parsed: Vec<RtpPacket> = packets
+            .iter()
+            .map(|p| RtpPacket::parse(p).unwrap())
+            .collect();
+        assert_eq!(parsed[0].payload[0] & 0x1F, NAL_STAP_A);
+        assert_eq!(parsed[1].payload[0] & 0x1F, NAL_FU_A);
+        assert_eq!(parsed[3].sequence_number, 1);
+        assert_eq!((parsed[3].timestamp, parsed[3].ssrc), (3000, 7));
+        assert!(parsed[3].marker && !parsed[2].marker);
+        // Nanosecond host-clock timestamps are rescaled without overflow
+        let host = SampleTiming {
+            pts: 100_000_000_000_000_000,
+            timescale: 1_000_000_000,
+            ..frame.timing
+        };
+        let expected = 9_000_000_000_000u64 as u32;
+        assert_eq!(packetizer.rtp_timestamp(&host), expected);
+
+        let mut depacketizer = H264Depacketizer::new();
+        let frames: Vec<MediaFrame> = parsed.iter().flat_map(|p| depacketizer.push(p)).collect();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].nal_units.len(), 1);
+        assert_eq!(frames[0].nal_units[0].data, idr);
+        let sets = depacketizer.parameter_sets().unwrap();
+        assert_eq!(
+            (sets.sps, sets.pps),
+            (vec![0x67, 0x42, 0x00, 0x1f], vec![0x68, 0xce])
+        );
+    }
+
+    #[test]
+    fn test_loss_waits_for_next_idr() {
+        let mut depacketizer = H264Depacketizer::new();
+        push(&mut depacketizer, &packet(1, 0, true, &[0x65, 1]));
+        // Packet 3 is lost mid-frame
+        push(&mut depacketizer, &packet(2, 3000, false, &[0x7C, 0x81, 1]));
+        assert!(push(&mut depacketizer, &packet(4, 3000, true, &[0x7C, 0x41, 3])).is_empty());
+        assert!(push(&mut depacketizer, &packet(5, 6000, true, &[0x41, 4])).is_empty());
+        let frames = push(&mut depacketizer, &packet(6, 9000, true, &[0x65, 5]));
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].is_keyframe);
+        assert_eq!(depacketizer.lost_packets(), 1);
+        assert_eq!(depacketizer.dropped_frames(), 2);
+    }
+
+    #[test]
+    fn test_partial_multi_slice_frames() {
+        // Each frame has three slices; the FU-A start of frame 2's middle
+        // slice is lost
+        let frames = |depacketizer: H264Depacketizer| {
+            let mut depacketizer = depacketizer;
+            let mut out = Vec::new();
+            let slices: [(u16, u32, bool, &[u8]); 9] = [
+                (1, 0, false, &[0x65, 0x88, 1]),
+                (2, 0, false, &[0x65, 0x40, 2]),
+                (3, 0, true, &[0x65, 0x20, 3]),
+                (4, 3000, false, &[0x41, 0x88, 4]),
+                (6, 3000, false, &[0x7C, 0x41, 5]),
+                (7, 3000, true, &[0x41, 0x20, 6]),
+                (8, 6000, false, &[0x41, 0x88, 7]),
+                (9, 6000, false, &[0x41, 0x40, 8]),
+                (10, 6000, true, &[0x41, 0x20, 9]),
+            ];
+            for (seq, ts, marker, payload) in slices {
+                out.extend(push(&mut depacketizer, &packet(seq, ts, marker, payload)));
+            }
+            (out, depacketizer)
+        };
+
+        let (strict, depacketizer) = frames(H264Depacketizer::new());
+        assert_eq!(strict.len(), 1);
+        assert_eq!(strict[0].nal_units.len(), 3);
+        assert_eq!(depacketizer.dropped_frames(), 2);
+
+        let (partial, depacketizer) = frames(H264Depacketizer::new().keep_partial_frames(true));
+        let slices: Vec<usize> = partial.iter().map(|f| f.nal_units.len()).collect();
+        assert_eq!(slices, [3, 2, 3]);
+        assert_eq!(partial[1].timing.pts, 3000);
+        assert_eq!(depacketizer.partial_frames(), 1);
+        assert_eq!(depacketizer.dropped_frames(), 0);
+    }
+}
@@ -0,0 +1,155 @@
+//! CMSampleBuffer construction from encoded data.
+
+use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, OSStatus};
+use core_media_sys::{
+    kCMBlockBufferAssureMemoryNowFlag, CMFormatDescriptionRef, CMSampleBufferRef,
+    CMSampleTimingInfo,
+};
+use libc::c_void;
+use std::ptr;
+
+use super::clock::make_time;
+use super::nal_extractor::SampleTiming;
+use crate::cm_sample_buffer::{
+    CMBlockBufferCreateWithMemoryBlock, CMBlockBufferRef, CMBlockBufferReplaceDataBytes,
+    CMSampleBufferCreateReady,
+};
+use crate::errors::kVTParameterErr;
+
+/// An owned CMSampleBuffer, released on drop.
+#[derive(Debug)]
+pub struct SampleBufferGuard {
+    raw: CMSampleBufferRef,
+}
+
+// SAFETY: CMSampleBuffers created by this module are ready and immutable
+// through the guard, and their retain count is atomic.
+unsafe impl Send for SampleBufferGuard {}
+
+impl SampleBufferGuard {
+    /// Take ownership of a +1 retained sample buffer.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be a valid sample buffer whose reference is transferred to
+    /// the guard.
+    pub unsafe fn from_retained(raw: CMSampleBufferRef) -> Self {
+        Self { raw }
+    }
+
+    pub fn as_raw(&self) -> CMSampleBufferRef {
+        self.raw
+    }
+
+    /// Give up the guard without releasing the sample buffer.
+    pub fn into_raw(self) -> CMSampleBufferRef {
+        let raw = self.raw;
+        std::mem::forget(self);
+        raw
+    }
+}
+
+impl Drop for SampleBufferGuard {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.raw as *const c_void) };
+    }
+}
+
+/// Copy one encoded sample, e.g. a length-prefixed (AVCC) frame, into a new
+/// CMSampleBuffer with the given timing.
+///
+/// The data is copied into a block buffer owned by the sample buffer, so it
+/// may be freed or reused as soon as this returns.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{create_encoded_sample_buffer, SampleTiming};
+///
+/// # let format_desc = std::ptr::null_mut();
+/// # let avcc_frame: Vec<u8> = Vec::new();
+/// let timing = SampleTiming { pts: 0, dts: 0, duration: 3000, timescale: 90000 };
+/// let sample_buffer =
+///     unsafe { create_encoded_sample_buffer(&avcc_frame, format_desc, timing) }.unwrap();
+/// // decode `sample_buffer.as_raw()`; it is released when dropped
+/// ```
+///
+/// # Safety
+///
+/// `format_desc` must be a valid format description matching `data`.
+pub unsafe fn create_encoded_sample_buffer(
+    data: &[u8],
+    format_desc: CMFormatDescriptionRef,
+    timing: SampleTiming,
+) -> Result<SampleBufferGuard, OSStatus> {
+    if data.is_empty() {
+        return Err(kVTParameterErr);
+    }
+
+    let mut block_buffer: CMBlockBufferRef = ptr::null_mut();
+    let status = CMBlockBufferCreateWithMemoryBlock(
+        kCFAllocatorDefault,
+        ptr::null_mut(),
+        data.len(),
+        kCFAllocatorDefault,
+        ptr::null(),
+        0,
+        data.len(),
+        kCMBlockBufferAssureMemoryNowFlag,
+        &mut block_buffer,
+    );
+    if status != 0 {
+        return Err(status);
+    }
+
+    let status =
+        CMBlockBufferReplaceDataBytes(data.as_ptr() as *const c_void, block_buffer, 0, data.len());
+    let mut sample_buffer: CMSampleBufferRef = ptr::null_mut();
+    let status = if status != 0 {
+        status
+    } else {
+        CMSampleBufferCreateReady(
+            kCFAllocatorDefault,
+            block_buffer,
+            format_desc,
+            1,
+            1,
+            &timing_info(&timing),
+            1,
+            &data.len(),
+            &mut sample_buffer,
+        )
+    };
+    // The sample buffer holds its own reference
+    CFRelease(block_buffer as *const c_void);
+    crate::errors::status_to_result(status)?;
+    Ok(SampleBufferGuard::from_retained(sample_buffer))
+}
+
+fn timing_info(timing: &SampleTiming) -> CMSampleTimingInfo {
+    CMSampleTimingInfo {
+        duration: make_time(timing.duration, timing.timescale),
+        presentation_time_stamp: make_time(timing.pts, timing.timescale),
+        decode_time_stamp: make_time(timing.dts, timing.timescale),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_timing_info() {
+        let timing = SampleTiming {
+            pts: 6000,
+            dts: 3000,
+            duration: 1500,
+            timescale: 90000,
+        };
+        let info = timing_info(&timing);
+        assert_eq!(info.presentation_time_stamp.value, 6000);
+        assert_eq!(info.decode_time_stamp.value, 3000);
+        assert_eq!(info.duration.value, 1500);
+        assert_eq!(info.duration.timescale, 90000);
+    }
+}
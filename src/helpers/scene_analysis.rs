@@ -0,0 +1,395 @@
+//! Scene-adaptive bitrate analysis for offline (VOD) encoding.
+//!
+//! A fast first pass encodes a downscaled copy of the content at constant
+//! quality. The encoded size of each frame is a good proxy for how hard it is
+//! to compress: static talking heads are small, confetti and water are large.
+//! [`SceneAnalysis`] groups first-pass frames into scenes and turns their
+//! complexity into per-segment bitrates for the full-resolution second pass.
+
+use core_foundation_sys::base::OSStatus;
+use core_media_sys::CMTime;
+use std::sync::{Arc, Mutex};
+
+use super::compression_builder::CompressionSessionBuilder;
+use super::compression_session::{CompressionSession, EncodeOutput};
+use super::nal_extractor::NalExtractor;
+use crate::cm_sample_buffer::{
+    CMSampleBufferGetDuration, CMSampleBufferGetPresentationTimeStamp,
+    CMSampleBufferGetTotalSampleSize,
+};
+use crate::cv_types::CVImageBufferRef;
+
+/// First-pass statistics for one frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FramePassStats {
+    /// Presentation time in seconds
+    pub pts: f64,
+    /// Frame duration in seconds
+    pub duration: f64,
+    /// Encoded size in bytes
+    pub bytes: usize,
+    /// Whether the encoder chose to emit a keyframe
+    pub is_keyframe: bool,
+}
+
+/// A run of frames with similar content.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scene {
+    /// Start time in seconds
+    pub start: f64,
+    /// Duration in seconds
+    pub duration: f64,
+    pub frame_count: usize,
+    /// First-pass bits per second; only meaningful relative to other scenes
+    pub complexity: f64,
+}
+
+/// Bitrate targets for [`SceneAnalysis::suggest_bitrates`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitratePlan {
+    /// Average bitrate over the whole title, in bits per second
+    pub target_bitrate: i64,
+    /// Lowest bitrate any segment may get
+    pub min_bitrate: i64,
+    /// Highest bitrate any segment may get
+    pub max_bitrate: i64,
+    /// Segment length in seconds (match the second pass's segmenter)
+    pub segment_duration: f64,
+    /// How strongly bitrate follows complexity: 0 is constant bitrate, 1 is
+    /// proportional. Values around 0.5 spend bits where they are visible
+    /// without starving easy scenes.
+    pub exponent: f64,
+}
+
+impl BitratePlan {
+    /// A plan averaging `target_bitrate`, allowing segments from half to
+    /// double the target.
+    pub fn new(target_bitrate: i64, segment_duration: f64) -> Self {
+        Self {
+            target_bitrate,
+            min_bitrate: target_bitrate / 2,
+            max_bitrate: target_bitrate * 2,
+            segment_duration,
+            exponent: 0.5,
+        }
+    }
+}
+
+/// Suggested bitrate for one segment of the second pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentBitrate {
+    /// Start time in seconds
+    pub start: f64,
+    /// Duration in seconds
+    pub duration: f64,
+    /// Average bitrate in bits per second
+    pub bitrate: i64,
+}
+
+/// Scene detection and bitrate allocation from first-pass statistics.
+///
+/// Scene cuts are placed at keyframes the encoder inserted on its own (the
+/// first pass disables periodic keyframes, so these mark content changes) and
+/// where the frame size jumps by more than [`cut_ratio`](Self::cut_ratio)
+/// from the running average of the current scene.
+#[derive(Debug, Clone)]
+pub struct SceneAnalysis {
+    frames: Vec<FramePassStats>,
+    cut_ratio: f64,
+    min_scene_duration: f64,
+}
+
+impl Default for SceneAnalysis {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneAnalysis {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            cut_ratio: 3.0,
+            min_scene_duration: 1.0,
+        }
+    }
+
+    /// Analyze recorded first-pass statistics.
+    pub fn from_frames(mut frames: Vec<FramePassStats>) -> Self {
+        frames.sort_by(|a, b| a.pts.total_cmp(&b.pts));
+        Self {
+            frames,
+            ..Self::new()
+        }
+    }
+
+    /// Size change (as a ratio either way) that starts a new scene (default: 3.0).
+    pub fn cut_ratio(mut self, ratio: f64) -> Self {
+        self.cut_ratio = ratio.max(1.0);
+        self
+    }
+
+    /// Shortest scene, in seconds, before another cut is allowed (default: 1.0).
+    pub fn min_scene_duration(mut self, seconds: f64) -> Self {
+        self.min_scene_duration = seconds.max(0.0);
+        self
+    }
+
+    /// Add a frame's statistics (in presentation order).
+    pub fn push(&mut self, frame: FramePassStats) {
+        self.frames.push(frame);
+    }
+
+    pub fn frames(&self) -> &[FramePassStats] {
+        &self.frames
+    }
+
+    /// Split the first pass into scenes.
+    pub fn scenes(&self) -> Vec<Scene> {
+        let mut scenes = Vec::new();
+        let Some(first) = self.frames.first() else {
+            return scenes;
+        };
+
+        let mut start = first.pts;
+        let mut frame_count = 0;
+        let mut bytes = 0usize;
+        let mut average: Option<f64> = None;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let elapsed = frame.pts - start;
+            if i > 0 && elapsed >= self.min_scene_duration {
+                let ratio = average.map_or(1.0, |a| frame.bytes.max(1) as f64 / a.max(1.0));
+                let jump = ratio > self.cut_ratio || ratio < 1.0 / self.cut_ratio;
+                if frame.is_keyframe || jump {
+                    scenes.push(Scene {
+                        start,
+                        duration: elapsed,
+                        frame_count,
+                        complexity: bytes as f64 * 8.0 / elapsed,
+                    });
+                    start = frame.pts;
+                    frame_count = 0;
+                    bytes = 0;
+                    average = None;
+                }
+            }
+            // Keyframes are large regardless of content; keep them out of the
+            // running average used for cut detection
+            if !frame.is_keyframe {
+                let size = frame.bytes as f64;
+                average = Some(average.map_or(size, |a| a + (size - a) * 0.1));
+            }
+            frame_count += 1;
+            bytes += frame.bytes;
+        }
+
+        let last = self.frames.last().unwrap();
+        let duration = (last.pts + last.duration - start).max(f64::EPSILON);
+        scenes.push(Scene {
+            start,
+            duration,
+            frame_count,
+            complexity: bytes as f64 * 8.0 / duration,
+        });
+        scenes
+    }
+
+    /// Suggest a bitrate for each fixed-length segment of the title.
+    ///
+    /// Segment complexity is the duration-weighted complexity of the scenes it
+    /// overlaps. Bitrates are scaled so their duration-weighted average is
+    /// `plan.target_bitrate`, then clamped to the plan's bounds (which can
+    /// move the average when many segments hit a bound).
+    pub fn suggest_bitrates(&self, plan: &BitratePlan) -> Vec<SegmentBitrate> {
+        let scenes = self.scenes();
+        let (Some(first), Some(last)) = (scenes.first(), scenes.last()) else {
+            return Vec::new();
+        };
+        let title_start = first.start;
+        let title_end = last.start + last.duration;
+        let segment_duration = plan.segment_duration.max(f64::EPSILON);
+
+        let mut segments = Vec::new();
+        let mut start = title_start;
+        while start < title_end - 1e-9 {
+            let end = (start + segment_duration).min(title_end);
+            let complexity = scenes
+                .iter()
+                .map(|scene| {
+                    let overlap = (scene.start + scene.duration).min(end) - scene.start.max(start);
+                    overlap.max(0.0) * scene.complexity
+                })
+                .sum::<f64>()
+                / (end - start);
+            segments.push((start, end - start, complexity.max(1.0).powf(plan.exponent)));
+            start = end;
+        }
+
+        let total_duration: f64 = segments.iter().map(|(_, d, _)| d).sum();
+        let weighted: f64 = segments.iter().map(|(_, d, w)| d * w).sum::<f64>() / total_duration;
+        segments
+            .into_iter()
+            .map(|(start, duration, weight)| {
+                let bitrate = plan.target_bitrate as f64 * weight / weighted;
+                SegmentBitrate {
+                    start,
+                    duration,
+                    bitrate: (bitrate.round() as i64).clamp(plan.min_bitrate, plan.max_bitrate),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A fast, downscaled constant-quality encode that records [`FramePassStats`].
+///
+/// The session is created at `width / downscale` by `height / downscale`;
+/// VideoToolbox scales full-resolution source frames on the way in. Periodic
+/// keyframes and B-frames are disabled and quality is set low so the pass
+/// runs well above real time.
+pub struct FirstPass {
+    session: CompressionSession,
+    stats: Arc<Mutex<Vec<FramePassStats>>>,
+}
+
+impl FirstPass {
+    /// Create a first-pass encoder for `width` x `height` source frames.
+    pub fn new(width: i32, height: i32, codec: u32, downscale: i32) -> Result<Self, OSStatus> {
+        let downscale = downscale.max(1);
+        // Encoders want even dimensions
+        let scaled = |v: i32| ((v / downscale) & !1).max(16);
+        Self::with_builder(
+            CompressionSessionBuilder::new(scaled(width), scaled(height), codec)
+                .real_time(false)
+                .quality(0.25)
+                .allow_frame_reordering(false)
+                .keyframe_interval(i32::MAX),
+        )
+    }
+
+    /// Create a first-pass encoder from a custom builder.
+    pub fn with_builder(builder: CompressionSessionBuilder) -> Result<Self, OSStatus> {
+        let stats = Arc::new(Mutex::new(Vec::new()));
+        let sink = stats.clone();
+        let extractor = NalExtractor::new();
+        let session = builder.build_session(move |output| {
+            if let EncodeOutput::Frame { sample_buffer, .. } = output {
+                let frame = unsafe {
+                    FramePassStats {
+                        pts: seconds(CMSampleBufferGetPresentationTimeStamp(sample_buffer)),
+                        duration: seconds(CMSampleBufferGetDuration(sample_buffer)),
+                        bytes: CMSampleBufferGetTotalSampleSize(sample_buffer),
+                        is_keyframe: extractor.is_keyframe(sample_buffer),
+                    }
+                };
+                sink.lock().unwrap_or_else(|e| e.into_inner()).push(frame);
+            }
+        })?;
+        Ok(Self { session, stats })
+    }
+
+    /// Submit a source frame.
+    ///
+    /// # Safety
+    ///
+    /// `image_buffer` must be a valid pixel buffer.
+    pub unsafe fn encode_frame(
+        &self,
+        image_buffer: CVImageBufferRef,
+        pts: CMTime,
+        duration: CMTime,
+    ) -> Result<(), OSStatus> {
+        self.session
+            .encode_frame(image_buffer, pts, duration)
+            .map(|_| ())
+    }
+
+    /// Complete the pass and analyze the recorded statistics.
+    pub fn finish(self) -> Result<SceneAnalysis, OSStatus> {
+        self.session.complete_frames()?;
+        let frames = std::mem::take(&mut *self.stats.lock().unwrap_or_else(|e| e.into_inner()));
+        Ok(SceneAnalysis::from_frames(frames))
+    }
+}
+
+fn seconds(time: CMTime) -> f64 {
+    if time.timescale == 0 {
+        return 0.0;
+    }
+    time.value as f64 / time.timescale as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames at 30 fps with the given per-frame sizes; the first is a keyframe.
+    fn frames(sizes: &[(usize, usize)]) -> Vec<FramePassStats> {
+        let mut out = Vec::new();
+        for &(count, bytes) in sizes {
+            for _ in 0..count {
+                out.push(FramePassStats {
+                    pts: out.len() as f64 / 30.0,
+                    duration: 1.0 / 30.0,
+                    bytes,
+                    is_keyframe: out.is_empty(),
+                });
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_scene_cuts_on_size_jumps() {
+        // 4 s static, 4 s busy, 4 s static
+        let analysis = SceneAnalysis::from_frames(frames(&[(120, 1000), (120, 8000), (120, 1000)]));
+        let scenes = analysis.scenes();
+        assert_eq!(scenes.len(), 3);
+        assert!((scenes[1].start - 4.0).abs() < 1e-6);
+        assert_eq!(scenes[1].frame_count, 120);
+        assert!(scenes[1].complexity > 7.0 * scenes[0].complexity);
+    }
+
+    #[test]
+    fn test_scene_cuts_on_encoder_keyframes() {
+        let mut stats = frames(&[(90, 2000)]);
+        stats[45].is_keyframe = true;
+        stats[50].is_keyframe = true; // too soon after the last cut
+        let scenes = SceneAnalysis::from_frames(stats).scenes();
+        assert_eq!(scenes.len(), 2);
+        assert_eq!(scenes[0].frame_count, 45);
+    }
+
+    #[test]
+    fn test_suggested_bitrates_follow_complexity() {
+        let analysis = SceneAnalysis::from_frames(frames(&[(120, 1000), (120, 8000), (120, 1000)]));
+        let plan = BitratePlan::new(4_000_000, 2.0);
+        let segments = analysis.suggest_bitrates(&plan);
+
+        assert_eq!(segments.len(), 6);
+        assert!(segments[2].bitrate > segments[0].bitrate);
+        assert!(segments
+            .iter()
+            .all(|s| s.bitrate >= plan.min_bitrate && s.bitrate <= plan.max_bitrate));
+        let average = segments
+            .iter()
+            .map(|s| s.bitrate as f64 * s.duration)
+            .sum::<f64>()
+            / 12.0;
+        assert!((average - 4_000_000.0).abs() < 100_000.0);
+    }
+
+    #[test]
+    fn test_constant_bitrate_with_zero_exponent() {
+        let analysis = SceneAnalysis::from_frames(frames(&[(60, 500), (60, 5000)]));
+        let plan = BitratePlan {
+            exponent: 0.0,
+            ..BitratePlan::new(1_000_000, 1.0)
+        };
+        assert!(analysis
+            .suggest_bitrates(&plan)
+            .iter()
+            .all(|s| s.bitrate == 1_000_000));
+        assert!(SceneAnalysis::new().suggest_bitrates(&plan).is_empty());
+    }
+}
@@ -0,0 +1,415 @@
+//! Lightweight scene-change detection for keyframe placement.
+//!
+//! Each frame is reduced to a small luma thumbnail and compared with the
+//! previous one. The score combines the luma histogram distance, which ignores
+//! motion, with the per-pixel difference (SAD), which catches cuts between
+//! shots of similar brightness.
+
+use super::compression_session::CompressionSession;
+use crate::codecs;
+use crate::cv_types::{
+    kCVPixelBufferLock_ReadOnly, kCVReturnInvalidPixelFormat, kCVReturnSuccess,
+    CVPixelBufferGetBaseAddress, CVPixelBufferGetBaseAddressOfPlane, CVPixelBufferGetBytesPerRow,
+    CVPixelBufferGetBytesPerRowOfPlane, CVPixelBufferGetHeight, CVPixelBufferGetPixelFormatType,
+    CVPixelBufferGetWidth, CVPixelBufferLockBaseAddress, CVPixelBufferRef,
+    CVPixelBufferUnlockBaseAddress,
+};
+
+const HISTOGRAM_BINS: usize = 32;
+
+/// A downscaled 8-bit luma image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LumaThumbnail {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl LumaThumbnail {
+    /// Box-filter a luma plane down to `thumb_width` x `thumb_height`.
+    ///
+    /// Returns `None` if the plane is smaller than its stated dimensions.
+    pub fn from_luma(
+        plane: &[u8],
+        width: usize,
+        height: usize,
+        bytes_per_row: usize,
+        thumb_width: usize,
+        thumb_height: usize,
+    ) -> Option<Self> {
+        Self::downscale(width, height, thumb_width, thumb_height, |x, y| {
+            plane.get(y * bytes_per_row + x).copied()
+        })
+    }
+
+    /// Box-filter packed 32-bit RGB (BGRA, ARGB or RGBA) to luma.
+    ///
+    /// `rgb_offsets` are the byte offsets of red, green and blue in a pixel.
+    pub fn from_rgb32(
+        data: &[u8],
+        width: usize,
+        height: usize,
+        bytes_per_row: usize,
+        rgb_offsets: [usize; 3],
+        thumb_width: usize,
+        thumb_height: usize,
+    ) -> Option<Self> {
+        Self::downscale(width, height, thumb_width, thumb_height, |x, y| {
+            let pixel = data.get(y * bytes_per_row + x * 4..)?.get(..4)?;
+            let [r, g, b] = rgb_offsets.map(|i| pixel[i] as u32);
+            // BT.601 weights in 8-bit fixed point
+            Some(((66 * r + 129 * g + 25 * b + 128) >> 8) as u8 + 16)
+        })
+    }
+
+    /// Thumbnail of a pixel buffer's luma.
+    ///
+    /// Supports 4:2:0 bi-planar and 32-bit RGB formats; others fail with
+    /// `kCVReturnInvalidPixelFormat`.
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid CVPixelBuffer.
+    pub unsafe fn from_pixel_buffer(
+        pixel_buffer: CVPixelBufferRef,
+        thumb_width: usize,
+        thumb_height: usize,
+    ) -> Result<Self, i32> {
+        let pixel_format = CVPixelBufferGetPixelFormatType(pixel_buffer);
+        let rgb_offsets = match pixel_format {
+            codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE
+            | codecs::pixel::YUV420_BIPLANAR_FULL_RANGE => None,
+            codecs::pixel::BGRA32 => Some([2, 1, 0]),
+            codecs::pixel::ARGB32 => Some([1, 2, 3]),
+            codecs::pixel::RGBA32 => Some([0, 1, 2]),
+            _ => return Err(kCVReturnInvalidPixelFormat),
+        };
+
+        let status = CVPixelBufferLockBaseAddress(pixel_buffer, kCVPixelBufferLock_ReadOnly);
+        if status != kCVReturnSuccess {
+            return Err(status);
+        }
+
+        let width = CVPixelBufferGetWidth(pixel_buffer);
+        let height = CVPixelBufferGetHeight(pixel_buffer);
+        let thumbnail = match rgb_offsets {
+            None => {
+                let base = CVPixelBufferGetBaseAddressOfPlane(pixel_buffer, 0) as *const u8;
+                let bytes_per_row = CVPixelBufferGetBytesPerRowOfPlane(pixel_buffer, 0);
+                (!base.is_null()).then(|| {
+                    let plane = std::slice::from_raw_parts(base, bytes_per_row * height);
+                    Self::from_luma(
+                        plane,
+                        width,
+                        height,
+                        bytes_per_row,
+                        thumb_width,
+                        thumb_height,
+                    )
+                })
+            }
+            Some(offsets) => {
+                let base = CVPixelBufferGetBaseAddress(pixel_buffer) as *const u8;
+                let bytes_per_row = CVPixelBufferGetBytesPerRow(pixel_buffer);
+                (!base.is_null()).then(|| {
+                    let data = std::slice::from_raw_parts(base, bytes_per_row * height);
+                    Self::from_rgb32(
+                        data,
+                        width,
+                        height,
+                        bytes_per_row,
+                        offsets,
+                        thumb_width,
+                        thumb_height,
+                    )
+                })
+            }
+        };
+
+        CVPixelBufferUnlockBaseAddress(pixel_buffer, kCVPixelBufferLock_ReadOnly);
+        thumbnail.flatten().ok_or(kCVReturnInvalidPixelFormat)
+    }
+
+    fn downscale(
+        width: usize,
+        height: usize,
+        thumb_width: usize,
+        thumb_height: usize,
+        sample: impl Fn(usize, usize) -> Option<u8>,
+    ) -> Option<Self> {
+        let thumb_width = thumb_width.clamp(1, width.max(1));
+        let thumb_height = thumb_height.clamp(1, height.max(1));
+        let mut data = Vec::with_capacity(thumb_width * thumb_height);
+        for ty in 0..thumb_height {
+            let (y0, y1) = (ty * height / thumb_height, (ty + 1) * height / thumb_height);
+            for tx in 0..thumb_width {
+                let (x0, x1) = (tx * width / thumb_width, (tx + 1) * width / thumb_width);
+                let mut sum = 0u32;
+                for y in y0..y1.max(y0 + 1) {
+                    for x in x0..x1.max(x0 + 1) {
+                        sum += sample(x, y)? as u32;
+                    }
+                }
+                let count = ((y1.max(y0 + 1) - y0) * (x1.max(x0 + 1) - x0)) as u32;
+                data.push((sum / count) as u8);
+            }
+        }
+        Some(Self {
+            width: thumb_width,
+            height: thumb_height,
+            data,
+        })
+    }
+
+    fn histogram(&self) -> [u32; HISTOGRAM_BINS] {
+        let mut histogram = [0; HISTOGRAM_BINS];
+        for &v in &self.data {
+            histogram[v as usize * HISTOGRAM_BINS / 256] += 1;
+        }
+        histogram
+    }
+}
+
+/// Difference between consecutive frames, each from 0 (identical) to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneChangeScore {
+    /// Half the L1 distance between normalized luma histograms
+    pub histogram: f32,
+    /// Mean absolute luma difference, as a fraction of full scale
+    pub sad: f32,
+}
+
+impl SceneChangeScore {
+    /// Combined score. SAD is doubled since even hard cuts rarely change
+    /// more than half of the luma range on average.
+    pub fn combined(&self) -> f32 {
+        (self.histogram + (self.sad * 2.0).min(1.0)) / 2.0
+    }
+}
+
+/// Detects scene cuts from consecutive frames.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{CmafMuxer, CompressionSession, SceneChangeDetector};
+/// # fn frame(session: &CompressionSession, muxer: &mut CmafMuxer, pixel_buffer: video_toolbox_sys::cv_types::CVPixelBufferRef) {
+/// let mut detector = SceneChangeDetector::new().sensitivity(0.7);
+///
+/// // For each captured frame, before encoding it:
+/// if let Ok(true) = unsafe { detector.process(pixel_buffer, session) } {
+///     muxer.split_at_next_keyframe();
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SceneChangeDetector {
+    sensitivity: f32,
+    min_interval: u32,
+    thumb_width: usize,
+    thumb_height: usize,
+    previous: Option<LumaThumbnail>,
+    frames_since_cut: u32,
+    last_score: Option<SceneChangeScore>,
+}
+
+impl Default for SceneChangeDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneChangeDetector {
+    /// Create a detector with sensitivity 0.5, a 64x36 thumbnail and at most
+    /// one cut every 15 frames.
+    pub fn new() -> Self {
+        Self {
+            sensitivity: 0.5,
+            min_interval: 15,
+            thumb_width: 64,
+            thumb_height: 36,
+            previous: None,
+            frames_since_cut: 0,
+            last_score: None,
+        }
+    }
+
+    /// How readily cuts are reported, from 0.0 (only drastic changes) to 1.0
+    /// (any noticeable change).
+    pub fn sensitivity(mut self, sensitivity: f32) -> Self {
+        self.sensitivity = sensitivity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Minimum number of frames between reported cuts, so flashes and fast
+    /// cuts do not produce a burst of keyframes.
+    pub fn min_interval(mut self, frames: u32) -> Self {
+        self.min_interval = frames;
+        self
+    }
+
+    /// Thumbnail size used for comparison (default: 64x36).
+    pub fn thumbnail_size(mut self, width: usize, height: usize) -> Self {
+        self.thumb_width = width.max(1);
+        self.thumb_height = height.max(1);
+        self
+    }
+
+    /// Combined score a frame must exceed to count as a cut.
+    pub fn threshold(&self) -> f32 {
+        0.9 - 0.8 * self.sensitivity
+    }
+
+    /// Score of the most recent frame against its predecessor.
+    pub fn last_score(&self) -> Option<SceneChangeScore> {
+        self.last_score
+    }
+
+    /// Compare a thumbnail with the previous frame. Returns true at a cut.
+    pub fn push_thumbnail(&mut self, thumbnail: LumaThumbnail) -> bool {
+        self.frames_since_cut = self.frames_since_cut.saturating_add(1);
+        let score = match &self.previous {
+            Some(previous) if previous.data.len() == thumbnail.data.len() => {
+                Some(score(previous, &thumbnail))
+            }
+            _ => None,
+        };
+        self.previous = Some(thumbnail);
+        self.last_score = score;
+
+        let is_cut = score.is_some_and(|s| s.combined() > self.threshold())
+            && self.frames_since_cut >= self.min_interval;
+        if is_cut {
+            self.frames_since_cut = 0;
+        }
+        is_cut
+    }
+
+    /// Check a pixel buffer for a cut.
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid CVPixelBuffer.
+    pub unsafe fn push_pixel_buffer(
+        &mut self,
+        pixel_buffer: CVPixelBufferRef,
+    ) -> Result<bool, i32> {
+        let thumbnail =
+            LumaThumbnail::from_pixel_buffer(pixel_buffer, self.thumb_width, self.thumb_height)?;
+        Ok(self.push_thumbnail(thumbnail))
+    }
+
+    /// Check a pixel buffer and force a keyframe on `session` at a cut.
+    ///
+    /// Call before submitting the frame so the cut frame itself is the keyframe.
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid CVPixelBuffer.
+    pub unsafe fn process(
+        &mut self,
+        pixel_buffer: CVPixelBufferRef,
+        session: &CompressionSession,
+    ) -> Result<bool, i32> {
+        let is_cut = self.push_pixel_buffer(pixel_buffer)?;
+        if is_cut {
+            session.force_keyframe();
+        }
+        Ok(is_cut)
+    }
+
+    /// Forget the previous frame (e.g., after a source switch).
+    pub fn reset(&mut self) {
+        self.previous = None;
+        self.last_score = None;
+        self.frames_since_cut = 0;
+    }
+}
+
+fn score(a: &LumaThumbnail, b: &LumaThumbnail) -> SceneChangeScore {
+    let n = a.data.len().max(1) as f32;
+    let (ha, hb) = (a.histogram(), b.histogram());
+    let histogram_distance: u32 = ha.iter().zip(&hb).map(|(x, y)| x.abs_diff(*y)).sum();
+    SceneChangeScore {
+        histogram: histogram_distance as f32 / (2.0 * n),
+        sad: mean_abs_diff(a, b),
+    }
+}
+
+/// Mean absolute luma difference of two equally sized thumbnails, as a
+/// fraction of full scale.
+pub(crate) fn mean_abs_diff(a: &LumaThumbnail, b: &LumaThumbnail) -> f32 {
+    let sad: u64 = a
+        .data
+        .iter()
+        .zip(&b.data)
+        .map(|(x, y)| x.abs_diff(*y) as u64)
+        .sum();
+    sad as f32 / (255.0 * a.data.len().max(1) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thumbnail(f: impl Fn(usize, usize) -> u8) -> LumaThumbnail {
+        let (width, height) = (16, 9);
+        let data = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+        LumaThumbnail {
+            width,
+            height,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_downscale_box_filter() {
+        // 4x2 luma plane with 2 bytes of row padding
+        let plane = [10, 20, 30, 50, 0, 0, 30, 40, 70, 90, 0, 0];
+        let thumb = LumaThumbnail::from_luma(&plane, 4, 2, 6, 2, 1).unwrap();
+        assert_eq!(thumb.data, vec![25, 60]);
+        assert!(LumaThumbnail::from_luma(&plane[..8], 4, 2, 6, 2, 1).is_none());
+
+        // Pure white BGRA maps to video-range white
+        let white = [255u8; 16];
+        let thumb = LumaThumbnail::from_rgb32(&white, 2, 2, 8, [2, 1, 0], 1, 1).unwrap();
+        assert_eq!(thumb.data, vec![235]);
+    }
+
+    #[test]
+    fn test_detects_cut_but_not_motion() {
+        let mut detector = SceneChangeDetector::new().min_interval(0);
+        // A gradient panning slowly: motion, not a cut
+        for shift in 0..10 {
+            assert!(!detector.push_thumbnail(thumbnail(|x, _| ((x + shift) * 8) as u8 + 40)));
+        }
+        // Cut to a dark, uniform scene
+        assert!(detector.push_thumbnail(thumbnail(|_, _| 20)));
+        assert!(detector.last_score().unwrap().combined() > 0.5);
+        assert!(!detector.push_thumbnail(thumbnail(|_, _| 21)));
+    }
+
+    #[test]
+    fn test_sensitivity_and_min_interval() {
+        let bright = thumbnail(|_, _| 200);
+        let dim = thumbnail(|_, _| 150);
+
+        let mut strict = SceneChangeDetector::new().sensitivity(0.0).min_interval(0);
+        strict.push_thumbnail(bright.clone());
+        assert!(!strict.push_thumbnail(dim.clone()));
+
+        let mut sensitive = SceneChangeDetector::new().sensitivity(1.0).min_interval(0);
+        sensitive.push_thumbnail(bright.clone());
+        assert!(sensitive.push_thumbnail(dim.clone()));
+
+        let mut limited = SceneChangeDetector::new().sensitivity(1.0).min_interval(3);
+        limited.push_thumbnail(bright.clone());
+        limited.push_thumbnail(bright.clone());
+        limited.push_thumbnail(bright.clone());
+        assert!(limited.push_thumbnail(dim.clone()));
+        assert!(!limited.push_thumbnail(bright.clone()));
+    }
+}
@@ -0,0 +1,408 @@
+//! Display and window capture with ScreenCaptureKit.
+//!
+//! Frames arrive as CMSampleBuffers through the same [`DelegateCallback`]
+//! used for camera capture, so a screen feeds the usual
+//! "capture → [`CompressionSession`](super::CompressionSession) → CMAF"
+//! pipeline unchanged.
+
+use block2::RcBlock;
+use core_foundation_sys::base::OSStatus;
+use libc::c_void;
+use objc2::rc::Retained;
+use objc2::runtime::AnyObject;
+use objc2::sel;
+use objc2_foundation::{NSArray, NSError, NSObject};
+use std::ffi::CString;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+use super::clock::make_time;
+use super::delegate::{create_delegate_with_method, create_dispatch_queue, DelegateCallback};
+use crate::cm_sample_buffer::CMSampleBufferGetImageBuffer;
+use crate::codecs;
+use crate::cv_types::CVPixelBufferRef;
+use crate::screen_capture_kit::{
+    SCContentFilter_initWithDesktopIndependentWindow, SCContentFilter_initWithDisplay,
+    SCDisplay_displayID, SCDisplay_height, SCDisplay_width, SCErrorCode,
+    SCRunningApplication_applicationName, SCShareableContent_displays,
+    SCShareableContent_getShareableContent, SCShareableContent_windows, SCStreamConfiguration_new,
+    SCStreamConfiguration_setHeight, SCStreamConfiguration_setMinimumFrameInterval,
+    SCStreamConfiguration_setPixelFormat, SCStreamConfiguration_setQueueDepth,
+    SCStreamConfiguration_setShowsCursor, SCStreamConfiguration_setWidth,
+    SCStreamErrorFailedToStart, SCStreamOutputTypeScreen, SCStream_addStreamOutput, SCStream_init,
+    SCStream_startCapture, SCStream_stopCapture, SCWindow_isOnScreen, SCWindow_owningApplication,
+    SCWindow_title, SCWindow_windowID,
+};
+
+/// Distinguishes the output classes registered by each [`ScreenCapture`]
+static OUTPUT_CLASS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Wait for a ScreenCaptureKit completion handler reporting only an error.
+fn wait_for_completion(call: impl FnOnce(&RcBlock<dyn Fn(*mut NSError)>)) -> Result<(), OSStatus> {
+    let (tx, rx) = mpsc::channel();
+    let handler: RcBlock<dyn Fn(*mut NSError)> = RcBlock::new(move |error: *mut NSError| {
+        let _ = tx.send(unsafe { SCErrorCode(error) });
+    });
+    call(&handler);
+    drop(handler);
+    match rx.recv() {
+        Ok(None) => Ok(()),
+        Ok(Some(code)) => Err(code),
+        Err(_) => Err(SCStreamErrorFailedToStart),
+    }
+}
+
+/// The displays and windows available for capture.
+pub struct ShareableContent {
+    raw: Retained<AnyObject>,
+}
+
+impl ShareableContent {
+    /// Query the current displays and on-screen windows, waiting for
+    /// ScreenCaptureKit's answer.
+    ///
+    /// The first call may prompt for screen recording permission; fails with
+    /// [`SCStreamErrorUserDeclined`](crate::screen_capture_kit::SCStreamErrorUserDeclined)
+    /// if it is denied, or `SCStreamErrorFailedToStart` where ScreenCaptureKit
+    /// is unavailable (before macOS 12.3).
+    pub fn current() -> Result<Self, OSStatus> {
+        let (tx, rx) = mpsc::channel();
+        let handler: RcBlock<dyn Fn(*mut AnyObject, *mut NSError)> =
+            RcBlock::new(move |content: *mut AnyObject, error: *mut NSError| {
+                let result = match unsafe { Retained::retain(content) } {
+                    Some(content) => Ok(content),
+                    None => {
+                        Err(unsafe { SCErrorCode(error) }.unwrap_or(SCStreamErrorFailedToStart))
+                    }
+                };
+                let _ = tx.send(result);
+            });
+        if !unsafe { SCShareableContent_getShareableContent(true, true, &handler) } {
+            return Err(SCStreamErrorFailedToStart);
+        }
+        drop(handler);
+        let raw = rx.recv().map_err(|_| SCStreamErrorFailedToStart)??;
+        Ok(Self { raw })
+    }
+
+    pub fn displays(&self) -> Vec<Display> {
+        let displays = unsafe { SCShareableContent_displays(&self.raw) };
+        displays.iter().map(|raw| Display { raw }).collect()
+    }
+
+    /// On-screen windows, front to back.
+    pub fn windows(&self) -> Vec<Window> {
+        let windows = unsafe { SCShareableContent_windows(&self.raw) };
+        windows.iter().map(|raw| Window { raw }).collect()
+    }
+
+    /// The display with the given `CGDirectDisplayID`.
+    pub fn display(&self, id: u32) -> Option<Display> {
+        self.displays()
+            .into_iter()
+            .find(|display| display.id() == id)
+    }
+}
+
+/// A display that can be captured.
+#[derive(Clone)]
+pub struct Display {
+    raw: Retained<AnyObject>,
+}
+
+impl Display {
+    /// The display's `CGDirectDisplayID`.
+    pub fn id(&self) -> u32 {
+        unsafe { SCDisplay_displayID(&self.raw) }
+    }
+
+    /// Width in points; multiply by the backing scale factor for pixels.
+    pub fn width(&self) -> usize {
+        unsafe { SCDisplay_width(&self.raw) as usize }
+    }
+
+    /// Height in points.
+    pub fn height(&self) -> usize {
+        unsafe { SCDisplay_height(&self.raw) as usize }
+    }
+
+    pub fn as_raw(&self) -> &AnyObject {
+        &self.raw
+    }
+}
+
+/// A window that can be captured.
+#[derive(Clone)]
+pub struct Window {
+    raw: Retained<AnyObject>,
+}
+
+impl Window {
+    /// The window's `CGWindowID`.
+    pub fn id(&self) -> u32 {
+        unsafe { SCWindow_windowID(&self.raw) }
+    }
+
+    pub fn title(&self) -> Option<String> {
+        unsafe { SCWindow_title(&self.raw) }.map(|title| title.to_string())
+    }
+
+    /// Name of the application owning the window.
+    pub fn application_name(&self) -> Option<String> {
+        let application = unsafe { SCWindow_owningApplication(&self.raw) }?;
+        Some(unsafe { SCRunningApplication_applicationName(&application) }.to_string())
+    }
+
+    pub fn is_on_screen(&self) -> bool {
+        unsafe { SCWindow_isOnScreen(&self.raw) }
+    }
+
+    pub fn as_raw(&self) -> &AnyObject {
+        &self.raw
+    }
+}
+
+/// Output size, rate and format of a [`ScreenCapture`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScreenCaptureConfig {
+    pub width: usize,
+    pub height: usize,
+    /// Maximum frame rate; ScreenCaptureKit only delivers frames when the
+    /// content changes
+    pub frame_rate: f64,
+    pub pixel_format: u32,
+    pub shows_cursor: bool,
+    /// Frames in flight before capture stalls (3 to 8)
+    pub queue_depth: u32,
+}
+
+impl ScreenCaptureConfig {
+    /// Capture at `width`x`height` pixels, 30 fps, in NV12 (the encoder's
+    /// native input), with the cursor.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            frame_rate: 30.0,
+            pixel_format: codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE,
+            shows_cursor: true,
+            queue_depth: 5,
+        }
+    }
+
+    pub fn frame_rate(mut self, frame_rate: f64) -> Self {
+        self.frame_rate = frame_rate;
+        self
+    }
+
+    pub fn pixel_format(mut self, pixel_format: u32) -> Self {
+        self.pixel_format = pixel_format;
+        self
+    }
+
+    pub fn shows_cursor(mut self, shows_cursor: bool) -> Self {
+        self.shows_cursor = shows_cursor;
+        self
+    }
+
+    pub fn queue_depth(mut self, queue_depth: u32) -> Self {
+        self.queue_depth = queue_depth.clamp(3, 8);
+        self
+    }
+
+    /// Minimum frame interval in microseconds.
+    fn frame_interval_us(&self) -> i64 {
+        (1_000_000.0 / self.frame_rate.max(1.0)).round() as i64
+    }
+
+    fn to_configuration(&self) -> Option<Retained<AnyObject>> {
+        unsafe {
+            let configuration = SCStreamConfiguration_new()?;
+            SCStreamConfiguration_setWidth(&configuration, self.width);
+            SCStreamConfiguration_setHeight(&configuration, self.height);
+            SCStreamConfiguration_setMinimumFrameInterval(
+                &configuration,
+                make_time(self.frame_interval_us(), 1_000_000).into(),
+            );
+            SCStreamConfiguration_setPixelFormat(&configuration, self.pixel_format);
+            SCStreamConfiguration_setShowsCursor(&configuration, self.shows_cursor);
+            SCStreamConfiguration_setQueueDepth(&configuration, self.queue_depth as isize);
+            Some(configuration)
+        }
+    }
+}
+
+/// Create an `SCStreamOutput` delegate whose
+/// `stream:didOutputSampleBuffer:ofType:` calls `callback`.
+///
+/// The callback has the [`create_capture_delegate`](super::create_capture_delegate)
+/// shape: its arguments are (self, _cmd, stream, sample_buffer, output_type),
+/// where the last one is the `SCStreamOutputType` as a pointer-sized integer
+/// (0 for screen frames) instead of a connection.
+pub fn create_screen_output_delegate(
+    class_name: &str,
+    callback: DelegateCallback,
+) -> Result<Retained<NSObject>, &'static str> {
+    let class_name = CString::new(class_name).map_err(|_| "Invalid class name")?;
+    // Method signature: v@:@@q (void, self, _cmd, stream, sampleBuffer, type)
+    create_delegate_with_method(
+        &class_name,
+        c"SCStreamOutput",
+        sel!(stream:didOutputSampleBuffer:ofType:),
+        c"v@:@@q",
+        callback,
+    )
+}
+
+/// The pixel buffer of a captured screen frame, or `None` for the status-only
+/// sample buffers ScreenCaptureKit sends while the content is unchanged.
+/// The buffer is owned by the sample buffer; retain it to keep it longer.
+///
+/// # Safety
+///
+/// `sample_buffer` must be a valid CMSampleBuffer, e.g. the one passed to
+/// a screen output callback.
+pub unsafe fn screen_frame_pixel_buffer(sample_buffer: *mut c_void) -> Option<CVPixelBufferRef> {
+    let image_buffer = CMSampleBufferGetImageBuffer(sample_buffer as _);
+    (!image_buffer.is_null()).then_some(image_buffer)
+}
+
+/// A running ScreenCaptureKit stream of a display or window.
+///
+/// Frames are delivered to the callback on a dedicated dispatch queue until
+/// the capture is stopped or dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use libc::c_void;
+/// use objc2::runtime::Sel;
+/// use video_toolbox_sys::helpers::{
+///     screen_frame_pixel_buffer, ScreenCapture, ScreenCaptureConfig, ShareableContent,
+/// };
+///
+/// extern "C-unwind" fn on_frame(
+///     _this: *mut c_void,
+///     _cmd: Sel,
+///     _stream: *mut c_void,
+///     sample_buffer: *mut c_void,
+///     _output_type: *mut c_void,
+/// ) {
+///     if let Some(pixel_buffer) = unsafe { screen_frame_pixel_buffer(sample_buffer) } {
+///         // encode pixel_buffer with a CompressionSession
+///     }
+/// }
+///
+/// let content = ShareableContent::current()?;
+/// let display = content.displays().into_iter().next().expect("no display");
+/// let config = ScreenCaptureConfig::new(display.width() * 2, display.height() * 2).frame_rate(60.0);
+/// let mut capture = ScreenCapture::display(&display, &config, on_frame)?;
+/// capture.start()?;
+/// # Ok::<(), i32>(())
+/// ```
+pub struct ScreenCapture {
+    stream: Retained<AnyObject>,
+    // Kept alive while the stream refers to it
+    _output: Retained<NSObject>,
+    running: bool,
+}
+
+// The stream is only messaged from the owning thread; frames arrive on the
+// capture queue.
+unsafe impl Send for ScreenCapture {}
+
+impl ScreenCapture {
+    /// Capture a whole display.
+    pub fn display(
+        display: &Display,
+        config: &ScreenCaptureConfig,
+        callback: DelegateCallback,
+    ) -> Result<Self, OSStatus> {
+        let no_windows = NSArray::<AnyObject>::new();
+        let filter = unsafe { SCContentFilter_initWithDisplay(&display.raw, &no_windows) };
+        Self::with_filter(filter, config, callback)
+    }
+
+    /// Capture a single window, wherever it is on screen.
+    pub fn window(
+        window: &Window,
+        config: &ScreenCaptureConfig,
+        callback: DelegateCallback,
+    ) -> Result<Self, OSStatus> {
+        let filter = unsafe { SCContentFilter_initWithDesktopIndependentWindow(&window.raw) };
+        Self::with_filter(filter, config, callback)
+    }
+
+    fn with_filter(
+        filter: Option<Retained<AnyObject>>,
+        config: &ScreenCaptureConfig,
+        callback: DelegateCallback,
+    ) -> Result<Self, OSStatus> {
+        let filter = filter.ok_or(SCStreamErrorFailedToStart)?;
+        let configuration = config
+            .to_configuration()
+            .ok_or(SCStreamErrorFailedToStart)?;
+        let stream = unsafe { SCStream_init(&filter, &configuration, None) }
+            .ok_or(SCStreamErrorFailedToStart)?;
+
+        let index = OUTPUT_CLASS_COUNT.fetch_add(1, Ordering::Relaxed);
+        let class_name = format!("VTScreenCaptureOutput{}", index);
+        let output = create_screen_output_delegate(&class_name, callback)
+            .map_err(|_| SCStreamErrorFailedToStart)?;
+        let queue = create_dispatch_queue(&format!("com.videotoolbox.{}.queue", class_name));
+        unsafe { SCStream_addStreamOutput(&stream, &output, SCStreamOutputTypeScreen, queue)? };
+        Ok(Self {
+            stream,
+            _output: output,
+            running: false,
+        })
+    }
+
+    /// Start delivering frames, waiting until capture is running.
+    pub fn start(&mut self) -> Result<(), OSStatus> {
+        wait_for_completion(|handler| unsafe { SCStream_startCapture(&self.stream, handler) })?;
+        self.running = true;
+        Ok(())
+    }
+
+    /// Stop delivering frames, waiting until the last one was delivered.
+    pub fn stop(&mut self) -> Result<(), OSStatus> {
+        if !self.running {
+            return Ok(());
+        }
+        self.running = false;
+        wait_for_completion(|handler| unsafe { SCStream_stopCapture(&self.stream, handler) })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn as_raw(&self) -> &AnyObject {
+        &self.stream
+    }
+}
+
+impl Drop for ScreenCapture {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_frame_interval() {
+        let config = ScreenCaptureConfig::new(1920, 1080)
+            .frame_rate(60.0)
+            .queue_depth(12);
+        assert_eq!(config.frame_interval_us(), 16_667);
+        assert_eq!(config.queue_depth, 8);
+        assert_eq!(
+            config.pixel_format,
+            codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE
+        );
+    }
+}
@@ -0,0 +1,262 @@
+//! Receive-side reordering of CMAF fragments by their `mfhd` sequence
+//! number.
+//!
+//! A relay may deliver MoQ groups out of order, and decoding fragments in
+//! arrival order shows glitches until the next keyframe. [`ReorderBuffer`]
+//! holds early fragments back until the missing ones arrive, and gives up on
+//! a gap after a bounded wait so one lost group does not stall playback.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use super::events::{emit, PipelineEvent};
+use super::mfra::{find_box, read_u32};
+
+/// Settings for [`ReorderBuffer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorderConfig {
+    /// How long to wait for a missing fragment before skipping it
+    pub max_wait: Duration,
+    /// Fragments held back at most; more skip the gap immediately
+    pub max_pending: usize,
+    /// A fragment further behind the expected one than this is taken as
+    /// the start of a restarted stream rather than as late
+    pub max_backward_jump: u32,
+}
+
+impl Default for ReorderConfig {
+    fn default() -> Self {
+        Self {
+            max_wait: Duration::from_millis(200),
+            max_pending: 64,
+            max_backward_jump: 1024,
+        }
+    }
+}
+
+/// Counters of a [`ReorderBuffer`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReorderStats {
+    /// Fragments that arrived ahead of a missing one
+    pub reordered: u64,
+    /// Sequence numbers given up on
+    pub skipped: u64,
+    /// Fragments dropped because they arrived after their gap was skipped,
+    /// or twice
+    pub late: u64,
+}
+
+/// The sequence number of a fragment's `moof/mfhd`, or `None` for data
+/// without one, such as an init segment.
+pub fn fragment_sequence_number(segment: &[u8]) -> Option<u32> {
+    let moof = find_box(segment, 0, segment.len(), b"moof")?;
+    let mfhd = find_box(segment, moof.content(), moof.end(), b"mfhd")?;
+    read_u32(segment, mfhd.content() + 4)
+}
+
+/// Releases received fragments in sequence number order.
+///
+/// Data without a fragment sequence number (init segments) is released
+/// immediately, after any held fragments, and the next fragment received
+/// sets the expected sequence number again. Later ones ahead of it wait
+/// until the gap is filled, or until the oldest of them has waited
+/// [`ReorderConfig::max_wait`], when the gap is skipped and reported as
+/// [`PipelineEvent::SegmentsSkipped`]. Sequence numbers are compared as
+/// serial numbers, so they may wrap around, and one more than
+/// [`ReorderConfig::max_backward_jump`] behind restarts the order. Call
+/// [`poll`](Self::poll) periodically so a stalled gap is skipped even when
+/// nothing else arrives.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Instant;
+/// use video_toolbox_sys::helpers::{ReorderBuffer, ReorderConfig};
+///
+/// let mut reorder = ReorderBuffer::new(ReorderConfig::default());
+/// # let received: Vec<Vec<u8>> = Vec::new();
+/// for data in received {
+///     for segment in reorder.push(data, Instant::now()) {
+///         // decode segment
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    config: ReorderConfig,
+    /// Expected sequence number, counted on past wrap-arounds
+    next: Option<i64>,
+    pending: BTreeMap<i64, (Instant, Vec<u8>)>,
+    stats: ReorderStats,
+}
+
+impl ReorderBuffer {
+    pub fn new(config: ReorderConfig) -> Self {
+        Self {
+            config,
+            next: None,
+            pending: BTreeMap::new(),
+            stats: ReorderStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> ReorderStats {
+        self.stats
+    }
+
+    /// Number of fragments held back.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Add received data, returning whatever can now be released in order.
+    pub fn push(&mut self, segment: Vec<u8>, now: Instant) -> Vec<Vec<u8>> {
+        match fragment_sequence_number(&segment) {
+            Some(sequence_number) => self.push_sequenced(sequence_number, segment, now),
+            None => {
+                // A new init segment may start a new stream
+                let mut released = self.flush();
+                self.next = None;
+                released.push(segment);
+                released
+            }
+        }
+    }
+
+    /// Add data whose sequence number is already known, e.g. a MoQ group
+    /// sequence.
+    pub fn push_sequenced(
+        &mut self,
+        sequence_number: u32,
+        segment: Vec<u8>,
+        now: Instant,
+    ) -> Vec<Vec<u8>> {
+        let mut released = Vec::new();
+        let next = *self.next.get_or_insert(sequence_number as i64);
+        let mut position = next + sequence_number.wrapping_sub(next as u32) as i32 as i64;
+        if next - position > self.config.max_backward_jump as i64 {
+            released = self.flush();
+            position = sequence_number as i64;
+            self.next = Some(position);
+        }
+        let next = self.next.unwrap_or(position);
+        if position < next || self.pending.contains_key(&position) {
+            self.stats.late += 1;
+        } else {
+            if position > next {
+                self.stats.reordered += 1;
+            }
+            self.pending.insert(position, (now, segment));
+        }
+        released.extend(self.poll(now));
+        released
+    }
+
+    /// Release fragments that are next in order, skipping a gap whose
+    /// waiting fragments have waited too long.
+    pub fn poll(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let mut released = Vec::new();
+        while let Some(next) = self.next {
+            if let Some((_, segment)) = self.pending.remove(&next) {
+                released.push(segment);
+                self.next = Some(next + 1);
+                continue;
+            }
+            let Some(oldest) = self.pending.values().map(|(arrived, _)| *arrived).min() else {
+                break;
+            };
+            let expired = now.saturating_duration_since(oldest) >= self.config.max_wait;
+            if !expired && self.pending.len() <= self.config.max_pending {
+                break;
+            }
+            let resume = *self.pending.keys().next().unwrap();
+            self.stats.skipped += (resume - next) as u64;
+            emit(PipelineEvent::SegmentsSkipped {
+                from: next as u32,
+                to: resume as u32,
+            });
+            self.next = Some(resume);
+        }
+        released
+    }
+
+    /// Release every held fragment in order, e.g. when the stream ends.
+    pub fn flush(&mut self) -> Vec<Vec<u8>> {
+        let pending = std::mem::take(&mut self.pending);
+        if let Some((&last, _)) = pending.iter().next_back() {
+            self.next = Some(last + 1);
+        }
+        pending.into_values().map(|(_, segment)| segment).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::audio_cmaf::write_box;
+
+    fn fragment(sequence_number: u32) -> Vec<u8> {
+        let mut mfhd = vec![0, 0, 0, 0];
+        mfhd.extend_from_slice(&sequence_number.to_be_bytes());
+        let mut moof = Vec::new();
+        write_box(&mut moof, b"mfhd", &mfhd);
+        let mut data = Vec::new();
+        write_box(&mut data, b"moof", &moof);
+        write_box(&mut data, b"mdat", &[sequence_number as u8]);
+        data
+    }
+
+    #[test]
+    fn test_reorder_and_skip_gaps() {
+        let start = Instant::now();
+        let mut reorder = ReorderBuffer::new(ReorderConfig::default());
+        assert_eq!(fragment_sequence_number(&fragment(7)), Some(7));
+
+        assert_eq!(reorder.push(b"init".to_vec(), start), [b"init".to_vec()]);
+        assert_eq!(reorder.push(fragment(1), start), [fragment(1)]);
+        // 3 arrives before 2
+        assert!(reorder.push(fragment(3), start).is_empty());
+        assert_eq!(reorder.push(fragment(2), start), [fragment(2), fragment(3)]);
+        assert_eq!(reorder.stats().reordered, 1);
+
+        // 5 is lost: 6 waits until max_wait, then 5 is skipped
+        assert!(reorder.push(fragment(4), start).len() == 1);
+        assert!(reorder.push(fragment(6), start).is_empty());
+        assert!(reorder.poll(start + Duration::from_millis(100)).is_empty());
+        assert_eq!(
+            reorder.poll(start + Duration::from_millis(200)),
+            [fragment(6)]
+        );
+        assert_eq!(reorder.stats().skipped, 1);
+
+        // 5 arriving now is too late
+        assert!(reorder.push(fragment(5), start).is_empty());
+        assert_eq!(reorder.stats().late, 1);
+    }
+    #[test]
+    fn test_wrap_around_and_restarts() {
+        let start = Instant::now();
+        let mut reorder = ReorderBuffer::new(ReorderConfig::default());
+        assert_eq!(reorder.push(fragment(u32::MAX - 1), start).len(), 1);
+        // 0 follows u32::MAX
+        assert!(reorder.push(fragment(0), start).is_empty());
+        assert_eq!(
+            reorder.push(fragment(u32::MAX), start),
+            [fragment(u32::MAX), fragment(0)]
+        );
+        assert_eq!(reorder.stats().reordered, 1);
+
+        // A large backward jump restarts the order, releasing held fragments
+        assert!(reorder.push(fragment(3), start).is_empty());
+        assert_eq!(
+            reorder.push(fragment(4_000_000_000), start),
+            [fragment(3), fragment(4_000_000_000)]
+        );
+        assert_eq!(reorder.stats().late, 0);
+
+        // So does an init segment
+        assert_eq!(reorder.push(b"init".to_vec(), start), [b"init".to_vec()]);
+        assert_eq!(reorder.push(fragment(1), start), [fragment(1)]);
+        assert_eq!(reorder.stats().skipped, 0);
+    }
+}
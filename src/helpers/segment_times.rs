@@ -0,0 +1,424 @@
+//! Mapping of emitted segments to capture host time and wall clock time.
+//!
+//! Media timestamps start at zero, so correlating a recording with an
+//! external log (IMU samples, lidar sweeps, a lab notebook) needs the clock
+//! time each segment was captured at. A [`SegmentTimeIndex`] is anchored to
+//! the capture clock once and then records, for every media segment, the
+//! earliest presentation time and the host and wall clock times it
+//! corresponds to.
+
+use core_media_sys::CMTime;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::clock::{host_time_now, FrameTimestamper};
+use super::mfra::{find_box, read_u32, read_u64};
+use super::nal_extractor::convert_time;
+use super::sink::{Segment, SegmentSink};
+
+const NANOS_PER_SECOND: i32 = 1_000_000_000;
+
+/// The capture times of one media segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentTime {
+    /// Fragment sequence number (`mfhd`)
+    pub sequence_number: u32,
+    /// Earliest presentation time of the segment in the media timescale:
+    /// its `tfdt` plus the smallest composition offset
+    pub earliest_pts: i64,
+    pub duration: Option<Duration>,
+    /// Host time of `earliest_pts` in nanoseconds, if the index was anchored
+    pub host_time_ns: Option<i64>,
+    /// Wall clock time of `earliest_pts`, if the index was anchored
+    pub wall_clock: Option<SystemTime>,
+}
+
+/// A known correspondence between media time and the clocks.
+#[derive(Debug, Clone, Copy)]
+struct Anchor {
+    pts: i64,
+    host_time_ns: i64,
+    wall_clock: SystemTime,
+}
+
+/// Records the capture host time and wall clock time of each segment.
+///
+/// [`anchor`](Self::anchor) the index to the capture clock (or use
+/// [`anchor_timestamper`](Self::anchor_timestamper) with the
+/// [`FrameTimestamper`] stamping the frames), then pass it every segment,
+/// directly or through a [`TimeIndexedSink`]. Segments added before the
+/// anchor are listed without clock times.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{FrameTimestamper, SegmentTimeIndex};
+/// # let segments: Vec<video_toolbox_sys::helpers::Segment> = Vec::new();
+///
+/// let stamper = FrameTimestamper::host_time(90000);
+/// let mut index = SegmentTimeIndex::new(90000);
+/// // ... after the first frame is stamped
+/// index.anchor_timestamper(&stamper);
+/// for segment in &segments {
+///     index.add_segment(segment);
+/// }
+/// # let sensor_host_time_ns = 0;
+/// if let Some(segment) = index.segment_at_host_time(sensor_host_time_ns) {
+///     println!("sensor sample falls in segment {}", segment.sequence_number);
+/// }
+/// index.save_json("recording.times.json")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct SegmentTimeIndex {
+    timescale: u32,
+    anchor: Option<Anchor>,
+    segments: Vec<SegmentTime>,
+}
+
+impl SegmentTimeIndex {
+    /// Create an index for media timestamps in `timescale` units.
+    pub fn new(timescale: u32) -> Self {
+        Self {
+            timescale: timescale.max(1),
+            anchor: None,
+            segments: Vec::new(),
+        }
+    }
+
+    /// Record that media time `pts` was captured at `host_time` on the host
+    /// time clock. The wall clock time is taken from the current offset
+    /// between the host clock and the system clock.
+    pub fn anchor(&mut self, pts: i64, host_time: CMTime) {
+        let host_time_ns = convert_time(host_time, NANOS_PER_SECOND);
+        let now_ns = convert_time(host_time_now(), NANOS_PER_SECOND);
+        let now = SystemTime::now();
+        let age = now_ns - host_time_ns;
+        let wall_clock = if age >= 0 {
+            now - Duration::from_nanos(age as u64)
+        } else {
+            now + Duration::from_nanos(age.unsigned_abs())
+        };
+        self.anchor_wall_clock(pts, host_time_ns, wall_clock);
+    }
+
+    /// Anchor pts 0 to the origin of a host time [`FrameTimestamper`].
+    /// Returns false if it has not stamped a frame yet.
+    pub fn anchor_timestamper(&mut self, stamper: &FrameTimestamper) -> bool {
+        match stamper.origin() {
+            Some(origin) => {
+                self.anchor(0, origin);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Anchor with explicit clock times, e.g. ones logged by another process.
+    pub fn anchor_wall_clock(&mut self, pts: i64, host_time_ns: i64, wall_clock: SystemTime) {
+        self.anchor = Some(Anchor {
+            pts,
+            host_time_ns,
+            wall_clock,
+        });
+    }
+
+    /// Host time of media time `pts` in nanoseconds, if anchored.
+    pub fn host_time_of(&self, pts: i64) -> Option<i64> {
+        let anchor = self.anchor?;
+        Some(anchor.host_time_ns + self.nanos_between(anchor.pts, pts))
+    }
+
+    /// Wall clock time of media time `pts`, if anchored.
+    pub fn wall_clock_of(&self, pts: i64) -> Option<SystemTime> {
+        let anchor = self.anchor?;
+        let delta = self.nanos_between(anchor.pts, pts);
+        Some(if delta >= 0 {
+            anchor.wall_clock + Duration::from_nanos(delta as u64)
+        } else {
+            anchor.wall_clock - Duration::from_nanos(delta.unsigned_abs())
+        })
+    }
+
+    fn nanos_between(&self, from: i64, to: i64) -> i64 {
+        ((to - from) as i128 * NANOS_PER_SECOND as i128 / self.timescale as i128) as i64
+    }
+
+    /// Record a media segment, reading its earliest presentation time from
+    /// the `moof`. Init segments and unparsable data are ignored.
+    pub fn add_segment(&mut self, segment: &Segment) -> Option<&SegmentTime> {
+        if segment.is_init() {
+            return None;
+        }
+        let earliest_pts = earliest_presentation_time(&segment.data)?;
+        Some(self.add(segment.sequence_number, earliest_pts, segment.duration))
+    }
+
+    /// Record a segment whose earliest presentation time is already known.
+    pub fn add(
+        &mut self,
+        sequence_number: u32,
+        earliest_pts: i64,
+        duration: Option<Duration>,
+    ) -> &SegmentTime {
+        self.segments.push(SegmentTime {
+            sequence_number,
+            earliest_pts,
+            duration,
+            host_time_ns: self.host_time_of(earliest_pts),
+            wall_clock: self.wall_clock_of(earliest_pts),
+        });
+        self.segments.last().unwrap()
+    }
+
+    pub fn segments(&self) -> &[SegmentTime] {
+        &self.segments
+    }
+
+    pub fn timescale(&self) -> u32 {
+        self.timescale
+    }
+
+    /// The last segment starting at or before `host_time_ns`, i.e. the one
+    /// containing that moment if the stream has no gaps.
+    pub fn segment_at_host_time(&self, host_time_ns: i64) -> Option<&SegmentTime> {
+        self.segments
+            .iter()
+            .filter(|segment| {
+                segment
+                    .host_time_ns
+                    .is_some_and(|time| time <= host_time_ns)
+            })
+            .max_by_key(|segment| segment.earliest_pts)
+    }
+
+    /// The index as JSON: the timescale and, per segment, the sequence
+    /// number, earliest pts, duration in milliseconds, host time in
+    /// nanoseconds and wall clock time in microseconds since the Unix epoch
+    /// (`null` where unknown).
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"timescale\":{},\"segments\":[", self.timescale);
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let duration = segment.duration.map(|d| d.as_millis());
+            let wall_clock = segment
+                .wall_clock
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_micros());
+            let _ = write!(
+                json,
+                "\n{{\"sequence_number\":{},\"earliest_pts\":{},\"duration_ms\":{},\"host_time_ns\":{},\"wall_clock_us\":{}}}",
+                segment.sequence_number,
+                segment.earliest_pts,
+                json_number(duration),
+                json_number(segment.host_time_ns),
+                json_number(wall_clock),
+            );
+        }
+        json.push_str("\n]}\n");
+        json
+    }
+
+    /// Write [`to_json`](Self::to_json) to `path`, replacing it atomically
+    /// so readers never see a partial index.
+    pub fn save_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, self.to_json())?;
+        std::fs::rename(&temporary, path)
+    }
+}
+
+fn json_number<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "null".to_string(), |value| value.to_string())
+}
+
+/// Earliest presentation time of a media segment's first track fragment,
+/// in the track timescale: its `tfdt` decode time plus the smallest
+/// composition offset of its samples. `None` without a `moof`.
+pub fn earliest_presentation_time(segment: &[u8]) -> Option<i64> {
+    let moof = find_box(segment, 0, segment.len(), b"moof")?;
+    let traf = find_box(segment, moof.content(), moof.end(), b"traf")?;
+    let tfhd = find_box(segment, traf.content(), traf.end(), b"tfhd")?;
+    let tfhd_flags = read_u32(segment, tfhd.content())? & 0x00FF_FFFF;
+    let mut field = tfhd.content() + 8;
+    if tfhd_flags & 0x01 != 0 {
+        field += 8; // base_data_offset
+    }
+    if tfhd_flags & 0x02 != 0 {
+        field += 4; // sample_description_index
+    }
+    let default_duration = if tfhd_flags & 0x08 != 0 {
+        read_u32(segment, field)?
+    } else {
+        0
+    };
+
+    let tfdt = find_box(segment, traf.content(), traf.end(), b"tfdt")?;
+    let mut dts = if *segment.get(tfdt.content())? == 1 {
+        read_u64(segment, tfdt.content() + 4)? as i64
+    } else {
+        read_u32(segment, tfdt.content() + 4)? as i64
+    };
+    let Some(trun) = find_box(segment, traf.content(), traf.end(), b"trun") else {
+        return Some(dts);
+    };
+    let flags = read_u32(segment, trun.content())? & 0x00FF_FFFF;
+    let count = read_u32(segment, trun.content() + 4)?;
+    let mut at = trun.content() + 8;
+    if flags & 0x01 != 0 {
+        at += 4; // data_offset
+    }
+    if flags & 0x04 != 0 {
+        at += 4; // first_sample_flags
+    }
+    let mut earliest: Option<i64> = None;
+    for _ in 0..count {
+        let mut next = |present: u32| -> Option<Option<u32>> {
+            if flags & present == 0 {
+                return Some(None);
+            }
+            let value = read_u32(segment, at)?;
+            at += 4;
+            Some(Some(value))
+        };
+        let duration = next(0x100)?.unwrap_or(default_duration);
+        // Sample size and flags
+        next(0x200)?;
+        next(0x400)?;
+        // Signed in version 1; version 0 offsets are positive in practice
+        let offset = next(0x800)?.map_or(0, |offset| offset as i32 as i64);
+        let pts = dts + offset;
+        earliest = Some(earliest.map_or(pts, |earliest| earliest.min(pts)));
+        dts += duration as i64;
+    }
+    Some(earliest.unwrap_or(dts))
+}
+
+/// A [`SegmentSink`] that records every segment in a [`SegmentTimeIndex`]
+/// before passing it on, optionally keeping a JSON sidecar file up to date.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{DirectorySink, SegmentTimeIndex, TimeIndexedSink};
+///
+/// let sink = TimeIndexedSink::new(DirectorySink::new("out")?, SegmentTimeIndex::new(90000))
+///     .sidecar("out/times.json");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct TimeIndexedSink<S> {
+    inner: S,
+    index: SegmentTimeIndex,
+    sidecar: Option<PathBuf>,
+}
+
+impl<S: SegmentSink> TimeIndexedSink<S> {
+    pub fn new(inner: S, index: SegmentTimeIndex) -> Self {
+        Self {
+            inner,
+            index,
+            sidecar: None,
+        }
+    }
+
+    /// Rewrite the index as JSON to `path` on every [`flush`](SegmentSink::flush).
+    pub fn sidecar(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sidecar = Some(path.into());
+        self
+    }
+
+    pub fn index(&self) -> &SegmentTimeIndex {
+        &self.index
+    }
+
+    /// The index, e.g. to [`anchor`](SegmentTimeIndex::anchor) it once the
+    /// first frame is captured.
+    pub fn index_mut(&mut self) -> &mut SegmentTimeIndex {
+        &mut self.index
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> (S, SegmentTimeIndex) {
+        (self.inner, self.index)
+    }
+}
+
+impl<S: SegmentSink> SegmentSink for TimeIndexedSink<S> {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        self.index.add_segment(segment);
+        self.inner.write_segment(segment)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        match &self.sidecar {
+            Some(path) => self.index.save_json(path),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{CmafConfig, CmafMuxer, NalUnit};
+
+    #[test]
+    fn test_segments_mapped_from_anchor() {
+        let mut muxer = CmafMuxer::new(CmafConfig {
+            fragment_duration_ms: 1000,
+            timescale: 1000,
+            ..Default::default()
+        });
+        muxer.create_init_segment(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 64, 64);
+        let mut index = SegmentTimeIndex::new(1000);
+        let wall_clock = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        index.anchor_wall_clock(0, 5_000_000_000, wall_clock);
+
+        let mut segments = Vec::new();
+        for i in 0..25i64 {
+            let keyframe = i % 10 == 0;
+            let nal = NalUnit {
+                nal_type: if keyframe { 5 } else { 1 },
+                data: vec![if keyframe { 0x65 } else { 0x61 }, i as u8],
+            };
+            // Reordered B-frames: the keyframe is not the earliest sample
+            let dts = i * 100;
+            let pts = match (keyframe, i % 2) {
+                (true, _) => dts + 100,
+                (false, 1) => dts + 200,
+                _ => dts,
+            };
+            if let Some(data) = muxer.add_frame(&[nal], pts, dts, 100, keyframe) {
+                segments.push(data);
+            }
+        }
+        assert_eq!(segments.len(), 2);
+        for (i, data) in segments.into_iter().enumerate() {
+            index.add_segment(&Segment::media(i as u32 + 1, data));
+        }
+
+        let times = index.segments();
+        assert_eq!(times[0].earliest_pts, 100);
+        assert_eq!(times[1].earliest_pts, 1100);
+        assert_eq!(times[1].host_time_ns, Some(6_100_000_000));
+        assert_eq!(
+            times[1].wall_clock,
+            Some(wall_clock + Duration::from_millis(1100))
+        );
+        let at = index.segment_at_host_time(6_000_000_000).unwrap();
+        assert_eq!(at.sequence_number, 1);
+        assert!(index.to_json().contains(
+            "\"earliest_pts\":1100,\"duration_ms\":null,\"host_time_ns\":6100000000,\"wall_clock_us\":1700000001100000}"
+        ));
+    }
+}
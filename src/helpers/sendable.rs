@@ -0,0 +1,335 @@
+//! Audited `Send`/`Sync` wrappers for raw sessions and pixel buffers.
+//!
+//! Raw VideoToolbox and CoreVideo references are plain pointers, so structs
+//! holding them are neither `Send` nor `Sync`, and it is tempting to add
+//! blanket `unsafe impl`s. These wrappers hold one reference each and state
+//! the invariants that make sharing them sound, with debug-build checks for
+//! the cases the type system cannot rule out.
+
+use core_foundation_sys::base::CFRelease;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::leak_tracker::{release_pixel_buffer, retain_pixel_buffer};
+use super::pixel_buffer::PixelBufferGuard;
+use crate::compression::{VTCompressionSessionInvalidate, VTCompressionSessionRef};
+use crate::cv_types::CVPixelBufferRef;
+use crate::decompression::{VTDecompressionSessionInvalidate, VTDecompressionSessionRef};
+use crate::session::VTSessionRef;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SessionKind {
+    Compression,
+    Decompression,
+}
+
+/// A raw compression or decompression session that can be shared between
+/// threads, e.g. from a capture callback on a dispatch queue.
+///
+/// # Invariants
+///
+/// - Clones share one reference to the session through an [`Arc`], and
+///   CoreFoundation release is thread-safe, so the last clone can be
+///   dropped on any thread.
+/// - VideoToolbox allows frames to be submitted and completed from any
+///   thread; sessions serialize that work internally.
+/// - A session must not be used after it is invalidated. Clones share the
+///   invalidated state, and [`as_raw`](Self::as_raw) asserts it in debug builds.
+///
+/// The last clone to be dropped invalidates the session if
+/// [`invalidate`](Self::invalidate) was not called.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::OnceLock;
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{CompressionSessionBuilder, SendableSession};
+///
+/// static SESSION: OnceLock<SendableSession> = OnceLock::new();
+///
+/// let raw = CompressionSessionBuilder::new(1280, 720, codecs::video::H264)
+///     .build(|_, _, _, _, _| {})
+///     .unwrap();
+/// SESSION.set(unsafe { SendableSession::from_compression(raw) }).ok();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SendableSession {
+    inner: Arc<SessionInner>,
+}
+
+/// The reference shared by every clone; invalidated and released when the
+/// last clone drops it.
+#[derive(Debug)]
+struct SessionInner {
+    raw: VTSessionRef,
+    kind: SessionKind,
+    invalidated: AtomicBool,
+}
+
+// SAFETY: see the invariants above; the wrapper owns one reference to a
+// thread-safe CoreFoundation object and only exposes it as a raw pointer.
+unsafe impl Send for SessionInner {}
+unsafe impl Sync for SessionInner {}
+
+impl SessionInner {
+    fn invalidate(&self) {
+        if self.invalidated.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        unsafe {
+            match self.kind {
+                SessionKind::Compression => VTCompressionSessionInvalidate(self.raw),
+                SessionKind::Decompression => VTDecompressionSessionInvalidate(self.raw),
+            }
+        }
+    }
+}
+
+impl Drop for SessionInner {
+    fn drop(&mut self) {
+        self.invalidate();
+        unsafe { CFRelease(self.raw) };
+    }
+}
+
+impl SendableSession {
+    /// Take ownership of one reference to a compression session.
+    ///
+    /// # Safety
+    ///
+    /// `session` must be a valid compression session whose reference is
+    /// transferred to the wrapper (it must not be released separately).
+    pub unsafe fn from_compression(session: VTCompressionSessionRef) -> Self {
+        Self::from_raw(session, SessionKind::Compression)
+    }
+
+    /// Take ownership of one reference to a decompression session.
+    ///
+    /// # Safety
+    ///
+    /// `session` must be a valid decompression session whose reference is
+    /// transferred to the wrapper (it must not be released separately).
+    pub unsafe fn from_decompression(session: VTDecompressionSessionRef) -> Self {
+        Self::from_raw(session, SessionKind::Decompression)
+    }
+
+    fn from_raw(raw: VTSessionRef, kind: SessionKind) -> Self {
+        Self {
+            inner: Arc::new(SessionInner {
+                raw,
+                kind,
+                invalidated: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// The raw session, for passing to VideoToolbox functions.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, if the session has been invalidated.
+    pub fn as_raw(&self) -> VTSessionRef {
+        debug_assert!(
+            !self.is_invalidated(),
+            "VideoToolbox session used after invalidate"
+        );
+        self.inner.raw
+    }
+
+    /// Whether this session is a compression session.
+    pub fn is_compression(&self) -> bool {
+        self.inner.kind == SessionKind::Compression
+    }
+
+    pub fn is_invalidated(&self) -> bool {
+        self.inner.invalidated.load(Ordering::Acquire)
+    }
+
+    /// Invalidate the session for every clone. Calling it again does nothing.
+    ///
+    /// Pending frames are discarded; complete them first to keep them.
+    pub fn invalidate(&self) {
+        self.inner.invalidate();
+    }
+}
+
+/// A pixel buffer reference that can be moved to and shared with other threads.
+///
+/// # Invariants
+///
+/// - CVPixelBuffer retain and release are atomic, so clones can be dropped
+///   on any thread.
+/// - Pixel data is only reachable through raw pointers from
+///   [`lock`](Self::lock), so writes are already `unsafe`. Writing from two
+///   threads at once is still a data race: debug builds panic when a buffer
+///   is locked on one thread while a lock taken through a wrapper is held on
+///   another.
+///
+/// References are taken and released with [`retain_pixel_buffer`] and
+/// [`release_pixel_buffer`], so buffers stay balanced under
+/// [leak tracking](super::LeakCheck).
+#[derive(Debug)]
+pub struct SendablePixelBuffer {
+    raw: CVPixelBufferRef,
+}
+
+// SAFETY: see the invariants above.
+unsafe impl Send for SendablePixelBuffer {}
+unsafe impl Sync for SendablePixelBuffer {}
+
+impl SendablePixelBuffer {
+    /// Take ownership of one reference to `pixel_buffer` (e.g. from
+    /// [`create_pixel_buffer`](super::create_pixel_buffer)).
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid CVPixelBuffer whose reference is
+    /// transferred to the wrapper.
+    pub unsafe fn new(pixel_buffer: CVPixelBufferRef) -> Self {
+        Self { raw: pixel_buffer }
+    }
+
+    /// Take a new reference to a borrowed pixel buffer (e.g. one handed to a
+    /// capture callback).
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid CVPixelBuffer.
+    pub unsafe fn retain(pixel_buffer: CVPixelBufferRef) -> Self {
+        Self {
+            raw: retain_pixel_buffer(pixel_buffer),
+        }
+    }
+
+    pub fn as_raw(&self) -> CVPixelBufferRef {
+        self.raw
+    }
+
+    /// Give up the wrapper without releasing the reference.
+    pub fn into_raw(self) -> CVPixelBufferRef {
+        let raw = self.raw;
+        std::mem::forget(self);
+        raw
+    }
+
+    /// Lock the pixel data for CPU access.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, if the same buffer is locked through a wrapper on
+    /// another thread.
+    pub fn lock(&self) -> Result<SendablePixelBufferLock<'_>, i32> {
+        lock_registry::acquire(self.raw as usize);
+        match unsafe { PixelBufferGuard::lock(self.raw) } {
+            Ok(guard) => Ok(SendablePixelBufferLock {
+                guard,
+                buffer: self,
+            }),
+            Err(status) => {
+                lock_registry::release(self.raw as usize);
+                Err(status)
+            }
+        }
+    }
+}
+
+impl Clone for SendablePixelBuffer {
+    fn clone(&self) -> Self {
+        unsafe { Self::retain(self.raw) }
+    }
+}
+
+impl Drop for SendablePixelBuffer {
+    fn drop(&mut self) {
+        unsafe { release_pixel_buffer(self.raw) };
+    }
+}
+
+/// CPU lock on a [`SendablePixelBuffer`], released on drop.
+pub struct SendablePixelBufferLock<'a> {
+    guard: PixelBufferGuard,
+    buffer: &'a SendablePixelBuffer,
+}
+
+impl Deref for SendablePixelBufferLock<'_> {
+    type Target = PixelBufferGuard;
+
+    fn deref(&self) -> &PixelBufferGuard {
+        &self.guard
+    }
+}
+
+impl Drop for SendablePixelBufferLock<'_> {
+    fn drop(&mut self) {
+        lock_registry::release(self.buffer.raw as usize);
+    }
+}
+
+/// Which thread holds each locked buffer (debug builds only).
+#[cfg(debug_assertions)]
+mod lock_registry {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use std::thread::{self, ThreadId};
+
+    static LOCKED: Mutex<BTreeMap<usize, (ThreadId, usize)>> = Mutex::new(BTreeMap::new());
+
+    pub(super) fn acquire(address: usize) {
+        let current = thread::current().id();
+        let mut locked = LOCKED.lock().unwrap_or_else(|e| e.into_inner());
+        let (owner, count) = locked.entry(address).or_insert((current, 0));
+        if *owner != current {
+            let owner = *owner;
+            drop(locked);
+            panic!(
+                "pixel buffer {:#x} locked on {:?} while locked on {:?}",
+                address, current, owner
+            );
+        }
+        *count += 1;
+    }
+
+    pub(super) fn release(address: usize) {
+        let mut locked = LOCKED.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, count)) = locked.get_mut(&address) {
+            *count -= 1;
+            if *count == 0 {
+                locked.remove(&address);
+            }
+        }
+    }
+}
+
+#[cfg(not(debug_assertions))]
+mod lock_registry {
+    pub(super) fn acquire(_address: usize) {}
+
+    pub(super) fn release(_address: usize) {}
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::lock_registry;
+    use std::thread;
+
+    #[test]
+    fn test_cross_thread_lock_panics() {
+        let address = 0x5000;
+        lock_registry::acquire(address);
+        lock_registry::acquire(address);
+        lock_registry::release(address);
+        assert!(thread::spawn(move || lock_registry::acquire(address))
+            .join()
+            .is_err());
+
+        lock_registry::release(address);
+        thread::spawn(move || {
+            lock_registry::acquire(address);
+            lock_registry::release(address);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,602 @@
+//! Reading back session properties as Rust values.
+
+use core_foundation::array::CFArray;
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_foundation_sys::array::CFArrayRef;
+use core_foundation_sys::base::{kCFAllocatorDefault, CFTypeRef, OSStatus};
+use core_foundation_sys::dictionary::CFDictionaryRef;
+use core_foundation_sys::number::CFNumberIsFloatType;
+use core_foundation_sys::string::CFStringRef;
+use libc::c_void;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{fmt, io, ptr};
+
+use super::cf_dict::{CFDictBuilder, DictValue};
+use crate::session::{
+    kVTPropertyDocumentationKey, kVTPropertyReadWriteStatusKey,
+    kVTPropertyReadWriteStatus_ReadOnly, kVTPropertyShouldBeSerializedKey,
+    kVTPropertySupportedValueListKey, kVTPropertySupportedValueMaximumKey,
+    kVTPropertySupportedValueMinimumKey, kVTPropertyTypeKey, kVTPropertyType_Boolean,
+    kVTPropertyType_Enumeration, kVTPropertyType_Number, VTSessionCopyProperty,
+    VTSessionCopySerializableProperties, VTSessionCopySupportedPropertyDictionary, VTSessionRef,
+    VTSessionSetProperties,
+};
+
+/// A property value converted from its Core Foundation type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    /// A CFNumber holding an integer
+    Int(i64),
+    /// A CFNumber holding a floating point value
+    Float(f64),
+    /// A CFString, including enumeration values such as profile levels
+    String(String),
+    /// A CFArray of convertible values, e.g. data rate limits
+    Array(Vec<PropertyValue>),
+}
+
+impl PropertyValue {
+    /// Convert a CFBoolean, CFNumber, CFString or an array of them; `None`
+    /// for other types (dictionaries, pixel buffers, ...).
+    pub fn from_cf(value: &CFType) -> Option<Self> {
+        if let Some(boolean) = value.downcast::<CFBoolean>() {
+            return Some(Self::Bool(boolean.into()));
+        }
+        if let Some(number) = value.downcast::<CFNumber>() {
+            let is_float = unsafe { CFNumberIsFloatType(number.as_concrete_TypeRef()) } != 0;
+            return if is_float {
+                number.to_f64().map(Self::Float)
+            } else {
+                number.to_i64().map(Self::Int)
+            };
+        }
+        if value.type_of() == CFArray::<CFType>::type_id() {
+            let array = unsafe {
+                CFArray::<CFType>::wrap_under_get_rule(value.as_CFTypeRef() as CFArrayRef)
+            };
+            return array
+                .iter()
+                .map(|item| Self::from_cf(&item))
+                .collect::<Option<Vec<_>>>()
+                .map(Self::Array);
+        }
+        value
+            .downcast::<CFString>()
+            .map(|string| Self::String(string.to_string()))
+    }
+
+    /// Parse the text written by `Display`, e.g. `true`, `30`, `29.97`,
+    /// `"H264_High_AutoLevel"` or `[1000000, 1.0]`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (value, rest) = parse_value(text)?;
+        rest.trim().is_empty().then_some(value)
+    }
+}
+
+impl DictValue for PropertyValue {
+    fn to_cf_type(&self) -> CFType {
+        match self {
+            Self::Bool(b) => b.to_cf_type(),
+            Self::Int(i) => i.to_cf_type(),
+            Self::Float(f) => f.to_cf_type(),
+            Self::String(s) => s.to_cf_type(),
+            Self::Array(items) => {
+                let items: Vec<CFType> = items.iter().map(DictValue::to_cf_type).collect();
+                CFArray::from_CFTypes(&items).as_CFType()
+            }
+        }
+    }
+}
+
+impl fmt::Display for PropertyValue {
+    /// Floats always have a decimal point or exponent, and strings are
+    /// quoted, so the text parses back to the same variant.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Int(i) => write!(f, "{}", i),
+            Self::Float(x) => write!(f, "{:?}", x),
+            Self::String(s) => {
+                f.write_str("\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        '\n' => f.write_str("\\n")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Self::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+/// Parse one value from the start of `text`, returning it and the rest.
+fn parse_value(text: &str) -> Option<(PropertyValue, &str)> {
+    let text = text.trim_start();
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Some((PropertyValue::Array(items), after));
+            }
+            if !items.is_empty() {
+                rest = rest.strip_prefix(',')?;
+            }
+            let (item, after) = parse_value(rest)?;
+            items.push(item);
+            rest = after;
+        }
+    }
+    if let Some(quoted) = text.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = quoted.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Some((PropertyValue::String(string), &quoted[i + 1..])),
+                '\\' => match chars.next()?.1 {
+                    'n' => string.push('\n'),
+                    escaped => string.push(escaped),
+                },
+                c => string.push(c),
+            }
+        }
+        return None;
+    }
+    let end = text.find([',', ']']).unwrap_or(text.len());
+    let (token, rest) = (text[..end].trim(), &text[end..]);
+    let value = match token {
+        "true" => PropertyValue::Bool(true),
+        "false" => PropertyValue::Bool(false),
+        _ => match token.parse::<i64>() {
+            Ok(i) => PropertyValue::Int(i),
+            Err(_) => PropertyValue::Float(token.parse().ok()?),
+        },
+    };
+    Some((value, rest))
+}
+
+/// Rust types a [`PropertyValue`] can be read as.
+///
+/// Numbers convert between integer and floating point when no precision is
+/// lost, since encoders are not consistent about which they report.
+pub trait FromPropertyValue: Sized {
+    fn from_property_value(value: PropertyValue) -> Option<Self>;
+}
+
+impl FromPropertyValue for PropertyValue {
+    fn from_property_value(value: PropertyValue) -> Option<Self> {
+        Some(value)
+    }
+}
+
+impl FromPropertyValue for bool {
+    fn from_property_value(value: PropertyValue) -> Option<Self> {
+        match value {
+            PropertyValue::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for i64 {
+    fn from_property_value(value: PropertyValue) -> Option<Self> {
+        match value {
+            PropertyValue::Int(i) => Some(i),
+            PropertyValue::Float(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+                Some(f as i64)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for i32 {
+    fn from_property_value(value: PropertyValue) -> Option<Self> {
+        i64::from_property_value(value).and_then(|i| i32::try_from(i).ok())
+    }
+}
+
+impl FromPropertyValue for f64 {
+    fn from_property_value(value: PropertyValue) -> Option<Self> {
+        match value {
+            PropertyValue::Float(f) => Some(f),
+            PropertyValue::Int(i) => Some(i as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for String {
+    fn from_property_value(value: PropertyValue) -> Option<Self> {
+        match value {
+            PropertyValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// The `kVTPropertyTypeKey` of a supported property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyType {
+    /// One of the strings in [`PropertyInfo::supported_values`]
+    Enumeration,
+    Boolean,
+    Number,
+    /// Dictionaries, arrays and other types the session does not describe
+    Other,
+}
+
+/// What a session reports about one of its properties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyInfo {
+    pub kind: PropertyType,
+    pub read_only: bool,
+    /// Whether the property is part of the session's serializable
+    /// configuration
+    pub should_be_serialized: bool,
+    pub minimum: Option<PropertyValue>,
+    pub maximum: Option<PropertyValue>,
+    /// Accepted values, for enumerations and some numbers
+    pub supported_values: Vec<PropertyValue>,
+    pub documentation: Option<String>,
+}
+
+/// Read property `key` of `session` as `T`, e.g. the average bitrate an
+/// encoder settled on or whether it is hardware accelerated.
+///
+/// Returns `Ok(None)` if the session has no value for the key or the value
+/// does not convert to `T`, and the session's error if it does not support
+/// the key (`kVTPropertyNotSupportedErr`).
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::compression::kVTCompressionPropertyKey_AverageBitRate;
+/// use video_toolbox_sys::helpers::get_property;
+/// # let session = std::ptr::null();
+///
+/// let bitrate: Option<i64> =
+///     unsafe { get_property(session, kVTCompressionPropertyKey_AverageBitRate)? };
+/// # Ok::<(), i32>(())
+/// ```
+///
+/// # Safety
+///
+/// `session` must be a valid, not invalidated VideoToolbox session and
+/// `key` a valid CFString.
+pub unsafe fn get_property<T: FromPropertyValue>(
+    session: VTSessionRef,
+    key: CFStringRef,
+) -> Result<Option<T>, OSStatus> {
+    let mut value: CFTypeRef = ptr::null();
+    let status = VTSessionCopyProperty(
+        session,
+        key,
+        kCFAllocatorDefault,
+        &mut value as *mut CFTypeRef as *mut c_void,
+    );
+    crate::errors::status_to_result(status)?;
+    if value.is_null() {
+        return Ok(None);
+    }
+    let value = CFType::wrap_under_create_rule(value);
+    Ok(PropertyValue::from_cf(&value).and_then(T::from_property_value))
+}
+
+/// Every property `session` supports, keyed by property name (e.g.
+/// `"AverageBitRate"`), with its type, access and accepted range.
+///
+/// # Safety
+///
+/// `session` must be a valid, not invalidated VideoToolbox session.
+pub unsafe fn copy_supported_property_dictionary(
+    session: VTSessionRef,
+) -> Result<BTreeMap<String, PropertyInfo>, OSStatus> {
+    let mut dictionary: CFDictionaryRef = ptr::null();
+    crate::errors::status_to_result(VTSessionCopySupportedPropertyDictionary(
+        session,
+        &mut dictionary,
+    ))?;
+    if dictionary.is_null() {
+        return Ok(BTreeMap::new());
+    }
+    let dictionary = CFDictionary::<CFString, CFType>::wrap_under_create_rule(dictionary);
+    let (keys, values) = dictionary.get_keys_and_values();
+    let mut properties = BTreeMap::new();
+    for (key, value) in keys.into_iter().zip(values) {
+        if key.is_null() || value.is_null() {
+            continue;
+        }
+        let name = CFString::wrap_under_get_rule(key as CFStringRef).to_string();
+        let value = CFType::wrap_under_get_rule(value);
+        if let Some(attributes) = as_dictionary(&value) {
+            properties.insert(name, property_info(&attributes));
+        }
+    }
+    Ok(properties)
+}
+
+/// A session's serializable configuration, keyed by property name (e.g.
+/// `"AverageBitRate"`), that can be saved and applied to another session.
+///
+/// Stored as a text file of `key value` lines, with values as written by
+/// [`PropertyValue`]'s `Display` (`true`, `30`, `29.97`, `"text"`,
+/// `[1000000, 1.0]`).
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{CompressionSessionBuilder, PropertyBag};
+///
+/// let builder = || CompressionSessionBuilder::new(1920, 1080, codecs::video::H264);
+/// let tuned = builder().bitrate(6_000_000).build_session(|_output| {})?;
+/// tuned.export_settings()?.save("encoder.settings").expect("failed to save settings");
+///
+/// // Later, or on another machine
+/// let settings = PropertyBag::load("encoder.settings").expect("failed to load settings");
+/// let session = builder().build_session(|_output| {})?;
+/// session.apply_settings(&settings)?;
+/// # Ok::<(), i32>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PropertyBag {
+    properties: BTreeMap<String, PropertyValue>,
+}
+
+impl PropertyBag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&PropertyValue> {
+        self.properties.get(key)
+    }
+
+    /// Add or replace a property, e.g. to adjust a saved configuration.
+    pub fn insert(&mut self, key: impl Into<String>, value: PropertyValue) {
+        self.properties.insert(key.into(), value);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<PropertyValue> {
+        self.properties.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &PropertyValue)> {
+        self.properties
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.properties.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty()
+    }
+
+    /// The properties as a dictionary for `VTSessionSetProperties`.
+    pub fn to_dictionary(&self) -> CFDictionary<CFString, CFType> {
+        let mut builder = CFDictBuilder::new();
+        for (key, value) in &self.properties {
+            builder.insert(key, value);
+        }
+        builder.build()
+    }
+
+    /// Parse the text format. Returns the offending line number on error.
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let mut bag = Self::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').ok_or(line_no + 1)?;
+            let value = PropertyValue::parse(value).ok_or(line_no + 1)?;
+            bag.insert(key, value);
+        }
+        Ok(bag)
+    }
+
+    /// Load a file written by [`save`](Self::save).
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|line| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid session property at line {}", line),
+            )
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for PropertyBag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.properties {
+            writeln!(f, "{} {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The serializable properties of `session`: its configuration, without
+/// read-only state. Values that are not booleans, numbers, strings or
+/// arrays of them (e.g. dictionaries) are left out.
+///
+/// # Safety
+///
+/// `session` must be a valid, not invalidated VideoToolbox session.
+pub unsafe fn copy_serializable_properties(session: VTSessionRef) -> Result<PropertyBag, OSStatus> {
+    let mut dictionary: CFDictionaryRef = ptr::null();
+    crate::errors::status_to_result(VTSessionCopySerializableProperties(
+        session,
+        kCFAllocatorDefault,
+        &mut dictionary,
+    ))?;
+    let mut bag = PropertyBag::new();
+    if dictionary.is_null() {
+        return Ok(bag);
+    }
+    let dictionary = CFDictionary::<CFString, CFType>::wrap_under_create_rule(dictionary);
+    let (keys, values) = dictionary.get_keys_and_values();
+    for (key, value) in keys.into_iter().zip(values) {
+        if key.is_null() || value.is_null() {
+            continue;
+        }
+        let name = CFString::wrap_under_get_rule(key as CFStringRef).to_string();
+        if let Some(value) = PropertyValue::from_cf(&CFType::wrap_under_get_rule(value)) {
+            bag.insert(name, value);
+        }
+    }
+    Ok(bag)
+}
+
+/// Set every property in `properties` on `session` in one call.
+///
+/// # Safety
+///
+/// `session` must be a valid, not invalidated VideoToolbox session.
+pub unsafe fn set_properties(
+    session: VTSessionRef,
+    properties: &PropertyBag,
+) -> Result<(), OSStatus> {
+    let dictionary = properties.to_dictionary();
+    crate::errors::status_to_result(VTSessionSetProperties(
+        session,
+        dictionary.as_concrete_TypeRef(),
+    ))
+}
+
+unsafe fn property_info(attributes: &CFDictionary<CFString, CFType>) -> PropertyInfo {
+    let get = |key: CFStringRef| attributes.find(CFString::wrap_under_get_rule(key));
+    let is = |value: Option<&CFType>, expected: CFStringRef| {
+        value
+            .and_then(|value| value.downcast::<CFString>())
+            .is_some_and(|value| value == CFString::wrap_under_get_rule(expected))
+    };
+    let kind_value = get(kVTPropertyTypeKey);
+    let kind = if is(kind_value.as_deref(), kVTPropertyType_Enumeration) {
+        PropertyType::Enumeration
+    } else if is(kind_value.as_deref(), kVTPropertyType_Boolean) {
+        PropertyType::Boolean
+    } else if is(kind_value.as_deref(), kVTPropertyType_Number) {
+        PropertyType::Number
+    } else {
+        PropertyType::Other
+    };
+    let supported_values = get(kVTPropertySupportedValueListKey)
+        .filter(|list| list.type_of() == CFArray::<CFType>::type_id())
+        .map(|list| {
+            CFArray::<CFType>::wrap_under_get_rule(list.as_CFTypeRef() as CFArrayRef)
+                .iter()
+                .filter_map(|value| PropertyValue::from_cf(&value))
+                .collect()
+        })
+        .unwrap_or_default();
+    PropertyInfo {
+        kind,
+        read_only: is(
+            get(kVTPropertyReadWriteStatusKey).as_deref(),
+            kVTPropertyReadWriteStatus_ReadOnly,
+        ),
+        should_be_serialized: get(kVTPropertyShouldBeSerializedKey)
+            .and_then(|value| value.downcast::<CFBoolean>())
+            .is_some_and(bool::from),
+        minimum: get(kVTPropertySupportedValueMinimumKey)
+            .and_then(|value| PropertyValue::from_cf(&value)),
+        maximum: get(kVTPropertySupportedValueMaximumKey)
+            .and_then(|value| PropertyValue::from_cf(&value)),
+        supported_values,
+        documentation: get(kVTPropertyDocumentationKey)
+            .and_then(|value| value.downcast::<CFString>())
+            .map(|value| value.to_string()),
+    }
+}
+
+fn as_dictionary(value: &CFType) -> Option<CFDictionary<CFString, CFType>> {
+    if value.type_of() != CFDictionary::<CFString, CFType>::type_id() {
+        return None;
+    }
+    Some(unsafe { CFDictionary::wrap_under_get_rule(value.as_CFTypeRef() as CFDictionaryRef) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_property_bag_text_round_trip() {
+        let mut bag = PropertyBag::new();
+        bag.insert("AverageBitRate", PropertyValue::Int(6_000_000));
+        bag.insert("ExpectedFrameRate", PropertyValue::Float(30.0));
+        bag.insert("RealTime", PropertyValue::Bool(false));
+        bag.insert(
+            "ProfileLevel",
+            PropertyValue::String("H264_\"High\"\\Auto".into()),
+        );
+        bag.insert(
+            "DataRateLimits",
+            PropertyValue::Array(vec![PropertyValue::Int(750_000), PropertyValue::Float(1.0)]),
+        );
+        bag.insert("Empty", PropertyValue::Array(Vec::new()));
+
+        let text = bag.to_string();
+        assert!(text.contains("ExpectedFrameRate 30.0\n"));
+        assert!(text.contains("DataRateLimits [750000, 1.0]\n"));
+        assert_eq!(PropertyBag::parse(&text), Ok(bag));
+
+        assert_eq!(
+            PropertyBag::parse("# saved\n\nQuality 0.5\n")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(PropertyBag::parse("Quality\n"), Err(1));
+        assert_eq!(PropertyBag::parse("A 1\nB [1, 2\n"), Err(2));
+        assert_eq!(PropertyBag::parse("A \"unterminated\n"), Err(1));
+    }
+
+    #[test]
+    fn test_numbers_convert_without_losing_precision() {
+        assert_eq!(
+            i64::from_property_value(PropertyValue::Int(5_000_000)),
+            Some(5_000_000)
+        );
+        assert_eq!(
+            i64::from_property_value(PropertyValue::Float(30.0)),
+            Some(30)
+        );
+        assert_eq!(i64::from_property_value(PropertyValue::Float(29.97)), None);
+        assert_eq!(f64::from_property_value(PropertyValue::Int(2)), Some(2.0));
+        assert_eq!(i32::from_property_value(PropertyValue::Int(i64::MAX)), None);
+        assert_eq!(bool::from_property_value(PropertyValue::Int(1)), None);
+        assert_eq!(
+            String::from_property_value(PropertyValue::String("H264_High_AutoLevel".into())),
+            Some("H264_High_AutoLevel".to_string())
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! Token-bucket bandwidth shaping with latency and jitter for transport tests.
+
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::lossy_sink::SplitMix64;
+use super::sink::{Segment, SegmentSink};
+
+/// Link parameters for a [`ShapedSink`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaperConfig {
+    /// Sustained throughput in bits per second
+    pub rate_bps: u64,
+    /// Token bucket size: bytes that may be sent at once after the link has
+    /// been idle
+    pub burst_bytes: u64,
+    /// Fixed delay added to every segment
+    pub latency: Duration,
+    /// Maximum random delay added on top of `latency`
+    pub jitter: Duration,
+    /// Jitter generator seed
+    pub seed: u64,
+}
+
+impl Default for ShaperConfig {
+    fn default() -> Self {
+        Self {
+            rate_bps: 5_000_000,
+            burst_bytes: 64 * 1024,
+            latency: Duration::from_millis(20),
+            jitter: Duration::ZERO,
+            seed: 0,
+        }
+    }
+}
+
+/// How a [`ShapedSink`] keeps time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShaperClock {
+    /// Wall-clock time: `write_segment` blocks until the segment has arrived.
+    #[default]
+    Real,
+    /// Virtual time that advances by each media segment's duration (or by
+    /// [`ShapedSink::advance`]); writes return immediately, so tests run as
+    /// fast as the CPU allows and give the same result every time.
+    Simulated,
+}
+
+/// Timing of one segment over the shaped link, relative to the sink's creation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delivery {
+    pub sequence_number: u32,
+    pub bytes: usize,
+    /// When the segment was handed to the sink
+    pub submitted: Duration,
+    /// When the link started sending it (later than `submitted` while an
+    /// earlier segment is still in flight)
+    pub started: Duration,
+    /// When it reached the inner sink
+    pub arrived: Duration,
+}
+
+impl Delivery {
+    /// Time from the start of sending to arrival, as a client measures it.
+    pub fn transfer_time(&self) -> Duration {
+        self.arrived - self.started
+    }
+
+    /// Throughput a client would measure for this segment.
+    pub fn throughput_bps(&self) -> f64 {
+        let secs = self.transfer_time().as_secs_f64();
+        if secs > 0.0 {
+            self.bytes as f64 * 8.0 / secs
+        } else {
+            f64::INFINITY
+        }
+    }
+}
+
+type DeliveryFn = Box<dyn FnMut(&Delivery) + Send>;
+
+/// A [`SegmentSink`] wrapper that limits throughput with a token bucket and
+/// delays segments by a latency plus seeded jitter.
+///
+/// Segments are sent one at a time, like sequential uploads: each starts once
+/// the previous one has arrived, waits for enough tokens, then takes
+/// [`latency`](ShaperConfig::latency) plus up to
+/// [`jitter`](ShaperConfig::jitter) more. [`set_rate_bps`](Self::set_rate_bps)
+/// changes the rate mid-stream to simulate a network getting worse.
+///
+/// Each [`Delivery`] is passed to the [`on_delivery`](Self::on_delivery) hook,
+/// which is where an adaptive bitrate controller under test gets its
+/// throughput samples.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{DirectorySink, ShapedSink, ShaperClock, ShaperConfig};
+///
+/// let config = ShaperConfig {
+///     rate_bps: 2_000_000,
+///     ..Default::default()
+/// };
+/// let sink = ShapedSink::new(DirectorySink::new("shaped")?, config)
+///     .clock(ShaperClock::Simulated)
+///     .on_delivery(|delivery| println!("{:.0} bps", delivery.throughput_bps()));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct ShapedSink<S> {
+    inner: S,
+    config: ShaperConfig,
+    clock: ShaperClock,
+    created: Instant,
+    simulated_now: Duration,
+    rng: SplitMix64,
+    tokens: f64,
+    tokens_at: Duration,
+    link_free: Duration,
+    deliveries: Vec<Delivery>,
+    on_delivery: Option<DeliveryFn>,
+}
+
+impl<S: SegmentSink> ShapedSink<S> {
+    pub fn new(inner: S, config: ShaperConfig) -> Self {
+        let rng = SplitMix64(config.seed);
+        let tokens = config.burst_bytes as f64;
+        Self {
+            inner,
+            config,
+            clock: ShaperClock::Real,
+            created: Instant::now(),
+            simulated_now: Duration::ZERO,
+            rng,
+            tokens,
+            tokens_at: Duration::ZERO,
+            link_free: Duration::ZERO,
+            deliveries: Vec::new(),
+            on_delivery: None,
+        }
+    }
+
+    pub fn clock(mut self, clock: ShaperClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Call `hook` with the timing of each segment once it has arrived.
+    pub fn on_delivery<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&Delivery) + Send + 'static,
+    {
+        self.on_delivery = Some(Box::new(hook));
+        self
+    }
+
+    pub fn config(&self) -> &ShaperConfig {
+        &self.config
+    }
+
+    /// Change the sustained rate; segments already sent are unaffected.
+    pub fn set_rate_bps(&mut self, rate_bps: u64) {
+        let now = self.now();
+        self.refill(now);
+        self.config.rate_bps = rate_bps;
+    }
+
+    /// Move the simulated clock forward, e.g. for time spent without
+    /// writing. Has no effect with [`ShaperClock::Real`].
+    pub fn advance(&mut self, elapsed: Duration) {
+        self.simulated_now += elapsed;
+    }
+
+    /// Current time relative to the sink's creation.
+    pub fn now(&self) -> Duration {
+        match self.clock {
+            ShaperClock::Real => self.created.elapsed(),
+            ShaperClock::Simulated => self.simulated_now,
+        }
+    }
+
+    /// Every delivery so far, in order.
+    pub fn deliveries(&self) -> &[Delivery] {
+        &self.deliveries
+    }
+
+    /// Overall throughput: bytes delivered over the time the link was busy.
+    pub fn average_throughput_bps(&self) -> f64 {
+        let bytes: usize = self.deliveries.iter().map(|d| d.bytes).sum();
+        let busy: Duration = self.deliveries.iter().map(Delivery::transfer_time).sum();
+        if busy.is_zero() {
+            return 0.0;
+        }
+        bytes as f64 * 8.0 / busy.as_secs_f64()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn bytes_per_sec(&self) -> f64 {
+        (self.config.rate_bps.max(1) as f64) / 8.0
+    }
+
+    fn refill(&mut self, at: Duration) {
+        if at > self.tokens_at {
+            let earned = (at - self.tokens_at).as_secs_f64() * self.bytes_per_sec();
+            self.tokens = (self.tokens + earned).min(self.config.burst_bytes as f64);
+            self.tokens_at = at;
+        }
+    }
+
+    fn jitter(&mut self) -> Duration {
+        if self.config.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        self.config.jitter.mul_f64(self.rng.next_f64())
+    }
+
+    /// Compute the timing of a segment of `bytes` submitted at `submitted`.
+    fn schedule(&mut self, sequence_number: u32, bytes: usize, submitted: Duration) -> Delivery {
+        let started = submitted.max(self.link_free);
+        self.refill(started);
+        let deficit = bytes as f64 - self.tokens;
+        let sent = if deficit > 0.0 {
+            let wait = Duration::from_secs_f64(deficit / self.bytes_per_sec());
+            self.tokens = 0.0;
+            self.tokens_at = started + wait;
+            started + wait
+        } else {
+            self.tokens -= bytes as f64;
+            started
+        };
+        let arrived = sent + self.config.latency + self.jitter();
+        self.link_free = arrived;
+        Delivery {
+            sequence_number,
+            bytes,
+            submitted,
+            started,
+            arrived,
+        }
+    }
+}
+
+impl<S: SegmentSink> SegmentSink for ShapedSink<S> {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        let submitted = self.now();
+        let delivery = self.schedule(segment.sequence_number, segment.data.len(), submitted);
+        match self.clock {
+            ShaperClock::Real => {
+                if let Some(wait) = delivery.arrived.checked_sub(self.now()) {
+                    thread::sleep(wait);
+                }
+            }
+            ShaperClock::Simulated => {
+                if let Some(duration) = segment.duration {
+                    self.simulated_now += duration;
+                }
+            }
+        }
+        self.inner.write_segment(segment)?;
+        if let Some(hook) = self.on_delivery.as_mut() {
+            hook(&delivery);
+        }
+        self.deliveries.push(delivery);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Discard;
+
+    impl SegmentSink for Discard {
+        fn write_segment(&mut self, _segment: &Segment) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn media(sequence_number: u32, bytes: usize) -> Segment {
+        Segment::media(sequence_number, vec![0; bytes]).with_duration(Duration::from_secs(1))
+    }
+
+    #[test]
+    fn test_token_bucket_and_latency() {
+        let config = ShaperConfig {
+            rate_bps: 800_000,
+            burst_bytes: 50_000,
+            latency: Duration::from_millis(30),
+            ..Default::default()
+        };
+        let mut sink = ShapedSink::new(Discard, config).clock(ShaperClock::Simulated);
+
+        // Fits in the full bucket: only the latency
+        sink.write_segment(&media(1, 50_000)).unwrap();
+        // 100 kB/s refills the bucket in the following second, so 150 kB
+        // takes one more second
+        sink.write_segment(&media(2, 150_000)).unwrap();
+        // Still sending segment 2 when segment 3 is submitted
+        sink.write_segment(&media(3, 10_000)).unwrap();
+
+        let d = sink.deliveries();
+        assert_eq!(d[0].arrived, Duration::from_millis(30));
+        assert_eq!(d[1].started, Duration::from_secs(1));
+        assert_eq!(d[1].arrived, Duration::from_millis(2030));
+        assert_eq!(d[2].submitted, Duration::from_secs(2));
+        assert_eq!(d[2].started, Duration::from_millis(2030));
+        assert!((d[1].throughput_bps() - 150_000.0 * 8.0 / 1.03).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_rate_change_and_seeded_jitter() {
+        let config = ShaperConfig {
+            rate_bps: 8_000_000,
+            burst_bytes: 0,
+            latency: Duration::ZERO,
+            jitter: Duration::from_millis(50),
+            seed: 3,
+        };
+        let run = |config: ShaperConfig| {
+            let mut sink = ShapedSink::new(Discard, config).clock(ShaperClock::Simulated);
+            for seq in 1..=4 {
+                sink.write_segment(&media(seq, 100_000)).unwrap();
+            }
+            sink.set_rate_bps(800_000);
+            sink.write_segment(&media(5, 100_000)).unwrap();
+            sink.deliveries().to_vec()
+        };
+        let a = run(config.clone());
+        assert_eq!(a, run(config));
+        for delivery in &a[..4] {
+            let jitter = delivery.transfer_time() - Duration::from_millis(100);
+            assert!(jitter <= Duration::from_millis(50));
+        }
+        assert!(a[4].transfer_time() >= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,247 @@
+//! Zero-copy frame handoff between processes through IOSurfaces.
+//!
+//! A capture process exports each frame's IOSurface as a Mach port, sends
+//! the port over its IPC channel (XPC, a bootstrap service, ...), and the
+//! encoding process wraps the surface as a CVPixelBuffer again. Both sides
+//! map the same memory; nothing is copied.
+
+use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease, CFRetain};
+use libc::c_void;
+use std::ptr;
+
+use super::leak_tracker::{track, TrackedKind};
+use super::sendable::SendablePixelBuffer;
+use crate::cv_types::{
+    kCVReturnSuccess, CVPixelBufferCreateWithIOSurface, CVPixelBufferGetIOSurface, CVPixelBufferRef,
+};
+use crate::io_surface::{
+    mach_port_release, mach_port_t, IOSurfaceCreateMachPort, IOSurfaceDecrementUseCount,
+    IOSurfaceGetHeight, IOSurfaceGetID, IOSurfaceGetPixelFormat, IOSurfaceGetUseCount,
+    IOSurfaceGetWidth, IOSurfaceID, IOSurfaceIncrementUseCount, IOSurfaceIsInUse, IOSurfaceLookup,
+    IOSurfaceLookupFromMachPort, IOSurfaceRef, MACH_PORT_NULL,
+};
+
+/// An owned Mach send right, deallocated when dropped.
+///
+/// Use [`into_raw`](Self::into_raw) when the transport takes ownership of
+/// the right (e.g. a `mach_msg` with a move disposition); keep the wrapper
+/// alive until the send completes when it copies the right.
+#[derive(Debug)]
+pub struct MachPort {
+    raw: mach_port_t,
+}
+
+impl MachPort {
+    /// Take ownership of a send right, e.g. one received from another process.
+    ///
+    /// # Safety
+    ///
+    /// `port` must be a send right owned by the caller, not deallocated
+    /// elsewhere.
+    pub unsafe fn from_raw(port: mach_port_t) -> Self {
+        Self { raw: port }
+    }
+
+    pub fn as_raw(&self) -> mach_port_t {
+        self.raw
+    }
+
+    /// Give up the wrapper without deallocating the right.
+    pub fn into_raw(self) -> mach_port_t {
+        let raw = self.raw;
+        std::mem::forget(self);
+        raw
+    }
+}
+
+impl Drop for MachPort {
+    fn drop(&mut self) {
+        if self.raw != MACH_PORT_NULL {
+            unsafe { mach_port_release(self.raw) };
+        }
+    }
+}
+
+/// An IOSurface held for cross-process use.
+///
+/// Holding one keeps the surface's system-wide use count raised, so the
+/// producer's pixel buffer pool does not hand the memory out for a new
+/// frame while the other process is still reading it. Drop it (and any
+/// pixel buffers made from it) once the frame is encoded.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{MachPort, SendablePixelBuffer, SharedSurface};
+/// # fn send_port(_port: &MachPort) {}
+/// # fn receive_port() -> MachPort { unimplemented!() }
+/// # let frame: SendablePixelBuffer = unimplemented!();
+///
+/// // Capture process: frames must be IOSurface-backed, e.g. from a PixelBufferPool
+/// let surface = SharedSurface::from_pixel_buffer(&frame).expect("not IOSurface-backed");
+/// let port = surface.create_mach_port().expect("failed to create port");
+/// send_port(&port);
+///
+/// // Encoding process
+/// let surface = SharedSurface::from_mach_port(&receive_port()).expect("invalid port");
+/// let pixel_buffer = surface.to_pixel_buffer()?;
+/// // encode pixel_buffer.as_raw(), then drop both to release the frame
+/// # Ok::<(), i32>(())
+/// ```
+#[derive(Debug)]
+pub struct SharedSurface {
+    raw: IOSurfaceRef,
+}
+
+// SAFETY: IOSurface retain/release and use count updates are atomic and
+// the wrapper only exposes the surface's immutable properties.
+unsafe impl Send for SharedSurface {}
+unsafe impl Sync for SharedSurface {}
+
+impl SharedSurface {
+    /// Take ownership of one reference to a surface, raising its use count.
+    ///
+    /// # Safety
+    ///
+    /// `surface` must be a valid IOSurface whose reference is transferred
+    /// to the wrapper.
+    pub unsafe fn from_raw(surface: IOSurfaceRef) -> Self {
+        IOSurfaceIncrementUseCount(surface);
+        Self { raw: surface }
+    }
+
+    /// The surface backing a pixel buffer; `None` if it is not
+    /// IOSurface-backed (buffers need `kCVPixelBufferIOSurfacePropertiesKey`,
+    /// as every [`PixelBufferPool`](super::PixelBufferPool) buffer has).
+    pub fn from_pixel_buffer(pixel_buffer: &SendablePixelBuffer) -> Option<Self> {
+        unsafe {
+            let surface = CVPixelBufferGetIOSurface(pixel_buffer.as_raw());
+            if surface.is_null() {
+                return None;
+            }
+            CFRetain(surface);
+            Some(Self::from_raw(surface))
+        }
+    }
+
+    /// The surface behind a send right from [`create_mach_port`](Self::create_mach_port).
+    /// The port stays owned by the caller.
+    pub fn from_mach_port(port: &MachPort) -> Option<Self> {
+        let surface = unsafe { IOSurfaceLookupFromMachPort(port.raw) };
+        (!surface.is_null()).then(|| unsafe { Self::from_raw(surface) })
+    }
+
+    /// Look up a surface by [`id`](Self::id). This only finds surfaces the
+    /// process can already see, e.g. ones it created or received through a
+    /// Mach port before, so a receiver can cache surfaces by ID and only
+    /// needs a port the first time a pool buffer comes around.
+    pub fn lookup(id: IOSurfaceID) -> Option<Self> {
+        let surface = unsafe { IOSurfaceLookup(id) };
+        (!surface.is_null()).then(|| unsafe { Self::from_raw(surface) })
+    }
+
+    /// Create a send right to pass to another process.
+    pub fn create_mach_port(&self) -> Option<MachPort> {
+        let port = unsafe { IOSurfaceCreateMachPort(self.raw) };
+        (port != MACH_PORT_NULL).then_some(MachPort { raw: port })
+    }
+
+    /// The surface's system-wide ID.
+    pub fn id(&self) -> IOSurfaceID {
+        unsafe { IOSurfaceGetID(self.raw) }
+    }
+
+    pub fn width(&self) -> usize {
+        unsafe { IOSurfaceGetWidth(self.raw) }
+    }
+
+    pub fn height(&self) -> usize {
+        unsafe { IOSurfaceGetHeight(self.raw) }
+    }
+
+    /// Pixel format (FourCC).
+    pub fn pixel_format(&self) -> u32 {
+        unsafe { IOSurfaceGetPixelFormat(self.raw) }
+    }
+
+    /// Holders of the surface across all processes, including this one.
+    pub fn use_count(&self) -> i32 {
+        unsafe { IOSurfaceGetUseCount(self.raw) }
+    }
+
+    /// Whether any process still uses the surface.
+    pub fn is_in_use(&self) -> bool {
+        unsafe { IOSurfaceIsInUse(self.raw) != 0 }
+    }
+
+    /// Wrap the surface as a pixel buffer, e.g. to encode it. The buffer
+    /// keeps the surface alive on its own.
+    pub fn to_pixel_buffer(&self) -> Result<SendablePixelBuffer, i32> {
+        let mut buffer: CVPixelBufferRef = ptr::null_mut();
+        let status = unsafe {
+            CVPixelBufferCreateWithIOSurface(
+                kCFAllocatorDefault,
+                self.raw,
+                ptr::null(),
+                &mut buffer,
+            )
+        };
+        if status != kCVReturnSuccess {
+            return Err(status);
+        }
+        track(TrackedKind::PixelBuffer, buffer as *const c_void);
+        Ok(unsafe { SendablePixelBuffer::new(buffer) })
+    }
+
+    pub fn as_raw(&self) -> IOSurfaceRef {
+        self.raw
+    }
+}
+
+impl Clone for SharedSurface {
+    fn clone(&self) -> Self {
+        unsafe {
+            CFRetain(self.raw);
+            Self::from_raw(self.raw)
+        }
+    }
+}
+
+impl Drop for SharedSurface {
+    fn drop(&mut self) {
+        unsafe {
+            IOSurfaceDecrementUseCount(self.raw);
+            CFRelease(self.raw);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs;
+    use crate::helpers::{PixelBufferConfig, PixelBufferPool};
+
+    #[test]
+    fn test_surface_round_trip_through_mach_port() {
+        let config = PixelBufferConfig::new(64, 32).pixel_format(codecs::pixel::BGRA32);
+        let pool = PixelBufferPool::new(&config, 1).unwrap();
+        let frame = pool.acquire().unwrap();
+
+        let surface = SharedSurface::from_pixel_buffer(&frame).unwrap();
+        assert!(surface.is_in_use());
+        let port = surface.create_mach_port().unwrap();
+        let received = SharedSurface::from_mach_port(&port).unwrap();
+        assert_eq!(received.id(), surface.id());
+        assert_eq!((received.width(), received.height()), (64, 32));
+        assert_eq!(received.use_count(), surface.use_count());
+        assert_eq!(
+            SharedSurface::lookup(surface.id()).unwrap().id(),
+            surface.id()
+        );
+
+        let rewrapped = received.to_pixel_buffer().unwrap();
+        let rewrapped_surface = SharedSurface::from_pixel_buffer(&rewrapped).unwrap();
+        assert_eq!(rewrapped_surface.id(), surface.id());
+    }
+}
@@ -0,0 +1,276 @@
+//! Encoding one capture feed at several resolutions at once.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use core_foundation_sys::base::OSStatus;
+use core_media_sys::CMTime;
+
+use super::compression_builder::{CompressionSessionBuilder, CompressionSessionConfig};
+use super::compression_session::{CompressionSession, EncodeOutput};
+use super::pixel_buffer_pool::OutputPool;
+use super::pixel_transfer::{DownsamplingMode, PixelTransfer, PixelTransferBuilder, ScalingMode};
+use crate::cv_types::{
+    CVPixelBufferGetHeight, CVPixelBufferGetPixelFormatType, CVPixelBufferGetWidth,
+    CVPixelBufferRef,
+};
+use crate::errors::kVTParameterErr;
+
+/// One output of a [`SimulcastEncoder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendition {
+    /// Identifier passed to the output callback, e.g. `"720p"`
+    pub id: String,
+    pub width: i32,
+    pub height: i32,
+    /// Average bitrate in bits per second; `None` keeps the base
+    /// configuration's
+    pub bitrate: Option<i64>,
+}
+
+impl Rendition {
+    pub fn new(id: impl Into<String>, width: i32, height: i32) -> Self {
+        Self {
+            id: id.into(),
+            width,
+            height,
+            bitrate: None,
+        }
+    }
+
+    pub fn bitrate(mut self, bps: i64) -> Self {
+        self.bitrate = Some(bps);
+        self
+    }
+
+    /// A common 16:9 live ladder: 1080p at 6 Mbps, 720p at 3 Mbps and 360p
+    /// at 800 kbps.
+    pub fn ladder_1080p() -> Vec<Self> {
+        vec![
+            Self::new("1080p", 1920, 1080).bitrate(6_000_000),
+            Self::new("720p", 1280, 720).bitrate(3_000_000),
+            Self::new("360p", 640, 360).bitrate(800_000),
+        ]
+    }
+
+    /// `base` with this rendition's size and bitrate.
+    fn config(&self, base: &CompressionSessionConfig) -> CompressionSessionConfig {
+        let mut config = base.clone();
+        config.width = self.width;
+        config.height = self.height;
+        config.bitrate = self.bitrate.or(base.bitrate);
+        config
+    }
+}
+
+/// Renditions must be non-empty, have distinct ids and positive even sizes
+/// (for 4:2:0 chroma).
+fn validate(renditions: &[Rendition]) -> Result<(), OSStatus> {
+    let mut ids = HashSet::new();
+    let valid = !renditions.is_empty()
+        && renditions.iter().all(|r| {
+            r.width > 0 && r.height > 0 && (r.width | r.height) & 1 == 0 && ids.insert(&r.id)
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(kVTParameterErr)
+    }
+}
+
+struct Output {
+    rendition: Arc<Rendition>,
+    session: CompressionSession,
+    /// Scaled frames, recycled once the encoder releases them
+    pool: OutputPool,
+}
+
+/// Encodes each frame of one source at several resolutions, e.g. 1080p,
+/// 720p and 360p for adaptive-bitrate streaming.
+///
+/// Each [`Rendition`] gets its own compression session, configured like the
+/// shared base configuration except for size and bitrate. Frames are
+/// scaled down through one [`PixelTransfer`] session shared by all
+/// renditions; a rendition matching the source's size and pixel format
+/// encodes the source directly. Every session reports to the same callback,
+/// which receives the rendition the output belongs to.
+///
+/// All renditions share the keyframe interval, and
+/// [`force_keyframe`](Self::force_keyframe) applies to all of them, so
+/// segments can be cut at the same frames across the ladder.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{
+///     CompressionSessionConfig, EncodeOutput, Rendition, SimulcastEncoder,
+/// };
+/// # fn frames() -> Vec<(video_toolbox_sys::cv_types::CVPixelBufferRef, core_media_sys::CMTime, core_media_sys::CMTime)> { Vec::new() }
+///
+/// let mut base = CompressionSessionConfig::new(1920, 1080, codecs::video::H264);
+/// base.pixel_format = codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE;
+/// base.keyframe_interval = Some(60);
+/// let mut encoder = SimulcastEncoder::new(base, Rendition::ladder_1080p(), |rendition, output| {
+///     if let EncodeOutput::Frame { .. } = output {
+///         // Hand the frame to the rendition's playlist or connection
+///         let _ = &rendition.id;
+///     }
+/// })?;
+/// for (pixel_buffer, pts, duration) in frames() {
+///     unsafe { encoder.encode_frame(pixel_buffer, pts, duration)? };
+/// }
+/// encoder.complete_frames()?;
+/// # Ok::<(), i32>(())
+/// ```
+pub struct SimulcastEncoder {
+    outputs: Vec<Output>,
+    transfer: PixelTransfer,
+    pixel_format: u32,
+}
+
+impl SimulcastEncoder {
+    /// Create a session per rendition from `base`.
+    ///
+    /// Fails with `kVTParameterErr` if `renditions` is empty, repeats an id
+    /// or has an odd or empty size.
+    pub fn new<F>(
+        base: CompressionSessionConfig,
+        renditions: Vec<Rendition>,
+        callback: F,
+    ) -> Result<Self, OSStatus>
+    where
+        F: Fn(&Rendition, EncodeOutput) + Send + Sync + 'static,
+    {
+        validate(&renditions)?;
+        let callback = Arc::new(callback);
+        let outputs = renditions
+            .into_iter()
+            .map(|rendition| {
+                let builder = CompressionSessionBuilder::from_config(rendition.config(&base));
+                let rendition = Arc::new(rendition);
+                let (callback, tag) = (callback.clone(), rendition.clone());
+                let session = builder.build_session(move |output| callback(&tag, output))?;
+                Ok(Output {
+                    rendition,
+                    session,
+                    pool: OutputPool::default(),
+                })
+            })
+            .collect::<Result<Vec<_>, OSStatus>>()?;
+        let transfer = PixelTransferBuilder::new()
+            .pixel_format(base.pixel_format)
+            .downsampling_mode(DownsamplingMode::Average)
+            .build()?;
+        Ok(Self {
+            outputs,
+            transfer,
+            pixel_format: base.pixel_format,
+        })
+    }
+
+    /// Renditions in the order given to [`new`](Self::new).
+    pub fn renditions(&self) -> impl Iterator<Item = &Rendition> {
+        self.outputs.iter().map(|output| &*output.rendition)
+    }
+
+    /// The session encoding rendition `id`, e.g. for its
+    /// [`stats`](CompressionSession::stats).
+    pub fn session(&self, id: &str) -> Option<&CompressionSession> {
+        self.outputs
+            .iter()
+            .find(|output| output.rendition.id == id)
+            .map(|output| &output.session)
+    }
+
+    /// How sources with a different aspect ratio than a rendition are
+    /// fitted (default [`ScalingMode::Normal`], which stretches).
+    pub fn set_scaling_mode(&mut self, mode: ScalingMode) -> Result<(), OSStatus> {
+        self.transfer.set_scaling_mode(mode)
+    }
+
+    /// Scale `pixel_buffer` to every rendition and submit it.
+    ///
+    /// Stops at the first rendition that fails; renditions before it have
+    /// already received the frame.
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid pixel buffer not being written to
+    /// concurrently.
+    pub unsafe fn encode_frame(
+        &mut self,
+        pixel_buffer: CVPixelBufferRef,
+        pts: CMTime,
+        duration: CMTime,
+    ) -> Result<(), OSStatus> {
+        let source_size = (
+            CVPixelBufferGetWidth(pixel_buffer),
+            CVPixelBufferGetHeight(pixel_buffer),
+        );
+        let direct = CVPixelBufferGetPixelFormatType(pixel_buffer) == self.pixel_format;
+        for output in &mut self.outputs {
+            let size = (
+                output.rendition.width as usize,
+                output.rendition.height as usize,
+            );
+            if direct && size == source_size {
+                output.session.encode_frame(pixel_buffer, pts, duration)?;
+                continue;
+            }
+            let scaled = output.pool.acquire(size.0, size.1, self.pixel_format)?;
+            self.transfer.transfer_into(pixel_buffer, scaled.as_raw())?;
+            output
+                .session
+                .encode_frame(scaled.as_raw(), pts, duration)?;
+        }
+        Ok(())
+    }
+
+    /// Force the next frame of every rendition to be a keyframe.
+    pub fn force_keyframe(&self) {
+        for output in &self.outputs {
+            output.session.force_keyframe();
+        }
+    }
+
+    /// Block until every rendition has emitted its pending frames.
+    pub fn complete_frames(&self) -> Result<(), OSStatus> {
+        for output in &self.outputs {
+            output.session.complete_frames()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs;
+
+    #[test]
+    fn test_rendition_configs() {
+        let mut base = CompressionSessionConfig::new(1920, 1080, codecs::video::H264);
+        base.bitrate = Some(5_000_000);
+        base.keyframe_interval = Some(60);
+        let ladder = Rendition::ladder_1080p();
+        assert_eq!(validate(&ladder), Ok(()));
+
+        let config = ladder[1].config(&base);
+        assert_eq!((config.width, config.height), (1280, 720));
+        assert_eq!(config.bitrate, Some(3_000_000));
+        assert_eq!(config.keyframe_interval, Some(60));
+        assert_eq!(
+            Rendition::new("source", 1920, 1080).config(&base).bitrate,
+            Some(5_000_000)
+        );
+
+        assert_eq!(validate(&[]), Err(kVTParameterErr));
+        let duplicate = vec![ladder[1].clone(), ladder[1].clone()];
+        assert_eq!(validate(&duplicate), Err(kVTParameterErr));
+        assert_eq!(
+            validate(&[Rendition::new("odd", 853, 480)]),
+            Err(kVTParameterErr)
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Destinations for muxed CMAF segments.
+//!
+//! [`CmafMuxer`](super::CmafMuxer) produces an initialization segment followed
+//! by media segments. A [`SegmentSink`] consumes them, whether that means
+//! writing to disk, publishing over the network, or fanning out to several
+//! sinks with [`TeeSink`](super::TeeSink).
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Kind of CMAF segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SegmentKind {
+    /// Initialization segment (ftyp + moov)
+    Init,
+    /// Media segment (moof + mdat)
+    Media,
+}
+
+/// A muxed segment on its way to a sink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    /// Fragment sequence number (`mfhd`); 0 for the init segment
+    pub sequence_number: u32,
+    pub data: Vec<u8>,
+    /// Media duration, if known (needed by playlist writers)
+    pub duration: Option<Duration>,
+}
+
+impl Segment {
+    /// An initialization segment.
+    pub fn init(data: Vec<u8>) -> Self {
+        Self {
+            kind: SegmentKind::Init,
+            sequence_number: 0,
+            data,
+            duration: None,
+        }
+    }
+
+    /// A media segment with the given fragment sequence number.
+    pub fn media(sequence_number: u32, data: Vec<u8>) -> Self {
+        Self {
+            kind: SegmentKind::Media,
+            sequence_number,
+            data,
+            duration: None,
+        }
+    }
+
+    /// Set the media duration (see [`CmafMuxer::last_fragment_duration`](super::CmafMuxer::last_fragment_duration)).
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    pub fn is_init(&self) -> bool {
+        self.kind == SegmentKind::Init
+    }
+
+    /// Conventional file name: `init.mp4` or `segment_NNN.m4s`.
+    pub fn file_name(&self) -> String {
+        match self.kind {
+            SegmentKind::Init => "init.mp4".to_string(),
+            SegmentKind::Media => format!("segment_{:03}.m4s", self.sequence_number),
+        }
+    }
+}
+
+/// A consumer of muxed segments.
+///
+/// Segments arrive in order, starting with the init segment. The init
+/// segment may be repeated if the stream is reconfigured.
+pub trait SegmentSink {
+    /// Write one segment.
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()>;
+
+    /// Flush any buffered output.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: SegmentSink + ?Sized> SegmentSink for Box<S> {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        (**self).write_segment(segment)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+/// Writes segments back to back, producing a single fragmented MP4 stream.
+pub struct WriterSink<W> {
+    writer: W,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl WriterSink<io::BufWriter<File>> {
+    /// Create (or truncate) a fragmented MP4 file.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(io::BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> SegmentSink for WriterSink<W> {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        self.writer.write_all(&segment.data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes each segment to its own file: `init.mp4` and `segment_NNN.m4s`.
+pub struct DirectorySink {
+    dir: PathBuf,
+}
+
+impl DirectorySink {
+    /// Use `dir` as the output directory, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Path a segment is written to.
+    pub fn segment_path(&self, segment: &Segment) -> PathBuf {
+        self.dir.join(segment.file_name())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl SegmentSink for DirectorySink {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        fs::write(self.segment_path(segment), &segment.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writer_sink_concatenates_segments() {
+        let mut sink = WriterSink::new(Vec::new());
+        sink.write_segment(&Segment::init(vec![1, 2])).unwrap();
+        sink.write_segment(&Segment::media(1, vec![3])).unwrap();
+        sink.flush().unwrap();
+        assert_eq!(sink.into_inner(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_directory_sink_paths() {
+        let dir = std::env::temp_dir().join(format!("vt-sink-{}", std::process::id()));
+        let mut sink = DirectorySink::new(&dir).unwrap();
+        sink.write_segment(&Segment::init(vec![0xAA])).unwrap();
+        sink.write_segment(&Segment::media(7, vec![0xBB])).unwrap();
+        assert_eq!(fs::read(dir.join("init.mp4")).unwrap(), vec![0xAA]);
+        assert_eq!(fs::read(dir.join("segment_007.m4s")).unwrap(), vec![0xBB]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+//! Frame sources for feeding encoded video into muxers and transports.
+
+use super::nal_extractor::{NalUnit, SampleTiming};
+
+/// An encoded video frame with its timing.
+#[derive(Debug, Clone)]
+pub struct MediaFrame {
+    /// NAL units making up the frame (video slices, not SPS/PPS)
+    pub nal_units: Vec<NalUnit>,
+    /// Presentation/decode timing in `timing.timescale` units
+    pub timing: SampleTiming,
+    /// Whether this is a sync sample (IDR frame)
+    pub is_keyframe: bool,
+    /// Motion relative to the previous frame (0 = static, 1 = maximal), when
+    /// attached by a [`MotionEstimator`](super::MotionEstimator)
+    pub motion_score: Option<f32>,
+}
+
+/// A producer of encoded frames in decode order.
+pub trait FrameSource {
+    /// Return the next frame, or `None` at end of stream.
+    fn next_frame(&mut self) -> Option<MediaFrame>;
+
+    /// Restart the source from its first frame.
+    ///
+    /// Returns false if the source cannot be rewound.
+    fn rewind(&mut self) -> bool {
+        false
+    }
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for Box<S> {
+    fn next_frame(&mut self) -> Option<MediaFrame> {
+        (**self).next_frame()
+    }
+
+    fn rewind(&mut self) -> bool {
+        (**self).rewind()
+    }
+}
+
+/// A rewindable source over frames held in memory (e.g. synthetic test content).
+#[derive(Debug, Clone, Default)]
+pub struct VecSource {
+    frames: Vec<MediaFrame>,
+    position: usize,
+}
+
+impl VecSource {
+    pub fn new(frames: Vec<MediaFrame>) -> Self {
+        Self {
+            frames,
+            position: 0,
+        }
+    }
+}
+
+impl FrameSource for VecSource {
+    fn next_frame(&mut self) -> Option<MediaFrame> {
+        let frame = self.frames.get(self.position)?.clone();
+        self.position += 1;
+        Some(frame)
+    }
+
+    fn rewind(&mut self) -> bool {
+        self.position = 0;
+        true
+    }
+}
+
+/// Replays a rewindable source indefinitely with continuously increasing timestamps.
+///
+/// Each pass is offset by the duration of the previous passes, so downstream
+/// muxers and transports see one uninterrupted stream. Useful for long-running
+/// soak tests without capture hardware.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{FrameSource, LoopingSource, VecSource};
+///
+/// let mut source = LoopingSource::new(VecSource::new(Vec::new())).max_loops(Some(1000));
+/// while let Some(frame) = source.next_frame() {
+///     // Feed frame.nal_units / frame.timing into the muxer
+/// }
+/// ```
+pub struct LoopingSource<S> {
+    inner: S,
+    max_loops: Option<u64>,
+    loops: u64,
+    offset: i64,
+    pass_start: Option<i64>,
+    pass_end: i64,
+}
+
+impl<S: FrameSource> LoopingSource<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            max_loops: None,
+            loops: 0,
+            offset: 0,
+            pass_start: None,
+            pass_end: 0,
+        }
+    }
+
+    /// Stop after the given number of complete passes (`None` loops forever).
+    pub fn max_loops(mut self, max_loops: Option<u64>) -> Self {
+        self.max_loops = max_loops;
+        self
+    }
+
+    /// Number of completed passes over the inner source.
+    pub fn loop_count(&self) -> u64 {
+        self.loops
+    }
+
+    /// Timestamp offset currently applied to frames, in source timescale units.
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Rewind the inner source, returning false if looping should stop.
+    fn start_next_pass(&mut self) -> bool {
+        // An empty pass would spin forever without producing frames.
+        let Some(start) = self.pass_start.take() else {
+            return false;
+        };
+        self.loops += 1;
+        if self.max_loops.is_some_and(|max| self.loops >= max) {
+            return false;
+        }
+        if !self.inner.rewind() {
+            return false;
+        }
+        self.offset += self.pass_end - start;
+        true
+    }
+}
+
+impl<S: FrameSource> FrameSource for LoopingSource<S> {
+    fn next_frame(&mut self) -> Option<MediaFrame> {
+        let mut frame = match self.inner.next_frame() {
+            Some(frame) => frame,
+            None => {
+                if !self.start_next_pass() {
+                    return None;
+                }
+                self.inner.next_frame()?
+            }
+        };
+
+        let timing = &mut frame.timing;
+        self.pass_start.get_or_insert(timing.dts);
+        self.pass_end = self.pass_end.max(timing.dts + timing.duration);
+        timing.pts += self.offset;
+        timing.dts += self.offset;
+        Some(frame)
+    }
+
+    fn rewind(&mut self) -> bool {
+        if !self.inner.rewind() {
+            return false;
+        }
+        self.loops = 0;
+        self.offset = 0;
+        self.pass_start = None;
+        self.pass_end = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(pts: i64, dts: i64, is_keyframe: bool) -> MediaFrame {
+        MediaFrame {
+            nal_units: Vec::new(),
+            timing: SampleTiming {
+                pts,
+                dts,
+                duration: 3000,
+                timescale: 90000,
+            },
+            is_keyframe,
+            motion_score: None,
+        }
+    }
+
+    #[test]
+    fn test_looping_source_offsets_timestamps() {
+        // I P B ordering: dts 1000..10000, pts shifted by one frame
+        let frames = vec![
+            frame(4000, 1000, true),
+            frame(10000, 4000, false),
+            frame(7000, 7000, false),
+        ];
+        let mut source = LoopingSource::new(VecSource::new(frames)).max_loops(Some(3));
+
+        let dts: Vec<i64> = std::iter::from_fn(|| source.next_frame())
+            .map(|f| f.timing.dts)
+            .collect();
+        assert_eq!(
+            dts,
+            vec![1000, 4000, 7000, 10000, 13000, 16000, 19000, 22000, 25000]
+        );
+        assert_eq!(source.loop_count(), 3);
+    }
+
+    #[test]
+    fn test_looping_source_preserves_composition_offset() {
+        let mut source = LoopingSource::new(VecSource::new(vec![frame(6000, 0, true)]));
+        for pass in 0..5 {
+            let f = source.next_frame().unwrap();
+            assert_eq!(f.timing.dts, pass * 3000);
+            assert_eq!(f.timing.pts - f.timing.dts, 6000);
+            assert!(f.is_keyframe);
+        }
+    }
+
+    #[test]
+    fn test_looping_source_stops_on_empty_or_unrewindable() {
+        let mut empty = LoopingSource::new(VecSource::new(Vec::new()));
+        assert!(empty.next_frame().is_none());
+
+        struct Once(Option<MediaFrame>);
+        impl FrameSource for Once {
+            fn next_frame(&mut self) -> Option<MediaFrame> {
+                self.0.take()
+            }
+        }
+        let mut once = LoopingSource::new(Once(Some(frame(0, 0, true))));
+        assert!(once.next_frame().is_some());
+        assert!(once.next_frame().is_none());
+    }
+}
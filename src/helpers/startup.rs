@@ -0,0 +1,148 @@
+//! Startup behavior of a receiver joining a stream mid-GOP.
+//!
+//! Predicted frames decoded without their reference keyframe come out as
+//! errors or gray smear. [`StartupGate`] decides whether such frames are
+//! discarded until the first keyframe or decoded anyway, and reports the
+//! resulting [`StartupState`] so a player can show "waiting for video"
+//! instead of a corrupt picture.
+
+use super::events::{emit, PipelineEvent};
+
+/// What to do with frames received before the first keyframe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StartupPolicy {
+    /// Discard frames until a keyframe arrives
+    #[default]
+    WaitForKeyframe,
+    /// Decode every frame, accepting artifacts until the first keyframe
+    DecodeImmediately,
+}
+
+/// Where a receiver is in starting up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupState {
+    /// Frames are discarded until a keyframe arrives
+    WaitingForKeyframe,
+    /// Frames are decoded without a keyframe; the picture may show artifacts
+    Provisional,
+    /// A keyframe was admitted and the picture is clean
+    Synced,
+}
+
+/// Applies a [`StartupPolicy`] to received frames.
+///
+/// Pass each frame's keyframe flag to [`admit`](Self::admit) before decoding
+/// it. State changes are reported as [`PipelineEvent::StartupStateChanged`]
+/// and can be polled with [`state`](Self::state). Call
+/// [`reset`](Self::reset) when the decoder is recreated, e.g. after a
+/// reconnect or a new init segment.
+///
+/// # Example
+///
+/// ```
+/// use video_toolbox_sys::helpers::{StartupGate, StartupPolicy, StartupState};
+///
+/// let mut gate = StartupGate::new(StartupPolicy::WaitForKeyframe);
+/// assert!(!gate.admit(false));
+/// assert_eq!(gate.state(), StartupState::WaitingForKeyframe);
+/// assert!(gate.admit(true));
+/// assert_eq!(gate.state(), StartupState::Synced);
+/// ```
+#[derive(Debug, Clone)]
+pub struct StartupGate {
+    policy: StartupPolicy,
+    state: StartupState,
+    discarded: u64,
+}
+
+impl StartupGate {
+    pub fn new(policy: StartupPolicy) -> Self {
+        Self {
+            policy,
+            state: Self::initial_state(policy),
+            discarded: 0,
+        }
+    }
+
+    fn initial_state(policy: StartupPolicy) -> StartupState {
+        match policy {
+            StartupPolicy::WaitForKeyframe => StartupState::WaitingForKeyframe,
+            StartupPolicy::DecodeImmediately => StartupState::Provisional,
+        }
+    }
+
+    pub fn policy(&self) -> StartupPolicy {
+        self.policy
+    }
+
+    /// Change the policy. Before the first keyframe, this also switches
+    /// between discarding and provisional decoding.
+    pub fn set_policy(&mut self, policy: StartupPolicy) {
+        self.policy = policy;
+        if self.state != StartupState::Synced {
+            self.transition(Self::initial_state(policy));
+        }
+    }
+
+    pub fn state(&self) -> StartupState {
+        self.state
+    }
+
+    /// Frames discarded while waiting for a keyframe.
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    /// Whether a frame should be decoded.
+    pub fn admit(&mut self, is_keyframe: bool) -> bool {
+        if is_keyframe {
+            self.transition(StartupState::Synced);
+            return true;
+        }
+        if self.state == StartupState::WaitingForKeyframe {
+            self.discarded += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Start over, as for a new decoder.
+    pub fn reset(&mut self) {
+        self.transition(Self::initial_state(self.policy));
+    }
+
+    fn transition(&mut self, state: StartupState) {
+        if self.state != state {
+            self.state = state;
+            emit(PipelineEvent::StartupStateChanged { state });
+        }
+    }
+}
+
+impl Default for StartupGate {
+    fn default() -> Self {
+        Self::new(StartupPolicy::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_immediately_until_keyframe() {
+        let mut gate = StartupGate::new(StartupPolicy::DecodeImmediately);
+        assert_eq!(gate.state(), StartupState::Provisional);
+        assert!(gate.admit(false));
+        assert!(gate.admit(true));
+        assert_eq!(gate.state(), StartupState::Synced);
+
+        // After a reset, waiting applies again once the policy asks for it
+        gate.set_policy(StartupPolicy::WaitForKeyframe);
+        assert_eq!(gate.state(), StartupState::Synced);
+        gate.reset();
+        assert!(!gate.admit(false));
+        assert_eq!(gate.discarded(), 1);
+        assert_eq!(gate.state(), StartupState::WaitingForKeyframe);
+    }
+}
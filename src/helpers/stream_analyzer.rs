@@ -0,0 +1,411 @@
+//! Offline analysis of encoded files: GOP structure, bitrate over time,
+//! keyframe intervals and timestamp gaps.
+//!
+//! A Rust-native stand-in for `ffprobe -show_frames` when checking what a
+//! pipeline produced. Files are read with the [`CmafDemuxer`], so they must
+//! be fragmented MP4 (CMAF segments or a recording from
+//! [`Fmp4Recorder`](super::Fmp4Recorder)) with an H.264 video track.
+
+use std::io;
+use std::path::Path;
+
+use super::cmaf_demuxer::{CmafDemuxer, DemuxError, DemuxedTrack};
+use super::source::MediaFrame;
+
+/// One group of pictures, from a keyframe up to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GopInfo {
+    /// Decode time of the first frame, in the track's timescale
+    pub start_dts: i64,
+    /// Decode time spanned by the GOP's frames
+    pub duration: i64,
+    pub frames: u32,
+    /// Sample bytes, including NAL length prefixes
+    pub bytes: u64,
+    /// Whether the GOP starts with a keyframe (only the first GOP of a
+    /// stream that starts mid-GOP does not)
+    pub starts_with_keyframe: bool,
+}
+
+/// Bytes in one window of decode time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitrateBucket {
+    /// Decode time of the window's start, in the track's timescale
+    pub start_dts: i64,
+    pub frames: u32,
+    pub bytes: u64,
+}
+
+/// A frame whose decode time does not follow from the previous frame's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampGap {
+    /// Decode time of the frame after the gap
+    pub dts: i64,
+    /// Decode time expected from the previous frame's dts and duration
+    pub expected_dts: i64,
+}
+
+impl TimestampGap {
+    /// Missing time in timescale units; negative if the frames overlap or
+    /// go backwards.
+    pub fn gap(&self) -> i64 {
+        self.dts.saturating_sub(self.expected_dts)
+    }
+}
+
+/// Results of analyzing a stream.
+#[derive(Debug, Clone)]
+pub struct StreamReport {
+    /// The video track, if an initialization segment was seen
+    pub track: Option<DemuxedTrack>,
+    /// Timescale of every timestamp in the report
+    pub timescale: u32,
+    pub frames: u64,
+    pub keyframes: u64,
+    pub bytes: u64,
+    /// Decode time from the first frame to the end of the last
+    pub duration: i64,
+    pub gops: Vec<GopInfo>,
+    /// Bytes per window of decode time, in order, including empty windows
+    /// except across a jump of more than [`MAX_EMPTY_WINDOWS`]
+    pub bitrate: Vec<BitrateBucket>,
+    /// Length of a bitrate window in timescale units
+    pub window: i64,
+    pub gaps: Vec<TimestampGap>,
+}
+
+impl StreamReport {
+    pub fn duration_seconds(&self) -> f64 {
+        self.duration as f64 / self.timescale.max(1) as f64
+    }
+
+    /// Mean bitrate over the whole stream, in bits per second.
+    pub fn average_bitrate(&self) -> u64 {
+        bits_per_second(self.bytes, self.duration, self.timescale)
+    }
+
+    /// Bitrate of each window in bits per second.
+    pub fn bitrates(&self) -> Vec<u64> {
+        self.bitrate
+            .iter()
+            .map(|bucket| bits_per_second(bucket.bytes, self.window, self.timescale))
+            .collect()
+    }
+
+    /// The highest window bitrate, in bits per second.
+    pub fn peak_bitrate(&self) -> u64 {
+        self.bitrates().into_iter().max().unwrap_or(0)
+    }
+
+    /// Seconds between consecutive keyframes.
+    pub fn keyframe_intervals(&self) -> Vec<f64> {
+        let timescale = self.timescale.max(1) as f64;
+        self.gops
+            .windows(2)
+            .filter(|pair| pair[0].starts_with_keyframe)
+            .map(|pair| (pair[1].start_dts - pair[0].start_dts) as f64 / timescale)
+            .collect()
+    }
+
+    /// Frames per GOP for each GOP starting with a keyframe, e.g. to check a
+    /// fixed keyframe interval.
+    pub fn gop_sizes(&self) -> Vec<u32> {
+        self.gops
+            .iter()
+            .filter(|gop| gop.starts_with_keyframe)
+            .map(|gop| gop.frames)
+            .collect()
+    }
+}
+
+fn bits_per_second(bytes: u64, duration: i64, timescale: u32) -> u64 {
+    if duration <= 0 {
+        return 0;
+    }
+    (bytes as u128 * 8 * timescale as u128 / duration as u128) as u64
+}
+
+/// Most empty bitrate windows filled in between two frames; a longer jump
+/// (e.g. a corrupt timestamp) leaves the windows in between out.
+pub const MAX_EMPTY_WINDOWS: i64 = 3600;
+
+/// Collects GOP, bitrate and timing statistics from a demuxed stream.
+///
+/// Feed it file or stream bytes with [`push`](Self::push), or frames that
+/// were demuxed elsewhere with [`push_frame`](Self::push_frame), then read
+/// the [`report`](Self::report).
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::analyze_file;
+///
+/// let report = analyze_file("recording.mp4")?;
+/// println!(
+///     "{} frames, {:.1} s, {} kbps average, {} kbps peak",
+///     report.frames,
+///     report.duration_seconds(),
+///     report.average_bitrate() / 1000,
+///     report.peak_bitrate() / 1000,
+/// );
+/// println!("GOP sizes: {:?}", report.gop_sizes());
+/// for gap in &report.gaps {
+///     println!("gap of {} ticks at dts {}", gap.gap(), gap.dts);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct StreamAnalyzer {
+    demuxer: CmafDemuxer,
+    /// Bitrate window in seconds
+    window_seconds: f64,
+    timescale: u32,
+    frames: u64,
+    keyframes: u64,
+    bytes: u64,
+    first_dts: Option<i64>,
+    end_dts: i64,
+    /// dts + duration of the previous frame
+    expected_dts: Option<i64>,
+    gops: Vec<GopInfo>,
+    bitrate: Vec<BitrateBucket>,
+    gaps: Vec<TimestampGap>,
+}
+
+impl Default for StreamAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamAnalyzer {
+    /// Create an analyzer with one-second bitrate windows.
+    pub fn new() -> Self {
+        Self::with_window(1.0)
+    }
+
+    /// Create an analyzer measuring bitrate over windows of `seconds`.
+    pub fn with_window(seconds: f64) -> Self {
+        Self {
+            demuxer: CmafDemuxer::new(),
+            window_seconds: if seconds > 0.0 { seconds } else { 1.0 },
+            timescale: 0,
+            frames: 0,
+            keyframes: 0,
+            bytes: 0,
+            first_dts: None,
+            end_dts: 0,
+            expected_dts: None,
+            gops: Vec::new(),
+            bitrate: Vec::new(),
+            gaps: Vec::new(),
+        }
+    }
+
+    /// Demux the next chunk of the file or stream and analyze its samples.
+    pub fn push(&mut self, data: &[u8]) -> Result<(), DemuxError> {
+        for frame in self.demuxer.push(data)? {
+            self.push_frame(&frame);
+        }
+        Ok(())
+    }
+
+    /// Analyze one frame. Frames must be in decode order and share a
+    /// timescale (the first frame's is used).
+    pub fn push_frame(&mut self, frame: &MediaFrame) {
+        let timing = frame.timing;
+        if self.timescale == 0 {
+            self.timescale = timing.timescale.max(1) as u32;
+        }
+        let length_size = self.demuxer.track().map_or(4, |track| {
+            track.parameter_sets.nal_length_size.max(1) as u64
+        });
+        let size = frame
+            .nal_units
+            .iter()
+            .map(|nal| nal.data.len() as u64 + length_size)
+            .sum::<u64>();
+
+        self.frames += 1;
+        self.bytes += size;
+        if let Some(expected_dts) = self.expected_dts {
+            if timing.dts != expected_dts {
+                self.gaps.push(TimestampGap {
+                    dts: timing.dts,
+                    expected_dts,
+                });
+            }
+        }
+        let end_dts = timing.dts.saturating_add(timing.duration);
+        self.expected_dts = Some(end_dts);
+        let first_dts = *self.first_dts.get_or_insert(timing.dts);
+        self.end_dts = self.end_dts.max(end_dts);
+
+        if frame.is_keyframe {
+            self.keyframes += 1;
+        }
+        match self.gops.last_mut() {
+            Some(gop) if !frame.is_keyframe => {
+                gop.frames += 1;
+                gop.bytes += size;
+                gop.duration = end_dts.saturating_sub(gop.start_dts);
+            }
+            _ => self.gops.push(GopInfo {
+                start_dts: timing.dts,
+                duration: timing.duration,
+                frames: 1,
+                bytes: size,
+                starts_with_keyframe: frame.is_keyframe,
+            }),
+        }
+
+        let window = self.window();
+        // Frames before the first (after a backwards jump) count toward it
+        let offset = timing.dts.saturating_sub(first_dts).max(0);
+        let start_dts = first_dts + offset / window * window;
+        let index = match self
+            .bitrate
+            .binary_search_by_key(&start_dts, |bucket| bucket.start_dts)
+        {
+            Ok(index) => index,
+            Err(mut index) => {
+                if let Some(last) = self.bitrate.last().filter(|_| index == self.bitrate.len()) {
+                    let last_start = last.start_dts;
+                    let empty = (start_dts - last_start) / window - 1;
+                    if empty <= MAX_EMPTY_WINDOWS {
+                        self.bitrate.extend((1..=empty).map(|i| BitrateBucket {
+                            start_dts: last_start + i * window,
+                            frames: 0,
+                            bytes: 0,
+                        }));
+                        index = self.bitrate.len();
+                    }
+                }
+                self.bitrate.insert(
+                    index,
+                    BitrateBucket {
+                        start_dts,
+                        frames: 0,
+                        bytes: 0,
+                    },
+                );
+                index
+            }
+        };
+        self.bitrate[index].frames += 1;
+        self.bitrate[index].bytes += size;
+    }
+
+    /// Bitrate window length in timescale units.
+    fn window(&self) -> i64 {
+        ((self.window_seconds * self.timescale.max(1) as f64).round() as i64).max(1)
+    }
+
+    /// The statistics of every frame analyzed so far.
+    pub fn report(&self) -> StreamReport {
+        StreamReport {
+            track: self.demuxer.track().cloned(),
+            timescale: self.timescale.max(1),
+            frames: self.frames,
+            keyframes: self.keyframes,
+            bytes: self.bytes,
+            duration: self
+                .first_dts
+                .map_or(0, |first_dts| self.end_dts.saturating_sub(first_dts)),
+            gops: self.gops.clone(),
+            bitrate: self.bitrate.clone(),
+            window: self.window(),
+            gaps: self.gaps.clone(),
+        }
+    }
+}
+
+/// Analyze a fragmented MP4 file with one-second bitrate windows.
+///
+/// Demuxing errors are returned as [`io::ErrorKind::InvalidData`].
+pub fn analyze_file(path: impl AsRef<Path>) -> io::Result<StreamReport> {
+    let data = std::fs::read(path)?;
+    let mut analyzer = StreamAnalyzer::new();
+    analyzer
+        .push(&data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(analyzer.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{NalUnit, SampleTiming};
+
+    fn frame(dts: i64, size: usize, is_keyframe: bool) -> MediaFrame {
+        MediaFrame {
+            nal_units: vec![NalUnit {
+                data: vec![0; size],
+                nal_type: if is_keyframe { 5 } else { 1 },
+            }],
+            timing: SampleTiming {
+                pts: dts,
+                dts,
+                duration: 3000,
+                timescale: 90000,
+            },
+            is_keyframe,
+            motion_score: None,
+        }
+    }
+
+    #[test]
+    fn test_gops_bitrate_and_gaps() {
+        let mut analyzer = StreamAnalyzer::new();
+        // 30 fps: a 30-frame GOP, then a 15-frame GOP after a 2-frame gap
+        for i in 0..30 {
+            analyzer.push_frame(&frame(i * 3000, 996, i == 0));
+        }
+        for i in 0..15 {
+            analyzer.push_frame(&frame(96000 + i * 3000, 496, i == 0));
+        }
+
+        let report = analyzer.report();
+        assert_eq!(report.frames, 45);
+        assert_eq!(report.keyframes, 2);
+        assert_eq!(report.bytes, 30 * 1000 + 15 * 500);
+        assert_eq!(report.duration, 141000);
+        assert_eq!(report.gop_sizes(), [30, 15]);
+        assert_eq!(report.keyframe_intervals(), [96000.0 / 90000.0]);
+        assert_eq!(
+            report.gaps,
+            [TimestampGap {
+                dts: 96000,
+                expected_dts: 90000
+            }]
+        );
+        assert_eq!(report.gaps[0].gap(), 6000);
+
+        // The first second holds exactly the first GOP
+        assert_eq!(report.bitrate.len(), 2);
+        assert_eq!(report.bitrate[0].frames, 30);
+        assert_eq!(report.bitrates()[0], 240_000);
+        assert_eq!(report.peak_bitrate(), 240_000);
+    }
+    #[test]
+    fn test_timestamp_jump() {
+        let mut analyzer = StreamAnalyzer::new();
+        analyzer.push_frame(&frame(0, 96, true));
+        analyzer.push_frame(&frame(i64::MAX - 3000, 96, false));
+        analyzer.push_frame(&frame(i64::MAX, 96, false));
+        analyzer.push_frame(&frame(95000, 96, false));
+
+        // The windows across the jump are left out rather than allocated
+        let report = analyzer.report();
+        assert_eq!(report.duration, i64::MAX);
+        assert_eq!(report.gaps.len(), 2);
+        assert_eq!(report.gaps[1].gap(), 95000 - i64::MAX);
+        let starts: Vec<_> = report
+            .bitrate
+            .iter()
+            .map(|bucket| bucket.start_dts)
+            .collect();
+        assert_eq!(starts, [0, 90000, i64::MAX - 55807]);
+        assert_eq!(report.bitrate[2].frames, 2);
+    }
+}
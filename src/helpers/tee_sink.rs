@@ -0,0 +1,382 @@
+//! Fan-out of segments to several sinks with independent backpressure.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use super::sink::{Segment, SegmentSink};
+
+/// What a [`TeeSink`] branch does when its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for the branch to catch up. Nothing is dropped, but a slow branch
+    /// stalls every writer (use for local recording).
+    Block,
+    /// Discard the oldest queued media segment to make room (use for live
+    /// network output, where fresh data matters most).
+    DropOldest,
+    /// Discard the incoming media segment.
+    DropNewest,
+}
+
+/// Delivery counters for one [`TeeSink`] branch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TeeBranchStats {
+    /// Segments written to the branch's sink
+    pub written: u64,
+    /// Segments discarded by the backpressure policy or after a failure
+    pub dropped: u64,
+    /// Segments waiting to be written
+    pub queued: usize,
+    /// Set once the branch's sink has failed; the branch then drops everything
+    pub error: Option<io::ErrorKind>,
+}
+
+struct BranchState {
+    queue: VecDeque<Arc<Segment>>,
+    closed: bool,
+    flush_requested: u64,
+    flushed: u64,
+    flush_error: Option<io::ErrorKind>,
+    stats: TeeBranchStats,
+}
+
+struct Shared {
+    state: Mutex<BranchState>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, BranchState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, BranchState>) -> MutexGuard<'a, BranchState> {
+        self.changed.wait(guard).unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct Branch {
+    shared: Arc<Shared>,
+    policy: Backpressure,
+    capacity: usize,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// A [`SegmentSink`] that writes every segment to several sinks.
+///
+/// Each branch has its own queue and worker thread, so a stalled network
+/// sink does not hold up the file sink (unless its policy is
+/// [`Backpressure::Block`]). Init segments are never dropped, since later
+/// media segments are undecodable without them.
+///
+/// A failing branch stops receiving segments and reports the error in its
+/// [`TeeBranchStats`]; the other branches keep running. `write_segment` only
+/// returns an error when a [`Backpressure::Block`] branch has failed, since
+/// its no-drop guarantee can no longer be met.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::{Backpressure, DirectorySink, TeeSink, WriterSink};
+///
+/// let tee = TeeSink::new()
+///     .add_sink(WriterSink::create("recording.mp4")?, Backpressure::Block, 64)
+///     .add_sink(DirectorySink::new("live")?, Backpressure::DropOldest, 4);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct TeeSink {
+    branches: Vec<Branch>,
+}
+
+impl Default for TeeSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TeeSink {
+    pub fn new() -> Self {
+        Self {
+            branches: Vec::new(),
+        }
+    }
+
+    /// Add a branch that queues up to `capacity` segments for `sink`.
+    pub fn add_sink<S>(mut self, sink: S, policy: Backpressure, capacity: usize) -> Self
+    where
+        S: SegmentSink + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(BranchState {
+                queue: VecDeque::new(),
+                closed: false,
+                flush_requested: 0,
+                flushed: 0,
+                flush_error: None,
+                stats: TeeBranchStats::default(),
+            }),
+            changed: Condvar::new(),
+        });
+        let worker_shared = shared.clone();
+        let worker = thread::Builder::new()
+            .name(format!("tee-sink-{}", self.branches.len()))
+            .spawn(move || run_branch(sink, &worker_shared))
+            .expect("failed to spawn tee sink worker");
+
+        self.branches.push(Branch {
+            shared,
+            policy,
+            capacity: capacity.max(1),
+            worker: Some(worker),
+        });
+        self
+    }
+
+    /// Number of branches.
+    pub fn branch_count(&self) -> usize {
+        self.branches.len()
+    }
+
+    /// Delivery counters for the branch at `index` (in the order added).
+    pub fn branch_stats(&self, index: usize) -> Option<TeeBranchStats> {
+        let branch = self.branches.get(index)?;
+        let state = branch.shared.lock();
+        let mut stats = state.stats.clone();
+        stats.queued = state.queue.len();
+        Some(stats)
+    }
+}
+
+impl Branch {
+    /// Queue a segment according to the backpressure policy.
+    fn enqueue(&self, segment: &Arc<Segment>) -> Result<(), io::ErrorKind> {
+        let mut state = self.shared.lock();
+        if let Some(kind) = state.stats.error {
+            state.stats.dropped += 1;
+            return if self.policy == Backpressure::Block {
+                Err(kind)
+            } else {
+                Ok(())
+            };
+        }
+
+        if state.queue.len() >= self.capacity && !segment.is_init() {
+            match self.policy {
+                Backpressure::Block => {
+                    while state.queue.len() >= self.capacity && state.stats.error.is_none() {
+                        state = self.shared.wait(state);
+                    }
+                    if let Some(kind) = state.stats.error {
+                        state.stats.dropped += 1;
+                        return Err(kind);
+                    }
+                }
+                Backpressure::DropOldest => {
+                    // Keep queued init segments; evict the oldest media segment
+                    if let Some(pos) = state.queue.iter().position(|s| !s.is_init()) {
+                        state.queue.remove(pos);
+                        state.stats.dropped += 1;
+                    }
+                }
+                Backpressure::DropNewest => {
+                    state.stats.dropped += 1;
+                    return Ok(());
+                }
+            }
+        }
+
+        state.queue.push_back(segment.clone());
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Wait for queued segments to be written, then flush the sink.
+    fn flush(&self) -> Result<(), io::ErrorKind> {
+        let mut state = self.shared.lock();
+        state.flush_requested += 1;
+        let target = state.flush_requested;
+        self.shared.changed.notify_all();
+        while state.flushed < target {
+            state = self.shared.wait(state);
+        }
+        match state.stats.error.or(state.flush_error.take()) {
+            Some(kind) => Err(kind),
+            None => Ok(()),
+        }
+    }
+}
+
+fn run_branch<S: SegmentSink>(mut sink: S, shared: &Shared) {
+    let mut state = shared.lock();
+    loop {
+        if let Some(segment) = state.queue.pop_front() {
+            drop(state);
+            let result = sink.write_segment(&segment);
+            state = shared.lock();
+            match result {
+                Ok(()) => state.stats.written += 1,
+                Err(e) => {
+                    state.stats.error = Some(e.kind());
+                    state.stats.dropped += 1 + state.queue.len() as u64;
+                    state.queue.clear();
+                }
+            }
+            shared.changed.notify_all();
+        } else if state.flushed < state.flush_requested {
+            let target = state.flush_requested;
+            let failed = state.stats.error.is_some();
+            drop(state);
+            let result = if failed { Ok(()) } else { sink.flush() };
+            state = shared.lock();
+            state.flushed = target;
+            state.flush_error = result.err().map(|e| e.kind());
+            shared.changed.notify_all();
+        } else if state.closed {
+            break;
+        } else {
+            state = shared.wait(state);
+        }
+    }
+    drop(state);
+    let _ = sink.flush();
+}
+
+impl SegmentSink for TeeSink {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        let segment = Arc::new(segment.clone());
+        let mut result = Ok(());
+        for (index, branch) in self.branches.iter().enumerate() {
+            if let Err(kind) = branch.enqueue(&segment) {
+                result = Err(io::Error::new(kind, format!("tee branch {} failed", index)));
+            }
+        }
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for (index, branch) in self.branches.iter().enumerate() {
+            if let Err(kind) = branch.flush() {
+                if branch.policy == Backpressure::Block {
+                    result = Err(io::Error::new(kind, format!("tee branch {} failed", index)));
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for TeeSink {
+    fn drop(&mut self) {
+        for branch in &self.branches {
+            branch.shared.lock().closed = true;
+            branch.shared.changed.notify_all();
+        }
+        // Workers drain their queues before exiting
+        for branch in &mut self.branches {
+            if let Some(worker) = branch.worker.take() {
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Records segment sequence numbers, optionally blocking until released.
+    struct RecordingSink {
+        written: Arc<Mutex<Vec<u32>>>,
+        gate: Option<mpsc::Receiver<()>>,
+        fail: bool,
+    }
+
+    impl SegmentSink for RecordingSink {
+        fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+            if let Some(gate) = &self.gate {
+                let _ = gate.recv();
+            }
+            if self.fail {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
+            }
+            self.written.lock().unwrap().push(segment.sequence_number);
+            Ok(())
+        }
+    }
+
+    fn recording(
+        gate: Option<mpsc::Receiver<()>>,
+        fail: bool,
+    ) -> (RecordingSink, Arc<Mutex<Vec<u32>>>) {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sink = RecordingSink {
+            written: written.clone(),
+            gate,
+            fail,
+        };
+        (sink, written)
+    }
+
+    #[test]
+    fn test_tee_independent_backpressure() {
+        let (file, file_written) = recording(None, false);
+        let (gate_tx, gate_rx) = mpsc::channel();
+        let (network, network_written) = recording(Some(gate_rx), false);
+
+        let mut tee = TeeSink::new()
+            .add_sink(file, Backpressure::Block, 2)
+            .add_sink(network, Backpressure::DropOldest, 2);
+
+        tee.write_segment(&Segment::init(Vec::new())).unwrap();
+        for seq in 1..=10 {
+            tee.write_segment(&Segment::media(seq, Vec::new())).unwrap();
+        }
+        // Release the stalled network branch
+        for _ in 0..11 {
+            let _ = gate_tx.send(());
+        }
+        tee.flush().unwrap();
+
+        assert_eq!(*file_written.lock().unwrap(), (0..=10).collect::<Vec<_>>());
+        let network = network_written.lock().unwrap().clone();
+        // Init segment survives and the newest segments win
+        assert_eq!(network.first(), Some(&0));
+        assert_eq!(network.last(), Some(&10));
+        let stats = tee.branch_stats(1).unwrap();
+        assert_eq!(stats.written + stats.dropped, 11);
+        assert!(stats.dropped > 0);
+        assert_eq!(tee.branch_stats(0).unwrap().dropped, 0);
+    }
+
+    #[test]
+    fn test_tee_failed_branch_is_isolated() {
+        let (file, file_written) = recording(None, false);
+        let (network, _) = recording(None, true);
+        let mut tee = TeeSink::new()
+            .add_sink(file, Backpressure::Block, 4)
+            .add_sink(network, Backpressure::DropNewest, 4);
+
+        for seq in 1..=3 {
+            tee.write_segment(&Segment::media(seq, Vec::new())).unwrap();
+        }
+        tee.flush().unwrap();
+
+        assert_eq!(*file_written.lock().unwrap(), vec![1, 2, 3]);
+        let stats = tee.branch_stats(1).unwrap();
+        assert_eq!(stats.error, Some(io::ErrorKind::BrokenPipe));
+        assert_eq!(stats.written, 0);
+    }
+
+    #[test]
+    fn test_tee_reports_failed_blocking_branch() {
+        let (file, _) = recording(None, true);
+        let mut tee = TeeSink::new().add_sink(file, Backpressure::Block, 4);
+        tee.write_segment(&Segment::media(1, Vec::new())).unwrap();
+        assert!(tee.flush().is_err());
+        assert!(tee.write_segment(&Segment::media(2, Vec::new())).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Time-lapse encoding: frame decimation and timestamp compression.
+
+use core_foundation_sys::base::OSStatus;
+use core_media_sys::CMTime;
+use std::time::Duration;
+
+use super::clock::make_time;
+use super::compression_builder::CompressionSessionConfig;
+use super::compression_session::CompressionSession;
+use crate::compression::EncodeInfoFlags;
+use crate::cv_types::CVImageBufferRef;
+
+/// Which captured frames go into a time-lapse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSelection {
+    /// One frame per interval of capture time
+    Interval(Duration),
+    /// Every Nth captured frame
+    EveryNth(u32),
+}
+
+/// Turns a long capture into a standard-speed time-lapse.
+///
+/// [`select`](Self::select) keeps one captured frame per
+/// [`FrameSelection`] step and assigns it the next timestamp of a
+/// constant-rate output stream at `output_fps`, so the resulting MP4 plays
+/// back at normal speed without post-processing. Interval selection is
+/// anchored to the first frame and does not drift with capture jitter; after
+/// a gap in capture (e.g. the camera paused) it resumes from the next frame.
+///
+/// [`configure`](Self::configure) adjusts the encoder for content where
+/// consecutive frames differ a lot.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::helpers::{
+///     CompressionSessionBuilder, CompressionSessionConfig, FrameSelection, TimeLapse,
+/// };
+///
+/// let mut time_lapse = TimeLapse::new(FrameSelection::Interval(Duration::from_secs(10)), 30.0);
+/// let mut config = CompressionSessionConfig::new(1920, 1080, codecs::video::H264);
+/// time_lapse.configure(&mut config);
+/// let session = CompressionSessionBuilder::from_config(config)
+///     .build_session(|_output| {})
+///     .unwrap();
+/// # let (pixel_buffer, capture_time) = (std::ptr::null_mut(), video_toolbox_sys::helpers::host_time_now());
+/// // In the capture callback:
+/// unsafe { time_lapse.encode(&session, pixel_buffer, capture_time) }.unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct TimeLapse {
+    selection: FrameSelection,
+    output_fps: f64,
+    /// Output timescale; each output frame lasts 1000 units
+    timescale: i32,
+    /// Capture time (ns) at which the next interval frame is due
+    next_due: Option<i128>,
+    frames_seen: u64,
+    frames_selected: u64,
+}
+
+/// Output ticks per frame in the output timescale.
+const TICKS_PER_FRAME: i64 = 1000;
+
+fn nanos(time: CMTime) -> i128 {
+    if time.timescale == 0 {
+        return 0;
+    }
+    time.value as i128 * 1_000_000_000 / time.timescale as i128
+}
+
+impl TimeLapse {
+    /// Select frames per `selection` for playback at `output_fps`.
+    pub fn new(selection: FrameSelection, output_fps: f64) -> Self {
+        let output_fps = if output_fps > 0.0 { output_fps } else { 30.0 };
+        Self {
+            selection,
+            output_fps,
+            timescale: (output_fps * TICKS_PER_FRAME as f64).round() as i32,
+            next_due: None,
+            frames_seen: 0,
+            frames_selected: 0,
+        }
+    }
+
+    pub fn output_fps(&self) -> f64 {
+        self.output_fps
+    }
+
+    /// Frames passed to [`select`](Self::select).
+    pub fn frames_seen(&self) -> u64 {
+        self.frames_seen
+    }
+
+    /// Frames kept for the time-lapse.
+    pub fn frames_selected(&self) -> u64 {
+        self.frames_selected
+    }
+
+    /// Playback length of the time-lapse so far.
+    pub fn output_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames_selected as f64 / self.output_fps)
+    }
+
+    /// Intra-heavy encoder settings for time-lapse content.
+    ///
+    /// - the frame rate is set to the output rate
+    /// - the keyframe interval defaults to half a second of output, since
+    ///   consecutive frames share little detail and long GOPs only make
+    ///   seeking slower
+    /// - frame reordering is disabled and real-time rate control is turned
+    ///   off, as frames arrive far slower than they are played
+    /// - quality defaults to 0.75
+    pub fn configure(&self, config: &mut CompressionSessionConfig) {
+        config.frame_rate = Some(self.output_fps);
+        config
+            .keyframe_interval
+            .get_or_insert(((self.output_fps / 2.0).round() as i32).max(1));
+        config.allow_frame_reordering = Some(false);
+        config.real_time = false;
+        config.low_latency = false;
+        config.quality.get_or_insert(0.75);
+    }
+
+    /// Decide whether a frame captured at `capture_time` is kept, returning
+    /// its output timestamp and duration if so.
+    pub fn select(&mut self, capture_time: CMTime) -> Option<(CMTime, CMTime)> {
+        let index = self.frames_seen;
+        self.frames_seen += 1;
+        let keep = match self.selection {
+            FrameSelection::EveryNth(n) => index.is_multiple_of(n.max(1) as u64),
+            FrameSelection::Interval(interval) => {
+                let interval = interval.as_nanos().max(1) as i128;
+                let now = nanos(capture_time);
+                match self.next_due {
+                    Some(due) if now < due => false,
+                    Some(due) => {
+                        // Stay on the original grid unless capture skipped a whole step
+                        let next = due + interval;
+                        self.next_due = Some(if now >= next { now + interval } else { next });
+                        true
+                    }
+                    None => {
+                        self.next_due = Some(now + interval);
+                        true
+                    }
+                }
+            }
+        };
+        if !keep {
+            return None;
+        }
+
+        let pts = make_time(
+            self.frames_selected as i64 * TICKS_PER_FRAME,
+            self.timescale,
+        );
+        self.frames_selected += 1;
+        Some((pts, make_time(TICKS_PER_FRAME, self.timescale)))
+    }
+
+    /// Encode the frame if it is selected, with the rewritten timestamps.
+    ///
+    /// Returns `None` for frames that are skipped.
+    ///
+    /// # Safety
+    ///
+    /// `image_buffer` must be a valid pixel buffer matching the session's dimensions.
+    pub unsafe fn encode(
+        &mut self,
+        session: &CompressionSession,
+        image_buffer: CVImageBufferRef,
+        capture_time: CMTime,
+    ) -> Result<Option<EncodeInfoFlags>, OSStatus> {
+        match self.select(capture_time) {
+            Some((pts, duration)) => session.encode_frame(image_buffer, pts, duration).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs;
+
+    #[test]
+    fn test_interval_selection_and_timestamps() {
+        let mut time_lapse = TimeLapse::new(FrameSelection::Interval(Duration::from_secs(2)), 30.0);
+        // ~1 fps capture with jitter, then a 10 second gap
+        let captures = [0, 990, 2010, 2980, 4005, 5000, 6100, 16000, 17000, 18100];
+        let kept: Vec<i64> = captures
+            .iter()
+            .filter(|&&ms| time_lapse.select(make_time(ms, 1000)).is_some())
+            .copied()
+            .collect();
+        assert_eq!(kept, [0, 2010, 4005, 6100, 16000, 18100]);
+
+        let (pts, duration) = time_lapse.select(make_time(20000, 1000)).unwrap();
+        assert_eq!((pts.value, pts.timescale), (6000, 30000));
+        assert_eq!(duration.value, 1000);
+        assert_eq!(time_lapse.frames_selected(), 7);
+        assert_eq!(time_lapse.frames_seen(), 11);
+    }
+
+    #[test]
+    fn test_every_nth_and_configure() {
+        let mut time_lapse = TimeLapse::new(FrameSelection::EveryNth(10), 29.97);
+        let kept = (0..35)
+            .filter(|&i| time_lapse.select(make_time(i, 30)).is_some())
+            .count();
+        assert_eq!(kept, 4);
+        assert_eq!(
+            time_lapse.output_duration(),
+            Duration::from_secs_f64(4.0 / 29.97)
+        );
+
+        let mut config = CompressionSessionConfig::new(1280, 720, codecs::video::H264);
+        config.real_time = true;
+        time_lapse.configure(&mut config);
+        assert_eq!(config.keyframe_interval, Some(15));
+        assert_eq!(config.allow_frame_reordering, Some(false));
+        assert!(!config.real_time);
+    }
+}
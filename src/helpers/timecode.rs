@@ -0,0 +1,558 @@
+//! SMPTE timecode and QuickTime timecode (`tmcd`) tracks.
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::audio_cmaf::write_box;
+use super::cmaf_demuxer::DemuxError;
+use super::metadata::{add_to_size, child_boxes};
+use super::mfra::{find_box, read_u32, BoxRange};
+use super::timed_metadata::{fragment_boxes, insert_trak, next_track_id, read_traf};
+
+/// `tmcd` sample description flag for drop-frame counting
+const TMCD_DROP_FRAME: u32 = 0x0001;
+/// `tmcd` sample description flag for timecodes wrapping at 24 hours
+const TMCD_24_HOUR_MAX: u32 = 0x0002;
+
+/// How timecode frames are counted: the frame rate as `timescale /
+/// frame_duration` (e.g. 30000/1001) and whether frame numbers are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimecodeFormat {
+    pub timescale: u32,
+    pub frame_duration: u32,
+    /// Skip frame numbers 0 and 1 (0-3 at 60 fps) at the start of every
+    /// minute except each tenth, so 29.97 fps timecode follows the clock
+    pub drop_frame: bool,
+}
+
+impl TimecodeFormat {
+    /// A format for `timescale / frame_duration` fps, using drop-frame
+    /// counting for the NTSC rates 29.97 and 59.94.
+    pub fn new(timescale: u32, frame_duration: u32) -> Self {
+        let frame_duration = frame_duration.max(1);
+        let nominal = (timescale + frame_duration / 2) / frame_duration;
+        Self {
+            timescale,
+            frame_duration,
+            drop_frame: !timescale.is_multiple_of(frame_duration) && nominal.is_multiple_of(30),
+        }
+    }
+
+    /// Override drop-frame counting.
+    pub fn drop_frame(mut self, enabled: bool) -> Self {
+        self.drop_frame = enabled;
+        self
+    }
+
+    /// The frame rate rounded to whole frames, e.g. 30 for 29.97: the
+    /// range of a timecode's frames field.
+    pub fn frames_per_second(&self) -> u32 {
+        let frame_duration = self.frame_duration.max(1);
+        ((self.timescale + frame_duration / 2) / frame_duration).max(1)
+    }
+
+    /// Frame numbers skipped at the start of a dropping minute.
+    fn dropped_per_minute(&self) -> u64 {
+        if self.drop_frame {
+            (self.frames_per_second() / 15) as u64
+        } else {
+            0
+        }
+    }
+
+    /// Frames counted in 24 hours of timecode.
+    pub fn frames_per_day(&self) -> u64 {
+        let fps = self.frames_per_second() as u64;
+        24 * (6 * (fps * 600 - 9 * self.dropped_per_minute()))
+    }
+
+    /// Frames elapsing in `ticks` of a `timescale` clock.
+    fn frames_in(&self, ticks: i64, timescale: u32) -> i64 {
+        let numerator = ticks as i128 * self.timescale as i128;
+        let denominator = timescale.max(1) as i128 * self.frame_duration.max(1) as i128;
+        numerator.div_euclid(denominator) as i64
+    }
+}
+
+/// A SMPTE timecode, `HH:MM:SS:FF` (`HH:MM:SS;FF` when drop-frame).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub drop_frame: bool,
+}
+
+impl Timecode {
+    pub fn new(hours: u8, minutes: u8, seconds: u8, frames: u8) -> Self {
+        Self {
+            hours,
+            minutes,
+            seconds,
+            frames,
+            drop_frame: false,
+        }
+    }
+
+    /// The timecode of frame `frame` counted from 00:00:00:00, wrapping at
+    /// 24 hours.
+    pub fn from_frame_number(frame: u64, format: &TimecodeFormat) -> Self {
+        let fps = format.frames_per_second() as u64;
+        let dropped = format.dropped_per_minute();
+        let mut frame = frame % format.frames_per_day();
+        if dropped > 0 {
+            let per_ten_minutes = fps * 600 - 9 * dropped;
+            let per_minute = fps * 60 - dropped;
+            let tens = frame / per_ten_minutes;
+            let rest = frame % per_ten_minutes;
+            frame += 9 * dropped * tens;
+            if rest > dropped {
+                frame += dropped * ((rest - dropped) / per_minute);
+            }
+        }
+        Self {
+            hours: (frame / (fps * 3600)) as u8,
+            minutes: (frame / (fps * 60) % 60) as u8,
+            seconds: (frame / fps % 60) as u8,
+            frames: (frame % fps) as u8,
+            drop_frame: format.drop_frame,
+        }
+    }
+
+    /// The frames counted from 00:00:00:00 up to this timecode.
+    pub fn frame_number(&self, format: &TimecodeFormat) -> u64 {
+        let fps = format.frames_per_second() as u64;
+        let minutes = self.hours as u64 * 60 + self.minutes as u64;
+        let nominal = (minutes * 60 + self.seconds as u64) * fps + self.frames as u64;
+        nominal - format.dropped_per_minute() * (minutes - minutes / 10)
+    }
+
+    /// The local time of day of `time` as timecode, e.g. to stamp a
+    /// recording with when it was made.
+    pub fn from_system_time(time: SystemTime, format: &TimecodeFormat) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since_epoch.as_secs() as libc::time_t;
+        let mut local: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe { libc::localtime_r(&seconds, &mut local) };
+        let seconds_of_day =
+            local.tm_hour as i64 * 3600 + local.tm_min as i64 * 60 + local.tm_sec as i64;
+        let nanos = seconds_of_day as i128 * 1_000_000_000 + since_epoch.subsec_nanos() as i128;
+        let frames = nanos * format.timescale as i128
+            / (1_000_000_000 * format.frame_duration.max(1) as i128);
+        Self::from_frame_number(frames as u64, format)
+    }
+
+    /// The current local time of day as timecode.
+    pub fn now(format: &TimecodeFormat) -> Self {
+        Self::from_system_time(SystemTime::now(), format)
+    }
+
+    /// Parse `HH:MM:SS:FF`; a `;` or `.` before the frames marks drop-frame.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (time, frames, drop_frame) = match text.rfind([':', ';', '.']) {
+            Some(at) => (&text[..at], &text[at + 1..], &text[at..at + 1] != ":"),
+            None => return None,
+        };
+        let mut fields = time.split(':').map(|field| field.parse::<u8>().ok());
+        let (hours, minutes, seconds) = (fields.next()??, fields.next()??, fields.next()??);
+        if fields.next().is_some() || minutes >= 60 || seconds >= 60 {
+            return None;
+        }
+        Some(Self {
+            hours,
+            minutes,
+            seconds,
+            frames: frames.parse().ok()?,
+            drop_frame,
+        })
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.drop_frame { ';' } else { ':' };
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours, self.minutes, self.seconds, separator, self.frames
+        )
+    }
+}
+
+/// A QuickTime timecode track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimecodeTrack {
+    pub track_id: u32,
+    /// Track timescale in ticks per second, usually the video track's
+    pub timescale: u32,
+    pub format: TimecodeFormat,
+    /// Track the timecode applies to (`tref`/`tmcd` in that track)
+    pub describes: Option<u32>,
+}
+
+impl TimecodeTrack {
+    /// Track 2 (or the next free ID), for video track 1, on the timecode's
+    /// own clock.
+    pub fn new(format: TimecodeFormat) -> Self {
+        Self {
+            track_id: 2,
+            timescale: format.timescale,
+            format,
+            describes: Some(1),
+        }
+    }
+}
+
+/// Writes a [`TimecodeTrack`] into fragmented MP4 alongside another
+/// muxer's track, as [`Fmp4Recorder`](super::Fmp4Recorder) does.
+///
+/// Editors such as Premiere, Resolve and Final Cut read the `tmcd` track to
+/// show source timecode. Each [`fragment`](Self::fragment) holds one sample:
+/// the frame number counted at the start of the fragment, on the
+/// [`TimecodeFormat`]'s clock, so the timecode stays correct when fragments
+/// are missing. Read it back with [`read_timecode`].
+#[derive(Debug, Clone)]
+pub struct TimecodeMuxer {
+    track: TimecodeTrack,
+    start: Timecode,
+    /// Track time of `start`, the start of the first fragment
+    origin: Option<i64>,
+    sequence_number: u32,
+}
+
+impl TimecodeMuxer {
+    /// Count from `start` at the first fragment, e.g. a timecode entered by
+    /// the user or [`Timecode::now`].
+    pub fn new(track: TimecodeTrack, start: Timecode) -> Self {
+        Self {
+            track,
+            start,
+            origin: None,
+            sequence_number: 1,
+        }
+    }
+
+    pub fn track(&self) -> &TimecodeTrack {
+        &self.track
+    }
+
+    /// Add the track's `trak` and `trex` to `init`, and a `tmcd` reference
+    /// to the described track. The track ID is moved past the IDs `init`
+    /// already uses. Returns `None` if `init` has no `mvhd`, or no track
+    /// to reference.
+    pub fn insert_track(&mut self, init: &mut Vec<u8>) -> Option<()> {
+        self.track.track_id = self.track.track_id.max(next_track_id(init)?);
+        if let Some(track_id) = self.track.describes {
+            insert_track_reference(init, track_id, b"tmcd", self.track.track_id)?;
+        }
+        insert_trak(init, self.track.track_id, &self.trak())
+    }
+
+    /// A fragment with one sample spanning `start..end` (in track timescale
+    /// units), or `None` if it is empty. Fragments should follow each other
+    /// without gaps, as the video fragments they accompany do.
+    pub fn fragment(&mut self, start: i64, end: i64) -> Option<Vec<u8>> {
+        if end <= start {
+            return None;
+        }
+        let origin = *self.origin.get_or_insert(start);
+        let format = &self.track.format;
+        let elapsed = format.frames_in(start - origin, self.track.timescale);
+        let frame = (self.start.frame_number(format) as i64 + elapsed)
+            .rem_euclid(format.frames_per_day() as i64) as u32;
+        let duration = (end - start).min(u32::MAX as i64) as u32;
+        let fragment = fragment_boxes(
+            self.sequence_number,
+            self.track.track_id,
+            start,
+            &[(duration, 4)],
+            &frame.to_be_bytes(),
+        );
+        self.sequence_number += 1;
+        Some(fragment)
+    }
+
+    fn trak(&self) -> Vec<u8> {
+        let format = &self.track.format;
+        let mut content = Vec::new();
+
+        let mut tkhd = vec![0, 0, 0, 3]; // version + flags (enabled, in movie)
+        tkhd.extend_from_slice(&[0; 8]); // creation and modification time
+        tkhd.extend_from_slice(&self.track.track_id.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 4]); // reserved
+        tkhd.extend_from_slice(&[0; 4]); // duration (unknown)
+        tkhd.extend_from_slice(&[0; 8]); // reserved
+        tkhd.extend_from_slice(&[0; 8]); // layer, alternate_group, volume, reserved
+        for m in [0x00010000u32, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000] {
+            tkhd.extend_from_slice(&m.to_be_bytes());
+        }
+        tkhd.extend_from_slice(&[0; 8]); // width and height
+        write_box(&mut content, b"tkhd", &tkhd);
+
+        let mut mdia = Vec::new();
+        let mut mdhd = vec![0, 0, 0, 0]; // version + flags
+        mdhd.extend_from_slice(&[0; 8]); // creation and modification time
+        mdhd.extend_from_slice(&self.track.timescale.to_be_bytes());
+        mdhd.extend_from_slice(&[0; 4]); // duration
+        mdhd.extend_from_slice(&0x55c4u16.to_be_bytes()); // language (und)
+        mdhd.extend_from_slice(&[0; 2]); // pre_defined
+        write_box(&mut mdia, b"mdhd", &mdhd);
+
+        let mut hdlr = vec![0; 8]; // version + flags, pre_defined
+        hdlr.extend_from_slice(b"tmcd");
+        hdlr.extend_from_slice(&[0; 12]); // reserved
+        hdlr.extend_from_slice(b"TimeCodeHandler\0");
+        write_box(&mut mdia, b"hdlr", &hdlr);
+
+        let mut minf = Vec::new();
+        // QuickTime base media header with the timecode display settings
+        let mut gmin = vec![0, 0, 0, 0];
+        gmin.extend_from_slice(&0x40u16.to_be_bytes()); // graphics mode (copy)
+        for _ in 0..3 {
+            gmin.extend_from_slice(&0x8000u16.to_be_bytes()); // opcolor
+        }
+        gmin.extend_from_slice(&[0; 4]); // balance, reserved
+        let mut tcmi = vec![0, 0, 0, 0];
+        tcmi.extend_from_slice(&[0; 4]); // text font, text face
+        tcmi.extend_from_slice(&12u16.to_be_bytes()); // text size
+        tcmi.extend_from_slice(&[0; 2]); // reserved
+        tcmi.extend_from_slice(&[0; 6]); // text color (black)
+        tcmi.extend_from_slice(&[0xFF; 6]); // background color (white)
+        let font = b"Lucida Grande";
+        tcmi.push(font.len() as u8);
+        tcmi.extend_from_slice(font);
+        let mut tmcd = Vec::new();
+        write_box(&mut tmcd, b"tcmi", &tcmi);
+        let mut gmhd = Vec::new();
+        write_box(&mut gmhd, b"gmin", &gmin);
+        write_box(&mut gmhd, b"tmcd", &tmcd);
+        write_box(&mut minf, b"gmhd", &gmhd);
+
+        let mut dref = vec![0, 0, 0, 0];
+        dref.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        write_box(&mut dref, b"url ", &[0, 0, 0, 1]); // self-contained
+        let mut dinf = Vec::new();
+        write_box(&mut dinf, b"dref", &dref);
+        write_box(&mut minf, b"dinf", &dinf);
+
+        let mut flags = TMCD_24_HOUR_MAX;
+        if format.drop_frame {
+            flags |= TMCD_DROP_FRAME;
+        }
+        let mut entry = vec![0; 6]; // reserved
+        entry.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+        entry.extend_from_slice(&[0; 4]); // reserved
+        entry.extend_from_slice(&flags.to_be_bytes());
+        entry.extend_from_slice(&format.timescale.to_be_bytes());
+        entry.extend_from_slice(&format.frame_duration.to_be_bytes());
+        entry.push(format.frames_per_second().min(u8::MAX as u32) as u8);
+        entry.push(0); // reserved
+        let mut stsd = vec![0, 0, 0, 0];
+        stsd.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        write_box(&mut stsd, b"tmcd", &entry);
+        let mut stbl = Vec::new();
+        write_box(&mut stbl, b"stsd", &stsd);
+        // Empty sample tables: all samples are in fragments
+        write_box(&mut stbl, b"stts", &[0; 8]);
+        write_box(&mut stbl, b"stsc", &[0; 8]);
+        write_box(&mut stbl, b"stsz", &[0; 12]);
+        write_box(&mut stbl, b"stco", &[0; 8]);
+        write_box(&mut minf, b"stbl", &stbl);
+        write_box(&mut mdia, b"minf", &minf);
+        write_box(&mut content, b"mdia", &mdia);
+
+        let mut buf = Vec::new();
+        write_box(&mut buf, b"trak", &content);
+        buf
+    }
+}
+
+/// Add a `kind` reference (e.g. `tmcd`) to track `referenced` in the
+/// `tref` of track `track_id`, creating the `tref` after its `tkhd` if
+/// needed.
+pub(super) fn insert_track_reference(
+    init: &mut Vec<u8>,
+    track_id: u32,
+    kind: &[u8; 4],
+    referenced: u32,
+) -> Option<()> {
+    let moov = find_box(init, 0, init.len(), b"moov")?;
+    let trak = child_boxes(init, moov, b"trak")
+        .into_iter()
+        .find(|&trak| trak_id(init, trak) == Some(track_id))?;
+    let mut reference = Vec::new();
+    write_box(&mut reference, kind, &referenced.to_be_bytes());
+
+    let (at, grown) = match find_box(init, trak.content(), trak.end(), b"tref") {
+        Some(tref) => {
+            add_to_size(init, tref, reference.len() as isize);
+            (tref.end(), reference)
+        }
+        None => {
+            let tkhd = find_box(init, trak.content(), trak.end(), b"tkhd")?;
+            let mut tref = Vec::new();
+            write_box(&mut tref, b"tref", &reference);
+            (tkhd.end(), tref)
+        }
+    };
+    add_to_size(init, trak, grown.len() as isize);
+    add_to_size(init, moov, grown.len() as isize);
+    init.splice(at..at, grown);
+    Some(())
+}
+
+/// The `track_ID` in the `tkhd` of `trak`.
+pub(super) fn trak_id(data: &[u8], trak: BoxRange) -> Option<u32> {
+    let tkhd = find_box(data, trak.content(), trak.end(), b"tkhd")?;
+    // After the creation and modification times, 64-bit in version 1
+    let field = match data.get(tkhd.content())? {
+        1 => 20,
+        _ => 12,
+    };
+    read_u32(data, tkhd.content() + field)
+}
+
+/// A timecode track read back from a fragmented MP4 file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedTimecode {
+    pub track: TimecodeTrack,
+    /// Timecode of the first sample
+    pub start: Timecode,
+}
+
+/// Extract the first `tmcd` track of a fragmented MP4 file, e.g. one
+/// written by [`Fmp4Recorder`](super::Fmp4Recorder).
+///
+/// Returns `Ok(None)` if the file has no timecode track or no timecode
+/// samples in its fragments.
+pub fn read_timecode(file: &[u8]) -> Result<Option<RecordedTimecode>, DemuxError> {
+    let moov = find_box(file, 0, file.len(), b"moov").ok_or(DemuxError::MissingInitSegment)?;
+    let Some(track) = child_boxes(file, moov, b"trak")
+        .into_iter()
+        .find_map(|trak| parse_timecode_trak(file, moov, trak))
+    else {
+        return Ok(None);
+    };
+
+    let mut samples = Vec::new();
+    let mut at = 0;
+    while let Some(moof) = find_box(file, at, file.len(), b"moof") {
+        at = moof.end();
+        for traf in child_boxes(file, moof, b"traf") {
+            read_traf(file, moof, traf, track.track_id, &mut samples)
+                .ok_or(DemuxError::InvalidBox(*b"traf"))?;
+        }
+    }
+    let first = samples.iter().min_by_key(|sample| sample.pts);
+    Ok(first.and_then(|sample| {
+        let frame = u32::from_be_bytes(sample.data.get(..4)?.try_into().ok()?);
+        Some(RecordedTimecode {
+            track,
+            start: Timecode::from_frame_number(frame as u64, &track.format),
+        })
+    }))
+}
+
+/// The track described by `trak` if it is a `tmcd` track.
+fn parse_timecode_trak(file: &[u8], moov: BoxRange, trak: BoxRange) -> Option<TimecodeTrack> {
+    let tkhd = find_box(file, trak.content(), trak.end(), b"tkhd")?;
+    let track_id = read_u32(file, tkhd.content() + 12)?;
+    let mdia = find_box(file, trak.content(), trak.end(), b"mdia")?;
+    let hdlr = find_box(file, mdia.content(), mdia.end(), b"hdlr")?;
+    if file.get(hdlr.content() + 8..hdlr.content() + 12)? != b"tmcd" {
+        return None;
+    }
+    let mdhd = find_box(file, mdia.content(), mdia.end(), b"mdhd")?;
+    let timescale = read_u32(file, mdhd.content() + 12)?;
+    let stsd = find_box(file, mdia.content(), mdia.end(), b"minf")
+        .and_then(|minf| find_box(file, minf.content(), minf.end(), b"stbl"))
+        .and_then(|stbl| find_box(file, stbl.content(), stbl.end(), b"stsd"))?;
+    let entry = find_box(file, stsd.content() + 8, stsd.end(), b"tmcd")?;
+    let flags = read_u32(file, entry.content() + 12)?;
+    let format = TimecodeFormat {
+        timescale: read_u32(file, entry.content() + 16)?,
+        frame_duration: read_u32(file, entry.content() + 20)?,
+        drop_frame: flags & TMCD_DROP_FRAME != 0,
+    };
+    // The track referencing this one through `tref`/`tmcd`
+    let describes = child_boxes(file, moov, b"trak")
+        .into_iter()
+        .find_map(|other| {
+            let tref = find_box(file, other.content(), other.end(), b"tref")?;
+            let tmcd = find_box(file, tref.content(), tref.end(), b"tmcd")?;
+            if read_u32(file, tmcd.content())? != track_id {
+                return None;
+            }
+            let tkhd = find_box(file, other.content(), other.end(), b"tkhd")?;
+            read_u32(file, tkhd.content() + 12)
+        });
+    Some(TimecodeTrack {
+        track_id,
+        timescale,
+        format,
+        describes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{CmafConfig, CmafMuxer};
+
+    #[test]
+    fn test_drop_frame_counting() {
+        let ntsc = TimecodeFormat::new(30000, 1001);
+        assert!(ntsc.drop_frame);
+        assert_eq!(ntsc.frames_per_second(), 30);
+        assert!(!TimecodeFormat::new(25, 1).drop_frame);
+
+        // Frame numbers 00 and 01 are skipped after 00:00:59;29 ...
+        let minute = Timecode::from_frame_number(1800, &ntsc);
+        assert_eq!(minute.to_string(), "00:01:00;02");
+        assert_eq!(minute.frame_number(&ntsc), 1800);
+        // ... but not at every tenth minute
+        let ten = Timecode::from_frame_number(17982, &ntsc);
+        assert_eq!(ten.to_string(), "00:10:00;00");
+        // One hour of 29.97 fps video is 01:00:00;00
+        assert_eq!(
+            Timecode::from_frame_number(107892, &ntsc),
+            Timecode::parse("01:00:00;00").unwrap()
+        );
+        let last = ntsc.frames_per_day() - 1;
+        for frame in [0, 1799, 1800, 17981, 17982, 100_000, last] {
+            let timecode = Timecode::from_frame_number(frame, &ntsc);
+            assert_eq!(timecode.frame_number(&ntsc), frame, "{}", timecode);
+        }
+
+        let pal = TimecodeFormat::new(25, 1);
+        let timecode = Timecode::parse("10:00:00:12").unwrap();
+        assert_eq!(timecode.frame_number(&pal), 36000 * 25 + 12);
+        assert_eq!(Timecode::parse("10:00:60:00"), None);
+    }
+
+    #[test]
+    fn test_track_round_trip() {
+        let mut video = CmafMuxer::new(CmafConfig::default());
+        let mut file = video.create_init_segment(&[0x67, 0x64, 0, 0x1f], &[0x68], 640, 480);
+        let format = TimecodeFormat::new(30000, 1001);
+        let mut track = TimecodeTrack::new(format);
+        track.timescale = 90000;
+        let start = Timecode::parse("01:00:00;00").unwrap();
+        let mut muxer = TimecodeMuxer::new(track, start);
+        muxer.insert_track(&mut file).unwrap();
+
+        file.extend(muxer.fragment(3003, 3003 + 90090).unwrap());
+        // 30 frames later
+        let second = muxer.fragment(3003 + 90090, 3003 + 180180).unwrap();
+        assert_eq!(muxer.fragment(10, 10), None);
+
+        let read = read_timecode(&file).unwrap().unwrap();
+        assert_eq!(read.start, start);
+        assert_eq!(read.track, *muxer.track());
+        assert_eq!(read.track.describes, Some(1));
+        let frame = u32::from_be_bytes(second[second.len() - 4..].try_into().unwrap());
+        assert_eq!(frame as u64, start.frame_number(&format) + 30);
+    }
+}
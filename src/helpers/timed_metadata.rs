@@ -0,0 +1,520 @@
+//! Timed metadata tracks for telemetry such as GPS, pose or sensor data.
+
+use super::audio_cmaf::write_box;
+use super::cmaf_demuxer::{DemuxError, MAX_TRUN_SAMPLES};
+use super::metadata::{add_to_size, child_boxes};
+use super::mfra::{find_box, read_u32, read_u64, BoxRange};
+use super::timecode::trak_id;
+
+/// Offset of `next_track_ID` in a version 0 `mvhd` payload
+const MVHD_NEXT_TRACK_ID: usize = 96;
+
+/// Offset of `next_track_ID` in a version 1 (64-bit times) `mvhd` payload
+const MVHD_V1_NEXT_TRACK_ID: usize = 108;
+
+/// A timed metadata track: samples of one MIME type, such as
+/// `application/json` GPS fixes or a binary pose format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedMetadataTrack {
+    pub track_id: u32,
+    /// Track timescale in ticks per second
+    pub timescale: u32,
+    /// `mime_format` of the `mett` sample entry
+    pub mime_type: String,
+    /// Track the samples describe (`tref`/`cdsc`), usually the video track
+    pub describes: Option<u32>,
+}
+
+impl TimedMetadataTrack {
+    /// Track 2, describing video track 1, at millisecond resolution.
+    pub fn new(mime_type: impl Into<String>) -> Self {
+        Self {
+            track_id: 2,
+            timescale: 1000,
+            mime_type: mime_type.into(),
+            describes: Some(1),
+        }
+    }
+}
+
+/// One timed metadata sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedMetadataSample {
+    /// Presentation time in track timescale units
+    pub pts: i64,
+    /// How long the sample applies, in track timescale units
+    pub duration: u32,
+    pub data: Vec<u8>,
+}
+
+/// Writes a [`TimedMetadataTrack`] into fragmented MP4 alongside another
+/// muxer's track, as [`Fmp4Recorder`](super::Fmp4Recorder) does.
+///
+/// [`insert_track`](Self::insert_track) adds the track to an existing init
+/// segment, e.g. from [`CmafMuxer`](super::CmafMuxer), and
+/// [`fragment_until`](Self::fragment_until) returns a `moof` + `mdat` pair
+/// to write after each of its fragments. Samples are ISO/IEC 14496-12
+/// `mett` text metadata; gaps between them are filled with empty samples so
+/// every sample keeps its exact time and duration. Read them back with
+/// [`read_timed_metadata`].
+#[derive(Debug, Clone)]
+pub struct TimedMetadataMuxer {
+    track: TimedMetadataTrack,
+    pending: Vec<TimedMetadataSample>,
+    sequence_number: u32,
+}
+
+impl TimedMetadataMuxer {
+    pub fn new(track: TimedMetadataTrack) -> Self {
+        Self {
+            track,
+            pending: Vec::new(),
+            sequence_number: 1,
+        }
+    }
+
+    pub fn track(&self) -> &TimedMetadataTrack {
+        &self.track
+    }
+
+    /// Queue a sample. Samples may arrive out of order; a sample overlapping
+    /// the next one is cut short when written.
+    pub fn add_sample(&mut self, pts: i64, duration: u32, data: Vec<u8>) {
+        let at = self.pending.partition_point(|sample| sample.pts <= pts);
+        let sample = TimedMetadataSample {
+            pts,
+            duration,
+            data,
+        };
+        self.pending.insert(at, sample);
+    }
+
+    pub fn pending_sample_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Add the track's `trak` and `trex` to `init`, an init segment with an
+    /// `mvex`. Returns `None` if it has no `mvhd`.
+    pub fn insert_track(&self, init: &mut Vec<u8>) -> Option<()> {
+        insert_trak(init, self.track.track_id, &self.trak())
+    }
+
+    /// A fragment with the queued samples starting before `end` (in track
+    /// timescale units), or `None` if there are none.
+    pub fn fragment_until(&mut self, end: i64) -> Option<Vec<u8>> {
+        let count = self.pending.partition_point(|sample| sample.pts < end);
+        if count == 0 {
+            return None;
+        }
+        let samples: Vec<_> = self.pending.drain(..count).collect();
+        let next_pts = self.pending.first().map(|sample| sample.pts);
+        Some(self.write_fragment(&samples, next_pts))
+    }
+
+    /// A fragment with every queued sample.
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        self.fragment_until(i64::MAX)
+    }
+
+    fn trak(&self) -> Vec<u8> {
+        let mut content = Vec::new();
+
+        let mut tkhd = vec![0, 0, 0, 3]; // version + flags (enabled, in movie)
+        tkhd.extend_from_slice(&[0; 8]); // creation and modification time
+        tkhd.extend_from_slice(&self.track.track_id.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 4]); // reserved
+        tkhd.extend_from_slice(&[0; 4]); // duration (unknown)
+        tkhd.extend_from_slice(&[0; 8]); // reserved
+        tkhd.extend_from_slice(&[0; 8]); // layer, alternate_group, volume, reserved
+        for m in [0x00010000u32, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000] {
+            tkhd.extend_from_slice(&m.to_be_bytes());
+        }
+        tkhd.extend_from_slice(&[0; 8]); // width and height
+        write_box(&mut content, b"tkhd", &tkhd);
+
+        if let Some(track_id) = self.track.describes {
+            let mut tref = Vec::new();
+            write_box(&mut tref, b"cdsc", &track_id.to_be_bytes());
+            write_box(&mut content, b"tref", &tref);
+        }
+
+        let mut mdia = Vec::new();
+        let mut mdhd = vec![0, 0, 0, 0]; // version + flags
+        mdhd.extend_from_slice(&[0; 8]); // creation and modification time
+        mdhd.extend_from_slice(&self.track.timescale.to_be_bytes());
+        mdhd.extend_from_slice(&[0; 4]); // duration
+        mdhd.extend_from_slice(&0x55c4u16.to_be_bytes()); // language (und)
+        mdhd.extend_from_slice(&[0; 2]); // pre_defined
+        write_box(&mut mdia, b"mdhd", &mdhd);
+
+        let mut hdlr = vec![0; 8]; // version + flags, pre_defined
+        hdlr.extend_from_slice(b"meta");
+        hdlr.extend_from_slice(&[0; 12]); // reserved
+        hdlr.extend_from_slice(b"TimedMetadataHandler\0");
+        write_box(&mut mdia, b"hdlr", &hdlr);
+
+        let mut minf = Vec::new();
+        write_box(&mut minf, b"nmhd", &[0; 4]);
+        let mut dref = vec![0, 0, 0, 0];
+        dref.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        write_box(&mut dref, b"url ", &[0, 0, 0, 1]); // self-contained
+        let mut dinf = Vec::new();
+        write_box(&mut dinf, b"dref", &dref);
+        write_box(&mut minf, b"dinf", &dinf);
+
+        let mut mett = vec![0; 6]; // reserved
+        mett.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+        mett.push(0); // content_encoding (none)
+        mett.extend_from_slice(self.track.mime_type.as_bytes());
+        mett.push(0);
+        let mut stsd = vec![0, 0, 0, 0];
+        stsd.extend_from_slice(&1u32.to_be_bytes()); // entry_count
+        write_box(&mut stsd, b"mett", &mett);
+        let mut stbl = Vec::new();
+        write_box(&mut stbl, b"stsd", &stsd);
+        // Empty sample tables: all samples are in fragments
+        write_box(&mut stbl, b"stts", &[0; 8]);
+        write_box(&mut stbl, b"stsc", &[0; 8]);
+        write_box(&mut stbl, b"stsz", &[0; 12]);
+        write_box(&mut stbl, b"stco", &[0; 8]);
+        write_box(&mut minf, b"stbl", &stbl);
+        write_box(&mut mdia, b"minf", &minf);
+        write_box(&mut content, b"mdia", &mdia);
+
+        let mut buf = Vec::new();
+        write_box(&mut buf, b"trak", &content);
+        buf
+    }
+
+    /// `moof` + `mdat` for `samples`, with empty samples over the gaps and
+    /// the last sample cut short if it overlaps `next_pts`.
+    fn write_fragment(
+        &mut self,
+        samples: &[TimedMetadataSample],
+        next_pts: Option<i64>,
+    ) -> Vec<u8> {
+        // (duration, size) of each sample, including gap fillers
+        let mut entries: Vec<(u32, u32)> = Vec::new();
+        for (i, sample) in samples.iter().enumerate() {
+            let next = samples.get(i + 1).map(|s| s.pts).or(next_pts);
+            let mut duration = sample.duration;
+            if let Some(next) = next {
+                duration = duration.min((next - sample.pts).clamp(0, u32::MAX as i64) as u32);
+            }
+            entries.push((duration, sample.data.len() as u32));
+            let end = sample.pts + duration as i64;
+            if let Some(gap) = samples
+                .get(i + 1)
+                .map(|s| s.pts - end)
+                .filter(|&gap| gap > 0)
+            {
+                entries.push((gap.min(u32::MAX as i64) as u32, 0));
+            }
+        }
+
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|s| s.data.iter().copied())
+            .collect();
+        let fragment = fragment_boxes(
+            self.sequence_number,
+            self.track.track_id,
+            samples[0].pts,
+            &entries,
+            &data,
+        );
+        self.sequence_number += 1;
+        fragment
+    }
+}
+
+/// Add `trak` for `track_id` to `init`, with a `trex` if it has an `mvex`,
+/// and move `next_track_ID` past it. Returns `None` if `init` has no
+/// `mvhd`.
+pub(super) fn insert_trak(init: &mut Vec<u8>, track_id: u32, trak: &[u8]) -> Option<()> {
+    let moov = find_box(init, 0, init.len(), b"moov")?;
+    let next_track_id = next_track_id_offset(init, moov)?;
+    let next = read_u32(init, next_track_id)?.max(track_id + 1);
+    init[next_track_id..next_track_id + 4].copy_from_slice(&next.to_be_bytes());
+
+    let Some(mvex) = find_box(init, moov.content(), moov.end(), b"mvex") else {
+        init.splice(moov.end()..moov.end(), trak.iter().copied());
+        add_to_size(init, moov, trak.len() as isize);
+        return Some(());
+    };
+    let mut trex = vec![0, 0, 0, 0]; // version + flags
+    trex.extend_from_slice(&track_id.to_be_bytes());
+    trex.extend_from_slice(&1u32.to_be_bytes()); // default_sample_description_index
+    trex.extend_from_slice(&[0; 12]); // default duration, size, flags
+    let mut trex_box = Vec::new();
+    write_box(&mut trex_box, b"trex", &trex);
+
+    init.splice(mvex.end()..mvex.end(), trex_box.iter().copied());
+    add_to_size(init, mvex, trex_box.len() as isize);
+    init.splice(mvex.offset..mvex.offset, trak.iter().copied());
+    add_to_size(init, moov, (trak.len() + trex_box.len()) as isize);
+    Some(())
+}
+
+/// The `next_track_ID` of an init segment's `mvhd`.
+pub(super) fn next_track_id(init: &[u8]) -> Option<u32> {
+    let moov = find_box(init, 0, init.len(), b"moov")?;
+    read_u32(init, next_track_id_offset(init, moov)?)
+}
+
+/// Offset of the `next_track_ID` field in `moov`'s `mvhd`.
+fn next_track_id_offset(data: &[u8], moov: BoxRange) -> Option<usize> {
+    let mvhd = find_box(data, moov.content(), moov.end(), b"mvhd")?;
+    let field = match data.get(mvhd.content())? {
+        1 => MVHD_V1_NEXT_TRACK_ID,
+        _ => MVHD_NEXT_TRACK_ID,
+    };
+    Some(mvhd.content() + field)
+}
+
+/// `moof` + `mdat` for one track, with a sample per `(duration, size)`
+/// entry starting at `base_time`. Every sample is a sync sample.
+pub(super) fn fragment_boxes(
+    sequence_number: u32,
+    track_id: u32,
+    base_time: i64,
+    entries: &[(u32, u32)],
+    data: &[u8],
+) -> Vec<u8> {
+    let mut traf = Vec::new();
+    let mut tfhd = vec![0, 0x02, 0, 0]; // default-base-is-moof
+    tfhd.extend_from_slice(&track_id.to_be_bytes());
+    write_box(&mut traf, b"tfhd", &tfhd);
+    let mut tfdt = vec![1, 0, 0, 0]; // version 1 (64-bit time)
+    tfdt.extend_from_slice(&(base_time.max(0) as u64).to_be_bytes());
+    write_box(&mut traf, b"tfdt", &tfdt);
+
+    // moof header + mfhd + traf header + tfhd + tfdt + trun
+    let trun_size = 8 + 12 + entries.len() * 12;
+    let moof_size = 8 + 16 + 8 + 16 + 20 + trun_size;
+    // data-offset, sample-duration, sample-size and sample-flags present
+    let mut trun = vec![0, 0x00, 0x07, 0x01];
+    trun.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    trun.extend_from_slice(&((moof_size + 8) as u32).to_be_bytes());
+    for (duration, size) in entries {
+        trun.extend_from_slice(&duration.to_be_bytes());
+        trun.extend_from_slice(&size.to_be_bytes());
+        trun.extend_from_slice(&0x02000000u32.to_be_bytes()); // sync, depends on none
+    }
+    write_box(&mut traf, b"trun", &trun);
+
+    let mut moof = Vec::new();
+    let mut mfhd = vec![0, 0, 0, 0];
+    mfhd.extend_from_slice(&sequence_number.to_be_bytes());
+    write_box(&mut moof, b"mfhd", &mfhd);
+    write_box(&mut moof, b"traf", &traf);
+
+    let mut buf = Vec::new();
+    write_box(&mut buf, b"moof", &moof);
+    write_box(&mut buf, b"mdat", data);
+    buf
+}
+
+/// A timed metadata track read back from a fragmented MP4 file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedMetadata {
+    pub track: TimedMetadataTrack,
+    /// Non-empty samples in presentation order
+    pub samples: Vec<TimedMetadataSample>,
+}
+
+/// Extract the first `mett` timed metadata track of a fragmented MP4 file,
+/// e.g. one written by [`Fmp4Recorder`](super::Fmp4Recorder).
+///
+/// Returns `Ok(None)` if the file has no such track. Samples described in
+/// the `moov` itself (non-fragmented files) are not read.
+pub fn read_timed_metadata(file: &[u8]) -> Result<Option<TimedMetadata>, DemuxError> {
+    let moov = find_box(file, 0, file.len(), b"moov").ok_or(DemuxError::MissingInitSegment)?;
+    let Some(track) = child_boxes(file, moov, b"trak")
+        .into_iter()
+        .find_map(|trak| parse_metadata_trak(file, trak))
+    else {
+        return Ok(None);
+    };
+
+    let mut samples = Vec::new();
+    let mut at = 0;
+    while let Some(moof) = find_box(file, at, file.len(), b"moof") {
+        at = moof.end();
+        for traf in child_boxes(file, moof, b"traf") {
+            read_traf(file, moof, traf, track.track_id, &mut samples)
+                .ok_or(DemuxError::InvalidBox(*b"traf"))?;
+        }
+    }
+    samples.sort_by_key(|sample| sample.pts);
+    Ok(Some(TimedMetadata { track, samples }))
+}
+
+/// The track described by `trak` if it is a `meta` track with a `mett`
+/// sample entry.
+fn parse_metadata_trak(file: &[u8], trak: BoxRange) -> Option<TimedMetadataTrack> {
+    let track_id = trak_id(file, trak)?;
+    let mdia = find_box(file, trak.content(), trak.end(), b"mdia")?;
+    let hdlr = find_box(file, mdia.content(), mdia.end(), b"hdlr")?;
+    if file.get(hdlr.content() + 8..hdlr.content() + 12)? != b"meta" {
+        return None;
+    }
+    let mdhd = find_box(file, mdia.content(), mdia.end(), b"mdhd")?;
+    let timescale = read_u32(file, mdhd.content() + 12)?;
+    let stsd = find_box(file, mdia.content(), mdia.end(), b"minf")
+        .and_then(|minf| find_box(file, minf.content(), minf.end(), b"stbl"))
+        .and_then(|stbl| find_box(file, stbl.content(), stbl.end(), b"stsd"))?;
+    let mett = find_box(file, stsd.content() + 8, stsd.end(), b"mett")?;
+    // content_encoding then mime_format, both null-terminated
+    let mut strings = file.get(mett.content() + 8..mett.end())?.split(|&b| b == 0);
+    strings.next()?;
+    let mime_type = String::from_utf8_lossy(strings.next()?).into_owned();
+    let describes = find_box(file, trak.content(), trak.end(), b"tref")
+        .and_then(|tref| find_box(file, tref.content(), tref.end(), b"cdsc"))
+        .and_then(|cdsc| read_u32(file, cdsc.content()));
+    Some(TimedMetadataTrack {
+        track_id,
+        timescale,
+        mime_type,
+        describes,
+    })
+}
+
+/// Append the non-empty samples of `traf` if it belongs to `track_id`.
+pub(super) fn read_traf(
+    file: &[u8],
+    moof: BoxRange,
+    traf: BoxRange,
+    track_id: u32,
+    samples: &mut Vec<TimedMetadataSample>,
+) -> Option<()> {
+    let tfhd = find_box(file, traf.content(), traf.end(), b"tfhd")?;
+    let tfhd_flags = read_u32(file, tfhd.content())? & 0x00FF_FFFF;
+    if read_u32(file, tfhd.content() + 4)? != track_id {
+        return Some(());
+    }
+    let mut field = tfhd.content() + 8;
+    let mut base = moof.offset as u64;
+    if tfhd_flags & 0x01 != 0 {
+        base = read_u64(file, field)?;
+        field += 8;
+    }
+    if tfhd_flags & 0x02 != 0 {
+        field += 4; // sample_description_index
+    }
+    let default_duration = if tfhd_flags & 0x08 != 0 {
+        let duration = read_u32(file, field)?;
+        field += 4;
+        duration
+    } else {
+        0
+    };
+    let default_size = if tfhd_flags & 0x10 != 0 {
+        read_u32(file, field)?
+    } else {
+        0
+    };
+
+    let tfdt = find_box(file, traf.content(), traf.end(), b"tfdt")?;
+    let mut pts = match file.get(tfdt.content())? {
+        1 => i64::try_from(read_u64(file, tfdt.content() + 4)?).ok()?,
+        _ => read_u32(file, tfdt.content() + 4)? as i64,
+    };
+    for trun in child_boxes(file, traf, b"trun") {
+        let flags = read_u32(file, trun.content())? & 0x00FF_FFFF;
+        let count = read_u32(file, trun.content() + 4)?;
+        let mut at = trun.content() + 8;
+        let mut offset = base;
+        if flags & 0x01 != 0 {
+            offset = base.checked_add_signed(read_u32(file, at)? as i32 as i64)?;
+            at += 4;
+        }
+        if flags & 0x04 != 0 {
+            at += 4; // first_sample_flags
+        }
+        // Duration, size, flags and composition offset, 4 bytes each
+        let sample_len = 4 * (flags & 0xF00).count_ones() as usize;
+        let entries = trun.end().checked_sub(at)?;
+        if (sample_len == 0 && count > MAX_TRUN_SAMPLES)
+            || (count as usize).checked_mul(sample_len)? > entries
+        {
+            return None;
+        }
+        for _ in 0..count {
+            let mut next = |present: u32, default: u32| -> Option<u32> {
+                if flags & present == 0 {
+                    return Some(default);
+                }
+                let value = read_u32(file, at)?;
+                at += 4;
+                Some(value)
+            };
+            let duration = next(0x100, default_duration)?;
+            let size = next(0x200, default_size)?;
+            next(0x400, 0)?; // sample_flags
+            next(0x800, 0)?; // composition offset
+            if size > 0 {
+                let start = usize::try_from(offset).ok()?;
+                let end = start.checked_add(size as usize)?;
+                let data = file.get(start..end)?.to_vec();
+                samples.push(TimedMetadataSample {
+                    pts,
+                    duration,
+                    data,
+                });
+            }
+            offset = offset.checked_add(size as u64)?;
+            pts = pts.checked_add(duration as i64)?;
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{CmafConfig, CmafMuxer};
+
+    #[test]
+    fn test_samples_round_trip_with_gaps() {
+        let mut video = CmafMuxer::new(CmafConfig::default());
+        let mut file = video.create_init_segment(&[0x67, 0x64, 0, 0x1f], &[0x68], 640, 480);
+        let mut muxer = TimedMetadataMuxer::new(TimedMetadataTrack::new("application/json"));
+        muxer.insert_track(&mut file).unwrap();
+
+        muxer.add_sample(400, 100, b"{\"lat\":2}".to_vec());
+        muxer.add_sample(0, 50, b"{\"lat\":0}".to_vec());
+        // Overlaps the next sample, so it is cut to 300
+        muxer.add_sample(100, 350, b"{\"lat\":1}".to_vec());
+        file.extend(muxer.fragment_until(300).unwrap());
+        assert_eq!(muxer.fragment_until(300), None);
+        file.extend(muxer.flush().unwrap());
+
+        let read = read_timed_metadata(&file).unwrap().unwrap();
+        assert_eq!(read.track, TimedMetadataTrack::new("application/json"));
+        let times: Vec<(i64, u32)> = read.samples.iter().map(|s| (s.pts, s.duration)).collect();
+        assert_eq!(times, [(0, 50), (100, 300), (400, 100)]);
+        assert_eq!(read.samples[2].data, b"{\"lat\":2}");
+
+        // The track follows the video one, and new tracks get ids after it
+        let moov = find_box(&file, 0, file.len(), b"moov").unwrap();
+        assert_eq!(child_boxes(&file, moov, b"trak").len(), 2);
+        let mvhd = find_box(&file, moov.content(), moov.end(), b"mvhd").unwrap();
+        assert_eq!(
+            read_u32(&file, mvhd.content() + MVHD_NEXT_TRACK_ID),
+            Some(3)
+        );
+
+        // Truncated or corrupted files are errors, never panics
+        for len in 0..file.len() {
+            let _ = read_timed_metadata(&file[..len]);
+        }
+        for i in 0..file.len() {
+            for value in [0x00, 0x80, 0xFF] {
+                let mut corrupt = file.clone();
+                corrupt[i] = value;
+                let _ = read_timed_metadata(&corrupt);
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Capture timestamp filtering with drift compensation.
+//!
+//! Camera and microphone clocks run slightly fast or slow relative to the
+//! wall clock. Over hours the difference adds up (100 ppm is 360 ms per hour),
+//! so segment durations computed from capture timestamps creep away from real
+//! time. [`TimestampFilter`] keeps the output timeline locked to the wall clock
+//! by slewing frame durations within a configurable ppm bound.
+
+use std::time::Duration;
+
+use super::clock::host_time_now;
+use super::nal_extractor::convert_time;
+
+/// Maps capture timestamps onto a monotonic timeline locked to the wall clock.
+///
+/// Each frame's output duration is its capture duration scaled by a small
+/// correction (at most [`max_slew_ppm`](Self::max_slew_ppm)), chosen to pull the
+/// output timeline back towards elapsed wall time over the
+/// [`correction_window`](Self::correction_window). Arrival jitter is smoothed
+/// out before it affects the correction, and the output never goes backwards.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::TimestampFilter;
+///
+/// let mut filter = TimestampFilter::new(90000).max_slew_ppm(200.0);
+/// # let capture_pts = 0;
+/// let pts = filter.push_host_time(capture_pts);
+/// println!("drift {:.1} ppm, slewing {:.1} ppm", filter.drift_ppm(), filter.slew_ppm());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TimestampFilter {
+    timescale: i32,
+    max_slew_ppm: f64,
+    correction_window: Duration,
+    smoothing: f64,
+    state: Option<FilterState>,
+}
+
+#[derive(Debug, Clone)]
+struct FilterState {
+    first_capture: i64,
+    first_wall: i64,
+    last_capture: i64,
+    last_wall: i64,
+    /// Output time relative to the first frame, with fractional ticks kept
+    output: f64,
+    /// Smoothed (output - wall) error in ticks
+    error: f64,
+    slew_ppm: f64,
+}
+
+impl TimestampFilter {
+    /// Create a filter producing timestamps in `timescale` units.
+    ///
+    /// Defaults: 500 ppm maximum slew, 30 second correction window.
+    pub fn new(timescale: i32) -> Self {
+        Self {
+            timescale,
+            max_slew_ppm: 500.0,
+            correction_window: Duration::from_secs(30),
+            smoothing: 0.02,
+            state: None,
+        }
+    }
+
+    /// Limit how far frame durations may be stretched or shrunk, in parts per
+    /// million. Larger bounds lock faster but are more visible as pacing changes.
+    pub fn max_slew_ppm(mut self, ppm: f64) -> Self {
+        self.max_slew_ppm = ppm.abs();
+        self
+    }
+
+    /// Time over which an offset from the wall clock is corrected.
+    pub fn correction_window(mut self, window: Duration) -> Self {
+        self.correction_window = window.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Weight of each new error sample in the jitter filter (0 to 1).
+    pub fn smoothing(mut self, weight: f64) -> Self {
+        self.smoothing = weight.clamp(f64::EPSILON, 1.0);
+        self
+    }
+
+    pub fn timescale(&self) -> i32 {
+        self.timescale
+    }
+
+    /// Filter a capture timestamp observed at `wall_time`.
+    ///
+    /// Both are in `timescale` units; `wall_time` should come from a clock that
+    /// tracks real time (e.g., host time at arrival). Returns the output PTS,
+    /// relative to the first frame.
+    pub fn push(&mut self, capture_pts: i64, wall_time: i64) -> i64 {
+        let Some(state) = self.state.as_mut() else {
+            self.state = Some(FilterState {
+                first_capture: capture_pts,
+                first_wall: wall_time,
+                last_capture: capture_pts,
+                last_wall: wall_time,
+                output: 0.0,
+                error: 0.0,
+                slew_ppm: 0.0,
+            });
+            return 0;
+        };
+
+        // Never let the output go backwards, even if capture timestamps do
+        let capture_delta = (capture_pts - state.last_capture).max(1) as f64;
+        state.last_capture = capture_pts;
+        state.last_wall = wall_time;
+        state.output += capture_delta * (1.0 + state.slew_ppm * 1e-6);
+
+        let error = state.output - (wall_time - state.first_wall) as f64;
+        state.error += (error - state.error) * self.smoothing;
+
+        // Proportional correction: remove the smoothed error over the window
+        let window = self.correction_window.as_secs_f64() * self.timescale as f64;
+        state.slew_ppm = (-state.error / window * 1e6).clamp(-self.max_slew_ppm, self.max_slew_ppm);
+
+        state.output.round() as i64
+    }
+
+    /// Filter a capture timestamp, using the current host time as `wall_time`.
+    pub fn push_host_time(&mut self, capture_pts: i64) -> i64 {
+        let now = convert_time(host_time_now(), self.timescale);
+        self.push(capture_pts, now)
+    }
+
+    /// Long-term drift of the capture clock relative to the wall clock, in ppm.
+    ///
+    /// Positive means capture timestamps advance faster than real time.
+    pub fn drift_ppm(&self) -> f64 {
+        match &self.state {
+            Some(s) if s.last_wall != s.first_wall => {
+                let capture = (s.last_capture - s.first_capture) as f64;
+                let wall = (s.last_wall - s.first_wall) as f64;
+                (capture / wall - 1.0) * 1e6
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Correction currently applied to frame durations, in ppm.
+    pub fn slew_ppm(&self) -> f64 {
+        self.state.as_ref().map_or(0.0, |s| s.slew_ppm)
+    }
+
+    /// Smoothed offset of the output timeline from the wall clock, in ticks.
+    pub fn error_ticks(&self) -> f64 {
+        self.state.as_ref().map_or(0.0, |s| s.error)
+    }
+
+    /// Forget all history; the next frame starts a new timeline at zero.
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMESCALE: i64 = 90000;
+    const FRAME: i64 = 3000;
+
+    /// Run `frames` frames whose capture clock drifts by `drift_ppm`, with
+    /// arrival jitter of up to +-5 ms. Returns the final (output - wall) error.
+    fn simulate(filter: &mut TimestampFilter, frames: i64, drift_ppm: f64) -> i64 {
+        let mut last = 0;
+        let mut error = 0;
+        for i in 0..frames {
+            let wall = i * FRAME;
+            let capture = (wall as f64 * (1.0 + drift_ppm * 1e-6)) as i64;
+            let jitter = ((i * 7919) % 901) - 450;
+            let pts = filter.push(capture, wall + jitter);
+            assert!(i == 0 || pts > last, "output went backwards at frame {}", i);
+            last = pts;
+            error = pts - wall;
+        }
+        error
+    }
+
+    #[test]
+    fn test_locks_to_wall_clock_over_hours() {
+        // Two hours at 30 fps with a camera clock running 100 ppm fast
+        let frames = 2 * 3600 * 30;
+        let mut filter = TimestampFilter::new(TIMESCALE as i32);
+        let error = simulate(&mut filter, frames, 100.0);
+
+        // Uncorrected drift would be 720 ms; stay within 20 ms
+        assert!(error.abs() < TIMESCALE / 50, "error {} ticks", error);
+        assert!((filter.drift_ppm() - 100.0).abs() < 2.0);
+        assert!(filter.slew_ppm() < 0.0);
+    }
+
+    #[test]
+    fn test_slew_is_bounded() {
+        let frames = 3600 * 30;
+        let mut filter = TimestampFilter::new(TIMESCALE as i32).max_slew_ppm(50.0);
+        let error = simulate(&mut filter, frames, 200.0);
+
+        // 150 ppm of uncorrectable drift over an hour is 540 ms
+        assert_eq!(filter.slew_ppm(), -50.0);
+        assert!(error > TIMESCALE / 3, "error {} ticks", error);
+    }
+
+    #[test]
+    fn test_backwards_capture_timestamps_stay_monotonic() {
+        let mut filter = TimestampFilter::new(TIMESCALE as i32);
+        assert_eq!(filter.push(10_000, 0), 0);
+        let a = filter.push(13_000, 3000);
+        let b = filter.push(12_000, 6000);
+        assert!(b > a);
+        filter.reset();
+        assert_eq!(filter.push(50_000, 50_000), 0);
+    }
+}
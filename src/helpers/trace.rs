@@ -0,0 +1,372 @@
+//! Low-overhead recording of per-frame events from hot paths.
+//!
+//! Printing from an output callback formats, allocates and takes the stdout
+//! lock on VideoToolbox's thread, which shows up as jitter in the very
+//! latencies being measured. A [`TraceRecorder`] instead stores fixed-size
+//! token events in a preallocated ring buffer, and a [`TraceLogger`] thread
+//! formats them later.
+
+use std::fmt;
+use std::io::Write;
+use std::ptr;
+use std::sync::atomic::{
+    fence, AtomicBool, AtomicI64, AtomicPtr, AtomicU64, AtomicUsize, Ordering,
+};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// One recorded event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// Time since the recorder was created
+    pub at: Duration,
+    /// What happened, e.g. `"vt.encode_output"`
+    pub token: &'static str,
+    /// Event-specific values, such as a status and a timestamp
+    pub values: [i64; 2],
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:>12.6}] {} {} {}",
+            self.at.as_secs_f64(),
+            self.token,
+            self.values[0],
+            self.values[1]
+        )
+    }
+}
+
+/// A ring buffer slot, guarded by a sequence number: `2 * position + 1`
+/// while an event is being written, `2 * position + 2` once it is complete.
+struct Slot {
+    seq: AtomicU64,
+    nanos: AtomicU64,
+    token_ptr: AtomicPtr<u8>,
+    token_len: AtomicUsize,
+    values: [AtomicI64; 2],
+}
+
+/// Fixed-size, lock-free recorder of [`TraceEvent`]s.
+///
+/// [`record`](Self::record) never allocates, locks or blocks, and may be
+/// called from any number of threads. When events are recorded faster than
+/// they are drained, the oldest ones are overwritten and counted as
+/// [`lost`](Self::lost). Events are read back in recording order with
+/// [`drain`](Self::drain), typically by a [`TraceLogger`].
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use video_toolbox_sys::helpers::{set_trace_recorder, trace_event, TraceLogger, TraceRecorder};
+///
+/// let recorder: &'static TraceRecorder = Box::leak(Box::new(TraceRecorder::new(4096)));
+/// // The session wrappers now record their output callbacks
+/// set_trace_recorder(recorder);
+/// let _logger = TraceLogger::spawn(recorder, Duration::from_millis(250), std::io::stderr());
+///
+/// // In an encode callback, instead of println!
+/// trace_event("app.frame_written", 42, 1200);
+/// ```
+pub struct TraceRecorder {
+    slots: Box<[Slot]>,
+    /// Next position to write
+    head: AtomicU64,
+    /// Next position to read, held while draining
+    tail: Mutex<u64>,
+    lost: AtomicU64,
+    epoch: Instant,
+}
+
+impl TraceRecorder {
+    /// Create a recorder holding up to `capacity` undrained events, rounded
+    /// up to a power of two.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+        Self {
+            slots: (0..capacity)
+                .map(|_| Slot {
+                    seq: AtomicU64::new(0),
+                    nanos: AtomicU64::new(0),
+                    token_ptr: AtomicPtr::new(ptr::null_mut()),
+                    token_len: AtomicUsize::new(0),
+                    values: [AtomicI64::new(0), AtomicI64::new(0)],
+                })
+                .collect(),
+            head: AtomicU64::new(0),
+            tail: Mutex::new(0),
+            lost: AtomicU64::new(0),
+            epoch: Instant::now(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Record an event.
+    pub fn record(&self, token: &'static str, a: i64, b: i64) {
+        let nanos = self.epoch.elapsed().as_nanos() as u64;
+        let pos = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[self.index(pos)];
+        let writing = 2 * pos + 1;
+
+        // Claim the slot. Another writer only holds it if the buffer wrapped
+        // around while it was writing, and then only for a few stores.
+        let mut current = slot.seq.load(Ordering::Relaxed);
+        loop {
+            if current > writing {
+                // A newer event already took the slot
+                self.lost.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            if current % 2 == 1 {
+                std::hint::spin_loop();
+                current = slot.seq.load(Ordering::Relaxed);
+                continue;
+            }
+            match slot.seq.compare_exchange_weak(
+                current,
+                writing,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+        fence(Ordering::Release);
+
+        slot.nanos.store(nanos, Ordering::Relaxed);
+        slot.token_ptr
+            .store(token.as_ptr() as *mut u8, Ordering::Relaxed);
+        slot.token_len.store(token.len(), Ordering::Relaxed);
+        slot.values[0].store(a, Ordering::Relaxed);
+        slot.values[1].store(b, Ordering::Relaxed);
+        slot.seq.store(writing + 1, Ordering::Release);
+    }
+
+    /// Pass every complete event recorded since the last drain to `f`, in
+    /// recording order. Returns the number of events passed.
+    ///
+    /// Stops early at an event that is still being written; it is picked
+    /// up by the next drain.
+    pub fn drain<F>(&self, mut f: F) -> usize
+    where
+        F: FnMut(&TraceEvent),
+    {
+        let mut tail = self.tail.lock().unwrap_or_else(|e| e.into_inner());
+        let head = self.head.load(Ordering::Acquire);
+        let capacity = self.slots.len() as u64;
+        if head - *tail > capacity {
+            self.lost
+                .fetch_add(head - *tail - capacity, Ordering::Relaxed);
+            *tail = head - capacity;
+        }
+
+        let mut drained = 0;
+        while *tail < head {
+            let slot = &self.slots[self.index(*tail)];
+            let complete = 2 * *tail + 2;
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq < complete {
+                break;
+            }
+            if seq == complete {
+                let nanos = slot.nanos.load(Ordering::Relaxed);
+                let token_ptr = slot.token_ptr.load(Ordering::Relaxed);
+                let token_len = slot.token_len.load(Ordering::Relaxed);
+                let values = [
+                    slot.values[0].load(Ordering::Relaxed),
+                    slot.values[1].load(Ordering::Relaxed),
+                ];
+                fence(Ordering::Acquire);
+                if slot.seq.load(Ordering::Relaxed) == complete {
+                    // SAFETY: the unchanged sequence number means all fields
+                    // were written together from one `&'static str`
+                    let token = unsafe {
+                        std::str::from_utf8_unchecked(std::slice::from_raw_parts(
+                            token_ptr, token_len,
+                        ))
+                    };
+                    f(&TraceEvent {
+                        at: Duration::from_nanos(nanos),
+                        token,
+                        values,
+                    });
+                    drained += 1;
+                    *tail += 1;
+                    continue;
+                }
+            }
+            // Overwritten before it could be read
+            self.lost.fetch_add(1, Ordering::Relaxed);
+            *tail += 1;
+        }
+        drained
+    }
+
+    /// Events overwritten before they were drained.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    fn index(&self, pos: u64) -> usize {
+        pos as usize & (self.slots.len() - 1)
+    }
+}
+
+static TRACE_RECORDER: AtomicPtr<TraceRecorder> = AtomicPtr::new(ptr::null_mut());
+
+/// Install the process-wide recorder used by [`trace_event`] and by the
+/// session wrappers' output callbacks.
+pub fn set_trace_recorder(recorder: &'static TraceRecorder) {
+    TRACE_RECORDER.store(recorder as *const _ as *mut _, Ordering::Release);
+}
+
+/// Stop recording process-wide events.
+pub fn clear_trace_recorder() {
+    TRACE_RECORDER.store(ptr::null_mut(), Ordering::Release);
+}
+
+/// Record an event with the process-wide recorder, if one is installed.
+#[inline]
+pub fn trace_event(token: &'static str, a: i64, b: i64) {
+    let recorder = TRACE_RECORDER.load(Ordering::Acquire);
+    if !recorder.is_null() {
+        // SAFETY: only `&'static` recorders are installed
+        unsafe { &*recorder }.record(token, a, b);
+    }
+}
+
+/// Drains a [`TraceRecorder`] on a background thread, writing one line per
+/// event.
+///
+/// Lost events are reported as they are noticed. Dropping the logger writes
+/// the remaining events and joins the thread.
+pub struct TraceLogger {
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl TraceLogger {
+    /// Drain `recorder` into `writer` every `interval`.
+    pub fn spawn<W>(recorder: &'static TraceRecorder, interval: Duration, mut writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = stop.clone();
+        let worker = thread::Builder::new()
+            .name("trace-logger".to_string())
+            .spawn(move || {
+                let mut reported_lost = 0;
+                loop {
+                    let stopping = worker_stop.load(Ordering::Acquire);
+                    recorder.drain(|event| {
+                        let _ = writeln!(writer, "{event}");
+                    });
+                    let lost = recorder.lost();
+                    if lost > reported_lost {
+                        let _ = writeln!(writer, "[trace] {} events lost", lost - reported_lost);
+                        reported_lost = lost;
+                    }
+                    let _ = writer.flush();
+                    if stopping {
+                        break;
+                    }
+                    thread::park_timeout(interval);
+                }
+            })
+            .expect("failed to spawn trace logger");
+
+        Self {
+            stop,
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Drop for TraceLogger {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain_all(recorder: &TraceRecorder) -> Vec<(&'static str, [i64; 2])> {
+        let mut events = Vec::new();
+        recorder.drain(|event| events.push((event.token, event.values)));
+        events
+    }
+
+    #[test]
+    fn test_records_in_order_and_counts_overwritten() {
+        let recorder = TraceRecorder::new(3);
+        assert_eq!(recorder.capacity(), 4);
+
+        recorder.record("encode", 0, 100);
+        recorder.record("decode", -1, 200);
+        assert_eq!(
+            drain_all(&recorder),
+            [("encode", [0, 100]), ("decode", [-1, 200])]
+        );
+        assert!(drain_all(&recorder).is_empty());
+
+        for n in 0..10 {
+            recorder.record("frame", n, 0);
+        }
+        let values: Vec<i64> = drain_all(&recorder).iter().map(|e| e.1[0]).collect();
+        assert_eq!(values, [6, 7, 8, 9]);
+        assert_eq!(recorder.lost(), 6);
+    }
+
+    #[test]
+    fn test_concurrent_writers_and_logger() {
+        let recorder: &'static TraceRecorder = Box::leak(Box::new(TraceRecorder::new(1 << 14)));
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                thread::spawn(move || {
+                    for n in 0..1000 {
+                        recorder.record("worker", t, n);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        drop(TraceLogger::spawn(
+            recorder,
+            Duration::from_millis(10),
+            Shared(output.clone()),
+        ));
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 4000);
+        assert!(output.lines().all(|line| line.contains("] worker ")));
+        assert_eq!(recorder.lost(), 0);
+    }
+}
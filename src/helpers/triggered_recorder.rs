@@ -0,0 +1,332 @@
+//! Triggered recording with pre-roll and post-roll (security-camera style).
+
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::cmaf_muxer::{CmafConfig, CmafMuxer};
+use super::events::{emit, PipelineEvent};
+use super::replay_buffer::ReplayBuffer;
+use super::sink::{DirectorySink, Segment, SegmentSink, WriterSink};
+use super::source::MediaFrame;
+
+type TriggerFn = Box<dyn FnMut(&MediaFrame) -> f32 + Send>;
+
+struct Recording {
+    muxer: CmafMuxer,
+    sink: WriterSink<io::BufWriter<std::fs::File>>,
+    path: PathBuf,
+    frames: u64,
+    last_trigger_dts: i64,
+}
+
+impl Recording {
+    fn write(&mut self, frame: &MediaFrame) -> io::Result<()> {
+        let segment = self
+            .muxer
+            .try_add_frame(
+                &frame.nal_units,
+                frame.timing.pts,
+                frame.timing.dts,
+                frame.timing.duration as u32,
+                frame.is_keyframe,
+            )
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.frames += 1;
+        if let Some(data) = segment {
+            self.sink.write_segment(&self.media_segment(data))?;
+        }
+        Ok(())
+    }
+
+    /// Wrap a fragment just emitted by the muxer.
+    fn media_segment(&self, data: Vec<u8>) -> Segment {
+        Segment::media(self.muxer.sequence_number() - 1, data)
+            .with_duration(self.muxer.last_fragment_duration())
+    }
+
+    fn finish(mut self) -> io::Result<PipelineEvent> {
+        if let Some(data) = self.muxer.flush() {
+            self.sink.write_segment(&self.media_segment(data))?;
+        }
+        self.sink.flush()?;
+        Ok(PipelineEvent::RecordingFinished {
+            path: self.path,
+            frames: self.frames,
+        })
+    }
+}
+
+/// Records to disk only around activity, keeping a rolling pre-roll.
+///
+/// While idle, frames go to a [`ReplayBuffer`] covering the pre-roll. When
+/// the trigger fires (a motion score callback at or above its threshold, or
+/// [`fire`](Self::fire) for external sources such as an [`AudioMeter`](super::AudioMeter)),
+/// the pre-roll is written to a new fragmented MP4 file and recording
+/// continues until no trigger has fired for the post-roll. Recordings start
+/// and stop on keyframes, so every file is independently decodable.
+///
+/// Files are named `recording_NNN.mp4` in the output directory;
+/// [`PipelineEvent::RecordingStarted`] and [`PipelineEvent::RecordingFinished`]
+/// are emitted and returned from [`push`](Self::push).
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use video_toolbox_sys::helpers::{CmafConfig, TriggeredRecorder};
+///
+/// let mut recorder = TriggeredRecorder::new("clips", CmafConfig::default())?
+///     .pre_roll(Duration::from_secs(10))
+///     .post_roll(Duration::from_secs(20))
+///     .trigger(0.02, |frame| frame.motion_score.unwrap_or(0.0));
+/// # let (sps, pps) = (Vec::new(), Vec::new());
+/// recorder.set_parameter_sets(&sps, &pps, 1920, 1080);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct TriggeredRecorder {
+    dir: DirectorySink,
+    config: CmafConfig,
+    pre_roll: ReplayBuffer,
+    post_roll: Duration,
+    trigger: Option<(f32, TriggerFn)>,
+    fired: bool,
+    parameter_sets: Option<(Vec<u8>, Vec<u8>, u32, u32)>,
+    recording: Option<Recording>,
+    file_index: u32,
+}
+
+impl TriggeredRecorder {
+    /// Record into `dir`, creating it if needed.
+    ///
+    /// Defaults: 5 second pre-roll, 10 second post-roll, no motion trigger.
+    pub fn new(dir: impl Into<PathBuf>, config: CmafConfig) -> io::Result<Self> {
+        Ok(Self {
+            dir: DirectorySink::new(dir)?,
+            config,
+            pre_roll: ReplayBuffer::new(Duration::from_secs(5)),
+            post_roll: Duration::from_secs(10),
+            trigger: None,
+            fired: false,
+            parameter_sets: None,
+            recording: None,
+            file_index: 0,
+        })
+    }
+
+    /// Minimum video kept from before the trigger. Recordings start at the
+    /// keyframe at or before this point.
+    pub fn pre_roll(mut self, duration: Duration) -> Self {
+        self.pre_roll = ReplayBuffer::new(duration);
+        self
+    }
+
+    /// Minimum video kept after the last trigger. Recordings end just before
+    /// the first keyframe after this point.
+    pub fn post_roll(mut self, duration: Duration) -> Self {
+        self.post_roll = duration;
+        self
+    }
+
+    /// Score every frame with `score` (e.g. the motion score attached by a
+    /// [`MotionEstimator`](super::MotionEstimator)); the trigger fires at or
+    /// above `threshold`.
+    pub fn trigger<F>(mut self, threshold: f32, score: F) -> Self
+    where
+        F: FnMut(&MediaFrame) -> f32 + Send + 'static,
+    {
+        self.trigger = Some((threshold, Box::new(score)));
+        self
+    }
+
+    /// Set the stream's parameter sets, needed before a recording can start.
+    pub fn set_parameter_sets(&mut self, sps: &[u8], pps: &[u8], width: u32, height: u32) {
+        self.parameter_sets = Some((sps.to_vec(), pps.to_vec(), width, height));
+    }
+
+    /// Fire the trigger for the next frame pushed.
+    pub fn fire(&mut self) {
+        self.fired = true;
+    }
+
+    /// Whether a recording is in progress.
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Path of the recording in progress.
+    pub fn current_path(&self) -> Option<&std::path::Path> {
+        self.recording.as_ref().map(|r| r.path.as_path())
+    }
+
+    /// Push the next encoded frame (in decode order).
+    ///
+    /// Returns the events emitted for this frame (usually none).
+    pub fn push(&mut self, frame: MediaFrame) -> io::Result<Vec<PipelineEvent>> {
+        let score = self
+            .trigger
+            .as_mut()
+            .map(|(threshold, score)| (score(&frame), *threshold));
+        let triggered = std::mem::take(&mut self.fired)
+            || score.is_some_and(|(score, threshold)| score >= threshold);
+        let mut events = Vec::new();
+
+        if let Some(recording) = self.recording.as_mut() {
+            if triggered {
+                recording.last_trigger_dts = frame.timing.dts;
+            }
+            let since_trigger = frame.timing.dts - recording.last_trigger_dts;
+            let timescale = frame.timing.timescale.max(1) as f64;
+            let expired =
+                Duration::from_secs_f64(since_trigger.max(0) as f64 / timescale) >= self.post_roll;
+            if !(frame.is_keyframe && expired) {
+                recording.write(&frame)?;
+                return Ok(events);
+            }
+            // This keyframe starts the next pre-roll
+            events.push(self.recording.take().unwrap().finish()?);
+        }
+
+        let dts = frame.timing.dts;
+        self.pre_roll.push(frame);
+        if triggered && !self.pre_roll.is_empty() {
+            if let Some(event) = self.start(dts)? {
+                events.push(event);
+            }
+        }
+
+        for event in &events {
+            emit(event.clone());
+        }
+        Ok(events)
+    }
+
+    /// Complete the recording in progress, if any.
+    pub fn finish(&mut self) -> io::Result<Option<PipelineEvent>> {
+        let Some(recording) = self.recording.take() else {
+            return Ok(None);
+        };
+        let event = recording.finish()?;
+        emit(event.clone());
+        Ok(Some(event))
+    }
+
+    /// Open a new file and write the pre-roll to it.
+    fn start(&mut self, trigger_dts: i64) -> io::Result<Option<PipelineEvent>> {
+        let Some((sps, pps, width, height)) = &self.parameter_sets else {
+            return Ok(None);
+        };
+        let mut muxer = CmafMuxer::try_new(self.config.clone())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let init = muxer.create_init_segment(sps, pps, *width, *height);
+
+        let path = self
+            .dir
+            .dir()
+            .join(format!("recording_{:03}.mp4", self.file_index));
+        self.file_index += 1;
+        let mut sink = WriterSink::create(&path)?;
+        sink.write_segment(&Segment::init(init))?;
+
+        let mut recording = Recording {
+            muxer,
+            sink,
+            path: path.clone(),
+            frames: 0,
+            last_trigger_dts: trigger_dts,
+        };
+        for frame in self.pre_roll.drain() {
+            recording.write(&frame)?;
+        }
+        self.recording = Some(recording);
+        Ok(Some(PipelineEvent::RecordingStarted { path }))
+    }
+}
+
+impl Drop for TriggeredRecorder {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{NalUnit, SampleTiming};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// 1 fps at timescale 1000, keyframe every 2 frames.
+    fn frame(index: i64) -> MediaFrame {
+        let keyframe = index % 2 == 0;
+        let nal_type = if keyframe { 5 } else { 1 };
+        MediaFrame {
+            nal_units: vec![NalUnit {
+                nal_type,
+                data: vec![0x60 | nal_type, 0x88],
+            }],
+            timing: SampleTiming {
+                pts: index * 1000,
+                dts: index * 1000,
+                duration: 1000,
+                timescale: 1000,
+            },
+            is_keyframe: keyframe,
+            motion_score: None,
+        }
+    }
+
+    #[test]
+    fn test_triggered_recording_pre_and_post_roll() {
+        let dir = std::env::temp_dir().join(format!("vt-trigger-{}", std::process::id()));
+        let motion = Arc::new(AtomicBool::new(false));
+        let score = motion.clone();
+        let mut recorder = TriggeredRecorder::new(&dir, CmafConfig::default())
+            .unwrap()
+            .pre_roll(Duration::from_secs(3))
+            .post_roll(Duration::from_secs(2))
+            .trigger(0.5, move |_| score.load(Ordering::SeqCst) as u8 as f32);
+        recorder.set_parameter_sets(&[0x67, 0x64, 0x00, 0x1f], &[0x68, 0xee], 64, 64);
+
+        let mut events = Vec::new();
+        for i in 0..20 {
+            // Motion on frames 10 and 11
+            motion.store(i == 10 || i == 11, Ordering::SeqCst);
+            events.extend(recorder.push(frame(i)).unwrap());
+            assert_eq!(
+                recorder.is_recording(),
+                (10..14).contains(&i),
+                "frame {}",
+                i
+            );
+        }
+
+        let path = dir.join("recording_000.mp4");
+        assert_eq!(
+            events,
+            vec![
+                PipelineEvent::RecordingStarted { path: path.clone() },
+                // Pre-roll 8..=10, then 11..=13 until the keyframe at 14,
+                // the first one 2 seconds after the last trigger
+                PipelineEvent::RecordingFinished {
+                    path: path.clone(),
+                    frames: 6
+                },
+            ]
+        );
+        let file = std::fs::read(&path).unwrap();
+        assert_eq!(&file[4..8], b"ftyp");
+        assert!(recorder.finish().unwrap().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_fire_without_parameter_sets_is_ignored() {
+        let dir = std::env::temp_dir().join(format!("vt-trigger-fire-{}", std::process::id()));
+        let mut recorder = TriggeredRecorder::new(&dir, CmafConfig::default()).unwrap();
+        recorder.fire();
+        assert!(recorder.push(frame(0)).unwrap().is_empty());
+        assert!(!recorder.is_recording());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
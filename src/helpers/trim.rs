@@ -0,0 +1,456 @@
+//! Frame-accurate trimming of recorded files.
+//!
+//! Cutting an H.264 stream by copying samples can only start at a keyframe,
+//! and re-encoding everything is slow and loses quality. [`trim`] does both
+//! where each is needed: the partial GOP at the start of the range (and at
+//! the end, if its frames are reordered) is decoded and re-encoded with
+//! VideoToolbox, and every complete GOP in between is copied as is. The
+//! re-encoded frames get their own sample description in the output, as
+//! their parameter sets differ from the source's.
+
+use core_foundation_sys::base::OSStatus;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::mpsc;
+
+use super::cmaf_demuxer::{CmafDemuxer, DemuxError, DemuxedTrack};
+use super::compression_builder::CompressionSessionBuilder;
+use super::compression_session::EncodeOutput;
+use super::decompression_session::{
+    DecodeOptions, DecompressionSession, DecompressionSessionConfig,
+};
+use super::encoded_frame::EncodedFrame;
+use super::mp4_muxer::{Mp4Codec, Mp4TrackConfig, Mp4Writer};
+use super::nal_extractor::{H264ParameterSets, NalError, NalExtractor};
+use super::source::MediaFrame;
+use crate::codecs;
+use crate::errors::kVTVideoEncoderMalfunctionErr;
+
+/// Errors from [`trim`].
+#[derive(Debug)]
+pub enum TrimError {
+    /// Reading the input or writing the output failed
+    Io(io::Error),
+    /// The input is not a fragmented MP4 with an H.264 track
+    Demux(DemuxError),
+    /// No frame of the input is presented in the requested range
+    EmptyRange,
+    /// Decoding the partial GOP failed
+    Decode(OSStatus),
+    /// Re-encoding the partial GOP failed
+    Encode(OSStatus),
+    /// The encoder output has no usable parameter sets
+    Nal(NalError),
+}
+
+impl std::fmt::Display for TrimError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrimError::Io(e) => write!(f, "I/O error: {}", e),
+            TrimError::Demux(e) => write!(f, "Cannot read input: {}", e),
+            TrimError::EmptyRange => write!(f, "No frames in the trim range"),
+            TrimError::Decode(status) => write!(f, "Decoding failed: OSStatus {}", status),
+            TrimError::Encode(status) => write!(f, "Re-encoding failed: OSStatus {}", status),
+            TrimError::Nal(e) => write!(f, "Invalid encoder output: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TrimError {}
+
+impl From<io::Error> for TrimError {
+    fn from(e: io::Error) -> Self {
+        TrimError::Io(e)
+    }
+}
+
+impl From<DemuxError> for TrimError {
+    fn from(e: DemuxError) -> Self {
+        TrimError::Demux(e)
+    }
+}
+
+/// What [`trim`] wrote.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrimStats {
+    /// Frames decoded and re-encoded
+    pub reencoded: u64,
+    /// Frames copied without re-encoding
+    pub copied: u64,
+}
+
+/// Bytes of the input read at a time.
+const READ_CHUNK: usize = 1 << 20;
+
+/// Which frames of the input (indices in decode order) go where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TrimPlan {
+    /// GOP to decode for a re-encoded start
+    lead: Option<Range<usize>>,
+    /// Frames to copy, dropping those presented at or after the end
+    copy: Range<usize>,
+    /// GOP to decode for a re-encoded end
+    tail: Option<Range<usize>>,
+}
+
+fn plan_trim(frames: &[MediaFrame], start_pts: i64, end_pts: i64) -> Option<TrimPlan> {
+    let pts = |index: usize| frames[index].timing.pts;
+    if !(0..frames.len()).any(|i| (start_pts..end_pts).contains(&pts(i))) {
+        return None;
+    }
+    let keyframes: Vec<usize> = (0..frames.len())
+        .filter(|&i| frames[i].is_keyframe)
+        .collect();
+    let next_keyframe = |index: usize| {
+        let next = keyframes.iter().find(|&&k| k > index);
+        next.copied().unwrap_or(frames.len())
+    };
+
+    let first = keyframes
+        .iter()
+        .rev()
+        .find(|&&k| pts(k) <= start_pts)
+        .or(keyframes.first())
+        .copied()?;
+    let (lead, copy_start) = if pts(first) >= start_pts {
+        (None, first)
+    } else {
+        let end = next_keyframe(first);
+        (Some(first..end), end)
+    };
+
+    let last = keyframes
+        .iter()
+        .rev()
+        .find(|&&k| k >= copy_start && pts(k) < end_pts)
+        .copied();
+    let (copy, tail) = match last {
+        None => (copy_start..copy_start, None),
+        Some(last) => {
+            let end = next_keyframe(last);
+            let gop = &frames[last..end];
+            let in_range = gop.iter().all(|f| f.timing.pts < end_pts);
+            // Without reordering, frames presented after the end are only
+            // referenced by each other and can be dropped
+            let in_order = gop.windows(2).all(|w| w[0].timing.pts <= w[1].timing.pts);
+            if in_range || in_order {
+                (copy_start..end, None)
+            } else {
+                (copy_start..last, Some(last..end))
+            }
+        }
+    };
+    Some(TrimPlan { lead, copy, tail })
+}
+
+/// Demux `input` a chunk at a time, keeping only the frames
+/// [`retain_range`] selects.
+fn read_range(
+    input: &Path,
+    start_pts: i64,
+    end_pts: i64,
+) -> Result<(DemuxedTrack, Vec<MediaFrame>), TrimError> {
+    let mut file = File::open(input)?;
+    let mut demuxer = CmafDemuxer::new();
+    let mut frames = Vec::new();
+    let mut chunk = vec![0; READ_CHUNK];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        let demuxed = demuxer.push(&chunk[..read])?;
+        if retain_range(&mut frames, demuxed, start_pts, end_pts) {
+            break;
+        }
+    }
+    let track = demuxer.track().cloned().ok_or(DemuxError::NoVideoTrack)?;
+    Ok((track, frames))
+}
+
+/// Append `demuxed` to `frames`, dropping the GOPs before the last keyframe
+/// presented at or before `start_pts`. Returns true at the first keyframe
+/// presented at or after `end_pts`, past which nothing is needed.
+fn retain_range(
+    frames: &mut Vec<MediaFrame>,
+    demuxed: Vec<MediaFrame>,
+    start_pts: i64,
+    end_pts: i64,
+) -> bool {
+    for frame in demuxed {
+        if frame.is_keyframe {
+            if frame.timing.pts >= end_pts {
+                return true;
+            }
+            if frame.timing.pts <= start_pts {
+                frames.clear();
+            }
+        }
+        frames.push(frame);
+    }
+    false
+}
+
+/// Source bitrate and frame rate, used for the re-encoded frames.
+fn encode_settings(frames: &[MediaFrame], timescale: u32) -> (i64, f64) {
+    let bytes: usize = frames
+        .iter()
+        .flat_map(|f| &f.nal_units)
+        .map(|nal| nal.data.len())
+        .sum();
+    let ticks: i64 = frames.iter().map(|f| f.timing.duration).sum();
+    let seconds = ticks.max(1) as f64 / timescale.max(1) as f64;
+    let bitrate = (bytes as f64 * 8.0 / seconds) as i64;
+    let frame_rate = frames.len() as f64 / seconds;
+    (bitrate.max(100_000), frame_rate)
+}
+
+/// Decode `gop` and re-encode its frames presented in `keep`, starting with
+/// a keyframe and without frame reordering. Decode times trail presentation
+/// times by `delay`, the reordering delay of the copied frames, so they keep
+/// increasing across the cut.
+fn reencode(
+    gop: &[MediaFrame],
+    track: &DemuxedTrack,
+    keep: Range<i64>,
+    delay: i64,
+    (bitrate, frame_rate): (i64, f64),
+) -> Result<(H264ParameterSets, Vec<MediaFrame>), TrimError> {
+    let config = DecompressionSessionConfig {
+        pixel_format: Some(codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE),
+        ..Default::default()
+    };
+    let (decoder, decoded) = DecompressionSession::h264_channel(&track.parameter_sets, &config)
+        .map_err(TrimError::Decode)?;
+    for frame in gop {
+        decoder
+            .decode_nal_units(&frame.nal_units, frame.timing, DecodeOptions::new())
+            .map_err(TrimError::Decode)?;
+    }
+    decoder
+        .wait_for_asynchronous_frames()
+        .map_err(TrimError::Decode)?;
+    let mut pictures = decoded
+        .try_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(TrimError::Decode)?;
+    pictures.retain(|picture| keep.contains(&picture.pts.value));
+    pictures.sort_by_key(|picture| picture.pts.value);
+
+    let (sender, receiver) = mpsc::channel();
+    let encoder = CompressionSessionBuilder::new(
+        track.width as i32,
+        track.height as i32,
+        codecs::video::H264,
+    )
+    .pixel_format(codecs::pixel::YUV420_BIPLANAR_VIDEO_RANGE)
+    .hardware_accelerated(true)
+    .allow_frame_reordering(false)
+    .bitrate(bitrate)
+    .frame_rate(frame_rate)
+    .keyframe_interval(pictures.len() as i32 + 1)
+    .build_session(move |output| {
+        let frame = match output {
+            EncodeOutput::Frame { sample_buffer, .. } => {
+                Ok(unsafe { EncodedFrame::retain(sample_buffer) })
+            }
+            EncodeOutput::Error(status) => Err(status),
+            EncodeOutput::Dropped { .. } => Err(kVTVideoEncoderMalfunctionErr),
+        };
+        let _ = sender.send(frame);
+    })
+    .map_err(TrimError::Encode)?;
+    encoder.force_keyframe();
+    for picture in &pictures {
+        unsafe {
+            encoder.encode_frame(picture.pixel_buffer.as_raw(), picture.pts, picture.duration)
+        }
+        .map_err(TrimError::Encode)?;
+    }
+    encoder.complete_frames().map_err(TrimError::Encode)?;
+    let encoded = receiver
+        .try_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(TrimError::Encode)?;
+    if encoded.len() != pictures.len() || encoded.is_empty() {
+        return Err(TrimError::Encode(kVTVideoEncoderMalfunctionErr));
+    }
+
+    let (parameter_sets, _) =
+        unsafe { NalExtractor::new().resolve_parameter_sets(encoded[0].as_raw()) }
+            .map_err(TrimError::Nal)?;
+    let mut frames = Vec::with_capacity(encoded.len());
+    for (frame, picture) in encoded.iter().zip(&pictures) {
+        let mut timing = gop[0].timing;
+        timing.pts = picture.pts.value;
+        timing.dts = picture.pts.value - delay;
+        timing.duration = picture.duration.value;
+        frames.push(MediaFrame {
+            nal_units: frame.to_nal_units().map_err(TrimError::Nal)?,
+            timing,
+            is_keyframe: frame.is_keyframe(),
+            motion_score: None,
+        });
+    }
+    Ok((parameter_sets, frames))
+}
+
+/// Cut the frames presented from `start_pts` up to (not including)
+/// `end_pts` out of a fragmented MP4 (e.g. from
+/// [`Fmp4Recorder`](super::Fmp4Recorder)) into a progressive MP4.
+///
+/// Timestamps are in the input track's timescale. The GOP containing the
+/// start is decoded from its keyframe and re-encoded from `start_pts` on,
+/// so the output starts exactly there with a new keyframe; the GOPs after it
+/// are copied. At the end, frames presented at or after `end_pts` are
+/// dropped, which needs re-encoding only when the last GOP uses frame
+/// reordering (B-frames). A start on a keyframe re-encodes nothing. The
+/// input is read incrementally, up to the first keyframe after the range.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::helpers::trim;
+///
+/// // Seconds 12.5 to 20 of a 90 kHz recording
+/// let stats = trim("recording.mp4", 1_125_000, 1_800_000, "clip.mp4")?;
+/// println!("{} frames re-encoded, {} copied", stats.reencoded, stats.copied);
+/// # Ok::<(), video_toolbox_sys::helpers::TrimError>(())
+/// ```
+pub fn trim(
+    input: impl AsRef<Path>,
+    start_pts: i64,
+    end_pts: i64,
+    output: impl AsRef<Path>,
+) -> Result<TrimStats, TrimError> {
+    let (track, frames) = read_range(input.as_ref(), start_pts, end_pts)?;
+    let plan = plan_trim(&frames, start_pts, end_pts).ok_or(TrimError::EmptyRange)?;
+    let settings = encode_settings(&frames, track.timescale);
+    let keep = start_pts..end_pts;
+    let copied: Vec<MediaFrame> = frames[plan.copy.clone()]
+        .iter()
+        .filter(|frame| frame.timing.pts < end_pts)
+        .cloned()
+        .collect();
+    let delay = copied
+        .first()
+        .map_or(0, |f| (f.timing.pts - f.timing.dts).max(0));
+
+    // Runs of output frames, each with the parameter sets describing it
+    let mut runs: Vec<(H264ParameterSets, Vec<MediaFrame>)> = Vec::new();
+    let mut stats = TrimStats::default();
+    if let Some(lead) = &plan.lead {
+        let gop = &frames[lead.clone()];
+        let (sets, reencoded) = reencode(gop, &track, keep.clone(), delay, settings)?;
+        stats.reencoded += reencoded.len() as u64;
+        runs.push((sets, reencoded));
+    }
+    if !copied.is_empty() {
+        stats.copied += copied.len() as u64;
+        runs.push((track.parameter_sets.clone(), copied));
+    }
+    if let Some(tail) = &plan.tail {
+        let (sets, reencoded) = reencode(&frames[tail.clone()], &track, keep, delay, settings)?;
+        stats.reencoded += reencoded.len() as u64;
+        runs.push((sets, reencoded));
+    }
+    // Each sample lasts until the next one is decoded
+    let dts: Vec<i64> = runs
+        .iter()
+        .flat_map(|(_, frames)| frames.iter().map(|f| f.timing.dts))
+        .collect();
+    for (frame, next_dts) in runs
+        .iter_mut()
+        .flat_map(|(_, frames)| frames.iter_mut())
+        .zip(dts.iter().skip(1))
+    {
+        frame.timing.duration = next_dts - frame.timing.dts;
+    }
+
+    let mut runs = runs.into_iter();
+    let (sets, first) = runs.next().ok_or(TrimError::EmptyRange)?;
+    let mut config = Mp4TrackConfig::new(Mp4Codec::H264(sets), track.width, track.height);
+    config.timescale = track.timescale;
+    let mut writer = Mp4Writer::create(output, config)?;
+    for frame in &first {
+        writer.push(frame)?;
+    }
+    for (sets, frames) in runs {
+        writer.set_codec(Mp4Codec::H264(sets));
+        for frame in &frames {
+            writer.push(frame)?;
+        }
+    }
+    writer.finish()?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::SampleTiming;
+
+    /// Frames in decode order from (pts, is_keyframe) pairs, 100 ticks apart.
+    fn frames(layout: &[(i64, bool)]) -> Vec<MediaFrame> {
+        (0..)
+            .zip(layout)
+            .map(|(index, &(pts, is_keyframe))| MediaFrame {
+                nal_units: Vec::new(),
+                timing: SampleTiming {
+                    pts,
+                    dts: index * 100,
+                    duration: 100,
+                    timescale: 1000,
+                },
+                is_keyframe,
+                motion_score: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_reencodes_partial_gops_only() {
+        // Three GOPs of three frames without reordering
+        let stream: Vec<_> = (0..9).map(|i| (i * 100, i % 3 == 0)).collect();
+        let stream = frames(&stream);
+
+        // Starting mid-GOP re-encodes the rest of it; the end is cut by copying
+        let plan = plan_trim(&stream, 100, 750).unwrap();
+        assert_eq!(plan.lead, Some(0..3));
+        assert_eq!((plan.copy, plan.tail), (3..9, None));
+
+        // Starting on a keyframe copies everything
+        let plan = plan_trim(&stream, 300, 900).unwrap();
+        assert_eq!((plan.lead, plan.copy), (None, 3..9));
+        assert!(plan_trim(&stream, 900, 1000).is_none());
+
+        // With B-frames, a GOP cut at the end is re-encoded as well
+        let reordered = frames(&[
+            (100, true),
+            (300, false),
+            (200, false),
+            (400, true),
+            (600, false),
+            (500, false),
+        ]);
+        let plan = plan_trim(&reordered, 100, 550).unwrap();
+        assert_eq!(plan.lead, None);
+        assert_eq!((plan.copy, plan.tail), (0..3, Some(3..6)));
+    }
+    #[test]
+    fn test_retain_range_keeps_start_gop_to_end() {
+        let stream: Vec<_> = (0..9).map(|i| (i * 100, i % 3 == 0)).collect();
+        let stream = frames(&stream);
+
+        // GOPs before the one containing the start are dropped, and reading
+        // stops at the first keyframe past the end
+        let mut kept = Vec::new();
+        assert!(!retain_range(&mut kept, stream[..4].to_vec(), 400, 550));
+        assert!(retain_range(&mut kept, stream[4..].to_vec(), 400, 550));
+        let pts: Vec<_> = kept.iter().map(|f| f.timing.pts).collect();
+        assert_eq!(pts, [300, 400, 500]);
+        assert_eq!(plan_trim(&kept, 400, 550).unwrap().lead, Some(0..3));
+    }
+}
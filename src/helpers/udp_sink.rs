@@ -0,0 +1,262 @@
+//! MPEG transport stream output over UDP (multicast or unicast).
+
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+/// Size of one MPEG-TS packet.
+pub const TS_PACKET_SIZE: usize = 188;
+
+/// TS packets per datagram; 7 × 188 bytes is the largest bundle that fits a
+/// 1500-byte Ethernet MTU and what most receivers expect.
+pub const TS_PACKETS_PER_DATAGRAM: usize = 7;
+
+const TS_SYNC_BYTE: u8 = 0x47;
+
+/// Settings for a [`UdpTsSink`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpTsConfig {
+    /// IPv4 TTL or IPv6 hop limit (at most 255); 1 keeps multicast on the
+    /// local subnet
+    pub ttl: u32,
+    /// TS packets bundled into each datagram
+    pub packets_per_datagram: usize,
+    /// Deliver multicast datagrams to listeners on this host (IPv4)
+    pub multicast_loop: bool,
+    /// Local interface address to send IPv4 multicast from; `None` lets the
+    /// system routing table decide
+    pub interface: Option<Ipv4Addr>,
+}
+
+impl Default for UdpTsConfig {
+    fn default() -> Self {
+        Self {
+            ttl: 1,
+            packets_per_datagram: TS_PACKETS_PER_DATAGRAM,
+            multicast_loop: false,
+            interface: None,
+        }
+    }
+}
+
+/// Sends an MPEG transport stream over UDP, e.g. to monitors on a LAN via
+/// multicast.
+///
+/// Written bytes are split into 188-byte TS packets and sent in datagrams of
+/// [`packets_per_datagram`](UdpTsConfig::packets_per_datagram) packets. Input
+/// may be written in arbitrary chunks; bytes before a sync byte (`0x47`) are
+/// discarded to realign on packet boundaries. [`flush`](Write::flush) sends
+/// a final partial bundle.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::io::Write;
+/// use video_toolbox_sys::helpers::{UdpTsConfig, UdpTsSink};
+///
+/// let config = UdpTsConfig {
+///     ttl: 4,
+///     ..Default::default()
+/// };
+/// let mut sink = UdpTsSink::new("239.0.0.1:5000", config)?;
+/// # let ts_packets: Vec<u8> = Vec::new();
+/// sink.write_all(&ts_packets)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct UdpTsSink {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    datagram_size: usize,
+    pending: Vec<u8>,
+    datagrams_sent: u64,
+    discarded_bytes: u64,
+}
+
+impl UdpTsSink {
+    /// Send to `destination` (a multicast group or unicast address).
+    pub fn new(destination: impl ToSocketAddrs, config: UdpTsConfig) -> io::Result<Self> {
+        let destination = destination
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no destination address"))?;
+        let socket = match destination {
+            SocketAddr::V4(addr) => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+                if addr.ip().is_multicast() {
+                    socket.set_multicast_ttl_v4(config.ttl)?;
+                    socket.set_multicast_loop_v4(config.multicast_loop)?;
+                    if let Some(interface) = config.interface {
+                        set_multicast_interface_v4(&socket, interface)?;
+                    }
+                } else {
+                    socket.set_ttl(config.ttl)?;
+                }
+                socket
+            }
+            SocketAddr::V6(addr) => {
+                let socket = UdpSocket::bind("[::]:0")?;
+                let option = if addr.ip().is_multicast() {
+                    libc::IPV6_MULTICAST_HOPS
+                } else {
+                    libc::IPV6_UNICAST_HOPS
+                };
+                set_hop_limit_v6(&socket, option, config.ttl)?;
+                socket
+            }
+        };
+        socket.connect(destination)?;
+
+        let datagram_size = config.packets_per_datagram.max(1) * TS_PACKET_SIZE;
+        Ok(Self {
+            socket,
+            destination,
+            datagram_size,
+            pending: Vec::with_capacity(datagram_size * 2),
+            datagrams_sent: 0,
+            discarded_bytes: 0,
+        })
+    }
+
+    pub fn destination(&self) -> SocketAddr {
+        self.destination
+    }
+
+    pub fn datagrams_sent(&self) -> u64 {
+        self.datagrams_sent
+    }
+
+    /// Bytes dropped while resynchronizing to TS packet boundaries.
+    pub fn discarded_bytes(&self) -> u64 {
+        self.discarded_bytes
+    }
+
+    /// Drop bytes up to the next sync byte at the start of `pending`.
+    fn resync(&mut self) {
+        let skip = self
+            .pending
+            .iter()
+            .position(|&b| b == TS_SYNC_BYTE)
+            .unwrap_or(self.pending.len());
+        if skip > 0 {
+            self.pending.drain(..skip);
+            self.discarded_bytes += skip as u64;
+        }
+    }
+
+    /// Send `len` bytes from the front of `pending` as one datagram.
+    fn send(&mut self, len: usize) -> io::Result<()> {
+        self.socket.send(&self.pending[..len])?;
+        self.pending.drain(..len);
+        self.datagrams_sent += 1;
+        Ok(())
+    }
+}
+
+fn set_multicast_interface_v4(socket: &UdpSocket, interface: Ipv4Addr) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let addr = libc::in_addr {
+        s_addr: u32::from(interface).to_be(),
+    };
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MULTICAST_IF,
+            &addr as *const libc::in_addr as *const libc::c_void,
+            std::mem::size_of::<libc::in_addr>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Set the IPv6 unicast or multicast hop limit, which `set_ttl` and
+/// `set_multicast_ttl_v4` do not cover.
+fn set_hop_limit_v6(socket: &UdpSocket, option: libc::c_int, hops: u32) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let hops = hops.min(255) as libc::c_int;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            option,
+            &hops as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+impl Write for UdpTsSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.resync();
+        while self.pending.len() >= self.datagram_size {
+            self.send(self.datagram_size)?;
+            self.resync();
+        }
+        Ok(buf.len())
+    }
+
+    /// Send buffered whole packets; a trailing partial packet is kept.
+    fn flush(&mut self) -> io::Result<()> {
+        let whole = self.pending.len() / TS_PACKET_SIZE * TS_PACKET_SIZE;
+        if whole > 0 {
+            self.send(whole)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UdpTsSink {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(index: u8) -> Vec<u8> {
+        let mut packet = vec![index; TS_PACKET_SIZE];
+        packet[0] = TS_SYNC_BYTE;
+        packet
+    }
+
+    #[test]
+    fn test_bundles_packets_and_resyncs() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sink =
+            UdpTsSink::new(receiver.local_addr().unwrap(), UdpTsConfig::default()).unwrap();
+
+        let mut stream = vec![0u8; 5];
+        for i in 0..10 {
+            stream.extend(packet(i));
+        }
+        // Write in uneven chunks
+        for chunk in stream.chunks(100) {
+            sink.write_all(chunk).unwrap();
+        }
+        sink.flush().unwrap();
+
+        let mut buf = [0u8; 2048];
+        let first = receiver.recv(&mut buf).unwrap();
+        assert_eq!(first, 7 * TS_PACKET_SIZE);
+        assert_eq!(&buf[..TS_PACKET_SIZE], &packet(0)[..]);
+        let second = receiver.recv(&mut buf).unwrap();
+        assert_eq!(second, 3 * TS_PACKET_SIZE);
+        assert_eq!(&buf[2 * TS_PACKET_SIZE..second], &packet(9)[..]);
+        assert_eq!(sink.datagrams_sent(), 2);
+        assert_eq!(sink.discarded_bytes(), 5);
+    }
+}
@@ -0,0 +1,231 @@
+//! Black and frozen video detection for broadcast monitoring.
+
+use super::events::{emit, PipelineEvent};
+use super::frame_hash::{FrameHash, FrameSnapshot};
+use super::scene_change::LumaThumbnail;
+use crate::cv_types::CVPixelBufferRef;
+
+/// Watches a sequence of frames for black or frozen video.
+///
+/// A frame is black when its mean luma is at or below
+/// [`black_threshold`](Self::black_threshold); video is frozen when
+/// consecutive frames hash identically. State changes are reported as
+/// [`PipelineEvent`]s once a condition has lasted the configured number of
+/// frames, both through [`set_event_handler`](super::set_event_handler) and as
+/// the return value of [`observe`](Self::observe).
+///
+/// Black frames are also identical, so frozen detection is suspended while
+/// the video is black.
+///
+/// Works on decoded frames or on pixel buffers before they are encoded.
+#[derive(Debug, Clone)]
+pub struct VideoMonitor {
+    black_threshold: f32,
+    black_min_frames: u64,
+    frozen_min_frames: u64,
+    frame_index: u64,
+    black_run: u64,
+    black: bool,
+    previous_hash: Option<FrameHash>,
+    frozen_run: u64,
+    frozen: bool,
+}
+
+impl Default for VideoMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VideoMonitor {
+    /// Create a monitor with a mean luma threshold of 24 (just above
+    /// video-range black), reporting black video after 15 frames and frozen
+    /// video after 30.
+    pub fn new() -> Self {
+        Self {
+            black_threshold: 24.0,
+            black_min_frames: 15,
+            frozen_min_frames: 30,
+            frame_index: 0,
+            black_run: 0,
+            black: false,
+            previous_hash: None,
+            frozen_run: 0,
+            frozen: false,
+        }
+    }
+
+    /// Mean 8-bit luma at or below which a frame counts as black.
+    pub fn black_threshold(mut self, luma: f32) -> Self {
+        self.black_threshold = luma;
+        self
+    }
+
+    /// Consecutive black frames before [`PipelineEvent::BlackVideoStarted`].
+    pub fn black_min_frames(mut self, frames: u64) -> Self {
+        self.black_min_frames = frames.max(1);
+        self
+    }
+
+    /// Consecutive repeated frames before [`PipelineEvent::FrozenVideoStarted`].
+    pub fn frozen_min_frames(mut self, frames: u64) -> Self {
+        self.frozen_min_frames = frames.max(1);
+        self
+    }
+
+    /// Whether black video is currently reported.
+    pub fn is_black(&self) -> bool {
+        self.black
+    }
+
+    /// Whether frozen video is currently reported.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Number of frames observed.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_index
+    }
+
+    /// Observe a frame by its mean luma and content hash.
+    ///
+    /// Returns the events emitted for this frame (usually none).
+    pub fn observe(&mut self, mean_luma: f32, hash: FrameHash) -> Vec<PipelineEvent> {
+        let index = self.frame_index;
+        self.frame_index += 1;
+        let mut events = Vec::new();
+
+        if mean_luma <= self.black_threshold {
+            self.black_run += 1;
+            if !self.black && self.black_run >= self.black_min_frames {
+                self.black = true;
+                events.push(PipelineEvent::BlackVideoStarted {
+                    frame_index: index + 1 - self.black_run,
+                });
+            }
+        } else {
+            if self.black {
+                events.push(PipelineEvent::BlackVideoEnded {
+                    frame_index: index,
+                    frames: self.black_run,
+                });
+            }
+            self.black = false;
+            self.black_run = 0;
+        }
+
+        let repeated = !self.black && self.previous_hash.as_ref() == Some(&hash);
+        if repeated {
+            self.frozen_run += 1;
+            if !self.frozen && self.frozen_run >= self.frozen_min_frames {
+                self.frozen = true;
+                events.push(PipelineEvent::FrozenVideoStarted {
+                    frame_index: index + 1 - self.frozen_run,
+                });
+            }
+        } else {
+            if self.frozen {
+                events.push(PipelineEvent::FrozenVideoEnded {
+                    frame_index: index,
+                    frames: self.frozen_run,
+                });
+            }
+            self.frozen = false;
+            self.frozen_run = 0;
+        }
+        self.previous_hash = Some(hash);
+
+        for event in &events {
+            emit(event.clone());
+        }
+        events
+    }
+
+    /// Observe a pixel buffer.
+    ///
+    /// Mean luma comes from a 64x36 thumbnail, so the pixel format must be
+    /// supported by [`LumaThumbnail::from_pixel_buffer`].
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid CVPixelBuffer.
+    pub unsafe fn observe_pixel_buffer(
+        &mut self,
+        pixel_buffer: CVPixelBufferRef,
+    ) -> Result<Vec<PipelineEvent>, i32> {
+        let thumbnail = LumaThumbnail::from_pixel_buffer(pixel_buffer, 64, 36)?;
+        let hash = FrameSnapshot::from_pixel_buffer(pixel_buffer)?.hash();
+        Ok(self.observe(mean(&thumbnail.data), hash))
+    }
+}
+
+fn mean(data: &[u8]) -> f32 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    data.iter().map(|&v| v as u64).sum::<u64>() as f32 / data.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(value: u64) -> FrameHash {
+        FrameHash {
+            planes: vec![value],
+        }
+    }
+
+    #[test]
+    fn test_black_video_events() {
+        let mut monitor = VideoMonitor::new().black_min_frames(3);
+        assert!(monitor.observe(120.0, hash(1)).is_empty());
+        assert!(monitor.observe(16.0, hash(2)).is_empty());
+        assert!(monitor.observe(17.0, hash(3)).is_empty());
+        assert_eq!(
+            monitor.observe(16.0, hash(2)),
+            vec![PipelineEvent::BlackVideoStarted { frame_index: 1 }]
+        );
+        assert!(monitor.is_black());
+        assert_eq!(
+            monitor.observe(90.0, hash(4)),
+            vec![PipelineEvent::BlackVideoEnded {
+                frame_index: 4,
+                frames: 3
+            }]
+        );
+        assert!(!monitor.is_black());
+    }
+
+    #[test]
+    fn test_frozen_video_events() {
+        let mut monitor = VideoMonitor::new().frozen_min_frames(2);
+        monitor.observe(100.0, hash(7));
+        assert!(monitor.observe(100.0, hash(7)).is_empty());
+        assert_eq!(
+            monitor.observe(100.0, hash(7)),
+            vec![PipelineEvent::FrozenVideoStarted { frame_index: 1 }]
+        );
+        assert!(monitor.is_frozen());
+        assert_eq!(
+            monitor.observe(100.0, hash(8)),
+            vec![PipelineEvent::FrozenVideoEnded {
+                frame_index: 3,
+                frames: 2
+            }]
+        );
+        assert_eq!(monitor.frame_count(), 4);
+    }
+
+    #[test]
+    fn test_black_video_is_not_frozen() {
+        let mut monitor = VideoMonitor::new().black_min_frames(1).frozen_min_frames(1);
+        let events: Vec<_> = (0..5).flat_map(|_| monitor.observe(0.0, hash(0))).collect();
+        assert_eq!(
+            events,
+            vec![PipelineEvent::BlackVideoStarted { frame_index: 0 }]
+        );
+        assert!(!monitor.is_frozen());
+    }
+}
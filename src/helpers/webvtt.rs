@@ -0,0 +1,344 @@
+//! Live WebVTT subtitle segments aligned with CMAF media segments.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::events::emit;
+use super::hls::{HlsConfig, SegmentRetention};
+use super::sink::{Segment, SegmentSink};
+
+/// A timed text cue on the media timeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VttCue {
+    pub start: Duration,
+    pub end: Duration,
+    pub text: String,
+}
+
+impl VttCue {
+    /// Whether the cue is shown at some point in `[start, end)`.
+    fn overlaps(&self, start: Duration, end: Duration) -> bool {
+        if self.start == self.end {
+            return self.start >= start && self.start < end;
+        }
+        self.start < end && self.end > start
+    }
+}
+
+/// Handle for adding cues from another thread, e.g. a speech recognizer,
+/// while the [`VttCueWriter`] itself is owned by a [`TeeSink`](super::TeeSink).
+#[derive(Debug, Clone, Default)]
+pub struct VttCueQueue {
+    cues: Arc<Mutex<Vec<VttCue>>>,
+}
+
+impl VttCueQueue {
+    /// Queue a cue shown from `start` to `end` (media time).
+    pub fn push(&self, start: Duration, end: Duration, text: impl Into<String>) {
+        let cue = VttCue {
+            start,
+            end: end.max(start),
+            text: text.into(),
+        };
+        self.cues
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(cue);
+    }
+
+    /// Cues queued and not yet fully written.
+    pub fn len(&self) -> usize {
+        self.cues.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Format a time as a WebVTT timestamp (`HH:MM:SS.mmm`).
+fn timestamp(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Escape cue text; blank lines would end the cue early, so they are dropped.
+fn escape(text: &str) -> String {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            line.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Clone)]
+struct VttEntry {
+    sequence_number: u32,
+    duration: Duration,
+    name: String,
+}
+
+/// Writes live captions as segmented WebVTT with its own HLS media playlist.
+///
+/// Cues are added on the media timeline (the same clock as the video
+/// timestamps) through [`add_cue`](Self::add_cue) or a [`VttCueQueue`].
+/// Each call to [`end_segment`](Self::end_segment) writes `subs_NNN.vtt`
+/// covering the same time range as video segment `NNN`, so subtitle and
+/// video segment boundaries line up. Cues spanning a boundary are repeated
+/// in both segments, as HLS requires. Used as a [`SegmentSink`] (for example
+/// next to an [`HlsSink`](super::HlsSink) in a [`TeeSink`](super::TeeSink)),
+/// a subtitle segment is written for every media segment automatically.
+///
+/// The playlist follows the [`HlsConfig`] window and retention settings. Set
+/// a distinct [`playlist_name`](HlsConfig::playlist_name) when sharing a
+/// directory with the video playlist, and reference it from the master
+/// playlist with [`media_tag`](Self::media_tag).
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use video_toolbox_sys::helpers::{HlsConfig, VttCueWriter};
+///
+/// let config = HlsConfig {
+///     playlist_name: "subtitles.m3u8".to_string(),
+///     ..Default::default()
+/// };
+/// let mut captions = VttCueWriter::new("live", config)?;
+/// captions.add_cue(Duration::from_millis(500), Duration::from_millis(2500), "Hello");
+/// // After the muxer emits video segment 1 (2 seconds long):
+/// captions.end_segment(1, Duration::from_secs(2))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct VttCueWriter {
+    dir: PathBuf,
+    config: HlsConfig,
+    queue: VttCueQueue,
+    entries: VecDeque<VttEntry>,
+    retention: SegmentRetention,
+    segment_start: Duration,
+    target_duration: u64,
+    ended: bool,
+}
+
+impl VttCueWriter {
+    /// Write into `dir`, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>, config: HlsConfig) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            retention: SegmentRetention::new(config.safety_margin),
+            config,
+            queue: VttCueQueue::default(),
+            entries: VecDeque::new(),
+            segment_start: Duration::ZERO,
+            target_duration: 1,
+            ended: false,
+        })
+    }
+
+    /// Media time at which the first segment starts (default: zero), e.g.
+    /// the first video timestamp.
+    pub fn start_time(mut self, start: Duration) -> Self {
+        self.segment_start = start;
+        self
+    }
+
+    /// A handle for adding cues from other threads.
+    pub fn cue_queue(&self) -> VttCueQueue {
+        self.queue.clone()
+    }
+
+    /// Add a cue shown from `start` to `end` (media time).
+    pub fn add_cue(&mut self, start: Duration, end: Duration, text: impl Into<String>) {
+        self.queue.push(start, end, text);
+    }
+
+    pub fn playlist_path(&self) -> PathBuf {
+        self.dir.join(&self.config.playlist_name)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Start of the next subtitle segment on the media timeline.
+    pub fn segment_start(&self) -> Duration {
+        self.segment_start
+    }
+
+    /// `#EXT-X-MEDIA` line for the master playlist.
+    pub fn media_tag(&self, group_id: &str, name: &str, language: &str) -> String {
+        format!(
+            "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"{}\",NAME=\"{}\",LANGUAGE=\"{}\",DEFAULT=YES,AUTOSELECT=YES,URI=\"{}\"",
+            group_id, name, language, self.config.playlist_name
+        )
+    }
+
+    /// Write the subtitle segment for media segment `sequence_number`, which
+    /// lasts `duration` from the end of the previous one, and update the
+    /// playlist. Returns the path of the new file.
+    pub fn end_segment(&mut self, sequence_number: u32, duration: Duration) -> io::Result<PathBuf> {
+        let start = self.segment_start;
+        let end = start + duration;
+        let name = format!("subs_{:03}.vtt", sequence_number);
+        let path = self.dir.join(&name);
+        fs::write(&path, self.render_segment(start, end))?;
+
+        self.segment_start = end;
+        self.target_duration = self
+            .target_duration
+            .max(duration.as_secs_f64().round() as u64);
+        self.entries.push_back(VttEntry {
+            sequence_number,
+            duration,
+            name,
+        });
+        while self.config.window > 0 && self.entries.len() > self.config.window {
+            let entry = self.entries.pop_front().unwrap();
+            self.retention.retire(self.dir.join(entry.name));
+        }
+
+        self.write_playlist()?;
+        for event in self.retention.collect() {
+            emit(event);
+        }
+        Ok(path)
+    }
+
+    /// Render the cues shown in `[start, end)` and drop those that are done.
+    fn render_segment(&self, start: Duration, end: Duration) -> String {
+        let mut cues = self.queue.cues.lock().unwrap_or_else(|e| e.into_inner());
+        cues.sort_by_key(|cue| cue.start);
+
+        // Cue times are media times; map them 1:1 onto the 90 kHz media clock
+        let mut out = String::from("WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:0,LOCAL:00:00:00.000\n");
+        for cue in cues.iter().filter(|cue| cue.overlaps(start, end)) {
+            let _ = write!(
+                out,
+                "\n{} --> {}\n{}\n",
+                timestamp(cue.start),
+                timestamp(cue.end),
+                escape(&cue.text)
+            );
+        }
+        cues.retain(|cue| cue.end > end || cue.start >= end);
+        out
+    }
+
+    /// Mark the stream as ended (`#EXT-X-ENDLIST`) and rewrite the playlist.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.ended = true;
+        self.write_playlist()
+    }
+
+    /// Render the current subtitle playlist.
+    pub fn playlist(&self) -> String {
+        let mut out = String::new();
+        let media_sequence = self.entries.front().map_or(0, |e| e.sequence_number);
+        let _ = writeln!(out, "#EXTM3U");
+        let _ = writeln!(out, "#EXT-X-VERSION:7");
+        let _ = writeln!(out, "#EXT-X-TARGETDURATION:{}", self.target_duration);
+        let _ = writeln!(out, "#EXT-X-MEDIA-SEQUENCE:{}", media_sequence);
+        if self.config.window == 0 {
+            let _ = writeln!(out, "#EXT-X-PLAYLIST-TYPE:EVENT");
+        }
+        for entry in &self.entries {
+            let _ = writeln!(out, "#EXTINF:{:.3},", entry.duration.as_secs_f64());
+            let _ = writeln!(out, "{}", entry.name);
+        }
+        if self.ended {
+            let _ = writeln!(out, "#EXT-X-ENDLIST");
+        }
+        out
+    }
+
+    /// Write the playlist via a temporary file so readers never see a partial one.
+    fn write_playlist(&self) -> io::Result<()> {
+        let path = self.playlist_path();
+        let tmp = path.with_extension("m3u8.tmp");
+        fs::write(&tmp, self.playlist())?;
+        fs::rename(&tmp, &path)
+    }
+}
+
+impl SegmentSink for VttCueWriter {
+    fn write_segment(&mut self, segment: &Segment) -> io::Result<()> {
+        if segment.is_init() {
+            return Ok(());
+        }
+        let duration = segment.duration.unwrap_or(self.config.default_duration);
+        self.end_segment(segment.sequence_number, duration)
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cues_split_on_segment_boundaries() {
+        let dir = std::env::temp_dir().join(format!("vt-vtt-{}", std::process::id()));
+        let config = HlsConfig {
+            playlist_name: "subtitles.m3u8".to_string(),
+            window: 2,
+            safety_margin: 0,
+            ..Default::default()
+        };
+        let mut writer = VttCueWriter::new(&dir, config).unwrap();
+        let queue = writer.cue_queue();
+        let ms = Duration::from_millis;
+        queue.push(ms(500), ms(1500), "first");
+        queue.push(ms(1800), ms(2600), "a <b> & c\n\nsecond line");
+        writer.add_cue(ms(4100), ms(4900), "third");
+
+        let media = |seq| Segment::media(seq, Vec::new()).with_duration(ms(2000));
+        writer.write_segment(&Segment::init(Vec::new())).unwrap();
+        writer.write_segment(&media(1)).unwrap();
+        let first = fs::read_to_string(dir.join("subs_001.vtt")).unwrap();
+        assert!(first.starts_with("WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:0,LOCAL:00:00:00.000\n"));
+        assert!(first.contains("\n00:00:00.500 --> 00:00:01.500\nfirst\n"));
+        assert!(
+            first.contains("\n00:00:01.800 --> 00:00:02.600\na &lt;b&gt; &amp; c\nsecond line\n")
+        );
+
+        // The cue crossing the 2 s boundary is repeated
+        writer.write_segment(&media(2)).unwrap();
+        let second = fs::read_to_string(dir.join("subs_002.vtt")).unwrap();
+        assert!(second.contains("00:00:01.800 --> 00:00:02.600") && !second.contains("first"));
+        assert_eq!(queue.len(), 1);
+
+        writer.write_segment(&media(3)).unwrap();
+        assert!(fs::read_to_string(dir.join("subs_003.vtt"))
+            .unwrap()
+            .contains("third"));
+        assert!(queue.is_empty());
+        assert!(!dir.join("subs_001.vtt").exists());
+
+        writer.finish().unwrap();
+        let playlist = fs::read_to_string(writer.playlist_path()).unwrap();
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:2\n#EXTINF:2.000,\nsubs_002.vtt\n"));
+        assert!(playlist.ends_with("subs_003.vtt\n#EXT-X-ENDLIST\n"));
+        assert!(writer
+            .media_tag("subs", "English", "en")
+            .ends_with("URI=\"subtitles.m3u8\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+//! IOSurface FFI for sharing pixel buffer memory between processes.
+//!
+//! Only the calls needed to hand a surface to another process and track its
+//! use are bound here.
+
+use core_foundation_sys::base::CFTypeRef;
+
+/// Reference to an IOSurface.
+pub type IOSurfaceRef = CFTypeRef;
+
+/// System-wide identifier of an IOSurface.
+pub type IOSurfaceID = u32;
+
+/// A Mach port name.
+pub type mach_port_t = u32;
+
+/// The null Mach port, returned when a port could not be created
+pub const MACH_PORT_NULL: mach_port_t = 0;
+
+/// `kern_return_t` success code
+pub const KERN_SUCCESS: i32 = 0;
+
+#[link(name = "IOSurface", kind = "framework")]
+extern "C" {
+    pub fn IOSurfaceGetID(buffer: IOSurfaceRef) -> IOSurfaceID;
+
+    /// Look up a surface by ID. Only finds surfaces visible to the calling
+    /// process (e.g. created by it, or received through a Mach port).
+    pub fn IOSurfaceLookup(csid: IOSurfaceID) -> IOSurfaceRef;
+
+    /// Create a Mach send right for the surface. The caller owns the right.
+    pub fn IOSurfaceCreateMachPort(buffer: IOSurfaceRef) -> mach_port_t;
+
+    /// Create a surface reference from a send right. The port is not consumed.
+    pub fn IOSurfaceLookupFromMachPort(port: mach_port_t) -> IOSurfaceRef;
+
+    pub fn IOSurfaceGetWidth(buffer: IOSurfaceRef) -> usize;
+
+    pub fn IOSurfaceGetHeight(buffer: IOSurfaceRef) -> usize;
+
+    pub fn IOSurfaceGetPixelFormat(buffer: IOSurfaceRef) -> u32;
+
+    pub fn IOSurfaceGetAllocSize(buffer: IOSurfaceRef) -> usize;
+
+    // The use count is shared by every process holding the surface; pools
+    // do not recycle surfaces that are in use.
+    pub fn IOSurfaceIncrementUseCount(buffer: IOSurfaceRef);
+
+    pub fn IOSurfaceDecrementUseCount(buffer: IOSurfaceRef);
+
+    pub fn IOSurfaceGetUseCount(buffer: IOSurfaceRef) -> i32;
+
+    pub fn IOSurfaceIsInUse(buffer: IOSurfaceRef) -> u8;
+}
+
+extern "C" {
+    static mach_task_self_: mach_port_t;
+
+    fn mach_port_deallocate(task: mach_port_t, name: mach_port_t) -> i32;
+}
+
+/// Release one user reference to a Mach port right owned by this task.
+///
+/// # Safety
+///
+/// `port` must be a right owned by the caller that is not used afterwards.
+pub unsafe fn mach_port_release(port: mach_port_t) -> i32 {
+    mach_port_deallocate(mach_task_self_, port)
+}
@@ -7,6 +7,17 @@
 //! # Features
 //!
 //! - `helpers` - Enable high-level helper utilities (requires additional dependencies)
+//! - `leak-tracking` - Record creation backtraces of helper-created pixel buffers and
+//!   sessions and report the ones never released (see `helpers::LeakCheck`)
+//! - `http-upload` - Upload segments and playlists with HTTP PUT (see `helpers::HttpPutSink`)
+//! - `mse-server` - Serve live segments to browsers over WebSocket for Media Source
+//!   Extensions playback (see `helpers::MseServer`)
+//! - `async` - Futures-based encoding and decoding (see `helpers::AsyncEncoder` and
+//!   `helpers::AsyncDecoder`)
+//! - `screen-capture` - ScreenCaptureKit display and window capture, macOS 12.3+
+//!   (see `helpers::ScreenCapture`)
+//! - `pixel-rotation` - Rotation and mirroring of decoded images with
+//!   VTPixelRotationSession, macOS 13+ (see `helpers::ImageRotator`)
 //!
 //! # Example
 //!
@@ -48,8 +59,10 @@ pub mod compression;
 pub mod cv_types;
 pub mod decompression;
 pub mod errors;
+pub mod frame_processor;
 pub mod frame_silo;
 pub mod multi_pass_storage;
+pub mod pixel_rotation;
 pub mod pixel_transfer;
 pub mod session;
 pub mod utilities;
@@ -60,4 +73,23 @@ pub mod codecs;
 // CoreMedia sample buffer bindings for NAL extraction
 pub mod cm_sample_buffer;
 
+// CoreMedia clock and timebase bindings for A/V sync
+pub mod cm_sync;
+
+// AudioToolbox converter bindings for audio format conversion
+pub mod audio_converter;
+
+// AVFoundation capture bindings for camera enumeration and configuration
+pub mod av_capture;
+
+// AVFoundation asset writer bindings for MP4 and MOV file writing
+pub mod av_asset_writer;
+
+// IOSurface bindings for cross-process frame sharing
+pub mod io_surface;
+
+// ScreenCaptureKit bindings for display and window capture
+#[cfg(feature = "screen-capture")]
+pub mod screen_capture_kit;
+
 pub mod helpers;
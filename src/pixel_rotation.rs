@@ -0,0 +1,33 @@
+use core_foundation_sys::base::{CFAllocatorRef, CFTypeID, CFTypeRef, OSStatus};
+use core_foundation_sys::string::CFStringRef;
+
+use crate::cv_types::CVPixelBufferRef;
+
+/// Pixel rotation sessions are available from macOS 13 and iOS 16.
+pub type VTPixelRotationSessionRef = CFTypeRef;
+
+#[link(name = "VideoToolBox", kind = "framework")]
+extern "C" {
+    /// Clockwise rotation applied to the image.
+    pub static kVTPixelRotationPropertyKey_Rotation: CFStringRef;
+    pub static kVTRotation_0: CFStringRef;
+    pub static kVTRotation_CW90: CFStringRef;
+    pub static kVTRotation_180: CFStringRef;
+    pub static kVTRotation_CCW90: CFStringRef;
+    /// Mirror the image left to right (applied after rotation).
+    pub static kVTPixelRotationPropertyKey_FlipHorizontalOrientation: CFStringRef;
+    /// Mirror the image top to bottom (applied after rotation).
+    pub static kVTPixelRotationPropertyKey_FlipVerticalOrientation: CFStringRef;
+
+    pub fn VTPixelRotationSessionCreate(
+        allocator: CFAllocatorRef,
+        pixelRotationSessionOut: *mut VTPixelRotationSessionRef,
+    ) -> OSStatus;
+    pub fn VTPixelRotationSessionRotateImage(
+        session: VTPixelRotationSessionRef,
+        sourceBuffer: CVPixelBufferRef,
+        destinationBuffer: CVPixelBufferRef,
+    ) -> OSStatus;
+    pub fn VTPixelRotationSessionGetTypeID() -> CFTypeID;
+    pub fn VTPixelRotationSessionInvalidate(session: VTPixelRotationSessionRef);
+}
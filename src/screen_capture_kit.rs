@@ -0,0 +1,355 @@
+//! ScreenCaptureKit bindings for capturing displays and windows
+//! (macOS 12.3+).
+//!
+//! Like [`frame_processor`](crate::frame_processor), ScreenCaptureKit is an
+//! Objective-C API: classes are looked up at runtime and reached through
+//! thin `unsafe` message wrappers named after their selectors. Objects are
+//! returned as `Retained<AnyObject>`; errors carry the NSError code, e.g.
+//! [`SCStreamErrorUserDeclined`] when screen recording permission is denied.
+
+use block2::Block;
+use core_foundation_sys::base::OSStatus;
+use libc::c_void;
+use objc2::msg_send;
+use objc2::rc::{Allocated, Retained};
+use objc2::runtime::{AnyClass, AnyObject, Bool};
+use objc2_foundation::{NSArray, NSError, NSInteger, NSString};
+use std::ffi::CStr;
+
+use crate::base::EncodedCMTime;
+
+// Linked so the classes below are registered with the runtime
+#[link(name = "ScreenCaptureKit", kind = "framework")]
+extern "C" {}
+
+pub type SCStreamOutputType = NSInteger;
+pub const SCStreamOutputTypeScreen: SCStreamOutputType = 0;
+pub const SCStreamOutputTypeAudio: SCStreamOutputType = 1;
+
+/// The user declined screen recording permission.
+pub const SCStreamErrorUserDeclined: OSStatus = -3801;
+pub const SCStreamErrorFailedToStart: OSStatus = -3802;
+pub const SCStreamErrorMissingEntitlements: OSStatus = -3803;
+pub const SCStreamErrorNoCaptureSource: OSStatus = -3815;
+/// The stream was stopped by the system or the user.
+pub const SCStreamErrorUserStopped: OSStatus = -3817;
+
+pub const SCShareableContentClass: &CStr = c"SCShareableContent";
+pub const SCContentFilterClass: &CStr = c"SCContentFilter";
+pub const SCStreamConfigurationClass: &CStr = c"SCStreamConfiguration";
+pub const SCStreamClass: &CStr = c"SCStream";
+
+/// Look up a ScreenCaptureKit class, or `None` on systems without it.
+pub fn SCGetClass(name: &CStr) -> Option<&'static AnyClass> {
+    AnyClass::get(name)
+}
+
+fn error_code(error: Retained<NSError>) -> OSStatus {
+    error.code() as OSStatus
+}
+
+unsafe fn alloc(name: &CStr) -> Option<Allocated<AnyObject>> {
+    let class = SCGetClass(name)?;
+    Some(msg_send![class, alloc])
+}
+
+/// `+[SCShareableContent getShareableContentExcludingDesktopWindows:onScreenWindowsOnly:completionHandler:]`
+///
+/// The handler is called once on an arbitrary queue with the content or an
+/// error; returns false if ScreenCaptureKit is unavailable.
+///
+/// # Safety
+///
+/// The `SCShareableContent` class registered at runtime must declare this
+/// selector with the signature bound here, as on the systems named in the
+/// module docs.
+pub unsafe fn SCShareableContent_getShareableContent(
+    excludeDesktopWindows: bool,
+    onScreenWindowsOnly: bool,
+    completionHandler: &Block<dyn Fn(*mut AnyObject, *mut NSError)>,
+) -> bool {
+    let Some(class) = SCGetClass(SCShareableContentClass) else {
+        return false;
+    };
+    let _: () = msg_send![
+        class,
+        getShareableContentExcludingDesktopWindows: Bool::new(excludeDesktopWindows),
+        onScreenWindowsOnly: Bool::new(onScreenWindowsOnly),
+        completionHandler: completionHandler
+    ];
+    true
+}
+
+/// `-[SCShareableContent displays]`
+///
+/// # Safety
+///
+/// `content` must be an `SCShareableContent`.
+pub unsafe fn SCShareableContent_displays(content: &AnyObject) -> Retained<NSArray<AnyObject>> {
+    msg_send![content, displays]
+}
+
+/// `-[SCShareableContent windows]`
+///
+/// # Safety
+///
+/// `content` must be an `SCShareableContent`.
+pub unsafe fn SCShareableContent_windows(content: &AnyObject) -> Retained<NSArray<AnyObject>> {
+    msg_send![content, windows]
+}
+
+/// `-[SCDisplay displayID]`, a `CGDirectDisplayID`
+///
+/// # Safety
+///
+/// `display` must be an `SCDisplay`.
+pub unsafe fn SCDisplay_displayID(display: &AnyObject) -> u32 {
+    msg_send![display, displayID]
+}
+
+/// `-[SCDisplay width]` in points
+///
+/// # Safety
+///
+/// `display` must be an `SCDisplay`.
+pub unsafe fn SCDisplay_width(display: &AnyObject) -> NSInteger {
+    msg_send![display, width]
+}
+
+/// `-[SCDisplay height]` in points
+///
+/// # Safety
+///
+/// `display` must be an `SCDisplay`.
+pub unsafe fn SCDisplay_height(display: &AnyObject) -> NSInteger {
+    msg_send![display, height]
+}
+
+/// `-[SCWindow windowID]`, a `CGWindowID`
+///
+/// # Safety
+///
+/// `window` must be an `SCWindow`.
+pub unsafe fn SCWindow_windowID(window: &AnyObject) -> u32 {
+    msg_send![window, windowID]
+}
+
+/// `-[SCWindow title]`
+///
+/// # Safety
+///
+/// `window` must be an `SCWindow`.
+pub unsafe fn SCWindow_title(window: &AnyObject) -> Option<Retained<NSString>> {
+    msg_send![window, title]
+}
+
+/// `-[SCWindow isOnScreen]`
+///
+/// # Safety
+///
+/// `window` must be an `SCWindow`.
+pub unsafe fn SCWindow_isOnScreen(window: &AnyObject) -> bool {
+    let on_screen: Bool = msg_send![window, isOnScreen];
+    on_screen.as_bool()
+}
+
+/// `-[SCWindow owningApplication]`, an SCRunningApplication
+///
+/// # Safety
+///
+/// `window` must be an `SCWindow`.
+pub unsafe fn SCWindow_owningApplication(window: &AnyObject) -> Option<Retained<AnyObject>> {
+    msg_send![window, owningApplication]
+}
+
+/// `-[SCRunningApplication applicationName]`
+///
+/// # Safety
+///
+/// `application` must be an `SCRunningApplication`.
+pub unsafe fn SCRunningApplication_applicationName(application: &AnyObject) -> Retained<NSString> {
+    msg_send![application, applicationName]
+}
+
+/// `-[SCContentFilter initWithDisplay:excludingWindows:]`
+///
+/// # Safety
+///
+/// `display` must be an `SCDisplay`; `excludingWindows` must be an array of
+/// `SCWindow`s.
+pub unsafe fn SCContentFilter_initWithDisplay(
+    display: &AnyObject,
+    excludingWindows: &NSArray<AnyObject>,
+) -> Option<Retained<AnyObject>> {
+    let object = alloc(SCContentFilterClass)?;
+    msg_send![
+        object,
+        initWithDisplay: display,
+        excludingWindows: excludingWindows
+    ]
+}
+
+/// `-[SCContentFilter initWithDesktopIndependentWindow:]`
+///
+/// # Safety
+///
+/// `window` must be an `SCWindow`.
+pub unsafe fn SCContentFilter_initWithDesktopIndependentWindow(
+    window: &AnyObject,
+) -> Option<Retained<AnyObject>> {
+    let object = alloc(SCContentFilterClass)?;
+    msg_send![object, initWithDesktopIndependentWindow: window]
+}
+
+/// `+[SCStreamConfiguration new]`
+///
+/// # Safety
+///
+/// The `SCStreamConfiguration` class registered at runtime must declare this
+/// selector with the signature bound here, as on the systems named in the
+/// module docs.
+pub unsafe fn SCStreamConfiguration_new() -> Option<Retained<AnyObject>> {
+    let class = SCGetClass(SCStreamConfigurationClass)?;
+    msg_send![class, new]
+}
+
+/// `-[SCStreamConfiguration setWidth:]` in pixels
+///
+/// # Safety
+///
+/// `configuration` must be an `SCStreamConfiguration`.
+pub unsafe fn SCStreamConfiguration_setWidth(configuration: &AnyObject, width: usize) {
+    let _: () = msg_send![configuration, setWidth: width];
+}
+
+/// `-[SCStreamConfiguration setHeight:]` in pixels
+///
+/// # Safety
+///
+/// `configuration` must be an `SCStreamConfiguration`.
+pub unsafe fn SCStreamConfiguration_setHeight(configuration: &AnyObject, height: usize) {
+    let _: () = msg_send![configuration, setHeight: height];
+}
+
+/// `-[SCStreamConfiguration setMinimumFrameInterval:]`, i.e. the maximum
+/// frame rate
+///
+/// # Safety
+///
+/// `configuration` must be an `SCStreamConfiguration`.
+pub unsafe fn SCStreamConfiguration_setMinimumFrameInterval(
+    configuration: &AnyObject,
+    minimumFrameInterval: EncodedCMTime,
+) {
+    let _: () = msg_send![configuration, setMinimumFrameInterval: minimumFrameInterval];
+}
+
+/// `-[SCStreamConfiguration setPixelFormat:]`, e.g. `'420v'` or `'BGRA'`
+///
+/// # Safety
+///
+/// `configuration` must be an `SCStreamConfiguration`.
+pub unsafe fn SCStreamConfiguration_setPixelFormat(configuration: &AnyObject, pixelFormat: u32) {
+    let _: () = msg_send![configuration, setPixelFormat: pixelFormat];
+}
+
+/// `-[SCStreamConfiguration setShowsCursor:]`
+///
+/// # Safety
+///
+/// `configuration` must be an `SCStreamConfiguration`.
+pub unsafe fn SCStreamConfiguration_setShowsCursor(configuration: &AnyObject, showsCursor: bool) {
+    let _: () = msg_send![configuration, setShowsCursor: Bool::new(showsCursor)];
+}
+
+/// `-[SCStreamConfiguration setQueueDepth:]`, the number of frames in
+/// flight (3 to 8)
+///
+/// # Safety
+///
+/// `configuration` must be an `SCStreamConfiguration`.
+pub unsafe fn SCStreamConfiguration_setQueueDepth(
+    configuration: &AnyObject,
+    queueDepth: NSInteger,
+) {
+    let _: () = msg_send![configuration, setQueueDepth: queueDepth];
+}
+
+/// `-[SCStream initWithFilter:configuration:delegate:]`
+///
+/// # Safety
+///
+/// `filter` must be an `SCContentFilter`; `configuration` must be an
+/// `SCStreamConfiguration`; `delegate`, if given, must be an object
+/// implementing `SCStreamDelegate`.
+pub unsafe fn SCStream_init(
+    filter: &AnyObject,
+    configuration: &AnyObject,
+    delegate: Option<&AnyObject>,
+) -> Option<Retained<AnyObject>> {
+    let object = alloc(SCStreamClass)?;
+    msg_send![
+        object,
+        initWithFilter: filter,
+        configuration: configuration,
+        delegate: delegate
+    ]
+}
+
+/// `-[SCStream addStreamOutput:type:sampleHandlerQueue:error:]`
+///
+/// `output` implements `SCStreamOutput`; `sampleHandlerQueue` is a dispatch
+/// queue, or null for a default queue.
+///
+/// # Safety
+///
+/// `stream` must be an `SCStream`; `output` must be an object implementing
+/// `SCStreamOutput`; `sampleHandlerQueue` must be null or a dispatch queue.
+pub unsafe fn SCStream_addStreamOutput(
+    stream: &AnyObject,
+    output: &AnyObject,
+    outputType: SCStreamOutputType,
+    sampleHandlerQueue: *mut c_void,
+) -> Result<(), OSStatus> {
+    let result: Result<(), Retained<NSError>> = msg_send![
+        stream,
+        addStreamOutput: output,
+        type: outputType,
+        sampleHandlerQueue: sampleHandlerQueue as *mut AnyObject,
+        error: _
+    ];
+    result.map_err(error_code)
+}
+
+/// `-[SCStream startCaptureWithCompletionHandler:]`
+///
+/// # Safety
+///
+/// `stream` must be an `SCStream`.
+pub unsafe fn SCStream_startCapture(
+    stream: &AnyObject,
+    completionHandler: &Block<dyn Fn(*mut NSError)>,
+) {
+    let _: () = msg_send![stream, startCaptureWithCompletionHandler: completionHandler];
+}
+
+/// `-[SCStream stopCaptureWithCompletionHandler:]`
+///
+/// # Safety
+///
+/// `stream` must be an `SCStream`.
+pub unsafe fn SCStream_stopCapture(
+    stream: &AnyObject,
+    completionHandler: &Block<dyn Fn(*mut NSError)>,
+) {
+    let _: () = msg_send![stream, stopCaptureWithCompletionHandler: completionHandler];
+}
+
+/// The NSError code of an error passed to a completion handler, if any.
+///
+/// # Safety
+///
+/// `error` must be null or point to a live `NSError`.
+pub unsafe fn SCErrorCode(error: *mut NSError) -> Option<OSStatus> {
+    error.as_ref().map(|error| error.code() as OSStatus)
+}
@@ -1,9 +1,18 @@
+use core_foundation::array::CFArray;
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
 use core_foundation_sys::array::CFArrayRef;
-use core_foundation_sys::base::{Boolean, CFTypeRef, OSStatus};
+use core_foundation_sys::base::{Boolean, CFRelease, CFTypeRef, OSStatus};
 use core_foundation_sys::dictionary::CFDictionaryRef;
 use core_foundation_sys::string::CFStringRef;
 use core_media_sys::CMVideoCodecType;
+use std::collections::HashMap;
+use std::ptr;
 
+use crate::compression::kVTVideoEncoderSpecification_RequireHardwareAcceleratedVideoEncoder;
 use crate::cv_types::CVPixelBufferRef;
 
 pub type CGImageRef = CFTypeRef;
@@ -26,6 +35,12 @@ extern "C" {
     pub static kVTVideoEncoderList_CodecName: CFStringRef;
     pub static kVTVideoEncoderList_EncoderName: CFStringRef;
     pub static kVTVideoEncoderList_DisplayName: CFStringRef;
+    pub static kVTVideoEncoderList_IsHardwareAccelerated: CFStringRef;
+    pub static kVTVideoEncoderList_SupportsFrameReordering: CFStringRef;
+    pub static kVTVideoEncoderList_PerformanceRating: CFStringRef;
+    pub static kVTVideoEncoderList_QualityRating: CFStringRef;
+    pub static kVTVideoEncoderList_InstanceLimit: CFStringRef;
+    pub static kVTVideoEncoderList_SupportedSelectionProperties: CFStringRef;
 
     pub fn VTCopyVideoEncoderList(
         options: CFDictionaryRef,
@@ -41,3 +56,147 @@ extern "C" {
     ) -> OSStatus;
     pub fn VTIsHardwareDecodeSupported(codecType: CMVideoCodecType) -> Boolean;
 }
+
+/// An installed video encoder, as reported by [`list_video_encoders`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncoderInfo {
+    /// FourCC, as in [`codecs::video`](crate::codecs::video)
+    pub codec_type: CMVideoCodecType,
+    /// Codec name, e.g. `"H.264"`
+    pub codec_name: String,
+    /// Encoder name, e.g. `"Apple H.264 (HW)"`
+    pub display_name: String,
+    /// Pass to [`CompressionSessionBuilder::encoder_id`](crate::helpers::CompressionSessionBuilder::encoder_id)
+    /// to select this encoder
+    pub encoder_id: String,
+    /// `false` if the system does not say (before macOS 10.15)
+    pub is_hardware: bool,
+}
+
+impl EncoderInfo {
+    /// Read one entry of the `VTCopyVideoEncoderList` array.
+    unsafe fn from_dictionary(entry: &CFDictionary<CFString, CFType>) -> Option<Self> {
+        let get = |key: CFStringRef| entry.find(CFString::wrap_under_get_rule(key));
+        let string = |key: CFStringRef| {
+            get(key)
+                .and_then(|value| value.downcast::<CFString>())
+                .map(|value| value.to_string())
+        };
+        let codec_type = get(kVTVideoEncoderList_CodecType)?
+            .downcast::<CFNumber>()?
+            .to_i64()? as CMVideoCodecType;
+        Some(Self {
+            codec_type,
+            codec_name: string(kVTVideoEncoderList_CodecName).unwrap_or_default(),
+            display_name: string(kVTVideoEncoderList_DisplayName).unwrap_or_default(),
+            encoder_id: string(kVTVideoEncoderList_EncoderID)?,
+            is_hardware: get(kVTVideoEncoderList_IsHardwareAccelerated)
+                .and_then(|value| value.downcast::<CFBoolean>())
+                .is_some_and(bool::from),
+        })
+    }
+}
+
+/// Every video encoder installed on this system, hardware and software.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::utilities::list_video_encoders;
+///
+/// let hevc_hardware = list_video_encoders()?
+///     .into_iter()
+///     .find(|encoder| encoder.codec_type == codecs::video::HEVC && encoder.is_hardware);
+/// # Ok::<(), i32>(())
+/// ```
+pub fn list_video_encoders() -> Result<Vec<EncoderInfo>, OSStatus> {
+    let mut list: CFArrayRef = ptr::null();
+    let status = unsafe { VTCopyVideoEncoderList(ptr::null(), &mut list) };
+    if status != 0 {
+        return Err(status);
+    }
+    if list.is_null() {
+        return Ok(Vec::new());
+    }
+    let list = unsafe { CFArray::<CFType>::wrap_under_create_rule(list) };
+    let dictionary_type = CFDictionary::<CFString, CFType>::type_id();
+    Ok(list
+        .iter()
+        .filter(|entry| entry.type_of() == dictionary_type)
+        .filter_map(|entry| unsafe {
+            let entry = CFDictionary::<CFString, CFType>::wrap_under_get_rule(
+                entry.as_CFTypeRef() as CFDictionaryRef
+            );
+            EncoderInfo::from_dictionary(&entry)
+        })
+        .collect())
+}
+
+/// Whether this system can encode `codec_type` in hardware, e.g. HEVC or
+/// ProRes on Apple silicon.
+///
+/// Checks the encoder list, then asks VideoToolbox for an encoder that
+/// requires hardware acceleration, which also covers systems whose encoder
+/// list does not report it.
+pub fn supports_hardware_encode(codec_type: CMVideoCodecType) -> bool {
+    let listed = list_video_encoders().is_ok_and(|encoders| {
+        encoders
+            .iter()
+            .any(|encoder| encoder.codec_type == codec_type && encoder.is_hardware)
+    });
+    listed || unsafe { hardware_encoder_available(codec_type) }
+}
+
+/// Whether this system can decode `codec_type` in hardware.
+pub fn supports_hardware_decode(codec_type: CMVideoCodecType) -> bool {
+    unsafe { VTIsHardwareDecodeSupported(codec_type) != 0 }
+}
+
+/// Hardware decode support for each of `codec_types`, e.g. to pick between
+/// AV1, HEVC and H.264 tracks before creating a decompression session.
+///
+/// # Example
+///
+/// ```no_run
+/// use video_toolbox_sys::codecs;
+/// use video_toolbox_sys::utilities::probe_decode_support;
+///
+/// let preference = [codecs::video::AV1, codecs::video::HEVC, codecs::video::H264];
+/// let support = probe_decode_support(&preference);
+/// let codec = preference
+///     .into_iter()
+///     .find(|codec| support[codec])
+///     .unwrap_or(codecs::video::H264);
+/// ```
+pub fn probe_decode_support(codec_types: &[CMVideoCodecType]) -> HashMap<CMVideoCodecType, bool> {
+    codec_types
+        .iter()
+        .map(|&codec_type| (codec_type, supports_hardware_decode(codec_type)))
+        .collect()
+}
+
+unsafe fn hardware_encoder_available(codec_type: CMVideoCodecType) -> bool {
+    let specification = CFDictionary::from_CFType_pairs(&[(
+        CFString::wrap_under_get_rule(
+            kVTVideoEncoderSpecification_RequireHardwareAcceleratedVideoEncoder,
+        ),
+        CFBoolean::true_value(),
+    )]);
+    let mut encoder_id: CFStringRef = ptr::null();
+    let mut properties: CFDictionaryRef = ptr::null();
+    let status = VTCopySupportedPropertyDictionaryForEncoder(
+        1920,
+        1080,
+        codec_type,
+        specification.as_concrete_TypeRef(),
+        &mut encoder_id,
+        &mut properties,
+    );
+    for owned in [encoder_id as CFTypeRef, properties as CFTypeRef] {
+        if !owned.is_null() {
+            CFRelease(owned);
+        }
+    }
+    status == 0
+}